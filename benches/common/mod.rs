@@ -0,0 +1,41 @@
+//! Upstream responses shared by the benchmarks, shaped like those of a Mighty server.
+
+use serde_json::{json, Value};
+
+/// An embeddings response of `tokens` vectors of `dimensions` values.
+pub fn embeddings_response(tokens: usize, dimensions: usize) -> Value {
+    let outputs: Vec<Vec<f32>> = (0..tokens)
+        .map(|token| {
+            (0..dimensions)
+                .map(|i| ((token * dimensions + i) as f32 * 0.618).sin())
+                .collect()
+        })
+        .collect();
+    json!({
+        "took": 12,
+        "text": "The quick brown fox jumps over the lazy dog",
+        "outputs": outputs,
+        "shape": [tokens, dimensions],
+    })
+}
+
+/// A token classification response of `entities` entities.
+pub fn token_classification_response(entities: usize) -> Value {
+    let entities: Vec<Value> = (0..entities)
+        .map(|i| {
+            json!({
+                "id": i.to_string(),
+                "label": "Location",
+                "text": "Paris",
+                "score": 0.998_765_4,
+                "offsets": [i * 6, i * 6 + 5],
+            })
+        })
+        .collect();
+    json!({
+        "took": 3,
+        "text": "Paris ".repeat(entities.len()),
+        "entities": entities,
+        "shape": [1, entities.len()],
+    })
+}
@@ -0,0 +1,67 @@
+//! Benchmarks the conversion of upstream JSON responses to protobuf responses, from their body
+//! as received, across embedding sizes, entity counts and batch sizes.
+
+mod common;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use serde_json::Value;
+
+use mighty_grpc::proto::mighty_proto::{EmbeddingsResponse, TokenClassificationResponse};
+use mighty_grpc::services::clients::json_response_converters::JsonResponse;
+use mighty_grpc::services::clients::response_format;
+
+use common::{embeddings_response, token_classification_response};
+
+/// Converts `body` the way the REST client does when no fields are renamed: deserialized once,
+/// straight into the typed fields of `T`.
+fn convert<T: JsonResponse>(body: &[u8]) -> T {
+    let json = response_format::deserialize::<T::Json>(None, body).unwrap();
+    T::convert(json, true).unwrap()
+}
+
+fn bench_embeddings(c: &mut Criterion) {
+    let mut group = c.benchmark_group("embeddings");
+    for (tokens, dimensions) in [(1, 384), (1, 768), (1, 1024), (16, 768), (128, 768)] {
+        let body = serde_json::to_vec(&embeddings_response(tokens, dimensions)).unwrap();
+        group.throughput(Throughput::Bytes(body.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{}x{}", tokens, dimensions)),
+            &body,
+            |b, body| b.iter(|| convert::<EmbeddingsResponse>(black_box(body))),
+        );
+    }
+    group.finish();
+}
+
+fn bench_token_classification(c: &mut Criterion) {
+    let mut group = c.benchmark_group("token_classification");
+    for entities in [0, 16, 256] {
+        let body = serde_json::to_vec(&token_classification_response(entities)).unwrap();
+        group.throughput(Throughput::Bytes(body.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(entities), &body, |b, body| {
+            b.iter(|| convert::<TokenClassificationResponse>(black_box(body)))
+        });
+    }
+    group.finish();
+}
+
+fn bench_embeddings_batch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("embeddings_batch");
+    for texts in [1, 8, 32] {
+        let batch: Vec<Value> = (0..texts).map(|_| embeddings_response(1, 768)).collect();
+        let body = serde_json::to_vec(&batch).unwrap();
+        group.throughput(Throughput::Elements(texts as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(texts), &body, |b, body| {
+            b.iter(|| convert::<Vec<EmbeddingsResponse>>(black_box(body)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_embeddings,
+    bench_token_classification,
+    bench_embeddings_batch
+);
+criterion_main!(benches);
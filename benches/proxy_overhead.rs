@@ -0,0 +1,135 @@
+//! Benchmarks the overhead of the gateway: calls to a no-op client made directly, and through
+//! the inference server configured as the binaries do, over a loopback gRPC connection.
+
+use async_trait::async_trait;
+use criterion::{criterion_group, criterion_main, Criterion};
+use futures::stream;
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tonic::transport::{Channel, Server};
+use tonic::{Request, Response, Status};
+
+use mighty_grpc::config::AppSettings;
+use mighty_grpc::proto::mighty_proto::mighty_inference_client::MightyInferenceClient;
+use mighty_grpc::proto::mighty_proto::{
+    Embedding, EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse,
+    QuestionAnswerRequest, QuestionAnswerResponse, SentenceTransformersResponse,
+    SequenceClassificationResponse, TextRequest, TokenClassificationResponse,
+};
+use mighty_grpc::services::clients::MightyClient;
+use mighty_grpc::services::server_proxy::MightyInferenceServerProxy;
+
+/// A client answering every call at once, embeddings with a single 768-dimension vector.
+struct NoopClient;
+
+#[async_trait]
+impl MightyClient for NoopClient {
+    async fn health_check(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        Ok(Response::new(HealthcheckResponse { success: true }))
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        Ok(Response::new(EmbeddingsResponse {
+            embeddings: vec![Embedding {
+                values: vec![0.5; 768],
+            }],
+            took: 1,
+            text: request.into_inner().text,
+            ..Default::default()
+        }))
+    }
+
+    async fn question_answering(
+        &self,
+        _request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        Ok(Response::new(QuestionAnswerResponse::default()))
+    }
+
+    async fn sentence_transformers(
+        &self,
+        _request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        Ok(Response::new(SentenceTransformersResponse::default()))
+    }
+
+    async fn sequence_classification(
+        &self,
+        _request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        Ok(Response::new(SequenceClassificationResponse::default()))
+    }
+
+    async fn token_classification(
+        &self,
+        _request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        Ok(Response::new(TokenClassificationResponse::default()))
+    }
+
+    async fn metadata(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        Ok(Response::new(MetadataResponse::default()))
+    }
+}
+
+fn text() -> Request<TextRequest> {
+    Request::new(TextRequest {
+        text: "The quick brown fox jumps over the lazy dog".to_string(),
+    })
+}
+
+/// Serves the inference server over `NoopClient` on a loopback port and connects to it.
+async fn serve(settings: &'static AppSettings) -> MightyInferenceClient<Channel> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    // Without Nagle's algorithm, as the gateway's listeners
+    let incoming = stream::unfold(listener, |listener| async {
+        let stream = listener.accept().await.and_then(|(stream, _)| {
+            stream.set_nodelay(true)?;
+            Ok(stream)
+        });
+        Some((stream, listener))
+    });
+    let server = MightyInferenceServerProxy::builder(Box::new(NoopClient))
+        .settings(settings)
+        .build_server();
+    tokio::spawn(
+        Server::builder()
+            .add_service(server)
+            .serve_with_incoming(incoming),
+    );
+    MightyInferenceClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap()
+}
+
+fn bench_proxy_overhead(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let settings = Box::leak(Box::new(AppSettings::default()));
+    let client = runtime.block_on(serve(settings));
+
+    let mut group = c.benchmark_group("embeddings");
+    group.bench_function("direct", |b| {
+        b.to_async(&runtime)
+            .iter(|| async { NoopClient.embeddings(text()).await.unwrap() })
+    });
+    group.bench_function("loopback", |b| {
+        b.to_async(&runtime).iter(|| {
+            let mut client = client.clone();
+            async move { client.embeddings(text()).await.unwrap() }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_proxy_overhead);
+criterion_main!(benches);
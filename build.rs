@@ -1,3 +1,16 @@
+use std::env;
+use std::fs;
+
+#[allow(dead_code)]
+#[path = "src/proto/schema.rs"]
+mod schema;
+
+const PROTO_PATH: &str = "src/proto/mighty_inference.proto";
+const HEALTH_PROTO_PATH: &str = "src/proto/health.proto";
+const DESCRIPTOR_PATH: &str = "src/proto/mighty_inference.bin";
+const GOLDEN_PATH: &str = "src/proto/mighty_inference.golden";
+const BLESS_ENV: &str = "MIGHTY_GRPC_BLESS_SCHEMA";
+
 /// Entry point for the build script.
 ///
 /// This script is responsible for compiling the Protobuf definitions using `tonic-build`.
@@ -5,16 +18,66 @@
 /// and outputs the corresponding Rust definitions. Additionally, it generates a binary
 /// descriptor set file used for gRPC reflection, enabling clients to understand the services
 /// the gRPC server exposes, including the methods and message types, without having the
-/// proto file at compile time.
+/// proto file at compile time. Messages are also (de)serializable with serde, missing fields
+/// taking their default values, for the JSON endpoints of the HTTP gateway.
+/// The standard gRPC health service (`health.proto`) is compiled separately, outside of the
+/// descriptor set and golden schema of the Mighty API.
+///
+/// The compiled descriptor is then checked against the committed golden schema
+/// (`mighty_inference.golden`) so that breaking changes to the proto (removed fields, changed
+/// field numbers or types, removed RPCs) fail the build. Setting `MIGHTY_GRPC_BLESS_SCHEMA=1`
+/// rewrites the golden schema after an intentional change.
 ///
 /// # Errors
 ///
-/// Returns `Err` if there is a problem compiling the Protobuf definitions.
+/// Returns `Err` if there is a problem compiling the Protobuf definitions or if the compiled
+/// schema contains breaking changes relative to the golden schema.
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::configure()
         .build_server(true)
-        .file_descriptor_set_path("src/proto/mighty_inference.bin")
-        .compile(&["src/proto/mighty_inference.proto"], &["proto"])?;
-    Ok(())
+        .message_attribute(
+            ".",
+            "#[derive(serde::Serialize, serde::Deserialize)] #[serde(default)]",
+        )
+        .file_descriptor_set_path(DESCRIPTOR_PATH)
+        .compile(&[PROTO_PATH], &["proto"])?;
+    // The standard health service, kept out of the descriptor set of the Mighty API
+    tonic_build::configure()
+        .build_client(false)
+        .compile(&[HEALTH_PROTO_PATH], &["proto"])?;
+
+    println!("cargo:rerun-if-changed={}", GOLDEN_PATH);
+    println!("cargo:rerun-if-env-changed={}", BLESS_ENV);
+    check_golden_schema()
 }
 
+/// Compares the compiled descriptor set against the golden schema.
+fn check_golden_schema() -> Result<(), Box<dyn std::error::Error>> {
+    let current = schema::Schema::decode(&fs::read(DESCRIPTOR_PATH)?)?;
+
+    if env::var_os(BLESS_ENV).is_some() {
+        fs::write(GOLDEN_PATH, current.to_golden_string())?;
+        return Ok(());
+    }
+
+    let golden = schema::Schema::parse(&fs::read_to_string(GOLDEN_PATH)?);
+    let violations = golden.breaking_changes(&current);
+    if !violations.is_empty() {
+        return Err(format!(
+            "breaking changes to {} relative to {}:\n  {}\nIf intentional, rebuild with {}=1",
+            PROTO_PATH,
+            GOLDEN_PATH,
+            violations.join("\n  "),
+            BLESS_ENV
+        )
+        .into());
+    }
+
+    for addition in golden.additions(&current) {
+        println!(
+            "cargo:warning={} is not recorded in {}; rebuild with {}=1 to update it",
+            addition, GOLDEN_PATH, BLESS_ENV
+        );
+    }
+    Ok(())
+}
@@ -3,86 +3,244 @@
  *
  * This Rust program initializes and starts both an Actix-web server for API REST requests and a
  * gRPC server using the tonic framework. It's meant as an example on how to modify the existing
- * Mighty Inference Server to serve up both interfaces if desired. Of course, the `BinaryClient`
- * implementation located at `/src/services/client/binary.rs` is not complete as this would be
- * completed only if the wish is to serve up both interfaces through a single binary.
+ * Mighty Inference Server to serve up both interfaces if desired. The `BinaryClient` located at
+ * `/src/services/clients/binary.rs` runs the Mighty executable as a pool of supervised workers,
+ * configured in the `[binary]` section of `config.toml`.
  *
  * It only supports running in binary mode, controlled by the `--features binary` flag.
  *
  *
  * The program performs the following steps:
- * 1. Initializes logging based on environment settings.
- * 2. Loads application settings from a configuration file.
+ * 1. Loads application settings from a configuration file (`--config`, `config.toml` by
+ *    default), overridden by `MIGHTY_GRPC__*` environment variables, then by the command-line
+ *    arguments (see `--help`).
+ * 2. Validates these settings, reporting every problem at once, and initializes logging.
  * 3. Creates a binary client for communication based on the enabled `binary` feature flag.
- * 4. Configures and starts a gRPC server on the specified address and port set in `config.toml`.
+ * 4. Configures and starts a gRPC server on the specified address and port set in `config.toml`,
+ *    on the Tokio runtime configured by `[runtime]`.
+ * 5. Starts the API server, mirroring every RPC of the inference service as a JSON endpoint
+ *    (e.g. `POST /embeddings`) served by the same proxy, client and interceptors as the gRPC
+ *    server, and batch embeddings as NumPy `.npz` archives on `POST /embeddings.npz` (see
+ *    `mighty_grpc::services::http_gateway`). Liveness and readiness are probed on `GET /livez`
+ *    and `GET /readyz`, and through the standard gRPC health service.
+ *
+ * Startup failures exit with a distinct code per failure class (see `mighty_grpc::startup`), e.g.
+ * 64 when built without the `binary` feature, 71 when a port can't be bound and 78 on a bad
+ * configuration.
  *
  * Usage:
  * To run the server:
  *   cargo run --bin api_and_grpc --features binary
+ *
+ * To run the server with a configuration file outside the working directory:
+ *   cargo run --bin api_and_grpc --features binary -- --config /etc/mighty-grpc/config.toml
  */
 
 #![allow(unused_imports, unused)] // turned on to silence clippy warnings due to using feature flags
-use std::env;
+use std::process::ExitCode;
+use std::sync::Arc;
 
-use actix_web::{App, HttpServer, middleware, Responder, web};
+use actix_web::{App, HttpServer, middleware};
 use cfg_if::cfg_if;
-use env_logger::Builder;
 use futures::TryFutureExt;
-use log::{error, info};
+use log::{error, info, warn};
 use tokio::signal;
+use tonic::transport::server::TcpIncoming;
 use tonic::transport::Server;
 
-use mighty_grpc::config::AppSettings;
+use mighty_grpc::config::cli::{Cli, USAGE};
+use mighty_grpc::config::{AppSettings, BackendKind, LoggingConfig};
+#[cfg(feature = "reflection")]
+use mighty_grpc::proto::create_reflection_server;
+use mighty_grpc::services::access_log::AccessLogLayer;
+use mighty_grpc::services::auth::AuthInterceptor;
+use mighty_grpc::services::client_rate_limit::ClientRateLimitInterceptor;
 #[cfg(feature = "binary")]
 use mighty_grpc::services::clients::binary::BinaryClient;
-use mighty_grpc::services::server_proxy::create_mighty_inference_server;
+use mighty_grpc::services::clients::metered::MeteredClient;
+use mighty_grpc::services::clients::validating::ValidatingClient;
+use mighty_grpc::services::clients::MightyClient;
+use mighty_grpc::services::health::HealthLayer;
+use mighty_grpc::services::http_gateway::HttpGateway;
+use mighty_grpc::services::logging::logger_factory;
+use mighty_grpc::services::network_acl::NetworkAclInterceptor;
+use mighty_grpc::services::panic_recovery::install_panic_hook;
+use mighty_grpc::services::readiness::Readiness;
+use mighty_grpc::services::reload::ReloadableLogger;
+use mighty_grpc::services::server_proxy::{
+    create_mighty_inference_proxy, create_mighty_inference_server_with_proxy,
+};
+use mighty_grpc::startup::{build_runtime, StartupError};
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static ALLOCATOR: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+fn init_logging(config: &LoggingConfig) -> Result<(), StartupError> {
+    let build = logger_factory(config).map_err(|e| {
+        let file = config.file.clone().unwrap_or_default();
+        StartupError::Config(format!("Can't open the log file {}: {}", file.display(), e))
+    })?;
+    ReloadableLogger::new(&config.level, build).init();
+    Ok(())
+}
 
-fn init_logging() {
-    let mut builder = Builder::from_default_env();
-    builder.filter(Some("h2"), log::LevelFilter::Warn);
-    builder.init();
+fn main() -> ExitCode {
+    let result = configure().and_then(|configured| match configured {
+        Some((cli, settings)) => build_runtime(&settings.runtime)?.block_on(run(cli, settings)),
+        None => Ok(()),
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            error!("{}", error);
+            error.exit()
+        }
+    }
+}
+
+/// Parses the command line and loads the settings, before any runtime is started, or returns
+/// `None` once the command line is served, e.g. `--help`.
+fn configure() -> Result<Option<(Cli, AppSettings)>, StartupError> {
+    cfg_if! {
+        if #[cfg(feature = "binary")] {
+            let cli = Cli::from_env().map_err(StartupError::Usage)?;
+            if cli.help {
+                println!("{}", USAGE);
+                return Ok(None);
+            }
+            let mut settings = AppSettings::load(&cli)?;
+            if settings.client.is_some_and(|kind| kind != BackendKind::Binary) {
+                return Err(StartupError::Config(
+                    "client must be binary with the api_and_grpc binary".to_string(),
+                ));
+            }
+            settings.client = Some(BackendKind::Binary);
+            if cli.print_config {
+                println!("{:#?}", settings);
+                return Ok(None);
+            }
+            settings.validate()?;
+            Ok(Some((cli, settings)))
+        } else {
+            Err(StartupError::FeatureMismatch(
+                "api_and_grpc requires `--features binary` to run".to_string(),
+            ))
+        }
+    }
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn run(cli: Cli, settings: AppSettings) -> Result<(), StartupError> {
     cfg_if! {
         if #[cfg(feature = "binary")] {
-            let settings = AppSettings::new()?;
-            env::set_var("RUST_LOG", &settings.logging.level);
-            init_logging();
+            init_logging(&settings.logging)?;
+            if !cli.config_file().is_file() {
+                warn!("No configuration file found, serving the default settings");
+            }
+            info!("Effective configuration: {:#?}", settings);
+            install_panic_hook();
 
-            let binary_client = BinaryClient::new();
+            let mut client: Box<dyn MightyClient> = Box::new(MeteredClient::new(Box::new(
+                BinaryClient::spawn(settings.binary.clone()),
+            )));
+            if settings.validation.is_enabled() {
+                client = Box::new(ValidatingClient::new(client, settings.validation.clone()));
+            }
+            let client: Arc<dyn MightyClient> = Arc::from(client);
+            let readiness = Arc::new(Readiness::new(settings.readiness.clone()));
+            readiness.mark_cache_warmed(); // no response cache
+            tokio::spawn({
+                let readiness = readiness.clone();
+                let client = client.clone();
+                async move {
+                    readiness.warm_up(client.as_ref()).await;
+                    readiness.monitor_upstream(client.as_ref()).await
+                }
+            });
+            // Shared by the gRPC server and the HTTP gateway
+            let proxy = Arc::new(create_mighty_inference_proxy(Box::new(client), &settings));
+            let client_rate_limit = ClientRateLimitInterceptor::new(&settings.client_rate_limit);
 
             // gRPC server setup
             let grpc_addr = format!(
                 "{}:{}",
                 settings.grpc_server.address, settings.grpc_server.port
             )
-            .parse()?;
+            .parse()
+            .map_err(|e| StartupError::Config(format!("Invalid gRPC server address: {}", e)))?;
+            if settings.grpc_server.tls.is_some() {
+                return Err(StartupError::Config(
+                    "grpc_server.tls is only supported by the grpc binary".to_string(),
+                ));
+            }
+            if !settings.grpc_server.listeners.is_empty() {
+                return Err(StartupError::Config(
+                    "grpc_server.listeners is only supported by the grpc binary".to_string(),
+                ));
+            }
+            if settings.tracing.enabled {
+                return Err(StartupError::Config(
+                    "tracing is only supported by the grpc binary".to_string(),
+                ));
+            }
+            if !settings.aliases.is_empty() {
+                return Err(StartupError::Config(
+                    "aliases are only supported by the grpc binary".to_string(),
+                ));
+            }
+            let auth = AuthInterceptor::new(&settings)?;
+            let grpc_incoming = TcpIncoming::new(grpc_addr, true, None)
+                .map_err(|e| StartupError::bind(grpc_addr, e))?;
             info!("gRPC Server listening on {}", grpc_addr);
-            let grpc_service = create_mighty_inference_server(Box::new(binary_client));
-            let grpc_future = Server::builder()
-                .add_service(grpc_service)
-                .serve(grpc_addr)
+            let grpc_service = create_mighty_inference_server_with_proxy(proxy.clone(), &settings);
+            let grpc_router = Server::builder()
+                .http2_keepalive_interval(settings.grpc_server.keepalive_interval)
+                .http2_keepalive_timeout(settings.grpc_server.keepalive_timeout)
+                .max_concurrent_streams(settings.grpc_server.max_concurrent_streams)
+                .layer(AccessLogLayer::new(&settings.logging))
+                .layer(tonic::service::interceptor(NetworkAclInterceptor::new(
+                    &settings.network_acl,
+                )))
+                // Probes carry no credentials, health is served ahead of auth and rate limiting
+                .layer(HealthLayer::new(readiness.clone()))
+                .layer(tonic::service::interceptor(auth.clone()))
+                .layer(tonic::service::interceptor(client_rate_limit.clone()))
+                .add_service(grpc_service);
+            #[cfg(feature = "reflection")]
+            let grpc_router = grpc_router.add_service(create_reflection_server()?);
+            let grpc_future = grpc_router
+                .serve_with_incoming(grpc_incoming)
                 .map_err(|e| anyhow::anyhow!(e));
 
             // REST API server setup
             let api_server = settings
                 .api_server
                 .as_ref()
-                .expect("API Server configuration is missing");
+                .ok_or_else(|| {
+                    StartupError::Config("API Server configuration is missing".to_string())
+                })?;
             let http_addr = format!(
                 "{}:{}",
                 api_server.address, api_server.port
             );
+            let http_socket_addr = http_addr
+                .parse()
+                .map_err(|e| StartupError::Config(format!("Invalid API server address: {}", e)))?;
             info!("API Server listening on {}", http_addr);
+            let mut http_gateway = HttpGateway::new(proxy, auth, client_rate_limit)
+                .with_network_acl(NetworkAclInterceptor::new(&settings.network_acl))
+                .with_readiness(readiness);
+            if let Some(size) = settings.grpc_server.max_receive_message_size {
+                http_gateway = http_gateway.with_max_request_size(size as usize);
+            }
+            let http_gateway = Arc::new(http_gateway);
             let actix_future = HttpServer::new(move || {
                 App::new()
                     .wrap(middleware::Logger::default())
-                    .service(web::resource("/healthcheck").route(web::get().to(mock_health_check)))
-                    .service(web::resource("/embeddings").route(web::get().to(mock_embeddings)))
+                    .configure(|config| http_gateway.configure(config))
             })
-            .bind(http_addr)?
+            .bind(http_socket_addr)
+            .map_err(|e| StartupError::bind(http_socket_addr, e))?
             .run()
             .map_err(|e| anyhow::anyhow!(e));
 
@@ -98,12 +256,15 @@ async fn main() -> anyhow::Result<()> {
             tokio::select! {
                 res = grpc_future => {
                     if let Err(e) = res {
-                        error!("gRPC server error: {:?}", e);
+                        return Err(StartupError::Server(format!("gRPC server error: {:?}", e)));
                     }
                 },
                 res = actix_future => {
                     if let Err(e) = res {
-                        error!("Actix web server error: {:?}", e);
+                        return Err(StartupError::Server(format!(
+                            "Actix web server error: {:?}",
+                            e
+                        )));
                     }
                 },
                 _ = shutdown_signal => {
@@ -111,17 +272,9 @@ async fn main() -> anyhow::Result<()> {
                 }
             }
         } else {
-            panic!("Requires `--features binary` to run")
+            unreachable!("configure fails without the binary feature");
         }
     }
 
     Ok(())
 }
-
-async fn mock_health_check() -> impl Responder {
-    web::Json("OK")
-}
-
-async fn mock_embeddings() -> impl Responder {
-    web::Json(vec![1.0, 2.0, 3.0])
-}
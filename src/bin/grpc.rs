@@ -2,69 +2,218 @@
  * grpc.rs
  *
  * This Rust program initializes and starts a gRPC server using the tonic framework.
- * It supports two modes of client communication: REST and binary, controlled by feature flags.
+ * It supports six modes of client communication: REST, binary, FFI, ONNX, OpenAI and TEI,
+ * controlled by feature flags.
  *
  * Features:
  * - `rest`: Enables REST client communication.
  * - `binary`: Enables binary client communication.
+ * - `ffi`: Enables in-process calls into the Mighty shared library.
+ * - `onnx`: Enables in-process inference with an ONNX model, without any Mighty server.
+ * - `openai`: Enables embeddings through an OpenAI-compatible embeddings API.
+ * - `tei`: Enables embeddings and reranking through a Hugging Face Text Embeddings Inference
+ *   server.
+ * - `edge`: Serves an ONNX model like `onnx` from a single static binary with an embedded default
+ *   configuration, printed by `grpc --dump-embedded-config`.
+ * - `redis`: Shares the response cache between gateway replicas through Redis
+ *   (`cache.redis_url`), alongside any of the modes above.
+ * - `tls`: Serves gRPC over TLS (`grpc_server.tls`), alongside any of the modes above.
+ * - `reflection` (default): Serves gRPC server reflection, for grpcurl and dynamic clients.
+ * - `otel`: Exports the spans of gRPC and upstream calls over OTLP (`[tracing]`), alongside any
+ *   of the modes above.
+ * - `jwt`: Authenticates gRPC calls by JWT bearer tokens (`[jwt]`), alongside any of the modes
+ *   above.
+ * - `jemalloc`: Allocates with jemalloc, exporting its statistics alongside the memory metrics.
  *
  * The program performs the following steps:
- * 1. Initializes logging based on environment settings.
- * 2. Loads application settings from a configuration file.
- * 3. Creates a client for communication based on the enabled feature flag (`rest` or `binary`).
- * 4. Configures and starts a gRPC server on the specified address and port.
+ * 1. Loads application settings from a configuration file (`--config`, `config.toml` by
+ *    default), overridden by `MIGHTY_GRPC__*` environment variables, then by the command-line
+ *    arguments (`--grpc-port`, `--mighty-url`, `--log-level`, `--client`; see `--help`).
+ * 2. Validates these settings, reporting every problem at once, initializes logging and logs the
+ *    effective settings, secrets redacted (`--print-config` prints them and exits).
+ * 3. Creates a client for communication based on `--client` or the enabled feature flag
+ *    (`rest`, `binary`, `ffi`, `onnx`, `openai`, `tei` or `edge`).
+ * 4. Configures and starts a gRPC server on the specified address and port, on the Tokio runtime
+ *    configured by `[runtime]`.
  *
- * Note: Either the `rest` or `binary` feature must be enabled for the program to compile and run.
- * The default feature set in `Cargo.toml` is `rest`.
+ * Startup failures exit with a distinct code per failure class (64: usage or feature mismatch,
+ * 69: upstream unreachable, 70: server error, 71: port bind, 74: TLS load, 78: configuration)
+ * after printing a single-line JSON report on stderr.
+ *
+ * Note: One of the `rest`, `binary`, `ffi`, `onnx`, `openai`, `tei` or `edge` features must be
+ * enabled for the program to compile and run.
+ * The default feature set in `Cargo.toml` is `rest` and `reflection`: `--no-default-features` also
+ * drops server reflection, kept with `--features reflection`.
  *
  * Usage:
- * To run the server with REST client support:
- *   cargo run --bin grpc --features rest (NOTE: `--features rest` can be omitted as it's the default)
+ * To run the server with REST client support and server reflection (the default features):
+ *   cargo run --bin grpc
+ *
+ * To run the server with a configuration file outside the working directory, against another
+ * Mighty server:
+ *   cargo run --bin grpc -- --config /etc/mighty-grpc/config.toml --mighty-url http://mighty:5050
  *
  * To run the server with binary client support:
  *   cargo run --bin grpc --features binary
+ *
+ * To run the server with the Mighty shared library loaded in-process:
+ *   cargo run --bin grpc --no-default-features --features ffi
+ *
+ * To run the server with an ONNX model served in-process:
+ *   cargo run --bin grpc --no-default-features --features onnx
+ *
+ * To run the server against an OpenAI-compatible embeddings API:
+ *   cargo run --bin grpc --no-default-features --features openai
+ *
+ * To run the server against a Text Embeddings Inference server:
+ *   cargo run --bin grpc --no-default-features --features tei
+ *
+ * To build the static edge bundle, with ONNX Runtime built as static libraries in ORT_LIB_LOCATION:
+ *   cargo build --bin grpc --profile edge --target x86_64-unknown-linux-musl \
+ *     --no-default-features --features edge
  */
 
 #![allow(unused_imports)] // turned on to silence clippy warnings due to using feature flags
-use std::env;
+use std::collections::HashMap;
+use std::process::ExitCode;
+use std::sync::Arc;
 
 use cfg_if::cfg_if;
-use env_logger::Builder;
-use log::info;
+use futures::future::{self, BoxFuture, FutureExt};
+use log::{error, info, warn};
+use tonic::transport::server::TcpIncoming;
 use tonic::transport::Server;
 
-use mighty_grpc::config::AppSettings;
+use mighty_grpc::config::cli::{Cli, USAGE};
+#[cfg(any(feature = "rest", feature = "binary"))]
+use mighty_grpc::config::MightyServerConfig;
+use mighty_grpc::config::{
+    AppSettings, BackendConfig, BackendKind, CacheBackendKind, CacheConfig, LoggingConfig, VcrMode,
+};
+#[cfg(feature = "reflection")]
+use mighty_grpc::proto::create_reflection_server;
+use mighty_grpc::proto::mighty_proto::Empty;
+use mighty_grpc::services::access_log::AccessLogLayer;
+use mighty_grpc::services::admin::create_mighty_admin_server;
+use mighty_grpc::services::aliases::AliasLayer;
+use mighty_grpc::services::auth::AuthInterceptor;
+use mighty_grpc::services::client_rate_limit::ClientRateLimitInterceptor;
+use mighty_grpc::services::clients::batching::BatchingClient;
 #[cfg(feature = "binary")]
 use mighty_grpc::services::clients::binary::BinaryClient;
-use mighty_grpc::services::clients::MightyClient;
-#[cfg(feature = "rest")]
-use mighty_grpc::services::clients::rest::MightyServerRestClient;
-use mighty_grpc::services::server_proxy::create_mighty_inference_server;
+use mighty_grpc::services::clients::caching::CachingClient;
+use mighty_grpc::services::clients::canary::CanaryClient;
+#[cfg(feature = "chaos")]
+use mighty_grpc::services::clients::chaos::ChaosClient;
+use mighty_grpc::services::clients::circuit_breaker::{CircuitBreakerClient, CircuitBreakers};
+use mighty_grpc::services::clients::fallback::FallbackClient;
+#[cfg(feature = "ffi")]
+use mighty_grpc::services::clients::ffi::FfiClient;
+use mighty_grpc::services::clients::metered::MeteredClient;
+#[cfg(any(feature = "onnx", feature = "edge"))]
+use mighty_grpc::services::clients::onnx::OnnxClient;
+#[cfg(feature = "openai")]
+use mighty_grpc::services::clients::openai::OpenAiClient;
+use mighty_grpc::services::clients::quota::{QuotaClient, Quotas};
+use mighty_grpc::services::clients::rate_limit::RateLimitingClient;
+#[cfg(feature = "redis")]
+use mighty_grpc::services::clients::redis_cache::RedisCache;
+use mighty_grpc::services::clients::retrying::RetryingClient;
+use mighty_grpc::services::clients::routing::RoutingClient;
+use mighty_grpc::services::clients::shadow::ShadowClient;
+use mighty_grpc::services::clients::single_flight::SingleFlightClient;
+use mighty_grpc::services::clients::switchable::{self, SwitchableClient};
+use mighty_grpc::services::clients::task_routing::TaskRoutingClient;
+#[cfg(feature = "tei")]
+use mighty_grpc::services::clients::tei::TeiClient;
+use mighty_grpc::services::clients::traced::TracedClient;
+use mighty_grpc::services::clients::validating::ValidatingClient;
+use mighty_grpc::services::clients::vcr::VcrClient;
+use mighty_grpc::services::clients::{
+    BackendSwitch, CacheFlush, FaultInjection, MightyClient, ModelUpgrade, UpstreamUpdate,
+};
+#[cfg(any(feature = "rest", feature = "binary"))]
+use mighty_grpc::services::clients::rest::{create_rest_backend, create_rest_client};
+use mighty_grpc::services::health::HealthLayer;
+use mighty_grpc::services::logging::logger_factory;
+use mighty_grpc::services::network_acl::NetworkAclInterceptor;
+use mighty_grpc::services::panic_recovery::install_panic_hook;
+use mighty_grpc::services::readiness::Readiness;
+use mighty_grpc::services::reload::{ConfigReloader, ReloadableLogger, UpstreamReload};
+use mighty_grpc::services::server_proxy::{
+    create_mighty_inference_proxy, create_mighty_inference_server_with_proxy,
+};
+use mighty_grpc::services::telemetry::{grpc_request_span, init_tracing};
+#[cfg(feature = "tls")]
+use mighty_grpc::services::tls::TlsListener;
+#[cfg(unix)]
+use mighty_grpc::services::unix_socket::bind_unix_socket;
+use mighty_grpc::startup::{build_runtime, StartupError};
 
-#[cfg(not(any(feature = "rest", feature = "binary")))]
-compile_error!("You must enable either the `rest` or `binary` feature.");
+#[cfg(not(any(
+    feature = "rest",
+    feature = "binary",
+    feature = "ffi",
+    feature = "onnx",
+    feature = "openai",
+    feature = "tei",
+    feature = "edge"
+)))]
+compile_error!(
+    "You must enable either the `rest`, `binary`, `ffi`, `onnx`, `openai`, `tei` or `edge` feature."
+);
 
-fn init_logging() {
-    let mut builder = Builder::from_default_env();
-    builder.filter(Some("h2"), log::LevelFilter::Warn);
-    builder.filter(Some("hyper"), log::LevelFilter::Warn);
-    builder.init();
+/// Allocates with jemalloc, which returns memory to the system more eagerly than glibc.
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static ALLOCATOR: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+/// Installs the global logger, rebuilt at the logging level of reloaded configurations.
+fn init_logging(config: &LoggingConfig) -> Result<ReloadableLogger, StartupError> {
+    let build = logger_factory(config).map_err(|e| {
+        let file = config.file.clone().unwrap_or_default();
+        StartupError::Config(format!("Can't open the log file {}: {}", file.display(), e))
+    })?;
+    Ok(ReloadableLogger::new(&config.level, build).init())
 }
 
-fn create_client(settings: &AppSettings) -> Box<dyn MightyClient> {
+/// The client of the enabled backend, along with handles to upgrade its model and to update its
+/// upstream instances while serving, when the backend supports it.
+type Backend = (
+    Box<dyn MightyClient>,
+    Option<Arc<dyn ModelUpgrade>>,
+    Option<Arc<dyn UpstreamUpdate>>,
+);
+
+fn create_default_client(settings: &AppSettings) -> Result<Backend, StartupError> {
     cfg_if! {
         if #[cfg(feature = "rest")] {
             let mighty_server_config = settings
                 .mighty_server
                 .as_ref()
-                .expect("Mighty Server configuration is missing");
-            let base_url = mighty_server_config
-                .base_url
-                .as_ref()
-                .expect("Base URL for Mighty Server is missing");
-            Box::new(MightyServerRestClient::new(base_url.clone()))
+                .ok_or_else(|| {
+                    StartupError::Config("Mighty Server configuration is missing".to_string())
+                })?;
+            if mighty_server_config.base_url.is_empty() {
+                return Err(StartupError::Config(
+                    "Base URL for Mighty Server is missing".to_string(),
+                ));
+            }
+            let (client, upstreams) =
+                create_rest_backend(mighty_server_config, &settings.resilience);
+            Ok((client, None, upstreams))
         } else if #[cfg(feature = "binary")] {
-            Box::new(BinaryClient::new())
+            Ok(spawn_binary_client(settings))
+        } else if #[cfg(feature = "ffi")] {
+            let client = FfiClient::open(&settings.ffi).map_err(StartupError::Config)?;
+            Ok((Box::new(client), None, None))
+        } else if #[cfg(any(feature = "onnx", feature = "edge"))] {
+            let client = OnnxClient::open(&settings.onnx).map_err(StartupError::Config)?;
+            Ok((Box::new(client), None, None))
+        } else if #[cfg(feature = "openai")] {
+            Ok((Box::new(OpenAiClient::new(settings.openai.clone())), None, None))
+        } else if #[cfg(feature = "tei")] {
+            Ok((Box::new(TeiClient::new(settings.tei.clone())), None, None))
         } else {
             unreachable!("No valid client configuration found")
         }
@@ -72,33 +221,620 @@ fn create_client(settings: &AppSettings) -> Box<dyn MightyClient> {
     }
 }
 
-static FILE_DESCRIPTOR_SET: &[u8] = include_bytes!("../proto/mighty_inference.bin");
+/// Creates the primary backend: the kind set by `client` when set, else the first enabled one.
+fn create_client(settings: &AppSettings) -> Result<Backend, StartupError> {
+    match settings.client {
+        #[cfg(feature = "binary")]
+        Some(BackendKind::Binary) => Ok(spawn_binary_client(settings)),
+        Some(kind) => {
+            let backend = BackendConfig {
+                kind,
+                base_url: None,
+            };
+            let (client, upstreams) = create_switchable_backend(&backend, settings)?;
+            Ok((client, None, upstreams))
+        }
+        None => create_default_client(settings),
+    }
+}
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let settings = AppSettings::new()?;
-    env::set_var("RUST_LOG", &settings.logging.level);
-    init_logging();
+#[cfg(feature = "binary")]
+fn spawn_binary_client(settings: &AppSettings) -> Backend {
+    let client = Arc::new(BinaryClient::spawn(settings.binary.clone()));
+    let model_upgrade: Arc<dyn ModelUpgrade> = client.clone();
+    (
+        Box::new(client as Arc<dyn MightyClient>),
+        Some(model_upgrade),
+        None,
+    )
+}
 
-    let client = create_client(&settings);
+/// Creates a backend of the given kind, e.g. of the fallback chain, configured by the section
+/// of its kind.
+fn create_backend(
+    backend: &BackendConfig,
+    settings: &AppSettings,
+) -> Result<Box<dyn MightyClient>, StartupError> {
+    match backend.kind {
+        #[cfg(any(feature = "rest", feature = "binary"))]
+        BackendKind::Rest => Ok(create_rest_client(
+            &rest_config(backend, settings)?,
+            &settings.resilience,
+        )),
+        #[cfg(feature = "binary")]
+        BackendKind::Binary => Ok(Box::new(
+            BinaryClient::try_spawn(settings.binary.clone()).map_err(StartupError::Config)?,
+        )),
+        #[cfg(feature = "ffi")]
+        BackendKind::Ffi => Ok(Box::new(
+            FfiClient::open(&settings.ffi).map_err(StartupError::Config)?,
+        )),
+        #[cfg(any(feature = "onnx", feature = "edge"))]
+        BackendKind::Onnx => Ok(Box::new(
+            OnnxClient::open(&settings.onnx).map_err(StartupError::Config)?,
+        )),
+        #[cfg(feature = "openai")]
+        BackendKind::OpenAi => Ok(Box::new(OpenAiClient::new(settings.openai.clone()))),
+        #[cfg(feature = "tei")]
+        BackendKind::Tei => Ok(Box::new(TeiClient::new(settings.tei.clone()))),
+        #[allow(unreachable_patterns)]
+        kind => Err(StartupError::FeatureMismatch(format!(
+            "the {} backend requires `--features {}`",
+            kind.as_str(),
+            kind.as_str()
+        ))),
+    }
+}
 
-    let addr = format!(
-        "{}:{}",
-        settings.grpc_server.address, settings.grpc_server.port
-    )
-    .parse()?;
-    info!("gRPC Server listening on {}", addr);
+/// Returns the `[mighty_server]` settings of a `rest` backend, at its own base URL if set.
+#[cfg(any(feature = "rest", feature = "binary"))]
+fn rest_config(
+    backend: &BackendConfig,
+    settings: &AppSettings,
+) -> Result<MightyServerConfig, StartupError> {
+    let mut config = settings.mighty_server.clone().unwrap_or_default();
+    if let Some(base_url) = &backend.base_url {
+        config.base_url = vec![base_url.clone()];
+        config.discovery.enabled = false;
+    }
+    if config.base_url.is_empty() {
+        return Err(StartupError::Config(
+            "Base URL for Mighty Server is missing".to_string(),
+        ));
+    }
+    Ok(config)
+}
+
+/// Creates a backend like `create_backend`, along with a handle to update its upstream
+/// instances while serving when it load balances them, as switched to by the `SwitchableClient`.
+fn create_switchable_backend(
+    backend: &BackendConfig,
+    settings: &AppSettings,
+) -> Result<switchable::Backend, StartupError> {
+    #[cfg(any(feature = "rest", feature = "binary"))]
+    if backend.kind == BackendKind::Rest {
+        let config = rest_config(backend, settings)?;
+        return Ok(create_rest_backend(&config, &settings.resilience));
+    }
+    Ok((create_backend(backend, settings)?, None))
+}
+
+/// Chains `client` with the configured fallback backends.
+fn create_fallback_client(
+    client: Box<dyn MightyClient>,
+    settings: &AppSettings,
+) -> Result<Box<dyn MightyClient>, StartupError> {
+    let mut backends = vec![("primary".to_string(), client)];
+    for backend in &settings.fallback.backends {
+        let name = backend.base_url.as_deref().unwrap_or(backend.kind.as_str());
+        info!("Falling back to the {} backend", name);
+        backends.push((name.to_string(), create_backend(backend, settings)?));
+    }
+    Ok(Box::new(FallbackClient::new(
+        backends,
+        settings.fallback.attempt_timeout,
+    )))
+}
+
+/// Serves the tasks configured in `[backends]` by their own backend, the others by `client`.
+fn create_task_routing_client(
+    client: Box<dyn MightyClient>,
+    settings: &AppSettings,
+) -> Result<Box<dyn MightyClient>, StartupError> {
+    let mut tasks = HashMap::new();
+    for (&task, backend) in &settings.backends {
+        info!(
+            "Serving {} from the {} backend",
+            task.as_str(),
+            backend.base_url.as_deref().unwrap_or(backend.kind.as_str())
+        );
+        tasks.insert(task, create_backend(backend, settings)?);
+    }
+    Ok(Box::new(TaskRoutingClient::new(client, tasks)))
+}
+
+/// Splits the calls between `client`, the stable backend, and the canary backend by weight. The
+/// canary backend is decorated like the primary one, with its own circuit breakers and cache,
+/// whose flush is added to `cache_flushes`.
+async fn create_canary_client(
+    client: Box<dyn MightyClient>,
+    settings: &AppSettings,
+    reloader: &ConfigReloader,
+    cache_flushes: &mut Vec<Arc<dyn CacheFlush>>,
+) -> Result<Box<dyn MightyClient>, StartupError> {
+    let backend = settings
+        .canary
+        .backend
+        .as_ref()
+        .ok_or_else(|| StartupError::Config("canary.backend must be set".to_string()))?;
+    info!(
+        "Serving {}% of the calls from the {} canary backend",
+        settings.canary.weight,
+        backend.base_url.as_deref().unwrap_or(backend.kind.as_str())
+    );
+    let (canary, cache_flush) =
+        create_decorated_backend(backend, "canary", settings, reloader).await?;
+    cache_flushes.extend(cache_flush);
+    Ok(Box::new(CanaryClient::new(
+        client,
+        canary,
+        &settings.canary,
+    )))
+}
+
+/// Routes the calls naming a model to the backend configured for it, the others to `client`.
+/// Each routed backend is decorated like the primary one, with its own circuit breakers and
+/// cache, whose flushes are added to `cache_flushes`.
+async fn create_routing_client(
+    client: Box<dyn MightyClient>,
+    settings: &AppSettings,
+    reloader: &ConfigReloader,
+    cache_flushes: &mut Vec<Arc<dyn CacheFlush>>,
+) -> Result<Box<dyn MightyClient>, StartupError> {
+    let mut routes = Vec::new();
+    for route in &settings.routing.routes {
+        info!(
+            "Routing the {} model to the {} backend",
+            route.model,
+            route.backend.kind.as_str()
+        );
+        let scope = format!("route:{}", route.model);
+        let (backend, cache_flush) =
+            create_decorated_backend(&route.backend, &scope, settings, reloader).await?;
+        cache_flushes.extend(cache_flush);
+        routes.push((route.model.clone(), backend));
+    }
+    Ok(Box::new(RoutingClient::new(
+        client,
+        routes,
+        settings.routing.default.clone(),
+    )))
+}
+
+/// Creates a backend decorated like the primary one, with its own circuit breakers and cache
+/// scoped to `scope`, returning the handle flushing its cache when enabled.
+async fn create_decorated_backend(
+    backend: &BackendConfig,
+    scope: &str,
+    settings: &AppSettings,
+    reloader: &ConfigReloader,
+) -> Result<(Box<dyn MightyClient>, Option<Arc<dyn CacheFlush>>), StartupError> {
+    let mut client = create_backend(backend, settings)?;
+    client = decorate_upstream(client, settings, reloader);
+    if settings.micro_batching.enabled {
+        client = Box::new(BatchingClient::new(client, &settings.micro_batching));
+    }
+    let circuit_breakers = Arc::new(CircuitBreakers::new(&settings.circuit_breaker));
+    decorate_calls(client, scope, settings, circuit_breakers).await
+}
+
+/// Wraps the client of a backend in the decorators of its upstream calls: tracing, metrics,
+/// rate limiting and retries.
+fn decorate_upstream(
+    mut client: Box<dyn MightyClient>,
+    settings: &AppSettings,
+    reloader: &ConfigReloader,
+) -> Box<dyn MightyClient> {
+    client = Box::new(TracedClient::new(client));
+    client = Box::new(MeteredClient::new(client));
+    if settings.rate_limit.enabled {
+        let rate_limiting = Arc::new(RateLimitingClient::new(client, &settings.rate_limit));
+        reloader.add(rate_limiting.clone());
+        client = Box::new(rate_limiting as Arc<dyn MightyClient>);
+    }
+    if RetryingClient::is_needed(&settings.resilience) {
+        client = Box::new(RetryingClient::new(client, &settings.resilience));
+    }
+    client
+}
+
+/// Wraps the client of a backend in the decorators of the calls it serves: validation, circuit
+/// breakers, request coalescing and the response cache, scoped to `scope`, returning the handle
+/// flushing the cache when enabled.
+async fn decorate_calls(
+    mut client: Box<dyn MightyClient>,
+    scope: &str,
+    settings: &AppSettings,
+    circuit_breakers: Arc<CircuitBreakers>,
+) -> Result<(Box<dyn MightyClient>, Option<Arc<dyn CacheFlush>>), StartupError> {
+    if settings.validation.is_enabled() {
+        client = Box::new(ValidatingClient::new(client, settings.validation.clone()));
+    }
+    if circuit_breakers.is_enabled() {
+        client = Box::new(CircuitBreakerClient::new(client, circuit_breakers));
+    }
+    if settings.single_flight.enabled {
+        client = Box::new(SingleFlightClient::new(client));
+    }
+    if !settings.cache.enabled {
+        return Ok((client, None));
+    }
+    let caching = create_caching_client(client, &settings.cache).await?;
+    let caching = Arc::new(caching.with_scope(scope));
+    Ok((
+        Box::new(caching.clone() as Arc<dyn MightyClient>),
+        Some(caching as Arc<dyn CacheFlush>),
+    ))
+}
+
+/// Fails startup if the upstream health check doesn't succeed in time, when required.
+async fn check_upstream(
+    client: &dyn MightyClient,
+    settings: &AppSettings,
+) -> Result<(), StartupError> {
+    if !settings.readiness.require_upstream_at_startup {
+        return Ok(());
+    }
+    let health_check = client.health_check(tonic::Request::new(Empty {}));
+    match tokio::time::timeout(settings.readiness.startup_timeout, health_check).await {
+        Ok(Ok(response)) if response.get_ref().success => Ok(()),
+        Ok(Ok(_)) => Err(StartupError::UpstreamUnreachable(
+            "health check reported failure".to_string(),
+        )),
+        Ok(Err(status)) => Err(StartupError::UpstreamUnreachable(
+            status.message().to_string(),
+        )),
+        Err(_) => Err(StartupError::UpstreamUnreachable(format!(
+            "health check timed out after {:?}",
+            settings.readiness.startup_timeout
+        ))),
+    }
+}
+
+fn main() -> ExitCode {
+    let result = configure().and_then(|configured| match configured {
+        Some((cli, settings)) => build_runtime(&settings.runtime)?.block_on(run(cli, settings)),
+        None => Ok(()),
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            error!("{}", error);
+            error.exit()
+        }
+    }
+}
+
+/// Wraps `client` to inject the configured faults, returning the handle toggling them, or leaves
+/// it untouched without the `chaos` feature.
+#[allow(clippy::type_complexity)]
+fn create_chaos_client(
+    client: Box<dyn MightyClient>,
+    settings: &AppSettings,
+) -> Result<(Box<dyn MightyClient>, Option<Arc<dyn FaultInjection>>), StartupError> {
+    cfg_if! {
+        if #[cfg(feature = "chaos")] {
+            if settings.chaos.enabled {
+                log::warn!("Injecting faults into upstream calls");
+            }
+            let chaos = Arc::new(ChaosClient::new(client, &settings.chaos));
+            let fault_injection: Arc<dyn FaultInjection> = chaos.clone();
+            Ok((Box::new(chaos as Arc<dyn MightyClient>), Some(fault_injection)))
+        } else {
+            if settings.chaos.enabled {
+                return Err(StartupError::FeatureMismatch(
+                    "chaos requires `--features chaos`".to_string(),
+                ));
+            }
+            Ok((client, None))
+        }
+    }
+}
+
+/// Wraps `client` to mirror calls to the configured shadow Mighty server.
+fn create_shadow_client(
+    client: Box<dyn MightyClient>,
+    settings: &AppSettings,
+) -> Result<Box<dyn MightyClient>, StartupError> {
+    cfg_if! {
+        if #[cfg(any(feature = "rest", feature = "binary"))] {
+            let mut config = settings.mighty_server.clone().unwrap_or_default();
+            config.base_url = vec![settings.shadow.base_url.clone()];
+            config.discovery.enabled = false;
+            info!("Mirroring calls to the shadow backend {}", settings.shadow.base_url);
+            let shadow = create_rest_client(&config, &settings.resilience);
+            Ok(Box::new(ShadowClient::new(client, shadow, &settings.shadow)))
+        } else {
+            let _ = (client, settings);
+            Err(StartupError::FeatureMismatch(
+                "shadow requires `--features rest` or `--features binary`".to_string(),
+            ))
+        }
+    }
+}
+
+/// Wraps `client` in a response cache, kept in memory or in Redis.
+async fn create_caching_client(
+    client: Box<dyn MightyClient>,
+    config: &CacheConfig,
+) -> Result<CachingClient, StartupError> {
+    let (CacheBackendKind::Redis, Some(url)) = (config.backend(), &config.redis_url) else {
+        return Ok(CachingClient::new(client, config));
+    };
+    cfg_if! {
+        if #[cfg(feature = "redis")] {
+            let backend = RedisCache::connect(url.expose(), &config.key_prefix)
+                .await
+                .map_err(|e| {
+                    StartupError::UpstreamUnreachable(format!("Redis cache is unreachable: {}", e))
+                })?;
+            info!("Caching responses in Redis");
+            Ok(CachingClient::with_backend(client, Box::new(backend), config))
+        } else {
+            let _ = url;
+            Err(StartupError::FeatureMismatch(
+                "cache.redis_url requires `--features redis`".to_string(),
+            ))
+        }
+    }
+}
 
-    // Set up the gRPC reflection service using the generated byte code
-    let reflection_service = tonic_reflection::server::Builder::configure()
-        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
-        .build()?;
+/// Parses the command line and loads the settings, before any runtime is started, or returns
+/// `None` once the command line is served, e.g. `--help`.
+fn configure() -> Result<Option<(Cli, AppSettings)>, StartupError> {
+    let cli = Cli::from_env().map_err(StartupError::Usage)?;
+    if cli.help {
+        println!("{}", USAGE);
+        return Ok(None);
+    }
+    #[cfg(feature = "edge")]
+    if cli.dump_embedded_config {
+        print!("{}", mighty_grpc::config::EMBEDDED_CONFIG);
+        return Ok(None);
+    }
 
-    Server::builder()
-        .add_service(create_mighty_inference_server(client))
-        .add_service(reflection_service)
-        .serve(addr)
-        .await?;
+    let settings = AppSettings::load(&cli)?;
+    if cli.print_config {
+        println!("{:#?}", settings);
+        return Ok(None);
+    }
+    settings.validate()?;
+    if let Some(format) = settings
+        .mighty_server
+        .as_ref()
+        .map(|mighty_server| mighty_server.response_format)
+        .filter(|format| !format.is_supported())
+    {
+        return Err(StartupError::FeatureMismatch(format!(
+            "mighty_server.response_format = \"{}\" requires `--features {}`",
+            format.as_str(),
+            format.as_str()
+        )));
+    }
+    Ok(Some((cli, settings)))
+}
+
+async fn run(cli: Cli, settings: AppSettings) -> Result<(), StartupError> {
+    let settings = Arc::new(settings);
+    let logger = init_logging(&settings.logging)?;
+    #[cfg(not(feature = "edge"))]
+    if !cli.config_file().is_file() {
+        warn!("No configuration file found, serving the default settings");
+    }
+    info!("Effective configuration: {:#?}", settings);
+    install_panic_hook();
+    let _tracing = init_tracing(&settings.tracing)?;
+    let reloader = Arc::new(ConfigReloader::new(
+        settings.clone(),
+        Box::new({
+            let cli = cli.clone();
+            move || AppSettings::load(&cli)
+        }),
+    ));
+    reloader.add(Arc::new(logger));
 
-    Ok(())
+    let (client, model_upgrade, upstreams) = match settings.vcr.mode {
+        VcrMode::Replay => (
+            Box::new(VcrClient::replay(&settings.vcr.fixtures)) as Box<dyn MightyClient>,
+            None,
+            None,
+        ),
+        _ => create_client(&settings)?,
+    };
+    let mut switchable = SwitchableClient::new(
+        client,
+        Box::new({
+            let reloader = reloader.clone();
+            move |backend| {
+                create_switchable_backend(backend, &reloader.settings()).map_err(|e| e.to_string())
+            }
+        }),
+        settings.readiness.startup_timeout,
+    );
+    if let Some(upstreams) = upstreams {
+        switchable = switchable.with_upstreams(upstreams);
+    }
+    let switchable = Arc::new(switchable);
+    let mut backend_switch: Arc<dyn BackendSwitch> = switchable.clone();
+    if cfg!(feature = "rest")
+        && matches!(settings.client, None | Some(BackendKind::Rest))
+        && settings.vcr.mode != VcrMode::Replay
+    {
+        // Reloads and SwitchBackend share the switch, the backends switched to by RPC prevailing
+        let upstream_reload = Arc::new(
+            UpstreamReload::new(backend_switch, &settings).with_upstreams(switchable.clone()),
+        );
+        reloader.add(upstream_reload.clone());
+        backend_switch = upstream_reload;
+    }
+    let mut client: Box<dyn MightyClient> = Box::new(switchable as Arc<dyn MightyClient>);
+    if !settings.backends.is_empty() {
+        client = create_task_routing_client(client, &settings)?;
+    }
+    let (client, fault_injection) = create_chaos_client(client, &settings)?;
+    let mut client = decorate_upstream(client, &settings, &reloader);
+    if settings.fallback.enabled {
+        client = create_fallback_client(client, &settings)?;
+    }
+    check_upstream(client.as_ref(), &settings).await?;
+    if settings.vcr.mode == VcrMode::Record {
+        client = Box::new(
+            VcrClient::record(client, &settings.vcr.fixtures).map_err(|e| {
+                StartupError::Config(format!(
+                    "Can't create the fixtures directory {}: {}",
+                    settings.vcr.fixtures.display(),
+                    e
+                ))
+            })?,
+        );
+    }
+    if settings.micro_batching.enabled {
+        client = Box::new(BatchingClient::new(client, &settings.micro_batching));
+    }
+    if settings.shadow.enabled {
+        client = create_shadow_client(client, &settings)?;
+    }
+    let circuit_breakers = Arc::new(CircuitBreakers::new(&settings.circuit_breaker));
+    let (mut client, cache_flush) =
+        decorate_calls(client, "primary", &settings, circuit_breakers.clone()).await?;
+    let mut cache_flushes: Vec<Arc<dyn CacheFlush>> = cache_flush.into_iter().collect();
+    // Above the cache, so the canary's responses are cached apart from the stable backend's
+    if settings.canary.enabled {
+        client = create_canary_client(client, &settings, &reloader, &mut cache_flushes).await?;
+    }
+    if !settings.routing.routes.is_empty() {
+        client = create_routing_client(client, &settings, &reloader, &mut cache_flushes).await?;
+    }
+    let cache_flush = match cache_flushes.len() {
+        0 => None,
+        1 => cache_flushes.pop(),
+        _ => Some(Arc::new(cache_flushes) as Arc<dyn CacheFlush>),
+    };
+    let quotas = Arc::new(Quotas::new(&settings.quotas));
+    if quotas.is_enabled() {
+        client = Box::new(QuotaClient::new(client, quotas.clone()));
+    }
+
+    // Run the readiness checks in the background, against the same client serving traffic, then
+    // monitor the health of the upstream
+    let readiness = Arc::new(Readiness::new(settings.readiness.clone()));
+    let client: Arc<dyn MightyClient> = Arc::from(client);
+    tokio::spawn({
+        let readiness = readiness.clone();
+        let client = client.clone();
+        async move {
+            readiness.warm_up(client.as_ref()).await;
+            readiness.monitor_upstream(client.as_ref()).await
+        }
+    });
+
+    let auth = AuthInterceptor::new(&settings)?;
+    let proxy = Arc::new(create_mighty_inference_proxy(Box::new(client), &settings));
+    reloader.add(proxy.clone());
+    #[cfg(unix)]
+    reloader
+        .reload_on_hangup()
+        .map_err(|e| StartupError::Server(format!("Can't handle SIGHUP: {}", e)))?;
+    if settings.hot_reload.watch {
+        reloader.watch(cli.config_file(), settings.hot_reload.interval);
+    }
+    let inference = create_mighty_inference_server_with_proxy(proxy, &settings);
+    let health = HealthLayer::new(readiness.clone());
+    let admin = create_mighty_admin_server(
+        settings.clone(),
+        readiness,
+        circuit_breakers,
+        model_upgrade,
+        Some(backend_switch),
+        fault_injection,
+        cache_flush,
+        quotas,
+        Some(reloader),
+    );
+    #[cfg(feature = "reflection")]
+    let reflection = create_reflection_server()?;
+    let client_rate_limit = ClientRateLimitInterceptor::new(&settings.client_rate_limit);
+    // Every listener is served by its own router over the same services and interceptors
+    let router = || {
+        let router = Server::builder()
+            .http2_keepalive_interval(settings.grpc_server.keepalive_interval)
+            .http2_keepalive_timeout(settings.grpc_server.keepalive_timeout)
+            .max_concurrent_streams(settings.grpc_server.max_concurrent_streams)
+            // The parent span of the spans of upstream calls
+            .trace_fn(grpc_request_span)
+            .layer(AccessLogLayer::new(&settings.logging))
+            .layer(tonic::service::interceptor(NetworkAclInterceptor::new(
+                &settings.network_acl,
+            )))
+            // Probes carry no credentials, health is served ahead of auth and rate limiting
+            .layer(health.clone())
+            .layer(AliasLayer::new(&settings.aliases))
+            .layer(tonic::service::interceptor(auth.clone()))
+            .layer(tonic::service::interceptor(client_rate_limit.clone()))
+            .add_service(inference.clone())
+            .add_service(admin.clone());
+        #[cfg(feature = "reflection")]
+        let router = router.add_service(reflection.clone());
+        router
+    };
+
+    let mut servers: Vec<BoxFuture<'static, Result<(), tonic::transport::Error>>> = Vec::new();
+    for listener in settings.grpc_server.all_listeners() {
+        #[cfg(not(feature = "tls"))]
+        if listener.tls.is_some() {
+            return Err(StartupError::FeatureMismatch(format!(
+                "the TLS of the gRPC listener {} requires `--features tls`",
+                listener
+            )));
+        }
+        let router = router();
+        let server = match &listener.path {
+            // Validated to be plaintext
+            #[cfg(unix)]
+            Some(path) => router.serve_with_incoming(bind_unix_socket(path)?).boxed(),
+            #[cfg(not(unix))]
+            Some(_) => {
+                return Err(StartupError::Config(format!(
+                    "the gRPC listener {} requires Unix domain sockets",
+                    listener
+                )));
+            }
+            None => {
+                let addr = format!("{}:{}", listener.address, listener.port)
+                    .parse()
+                    .map_err(|e| {
+                        StartupError::Config(format!("Invalid gRPC server address: {}", e))
+                    })?;
+                let incoming =
+                    TcpIncoming::new(addr, true, None).map_err(|e| StartupError::bind(addr, e))?;
+                match &listener.tls {
+                    #[cfg(feature = "tls")]
+                    Some(tls) => router
+                        .serve_with_incoming(TlsListener::new(tls)?.accept(incoming))
+                        .boxed(),
+                    _ => router.serve_with_incoming(incoming).boxed(),
+                }
+            }
+        };
+        match &listener.tls {
+            Some(_) => info!("gRPC Server listening on {} over TLS", listener),
+            None => info!("gRPC Server listening on {}", listener),
+        }
+        servers.push(server);
+    }
+    // The server stops as soon as any of its listeners fails
+    future::try_join_all(servers)
+        .await
+        .map(|_| ())
+        .map_err(|e| StartupError::Server(e.to_string()))
 }
@@ -0,0 +1,358 @@
+/*
+ * mighty-bench.rs
+ *
+ * A load generator for capacity planning: drives the gateway over gRPC, or a Mighty server
+ * directly over REST, from a number of concurrent workers for a given duration, then reports
+ * the throughput and latency percentiles of each task.
+ *
+ * Each worker sends one call at a time, picking its task at random according to the weights of
+ * the task mix, with texts of random words so responses aren't served from caches.
+ *
+ * Usage:
+ *   cargo run --release --bin mighty-bench -- --url grpc://127.0.0.1:50051 --concurrency 64 \
+ *     --duration 60s --mix embeddings=3,token_classification=1
+ *   cargo run --release --bin mighty-bench -- --url http://localhost:5050 --words 128
+ */
+
+use std::collections::BTreeMap;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::future::join_all;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use tonic::{Request, Status};
+
+use mighty_grpc::config::units::parse_duration;
+use mighty_grpc::config::{MightyServerConfig, ResilienceConfig, Task};
+use mighty_grpc::proto::mighty_proto::{Empty, QuestionAnswerRequest, RerankRequest, TextRequest};
+use mighty_grpc::services::clients::rest::create_rest_client;
+use mighty_grpc::services::clients::MightyClient;
+use mighty_grpc::startup::StartupError;
+
+const USAGE: &str = "\
+Usage: mighty-bench [OPTIONS]
+
+Options:
+      --url <URL>              The target: the gateway at grpc://host:port, or a Mighty server at
+                               http(s)://host:port [default: grpc://127.0.0.1:50051]
+  -c, --concurrency <N>        The number of concurrent workers, each sending one call at a time
+                               [default: 16]
+  -d, --duration <DURATION>    How long to send calls for, e.g. `60s` [default: 30s]
+      --words <N>              The number of words of each text [default: 32]
+      --texts <N>              The number of texts of each rerank call [default: 8]
+      --mix <TASK=WEIGHT,...>  The tasks called and their relative weights, e.g.
+                               `embeddings=3,token_classification=1` [default: embeddings=1]
+  -h, --help                   Prints this help";
+
+/// The words texts are made of.
+const WORDS: &[&str] = &[
+    "the",
+    "quick",
+    "brown",
+    "fox",
+    "jumps",
+    "over",
+    "lazy",
+    "dog",
+    "search",
+    "engine",
+    "query",
+    "document",
+    "river",
+    "mountain",
+    "Paris",
+    "Berlin",
+    "John",
+    "Smith",
+    "model",
+    "vector",
+    "language",
+    "inference",
+    "gateway",
+    "latency",
+    "request",
+    "response",
+    "cluster",
+    "replica",
+];
+
+/// The options of a run.
+#[derive(Debug, Clone, PartialEq)]
+struct Options {
+    url: String,
+    concurrency: usize,
+    duration: Duration,
+    words: usize,
+    texts: usize,
+    mix: Vec<(Task, u32)>,
+    help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            url: "grpc://127.0.0.1:50051".to_string(),
+            concurrency: 16,
+            duration: Duration::from_secs(30),
+            words: 32,
+            texts: 8,
+            mix: vec![(Task::Embeddings, 1)],
+            help: false,
+        }
+    }
+}
+
+impl Options {
+    /// Parses `args`, without the name of the binary, like the options of the server binaries.
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => {
+                    (name.to_string(), Some(value.to_string()))
+                }
+                _ => (arg, None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{} requires a value", name))
+            };
+            let count = |value: String| match value.parse() {
+                Ok(count) if count > 0 => Ok(count),
+                _ => Err(format!("{}: {:?} is not a positive number", name, value)),
+            };
+            match name.as_str() {
+                "--url" => options.url = value()?,
+                "-c" | "--concurrency" => options.concurrency = count(value()?)?,
+                "-d" | "--duration" => {
+                    options.duration =
+                        parse_duration(&value()?).map_err(|e| format!("{}: {}", name, e))?;
+                }
+                "--words" => options.words = count(value()?)?,
+                "--texts" => options.texts = count(value()?)?,
+                "--mix" => options.mix = parse_mix(&value()?)?,
+                "-h" | "--help" if inline.is_none() => options.help = true,
+                _ => return Err(format!("unexpected argument {:?}", name)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Parses a task mix, e.g. `embeddings=3,token_classification=1`, a task without weight
+/// weighing 1.
+fn parse_mix(value: &str) -> Result<Vec<(Task, u32)>, String> {
+    let mix = value
+        .split(',')
+        .map(|entry| {
+            let (name, weight) = entry.split_once('=').unwrap_or((entry, "1"));
+            let task = Task::parse(name.trim())
+                .ok_or_else(|| format!("--mix: {:?} is not a task", name))?;
+            let weight = weight
+                .trim()
+                .parse()
+                .map_err(|_| format!("--mix: {:?} is not a weight", weight))?;
+            Ok((task, weight))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    if mix.iter().all(|(_, weight)| *weight == 0) {
+        return Err("--mix: at least one task must weigh more than 0".to_string());
+    }
+    Ok(mix)
+}
+
+/// The outcomes of the calls of a task.
+#[derive(Debug, Default)]
+struct TaskStats {
+    latencies: Vec<Duration>,
+    errors: BTreeMap<String, usize>,
+}
+
+impl TaskStats {
+    fn merge(&mut self, other: TaskStats) {
+        self.latencies.extend(other.latencies);
+        for (code, count) in other.errors {
+            *self.errors.entry(code).or_default() += count;
+        }
+    }
+}
+
+/// Returns the `percentile` of sorted `latencies`, by the nearest-rank method.
+fn percentile(latencies: &[Duration], percentile: f64) -> Duration {
+    if latencies.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (percentile / 100.0 * latencies.len() as f64).ceil() as usize;
+    latencies[rank.clamp(1, latencies.len()) - 1]
+}
+
+/// Returns a text of `words` random words.
+fn text(rng: &mut impl Rng, words: usize) -> String {
+    (0..words)
+        .map(|_| *WORDS.choose(rng).expect("There are words"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Calls `task` with random texts, returning the call's status on failure.
+async fn call(client: &dyn MightyClient, task: Task, options: &Options) -> Result<(), Status> {
+    // Generated before awaiting, the generator isn't `Send`
+    let (first, second, texts) = {
+        let mut rng = rand::thread_rng();
+        let texts = match task {
+            Task::Rerank => (0..options.texts)
+                .map(|_| text(&mut rng, options.words))
+                .collect(),
+            _ => Vec::new(),
+        };
+        (
+            text(&mut rng, options.words),
+            text(&mut rng, options.words),
+            texts,
+        )
+    };
+    let request = || {
+        Request::new(TextRequest {
+            text: first.clone(),
+        })
+    };
+    match task {
+        Task::Embeddings => client.embeddings(request()).await.map(drop),
+        Task::SentenceTransformers => client.sentence_transformers(request()).await.map(drop),
+        Task::SequenceClassification => client.sequence_classification(request()).await.map(drop),
+        Task::TokenClassification => client.token_classification(request()).await.map(drop),
+        Task::QuestionAnswering => client
+            .question_answering(Request::new(QuestionAnswerRequest {
+                question: first.clone(),
+                context: second,
+            }))
+            .await
+            .map(drop),
+        Task::Rerank => client
+            .rerank(Request::new(RerankRequest {
+                query: first.clone(),
+                texts,
+            }))
+            .await
+            .map(drop),
+    }
+}
+
+/// Sends calls one at a time until `deadline`, returning the outcomes by task.
+async fn worker(
+    client: &dyn MightyClient,
+    options: &Options,
+    deadline: Instant,
+) -> BTreeMap<&'static str, TaskStats> {
+    let mut stats: BTreeMap<&'static str, TaskStats> = BTreeMap::new();
+    while Instant::now() < deadline {
+        let task = options
+            .mix
+            .choose_weighted(&mut rand::thread_rng(), |(_, weight)| *weight)
+            .expect("The mix is valid")
+            .0;
+        let start = Instant::now();
+        let result = call(client, task, options).await;
+        let task_stats = stats.entry(task.as_str()).or_default();
+        match result {
+            Ok(()) => task_stats.latencies.push(start.elapsed()),
+            Err(status) => {
+                *task_stats
+                    .errors
+                    .entry(format!("{:?}", status.code()))
+                    .or_default() += 1
+            }
+        }
+    }
+    stats
+}
+
+/// Prints the throughput and latency percentiles of each task, then of all of them, and the
+/// errors by status code.
+fn report(stats: BTreeMap<&'static str, TaskStats>, elapsed: Duration) {
+    println!(
+        "{:<24} {:>9} {:>7} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        "task", "calls", "errors", "calls/s", "p50", "p90", "p99", "max"
+    );
+    let mut total = TaskStats::default();
+    for (name, task_stats) in stats {
+        print_row(name, &task_stats, elapsed);
+        total.merge(task_stats);
+    }
+    print_row("total", &total, elapsed);
+    for (code, count) in &total.errors {
+        println!("{} calls failed with {}", count, code);
+    }
+}
+
+fn print_row(name: &str, stats: &TaskStats, elapsed: Duration) {
+    let mut latencies = stats.latencies.clone();
+    latencies.sort();
+    let calls = latencies.len() + stats.errors.values().sum::<usize>();
+    println!(
+        "{:<24} {:>9} {:>7} {:>9.1} {:>9.2?} {:>9.2?} {:>9.2?} {:>9.2?}",
+        name,
+        calls,
+        calls - latencies.len(),
+        calls as f64 / elapsed.as_secs_f64(),
+        percentile(&latencies, 50.0),
+        percentile(&latencies, 90.0),
+        percentile(&latencies, 99.0),
+        latencies.last().copied().unwrap_or_default(),
+    );
+}
+
+async fn run() -> Result<(), StartupError> {
+    let options = Options::parse(std::env::args().skip(1)).map_err(StartupError::Usage)?;
+    if options.help {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let config = MightyServerConfig {
+        base_url: vec![options.url.clone()],
+        ..MightyServerConfig::default()
+    };
+    let client = create_rest_client(&config, &ResilienceConfig::default());
+    client
+        .health_check(Request::new(Empty {}))
+        .await
+        .map_err(|status| {
+            StartupError::UpstreamUnreachable(format!("{}: {}", options.url, status.message()))
+        })?;
+
+    println!(
+        "Calling {} from {} workers for {:?}",
+        options.url, options.concurrency, options.duration
+    );
+    let client: Arc<dyn MightyClient> = Arc::from(client);
+    let options = Arc::new(options);
+    let start = Instant::now();
+    let deadline = start + options.duration;
+    // Spawned, so the workers are spread across the threads of the runtime
+    let workers = (0..options.concurrency).map(|_| {
+        let (client, options) = (client.clone(), options.clone());
+        tokio::spawn(async move { worker(client.as_ref(), &options, deadline).await })
+    });
+    let mut stats: BTreeMap<&'static str, TaskStats> = BTreeMap::new();
+    for worker_stats in join_all(workers).await {
+        let worker_stats = worker_stats.map_err(|e| StartupError::Server(e.to_string()))?;
+        for (task, task_stats) in worker_stats {
+            stats.entry(task).or_default().merge(task_stats);
+        }
+    }
+    report(stats, start.elapsed());
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => error.exit(),
+    }
+}
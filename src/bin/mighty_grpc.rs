@@ -0,0 +1,60 @@
+/*
+ * mighty_grpc.rs
+ *
+ * Maintenance commands for the gateway configuration, which don't start any server.
+ *
+ * Usage:
+ *   cargo run --bin mighty_grpc -- config lint [PATH]
+ *     Validates the configuration file at PATH (`config.toml`, `config.yaml` or `config.json` by
+ *     default), TOML, YAML or JSON by extension, against the typed settings, including
+ *     cross-field rules and unknown keys. Exits with code 78 (EX_CONFIG) when problems are found,
+ *     printing one per line.
+ *
+ *   cargo run --bin mighty_grpc -- config schema
+ *     Prints the JSON Schema of the configuration file, e.g. for editor autocompletion.
+ */
+
+use std::env;
+use std::process::ExitCode;
+
+use mighty_grpc::config::find_config_file;
+use mighty_grpc::config::lint::lint_file;
+use mighty_grpc::config::schema::schema;
+
+const USAGE: &str = "Usage: mighty_grpc config lint [PATH] | mighty_grpc config schema";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["config", "lint"] => lint(&find_config_file("config").to_string_lossy()),
+        ["config", "lint", path] => lint(path),
+        ["config", "schema"] => {
+            println!("{:#}", schema());
+            ExitCode::SUCCESS
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            ExitCode::from(64) // EX_USAGE
+        }
+    }
+}
+
+fn lint(path: &str) -> ExitCode {
+    match lint_file(path) {
+        Ok(problems) if problems.is_empty() => {
+            println!("{}: OK", path);
+            ExitCode::SUCCESS
+        }
+        Ok(problems) => {
+            for problem in problems {
+                println!("{}: {}", path, problem);
+            }
+            ExitCode::from(78) // EX_CONFIG
+        }
+        Err(error) => {
+            println!("{}: {}", path, error);
+            ExitCode::from(78)
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//! The command-line arguments of the server binaries, overriding the settings of the
+//! configuration file and of the environment.
+
+use std::path::PathBuf;
+
+use config::builder::DefaultState;
+use config::{ConfigBuilder, ConfigError};
+
+use super::{find_config_file, BackendKind};
+
+/// The usage of the server binaries.
+pub const USAGE: &str = "\
+Usage: grpc [OPTIONS] | api_and_grpc [OPTIONS]
+
+Options:
+  -c, --config <PATH>     The configuration file, TOML, YAML or JSON by extension, `config.toml`,
+                          `config.yaml` or `config.json` in the working directory by default
+      --grpc-port <PORT>  The port the gRPC server listens on, overriding `grpc_server.port`
+      --mighty-url <URL>  A base URL of the Mighty server, overriding `mighty_server.base_url`;
+                          repeat it to load balance across several replicas
+      --log-level <LEVEL> The log level, e.g. `info,mighty_grpc=debug`, overriding `logging.level`
+      --client <KIND>     The primary backend: rest, binary, ffi, onnx, openai or tei,
+                          overriding `client`
+      --print-config      Prints the effective configuration, secrets redacted, and exits
+  -h, --help              Prints this help";
+
+/// The `Cli` struct holds the command-line arguments, applied over the configuration file and
+/// the `MIGHTY_GRPC__*` environment variables.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Cli {
+    /// The configuration file, whose extension may be omitted.
+    pub config: Option<String>,
+    /// Overrides `grpc_server.port`.
+    pub grpc_port: Option<u16>,
+    /// Overrides `mighty_server.base_url` when not empty.
+    pub mighty_url: Vec<String>,
+    /// Overrides `logging.level`.
+    pub log_level: Option<String>,
+    /// Overrides `client`.
+    pub client: Option<BackendKind>,
+    /// Whether the effective configuration is printed instead of serving.
+    pub print_config: bool,
+    /// Whether the usage was asked for.
+    pub help: bool,
+    /// Whether the embedded configuration of edge bundles is printed instead of serving.
+    #[cfg(feature = "edge")]
+    pub dump_embedded_config: bool,
+}
+
+impl Cli {
+    /// Parses the arguments of the process.
+    ///
+    /// # Errors
+    ///
+    /// Returns the problem with the first argument that can't be parsed.
+    pub fn from_env() -> Result<Self, String> {
+        Self::parse(std::env::args().skip(1))
+    }
+
+    /// Parses `args`, without the name of the binary. Options take their value either as the
+    /// next argument or after `=`, e.g. `--grpc-port=50052`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the problem with the first argument that can't be parsed.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut cli = Cli::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => {
+                    (name.to_string(), Some(value.to_string()))
+                }
+                _ => (arg, None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{} requires a value", name))
+            };
+            match name.as_str() {
+                "-c" | "--config" => cli.config = Some(value()?),
+                "--grpc-port" => {
+                    let port = value()?;
+                    cli.grpc_port = Some(
+                        port.parse()
+                            .map_err(|_| format!("--grpc-port: {:?} is not a port", port))?,
+                    );
+                }
+                "--mighty-url" => cli.mighty_url.push(value()?),
+                "--log-level" => cli.log_level = Some(value()?),
+                "--client" => {
+                    let kind = value()?;
+                    cli.client =
+                        Some(BackendKind::parse(&kind).ok_or_else(|| {
+                            format!("--client: {:?} is not a kind of backend", kind)
+                        })?);
+                }
+                "--print-config" if inline.is_none() => cli.print_config = true,
+                "-h" | "--help" if inline.is_none() => cli.help = true,
+                #[cfg(feature = "edge")]
+                "--dump-embedded-config" if inline.is_none() => cli.dump_embedded_config = true,
+                _ => return Err(format!("unexpected argument {:?}", name)),
+            }
+        }
+        Ok(cli)
+    }
+
+    /// Returns the configuration file, the first of `config.toml`, `config.yaml`, `config.yml`
+    /// and `config.json` found in the working directory by default.
+    pub fn config_file(&self) -> PathBuf {
+        find_config_file(self.config.as_deref().unwrap_or("config"))
+    }
+
+    /// Adds the overrides of the arguments to `builder`, taking precedence over its sources.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ConfigError` if an override can't be set.
+    pub fn apply(
+        &self,
+        mut builder: ConfigBuilder<DefaultState>,
+    ) -> Result<ConfigBuilder<DefaultState>, ConfigError> {
+        if let Some(port) = self.grpc_port {
+            builder = builder.set_override("grpc_server.port", port)?;
+        }
+        if !self.mighty_url.is_empty() {
+            builder = builder.set_override("mighty_server.base_url", self.mighty_url.clone())?;
+        }
+        if let Some(level) = &self.log_level {
+            builder = builder.set_override("logging.level", level.as_str())?;
+        }
+        if let Some(kind) = self.client {
+            builder = builder.set_override("client", kind.as_str())?;
+        }
+        Ok(builder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::{Config, File, FileFormat};
+
+    use super::*;
+    use crate::config::AppSettings;
+
+    fn parse(args: &[&str]) -> Result<Cli, String> {
+        Cli::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_arguments_override_the_file() {
+        let cli = parse(&[
+            "--config=/etc/mighty-grpc/config.toml",
+            "--grpc-port",
+            "50052",
+            "--mighty-url",
+            "http://mighty-1:5050",
+            "--mighty-url=http://mighty-2:5050",
+            "--log-level",
+            "warn",
+            "--client",
+            "binary",
+        ])
+        .unwrap();
+        assert_eq!(cli.config.as_deref(), Some("/etc/mighty-grpc/config.toml"));
+        assert_eq!(
+            cli.config_file(),
+            PathBuf::from("/etc/mighty-grpc/config.toml")
+        );
+
+        let builder = Config::builder().add_source(File::from_str(
+            r#"
+            grpc_server = { address = "127.0.0.1", port = 50051 }
+            mighty_server = { base_url = "http://localhost:5050" }
+            logging = { level = "debug" }
+            "#,
+            FileFormat::Toml,
+        ));
+        let settings: AppSettings = cli
+            .apply(builder)
+            .unwrap()
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        assert_eq!(settings.grpc_server.port, 50052);
+        assert_eq!(
+            settings.mighty_server.unwrap().base_url,
+            vec!["http://mighty-1:5050", "http://mighty-2:5050"]
+        );
+        assert_eq!(settings.logging.level, "warn");
+        assert_eq!(settings.client, Some(BackendKind::Binary));
+    }
+
+    #[test]
+    fn test_invalid_arguments_are_rejected() {
+        assert_eq!(parse(&[]).unwrap(), Cli::default());
+        assert_eq!(
+            parse(&["--config"]).unwrap_err(),
+            "--config requires a value"
+        );
+        assert!(parse(&["--grpc-port", "http"]).is_err());
+        assert!(parse(&["--client", "grpc"]).is_err());
+        assert!(parse(&["--verbose"]).is_err());
+        assert!(parse(&["-h"]).unwrap().help);
+        assert!(parse(&["--print-config"]).unwrap().print_config);
+        assert!(parse(&["--print-config=true"]).is_err());
+    }
+}
@@ -0,0 +1,980 @@
+//! Cross-field rules checked by `mighty_grpc config lint`, beyond what deserializing the typed
+//! settings already rejects (unknown enum variants, malformed durations, wrong types), and the
+//! validation of the settings the server binaries refuse to start with.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use config::{Config, ConfigError, File};
+use reqwest::Url;
+use serde_json::Value;
+
+use super::schema::{schema, unknown_keys};
+use super::{
+    AppSettings, BackendConfig, BackendKind, CacheBackendKind, HealthCheckConfig, RuntimeFlavor,
+    Task, METHODS,
+};
+
+/// The length under which an API key is considered guessable.
+const MIN_API_KEY_LEN: usize = 16;
+
+/// The schemes of the base URLs of Mighty servers.
+const BASE_URL_SCHEMES: [&str; 4] = ["http://", "https://", "unix://", "grpc://"];
+
+/// Lints the configuration file at `path`: reports its unknown keys (typically typos, which
+/// would otherwise be silently ignored) and the cross-field rules its settings break.
+///
+/// # Errors
+///
+/// Returns a `ConfigError` if the file cannot be read, parsed or deserialized into the settings.
+pub fn lint_file(path: &str) -> Result<Vec<String>, ConfigError> {
+    let config = Config::builder()
+        .add_source(File::with_name(path))
+        .build()?;
+    let raw: Value = config.clone().try_deserialize()?;
+    let settings: AppSettings = config.try_deserialize()?;
+
+    let mut problems: Vec<String> = unknown_keys(&schema(), &raw)
+        .into_iter()
+        .map(|key| format!("{}: unknown key", key))
+        .collect();
+    problems.extend(validate(&settings));
+    problems.extend(lint(&settings));
+    Ok(problems)
+}
+
+/// Returns a description of every problem preventing the settings from being served, prefixed with
+/// the section at fault: ports out of range, base URLs that can't be parsed, backends missing the
+/// settings they require, TLS files and log directories that don't exist, unknown log levels and
+/// call rates that aren't positive. Unlike the rules of `lint`, these fail the startup and reloads,
+/// reported all at once.
+pub fn validate(settings: &AppSettings) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let servers = [
+        ("grpc_server", Some(&settings.grpc_server)),
+        ("api_server", settings.api_server.as_ref()),
+    ];
+    for (name, server) in servers {
+        if server.is_some_and(|server| server.port == 0) {
+            problems.push(format!("{}: port must be in [1, 65535]", name));
+        }
+    }
+    // The primary listener's port is checked with the other servers'
+    for (i, listener) in settings.grpc_server.all_listeners().iter().enumerate() {
+        let section = match i {
+            0 => "grpc_server".to_string(),
+            i => format!("grpc_server.listeners[{}]", i - 1),
+        };
+        if let Some(path) = &listener.path {
+            if listener.tls.is_some() {
+                problems.push(format!(
+                    "{}: tls isn't supported on a Unix domain socket",
+                    section
+                ));
+            }
+            let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+            if dir.is_some_and(|dir| !dir.is_dir()) {
+                problems.push(format!(
+                    "{}: the directory of path {} doesn't exist",
+                    section,
+                    path.display()
+                ));
+            }
+        } else if i > 0 && listener.port == 0 {
+            problems.push(format!("{}: port must be in [1, 65535]", section));
+        }
+        if let Some(tls) = &listener.tls {
+            let files = [
+                ("cert_path", Some(&tls.cert_path)),
+                ("key_path", Some(&tls.key_path)),
+                ("client_ca_path", tls.client_ca_path.as_ref()),
+            ];
+            for (key, path) in files {
+                if let Some(path) = path.filter(|path| !path.is_file()) {
+                    problems.push(format!(
+                        "{}.tls: {} {} doesn't exist",
+                        section,
+                        key,
+                        path.display()
+                    ));
+                }
+            }
+        }
+    }
+
+    let logging = &settings.logging;
+    let mut targets: Vec<_> = logging.targets.iter().collect();
+    targets.sort();
+    for (target, level) in targets {
+        if level.parse::<log::LevelFilter>().is_err() {
+            problems.push(format!(
+                "logging.targets: {:?} of {} is not a level",
+                level, target
+            ));
+        }
+    }
+    if let Some(file) = &logging.file {
+        let dir = file.parent().filter(|dir| !dir.as_os_str().is_empty());
+        if dir.is_some_and(|dir| !dir.is_dir()) {
+            problems.push(format!(
+                "logging: the directory of file {} doesn't exist",
+                file.display()
+            ));
+        }
+    }
+
+    let runtime = [
+        ("worker_threads", settings.runtime.worker_threads),
+        (
+            "max_blocking_threads",
+            settings.runtime.max_blocking_threads,
+        ),
+    ];
+    for (key, _) in runtime.iter().filter(|(_, threads)| *threads == Some(0)) {
+        problems.push(format!("runtime: {} must be positive", key));
+    }
+    for task in settings
+        .readiness
+        .warm_up
+        .tasks
+        .iter()
+        .filter(|task| matches!(task, Task::QuestionAnswering | Task::Rerank))
+    {
+        problems.push(format!(
+            "readiness.warm_up: task {} doesn't take a single text",
+            task.as_str()
+        ));
+    }
+    let warm_up = &settings.readiness.warm_up;
+    if settings.readiness.require_cache_warmup
+        && !(warm_up.enabled && !warm_up.texts.is_empty() && !warm_up.tasks.is_empty())
+    {
+        problems.push(
+            "readiness: require_cache_warmup needs a warm_up sending texts to tasks".to_string(),
+        );
+    }
+
+    let mut rates = vec![
+        (
+            "rate_limit".to_string(),
+            settings.rate_limit.requests_per_second,
+        ),
+        (
+            "client_rate_limit".to_string(),
+            settings.client_rate_limit.requests_per_second,
+        ),
+    ];
+    for (index, client) in settings.client_rate_limit.clients.iter().enumerate() {
+        rates.push((
+            format!("client_rate_limit.clients[{}]", index),
+            client.requests_per_second,
+        ));
+    }
+    for (section, _) in rates
+        .iter()
+        .filter(|(_, rate)| rate.is_nan() || *rate <= 0.0)
+    {
+        problems.push(format!("{}: requests_per_second must be positive", section));
+    }
+
+    let base_urls = settings
+        .mighty_server
+        .as_ref()
+        .map(|mighty_server| mighty_server.base_url.as_slice())
+        .unwrap_or_default();
+    for base_url in base_urls {
+        if let Some(reason) = invalid_base_url(base_url) {
+            problems.push(format!(
+                "mighty_server.base_url: {:?} is invalid: {}",
+                base_url, reason
+            ));
+        }
+    }
+    if settings.primary_backend() == Some(BackendKind::Rest) && base_urls.is_empty() {
+        problems.push("mighty_server: base_url is required by the rest backend".to_string());
+    }
+
+    let mut backends: Vec<(String, &BackendConfig)> = Vec::new();
+    for (index, backend) in settings.fallback.backends.iter().enumerate() {
+        backends.push((format!("fallback.backends[{}]", index), backend));
+    }
+    for (task, backend) in &settings.backends {
+        backends.push((format!("backends.{}", task.as_str()), backend));
+    }
+    for (index, route) in settings.routing.routes.iter().enumerate() {
+        backends.push((format!("routing.routes[{}]", index), &route.backend));
+    }
+    if let Some(backend) = &settings.canary.backend {
+        backends.push(("canary.backend".to_string(), backend));
+    }
+    for (section, backend) in backends {
+        match &backend.base_url {
+            Some(base_url) => {
+                if let Some(reason) = invalid_base_url(base_url) {
+                    problems.push(format!(
+                        "{}: base_url {:?} is invalid: {}",
+                        section, base_url, reason
+                    ));
+                }
+            }
+            None if backend.kind == BackendKind::Rest && base_urls.is_empty() => {
+                problems.push(format!(
+                    "{}: a rest backend requires base_url or mighty_server.base_url",
+                    section
+                ));
+            }
+            None => {}
+        }
+    }
+    let shadow = &settings.shadow;
+    if shadow.enabled && !shadow.base_url.is_empty() {
+        if let Some(reason) = invalid_base_url(&shadow.base_url) {
+            problems.push(format!(
+                "shadow: base_url {:?} is invalid: {}",
+                shadow.base_url, reason
+            ));
+        }
+    }
+
+    problems
+}
+
+/// Returns why the base URL of a Mighty server can't be called, if it can't.
+fn invalid_base_url(base_url: &str) -> Option<String> {
+    if base_url.starts_with("grpcs://") {
+        return Some("gRPC upstreams are called in plaintext, use grpc://".to_string());
+    }
+    if !BASE_URL_SCHEMES
+        .iter()
+        .any(|scheme| base_url.starts_with(scheme))
+    {
+        return Some("it must start with http://, https://, unix:// or grpc://".to_string());
+    }
+    if let Some(path) = base_url.strip_prefix("unix://") {
+        return path
+            .is_empty()
+            .then(|| "the socket path is missing".to_string());
+    }
+    let url = match base_url.strip_prefix("grpc://") {
+        Some(authority) => format!("http://{}", authority),
+        None => base_url.to_string(),
+    };
+    match Url::parse(&url) {
+        Err(e) => Some(e.to_string()),
+        Ok(url) if url.host_str().is_none_or(str::is_empty) => {
+            Some("the host is missing".to_string())
+        }
+        Ok(_) => None,
+    }
+}
+
+/// Returns a description of every rule the settings break, prefixed with the section at fault,
+/// or an empty list when they are consistent.
+pub fn lint(settings: &AppSettings) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if settings.logging.file.is_some() && settings.logging.max_file_size == 0 {
+        problems.push("logging: max_file_size must be positive".to_string());
+    }
+
+    if let Some(api_server) = &settings.api_server {
+        if api_server.address == settings.grpc_server.address
+            && api_server.port == settings.grpc_server.port
+        {
+            problems.push(format!(
+                "api_server: listens on the gRPC server address {}:{}",
+                api_server.address, api_server.port
+            ));
+        }
+        let grpc_only = [
+            ("tls", api_server.tls.is_some()),
+            (
+                "max_receive_message_size",
+                api_server.max_receive_message_size.is_some(),
+            ),
+            (
+                "max_send_message_size",
+                api_server.max_send_message_size.is_some(),
+            ),
+            (
+                "keepalive_interval",
+                api_server.keepalive_interval.is_some(),
+            ),
+            ("keepalive_timeout", api_server.keepalive_timeout.is_some()),
+            (
+                "max_concurrent_streams",
+                api_server.max_concurrent_streams.is_some(),
+            ),
+            ("listeners", !api_server.listeners.is_empty()),
+        ];
+        for (key, _) in grpc_only.iter().filter(|(_, set)| *set) {
+            problems.push(format!(
+                "api_server: {} is only supported by the gRPC server",
+                key
+            ));
+        }
+    }
+    let grpc_server = &settings.grpc_server;
+    let sizes = [
+        (
+            "max_receive_message_size",
+            grpc_server.max_receive_message_size,
+        ),
+        ("max_send_message_size", grpc_server.max_send_message_size),
+    ];
+    for (key, _) in sizes.iter().filter(|(_, size)| *size == Some(0)) {
+        problems.push(format!("grpc_server: {} must be positive", key));
+    }
+    let timings = [
+        ("keepalive_interval", grpc_server.keepalive_interval),
+        ("keepalive_timeout", grpc_server.keepalive_timeout),
+    ];
+    for (key, _) in timings
+        .iter()
+        .filter(|(_, timing)| *timing == Some(Duration::ZERO))
+    {
+        problems.push(format!("grpc_server: {} must be positive", key));
+    }
+    if grpc_server.max_concurrent_streams == Some(0) {
+        problems.push("grpc_server: max_concurrent_streams must be positive".to_string());
+    }
+    if grpc_server.keepalive_timeout.is_some() && grpc_server.keepalive_interval.is_none() {
+        problems.push("grpc_server: keepalive_timeout needs keepalive_interval".to_string());
+    }
+    let listeners = grpc_server.all_listeners();
+    for (i, listener) in listeners.iter().enumerate() {
+        let section = match i {
+            0 => "grpc_server".to_string(),
+            i => format!("grpc_server.listeners[{}]", i - 1),
+        };
+        if listeners[..i]
+            .iter()
+            .any(|other| other.to_string() == listener.to_string())
+        {
+            problems.push(format!("{}: {} is listened on twice", section, listener));
+        }
+        if let Some(tls) = &listener.tls {
+            if tls.reload_interval == Some(Duration::ZERO) {
+                problems.push(format!("{}.tls: reload_interval must be positive", section));
+            }
+            if tls.client_cert_optional && tls.client_ca_path.is_none() {
+                problems.push(format!(
+                    "{}.tls: client_cert_optional needs client_ca_path",
+                    section
+                ));
+            }
+        }
+    }
+
+    if let Some(mighty_server) = &settings.mighty_server {
+        lint_health_check(
+            "mighty_server.health_check",
+            &mighty_server.health_check,
+            &mut problems,
+        );
+
+        let hedging = &mighty_server.hedging;
+        if !(hedging.percentile > 0.0 && hedging.percentile <= 100.0) {
+            problems.push(format!(
+                "mighty_server.hedging: percentile {} must be in (0, 100]",
+                hedging.percentile
+            ));
+        }
+        if hedging.min_delay > hedging.max_delay {
+            problems.push(format!(
+                "mighty_server.hedging: min_delay {:?} exceeds max_delay {:?}",
+                hedging.min_delay, hedging.max_delay
+            ));
+        }
+
+        let mut aliases: Vec<_> = mighty_server.field_aliases.iter().collect();
+        aliases.sort();
+        for (alias, target) in aliases {
+            if target.is_empty() || alias == target {
+                problems.push(format!(
+                    "mighty_server.field_aliases: {} must be renamed to another field",
+                    alias
+                ));
+            }
+        }
+        let ramp = &mighty_server.ramp;
+        if ramp.enabled && ramp.steps.is_empty() {
+            problems.push("mighty_server.ramp: steps must not be empty".to_string());
+        }
+        if ramp
+            .steps
+            .iter()
+            .any(|step| !(*step > 0.0 && *step <= 100.0))
+        {
+            problems.push("mighty_server.ramp: steps must be percentages in (0, 100]".to_string());
+        }
+        if ramp.steps.windows(2).any(|steps| steps[0] >= steps[1]) {
+            problems.push("mighty_server.ramp: steps must be increasing".to_string());
+        }
+        if !(0.0..=1.0).contains(&ramp.max_error_rate) {
+            problems.push(format!(
+                "mighty_server.ramp: max_error_rate {} must be in [0, 1]",
+                ramp.max_error_rate
+            ));
+        }
+    }
+
+    let binary = &settings.binary;
+    if binary.workers == 0 {
+        problems.push("binary: workers must be at least 1".to_string());
+    }
+    // The standby workers started by model upgrades use the ports following the current ones
+    let last_port = u32::from(binary.port) + 2 * u32::from(binary.workers.max(1)) - 1;
+    if last_port > u32::from(u16::MAX) {
+        problems.push(format!(
+            "binary: {} workers and their standbys from port {} exceed port {}",
+            binary.workers,
+            binary.port,
+            u16::MAX
+        ));
+    }
+    let worker_ports = u32::from(binary.port)..=last_port;
+    let servers = [
+        ("grpc_server", Some(&settings.grpc_server)),
+        ("api_server", settings.api_server.as_ref()),
+    ];
+    for (name, server) in servers {
+        if let Some(server) = server.filter(|server| worker_ports.contains(&u32::from(server.port)))
+        {
+            problems.push(format!(
+                "binary: worker ports {}-{} overlap the {} port {}",
+                binary.port, last_port, name, server.port
+            ));
+        }
+    }
+    if binary.restart_delay > binary.max_restart_delay {
+        problems.push(format!(
+            "binary: restart_delay {:?} exceeds max_restart_delay {:?}",
+            binary.restart_delay, binary.max_restart_delay
+        ));
+    }
+    lint_health_check("binary.health_check", &binary.health_check, &mut problems);
+
+    for task in Task::ALL {
+        let thresholds = settings.circuit_breaker.thresholds(task);
+        if thresholds.failure_threshold == 0 || thresholds.half_open_probes == 0 {
+            problems.push(format!(
+                "circuit_breaker: failure_threshold and half_open_probes of {} must be at least 1",
+                task.as_str()
+            ));
+        }
+        let policy = settings.resilience.policy(task);
+        if policy.max_attempts == 0 {
+            problems.push(format!(
+                "resilience: max_attempts of {} must be at least 1",
+                task.as_str()
+            ));
+        }
+        if policy.multiplier < 1.0 {
+            problems.push(format!(
+                "resilience: multiplier {} of {} must be at least 1",
+                policy.multiplier,
+                task.as_str()
+            ));
+        }
+        if policy.initial_backoff > policy.max_backoff {
+            problems.push(format!(
+                "resilience: initial_backoff {:?} of {} exceeds max_backoff {:?}",
+                policy.initial_backoff,
+                task.as_str(),
+                policy.max_backoff
+            ));
+        }
+        if policy.attempt_timeout == Some(Duration::ZERO) {
+            problems.push(format!(
+                "resilience: attempt_timeout of {} must be positive",
+                task.as_str()
+            ));
+        }
+    }
+
+    let rate_limit = &settings.rate_limit;
+    if rate_limit.enabled {
+        if rate_limit.requests_per_second <= 0.0 {
+            problems.push(format!(
+                "rate_limit: requests_per_second {} must be positive",
+                rate_limit.requests_per_second
+            ));
+        }
+        if rate_limit.burst == 0 {
+            problems.push("rate_limit: burst must be at least 1".to_string());
+        }
+        if rate_limit.max_concurrency == 0 {
+            problems.push("rate_limit: max_concurrency must be at least 1".to_string());
+        }
+    }
+    for method in settings.compression.methods.keys() {
+        if !METHODS.contains(&method.as_str()) {
+            problems.push(format!("compression.methods: unknown method {}", method));
+        }
+    }
+    for method in settings.endpoints.methods.keys() {
+        if !METHODS.contains(&method.as_str()) {
+            problems.push(format!("endpoints: unknown method {}", method));
+        }
+    }
+    let timeouts = &settings.timeouts;
+    if timeouts.default == Some(Duration::ZERO) {
+        problems.push("timeouts: default must be positive".to_string());
+    }
+    for (method, timeout) in &timeouts.methods {
+        if !METHODS.contains(&method.as_str()) {
+            problems.push(format!("timeouts.methods: unknown method {}", method));
+        } else if timeout.is_zero() {
+            problems.push(format!("timeouts.methods: {} must be positive", method));
+        }
+    }
+    let request_validation = &settings.request_validation;
+    if request_validation.max_text_bytes == Some(0) {
+        problems.push("request_validation: max_text_bytes must be positive".to_string());
+    }
+    if request_validation.max_text_chars == Some(0) {
+        problems.push("request_validation: max_text_chars must be positive".to_string());
+    }
+    let tracing = &settings.tracing;
+    if !(0.0..=1.0).contains(&tracing.sample_ratio) {
+        problems.push(format!(
+            "tracing: sample_ratio {} must be in [0, 1]",
+            tracing.sample_ratio
+        ));
+    }
+    if tracing.enabled && tracing.endpoint.is_empty() {
+        problems.push("tracing: enabled without an endpoint".to_string());
+    }
+    let chaos = &settings.chaos;
+    for (name, rate) in [
+        ("error_rate", chaos.error_rate),
+        ("truncation_rate", chaos.truncation_rate),
+        ("malformed_json_rate", chaos.malformed_json_rate),
+    ] {
+        if !(0.0..=1.0).contains(&rate) {
+            problems.push(format!("chaos: {} {} must be in [0, 1]", name, rate));
+        }
+    }
+    let fallback = &settings.fallback;
+    if fallback.enabled && fallback.backends.is_empty() {
+        problems.push("fallback: backends must list at least one backend".to_string());
+    }
+    if fallback
+        .attempt_timeout
+        .is_some_and(|timeout| timeout.is_zero())
+    {
+        problems.push("fallback: attempt_timeout must be positive".to_string());
+    }
+    for (index, backend) in fallback.backends.iter().enumerate() {
+        if backend.base_url.is_some() && backend.kind != BackendKind::Rest {
+            problems.push(format!(
+                "fallback.backends[{}]: base_url only applies to rest backends",
+                index
+            ));
+        }
+    }
+    for (task, backend) in &settings.backends {
+        if backend.base_url.is_some() && backend.kind != BackendKind::Rest {
+            problems.push(format!(
+                "backends.{}: base_url only applies to rest backends",
+                task.as_str()
+            ));
+        }
+    }
+    let canary = &settings.canary;
+    match &canary.backend {
+        None if canary.enabled => problems.push("canary: backend must be set".to_string()),
+        Some(backend) if backend.base_url.is_some() && backend.kind != BackendKind::Rest => {
+            problems.push("canary.backend: base_url only applies to rest backends".to_string());
+        }
+        _ => {}
+    }
+    let weights = canary
+        .weights
+        .iter()
+        .map(|(task, &weight)| (format!("weights.{}", task.as_str()), weight));
+    for (field, weight) in std::iter::once(("weight".to_string(), canary.weight)).chain(weights) {
+        if !(0.0..=100.0).contains(&weight) {
+            problems.push(format!("canary.{}: must be within [0, 100]", field));
+        }
+    }
+    let routing = &settings.routing;
+    let mut models = HashSet::new();
+    for (index, route) in routing.routes.iter().enumerate() {
+        if route.model.is_empty() {
+            problems.push(format!(
+                "routing.routes[{}]: model must not be empty",
+                index
+            ));
+        } else if !models.insert(route.model.as_str()) {
+            problems.push(format!(
+                "routing.routes[{}]: model {} is routed twice",
+                index, route.model
+            ));
+        }
+        if route.backend.base_url.is_some() && route.backend.kind != BackendKind::Rest {
+            problems.push(format!(
+                "routing.routes[{}]: base_url only applies to rest backends",
+                index
+            ));
+        }
+    }
+    if let Some(default) = &routing.default {
+        if !models.contains(default.as_str()) {
+            problems.push(format!("routing: default model {} has no route", default));
+        }
+    }
+    let shadow = &settings.shadow;
+    if shadow.enabled {
+        if shadow.base_url.is_empty() {
+            problems.push("shadow: base_url is required when enabled".to_string());
+        }
+        if !(0.0..=1.0).contains(&shadow.sample_rate) {
+            problems.push(format!(
+                "shadow: sample_rate {} must be in [0, 1]",
+                shadow.sample_rate
+            ));
+        }
+        if shadow.max_in_flight == 0 {
+            problems.push("shadow: max_in_flight must be at least 1".to_string());
+        }
+        if shadow.timeout.is_zero() {
+            problems.push("shadow: timeout must be positive".to_string());
+        }
+    }
+    if settings.micro_batching.enabled && settings.micro_batching.max_batch_size == 0 {
+        problems.push("micro_batching: max_batch_size must be at least 1".to_string());
+    }
+    if settings.cache.enabled && settings.cache.capacity == 0 {
+        problems.push("cache: capacity must be at least 1".to_string());
+    }
+    match (settings.cache.backend(), &settings.cache.redis_url) {
+        (CacheBackendKind::Redis, None) => {
+            problems.push("cache: the redis backend requires a redis_url".to_string());
+        }
+        (CacheBackendKind::Memory, Some(_)) => {
+            problems.push("cache.redis_url: only applies to the redis backend".to_string());
+        }
+        _ => {}
+    }
+    if settings.cache.max_entry_size == Some(0) {
+        problems.push("cache: max_entry_size must be positive".to_string());
+    }
+    if let Some(url) = &settings.cache.redis_url {
+        if !["redis://", "rediss://", "redis+unix://"]
+            .iter()
+            .any(|scheme| url.expose().starts_with(scheme))
+        {
+            problems.push(
+                "cache.redis_url: must start with redis://, rediss:// or redis+unix://".to_string(),
+            );
+        }
+    }
+    let readiness = &settings.readiness;
+    if readiness.upstream_failure_threshold > 0 && readiness.upstream_check_interval.is_zero() {
+        problems.push("readiness: upstream_check_interval must not be zero".to_string());
+    }
+    let warm_up = &readiness.warm_up;
+    if warm_up.enabled && !warm_up.texts.is_empty() && warm_up.tasks.is_empty() {
+        problems.push("readiness.warm_up: texts are sent to no tasks".to_string());
+    }
+    if settings.hot_reload.watch && settings.hot_reload.interval.is_zero() {
+        problems.push("hot_reload: interval must not be zero".to_string());
+    }
+    if settings.runtime.flavor == RuntimeFlavor::CurrentThread
+        && settings.runtime.worker_threads.is_some()
+    {
+        problems
+            .push("runtime: worker_threads is ignored by the current_thread flavor".to_string());
+    }
+
+    if !matches!(
+        settings.onnx.task,
+        Task::Embeddings | Task::SequenceClassification
+    ) {
+        problems.push(format!(
+            "onnx: task {} can't be served by ONNX models",
+            settings.onnx.task.as_str()
+        ));
+    }
+
+    let http_base_urls = [
+        ("openai.base_url", &settings.openai.base_url),
+        ("tei.base_url", &settings.tei.base_url),
+    ];
+    for (key, base_url) in http_base_urls {
+        if !["http://", "https://"]
+            .iter()
+            .any(|scheme| base_url.starts_with(scheme))
+        {
+            problems.push(format!(
+                "{}: {:?} must start with http:// or https://",
+                key, base_url
+            ));
+        }
+    }
+    let openai = &settings.openai;
+    if openai.dimensions == Some(0) {
+        problems.push("openai: dimensions must be at least 1".to_string());
+    }
+
+    if !(-1.0..=1.0).contains(&settings.recently_similar.threshold) {
+        problems.push(format!(
+            "recently_similar: threshold {} must be a cosine similarity in [-1, 1]",
+            settings.recently_similar.threshold
+        ));
+    }
+
+    let mut aliased = HashSet::new();
+    for alias in &settings.aliases {
+        if alias.from == alias.to {
+            problems.push(format!("aliases: {} is aliased to itself", alias.from));
+        }
+        if !aliased.insert(&alias.from) {
+            problems.push(format!("aliases: {} is aliased more than once", alias.from));
+        }
+    }
+
+    let api_keys = &settings.api_keys;
+    if api_keys.enabled && api_keys.keys.is_empty() {
+        problems.push("api_keys: enabled without any key, every call is rejected".to_string());
+    }
+    let (mut names, mut keys) = (HashSet::new(), HashSet::new());
+    for api_key in &api_keys.keys {
+        if !names.insert(&api_key.name) {
+            problems.push(format!("api_keys: {} is configured twice", api_key.name));
+        }
+        if api_key.key.expose().len() < MIN_API_KEY_LEN {
+            problems.push(format!(
+                "api_keys: the {} key is shorter than {} characters",
+                api_key.name, MIN_API_KEY_LEN
+            ));
+        }
+        if !keys.insert(&api_key.key) {
+            problems.push(format!("api_keys: the {} key is not unique", api_key.name));
+        }
+    }
+
+    let jwt = &settings.jwt;
+    if jwt.enabled && jwt.issuer.is_empty() {
+        problems.push("jwt: enabled without an issuer".to_string());
+    }
+    if jwt.enabled && jwt.audience.is_empty() {
+        problems.push("jwt: no audience, tokens for any audience are accepted".to_string());
+    }
+    if jwt.jwks_refresh_interval.is_zero() {
+        problems.push("jwt: jwks_refresh_interval must not be zero".to_string());
+    }
+
+    let client_rate_limit = &settings.client_rate_limit;
+    if client_rate_limit.enabled {
+        let mut budgets = vec![(
+            "client_rate_limit".to_string(),
+            client_rate_limit.requests_per_second,
+            client_rate_limit.burst,
+        )];
+        let mut clients = HashSet::new();
+        for client in &client_rate_limit.clients {
+            if !clients.insert(&client.identity) {
+                problems.push(format!(
+                    "client_rate_limit: {} is configured twice",
+                    client.identity
+                ));
+            }
+            let section = format!("client_rate_limit: {}", client.identity);
+            budgets.push((section, client.requests_per_second, client.burst));
+        }
+        for (section, requests_per_second, burst) in budgets {
+            if requests_per_second <= 0.0 {
+                problems.push(format!(
+                    "{}: requests_per_second {} must be positive",
+                    section, requests_per_second
+                ));
+            }
+            if burst == 0 {
+                problems.push(format!("{}: burst must be at least 1", section));
+            }
+        }
+    }
+
+    let quotas = &settings.quotas;
+    let mut callers = HashSet::new();
+    for caller in &quotas.callers {
+        if !callers.insert(&caller.identity) {
+            problems.push(format!("quotas: {} is configured twice", caller.identity));
+        }
+    }
+    if quotas.enabled && !settings.api_keys.enabled && !settings.jwt.enabled {
+        problems.push(
+            "quotas: without api_keys or jwt, only callers with client certificates are accounted"
+                .to_string(),
+        );
+    }
+
+    problems
+}
+
+fn lint_health_check(section: &str, health_check: &HealthCheckConfig, problems: &mut Vec<String>) {
+    if health_check.unhealthy_threshold == 0 || health_check.healthy_threshold == 0 {
+        problems.push(format!("{}: thresholds must be at least 1", section));
+    }
+    if health_check.enabled && health_check.interval.is_zero() {
+        problems.push(format!("{}: interval must not be zero", section));
+    }
+    if health_check.enabled && health_check.timeout.is_zero() {
+        problems.push(format!("{}: timeout must not be zero", section));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::{Config, File, FileFormat};
+
+    use super::*;
+
+    fn settings(toml: &str) -> AppSettings {
+        Config::builder()
+            .add_source(File::from_str(toml, FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_documented_config_is_consistent() {
+        let documented = settings(include_str!("../../config.toml"));
+        assert_eq!(validate(&documented), Vec::<String>::new());
+        assert_eq!(lint(&documented), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_embedded_edge_config_is_consistent() {
+        let embedded = settings(include_str!("../../config.edge.toml"));
+        assert_eq!(lint(&embedded), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_lint() {
+        let settings = settings(
+            r#"
+            logging = { level = "info" }
+            runtime = { flavor = "current_thread", worker_threads = 2 }
+            readiness = { warm_up = { enabled = true, texts = ["warm"], tasks = [] } }
+
+            [grpc_server]
+            address = "127.0.0.1"
+            port = 5051
+            keepalive_timeout = "20s"
+            listeners = [{ address = "127.0.0.1", port = 5051 }]
+
+            [mighty_server]
+            base_url = ["http://localhost:5050"]
+            hedging = { min_delay = "2s", max_delay = "1s" }
+            ramp = { steps = [10, 1] }
+            field_aliases = { embeddings = "outputs", took = "took" }
+
+            [binary]
+            workers = 2
+            port = 5050
+
+            [circuit_breaker.tasks]
+            embeddings = { half_open_probes = 0 }
+
+            [resilience.tasks]
+            rerank = { max_attempts = 0, attempt_timeout = "5s" }
+            "#,
+        );
+        assert_eq!(
+            lint(&settings),
+            vec![
+                "grpc_server: keepalive_timeout needs keepalive_interval",
+                "grpc_server.listeners[0]: 127.0.0.1:5051 is listened on twice",
+                "mighty_server.hedging: min_delay 2s exceeds max_delay 1s",
+                "mighty_server.field_aliases: took must be renamed to another field",
+                "mighty_server.ramp: steps must be increasing",
+                "binary: worker ports 5050-5053 overlap the grpc_server port 5051",
+                "circuit_breaker: failure_threshold and half_open_probes of embeddings must be at least 1",
+                "resilience: max_attempts of rerank must be at least 1",
+                "readiness.warm_up: texts are sent to no tasks",
+                "runtime: worker_threads is ignored by the current_thread flavor",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let invalid = settings(
+            r#"
+            logging = { level = "info", targets = { h2 = "quiet" }, file = "/nonexistent/grpc.log" }
+            runtime = { max_blocking_threads = 0 }
+            readiness = { require_cache_warmup = true, warm_up = { tasks = ["embeddings", "rerank"] } }
+            rate_limit = { requests_per_second = 0.0 }
+            client_rate_limit = { clients = [{ identity = "batch", requests_per_second = -1.0, burst = 1 }] }
+
+            [grpc_server]
+            address = "127.0.0.1"
+            port = 0
+            tls = { cert_path = "/nonexistent/tls.crt", key_path = "/nonexistent/tls.key" }
+
+            [[grpc_server.listeners]]
+            address = "0.0.0.0"
+
+            [[grpc_server.listeners]]
+            path = "/nonexistent/grpc.sock"
+            tls = { cert_path = "/nonexistent/tls.crt", key_path = "/nonexistent/tls.key" }
+
+            [mighty_server]
+            base_url = ["http://", "unix://", "grpc://mighty:50052", "grpcs://mighty:50052", "localhost:5051"]
+
+            [[fallback.backends]]
+            kind = "rest"
+            base_url = "http//mighty-eu:5050"
+            "#,
+        );
+        assert_eq!(
+            validate(&invalid),
+            vec![
+                "grpc_server: port must be in [1, 65535]",
+                "grpc_server.tls: cert_path /nonexistent/tls.crt doesn't exist",
+                "grpc_server.tls: key_path /nonexistent/tls.key doesn't exist",
+                "grpc_server.listeners[0]: port must be in [1, 65535]",
+                "grpc_server.listeners[1]: tls isn't supported on a Unix domain socket",
+                "grpc_server.listeners[1]: the directory of path /nonexistent/grpc.sock doesn't exist",
+                "grpc_server.listeners[1].tls: cert_path /nonexistent/tls.crt doesn't exist",
+                "grpc_server.listeners[1].tls: key_path /nonexistent/tls.key doesn't exist",
+                "logging.targets: \"quiet\" of h2 is not a level",
+                "logging: the directory of file /nonexistent/grpc.log doesn't exist",
+                "runtime: max_blocking_threads must be positive",
+                "readiness.warm_up: task rerank doesn't take a single text",
+                "readiness: require_cache_warmup needs a warm_up sending texts to tasks",
+                "rate_limit: requests_per_second must be positive",
+                "client_rate_limit.clients[0]: requests_per_second must be positive",
+                "mighty_server.base_url: \"http://\" is invalid: empty host",
+                "mighty_server.base_url: \"unix://\" is invalid: the socket path is missing",
+                "mighty_server.base_url: \"grpcs://mighty:50052\" is invalid: gRPC upstreams are called in plaintext, use grpc://",
+                "mighty_server.base_url: \"localhost:5051\" is invalid: it must start with http://, https://, unix:// or grpc://",
+                "fallback.backends[0]: base_url \"http//mighty-eu:5050\" is invalid: it must start with http://, https://, unix:// or grpc://",
+            ]
+        );
+
+        let incomplete = settings(
+            r#"
+            client = "rest"
+            grpc_server = { address = "127.0.0.1", port = 5051 }
+            logging = { level = "info" }
+            mighty_server = { user_agent = "search-gateway" }
+            routing = { routes = [{ model = "ner", kind = "rest" }] }
+            "#,
+        );
+        assert_eq!(
+            validate(&incomplete),
+            vec![
+                "mighty_server: base_url is required by the rest backend",
+                "routing.routes[0]: a rest backend requires base_url or mighty_server.base_url",
+            ]
+        );
+    }
+}
@@ -1,58 +1,2323 @@
-use config::{Config, ConfigError, File};
-use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[cfg(feature = "edge")]
+use config::FileFormat;
+use config::{Config, ConfigError, Environment, File};
+use serde::{Deserialize, Deserializer};
+
+use self::cli::Cli;
+use self::secrets::Secret;
+
+pub mod cli;
+pub mod lint;
+pub mod schema;
+pub mod secrets;
+pub mod units;
+
+/// The prefix of the environment variables overriding settings, e.g.
+/// `MIGHTY_GRPC__GRPC_SERVER__PORT=50052` for `grpc_server.port`.
+pub const ENV_PREFIX: &str = "MIGHTY_GRPC";
+
+/// The extensions of the configuration files, in the order they are looked up: TOML, YAML or
+/// JSON files of the same structure.
+pub const CONFIG_EXTENSIONS: [&str; 4] = ["toml", "yaml", "yml", "json"];
+
+/// The default configuration compiled into edge bundles, from `config.edge.toml`.
+#[cfg(feature = "edge")]
+pub const EMBEDDED_CONFIG: &str = include_str!("../../config.edge.toml");
+
+/// The base URL of the Mighty server when `[mighty_server]` isn't configured.
+pub const DEFAULT_BASE_URL: &str = "http://localhost:5050";
 
 /// Represents the configuration for a server, either API or gRPC.
 #[derive(Debug, Deserialize)]
+#[serde(default)]
 pub struct ServerConfig {
     /// The address on which the server will listen.
     pub address: String,
     /// The port on which the server will listen.
     pub port: u16,
+    /// Serves over TLS when set, with the `tls` feature. Only supported by the gRPC server.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// The maximum size of a request message, e.g. `"16MiB"`, after decompression. Larger
+    /// requests fail with `OUT_OF_RANGE`. 4 MiB when unset. Only supported by the gRPC server.
+    #[serde(default, deserialize_with = "units::option_byte_size")]
+    pub max_receive_message_size: Option<u64>,
+    /// The maximum size of a response message, e.g. `"64MiB"`. Larger responses fail with
+    /// `OUT_OF_RANGE`. Unlimited when unset. Only supported by the gRPC server.
+    #[serde(default, deserialize_with = "units::option_byte_size")]
+    pub max_send_message_size: Option<u64>,
+    /// The interval at which HTTP/2 pings are sent on idle connections, e.g. `"30s"`, so dead
+    /// peers are detected and connections kept open through proxies. Never sent when unset.
+    /// Only supported by the gRPC server.
+    #[serde(default, deserialize_with = "units::option_duration")]
+    pub keepalive_interval: Option<Duration>,
+    /// How long to wait for the acknowledgement of a keepalive ping before closing the
+    /// connection. 20 seconds when unset. Only supported by the gRPC server.
+    #[serde(default, deserialize_with = "units::option_duration")]
+    pub keepalive_timeout: Option<Duration>,
+    /// The maximum number of calls served at once on a connection. Unlimited when unset. Only
+    /// supported by the gRPC server.
+    #[serde(default)]
+    pub max_concurrent_streams: Option<u32>,
+    /// Further listeners serving the same server, e.g. plaintext on the loopback interface for
+    /// sidecars alongside TLS on the pod IP, or a Unix domain socket. Only supported by the gRPC
+    /// server of the `grpc` binary.
+    #[serde(default)]
+    pub listeners: Vec<ListenerConfig>,
+}
+
+impl ServerConfig {
+    /// Returns every listener of the server: its own `address`, `port` and `tls`, then
+    /// `listeners`.
+    pub fn all_listeners(&self) -> Vec<ListenerConfig> {
+        let primary = ListenerConfig {
+            address: self.address.clone(),
+            port: self.port,
+            path: None,
+            tls: self.tls.clone(),
+        };
+        std::iter::once(primary)
+            .chain(self.listeners.iter().cloned())
+            .collect()
+    }
+}
+
+/// Represents a listener of the gRPC server: a TCP address and port, or a Unix domain socket.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ListenerConfig {
+    /// The address listened on, e.g. `"10.0.3.7"`.
+    pub address: String,
+    /// The port listened on.
+    pub port: u16,
+    /// The Unix domain socket listened on instead of `address` and `port`, e.g.
+    /// `"/run/mighty-grpc/grpc.sock"`, replacing any stale socket file.
+    pub path: Option<PathBuf>,
+    /// Serves the listener over TLS when set, with the `tls` feature.
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1".to_string(),
+            port: 0,
+            path: None,
+            tls: None,
+        }
+    }
+}
+
+impl fmt::Display for ListenerConfig {
+    /// Formats the listener as `address:port`, or as `unix:path` for a Unix domain socket.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "unix:{}", path.display()),
+            None => write!(f, "{}:{}", self.address, self.port),
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    /// The gRPC server on the loopback interface, at port 50051.
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1".to_string(),
+            port: 50051,
+            tls: None,
+            max_receive_message_size: None,
+            max_send_message_size: None,
+            keepalive_interval: None,
+            keepalive_timeout: None,
+            max_concurrent_streams: None,
+            listeners: Vec::new(),
+        }
+    }
+}
+
+/// Represents the TLS configuration of a server.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TlsConfig {
+    /// The PEM file of the server certificate chain, leaf first.
+    pub cert_path: PathBuf,
+    /// The PEM file of the server private key.
+    pub key_path: PathBuf,
+    /// The interval at which both files are checked for changes, e.g. `"1m"`, so a rotated
+    /// certificate is served to new connections without a restart. Never reloaded when unset.
+    #[serde(default, deserialize_with = "units::option_duration")]
+    pub reload_interval: Option<Duration>,
+    /// The PEM bundle of the CAs client certificates are verified against, enabling mutual
+    /// TLS. The identity of a verified client certificate is attached to its calls.
+    pub client_ca_path: Option<PathBuf>,
+    /// Whether clients without a certificate are still served when `client_ca_path` is set,
+    /// their calls carrying no identity.
+    #[serde(default)]
+    pub client_cert_optional: bool,
 }
 
 /// Represents the configuration for the Mighty server.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Clone, Deserialize)]
 pub struct MightyServerConfig {
-    /// The base URL for the Mighty server. This is optional as it's not required when running
-    /// in `binary` mode.
-    pub base_url: Option<String>,
+    /// The base URL(s) for the Mighty server, given either as a single string or as a list of
+    /// replicas to load balance across. This is optional (empty) as it's not required when
+    /// running in `binary` mode.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub base_url: Vec<String>,
+    /// The strategy used to distribute calls when several base URLs are configured.
+    #[serde(default)]
+    pub load_balancing: LoadBalancingStrategy,
+    /// Per task overrides of `load_balancing`, e.g. `embeddings = "consistent_hash"`.
+    #[serde(default)]
+    pub task_load_balancing: HashMap<Task, LoadBalancingStrategy>,
+    /// Health checking of upstream instances when several base URLs are configured.
+    #[serde(default)]
+    pub health_check: HealthCheckConfig,
+    /// DNS based discovery of upstream instances behind the base URL hostnames.
+    #[serde(default)]
+    pub discovery: DiscoveryConfig,
+    /// Connection pool settings for the REST client.
+    #[serde(default)]
+    pub pool: PoolConfig,
+    /// An identifier of the gateway deployment, e.g. `"search-gateway-eu1"`, prepended to the
+    /// `User-Agent` sent upstream (`mighty-grpc/<version>`) so upstream operators can attribute
+    /// traffic.
+    pub user_agent: Option<String>,
+    /// Whether the address of the calling gRPC peer is sent upstream in `X-Forwarded-For`,
+    /// appended to any `x-forwarded-for` metadata received from the caller.
+    #[serde(default)]
+    pub forward_client_address: bool,
+    /// The maximum size of an upstream response body, e.g. `"64MiB"`, before and after
+    /// decompression. Larger responses fail with `RESOURCE_EXHAUSTED`. Unlimited when unset.
+    #[serde(default, deserialize_with = "units::option_byte_size")]
+    pub max_body_size: Option<u64>,
+    /// Hedging of slow calls across upstream instances when several are configured.
+    #[serde(default)]
+    pub hedging: HedgingConfig,
+    /// Gradual ramp-up of traffic to upstream instances discovered or reloaded while others serve.
+    #[serde(default)]
+    pub ramp: RampConfig,
+    /// Whether upstream JSON responses with missing or mistyped fields fail with `INTERNAL`,
+    /// instead of being converted with zeros and empty strings in their place.
+    #[serde(default)]
+    pub strict_responses: bool,
+    /// Whether the upstream serves batch calls: a POST of a JSON `texts` array answered with a
+    /// JSON array of responses, in order. Otherwise batches are sent one text per call.
+    #[serde(default)]
+    pub batch_endpoints: bool,
+    /// The fields of upstream responses to rename before conversion, by upstream name, e.g.
+    /// `embeddings = "outputs"` for upstreams naming the fields of their responses differently.
+    #[serde(default)]
+    pub field_aliases: HashMap<String, String>,
+    /// The body format requested from the upstream, which may still answer with JSON.
+    #[serde(default)]
+    pub response_format: ResponseFormat,
+}
+
+/// The strategy used to pick an upstream instance for each call.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadBalancingStrategy {
+    /// Cycle through the upstreams in order.
+    #[default]
+    RoundRobin,
+    /// Pick the upstream with the fewest calls currently in flight.
+    LeastOutstanding,
+    /// Pick an upstream uniformly at random.
+    Random,
+    /// Pick the upstream the request text hashes to, so identical inputs hit the same upstream
+    /// and benefit from its caches. Calls without text are distributed round robin.
+    ConsistentHash,
+}
+
+/// An inference task served by the Mighty server, used to configure behavior per task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Task {
+    Embeddings,
+    QuestionAnswering,
+    SentenceTransformers,
+    SequenceClassification,
+    TokenClassification,
+    Rerank,
+}
+
+impl Task {
+    /// Every task, in declaration order.
+    pub const ALL: [Task; 6] = [
+        Task::Embeddings,
+        Task::QuestionAnswering,
+        Task::SentenceTransformers,
+        Task::SequenceClassification,
+        Task::TokenClassification,
+        Task::Rerank,
+    ];
+
+    /// Returns the task of the given configuration name, e.g. `question_answering`.
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|task| task.as_str() == name)
+    }
+
+    /// Returns the configuration name of the task, e.g. `question_answering`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Task::Embeddings => "embeddings",
+            Task::QuestionAnswering => "question_answering",
+            Task::SentenceTransformers => "sentence_transformers",
+            Task::SequenceClassification => "sequence_classification",
+            Task::TokenClassification => "token_classification",
+            Task::Rerank => "rerank",
+        }
+    }
+}
+
+/// Represents the health checking settings used to eject and re-admit upstream instances.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HealthCheckConfig {
+    /// Whether upstreams are actively probed via their `/healthcheck` endpoint. Failed calls
+    /// are always counted (passive health checking).
+    pub enabled: bool,
+    /// The interval between active probes, e.g. `"10s"`.
+    #[serde(deserialize_with = "units::duration")]
+    pub interval: Duration,
+    /// How long a probe may take before counting as failed, e.g. `"2s"`.
+    #[serde(deserialize_with = "units::duration")]
+    pub timeout: Duration,
+    /// The number of consecutive failed calls or probes after which an upstream is ejected.
+    pub unhealthy_threshold: u32,
+    /// The number of consecutive successful calls or probes after which an ejected upstream is
+    /// re-admitted.
+    pub healthy_threshold: u32,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(2),
+            unhealthy_threshold: 3,
+            healthy_threshold: 1,
+        }
+    }
+}
+
+/// Represents the DNS discovery settings. When enabled, the hostname of each base URL is
+/// re-resolved periodically and every resolved address becomes a load balanced upstream, which
+/// suits hostnames backed by several instances such as headless Kubernetes services.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DiscoveryConfig {
+    /// Whether base URL hostnames are re-resolved periodically.
+    pub enabled: bool,
+    /// The interval between resolutions, e.g. `"30s"`.
+    #[serde(deserialize_with = "units::duration")]
+    pub refresh_interval: Duration,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            refresh_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Represents the hedging settings. When enabled, a call that hasn't completed after a delay
+/// derived from recent latencies is duplicated to another upstream and whichever response
+/// arrives first is used, the other call being cancelled.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HedgingConfig {
+    /// Whether slow calls are hedged.
+    pub enabled: bool,
+    /// The latency percentile, over recent successful calls, after which a call is hedged.
+    pub percentile: f64,
+    /// The lower bound of the hedging delay, e.g. `"10ms"`.
+    #[serde(deserialize_with = "units::duration")]
+    pub min_delay: Duration,
+    /// The upper bound of the hedging delay, e.g. `"1s"`. Also used until enough latency
+    /// samples have been collected.
+    #[serde(deserialize_with = "units::duration")]
+    pub max_delay: Duration,
+    /// The number of recent call latencies the percentile is computed over.
+    pub window_size: usize,
+}
+
+impl Default for HedgingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            percentile: 95.0,
+            min_delay: Duration::from_millis(10),
+            max_delay: Duration::from_secs(1),
+            window_size: 1000,
+        }
+    }
+}
+
+/// Represents the traffic ramp-up schedule applied to newly added upstream instances.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RampConfig {
+    /// Whether newly added upstreams are ramped up rather than receiving their full share at once.
+    pub enabled: bool,
+    /// The percentages of its full traffic share a new upstream receives at each step, e.g.
+    /// `[1, 10, 50, 100]`.
+    pub steps: Vec<f64>,
+    /// The total duration of the ramp, split evenly across the steps, e.g. `"30m"`.
+    #[serde(deserialize_with = "units::duration")]
+    pub duration: Duration,
+    /// The error rate of a ramping upstream above which its ramp is rolled back and it stops
+    /// receiving traffic.
+    pub max_error_rate: f64,
+    /// The number of calls to a ramping upstream before its error rate is evaluated.
+    pub min_requests: u64,
+}
+
+impl Default for RampConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            steps: vec![1.0, 10.0, 50.0, 100.0],
+            duration: Duration::from_secs(30 * 60),
+            max_error_rate: 0.05,
+            min_requests: 20,
+        }
+    }
+}
+
+/// Represents the connection pool settings applied to the upstream HTTP connections.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct PoolConfig {
+    /// The maximum number of idle connections kept per upstream host. Unlimited when unset.
+    pub max_idle_per_host: Option<usize>,
+    /// How long an idle connection is kept before being closed, e.g. `"90s"`. Defaults to 90
+    /// seconds when unset.
+    #[serde(default, deserialize_with = "units::option_duration")]
+    pub idle_timeout: Option<Duration>,
+    /// The HTTP version used for upstream connections.
+    #[serde(default)]
+    pub http_version: HttpVersion,
+    /// The number of HTTP/2 channels opened to each `grpc://` upstream, calls being spread
+    /// round-robin across them. Defaults to 1 when unset.
+    pub grpc_channels: Option<usize>,
+}
+
+/// The HTTP version preference for upstream connections.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpVersion {
+    /// Negotiate the version with the upstream (HTTP/1.1 unless upgraded).
+    #[default]
+    Auto,
+    /// Only use HTTP/1.1, with one request in flight per connection.
+    Http1,
+    /// Use HTTP/2 with prior knowledge, multiplexing requests over few connections.
+    Http2,
+}
+
+/// The body format of upstream responses: JSON, or a binary format whose floats are decoded
+/// without parsing their text, which the upstream must support.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseFormat {
+    #[default]
+    Json,
+    /// MessagePack (`application/msgpack`), with `--features msgpack`.
+    Msgpack,
+    /// CBOR (`application/cbor`), with `--features cbor`.
+    Cbor,
+}
+
+impl ResponseFormat {
+    /// Returns the configuration name of the format, which is also the feature decoding it.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResponseFormat::Json => "json",
+            ResponseFormat::Msgpack => "msgpack",
+            ResponseFormat::Cbor => "cbor",
+        }
+    }
+
+    /// Whether the gateway was built with the feature decoding the format.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            ResponseFormat::Json => true,
+            ResponseFormat::Msgpack => cfg!(feature = "msgpack"),
+            ResponseFormat::Cbor => cfg!(feature = "cbor"),
+        }
+    }
 }
 
 /// Represents the logging configuration.
 #[derive(Debug, Deserialize)]
+#[serde(default)]
 pub struct LoggingConfig {
     /// The logging level (e.g., "info", "debug").
     pub level: String,
+    /// The levels of specific targets, overriding `level`, e.g. `hyper = "warn"` or
+    /// `"mighty_grpc::services" = "debug"`. `h2` and `hyper` log at `warn` by default.
+    pub targets: HashMap<String, String>,
+    /// The format of log lines.
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Whether a line is logged for every RPC served, whatever the logging level.
+    #[serde(default)]
+    pub access_log: bool,
+    /// The file log lines are written to instead of the standard error, e.g.
+    /// `"/var/log/mighty-grpc.log"`.
+    pub file: Option<PathBuf>,
+    /// The size the log file is rotated past, e.g. `"100MiB"`.
+    #[serde(deserialize_with = "units::byte_size")]
+    pub max_file_size: u64,
+    /// The number of rotated log files kept, `.1` being the most recent; `0` keeps none.
+    pub max_files: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            targets: HashMap::from([
+                ("h2".to_string(), "warn".to_string()),
+                ("hyper".to_string(), "warn".to_string()),
+            ]),
+            format: LogFormat::default(),
+            access_log: false,
+            file: None,
+            max_file_size: 100 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+/// The format of log lines.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human-readable text lines, as formatted by `env_logger`.
+    #[default]
+    Text,
+    /// One JSON object per line, for log pipelines.
+    Json,
 }
 
 /// Represents the entire application settings, which includes gRPC server, API server,
 /// Mighty server, and logging configurations.
 #[derive(Debug, Deserialize)]
 pub struct AppSettings {
-    /// Configuration for the gRPC server.
+    /// Configuration for the gRPC server, listening on 127.0.0.1:50051 by default.
+    #[serde(default)]
     pub grpc_server: ServerConfig,
     /// Optional configuration for the API server.
     pub api_server: Option<ServerConfig>,
-    /// Optional configuration for the Mighty server.
+    /// Optional configuration for the Mighty server, at `DEFAULT_BASE_URL` by default.
+    #[serde(default = "default_mighty_server")]
     pub mighty_server: Option<MightyServerConfig>,
-    /// Configuration for logging.
+    /// The kind of the primary backend, e.g. `"binary"`, configured by its own section. The
+    /// first enabled feature of `rest`, `binary`, `ffi`, `onnx`, `openai` and `tei` when unset.
+    pub client: Option<BackendKind>,
+    /// Configuration for logging, at the `info` level by default.
+    #[serde(default)]
     pub logging: LoggingConfig,
+    /// The export of spans to an OpenTelemetry collector, with the `otel` feature.
+    #[serde(default)]
+    pub tracing: TracingConfig,
+    /// Validation rules applied to upstream responses.
+    #[serde(default)]
+    pub validation: ValidationConfig,
+    /// Legacy RPC paths served as deprecated aliases of current endpoints.
+    #[serde(default)]
+    pub aliases: Vec<AliasConfig>,
+    /// The API keys calls must carry in `x-api-key`.
+    #[serde(default)]
+    pub api_keys: ApiKeysConfig,
+    /// The validation of JWT bearer tokens calls must carry, with the `jwt` feature.
+    #[serde(default)]
+    pub jwt: JwtConfig,
+    /// The callers allowed to operate the gateway through the admin RPCs.
+    #[serde(default)]
+    pub admin: AdminConfig,
+    /// The debug output callers may request, none by default.
+    #[serde(default)]
+    pub debug: DebugConfig,
+    /// The per-client bound on the rate of calls to the gRPC server.
+    #[serde(default)]
+    pub client_rate_limit: ClientRateLimitConfig,
+    /// The IP address ranges allowed and denied to call the gRPC server.
+    #[serde(default)]
+    pub network_acl: NetworkAclConfig,
+    /// The daily usage quotas of authenticated callers.
+    #[serde(default)]
+    pub quotas: QuotasConfig,
+    /// Checks that must pass before the gateway reports itself ready.
+    #[serde(default)]
+    pub readiness: ReadinessConfig,
+    /// Preprocessing applied to the texts of batch (`StreamEmbeddings`) requests.
+    #[serde(default)]
+    pub batch: BatchConfig,
+    /// The compression of the messages exchanged with gRPC callers.
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    /// The server-side timeouts of the calls served, by method.
+    #[serde(default)]
+    pub timeouts: TimeoutsConfig,
+    /// The checks applied to the texts of requests before they are sent upstream.
+    #[serde(default)]
+    pub request_validation: RequestValidationConfig,
+    /// The methods of the inference service served, all by default.
+    #[serde(default)]
+    pub endpoints: EndpointsConfig,
+    /// The Mighty server run as a managed subprocess in `binary` mode.
+    #[serde(default)]
+    pub binary: BinaryConfig,
+    /// The Mighty shared library loaded in-process in `ffi` mode.
+    #[serde(default)]
+    pub ffi: FfiConfig,
+    /// The ONNX model served in-process in `onnx` mode.
+    #[serde(default)]
+    pub onnx: OnnxConfig,
+    /// The OpenAI-compatible embeddings API calls are translated to in `openai` mode.
+    #[serde(default)]
+    pub openai: OpenAiConfig,
+    /// The Text Embeddings Inference server calls are translated to in `tei` mode.
+    #[serde(default)]
+    pub tei: TeiConfig,
+    /// The window of recent embeddings `RecentlySimilar` checks texts against.
+    #[serde(default)]
+    pub recently_similar: RecentlySimilarConfig,
+    /// Circuit breakers failing calls fast while a task keeps failing upstream.
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+    /// The retries, attempt timeouts, hedging and breaker thresholds of each task's upstream
+    /// calls.
+    #[serde(default)]
+    pub resilience: ResilienceConfig,
+    /// The bound on the rate and concurrency of the calls sent upstream.
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    /// The faults injected into upstream calls, with the `chaos` feature.
+    #[serde(default)]
+    pub chaos: ChaosConfig,
+    /// The backends tried in order when the primary one is unavailable.
+    #[serde(default)]
+    pub fallback: FallbackConfig,
+    /// The mirroring of inference calls to a shadow backend, compared to the primary.
+    #[serde(default)]
+    pub shadow: ShadowConfig,
+    /// The backends serving each task, rather than the primary backend, e.g. embeddings and
+    /// question answering served by different Mighty servers.
+    #[serde(default)]
+    pub backends: HashMap<Task, BackendConfig>,
+    /// The backends serving each model, selected per call by `x-model`.
+    #[serde(default)]
+    pub routing: RoutingConfig,
+    /// The weighted split of calls between the primary backend and a canary backend.
+    #[serde(default)]
+    pub canary: CanaryConfig,
+    /// The recording and replaying of upstream responses, for offline tests.
+    #[serde(default)]
+    pub vcr: VcrConfig,
+    /// The aggregation of concurrent embeddings calls into upstream batch calls.
+    #[serde(default)]
+    pub micro_batching: MicroBatchingConfig,
+    /// The coalescing of concurrent identical inference calls into one upstream call.
+    #[serde(default)]
+    pub single_flight: SingleFlightConfig,
+    /// The cache of responses served to repeated inference calls.
+    #[serde(default)]
+    pub cache: CacheConfig,
+    /// The reload of the configuration file while serving.
+    #[serde(default)]
+    pub hot_reload: HotReloadConfig,
+    /// The Tokio runtime the server binaries run on, applied on restart.
+    #[serde(default)]
+    pub runtime: RuntimeConfig,
+}
+
+impl Default for AppSettings {
+    /// The settings of an empty configuration file: the gRPC server on 127.0.0.1:50051, in
+    /// front of a Mighty server at `DEFAULT_BASE_URL`, logging at the `info` level.
+    fn default() -> Self {
+        serde_json::from_value(serde_json::json!({})).expect("every setting has a default")
+    }
+}
+
+fn default_mighty_server() -> Option<MightyServerConfig> {
+    Some(MightyServerConfig {
+        base_url: vec![DEFAULT_BASE_URL.to_string()],
+        ..MightyServerConfig::default()
+    })
+}
+
+/// Represents the bounded window of recent embeddings near-duplicate checks are made against.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RecentlySimilarConfig {
+    /// The maximum number of embeddings remembered per caller; the oldest are evicted first.
+    pub window_size: usize,
+    /// The maximum number of embeddings remembered across callers; the windows of the least
+    /// recently active callers are dropped first.
+    pub max_entries: usize,
+    /// How long an embedding is remembered, e.g. `"10m"`.
+    #[serde(deserialize_with = "units::duration")]
+    pub ttl: Duration,
+    /// The cosine similarity above which texts are near-duplicates, unless set per request.
+    pub threshold: f32,
+}
+
+impl Default for RecentlySimilarConfig {
+    fn default() -> Self {
+        Self {
+            window_size: 10_000,
+            max_entries: 100_000,
+            ttl: Duration::from_secs(10 * 60),
+            threshold: 0.95,
+        }
+    }
+}
+
+/// Represents the budget of calls sent upstream, excess calls being queued, then shed.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Whether upstream calls are limited.
+    pub enabled: bool,
+    /// The sustained number of calls sent upstream per second.
+    pub requests_per_second: f64,
+    /// The number of calls that may be sent at once after an idle period.
+    pub burst: u32,
+    /// The maximum number of calls in flight upstream.
+    pub max_concurrency: usize,
+    /// The maximum number of calls waiting to be sent; further calls are shed.
+    pub max_queue: usize,
+    /// How long a call may wait to be sent before being shed, e.g. `"1s"`.
+    #[serde(deserialize_with = "units::duration")]
+    pub queue_timeout: Duration,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            requests_per_second: 100.0,
+            burst: 20,
+            max_concurrency: 32,
+            max_queue: 256,
+            queue_timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+/// The error of upstream calls failed by fault injection.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChaosError {
+    #[default]
+    Unavailable,
+    Internal,
+    DeadlineExceeded,
+    ResourceExhausted,
 }
 
+/// Represents the faults injected into upstream calls to test the resilience of the gateway's
+/// consumers. Requires the `chaos` feature; never enable it in production.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ChaosConfig {
+    /// Whether faults are injected from startup; they can also be toggled by the `SetChaos`
+    /// admin RPC.
+    pub enabled: bool,
+    /// The fraction of calls failed with `error`.
+    pub error_rate: f64,
+    /// The error of failed calls.
+    pub error: ChaosError,
+    /// The latency added to every call, e.g. `"100ms"`.
+    #[serde(deserialize_with = "units::duration")]
+    pub latency: Duration,
+    /// The upper bound of the random latency added on top of `latency`.
+    #[serde(deserialize_with = "units::duration")]
+    pub jitter: Duration,
+    /// The fraction of responses cut in half, e.g. embeddings with half their values.
+    pub truncation_rate: f64,
+    /// The fraction of calls failed as if the upstream returned malformed JSON.
+    pub malformed_json_rate: f64,
+}
+
+/// A kind of backend, configured by the section of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendKind {
+    /// A Mighty server, configured by `[mighty_server]`.
+    Rest,
+    /// Mighty server subprocesses, configured by `[binary]`.
+    Binary,
+    /// The Mighty shared library, configured by `[ffi]`.
+    Ffi,
+    /// An in-process ONNX model, configured by `[onnx]`.
+    Onnx,
+    /// An OpenAI-compatible embeddings API, configured by `[openai]`.
+    #[serde(rename = "openai")]
+    OpenAi,
+    /// A Text Embeddings Inference server, configured by `[tei]`.
+    Tei,
+}
+
+impl BackendKind {
+    /// Every kind of backend, in declaration order.
+    pub const ALL: [BackendKind; 6] = [
+        BackendKind::Rest,
+        BackendKind::Binary,
+        BackendKind::Ffi,
+        BackendKind::Onnx,
+        BackendKind::OpenAi,
+        BackendKind::Tei,
+    ];
+
+    /// Returns the kind of backend of the given configuration name, e.g. `onnx`.
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.as_str() == name)
+    }
+
+    /// Returns the configuration name of the backend, which is also the feature serving it.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackendKind::Rest => "rest",
+            BackendKind::Binary => "binary",
+            BackendKind::Ffi => "ffi",
+            BackendKind::Onnx => "onnx",
+            BackendKind::OpenAi => "openai",
+            BackendKind::Tei => "tei",
+        }
+    }
+}
+
+/// Represents a backend selected by kind, e.g. in the fallback chain.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BackendConfig {
+    /// The kind of backend, configured by its own section, e.g. `[onnx]`.
+    pub kind: BackendKind,
+    /// The base URL of a `rest` backend, e.g. a Mighty server in another region, in place of
+    /// those of `[mighty_server]`.
+    pub base_url: Option<String>,
+}
+
+/// Represents the chain of backends calls fall back to, in order, when the primary backend is
+/// unavailable, e.g. a local ONNX model behind a remote Mighty server.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FallbackConfig {
+    /// Whether calls fall back.
+    pub enabled: bool,
+    /// How long a call may take on a backend before falling back to the next one, e.g.
+    /// `"2s"`. Unbounded when unset.
+    #[serde(deserialize_with = "units::option_duration")]
+    pub attempt_timeout: Option<Duration>,
+    /// The backends tried after the primary one, in order.
+    pub backends: Vec<BackendConfig>,
+}
+
+/// Represents the backends serving named models, e.g. embedding, NER and QA Mighty servers
+/// fronted by one gateway. Calls select a model with the `x-model` metadata entry; calls without
+/// one are served by the `default` route, else by the primary backend.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RoutingConfig {
+    /// The model serving calls without `x-model`, rather than the primary backend.
+    pub default: Option<String>,
+    /// The routes, by model.
+    pub routes: Vec<RouteConfig>,
+}
+
+/// Represents the backend serving a model.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteConfig {
+    /// The model identifier calls select the route with, e.g. `"ner"`.
+    pub model: String,
+    /// The backend serving the model.
+    #[serde(flatten)]
+    pub backend: BackendConfig,
+}
+
+/// Represents the canary backend a share of the calls is served by, e.g. a new model version
+/// rolled out gradually, the primary backend serving the others as the stable one.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CanaryConfig {
+    /// Whether calls are split.
+    pub enabled: bool,
+    /// The canary backend.
+    pub backend: Option<BackendConfig>,
+    /// The percentage of calls served by the canary, in [0, 100].
+    pub weight: f64,
+    /// The percentage of the calls of a task served by the canary, overriding `weight`.
+    pub weights: HashMap<Task, f64>,
+}
+
+impl CanaryConfig {
+    /// Returns the percentage of the calls of `task` served by the canary.
+    pub fn weight(&self, task: Task) -> f64 {
+        self.weights.get(&task).copied().unwrap_or(self.weight)
+    }
+}
+
+/// Represents the shadow backend inference calls are mirrored to, e.g. a new model version
+/// validated before cutover.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ShadowConfig {
+    /// Whether calls are mirrored.
+    pub enabled: bool,
+    /// The base URL of the shadow Mighty server, e.g. `"http://mighty-next:5050"` or
+    /// `"grpc://mighty-next:50052"`, called with the `mighty_server` settings.
+    pub base_url: String,
+    /// The fraction of calls mirrored, in [0, 1].
+    pub sample_rate: f64,
+    /// The maximum number of mirrored calls in flight; calls beyond it aren't mirrored.
+    pub max_in_flight: usize,
+    /// The cosine distance above which embeddings diverge.
+    pub max_cosine_distance: f32,
+    /// The timeout of each mirrored call, e.g. `"5s"`, independent of the caller's deadline.
+    #[serde(deserialize_with = "units::duration")]
+    pub timeout: Duration,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            base_url: String::new(),
+            sample_rate: 1.0,
+            max_in_flight: 64,
+            max_cosine_distance: 0.01,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Whether upstream responses are recorded to, or replayed from, fixture files.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VcrMode {
+    /// Calls are served by the upstream as usual.
+    #[default]
+    Off,
+    /// Calls are served by the upstream, whose responses are recorded.
+    Record,
+    /// Calls are answered from the recorded responses, without any upstream.
+    Replay,
+}
+
+/// Represents the fixture files upstream responses are recorded to and replayed from.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct VcrConfig {
+    pub mode: VcrMode,
+    /// The directory of the fixture files, one per distinct call.
+    pub fixtures: PathBuf,
+}
+
+impl Default for VcrConfig {
+    fn default() -> Self {
+        Self {
+            mode: VcrMode::Off,
+            fixtures: PathBuf::from("tests/fixtures"),
+        }
+    }
+}
+
+/// Represents the aggregation of concurrent single-text embeddings and token classification
+/// calls into upstream batch calls.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MicroBatchingConfig {
+    /// Whether embeddings and token classification calls are batched.
+    pub enabled: bool,
+    /// How long a batch waits for more texts after its first one, e.g. `"5ms"`.
+    #[serde(deserialize_with = "units::duration")]
+    pub window: Duration,
+    /// The number of texts sending a batch before its window elapses.
+    pub max_batch_size: usize,
+    /// The maximum number of calls of each task waiting to be batched; further calls are shed.
+    pub max_queue: usize,
+}
+
+impl Default for MicroBatchingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            window: Duration::from_millis(5),
+            max_batch_size: 32,
+            max_queue: 1024,
+        }
+    }
+}
+
+/// Represents the coalescing of concurrent calls of the same task and input into one upstream
+/// call.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct SingleFlightConfig {
+    /// Whether concurrent identical calls share one upstream call.
+    pub enabled: bool,
+}
+
+/// Represents the cache of responses, keyed on the task and the input texts, kept in memory or in
+/// Redis.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Whether responses are cached.
+    pub enabled: bool,
+    /// The maximum number of responses cached in memory; the least recently used are evicted
+    /// first.
+    pub capacity: usize,
+    /// Where responses are cached; in Redis when `redis_url` is set, else in memory, if unset.
+    pub backend: Option<CacheBackendKind>,
+    /// How long a response is served from the cache, e.g. `"10m"`.
+    #[serde(deserialize_with = "units::duration")]
+    pub ttl: Duration,
+    /// How long the responses of a task are served from the cache, overriding `ttl`.
+    #[serde(deserialize_with = "units::duration_map")]
+    pub ttls: HashMap<Task, Duration>,
+    /// The size above which encoded responses aren't cached, e.g. `"64KiB"`; unlimited if unset.
+    #[serde(deserialize_with = "units::option_byte_size")]
+    pub max_entry_size: Option<u64>,
+    /// How long concurrent calls of a text wait for the first one to compute its response.
+    #[serde(deserialize_with = "units::duration")]
+    pub lock_timeout: Duration,
+    /// The Redis server responses are cached in with `--features redis`, e.g.
+    /// `"redis://cache:6379"`, shared by every gateway replica; in memory when unset. May be a
+    /// `file:` or `env:` secret reference, as it may carry a password.
+    pub redis_url: Option<Secret>,
+    /// The prefix of the Redis keys, separating gateways sharing a Redis server.
+    pub key_prefix: String,
+    /// The generation of the models served, part of every key, e.g. `"v2"`: changing it when a
+    /// model changes keeps its previous responses from being served.
+    pub generation: String,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            capacity: 10_000,
+            backend: None,
+            ttl: Duration::from_secs(10 * 60),
+            ttls: HashMap::new(),
+            max_entry_size: None,
+            lock_timeout: Duration::from_secs(5),
+            redis_url: None,
+            key_prefix: "mighty:".to_string(),
+            generation: String::new(),
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Returns where responses are cached.
+    pub fn backend(&self) -> CacheBackendKind {
+        self.backend.unwrap_or(match self.redis_url {
+            Some(_) => CacheBackendKind::Redis,
+            None => CacheBackendKind::Memory,
+        })
+    }
+
+    /// Returns how long the responses of `task` are served from the cache.
+    pub fn ttl(&self, task: Task) -> Duration {
+        self.ttls.get(&task).copied().unwrap_or(self.ttl)
+    }
+}
+
+/// Represents the reload of the configuration file while serving, on `SIGHUP`, on the
+/// `ReloadConfig` admin RPC and, when watched, whenever the file changes. The logging level,
+/// the base URLs of `[mighty_server]`, `[timeouts]` and `[rate_limit]` apply to calls made after
+/// a reload; other settings apply on restart.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HotReloadConfig {
+    /// Whether the configuration file is reloaded whenever it changes.
+    pub watch: bool,
+    /// The interval between the checks of the modification time of the file, e.g. `"5s"`.
+    #[serde(deserialize_with = "units::duration")]
+    pub interval: Duration,
+}
+
+impl Default for HotReloadConfig {
+    fn default() -> Self {
+        Self {
+            watch: false,
+            interval: Duration::from_secs(5),
+        }
+    }
+}
+
+/// The scheduler of the Tokio runtime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuntimeFlavor {
+    /// Tasks are spread over a pool of `worker_threads`.
+    #[default]
+    MultiThread,
+    /// Tasks all run on the thread of `main`, e.g. in containers limited to one CPU.
+    CurrentThread,
+}
+
+/// Represents the Tokio runtime of the server binaries. Tokio defaults to a worker thread per
+/// CPU of the host, which oversubscribes containers limited to a fraction of them.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RuntimeConfig {
+    /// The scheduler of the runtime.
+    pub flavor: RuntimeFlavor,
+    /// The number of worker threads of the `multi_thread` scheduler, one per CPU by default.
+    pub worker_threads: Option<usize>,
+    /// The maximum number of threads running blocking calls (e.g. file reads), 512 by default.
+    pub max_blocking_threads: Option<usize>,
+}
+
+/// The store responses are cached in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheBackendKind {
+    /// A bounded LRU local to the gateway process.
+    Memory,
+    /// The Redis server at `redis_url`, shared by every gateway replica.
+    Redis,
+}
+
+/// Represents the Mighty shared library inference is delegated to in-process in `ffi` mode.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FfiConfig {
+    /// The path of the shared library, looked up in the dynamic linker search path when it has
+    /// no directory component.
+    pub library: PathBuf,
+    /// The directory the models are loaded from, passed to the library on initialization.
+    pub model_dir: Option<PathBuf>,
+    /// Whether library responses with missing or mistyped fields fail with `INTERNAL`, like
+    /// `mighty_server.strict_responses`.
+    pub strict_responses: bool,
+}
+
+impl Default for FfiConfig {
+    fn default() -> Self {
+        Self {
+            library: PathBuf::from("libmighty.so"),
+            model_dir: None,
+            strict_responses: false,
+        }
+    }
+}
+
+/// Represents the ONNX model and tokenizer inference is run with in-process in `onnx` mode,
+/// through ONNX Runtime.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct OnnxConfig {
+    /// The path of the ONNX model, e.g. exported with Hugging Face Optimum.
+    pub model: PathBuf,
+    /// The path of the Hugging Face `tokenizer.json` matching the model.
+    pub tokenizer: PathBuf,
+    /// The task the model was exported for: `embeddings` (also serving sentence transformers)
+    /// or `sequence_classification`.
+    pub task: Task,
+    /// The maximum number of tokens per input; longer inputs are truncated.
+    pub max_length: usize,
+    /// The number of threads used to run each inference. Chosen by ONNX Runtime when unset.
+    pub intra_threads: Option<usize>,
+    /// The path of the ONNX Runtime shared library. Taken from the `ORT_DYLIB_PATH` environment
+    /// variable, or looked up in the dynamic linker search path, when unset.
+    pub runtime_library: Option<PathBuf>,
+}
+
+impl Default for OnnxConfig {
+    fn default() -> Self {
+        Self {
+            model: PathBuf::from("model.onnx"),
+            tokenizer: PathBuf::from("tokenizer.json"),
+            task: Task::Embeddings,
+            max_length: 512,
+            intra_threads: None,
+            runtime_library: None,
+        }
+    }
+}
+
+/// Represents the OpenAI-compatible embeddings API (`POST /embeddings`) calls are translated to
+/// in `openai` mode, spoken by OpenAI as well as many self-hosted inference servers.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct OpenAiConfig {
+    /// The base URL of the API, including its version, e.g. `"https://api.openai.com/v1"`.
+    pub base_url: String,
+    /// The embedding model requested, e.g. `"text-embedding-3-small"`.
+    pub model: String,
+    /// The API key sent as a bearer token, usually a `file:` or `env:` secret reference, taking
+    /// precedence over `api_key_env`.
+    pub api_key: Option<Secret>,
+    /// The environment variable holding the API key sent as a bearer token. No key is sent when
+    /// the variable is unset, as self-hosted servers often don't require one.
+    pub api_key_env: String,
+    /// The number of dimensions requested from models supporting shortened embeddings. The
+    /// model's own dimension when unset.
+    pub dimensions: Option<u32>,
+    /// The timeout of each call, e.g. `"30s"`, shortened to the caller's deadline if any.
+    #[serde(deserialize_with = "units::duration")]
+    pub timeout: Duration,
+}
+
+impl Default for OpenAiConfig {
+    fn default() -> Self {
+        Self {
+            base_url: "https://api.openai.com/v1".to_string(),
+            model: "text-embedding-3-small".to_string(),
+            api_key: None,
+            api_key_env: "OPENAI_API_KEY".to_string(),
+            dimensions: None,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Represents the Hugging Face Text Embeddings Inference (TEI) server calls are translated to in
+/// `tei` mode, through its `/embed` and `/rerank` endpoints.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TeiConfig {
+    /// The base URL of the TEI server, e.g. `"http://localhost:8080"`.
+    pub base_url: String,
+    /// The API key the server was started with (`--api-key`), sent as a bearer token, usually a
+    /// `file:` or `env:` secret reference, taking precedence over `api_key_env`.
+    pub api_key: Option<Secret>,
+    /// The environment variable holding the API key the server was started with
+    /// (`--api-key`), sent as a bearer token. No key is sent when the variable is unset.
+    pub api_key_env: String,
+    /// Whether inputs longer than the model's maximum are truncated rather than rejected.
+    pub truncate: bool,
+    /// The timeout of each call, e.g. `"30s"`, shortened to the caller's deadline if any.
+    #[serde(deserialize_with = "units::duration")]
+    pub timeout: Duration,
+}
+
+impl Default for TeiConfig {
+    fn default() -> Self {
+        Self {
+            base_url: "http://localhost:8080".to_string(),
+            api_key: None,
+            api_key_env: "TEI_API_KEY".to_string(),
+            truncate: true,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Represents the circuit breakers guarding each task independently, so one failing task (e.g.
+/// question answering) doesn't affect the others.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    /// Whether calls are guarded by circuit breakers.
+    pub enabled: bool,
+    /// The default thresholds of every task's breaker.
+    #[serde(flatten)]
+    pub thresholds: BreakerThresholds,
+    /// Per task overrides of the thresholds, e.g.
+    /// `question_answering = { failure_threshold = 10 }`.
+    pub tasks: HashMap<Task, BreakerOverrides>,
+}
+
+impl CircuitBreakerConfig {
+    /// Returns the thresholds of `task`'s breaker.
+    pub fn thresholds(&self, task: Task) -> BreakerThresholds {
+        let defaults = &self.thresholds;
+        match self.tasks.get(&task) {
+            Some(overrides) => BreakerThresholds {
+                failure_threshold: overrides
+                    .failure_threshold
+                    .unwrap_or(defaults.failure_threshold),
+                open_duration: overrides.open_duration.unwrap_or(defaults.open_duration),
+                half_open_probes: overrides
+                    .half_open_probes
+                    .unwrap_or(defaults.half_open_probes),
+            },
+            None => defaults.clone(),
+        }
+    }
+}
+
+/// Represents the thresholds of a circuit breaker.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct BreakerThresholds {
+    /// The number of consecutive upstream failures after which the breaker opens.
+    pub failure_threshold: u32,
+    /// How long the breaker stays open, failing calls fast, before letting probes through,
+    /// e.g. `"30s"`.
+    #[serde(deserialize_with = "units::duration")]
+    pub open_duration: Duration,
+    /// The number of probe calls let through at once while half-open, all of which must succeed
+    /// for the breaker to close.
+    pub half_open_probes: u32,
+}
+
+impl Default for BreakerThresholds {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(30),
+            half_open_probes: 1,
+        }
+    }
+}
+
+/// Represents per task overrides of the circuit breaker thresholds.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct BreakerOverrides {
+    pub failure_threshold: Option<u32>,
+    #[serde(deserialize_with = "units::option_duration")]
+    pub open_duration: Option<Duration>,
+    pub half_open_probes: Option<u32>,
+}
+
+/// Represents the resilience policies of the calls sent upstream, by task: how failed calls are
+/// retried, how long each attempt may take and whether slow calls are hedged. Calls aren't
+/// retried by default.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct ResilienceConfig {
+    /// The default policy of every task.
+    #[serde(flatten)]
+    pub policy: ResiliencePolicy,
+    /// Per task overrides of the policy, e.g.
+    /// `question_answering = { max_attempts = 1, hedge = false }`.
+    pub tasks: HashMap<Task, ResilienceOverrides>,
+}
+
+impl ResilienceConfig {
+    /// Returns the policy of `task`'s calls.
+    pub fn policy(&self, task: Task) -> ResiliencePolicy {
+        let defaults = &self.policy;
+        match self.tasks.get(&task) {
+            Some(overrides) => ResiliencePolicy {
+                max_attempts: overrides.max_attempts.unwrap_or(defaults.max_attempts),
+                initial_backoff: overrides
+                    .initial_backoff
+                    .unwrap_or(defaults.initial_backoff),
+                max_backoff: overrides.max_backoff.unwrap_or(defaults.max_backoff),
+                multiplier: overrides.multiplier.unwrap_or(defaults.multiplier),
+                attempt_timeout: overrides.attempt_timeout.or(defaults.attempt_timeout),
+                hedge: overrides.hedge.unwrap_or(defaults.hedge),
+            },
+            None => defaults.clone(),
+        }
+    }
+
+    /// Returns the tasks whose calls are never hedged.
+    pub fn unhedged_tasks(&self) -> HashSet<Task> {
+        Task::ALL
+            .into_iter()
+            .filter(|&task| !self.policy(task).hedge)
+            .collect()
+    }
+}
+
+/// Represents the resilience policy of a task's upstream calls.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ResiliencePolicy {
+    /// The number of attempts of a call failing transiently upstream (`UNAVAILABLE`, `UNKNOWN` or
+    /// `DEADLINE_EXCEEDED`), including the first one; `1` disables retries.
+    pub max_attempts: u32,
+    /// The delay before the first retry, e.g. `"50ms"`.
+    #[serde(deserialize_with = "units::duration")]
+    pub initial_backoff: Duration,
+    /// The upper bound of the delay between attempts, e.g. `"1s"`.
+    #[serde(deserialize_with = "units::duration")]
+    pub max_backoff: Duration,
+    /// The factor the delay grows by after each retry.
+    pub multiplier: f64,
+    /// How long each attempt may take before it's abandoned with `DEADLINE_EXCEEDED`, and
+    /// retried if attempts remain, e.g. `"5s"`. Attempts aren't bounded by default.
+    #[serde(deserialize_with = "units::option_duration")]
+    pub attempt_timeout: Option<Duration>,
+    /// Whether slow calls may be hedged, when `[mighty_server.hedging]` is enabled.
+    pub hedge: bool,
+}
+
+impl ResiliencePolicy {
+    /// Returns the delay before the `retry`th retry, counting from 1, before jitter.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponent = retry.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.max(1.0).powi(exponent);
+        Duration::try_from_secs_f64(backoff)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for ResiliencePolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(1),
+            multiplier: 2.0,
+            attempt_timeout: None,
+            hedge: true,
+        }
+    }
+}
+
+/// Represents per task overrides of the resilience policy. Unset fields keep the value of the
+/// default policy.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct ResilienceOverrides {
+    /// Overrides `ResiliencePolicy::max_attempts`.
+    pub max_attempts: Option<u32>,
+    /// Overrides `ResiliencePolicy::initial_backoff`.
+    #[serde(deserialize_with = "units::option_duration")]
+    pub initial_backoff: Option<Duration>,
+    /// Overrides `ResiliencePolicy::max_backoff`.
+    #[serde(deserialize_with = "units::option_duration")]
+    pub max_backoff: Option<Duration>,
+    /// Overrides `ResiliencePolicy::multiplier`.
+    pub multiplier: Option<f64>,
+    /// Bounds the task's attempts even when the default policy doesn't; see
+    /// `ResiliencePolicy::attempt_timeout`.
+    #[serde(deserialize_with = "units::option_duration")]
+    pub attempt_timeout: Option<Duration>,
+    /// Overrides `ResiliencePolicy::hedge`.
+    pub hedge: Option<bool>,
+}
+
+/// Represents the Mighty server executables run and supervised by the gateway in `binary` mode.
+/// Requests are distributed across the worker subprocesses over HTTP on the loopback interface,
+/// where they listen on consecutive ports starting at `port`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BinaryConfig {
+    /// The path of the Mighty server executable, looked up in `PATH` when relative.
+    pub path: PathBuf,
+    /// The arguments passed to the executable, where `{port}` and `{model_dir}` are replaced
+    /// with the worker's port and the `model_dir` setting.
+    pub args: Vec<String>,
+    /// The directory the models are loaded from.
+    pub model_dir: Option<PathBuf>,
+    /// The directory the model versions loaded by `UpgradeModel` must be in, e.g. `"/models"`.
+    /// Only the configured `model_dir` can be reloaded when unset.
+    pub model_root: Option<PathBuf>,
+    /// The number of worker subprocesses, e.g. one per core as a worker saturates a core.
+    pub workers: u16,
+    /// The loopback port the first worker listens on; the others use the following ports, and
+    /// the standby workers started by model upgrades the `workers` ports after those.
+    pub port: u16,
+    /// How long the subprocess may take to pass its health check after being started, e.g.
+    /// `"60s"`. Calls made meanwhile wait for it.
+    #[serde(deserialize_with = "units::duration")]
+    pub startup_timeout: Duration,
+    /// The delay before a crashed subprocess is restarted, e.g. `"1s"`. Doubled after every
+    /// restart of a worker that didn't become ready, up to `max_restart_delay`.
+    #[serde(deserialize_with = "units::duration")]
+    pub restart_delay: Duration,
+    /// The upper bound of the restart delay, e.g. `"30s"`.
+    #[serde(deserialize_with = "units::duration")]
+    pub max_restart_delay: Duration,
+    /// Health checking of ready workers; a worker failing `unhealthy_threshold` consecutive
+    /// probes is killed and restarted.
+    pub health_check: HealthCheckConfig,
+}
+
+impl Default for BinaryConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("mighty-server"),
+            args: vec!["--port".to_string(), "{port}".to_string()],
+            model_dir: None,
+            model_root: None,
+            workers: 1,
+            port: 5050,
+            startup_timeout: Duration::from_secs(60),
+            restart_delay: Duration::from_secs(1),
+            max_restart_delay: Duration::from_secs(30),
+            health_check: HealthCheckConfig::default(),
+        }
+    }
+}
+
+/// The names of the methods of the inference service, keying per-method settings.
+pub const METHODS: [&str; 10] = [
+    "embeddings",
+    "question_answering",
+    "sentence_transformers",
+    "sequence_classification",
+    "token_classification",
+    "metadata",
+    "health_check",
+    "rerank",
+    "stream_embeddings",
+    "recently_similar",
+];
+
+/// Represents the compression of the messages exchanged with gRPC callers. Responses are only
+/// compressed with encodings callers advertise in `grpc-accept-encoding`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+    /// The encodings request messages may be compressed with.
+    pub accept: Vec<CompressionKind>,
+    /// The encodings response messages may be compressed with.
+    pub send: Vec<CompressionKind>,
+    /// Whether the responses of each method are compressed, by method name (e.g. `metadata`),
+    /// overriding the defaults: every method but `health_check` and `metadata`, whose responses
+    /// are too small to benefit.
+    pub methods: HashMap<String, bool>,
+}
+
+impl CompressionConfig {
+    /// Whether the responses of `method` are compressed.
+    pub fn compresses(&self, method: &str) -> bool {
+        self.methods
+            .get(method)
+            .copied()
+            .unwrap_or(!matches!(method, "health_check" | "metadata"))
+    }
+}
+
+/// Represents the methods of the inference service served, e.g. only the embedding ones in front
+/// of an embedding model. Calls of disabled methods fail with `UNIMPLEMENTED` without reaching
+/// the backend.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct EndpointsConfig {
+    /// Whether each method is served, by method name (e.g. `question_answering`).
+    pub methods: HashMap<String, bool>,
+}
+
+impl EndpointsConfig {
+    /// Whether the calls of `method` are served.
+    pub fn serves(&self, method: &str) -> bool {
+        self.methods.get(method).copied().unwrap_or(true)
+    }
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            accept: vec![CompressionKind::Gzip, CompressionKind::Zstd],
+            send: vec![CompressionKind::Gzip, CompressionKind::Zstd],
+            methods: HashMap::new(),
+        }
+    }
+}
+
+/// A compression encoding of gRPC messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionKind {
+    Gzip,
+    Zstd,
+}
+
+/// Represents the timeouts of the calls served, by method, after which they fail with
+/// `DEADLINE_EXCEEDED` whatever the deadline of their caller, their upstream calls being
+/// abandoned. Calls aren't bounded by default.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct TimeoutsConfig {
+    /// The timeout of the calls of methods without one of their own, e.g. `"30s"`.
+    #[serde(deserialize_with = "units::option_duration")]
+    pub default: Option<Duration>,
+    /// The timeouts of the calls of each method, by method name, e.g.
+    /// `question_answering = "60s"`. Bounds each batch of `stream_embeddings` streams.
+    #[serde(deserialize_with = "units::duration_map")]
+    pub methods: HashMap<String, Duration>,
+}
+
+impl TimeoutsConfig {
+    /// The timeout of the calls of `method`, if any.
+    pub fn timeout(&self, method: &str) -> Option<Duration> {
+        self.methods.get(method).copied().or(self.default)
+    }
+}
+
+/// Represents the limits on the texts of requests, checked before they are sent upstream. Empty
+/// texts, questions and contexts and texts with control characters are rejected regardless;
+/// texts aren't limited in length by default.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct RequestValidationConfig {
+    /// Reject texts longer than this size in UTF-8, e.g. `"64KiB"`.
+    #[serde(deserialize_with = "units::option_byte_size")]
+    pub max_text_bytes: Option<u64>,
+    /// Reject texts longer than this number of characters.
+    pub max_text_chars: Option<usize>,
+}
+
+/// Represents the preprocessing applied to the texts of batch requests before they are sent
+/// upstream. Nothing is applied by default; callers can request a per-text provenance report of
+/// what was applied.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct BatchConfig {
+    /// Trim texts and collapse runs of whitespace into a single space.
+    pub normalize: bool,
+    /// Embed texts that are identical (after normalization) once per batch.
+    pub deduplicate: bool,
+    /// Truncate texts longer than this number of characters.
+    pub max_text_chars: Option<usize>,
+}
+
+/// Represents the checks required before the gateway reports itself ready. None are required by
+/// default, in which case the gateway is ready as soon as it starts, and stays ready while the
+/// upstream health check passes.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReadinessConfig {
+    /// Whether the model metadata must have been fetched from the upstream.
+    pub require_metadata: bool,
+    /// Whether every call of the startup warm-up must have succeeded, warming up the response
+    /// cache.
+    pub require_cache_warmup: bool,
+    /// The number of successful canary inferences (embeddings of `canary_text`) required.
+    pub canary_inferences: u32,
+    /// The text sent with canary inferences.
+    pub canary_text: String,
+    /// The interval between retries of failed checks, e.g. `"5s"`.
+    #[serde(deserialize_with = "units::duration")]
+    pub retry_interval: Duration,
+    /// Whether startup fails when the upstream health check doesn't succeed within
+    /// `startup_timeout`, rather than waiting for the upstream to come up.
+    pub require_upstream_at_startup: bool,
+    /// How long the startup upstream health check may take, e.g. `"5s"`.
+    #[serde(deserialize_with = "units::duration")]
+    pub startup_timeout: Duration,
+    /// The number of consecutive failed upstream health checks after which the gateway reports
+    /// itself not ready, until one succeeds. The upstream isn't monitored when 0.
+    pub upstream_failure_threshold: u32,
+    /// The interval between upstream health checks, e.g. `"10s"`.
+    #[serde(deserialize_with = "units::duration")]
+    pub upstream_check_interval: Duration,
+    /// The warm-up of the upstream run at startup, before the gateway reports itself ready.
+    pub warm_up: WarmUpConfig,
+}
+
+impl Default for ReadinessConfig {
+    fn default() -> Self {
+        Self {
+            require_metadata: false,
+            require_cache_warmup: false,
+            canary_inferences: 0,
+            canary_text: "readiness canary".to_string(),
+            retry_interval: Duration::from_secs(5),
+            require_upstream_at_startup: false,
+            startup_timeout: Duration::from_secs(5),
+            upstream_failure_threshold: 3,
+            upstream_check_interval: Duration::from_secs(10),
+            warm_up: WarmUpConfig::default(),
+        }
+    }
+}
+
+/// Represents the warm-up of the upstream run at startup, so the first calls served don't pay
+/// for opening connections, loading models or filling the response cache. The gateway isn't
+/// ready until it completes or `timeout` elapses; calls failing during the warm-up are logged.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WarmUpConfig {
+    /// Whether the upstream is warmed up at startup.
+    pub enabled: bool,
+    /// The number of upstream connections opened ahead of traffic, by as many concurrent health
+    /// checks, spread over the replicas by load balancing.
+    pub connections: usize,
+    /// Whether the model metadata is fetched.
+    pub metadata: bool,
+    /// The texts sent to each of `tasks`, in order.
+    pub texts: Vec<String>,
+    /// The tasks `texts` are sent to, taking a single text.
+    pub tasks: Vec<Task>,
+    /// How long the warm-up may take before the gateway reports itself ready anyway, e.g. `"30s"`.
+    #[serde(deserialize_with = "units::duration")]
+    pub timeout: Duration,
+}
+
+impl Default for WarmUpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            connections: 4,
+            metadata: true,
+            texts: Vec::new(),
+            tasks: vec![Task::Embeddings],
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Represents a legacy RPC path mapped onto a current endpoint.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AliasConfig {
+    /// The legacy path, e.g. `/mighty_inference_server.MightyInference/GetEmbeddings`.
+    pub from: String,
+    /// The current path requests are routed to, e.g.
+    /// `/mighty_inference_server.MightyInference/Embeddings`.
+    pub to: String,
+    /// The deprecation notice returned with aliased responses. Defaults to naming the
+    /// replacement path.
+    pub message: Option<String>,
+}
+
+/// Represents the API keys authenticating calls to the gRPC server.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ApiKeysConfig {
+    /// Whether calls without a valid `x-api-key` are rejected with `UNAUTHENTICATED`.
+    pub enabled: bool,
+    /// The accepted keys.
+    pub keys: Vec<ApiKeyConfig>,
+}
+
+/// Represents the callers allowed to operate the gateway through the admin RPCs changing its
+/// state (`UpgradeModel`, `SwitchBackend`, `SetChaos`, `FlushCache` and `ReloadConfig`) or
+/// reporting on other callers (`QuotaUsage`). Other callers are denied them with
+/// `PERMISSION_DENIED`, every caller when none is set.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AdminConfig {
+    /// The caller identities allowed, as established by `[api_keys]`, `[jwt]` or the client
+    /// certificate over mutual TLS, e.g. `["ops"]`.
+    pub identities: Vec<String>,
+}
+
+impl AdminConfig {
+    /// Returns whether `identity` may call the admin RPCs operating the gateway.
+    pub fn is_admin(&self, identity: Option<&str>) -> bool {
+        identity.is_some_and(|identity| self.identities.iter().any(|admin| admin == identity))
+    }
+}
+
+/// Represents the debug output callers may request through `x-mighty-debug`, reserved to the
+/// identities of `AdminConfig`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DebugConfig {
+    /// Whether admins may have the raw upstream JSON attached to responses with
+    /// `x-mighty-debug: raw-json`. Such calls bypass the cache and request coalescing.
+    pub raw_json: bool,
+}
+
+/// Represents an API key, named for attribution in logs and metrics.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiKeyConfig {
+    /// The name of the key holder, e.g. `"search-indexer"`, used as the caller identity.
+    pub name: String,
+    /// The secret key, or a `file:` or `env:` secret reference.
+    pub key: Secret,
+}
+
+/// Represents the validation of the JWT bearer tokens authenticating calls to the gRPC server,
+/// e.g. issued by an OIDC provider.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JwtConfig {
+    /// Whether calls without a valid `authorization: Bearer <token>` are rejected with
+    /// `UNAUTHENTICATED`.
+    pub enabled: bool,
+    /// The expected `iss` claim, e.g. `"https://accounts.example.com"`.
+    pub issuer: String,
+    /// The accepted `aud` claims, given either as a single string or as a list.
+    #[serde(deserialize_with = "one_or_many")]
+    pub audience: Vec<String>,
+    /// The URL of the JSON Web Key Set verifying token signatures. Discovered from the
+    /// issuer's `/.well-known/openid-configuration` when unset.
+    pub jwks_url: Option<String>,
+    /// The interval at which the key set is fetched again, to follow key rotations. Tokens
+    /// signed by an unknown key trigger an earlier refresh.
+    #[serde(deserialize_with = "units::duration")]
+    pub jwks_refresh_interval: Duration,
+    /// The clock skew tolerated when checking the `exp` and `nbf` claims.
+    #[serde(deserialize_with = "units::duration")]
+    pub leeway: Duration,
+    /// The claim used as the caller identity.
+    pub identity_claim: String,
+    /// The claim used as the tenant of calls, if any, taking precedence over `x-tenant-id`.
+    pub tenant_claim: Option<String>,
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            issuer: String::new(),
+            audience: Vec::new(),
+            jwks_url: None,
+            jwks_refresh_interval: Duration::from_secs(600),
+            leeway: Duration::from_secs(60),
+            identity_claim: "sub".to_string(),
+            tenant_claim: None,
+        }
+    }
+}
+
+/// Represents the export of the spans of gRPC and upstream calls to an OpenTelemetry collector
+/// over OTLP/gRPC.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TracingConfig {
+    /// Whether spans are exported.
+    pub enabled: bool,
+    /// The OTLP/gRPC endpoint of the collector, e.g. `"http://localhost:4317"`.
+    pub endpoint: String,
+    /// The `service.name` resource attribute of the exported spans.
+    pub service_name: String,
+    /// The fraction of the traces started by the gateway that are exported; traces continued
+    /// from callers follow their sampling decision.
+    pub sample_ratio: f64,
+    /// The timeout of each export to the collector, e.g. `"10s"`.
+    #[serde(deserialize_with = "units::duration")]
+    pub export_timeout: Duration,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: "http://localhost:4317".to_string(),
+            service_name: "mighty-grpc".to_string(),
+            sample_ratio: 1.0,
+            export_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Represents the perimeter control of the gRPC server: calls from peers in a `deny` range, or
+/// outside the `allow` ranges when any is set, are rejected with `PERMISSION_DENIED`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct NetworkAclConfig {
+    /// The ranges of the peers allowed to call, e.g. `["10.0.0.0/8"]`; every peer when empty.
+    pub allow: Vec<IpRange>,
+    /// The ranges of the peers denied, even within an `allow` range.
+    pub deny: Vec<IpRange>,
+}
+
+impl NetworkAclConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.allow.is_empty() || !self.deny.is_empty()
+    }
+}
+
+/// A range of IP addresses in CIDR notation, e.g. `"192.168.0.0/16"` or `"fd00::/8"`, or a single
+/// address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct IpRange {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    /// Returns whether `addr` is in the range, IPv4-mapped IPv6 addresses matching IPv4 ranges.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.network, addr.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl TryFrom<String> for IpRange {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let (network, prefix_len) = match value.split_once('/') {
+            Some((network, prefix_len)) => (network, Some(prefix_len)),
+            None => (value.as_str(), None),
+        };
+        let network: IpAddr = network
+            .parse()
+            .map_err(|_| format!("invalid IP address range {:?}", value))?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len
+                .parse()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .ok_or_else(|| format!("invalid prefix length in {:?}", value))?,
+            None => max_len,
+        };
+        Ok(Self {
+            network,
+            prefix_len,
+        })
+    }
+}
+
+/// Represents the budget of calls each client of the gRPC server may make, so a noisy client
+/// can't starve the others. Clients are told apart by identity (API key name, token subject or
+/// certificate identity), else by IP address.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ClientRateLimitConfig {
+    /// Whether calls past a client's budget are rejected with `RESOURCE_EXHAUSTED`.
+    pub enabled: bool,
+    /// The sustained number of calls per second of each client.
+    pub requests_per_second: f64,
+    /// The number of calls a client may make at once after an idle period.
+    pub burst: u32,
+    /// The budgets of specific clients, overriding the defaults above.
+    pub clients: Vec<ClientQuotaConfig>,
+    /// The number of clients tracked past which idle clients are forgotten.
+    pub max_clients: usize,
+}
+
+impl Default for ClientRateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            requests_per_second: 20.0,
+            burst: 40,
+            clients: Vec::new(),
+            max_clients: 10_000,
+        }
+    }
+}
+
+/// Represents the budget of calls of a specific client.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClientQuotaConfig {
+    /// The identity of the client, e.g. the name of its API key.
+    pub identity: String,
+    /// The sustained number of calls per second of the client.
+    pub requests_per_second: f64,
+    /// The number of calls the client may make at once after an idle period.
+    pub burst: u32,
+}
+
+/// Represents the daily usage quotas of the authenticated callers of the gRPC server, by identity
+/// (API key name, token subject or certificate identity), e.g. to bill internal teams by usage.
+/// Quotas reset at midnight UTC; calls past them fail with `RESOURCE_EXHAUSTED`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct QuotasConfig {
+    /// Whether usage is accounted and quotas enforced.
+    pub enabled: bool,
+    /// The number of inference calls a caller may make per day; unlimited if unset.
+    pub daily_requests: Option<u64>,
+    /// The number of tokens a caller may embed per day; unlimited if unset.
+    pub daily_tokens: Option<u64>,
+    /// The quotas of specific callers, overriding the defaults above.
+    pub callers: Vec<CallerQuotaConfig>,
+}
+
+impl QuotasConfig {
+    /// Returns the daily request and token quotas of `identity`.
+    pub fn limits(&self, identity: &str) -> (Option<u64>, Option<u64>) {
+        let caller = self
+            .callers
+            .iter()
+            .find(|caller| caller.identity == identity);
+        (
+            caller
+                .and_then(|caller| caller.daily_requests)
+                .or(self.daily_requests),
+            caller
+                .and_then(|caller| caller.daily_tokens)
+                .or(self.daily_tokens),
+        )
+    }
+}
+
+/// Represents the daily usage quotas of a specific caller.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CallerQuotaConfig {
+    /// The identity of the caller, e.g. the name of its API key.
+    pub identity: String,
+    /// The number of inference calls the caller may make per day, else the default.
+    #[serde(default)]
+    pub daily_requests: Option<u64>,
+    /// The number of tokens the caller may embed per day, else the default.
+    #[serde(default)]
+    pub daily_tokens: Option<u64>,
+}
+
+/// Represents the validation rules applied to upstream responses before they are returned.
+/// Every rule is disabled by default.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct ValidationConfig {
+    /// Reject responses without outputs (no embeddings, empty vectors, no logits or answer).
+    pub non_empty_outputs: bool,
+    /// Reject token classification responses with entity scores outside `[0, 1]`.
+    pub score_range: bool,
+    /// Reject embeddings whose dimensionality differs from this value.
+    pub expected_dimension: Option<usize>,
+    /// Reject embeddings whose dimensionality differs from the hidden size reported in the
+    /// upstream model metadata. Ignored when `expected_dimension` is set.
+    pub dimension_from_metadata: bool,
+}
+
+impl ValidationConfig {
+    /// Returns whether any validation rule is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.non_empty_outputs
+            || self.score_range
+            || self.expected_dimension.is_some()
+            || self.dimension_from_metadata
+    }
+}
+
+/// Deserializes either a single string or a list of strings into a `Vec<String>`.
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+/// Returns the source of the environment overrides of settings: variables named after the path
+/// of a key, prefixed with `ENV_PREFIX`, its sections separated by `__`, e.g.
+/// `MIGHTY_GRPC__CACHE__TTLS__RERANK=1h`. Values are parsed like those of the file; lists
+/// can't be overridden.
+fn environment() -> Environment {
+    Environment::with_prefix(ENV_PREFIX).separator("__")
+}
+
+/// Returns the configuration file at `path`, or else the first existing one named `path` with
+/// an extension of `CONFIG_EXTENSIONS`, e.g. "config.yaml" for "config". The format of the file
+/// is detected by its extension.
+pub fn find_config_file(path: &str) -> PathBuf {
+    let file = PathBuf::from(path);
+    if file.is_file() {
+        return file;
+    }
+    CONFIG_EXTENSIONS
+        .iter()
+        .map(|extension| PathBuf::from(format!("{}.{}", path, extension)))
+        .find(|file| file.is_file())
+        .unwrap_or(file)
+}
 
 impl AppSettings {
-    /// Loads the application settings from a configuration file named "config.toml".
+    /// Loads the application settings from a configuration file named "config.toml",
+    /// "config.yaml" or "config.json", overridden key by key by `MIGHTY_GRPC__*` environment
+    /// variables. Without any such file, the settings are those of `AppSettings::default`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ConfigError` if the configuration file cannot be parsed.
+    pub fn new() -> Result<Self, ConfigError> {
+        Self::load(&Cli::default())
+    }
+
+    /// Loads the application settings from the configuration file of `cli`, "config.toml",
+    /// "config.yaml" or "config.json" by default, overridden key by key by `MIGHTY_GRPC__*`
+    /// environment variables, then by the arguments of `cli`. Settings missing from the file,
+    /// or the whole file when `cli` names none and none exists, take their default values.
     ///
     /// This function uses the `config` crate to load the settings from a file and
     /// deserializes them into an `AppSettings` struct.
     ///
     /// # Errors
     ///
+    /// Returns a `ConfigError` if the configuration file named by `cli` cannot be read, or if
+    /// the configuration file cannot be parsed.
+    #[cfg(not(feature = "edge"))]
+    pub fn load(cli: &Cli) -> Result<Self, ConfigError> {
+        let builder = Config::builder()
+            .add_source(File::from(cli.config_file()).required(cli.config.is_some()))
+            .add_source(environment());
+        cli.apply(builder)?.build()?.try_deserialize()
+    }
+
+    /// Loads the application settings of edge bundles: the embedded `EMBEDDED_CONFIG`,
+    /// overridden key by key by the configuration file of `cli` (a "config.toml", "config.yaml"
+    /// or "config.json" file if one exists by default), then by `MIGHTY_GRPC__*` environment
+    /// variables and the arguments of `cli`.
+    ///
+    /// # Errors
+    ///
     /// Returns a `ConfigError` if the configuration file cannot be read or parsed.
-    pub fn new() -> Result<Self, ConfigError> {
+    #[cfg(feature = "edge")]
+    pub fn load(cli: &Cli) -> Result<Self, ConfigError> {
+        let file = File::from(cli.config_file()).required(cli.config.is_some());
+        let builder = Config::builder()
+            .add_source(File::from_str(EMBEDDED_CONFIG, FileFormat::Toml))
+            .add_source(file)
+            .add_source(environment());
+        cli.apply(builder)?.build()?.try_deserialize()
+    }
+
+    /// Returns the kind of the primary backend: `client` when set, else the first of the enabled
+    /// features, in the order of `BackendKind::ALL`.
+    pub fn primary_backend(&self) -> Option<BackendKind> {
+        let enabled = [
+            cfg!(feature = "rest"),
+            cfg!(feature = "binary"),
+            cfg!(feature = "ffi"),
+            cfg!(any(feature = "onnx", feature = "edge")),
+            cfg!(feature = "openai"),
+            cfg!(feature = "tei"),
+        ];
+        self.client.or_else(|| {
+            BackendKind::ALL
+                .into_iter()
+                .zip(enabled)
+                .find_map(|(kind, enabled)| enabled.then_some(kind))
+        })
+    }
+
+    /// Returns the upstream base URLs of the configured backends: those of `[mighty_server]`,
+    /// then those set for the fallback chain, the task backends, the routes and the canary.
+    pub fn configured_base_urls(&self) -> Vec<&str> {
+        let mighty_server = self
+            .mighty_server
+            .iter()
+            .flat_map(|config| &config.base_url)
+            .map(String::as_str);
+        let backends = self
+            .fallback
+            .backends
+            .iter()
+            .chain(self.backends.values())
+            .chain(self.routing.routes.iter().map(|route| &route.backend))
+            .chain(&self.canary.backend)
+            .filter_map(|backend| backend.base_url.as_deref());
+        mighty_server.chain(backends).collect()
+    }
+
+    /// Checks that the settings can be served (see `lint::validate`), so that a bad
+    /// configuration fails at startup rather than at first use.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ConfigError` listing every problem found.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let problems = lint::validate(self);
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(ConfigError::Message(problems.join("; ")))
+    }
+
+    /// Loads the application settings from the configuration file at `path`, whose extension
+    /// may be omitted, without environment overrides.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ConfigError` if the configuration file cannot be read or parsed.
+    pub fn from_file(path: &str) -> Result<Self, ConfigError> {
         let config = Config::builder()
-            .add_source(File::with_name("config"))
+            .add_source(File::with_name(path))
             .build()?;
         config.try_deserialize()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use config::FileFormat;
+
+    use super::*;
+
+    #[test]
+    fn test_environment_overrides_the_file() {
+        let variables = [
+            ("MIGHTY_GRPC__GRPC_SERVER__PORT", "50052"),
+            ("MIGHTY_GRPC__LOGGING__ACCESS_LOG", "true"),
+            ("MIGHTY_GRPC__CACHE__MAX_ENTRY_SIZE", "64KiB"),
+            ("MIGHTY_GRPC__CACHE__TTLS__RERANK", "1h"),
+            ("MIGHTY_GRPC__RATE_LIMIT__REQUESTS_PER_SECOND", "2.5"),
+            ("GRPC_SERVER__ADDRESS", "0.0.0.0"),
+        ]
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+        let settings: AppSettings = Config::builder()
+            .add_source(File::from_str(
+                r#"
+                grpc_server = { address = "127.0.0.1", port = 50051 }
+                logging = { level = "info" }
+                "#,
+                FileFormat::Toml,
+            ))
+            .add_source(environment().source(Some(variables)))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        assert_eq!(settings.grpc_server.port, 50052);
+        assert_eq!(settings.grpc_server.address, "127.0.0.1");
+        assert!(settings.logging.access_log);
+        assert_eq!(settings.cache.max_entry_size, Some(64 * 1024));
+        assert_eq!(
+            settings.cache.ttl(Task::Rerank),
+            Duration::from_secs(60 * 60)
+        );
+        assert_eq!(settings.rate_limit.requests_per_second, 2.5);
+    }
+
+    #[test]
+    fn test_missing_settings_take_their_default_values() {
+        let defaults = AppSettings::default();
+        assert_eq!(defaults.grpc_server.address, "127.0.0.1");
+        assert_eq!(defaults.grpc_server.port, 50051);
+        assert_eq!(defaults.logging.level, "info");
+        assert_eq!(
+            defaults.mighty_server.unwrap().base_url,
+            vec![DEFAULT_BASE_URL]
+        );
+        assert!(defaults.api_server.is_none());
+
+        let settings: AppSettings = Config::builder()
+            .add_source(File::from_str(
+                "[grpc_server]\nport = 50052\n\n[mighty_server]\npool = { grpc_channels = 2 }",
+                FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        assert_eq!(settings.grpc_server.address, "127.0.0.1");
+        assert_eq!(settings.grpc_server.port, 50052);
+        assert_eq!(settings.logging.level, "info");
+        assert!(settings.mighty_server.unwrap().base_url.is_empty());
+    }
+
+    #[test]
+    fn test_yaml_and_json_files_are_loaded() {
+        let dir = std::env::temp_dir().join(format!("mighty-grpc-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let name = dir.join("config").to_string_lossy().into_owned();
+        let cli = Cli {
+            config: Some(name.clone()),
+            ..Cli::default()
+        };
+
+        std::fs::write(
+            dir.join("config.json"),
+            r#"{ "grpc_server": { "address": "127.0.0.1", "port": 50052 },
+                 "logging": { "level": "info" }, "cache": { "ttl": "1m" } }"#,
+        )
+        .unwrap();
+        assert_eq!(find_config_file(&name), dir.join("config.json"));
+        let settings = AppSettings::load(&cli).unwrap();
+        assert_eq!(settings.grpc_server.port, 50052);
+        assert_eq!(settings.cache.ttl(Task::Rerank), Duration::from_secs(60));
+
+        std::fs::write(
+            dir.join("config.yaml"),
+            "grpc_server:\n  address: 127.0.0.1\n  port: 50053\nlogging:\n  level: info\n",
+        )
+        .unwrap();
+        assert_eq!(find_config_file(&name), dir.join("config.yaml"));
+        assert_eq!(AppSettings::load(&cli).unwrap().grpc_server.port, 50053);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_resilience_overrides_per_task() {
+        let settings: AppSettings = Config::builder()
+            .add_source(File::from_str(
+                r#"
+                [resilience]
+                max_attempts = 3
+                initial_backoff = "100ms"
+                max_backoff = "300ms"
+
+                [resilience.tasks]
+                question_answering = { max_attempts = 1, hedge = false }
+                "#,
+                FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        let resilience = &settings.resilience;
+
+        let policy = resilience.policy(Task::Embeddings);
+        assert_eq!(policy.max_attempts, 3);
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(300));
+        assert_eq!(policy.backoff(100), Duration::from_millis(300));
+
+        assert_eq!(resilience.policy(Task::QuestionAnswering).max_attempts, 1);
+        assert_eq!(
+            resilience.unhedged_tasks(),
+            HashSet::from([Task::QuestionAnswering])
+        );
+    }
+
+    #[test]
+    fn test_grpc_server_listeners() {
+        let settings: AppSettings = Config::builder()
+            .add_source(File::from_str(
+                r#"
+                [grpc_server]
+                address = "127.0.0.1"
+                port = 50051
+
+                [[grpc_server.listeners]]
+                address = "10.0.3.7"
+                port = 50443
+                tls = { cert_path = "tls.crt", key_path = "tls.key" }
+
+                [[grpc_server.listeners]]
+                path = "/run/mighty-grpc/grpc.sock"
+                "#,
+                FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        let listeners = settings.grpc_server.all_listeners();
+        let names: Vec<String> = listeners.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            names,
+            [
+                "127.0.0.1:50051",
+                "10.0.3.7:50443",
+                "unix:/run/mighty-grpc/grpc.sock"
+            ]
+        );
+        assert!(listeners[0].tls.is_none());
+        assert!(listeners[1].tls.is_some());
+    }
+}
@@ -0,0 +1,639 @@
+//! The JSON Schema of `config.toml`, exported by `mighty_grpc config schema` for editor
+//! autocompletion and used by `config lint` to report unknown keys.
+//!
+//! The schema is maintained by hand alongside the settings types; the tests check that it
+//! covers every key of the documented `config.toml`.
+
+use serde_json::{json, Map, Value};
+
+use super::{Task, METHODS};
+
+const DURATION: &str = "A duration with a unit, e.g. \"500ms\", \"10s\", \"5m\" or \"1h\"";
+const BYTE_SIZE: &str = "A size in bytes, or with a unit, e.g. \"64MiB\" or \"512kB\"";
+
+fn object(properties: Value) -> Value {
+    json!({ "type": "object", "properties": properties, "additionalProperties": false })
+}
+
+fn typed(kind: &str, description: &str) -> Value {
+    json!({ "type": kind, "description": description })
+}
+
+fn duration(description: &str) -> Value {
+    json!({ "type": "string", "description": format!("{} ({}).", description, DURATION) })
+}
+
+fn byte_size(description: &str) -> Value {
+    let description = format!("{} ({}).", description, BYTE_SIZE);
+    json!({ "type": ["string", "integer"], "description": description })
+}
+
+fn one_of(values: &[&str], description: &str) -> Value {
+    json!({ "enum": values, "description": description })
+}
+
+/// A table keyed by task name, e.g. `[circuit_breaker.tasks]`.
+fn per_task(value: Value) -> Value {
+    let properties: Map<String, Value> = Task::ALL
+        .iter()
+        .map(|task| (task.as_str().to_string(), value.clone()))
+        .collect();
+    object(Value::Object(properties))
+}
+
+/// A table keyed by inference method name, e.g. `[compression.methods]`.
+fn per_method(value: Value) -> Value {
+    let properties: Map<String, Value> = METHODS
+        .iter()
+        .map(|method| (method.to_string(), value.clone()))
+        .collect();
+    object(Value::Object(properties))
+}
+
+/// A backend selected by kind, e.g. of the fallback chain.
+fn backend() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "kind": one_of(
+                &["rest", "binary", "ffi", "onnx", "openai", "tei"],
+                "The kind of backend, configured by its own section.",
+            ),
+            "base_url": typed("string", "The base URL of a rest backend."),
+        },
+        "required": ["kind"],
+        "additionalProperties": false,
+    })
+}
+
+/// The backend serving a model, in `[routing]`.
+fn route() -> Value {
+    let mut route = backend();
+    route["properties"]["model"] = typed("string", "The model calls select the route with.");
+    route["required"] = json!(["model", "kind"]);
+    route
+}
+
+fn server() -> Value {
+    object(json!({
+        "address": typed("string", "The address the server listens on."),
+        "port": typed("integer", "The port the server listens on."),
+        "tls": tls(),
+        "max_receive_message_size": {
+            "type": ["string", "integer"],
+            "description": format!("The maximum request message size ({}).", BYTE_SIZE),
+        },
+        "max_send_message_size": {
+            "type": ["string", "integer"],
+            "description": format!("The maximum response message size ({}).", BYTE_SIZE),
+        },
+        "keepalive_interval": duration("The interval between HTTP/2 pings on idle connections"),
+        "keepalive_timeout": duration("How long to wait for a ping acknowledgement"),
+        "max_concurrent_streams": typed("integer", "The maximum calls at once per connection."),
+        "listeners": {
+            "type": "array",
+            "items": object(json!({
+                "address": typed("string", "The address listened on."),
+                "port": typed("integer", "The port listened on."),
+                "path": typed("string", "The Unix domain socket listened on instead."),
+                "tls": tls(),
+            })),
+            "description": "Further listeners serving the same gRPC server.",
+        },
+    }))
+}
+
+fn tls() -> Value {
+    let mut tls = object(json!({
+        "cert_path": typed("string", "The PEM file of the certificate chain."),
+        "key_path": typed("string", "The PEM file of the private key."),
+        "reload_interval": duration("The interval between checks for a rotated certificate"),
+        "client_ca_path": typed("string", "The PEM bundle of the client CAs, for mTLS."),
+        "client_cert_optional": typed("boolean", "Whether clients may omit a certificate."),
+    }));
+    tls["required"] = json!(["cert_path", "key_path"]);
+    tls
+}
+
+fn health_check() -> Value {
+    object(json!({
+        "enabled": typed("boolean", "Whether instances are actively probed."),
+        "interval": duration("The interval between probes"),
+        "timeout": duration("How long a probe may take before failing"),
+        "unhealthy_threshold": typed("integer", "Consecutive failures before ejection."),
+        "healthy_threshold": typed("integer", "Consecutive successes before re-admission."),
+    }))
+}
+
+fn load_balancing() -> Value {
+    one_of(
+        &[
+            "round_robin",
+            "least_outstanding",
+            "random",
+            "consistent_hash",
+        ],
+        "The strategy used to distribute calls across replicas.",
+    )
+}
+
+fn mighty_server() -> Value {
+    object(json!({
+        "base_url": {
+            "description": "The base URL of the Mighty server, or a list of replicas.",
+            "oneOf": [{ "type": "string" }, { "type": "array", "items": { "type": "string" } }],
+        },
+        "load_balancing": load_balancing(),
+        "task_load_balancing": per_task(load_balancing()),
+        "health_check": health_check(),
+        "discovery": object(json!({
+            "enabled": typed("boolean", "Whether base URL hostnames are re-resolved."),
+            "refresh_interval": duration("The interval between resolutions"),
+        })),
+        "pool": object(json!({
+            "max_idle_per_host": typed("integer", "Idle connections kept per upstream host."),
+            "idle_timeout": duration("How long idle connections are kept"),
+            "http_version": one_of(&["auto", "http1", "http2"], "The upstream HTTP version."),
+            "grpc_channels": typed("integer", "HTTP/2 channels per grpc:// upstream."),
+        })),
+        "user_agent": typed("string", "Prepended to the User-Agent sent upstream."),
+        "forward_client_address": typed("boolean", "Send the peer address in X-Forwarded-For."),
+        "strict_responses": typed("boolean", "Reject responses missing or mistyping fields."),
+        "batch_endpoints": typed("boolean", "Send batches of texts in one upstream call."),
+        "response_format": one_of(&["json", "msgpack", "cbor"], "The upstream body format."),
+        "field_aliases": {
+            "type": "object",
+            "additionalProperties": typed("string", "The field the upstream field is read as."),
+            "description": "Upstream response fields to rename, e.g. \"embeddings\" = \"outputs\".",
+        },
+        "max_body_size": {
+            "type": ["string", "integer"],
+            "description": format!("The maximum upstream response size ({}).", BYTE_SIZE),
+        },
+        "hedging": object(json!({
+            "enabled": typed("boolean", "Whether slow calls are hedged."),
+            "percentile": typed("number", "The latency percentile after which calls are hedged."),
+            "min_delay": duration("The lower bound of the hedging delay"),
+            "max_delay": duration("The upper bound of the hedging delay"),
+            "window_size": typed("integer", "The number of recent latencies tracked."),
+        })),
+        "ramp": object(json!({
+            "enabled": typed("boolean", "Whether new replicas are ramped up."),
+            "steps": {
+                "type": "array",
+                "items": { "type": "number" },
+                "description": "Percent of a replica's full share at each step.",
+            },
+            "duration": duration("The total duration of the ramp"),
+            "max_error_rate": typed("number", "The error rate rolling the ramp back."),
+            "min_requests": typed("integer", "Calls before the error rate is evaluated."),
+        })),
+    }))
+}
+
+fn circuit_breaker() -> Value {
+    let thresholds = json!({
+        "failure_threshold": typed("integer", "Consecutive failures opening the breaker."),
+        "open_duration": duration("How long the breaker stays open"),
+        "half_open_probes": typed("integer", "Probes that must succeed to close the breaker."),
+    });
+    let mut properties = thresholds.clone();
+    properties["enabled"] = typed("boolean", "Whether calls are guarded by circuit breakers.");
+    properties["tasks"] = per_task(object(thresholds));
+    object(properties)
+}
+
+fn resilience() -> Value {
+    let policy = json!({
+        "max_attempts": typed("integer", "Attempts of a call failing upstream, 1 not retrying."),
+        "initial_backoff": duration("The delay before the first retry"),
+        "max_backoff": duration("The upper bound of the delay between attempts"),
+        "multiplier": typed("number", "The factor the delay grows by after each retry."),
+        "attempt_timeout": duration("How long each attempt may take"),
+        "hedge": typed("boolean", "Whether slow calls may be hedged."),
+    });
+    let mut properties = policy.clone();
+    properties["tasks"] = per_task(object(policy));
+    object(properties)
+}
+
+/// Returns the JSON Schema (draft 2020-12) of the configuration file.
+pub fn schema() -> Value {
+    let mut schema = object(json!({
+        "grpc_server": server(),
+        "api_server": server(),
+        "mighty_server": mighty_server(),
+        "client": one_of(
+            &["rest", "binary", "ffi", "onnx", "openai", "tei"],
+            "The kind of the primary backend, configured by its own section.",
+        ),
+        "logging": object(json!({
+            "level": typed("string", "The log level, e.g. \"info\"."),
+            "targets": {
+                "type": "object",
+                "additionalProperties": typed("string", "The log level of the target."),
+                "description": "The levels of specific targets, e.g. \"hyper\" = \"warn\".",
+            },
+            "format": one_of(&["text", "json"], "The format of log lines."),
+            "access_log": typed("boolean", "Whether a line is logged per RPC."),
+            "file": typed("string", "The file log lines are written to, rotated by size."),
+            "max_file_size": byte_size("The size the log file is rotated past"),
+            "max_files": typed("integer", "The number of rotated log files kept."),
+        })),
+        "tracing": object(json!({
+            "enabled": typed("boolean", "Whether spans are exported over OTLP."),
+            "endpoint": typed("string", "The OTLP/gRPC endpoint of the collector."),
+            "service_name": typed("string", "The service.name of the exported spans."),
+            "sample_ratio": typed("number", "The fraction of new traces exported."),
+            "export_timeout": duration("The timeout of each export"),
+        })),
+        "validation": object(json!({
+            "non_empty_outputs": typed("boolean", "Reject responses without outputs."),
+            "score_range": typed("boolean", "Reject entity scores outside [0, 1]."),
+            "expected_dimension": typed("integer", "The expected embedding dimension."),
+            "dimension_from_metadata": typed("boolean", "Check dimensions against metadata."),
+        })),
+        "api_keys": object(json!({
+            "enabled": typed("boolean", "Whether calls must carry a valid x-api-key."),
+            "keys": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "name": typed("string", "The name of the key holder."),
+                        "key": typed("string", "The secret key, or a file: or env: reference."),
+                    },
+                    "required": ["name", "key"],
+                    "additionalProperties": false,
+                },
+            },
+        })),
+        "jwt": object(json!({
+            "enabled": typed("boolean", "Whether calls must carry a valid bearer token."),
+            "issuer": typed("string", "The expected iss claim."),
+            "audience": {
+                "description": "The accepted aud claims.",
+                "oneOf": [{ "type": "string" }, { "type": "array", "items": { "type": "string" } }],
+            },
+            "jwks_url": typed("string", "The URL of the JSON Web Key Set."),
+            "jwks_refresh_interval": duration("The interval between key set fetches"),
+            "leeway": duration("The clock skew tolerated on exp and nbf"),
+            "identity_claim": typed("string", "The claim used as the caller identity."),
+            "tenant_claim": typed("string", "The claim used as the tenant."),
+        })),
+        "quotas": object(json!({
+            "enabled": typed("boolean", "Whether daily usage quotas are enforced."),
+            "daily_requests": typed("integer", "The inference calls a caller may make per day."),
+            "daily_tokens": typed("integer", "The tokens a caller may embed per day."),
+            "callers": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "identity": typed("string", "The identity of the caller."),
+                        "daily_requests": typed("integer", "The caller's daily calls."),
+                        "daily_tokens": typed("integer", "The caller's daily tokens."),
+                    },
+                    "required": ["identity"],
+                    "additionalProperties": false,
+                },
+            },
+        })),
+        "admin": object(json!({
+            "identities": {
+                "type": "array",
+                "items": { "type": "string" },
+                "description": "The caller identities allowed to call the admin RPCs operating the gateway.",
+            },
+        })),
+        "debug": object(json!({
+            "raw_json": typed("boolean", "Whether admins may request the raw upstream JSON."),
+        })),
+        "network_acl": object(json!({
+            "allow": {
+                "type": "array",
+                "items": { "type": "string" },
+                "description": "The IP ranges allowed to call, e.g. 10.0.0.0/8; all if empty.",
+            },
+            "deny": {
+                "type": "array",
+                "items": { "type": "string" },
+                "description": "The IP ranges denied, even within an allowed range.",
+            },
+        })),
+        "client_rate_limit": object(json!({
+            "enabled": typed("boolean", "Whether calls are limited per client."),
+            "requests_per_second": typed("number", "The sustained call rate of a client."),
+            "burst": typed("integer", "The calls a client may make at once after idling."),
+            "clients": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "identity": typed("string", "The identity of the client."),
+                        "requests_per_second": typed("number", "The client's sustained rate."),
+                        "burst": typed("integer", "The client's burst."),
+                    },
+                    "required": ["identity", "requests_per_second", "burst"],
+                    "additionalProperties": false,
+                },
+            },
+            "max_clients": typed("integer", "The clients tracked before idle ones are dropped."),
+        })),
+        "aliases": {
+            "type": "array",
+            "items": {
+                "type": "object",
+                "properties": {
+                    "from": typed("string", "The legacy RPC path."),
+                    "to": typed("string", "The current RPC path."),
+                    "message": typed("string", "The deprecation notice."),
+                },
+                "required": ["from", "to"],
+                "additionalProperties": false,
+            },
+        },
+        "readiness": object(json!({
+            "require_metadata": typed("boolean", "Require the model metadata."),
+            "require_cache_warmup": typed("boolean", "Require the cache warmup."),
+            "canary_inferences": typed("integer", "Successful canary embeddings required."),
+            "canary_text": typed("string", "The text of canary inferences."),
+            "retry_interval": duration("The interval between retries of failed checks"),
+            "require_upstream_at_startup": typed("boolean", "Exit if the upstream is down."),
+            "startup_timeout": duration("The startup upstream health check timeout"),
+            "upstream_failure_threshold": typed(
+                "integer",
+                "Failed upstream health checks in a row before the gateway isn't ready; 0 disables.",
+            ),
+            "upstream_check_interval": duration("The interval between upstream health checks"),
+            "warm_up": object(json!({
+                "enabled": typed("boolean", "Whether the upstream is warmed up at startup."),
+                "connections": typed("integer", "The upstream connections opened ahead."),
+                "metadata": typed("boolean", "Whether the model metadata is fetched."),
+                "texts": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "The texts sent to each of tasks.",
+                },
+                "tasks": {
+                    "type": "array",
+                    "items": one_of(
+                        &[
+                            "embeddings",
+                            "sentence_transformers",
+                            "sequence_classification",
+                            "token_classification",
+                        ],
+                        "A task taking a single text.",
+                    ),
+                    "description": "The tasks texts are sent to.",
+                },
+                "timeout": duration("How long the warm-up may delay readiness"),
+            })),
+        })),
+        "endpoints": per_method(typed("boolean", "Whether the method is served.")),
+        "compression": object(json!({
+            "accept": {
+                "type": "array",
+                "items": one_of(&["gzip", "zstd"], "An encoding."),
+                "description": "The encodings requests may be compressed with.",
+            },
+            "send": {
+                "type": "array",
+                "items": one_of(&["gzip", "zstd"], "An encoding."),
+                "description": "The encodings responses may be compressed with.",
+            },
+            "methods": per_method(typed(
+                "boolean",
+                "Whether the method's responses are compressed.",
+            )),
+        })),
+        "timeouts": object(json!({
+            "default": duration("The timeout of methods without their own"),
+            "methods": per_method(duration("The timeout of the method's calls")),
+        })),
+        "request_validation": object(json!({
+            "max_text_bytes": byte_size("Reject longer texts"),
+            "max_text_chars": typed("integer", "Reject texts of more characters."),
+        })),
+        "batch": object(json!({
+            "normalize": typed("boolean", "Trim and collapse whitespace."),
+            "deduplicate": typed("boolean", "Embed identical texts once per batch."),
+            "max_text_chars": typed("integer", "Truncate longer texts."),
+        })),
+        "binary": object(json!({
+            "path": typed("string", "The Mighty server executable."),
+            "args": {
+                "type": "array",
+                "items": { "type": "string" },
+                "description": "Arguments, where {port} and {model_dir} are substituted.",
+            },
+            "model_dir": typed("string", "The directory the models are loaded from."),
+            "model_root": typed("string", "The directory UpgradeModel may load model versions from."),
+            "workers": typed("integer", "The number of worker subprocesses."),
+            "port": typed("integer", "The port of the first worker."),
+            "startup_timeout": duration("How long a worker may take to become ready"),
+            "restart_delay": duration("The delay before a crashed worker is restarted"),
+            "max_restart_delay": duration("The upper bound of the restart delay"),
+            "health_check": health_check(),
+        })),
+        "ffi": object(json!({
+            "library": typed("string", "The Mighty shared library."),
+            "model_dir": typed("string", "The directory the models are loaded from."),
+            "strict_responses": typed("boolean", "Reject responses missing or mistyping fields."),
+        })),
+        "onnx": object(json!({
+            "model": typed("string", "The ONNX model."),
+            "tokenizer": typed("string", "The tokenizer.json matching the model."),
+            "task": one_of(&["embeddings", "sequence_classification"], "The model's task."),
+            "max_length": typed("integer", "The maximum number of tokens per input."),
+            "intra_threads": typed("integer", "The number of threads per inference."),
+            "runtime_library": typed("string", "The ONNX Runtime shared library."),
+        })),
+        "openai": object(json!({
+            "base_url": typed("string", "The API base URL, including its version."),
+            "model": typed("string", "The embedding model requested."),
+            "api_key": typed("string", "The API key, or a file: or env: secret reference."),
+            "api_key_env": typed("string", "The environment variable holding the API key."),
+            "dimensions": typed("integer", "The number of dimensions requested."),
+            "timeout": duration("The timeout of each call"),
+        })),
+        "tei": object(json!({
+            "base_url": typed("string", "The base URL of the TEI server."),
+            "api_key": typed("string", "The API key, or a file: or env: secret reference."),
+            "api_key_env": typed("string", "The environment variable holding the API key."),
+            "truncate": typed("boolean", "Truncate inputs longer than the model's maximum."),
+            "timeout": duration("The timeout of each call"),
+        })),
+        "recently_similar": object(json!({
+            "window_size": typed("integer", "The number of recent embeddings remembered."),
+            "max_entries": typed("integer", "The recent embeddings remembered across callers."),
+            "ttl": duration("How long embeddings are remembered"),
+            "threshold": typed("number", "The cosine similarity of near-duplicates."),
+        })),
+        "circuit_breaker": circuit_breaker(),
+        "resilience": resilience(),
+        "rate_limit": object(json!({
+            "enabled": typed("boolean", "Whether upstream calls are limited."),
+            "requests_per_second": typed("number", "The sustained upstream call rate."),
+            "burst": typed("integer", "The calls that may be sent at once after idling."),
+            "max_concurrency": typed("integer", "The maximum number of calls in flight."),
+            "max_queue": typed("integer", "The maximum number of calls waiting."),
+            "queue_timeout": duration("How long a call may wait before being shed"),
+        })),
+        "chaos": object(json!({
+            "enabled": typed("boolean", "Whether faults are injected from startup."),
+            "error_rate": typed("number", "The fraction of calls failed."),
+            "error": one_of(
+                &["unavailable", "internal", "deadline_exceeded", "resource_exhausted"],
+                "The error of failed calls.",
+            ),
+            "latency": duration("The latency added to every call"),
+            "jitter": duration("The upper bound of the random latency added"),
+            "truncation_rate": typed("number", "The fraction of responses cut in half."),
+            "malformed_json_rate": typed("number", "The fraction of calls given bad JSON."),
+        })),
+        "backends": per_task(backend()),
+        "routing": object(json!({
+            "default": typed("string", "The model serving calls without x-model."),
+            "routes": { "type": "array", "items": route() },
+        })),
+        "canary": object(json!({
+            "enabled": typed("boolean", "Whether calls are split with a canary backend."),
+            "backend": backend(),
+            "weight": typed("number", "The percentage of calls served by the canary."),
+            "weights": per_task(typed("number", "The task's percentage of calls on the canary.")),
+        })),
+        "fallback": object(json!({
+            "enabled": typed("boolean", "Whether calls fall back to other backends."),
+            "attempt_timeout": duration("How long a call may take on a backend"),
+            "backends": {
+                "type": "array",
+                "items": backend(),
+                "description": "The backends tried after the primary one, in order.",
+            },
+        })),
+        "shadow": object(json!({
+            "enabled": typed("boolean", "Whether calls are mirrored to a shadow backend."),
+            "base_url": typed("string", "The base URL of the shadow Mighty server."),
+            "sample_rate": typed("number", "The fraction of calls mirrored."),
+            "max_in_flight": typed("integer", "The maximum number of mirrored calls in flight."),
+            "max_cosine_distance": typed("number", "The distance of diverging embeddings."),
+            "timeout": duration("The timeout of each mirrored call"),
+        })),
+        "vcr": object(json!({
+            "mode": one_of(&["off", "record", "replay"], "Record or replay upstream responses."),
+            "fixtures": typed("string", "The directory of the fixture files."),
+        })),
+        "micro_batching": object(json!({
+            "enabled": typed("boolean", "Whether single-text calls are batched."),
+            "window": duration("How long a batch waits for more texts"),
+            "max_batch_size": typed("integer", "The number of texts sending a batch early."),
+            "max_queue": typed("integer", "The maximum number of calls waiting to be batched."),
+        })),
+        "single_flight": object(json!({
+            "enabled": typed("boolean", "Whether identical concurrent calls are coalesced."),
+        })),
+        "cache": object(json!({
+            "enabled": typed("boolean", "Whether responses are cached."),
+            "capacity": typed("integer", "The maximum number of responses cached."),
+            "backend": one_of(&["memory", "redis"], "Where responses are cached."),
+            "ttl": duration("How long responses are served from the cache"),
+            "ttls": per_task(duration("How long the task's responses are served from the cache")),
+            "max_entry_size": byte_size("Don't cache larger responses"),
+            "lock_timeout": duration("How long duplicate calls wait for the first one"),
+            "redis_url": typed("string", "The Redis server responses are cached in."),
+            "key_prefix": typed("string", "The prefix of the Redis keys."),
+            "generation": typed("string", "The generation of the models, part of every key."),
+        })),
+        "hot_reload": object(json!({
+            "watch": typed("boolean", "Whether the file is reloaded whenever it changes."),
+            "interval": duration("The interval between checks of the file"),
+        })),
+        "runtime": object(json!({
+            "flavor": one_of(&["multi_thread", "current_thread"], "The Tokio scheduler."),
+            "worker_threads": typed("integer", "The worker threads, one per CPU by default."),
+            "max_blocking_threads": typed("integer", "The threads running blocking calls."),
+        })),
+    }));
+    schema["$schema"] = json!("https://json-schema.org/draft/2020-12/schema");
+    schema["title"] = json!("mighty-grpc configuration");
+    schema
+}
+
+/// Returns the dotted paths of the keys of `value` that `schema` doesn't declare, e.g.
+/// `mighty_server.helth_check`.
+pub fn unknown_keys(schema: &Value, value: &Value) -> Vec<String> {
+    let mut unknown = Vec::new();
+    collect_unknown_keys(schema, value, "", &mut unknown);
+    unknown.sort();
+    unknown
+}
+
+fn collect_unknown_keys(schema: &Value, value: &Value, path: &str, unknown: &mut Vec<String>) {
+    match value {
+        Value::Object(map) => {
+            let Some(properties) = schema.get("properties").and_then(Value::as_object) else {
+                return;
+            };
+            for (key, value) in map {
+                let key_path = match path {
+                    "" => key.clone(),
+                    path => format!("{}.{}", path, key),
+                };
+                match properties.get(key) {
+                    Some(schema) => collect_unknown_keys(schema, value, &key_path, unknown),
+                    None => unknown.push(key_path),
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    let item_path = format!("{}[{}]", path, index);
+                    collect_unknown_keys(schema, item, &item_path, unknown);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::{Config, File, FileFormat};
+
+    use super::*;
+
+    #[test]
+    fn test_schema_covers_the_documented_config() {
+        let documented: Value = Config::builder()
+            .add_source(File::from_str(
+                include_str!("../../config.toml"),
+                FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        assert_eq!(unknown_keys(&schema(), &documented), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let value = json!({
+            "grpc_server": { "address": "127.0.0.1", "port": 50051, "prot": 1 },
+            "mighty_server": { "helth_check": {}, "task_load_balancing": { "embedding": "random" } },
+            "aliases": [{ "from": "/a", "to": "/b", "msg": "deprecated" }],
+        });
+        assert_eq!(
+            unknown_keys(&schema(), &value),
+            vec![
+                "aliases[0].msg",
+                "grpc_server.prot",
+                "mighty_server.helth_check",
+                "mighty_server.task_load_balancing.embedding",
+            ]
+        );
+    }
+}
@@ -0,0 +1,101 @@
+//! Secrets referenced by the configuration rather than written in it.
+//!
+//! Sensitive values, e.g. API keys or the Redis URL, may be given as `file:/path`, read from a
+//! file such as a mounted Kubernetes or Docker secret, or as `env:VAR`, read from an environment
+//! variable. References are resolved when the configuration is loaded, so a missing secret fails
+//! the startup (or the reload) instead of the first call using it. Other values are used as is.
+
+use std::env;
+use std::fmt;
+use std::fs;
+
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+
+/// What the value of a secret is replaced with in `Debug` output.
+const REDACTED: &str = "\"<redacted>\"";
+
+/// The prefix of secrets read from a file, e.g. `file:/run/secrets/api-key`.
+pub const FILE_PREFIX: &str = "file:";
+
+/// The prefix of secrets read from an environment variable, e.g. `env:OPENAI_API_KEY`.
+pub const ENV_PREFIX: &str = "env:";
+
+/// Resolves a secret: the content of the file of a `file:` reference, without its trailing line
+/// break, the value of the variable of an `env:` reference, else `value` itself.
+pub fn resolve(value: &str) -> Result<String, String> {
+    if let Some(path) = value.strip_prefix(FILE_PREFIX) {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("the secret file {} can't be read: {}", path, e))?;
+        return Ok(content.trim_end_matches(['\r', '\n']).to_string());
+    }
+    if let Some(name) = value.strip_prefix(ENV_PREFIX) {
+        return env::var(name)
+            .map_err(|_| format!("the secret environment variable {} is not set", name));
+    }
+    Ok(value.to_string())
+}
+
+/// The `Secret` struct holds a sensitive setting, resolving references when deserialized. Its
+/// value is redacted from its `Debug` output, e.g. from the effective configuration logged at
+/// startup, and must be exposed explicitly where it's used.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    /// Returns the value of the secret.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        resolve(&String::deserialize(deserializer)?)
+            .map(Secret)
+            .map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let path = env::temp_dir().join(format!("mighty-grpc-secret-{}", std::process::id()));
+        fs::write(&path, "s3cr3t\n").unwrap();
+        env::set_var("MIGHTY_GRPC_TEST_SECRET", "from-env");
+
+        assert_eq!(
+            resolve(&format!("file:{}", path.display())).unwrap(),
+            "s3cr3t"
+        );
+        assert_eq!(resolve("env:MIGHTY_GRPC_TEST_SECRET").unwrap(), "from-env");
+        assert_eq!(resolve("literal").unwrap(), "literal");
+        assert!(resolve("env:MIGHTY_GRPC_TEST_UNSET_SECRET").is_err());
+        assert!(resolve("file:/nonexistent/secret").is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_secrets_are_redacted() {
+        let secret = Secret::new("s3cr3t");
+        assert_eq!(secret.expose(), "s3cr3t");
+        assert_eq!(format!("{:?}", Some(secret)), "Some(\"<redacted>\")");
+    }
+}
@@ -0,0 +1,184 @@
+//! Human-friendly duration and size values for the configuration.
+//!
+//! Durations are written as a sequence of amounts with units, e.g. `"250ms"`, `"10s"` or
+//! `"1m30s"`. Sizes are written as an amount with an optional decimal (`KB`, `MB`, `GB`) or
+//! binary (`KiB`, `MiB`, `GiB`) unit, e.g. `"8MiB"`; bare numbers are bytes. Durations always
+//! require a unit, so a value can't silently be read in seconds where milliseconds were meant.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::Duration;
+
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+
+/// Parses a duration such as `"2s500ms"`. Supported units are `ns`, `us`, `ms`, `s`, `m`, `h`
+/// and `d`; amounts may be fractional (`"1.5s"`).
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err("empty duration".to_string());
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let (amount, unit, remainder) = split_component(rest);
+        let amount = amount
+            .parse::<f64>()
+            .map_err(|_| format!("invalid duration `{}`", value))?;
+        let nanos_per_unit: u64 = match unit {
+            "ns" => 1,
+            "us" | "µs" => 1_000,
+            "ms" => 1_000_000,
+            "s" => 1_000_000_000,
+            "m" => 60 * 1_000_000_000,
+            "h" => 3600 * 1_000_000_000,
+            "d" => 86400 * 1_000_000_000,
+            "" => {
+                return Err(format!(
+                    "duration `{}` is missing a unit, e.g. \"{}s\"",
+                    value, amount
+                ))
+            }
+            unit => return Err(format!("unknown duration unit `{}` in `{}`", unit, value)),
+        };
+        let nanos = (amount * nanos_per_unit as f64).round();
+        if !(0.0..=u64::MAX as f64).contains(&nanos) {
+            return Err(format!("duration `{}` is out of range", value));
+        }
+        total = total
+            .checked_add(Duration::from_nanos(nanos as u64))
+            .ok_or_else(|| format!("duration `{}` is out of range", value))?;
+        rest = remainder.trim_start();
+    }
+    Ok(total)
+}
+
+/// Parses a size such as `"8MiB"` or `"512"` (bytes) into a number of bytes.
+pub fn parse_byte_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (amount, unit, remainder) = split_component(value);
+    if !remainder.trim().is_empty() {
+        return Err(format!("invalid size `{}`", value));
+    }
+    let amount = amount
+        .parse::<f64>()
+        .map_err(|_| format!("invalid size `{}`", value))?;
+    let multiplier: u64 = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "kib" => 1 << 10,
+        "mb" => 1000 * 1000,
+        "mib" => 1 << 20,
+        "gb" => 1000 * 1000 * 1000,
+        "gib" => 1 << 30,
+        _ => return Err(format!("unknown size unit `{}` in `{}`", unit, value)),
+    };
+    let bytes = amount * multiplier as f64;
+    if !(0.0..=u64::MAX as f64).contains(&bytes) {
+        return Err(format!("size `{}` is out of range", value));
+    }
+    Ok(bytes as u64)
+}
+
+/// Splits the leading `<amount><unit>` component off `value`, returning the amount, the unit
+/// and the remainder.
+fn split_component(value: &str) -> (&str, &str, &str) {
+    let amount_end = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (amount, rest) = value.split_at(amount_end);
+    let rest = rest.trim_start();
+    let unit_end = rest
+        .find(|c: char| c.is_ascii_digit() || c == '.' || c.is_whitespace())
+        .unwrap_or(rest.len());
+    let (unit, remainder) = rest.split_at(unit_end);
+    (amount, unit, remainder)
+}
+
+/// Deserializes a duration string, e.g. `"10s"`.
+pub fn duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    parse_duration(&value).map_err(D::Error::custom)
+}
+
+/// Deserializes an optional duration string.
+pub fn option_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| parse_duration(&value).map_err(D::Error::custom))
+        .transpose()
+}
+
+/// Deserializes a table of duration strings, e.g. `{ question_answering = "60s" }`.
+pub fn duration_map<'de, D, K>(deserializer: D) -> Result<HashMap<K, Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+    K: Deserialize<'de> + Eq + Hash,
+{
+    HashMap::<K, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(key, value)| Ok((key, parse_duration(&value).map_err(D::Error::custom)?)))
+        .collect()
+}
+
+/// Deserializes a size given either as a number of bytes or as a string with a unit.
+pub fn byte_size<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+
+    match Size::deserialize(deserializer)? {
+        Size::Bytes(bytes) => Ok(bytes),
+        Size::Text(value) => parse_byte_size(&value).map_err(D::Error::custom),
+    }
+}
+
+/// Deserializes an optional size given either as a number of bytes or as a string with a unit.
+pub fn option_byte_size<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct Size(#[serde(deserialize_with = "byte_size")] u64);
+
+    Ok(Option::<Size>::deserialize(deserializer)?.map(|Size(bytes)| bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("2s500ms"), Ok(Duration::from_millis(2500)));
+        assert_eq!(parse_duration("1m 30s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1.5h"), Ok(Duration::from_secs(5400)));
+        assert!(parse_duration("10").unwrap_err().contains("missing a unit"));
+        assert!(parse_duration("10 parsecs").is_err());
+        assert!(parse_duration("").is_err());
+    }
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("512"), Ok(512));
+        assert_eq!(parse_byte_size("8MiB"), Ok(8 * 1024 * 1024));
+        assert_eq!(parse_byte_size("1.5 kb"), Ok(1500));
+        assert_eq!(parse_byte_size("2GB"), Ok(2_000_000_000));
+        assert!(parse_byte_size("8 MiB extra").is_err());
+        assert!(parse_byte_size("8XB").is_err());
+    }
+}
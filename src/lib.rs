@@ -1,3 +1,7 @@
+#![allow(clippy::result_large_err)] // `tonic::Status` is the error type mandated by the gRPC handlers
+#![recursion_limit = "256"] // the `json!` of the configuration schema
+
 pub mod config;
 pub mod proto;
 pub mod services;
+pub mod startup;
@@ -1,3 +1,34 @@
+#[cfg(feature = "reflection")]
+use tonic_reflection::server::{ServerReflection, ServerReflectionServer};
+
+#[cfg(feature = "reflection")]
+use crate::startup::StartupError;
+
+pub mod schema;
+
 pub mod mighty_proto {
     tonic::include_proto!("mighty_inference_server");
 }
+
+/// The standard gRPC health checking protocol, `grpc.health.v1`.
+pub mod health_proto {
+    tonic::include_proto!("grpc.health.v1");
+}
+
+/// The encoded file descriptor set generated by the build script, used for gRPC reflection and
+/// schema compatibility checks.
+pub const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!("mighty_inference.bin");
+
+/// The committed golden schema the compiled descriptor set is checked against.
+pub const GOLDEN_SCHEMA: &str = include_str!("mighty_inference.golden");
+
+/// Creates the gRPC server reflection service describing the API from `FILE_DESCRIPTOR_SET`, so
+/// grpcurl, grpcui and dynamic clients can call it without the proto file.
+#[cfg(feature = "reflection")]
+pub fn create_reflection_server(
+) -> Result<ServerReflectionServer<impl ServerReflection>, StartupError> {
+    tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .build()
+        .map_err(|e| StartupError::Server(e.to_string()))
+}
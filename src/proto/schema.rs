@@ -0,0 +1,232 @@
+//! Schema compatibility checks for the Mighty Inference protobuf definitions.
+//!
+//! A `Schema` is a flattened, order-independent view of a compiled file descriptor set: one entry
+//! per message field (number, label and type) and one per RPC (input and output types). The
+//! committed golden schema (`mighty_inference.golden`) is compared against the freshly compiled
+//! descriptor by the build script and by the `MightyAdmin/SchemaCompatibility` RPC, flagging
+//! changes that would break clients generated from an earlier version of the proto.
+//!
+//! This module is shared with `build.rs` and therefore must not depend on anything else in the
+//! crate.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use prost::Message;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{DescriptorProto, FileDescriptorSet};
+
+/// A flattened view of the fields and RPCs defined in a file descriptor set.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Schema {
+    entries: BTreeMap<String, String>,
+}
+
+impl Schema {
+    /// Decodes an encoded `FileDescriptorSet` (as produced by `protoc -o`) into a `Schema`.
+    pub fn decode(descriptor_set: &[u8]) -> Result<Self, prost::DecodeError> {
+        FileDescriptorSet::decode(descriptor_set).map(|set| Self::from_descriptor_set(&set))
+    }
+
+    /// Builds a `Schema` from a decoded `FileDescriptorSet`.
+    pub fn from_descriptor_set(set: &FileDescriptorSet) -> Self {
+        let mut entries = BTreeMap::new();
+        for file in &set.file {
+            let package = file.package();
+            for message in &file.message_type {
+                collect_fields(package, message, &mut entries);
+            }
+            for service in &file.service {
+                for method in &service.method {
+                    let key = format!("rpc {}.{}.{}", package, service.name(), method.name());
+                    let value = format!(
+                        "({}{}) returns ({}{})",
+                        stream_prefix(method.client_streaming()),
+                        method.input_type(),
+                        stream_prefix(method.server_streaming()),
+                        method.output_type()
+                    );
+                    entries.insert(key, value);
+                }
+            }
+        }
+        Self { entries }
+    }
+
+    /// Parses the textual golden format produced by `to_golden_string`. Blank lines and lines
+    /// starting with `#` are ignored.
+    pub fn parse(golden: &str) -> Self {
+        let entries = golden
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once(" = "))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Self { entries }
+    }
+
+    /// Renders the schema in the textual golden format, one entry per line.
+    pub fn to_golden_string(&self) -> String {
+        let mut out = String::from(
+            "# Golden schema for mighty_inference.proto. Regenerate after an intentional change with:\n\
+             #   MIGHTY_GRPC_BLESS_SCHEMA=1 cargo build\n",
+        );
+        for (key, value) in &self.entries {
+            let _ = writeln!(out, "{} = {}", key, value);
+        }
+        out
+    }
+
+    /// Returns a description of every change from `self` (the baseline) to `current` that breaks
+    /// wire or API compatibility: removed or renamed fields, changed field numbers, labels or
+    /// types, and removed or re-typed RPCs.
+    pub fn breaking_changes(&self, current: &Schema) -> Vec<String> {
+        let mut violations = Vec::new();
+        for (key, baseline) in &self.entries {
+            match current.entries.get(key) {
+                Some(value) if value == baseline => {}
+                Some(value) if key.starts_with("field ") => {
+                    let (old_number, old_type) = split_field(baseline);
+                    let (new_number, new_type) = split_field(value);
+                    if old_number != new_number {
+                        violations.push(format!(
+                            "{}: field number changed from {} to {}",
+                            key, old_number, new_number
+                        ));
+                    }
+                    if old_type != new_type {
+                        violations.push(format!(
+                            "{}: type changed from `{}` to `{}`",
+                            key, old_type, new_type
+                        ));
+                    }
+                }
+                Some(value) => violations.push(format!(
+                    "{}: signature changed from `{}` to `{}`",
+                    key, baseline, value
+                )),
+                None if key.starts_with("field ") => {
+                    let (number, _) = split_field(baseline);
+                    match current.renamed_field(key, number) {
+                        Some(renamed) => violations
+                            .push(format!("{}: field {} renamed to {}", key, number, renamed)),
+                        None => violations.push(format!("{}: field removed", key)),
+                    }
+                }
+                None => violations.push(format!("{}: rpc removed", key)),
+            }
+        }
+        violations
+    }
+
+    /// Returns the keys present in `current` but not in `self`, i.e. backwards-compatible
+    /// additions that should be recorded in the golden schema.
+    pub fn additions(&self, current: &Schema) -> Vec<String> {
+        current
+            .entries
+            .keys()
+            .filter(|key| !self.entries.contains_key(*key))
+            .cloned()
+            .collect()
+    }
+
+    fn renamed_field(&self, key: &str, number: &str) -> Option<&str> {
+        let message = key.rsplit_once('.').map(|(message, _)| message)?;
+        self.entries.iter().find_map(|(other, value)| {
+            let (other_message, name) = other.rsplit_once('.')?;
+            (other_message == message && split_field(value).0 == number).then_some(name)
+        })
+    }
+}
+
+fn collect_fields(scope: &str, message: &DescriptorProto, entries: &mut BTreeMap<String, String>) {
+    let scope = format!("{}.{}", scope, message.name());
+    for field in &message.field {
+        let label = match field.label() {
+            Label::Optional => "optional",
+            Label::Required => "required",
+            Label::Repeated => "repeated",
+        };
+        let field_type = match field.r#type() {
+            Type::Message | Type::Enum | Type::Group => field.type_name().to_string(),
+            other => other
+                .as_str_name()
+                .trim_start_matches("TYPE_")
+                .to_lowercase(),
+        };
+        entries.insert(
+            format!("field {}.{}", scope, field.name()),
+            format!("{} {} {}", field.number(), label, field_type),
+        );
+    }
+    for nested in &message.nested_type {
+        collect_fields(&scope, nested, entries);
+    }
+}
+
+fn stream_prefix(streaming: bool) -> &'static str {
+    if streaming {
+        "stream "
+    } else {
+        ""
+    }
+}
+
+/// Splits a field entry value into its number and its `label type` remainder.
+fn split_field(value: &str) -> (&str, &str) {
+    value.split_once(' ').unwrap_or((value, ""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GOLDEN: &str = "\
+field pkg.TextRequest.text = 1 optional string
+field pkg.TextRequest.model = 2 optional string
+rpc pkg.Service.Embeddings = (.pkg.TextRequest) returns (.pkg.EmbeddingsResponse)
+";
+
+    #[test]
+    fn test_golden_round_trip() {
+        let schema = Schema::parse(GOLDEN);
+        assert_eq!(Schema::parse(&schema.to_golden_string()), schema);
+    }
+
+    #[test]
+    fn test_additions_are_compatible() {
+        let baseline = Schema::parse(GOLDEN);
+        let current = Schema::parse(&format!(
+            "{}field pkg.TextRequest.extra = 3 optional bool\n",
+            GOLDEN
+        ));
+
+        assert!(baseline.breaking_changes(&current).is_empty());
+        assert_eq!(
+            baseline.additions(&current),
+            vec!["field pkg.TextRequest.extra"]
+        );
+    }
+
+    #[test]
+    fn test_breaking_changes() {
+        let baseline = Schema::parse(GOLDEN);
+        let current = Schema::parse(
+            "\
+field pkg.TextRequest.body = 1 optional string
+field pkg.TextRequest.model = 3 optional bytes
+",
+        );
+
+        assert_eq!(
+            baseline.breaking_changes(&current),
+            vec![
+                "field pkg.TextRequest.model: field number changed from 2 to 3",
+                "field pkg.TextRequest.model: type changed from `optional string` to `optional bytes`",
+                "field pkg.TextRequest.text: field 1 renamed to body",
+                "rpc pkg.Service.Embeddings: rpc removed",
+            ]
+        );
+    }
+}
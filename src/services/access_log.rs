@@ -0,0 +1,346 @@
+//! Access logging of the calls made to the gRPC server: one line per RPC with its method, peer,
+//! status code, duration, request size, upstream inference duration and request id, as text or
+//! as a JSON object ingestible by log pipelines (`[logging]`).
+
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use futures::future::BoxFuture;
+use log::info;
+use serde_json::{json, Map, Value};
+use tonic::codegen::http::{HeaderMap, HeaderValue, Request, Response};
+use tonic::codegen::Body;
+use tonic::transport::server::TcpConnectInfo;
+use tonic::Code;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::config::{LogFormat, LoggingConfig};
+use crate::services::context::{generate_request_id, REQUEST_ID_HEADER};
+
+/// The target of access log lines, logged at the info level.
+pub const ACCESS_LOG_TARGET: &str = "mighty_grpc::access";
+
+/// Header carrying the status code of a call, in the trailers or in trailers-only responses.
+const GRPC_STATUS_HEADER: &str = "grpc-status";
+
+/// The facts about a call learned past the access log layer, reported through the extensions of
+/// the request by the inference server (the request size) and the `MeteredClient` (the
+/// inference duration reported by the upstream).
+#[derive(Debug, Clone, Default)]
+pub struct AccessRecord(Arc<Mutex<Reported>>);
+
+#[derive(Debug, Default)]
+struct Reported {
+    request_size: Option<usize>,
+    upstream_took: Option<i32>,
+}
+
+impl AccessRecord {
+    /// Returns the record of `request`, if its call is access logged.
+    pub fn of<T>(request: &tonic::Request<T>) -> Option<AccessRecord> {
+        request.extensions().get::<AccessRecord>().cloned()
+    }
+
+    /// Reports the size of the encoded request message, in bytes.
+    pub fn set_request_size(&self, size: usize) {
+        self.0.lock().unwrap().request_size = Some(size);
+    }
+
+    /// Reports the inference duration of the call reported by the upstream, in milliseconds.
+    pub fn set_upstream_took(&self, took: i32) {
+        self.0.lock().unwrap().upstream_took = Some(took);
+    }
+}
+
+/// Returns the canonical name of `code`, e.g. `DEADLINE_EXCEEDED`.
+fn code_name(code: Code) -> &'static str {
+    match code {
+        Code::Ok => "OK",
+        Code::Cancelled => "CANCELLED",
+        Code::Unknown => "UNKNOWN",
+        Code::InvalidArgument => "INVALID_ARGUMENT",
+        Code::DeadlineExceeded => "DEADLINE_EXCEEDED",
+        Code::NotFound => "NOT_FOUND",
+        Code::AlreadyExists => "ALREADY_EXISTS",
+        Code::PermissionDenied => "PERMISSION_DENIED",
+        Code::ResourceExhausted => "RESOURCE_EXHAUSTED",
+        Code::FailedPrecondition => "FAILED_PRECONDITION",
+        Code::Aborted => "ABORTED",
+        Code::OutOfRange => "OUT_OF_RANGE",
+        Code::Unimplemented => "UNIMPLEMENTED",
+        Code::Internal => "INTERNAL",
+        Code::Unavailable => "UNAVAILABLE",
+        Code::DataLoss => "DATA_LOSS",
+        Code::Unauthenticated => "UNAUTHENTICATED",
+    }
+}
+
+fn status_of(headers: &HeaderMap) -> Option<Code> {
+    headers
+        .get(GRPC_STATUS_HEADER)
+        .map(|status| Code::from_bytes(status.as_bytes()))
+}
+
+fn peer_of<B>(request: &Request<B>) -> Option<SocketAddr> {
+    let extensions = request.extensions();
+    let tcp = extensions
+        .get::<TcpConnectInfo>()
+        .and_then(TcpConnectInfo::remote_addr);
+    #[cfg(feature = "tls")]
+    let tcp = tcp.or_else(|| {
+        extensions
+            .get::<tonic::transport::server::TlsConnectInfo<TcpConnectInfo>>()
+            .and_then(|info| info.get_ref().remote_addr())
+    });
+    tcp
+}
+
+/// The access log line of a call, logged when dropped along with its response body, once the
+/// response was sent or the call was cancelled.
+struct AccessEntry {
+    format: LogFormat,
+    method: String,
+    peer: Option<SocketAddr>,
+    request_id: String,
+    started: Instant,
+    status: Option<Code>,
+    record: AccessRecord,
+}
+
+impl Drop for AccessEntry {
+    fn drop(&mut self) {
+        // Calls whose response ended without a status were cancelled by their client
+        let status = code_name(self.status.unwrap_or(Code::Cancelled));
+        let duration_ms = self.started.elapsed().as_secs_f64() * 1000.0;
+        let reported = self.record.0.lock().unwrap();
+        match self.format {
+            LogFormat::Json => info!(
+                target: ACCESS_LOG_TARGET,
+                "{}",
+                json!({
+                    "method": self.method,
+                    "peer": self.peer.map(|peer| peer.to_string()),
+                    "status": status,
+                    "duration_ms": duration_ms,
+                    "request_size": reported.request_size,
+                    "upstream_took_ms": reported.upstream_took,
+                    "request_id": self.request_id,
+                })
+            ),
+            LogFormat::Text => {
+                let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+                info!(
+                    target: ACCESS_LOG_TARGET,
+                    "{} {} {} {:.1}ms request_size={} upstream_took={} request_id={}",
+                    optional(self.peer.map(|peer| peer.to_string())),
+                    self.method,
+                    status,
+                    duration_ms,
+                    optional(reported.request_size.map(|size| size.to_string())),
+                    optional(reported.upstream_took.map(|took| format!("{}ms", took))),
+                    self.request_id
+                )
+            }
+        }
+    }
+}
+
+/// Formats log records as JSON objects, one per line, with their timestamp, level, target and
+/// message. The fields of access log lines are set at the top level rather than in a message.
+pub fn format_json(buf: &mut env_logger::fmt::Formatter, record: &log::Record) -> io::Result<()> {
+    let message = record.args().to_string();
+    let mut line = match record.target() {
+        ACCESS_LOG_TARGET => serde_json::from_str(&message).unwrap_or_default(),
+        _ => Map::new(),
+    };
+    if line.is_empty() {
+        line.insert("message".to_string(), Value::String(message));
+    }
+    line.insert("ts".to_string(), buf.timestamp_millis().to_string().into());
+    line.insert("level".to_string(), record.level().as_str().into());
+    line.insert("target".to_string(), record.target().into());
+    writeln!(buf, "{}", Value::Object(line))
+}
+
+/// A tower layer logging a line per RPC under the `mighty_grpc::access` target, when
+/// `logging.access_log` is enabled. Calls without an `x-request-id` are given one, so their
+/// access log line and `RequestContext` share it.
+#[derive(Debug, Clone)]
+pub struct AccessLogLayer {
+    format: Option<LogFormat>,
+}
+
+impl AccessLogLayer {
+    pub fn new(config: &LoggingConfig) -> Self {
+        Self {
+            format: config.access_log.then_some(config.format),
+        }
+    }
+}
+
+impl<S> Layer<S> for AccessLogLayer {
+    type Service = AccessLogService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AccessLogService {
+            inner,
+            format: self.format,
+        }
+    }
+}
+
+/// The service produced by `AccessLogLayer`.
+#[derive(Debug, Clone)]
+pub struct AccessLogService<S> {
+    inner: S,
+    format: Option<LogFormat>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for AccessLogService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<AccessLogBody<ResBody>>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<ReqBody>) -> Self::Future {
+        let entry = self.format.map(|format| {
+            let request_id = request
+                .headers()
+                .get(REQUEST_ID_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+                .unwrap_or_else(|| {
+                    let request_id = generate_request_id();
+                    if let Ok(value) = HeaderValue::try_from(&request_id) {
+                        request.headers_mut().insert(REQUEST_ID_HEADER, value);
+                    }
+                    request_id
+                });
+            let record = AccessRecord::default();
+            request.extensions_mut().insert(record.clone());
+            AccessEntry {
+                format,
+                method: request.uri().path().to_string(),
+                peer: peer_of(&request),
+                request_id,
+                started: Instant::now(),
+                status: None,
+                record,
+            }
+        });
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = response.await?;
+            let entry = entry.map(|mut entry| {
+                // Set in the headers of trailers-only responses, i.e. errors
+                entry.status = status_of(response.headers());
+                entry
+            });
+            Ok(response.map(|inner| AccessLogBody { inner, entry }))
+        })
+    }
+}
+
+/// The response body of an access logged call, taking its status from the trailers.
+pub struct AccessLogBody<B> {
+    inner: B,
+    entry: Option<AccessEntry>,
+}
+
+impl<B: Body + Unpin> Body for AccessLogBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let trailers = Pin::new(&mut self.inner).poll_trailers(cx);
+        if let (Poll::Ready(Ok(Some(trailers))), Some(entry)) = (&trailers, &mut self.entry) {
+            entry.status = entry.status.or(status_of(trailers));
+        }
+        trailers
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::future::{ready, Ready};
+
+    use super::*;
+
+    /// Echoes the request id back as the response body, when the call is access logged.
+    struct EchoRequestId;
+
+    impl Service<Request<()>> for EchoRequestId {
+        type Response = Response<String>;
+        type Error = Infallible;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<()>) -> Self::Future {
+            let logged = request.extensions().get::<AccessRecord>().is_some();
+            let request_id = request.headers().get(REQUEST_ID_HEADER);
+            let body = match request_id {
+                Some(request_id) if logged => request_id.to_str().unwrap().to_string(),
+                _ => String::new(),
+            };
+            ready(Ok(Response::new(body)))
+        }
+    }
+
+    fn logging(access_log: bool) -> LoggingConfig {
+        LoggingConfig {
+            format: LogFormat::Json,
+            access_log,
+            ..LoggingConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_access_logged_calls_share_their_request_id() {
+        let mut service = AccessLogLayer::new(&logging(true)).layer(EchoRequestId);
+        let response = service.call(Request::new(())).await.unwrap();
+        assert_eq!(response.body().inner.len(), 32);
+
+        let request = Request::builder()
+            .header(REQUEST_ID_HEADER, "req-1")
+            .body(())
+            .unwrap();
+        let response = service.call(request).await.unwrap();
+        assert_eq!(response.body().inner, "req-1");
+
+        let mut service = AccessLogLayer::new(&logging(false)).layer(EchoRequestId);
+        let response = service.call(Request::new(())).await.unwrap();
+        assert!(response.body().inner.is_empty());
+    }
+}
@@ -0,0 +1,576 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use log::warn;
+use tonic::{Request, Response, Status};
+
+use crate::config::{AppSettings, BackendConfig, BackendKind};
+use crate::proto::mighty_proto::mighty_admin_server::{MightyAdmin, MightyAdminServer};
+use crate::proto::mighty_proto::{
+    CallerQuotaUsage, CircuitBreakerStats, Empty, FlushCacheResponse, MetricsResponse,
+    QuotaUsageRequest, QuotaUsageResponse, ReadinessResponse, ReloadConfigResponse,
+    SchemaCompatibilityRequest, SchemaCompatibilityResponse, SetChaosRequest, SetChaosResponse,
+    StatsResponse, SwitchBackendRequest, SwitchBackendResponse, UpgradeModelRequest,
+    UpgradeModelResponse,
+};
+use crate::proto::schema::Schema;
+use crate::proto::{FILE_DESCRIPTOR_SET, GOLDEN_SCHEMA};
+use crate::services::clients::circuit_breaker::CircuitBreakers;
+use crate::services::clients::quota::Quotas;
+use crate::services::clients::{BackendSwitch, CacheFlush, FaultInjection, ModelUpgrade};
+use crate::services::context::RequestContext;
+use crate::services::memory::record_memory_metrics;
+use crate::services::metrics::Metrics;
+use crate::services::readiness::Readiness;
+use crate::services::reload::ConfigReloader;
+
+/// The `MightyAdminService` struct implements the administrative gRPC service used to operate
+/// the gateway, as opposed to the inference services proxied by `MightyInferenceServerProxy`.
+///
+/// The RPCs changing the state of the gateway are reserved to the caller identities of
+/// `[admin]`, other callers being denied them with `PERMISSION_DENIED`.
+#[derive(Default)]
+pub struct MightyAdminService {
+    settings: Arc<AppSettings>,
+    readiness: Arc<Readiness>,
+    circuit_breakers: Arc<CircuitBreakers>,
+    model_upgrade: Option<Arc<dyn ModelUpgrade>>,
+    backend_switch: Option<Arc<dyn BackendSwitch>>,
+    fault_injection: Option<Arc<dyn FaultInjection>>,
+    cache_flush: Option<Arc<dyn CacheFlush>>,
+    quotas: Arc<Quotas>,
+    config_reloader: Option<Arc<ConfigReloader>>,
+}
+
+impl MightyAdminService {
+    pub fn new(readiness: Arc<Readiness>) -> Self {
+        Self {
+            settings: Arc::default(),
+            readiness,
+            circuit_breakers: Arc::default(),
+            model_upgrade: None,
+            backend_switch: None,
+            fault_injection: None,
+            cache_flush: None,
+            quotas: Arc::default(),
+            config_reloader: None,
+        }
+    }
+
+    /// Sets the settings the admin RPCs are authorized and checked against, superseded by the
+    /// latest ones of the configuration reloader when one is set.
+    pub fn with_settings(mut self, settings: Arc<AppSettings>) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Sets the circuit breakers whose state is reported by the `Stats` RPC.
+    pub fn with_circuit_breakers(mut self, circuit_breakers: Arc<CircuitBreakers>) -> Self {
+        self.circuit_breakers = circuit_breakers;
+        self
+    }
+
+    /// Sets the backend upgraded by the `UpgradeModel` RPC, which is unimplemented otherwise.
+    pub fn with_model_upgrade(mut self, model_upgrade: Option<Arc<dyn ModelUpgrade>>) -> Self {
+        self.model_upgrade = model_upgrade;
+        self
+    }
+
+    /// Sets the client whose backend is replaced by the `SwitchBackend` RPC, which is
+    /// unimplemented otherwise.
+    pub fn with_backend_switch(mut self, backend_switch: Option<Arc<dyn BackendSwitch>>) -> Self {
+        self.backend_switch = backend_switch;
+        self
+    }
+
+    /// Sets the client toggled by the `SetChaos` RPC, which is unimplemented otherwise.
+    pub fn with_fault_injection(
+        mut self,
+        fault_injection: Option<Arc<dyn FaultInjection>>,
+    ) -> Self {
+        self.fault_injection = fault_injection;
+        self
+    }
+
+    /// Sets the response cache flushed by the `FlushCache` RPC, which is unimplemented otherwise.
+    pub fn with_cache_flush(mut self, cache_flush: Option<Arc<dyn CacheFlush>>) -> Self {
+        self.cache_flush = cache_flush;
+        self
+    }
+
+    /// Sets the quotas whose usage is reported by the `QuotaUsage` RPC.
+    pub fn with_quotas(mut self, quotas: Arc<Quotas>) -> Self {
+        self.quotas = quotas;
+        self
+    }
+
+    /// Sets the reloader of the configuration run by the `ReloadConfig` RPC, which is
+    /// unimplemented otherwise.
+    pub fn with_config_reloader(mut self, config_reloader: Option<Arc<ConfigReloader>>) -> Self {
+        self.config_reloader = config_reloader;
+        self
+    }
+
+    /// Returns the latest settings.
+    fn settings(&self) -> Arc<AppSettings> {
+        match &self.config_reloader {
+            Some(config_reloader) => config_reloader.settings(),
+            None => self.settings.clone(),
+        }
+    }
+
+    /// Fails with `PERMISSION_DENIED` unless the caller of `request` is an admin.
+    fn authorize<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let identity = caller_identity(request);
+        if self.settings().admin.is_admin(identity.as_deref()) {
+            return Ok(());
+        }
+        Err(Status::permission_denied(
+            "Operating the gateway requires an identity listed in admin.identities",
+        ))
+    }
+
+    /// Returns the canonical path of `model_dir`, failing with `INVALID_ARGUMENT` unless it is
+    /// in `binary.model_root`.
+    fn check_model_dir(&self, model_dir: &Path) -> Result<PathBuf, Status> {
+        let settings = self.settings();
+        let root = settings.binary.model_root.as_ref().ok_or_else(|| {
+            Status::invalid_argument("model_dir requires binary.model_root to be set")
+        })?;
+        let outside = || {
+            Status::invalid_argument(format!(
+                "model_dir must be a directory in {}",
+                root.display()
+            ))
+        };
+        let root = root.canonicalize().map_err(|_| outside())?;
+        let model_dir = model_dir.canonicalize().map_err(|_| outside())?;
+        if !model_dir.starts_with(&root) || !model_dir.is_dir() {
+            return Err(outside());
+        }
+        Ok(model_dir)
+    }
+
+    /// Evicts the responses cached from the previous backend or model, if any.
+    async fn flush_cached_responses(&self) {
+        if let Some(cache_flush) = &self.cache_flush {
+            if let Err(status) = cache_flush.flush().await {
+                warn!(
+                    "The responses cached from the previous backend can't be flushed: {}",
+                    status.message()
+                );
+            }
+        }
+    }
+}
+
+/// Returns the identity of the caller of `request`, as established by the authentication
+/// interceptors, or by its client certificate over mutual TLS.
+fn caller_identity<T>(request: &Request<T>) -> Option<String> {
+    let identity = RequestContext::get(request).and_then(|context| context.identity.clone());
+    #[cfg(feature = "tls")]
+    let identity = identity.or_else(|| crate::services::tls::peer_identity(request));
+    identity
+}
+
+#[tonic::async_trait]
+impl MightyAdmin for MightyAdminService {
+    async fn schema_compatibility(
+        &self,
+        request: Request<SchemaCompatibilityRequest>,
+    ) -> Result<Response<SchemaCompatibilityResponse>, Status> {
+        let descriptor_set = request.into_inner().descriptor_set;
+        let baseline = if descriptor_set.is_empty() {
+            Schema::parse(GOLDEN_SCHEMA)
+        } else {
+            Schema::decode(&descriptor_set)
+                .map_err(|e| Status::invalid_argument(format!("Invalid descriptor set: {}", e)))?
+        };
+        let current = Schema::decode(FILE_DESCRIPTOR_SET)
+            .map_err(|e| Status::internal(format!("Invalid served descriptor set: {}", e)))?;
+
+        let violations = baseline.breaking_changes(&current);
+        Ok(Response::new(SchemaCompatibilityResponse {
+            compatible: violations.is_empty(),
+            violations,
+        }))
+    }
+
+    async fn metrics(&self, _request: Request<Empty>) -> Result<Response<MetricsResponse>, Status> {
+        record_memory_metrics(Metrics::global());
+        Ok(Response::new(MetricsResponse {
+            text: Metrics::global().render(),
+        }))
+    }
+
+    async fn readiness(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<ReadinessResponse>, Status> {
+        let pending = self.readiness.pending();
+        Ok(Response::new(ReadinessResponse {
+            ready: pending.is_empty(),
+            pending,
+        }))
+    }
+
+    async fn stats(&self, _request: Request<Empty>) -> Result<Response<StatsResponse>, Status> {
+        let circuit_breakers = self
+            .circuit_breakers
+            .snapshots()
+            .into_iter()
+            .map(|snapshot| CircuitBreakerStats {
+                task: snapshot.task.as_str().to_string(),
+                state: snapshot.state.as_str().to_string(),
+                consecutive_failures: snapshot.consecutive_failures,
+                rejected: snapshot.rejected,
+            })
+            .collect();
+        Ok(Response::new(StatsResponse { circuit_breakers }))
+    }
+
+    async fn upgrade_model(
+        &self,
+        request: Request<UpgradeModelRequest>,
+    ) -> Result<Response<UpgradeModelResponse>, Status> {
+        self.authorize(&request)?;
+        let model_upgrade = self.model_upgrade.as_ref().ok_or_else(|| {
+            Status::unimplemented("Model upgrades are only supported in binary mode")
+        })?;
+        let model_dir = Some(request.into_inner().model_dir)
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| self.check_model_dir(Path::new(&dir)))
+            .transpose()?;
+        let generation = model_upgrade.upgrade(model_dir).await?;
+        self.flush_cached_responses().await;
+        Ok(Response::new(UpgradeModelResponse { generation }))
+    }
+
+    async fn switch_backend(
+        &self,
+        request: Request<SwitchBackendRequest>,
+    ) -> Result<Response<SwitchBackendResponse>, Status> {
+        self.authorize(&request)?;
+        let backend_switch = self
+            .backend_switch
+            .as_ref()
+            .ok_or_else(|| Status::unimplemented("Backend switches aren't enabled"))?;
+        let SwitchBackendRequest { kind, base_url } = request.into_inner();
+        let kind = BackendKind::parse(&kind)
+            .ok_or_else(|| Status::invalid_argument(format!("Unknown backend kind `{}`", kind)))?;
+        let base_url = Some(base_url).filter(|url| !url.is_empty());
+        if base_url.is_some() && kind != BackendKind::Rest {
+            return Err(Status::invalid_argument(
+                "base_url only applies to rest backends",
+            ));
+        }
+        if let Some(base_url) = &base_url {
+            if !self
+                .settings()
+                .configured_base_urls()
+                .contains(&base_url.as_str())
+            {
+                return Err(Status::invalid_argument(format!(
+                    "{} isn't the base URL of a configured backend",
+                    base_url
+                )));
+            }
+        }
+        let generation = backend_switch
+            .switch(&BackendConfig { kind, base_url })
+            .await?;
+        self.flush_cached_responses().await;
+        Ok(Response::new(SwitchBackendResponse { generation }))
+    }
+
+    async fn set_chaos(
+        &self,
+        request: Request<SetChaosRequest>,
+    ) -> Result<Response<SetChaosResponse>, Status> {
+        self.authorize(&request)?;
+        let fault_injection = self
+            .fault_injection
+            .as_ref()
+            .ok_or_else(|| Status::unimplemented("Fault injection requires the `chaos` feature"))?;
+        let enabled = request.into_inner().enabled;
+        fault_injection.set_enabled(enabled);
+        Ok(Response::new(SetChaosResponse { enabled }))
+    }
+
+    async fn flush_cache(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<FlushCacheResponse>, Status> {
+        self.authorize(&request)?;
+        let cache_flush = self
+            .cache_flush
+            .as_ref()
+            .ok_or_else(|| Status::unimplemented("The response cache isn't enabled"))?;
+        let flushed = cache_flush.flush().await?;
+        Ok(Response::new(FlushCacheResponse { flushed }))
+    }
+
+    async fn quota_usage(
+        &self,
+        request: Request<QuotaUsageRequest>,
+    ) -> Result<Response<QuotaUsageResponse>, Status> {
+        // Callers other than admins only see their own usage
+        let caller = caller_identity(&request);
+        let requested = Some(request.into_inner().identity).filter(|identity| !identity.is_empty());
+        let identity = if self.settings().admin.is_admin(caller.as_deref()) {
+            requested
+        } else {
+            let caller = caller.ok_or_else(|| {
+                Status::permission_denied("Quota usage is only reported to authenticated callers")
+            })?;
+            if requested
+                .as_ref()
+                .is_some_and(|requested| *requested != caller)
+            {
+                return Err(Status::permission_denied(
+                    "The quota usage of other callers is only reported to admins",
+                ));
+            }
+            Some(caller)
+        };
+        if !self.quotas.is_enabled() {
+            return Ok(Response::new(QuotaUsageResponse::default()));
+        }
+        let identity = identity.as_deref();
+        let callers = self
+            .quotas
+            .usage(identity)
+            .into_iter()
+            .map(|usage| CallerQuotaUsage {
+                identity: usage.identity,
+                requests: usage.requests,
+                tokens: usage.tokens,
+                daily_requests: usage.daily_requests.unwrap_or(0),
+                daily_tokens: usage.daily_tokens.unwrap_or(0),
+            })
+            .collect();
+        Ok(Response::new(QuotaUsageResponse { callers }))
+    }
+
+    async fn reload_config(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<ReloadConfigResponse>, Status> {
+        self.authorize(&request)?;
+        let config_reloader = self
+            .config_reloader
+            .as_ref()
+            .ok_or_else(|| Status::unimplemented("The configuration can't be reloaded"))?;
+        let applied = config_reloader.reload().await?;
+        Ok(Response::new(ReloadConfigResponse {
+            applied: applied.into_iter().map(str::to_string).collect(),
+        }))
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn create_mighty_admin_server(
+    settings: Arc<AppSettings>,
+    readiness: Arc<Readiness>,
+    circuit_breakers: Arc<CircuitBreakers>,
+    model_upgrade: Option<Arc<dyn ModelUpgrade>>,
+    backend_switch: Option<Arc<dyn BackendSwitch>>,
+    fault_injection: Option<Arc<dyn FaultInjection>>,
+    cache_flush: Option<Arc<dyn CacheFlush>>,
+    quotas: Arc<Quotas>,
+    config_reloader: Option<Arc<ConfigReloader>>,
+) -> MightyAdminServer<MightyAdminService> {
+    MightyAdminServer::new(
+        MightyAdminService::new(readiness)
+            .with_settings(settings)
+            .with_circuit_breakers(circuit_breakers)
+            .with_model_upgrade(model_upgrade)
+            .with_backend_switch(backend_switch)
+            .with_fault_injection(fault_injection)
+            .with_cache_flush(cache_flush)
+            .with_quotas(quotas)
+            .with_config_reloader(config_reloader),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use async_trait::async_trait;
+    use config::{Config, File, FileFormat};
+    use tonic::Code;
+
+    use crate::services::auth::update_context;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Backends {
+        switches: AtomicU64,
+        flushes: AtomicU64,
+    }
+
+    #[async_trait]
+    impl BackendSwitch for Backends {
+        async fn switch(&self, _backend: &BackendConfig) -> Result<u64, Status> {
+            Ok(self.switches.fetch_add(1, Ordering::Relaxed) + 1)
+        }
+    }
+
+    #[async_trait]
+    impl CacheFlush for Backends {
+        async fn flush(&self) -> Result<u64, Status> {
+            self.flushes.fetch_add(1, Ordering::Relaxed);
+            Ok(0)
+        }
+    }
+
+    #[async_trait]
+    impl ModelUpgrade for Backends {
+        async fn upgrade(&self, _model_dir: Option<PathBuf>) -> Result<u64, Status> {
+            Ok(self.switches.fetch_add(1, Ordering::Relaxed) + 1)
+        }
+    }
+
+    /// Returns a directory of model versions, `v1` and `v2`, next to an `other` directory.
+    fn model_root() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mighty-admin-{}", std::process::id()));
+        for version in ["models/v1", "models/v2", "other"] {
+            std::fs::create_dir_all(dir.join(version)).unwrap();
+        }
+        dir.join("models")
+    }
+
+    fn service(backends: &Arc<Backends>) -> MightyAdminService {
+        let settings: AppSettings = Config::builder()
+            .add_source(File::from_str(
+                &format!(
+                    r#"
+                    mighty_server = {{ base_url = ["http://mighty-a:5050", "http://mighty-b:5050"] }}
+                    admin = {{ identities = ["ops"] }}
+                    binary = {{ model_root = "{}" }}
+                    "#,
+                    model_root().display()
+                ),
+                FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        MightyAdminService::new(Arc::default())
+            .with_settings(Arc::new(settings))
+            .with_backend_switch(Some(backends.clone() as Arc<dyn BackendSwitch>))
+            .with_cache_flush(Some(backends.clone() as Arc<dyn CacheFlush>))
+            .with_model_upgrade(Some(backends.clone() as Arc<dyn ModelUpgrade>))
+    }
+
+    fn called_by<T>(message: T, identity: Option<&str>) -> Request<T> {
+        let mut request = Request::new(message);
+        if let Some(identity) = identity {
+            update_context(&mut request, |context| {
+                context.identity = Some(identity.to_string())
+            });
+        }
+        request
+    }
+
+    fn switch_to(base_url: &str, identity: Option<&str>) -> Request<SwitchBackendRequest> {
+        let request = SwitchBackendRequest {
+            kind: "rest".to_string(),
+            base_url: base_url.to_string(),
+        };
+        called_by(request, identity)
+    }
+
+    #[tokio::test]
+    async fn test_backend_switches_are_reserved_to_admins() {
+        let backends = Arc::new(Backends::default());
+        let service = service(&backends);
+
+        for identity in [None, Some("search-indexer")] {
+            let request = switch_to("http://mighty-b:5050", identity);
+            let status = service.switch_backend(request).await.unwrap_err();
+            assert_eq!(status.code(), Code::PermissionDenied);
+        }
+        assert_eq!(backends.switches.load(Ordering::Relaxed), 0);
+
+        let request = switch_to("http://mighty-b:5050", Some("ops"));
+        let response = service.switch_backend(request).await.unwrap();
+        assert_eq!(response.into_inner().generation, 1);
+        assert_eq!(backends.flushes.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_backend_switches_are_limited_to_configured_urls() {
+        let backends = Arc::new(Backends::default());
+        let service = service(&backends);
+
+        for base_url in ["http://attacker:5050", "grpc://[::1"] {
+            let request = switch_to(base_url, Some("ops"));
+            let status = service.switch_backend(request).await.unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument);
+        }
+        assert_eq!(backends.switches.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_model_upgrades_are_limited_to_the_model_root() {
+        let backends = Arc::new(Backends::default());
+        let service = service(&backends);
+        let upgrade_to = |model_dir: PathBuf, identity| {
+            let model_dir = model_dir.display().to_string();
+            called_by(UpgradeModelRequest { model_dir }, identity)
+        };
+        let root = model_root();
+
+        let status = service
+            .upgrade_model(upgrade_to(root.join("v2"), None))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        for model_dir in [root.join("../other"), root.join("v3"), PathBuf::from("/")] {
+            let status = service
+                .upgrade_model(upgrade_to(model_dir, Some("ops")))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument);
+        }
+        assert_eq!(backends.switches.load(Ordering::Relaxed), 0);
+
+        service
+            .upgrade_model(upgrade_to(root.join("v2"), Some("ops")))
+            .await
+            .unwrap();
+        assert_eq!(backends.switches.load(Ordering::Relaxed), 1);
+        assert_eq!(backends.flushes.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_quota_usage_of_other_callers_is_reserved_to_admins() {
+        let service = service(&Arc::default());
+        let usage_of = |identity: &str, caller| {
+            let identity = identity.to_string();
+            called_by(QuotaUsageRequest { identity }, caller)
+        };
+
+        for (identity, caller) in [("", None), ("ops", None), ("ops", Some("search-indexer"))] {
+            let status = service
+                .quota_usage(usage_of(identity, caller))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), Code::PermissionDenied);
+        }
+        for (identity, caller) in [
+            ("", Some("search-indexer")),
+            ("search-indexer", Some("search-indexer")),
+            ("search-indexer", Some("ops")),
+        ] {
+            assert!(service
+                .quota_usage(usage_of(identity, caller))
+                .await
+                .is_ok());
+        }
+    }
+}
@@ -0,0 +1,266 @@
+//! Config-driven aliasing of legacy RPC paths onto current endpoints.
+//!
+//! Each configured alias maps a legacy path (e.g.
+//! `/mighty_inference_server.MightyInference/GetEmbeddings`) onto a current one. Aliased requests
+//! are rewritten before routing, their responses carry deprecation metadata (`deprecation: true`
+//! and an `x-deprecation-notice`), and every use is counted in the `mighty_alias_requests_total`
+//! metric so migrations can be tracked.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::future::BoxFuture;
+use log::warn;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::codegen::http::{HeaderValue, Request, Response, Uri};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::config::AliasConfig;
+use crate::services::metrics::Metrics;
+
+/// Response header flagging the use of a deprecated path.
+pub const DEPRECATION_HEADER: &str = "deprecation";
+/// Response header carrying the human-readable deprecation notice.
+pub const DEPRECATION_NOTICE_HEADER: &str = "x-deprecation-notice";
+
+/// Counter incremented for every request made through an alias.
+const ALIAS_REQUESTS_METRIC: &str = "mighty_alias_requests_total";
+
+#[derive(Debug)]
+struct Alias {
+    target: PathAndQuery,
+    notice: HeaderValue,
+}
+
+/// A tower layer rewriting aliased request paths and flagging their responses as deprecated.
+#[derive(Debug, Clone, Default)]
+pub struct AliasLayer {
+    aliases: Arc<HashMap<String, Alias>>,
+}
+
+impl AliasLayer {
+    /// Creates the layer from the configured aliases, skipping (and logging) invalid entries.
+    pub fn new(config: &[AliasConfig]) -> Self {
+        let aliases = config
+            .iter()
+            .filter_map(|alias| {
+                let target = match PathAndQuery::try_from(alias.to.as_str()) {
+                    Ok(target) => target,
+                    Err(e) => {
+                        warn!("Ignoring alias {} -> {}: {}", alias.from, alias.to, e);
+                        return None;
+                    }
+                };
+                let notice = alias
+                    .message
+                    .clone()
+                    .unwrap_or_else(|| format!("{} is deprecated, use {}", alias.from, alias.to));
+                let notice = HeaderValue::try_from(notice)
+                    .unwrap_or_else(|_| HeaderValue::from_static("deprecated"));
+                Some((alias.from.clone(), Alias { target, notice }))
+            })
+            .collect();
+        Self {
+            aliases: Arc::new(aliases),
+        }
+    }
+}
+
+impl<S> Layer<S> for AliasLayer {
+    type Service = AliasService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AliasService {
+            inner,
+            aliases: self.aliases.clone(),
+        }
+    }
+}
+
+/// The service produced by `AliasLayer`.
+#[derive(Debug, Clone)]
+pub struct AliasService<S> {
+    inner: S,
+    aliases: Arc<HashMap<String, Alias>>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for AliasService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<ReqBody>) -> Self::Future {
+        let Some(alias) = self.aliases.get(request.uri().path()) else {
+            return Box::pin(self.inner.call(request));
+        };
+
+        Metrics::global()
+            .counter(
+                ALIAS_REQUESTS_METRIC,
+                &[
+                    ("alias", request.uri().path()),
+                    ("target", alias.target.path()),
+                ],
+            )
+            .increment(1);
+
+        // The query of the aliased request, if any, is kept
+        let target = match request.uri().query() {
+            Some(query) => format!("{}?{}", alias.target.path(), query)
+                .parse()
+                .unwrap_or_else(|_| alias.target.clone()),
+            None => alias.target.clone(),
+        };
+        let mut parts = request.uri().clone().into_parts();
+        parts.path_and_query = Some(target);
+        if let Ok(uri) = Uri::from_parts(parts) {
+            *request.uri_mut() = uri;
+        }
+
+        let notice = alias.notice.clone();
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let mut response = response.await?;
+            let headers = response.headers_mut();
+            headers.insert(DEPRECATION_HEADER, HeaderValue::from_static("true"));
+            headers.insert(DEPRECATION_NOTICE_HEADER, notice);
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::future::{ready, Ready};
+
+    use super::*;
+
+    /// Echoes the request path, and query if any, back as the response body.
+    struct EchoPath;
+
+    impl Service<Request<()>> for EchoPath {
+        type Response = Response<String>;
+        type Error = Infallible;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<()>) -> Self::Future {
+            let path = request.uri().path_and_query().map(PathAndQuery::as_str);
+            ready(Ok(Response::new(path.unwrap_or_default().to_string())))
+        }
+    }
+
+    fn request(path: &str) -> Request<()> {
+        Request::builder()
+            .uri(format!("http://localhost{}", path))
+            .body(())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_alias_rewrites_path_and_flags_deprecation() {
+        let layer = AliasLayer::new(&[AliasConfig {
+            from: "/pkg.Service/GetEmbeddings".to_string(),
+            to: "/pkg.Service/Embeddings".to_string(),
+            message: None,
+        }]);
+        let mut service = layer.layer(EchoPath);
+
+        let response = service
+            .call(request("/pkg.Service/GetEmbeddings"))
+            .await
+            .unwrap();
+        assert_eq!(response.body(), "/pkg.Service/Embeddings");
+        assert_eq!(response.headers()[DEPRECATION_HEADER], "true");
+        assert_eq!(
+            response.headers()[DEPRECATION_NOTICE_HEADER],
+            "/pkg.Service/GetEmbeddings is deprecated, use /pkg.Service/Embeddings"
+        );
+
+        let response = service
+            .call(request("/pkg.Service/Embeddings"))
+            .await
+            .unwrap();
+        assert_eq!(response.body(), "/pkg.Service/Embeddings");
+        assert!(response.headers().get(DEPRECATION_HEADER).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_alias_target_is_skipped() {
+        let layer = AliasLayer::new(&[
+            AliasConfig {
+                from: "/pkg.Service/GetEmbeddings".to_string(),
+                to: "not a path".to_string(),
+                message: None,
+            },
+            AliasConfig {
+                from: "/pkg.Service/GetMetadata".to_string(),
+                to: "/pkg.Service/Metadata".to_string(),
+                message: None,
+            },
+        ]);
+        let mut service = layer.layer(EchoPath);
+
+        let response = service
+            .call(request("/pkg.Service/GetEmbeddings"))
+            .await
+            .unwrap();
+        assert_eq!(response.body(), "/pkg.Service/GetEmbeddings");
+        assert!(response.headers().get(DEPRECATION_HEADER).is_none());
+
+        let response = service
+            .call(request("/pkg.Service/GetMetadata"))
+            .await
+            .unwrap();
+        assert_eq!(response.body(), "/pkg.Service/Metadata");
+    }
+
+    #[tokio::test]
+    async fn test_alias_message_is_the_deprecation_notice() {
+        let layer = AliasLayer::new(&[AliasConfig {
+            from: "/pkg.Service/GetEmbeddings".to_string(),
+            to: "/pkg.Service/Embeddings".to_string(),
+            message: Some("GetEmbeddings is going away, use Embeddings".to_string()),
+        }]);
+        let mut service = layer.layer(EchoPath);
+
+        let response = service
+            .call(request("/pkg.Service/GetEmbeddings"))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[DEPRECATION_NOTICE_HEADER],
+            "GetEmbeddings is going away, use Embeddings"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_alias_keeps_the_query() {
+        let layer = AliasLayer::new(&[AliasConfig {
+            from: "/pkg.Service/GetEmbeddings".to_string(),
+            to: "/pkg.Service/Embeddings".to_string(),
+            message: None,
+        }]);
+        let mut service = layer.layer(EchoPath);
+
+        let response = service
+            .call(request("/pkg.Service/GetEmbeddings?text=hello"))
+            .await
+            .unwrap();
+        assert_eq!(response.body(), "/pkg.Service/Embeddings?text=hello");
+    }
+}
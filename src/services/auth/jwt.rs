@@ -0,0 +1,376 @@
+//! Validation of JWT bearer tokens, verified by the keys of a JSON Web Key Set.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tokio::sync::Notify;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use crate::config::JwtConfig;
+
+use super::{reject, update_context};
+
+/// Metadata key carrying the bearer token.
+pub const AUTHORIZATION_HEADER: &str = "authorization";
+
+/// The minimum interval between two fetches of the key set, bounding the fetches triggered by
+/// tokens signed by unknown keys.
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The timeout of the requests fetching the key set.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The accepted signature algorithms. Symmetric ones are excluded, key sets holding public keys.
+const ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// The claims of a validated token, attached to its call as a request extension, so policies
+/// can be based on claims beyond the caller identity, e.g. scopes or groups.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JwtClaims(pub Map<String, Value>);
+
+impl JwtClaims {
+    /// Returns the claims attached to `request`, if any.
+    pub fn get<T>(request: &Request<T>) -> Option<&JwtClaims> {
+        request.extensions().get::<JwtClaims>()
+    }
+}
+
+async fn get_json<T: DeserializeOwned>(http: &reqwest::Client, url: &str) -> Result<T, String> {
+    let response = http
+        .get(url)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|e| format!("Failed to fetch {}: {}", url, e))?;
+    response
+        .json()
+        .await
+        .map_err(|e| format!("Failed to parse {}: {}", url, e))
+}
+
+/// Validates tokens against the configured claims and the keys last fetched.
+struct JwtValidator {
+    config: JwtConfig,
+    keys: RwLock<HashMap<String, DecodingKey>>,
+    refresh: Notify,
+}
+
+impl JwtValidator {
+    fn new(config: &JwtConfig) -> Self {
+        Self {
+            config: config.clone(),
+            keys: RwLock::default(),
+            refresh: Notify::new(),
+        }
+    }
+
+    /// Replaces the verifying keys by those of `jwks` with a key id, returning their number.
+    fn set_keys(&self, jwks: &JwkSet) -> usize {
+        let keys: HashMap<_, _> = jwks
+            .keys
+            .iter()
+            .filter_map(|jwk| {
+                let kid = jwk.common.key_id.clone()?;
+                match DecodingKey::from_jwk(jwk) {
+                    Ok(key) => Some((kid, key)),
+                    Err(e) => {
+                        warn!("Ignoring the JWT signing key {}: {}", kid, e);
+                        None
+                    }
+                }
+            })
+            .collect();
+        let count = keys.len();
+        *self.keys.write().unwrap() = keys;
+        count
+    }
+
+    /// Returns the claims of `token`, or the status its call is rejected with.
+    fn validate(&self, token: &str) -> Result<Map<String, Value>, Status> {
+        let invalid = |e: jsonwebtoken::errors::Error| {
+            reject("invalid_token", format!("Invalid bearer token: {}", e))
+        };
+        let header = jsonwebtoken::decode_header(token).map_err(invalid)?;
+        if !ALGORITHMS.contains(&header.alg) {
+            return Err(reject(
+                "invalid_token",
+                format!("Unsupported bearer token algorithm {:?}", header.alg),
+            ));
+        }
+        let kid = header.kid.unwrap_or_default();
+        let keys = self.keys.read().unwrap();
+        let Some(key) = keys.get(&kid) else {
+            // The issuer may have rotated its keys since they were fetched
+            self.refresh.notify_one();
+            return Err(reject(
+                "unknown_key",
+                format!("The bearer token is signed by the unknown key {:?}", kid),
+            ));
+        };
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = self.config.leeway.as_secs();
+        validation.set_issuer(&[&self.config.issuer]);
+        if self.config.audience.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&self.config.audience);
+        }
+        match jsonwebtoken::decode::<Map<String, Value>>(token, key, &validation) {
+            Ok(data) => Ok(data.claims),
+            Err(e) if matches!(e.kind(), ErrorKind::ExpiredSignature) => Err(reject(
+                "expired_token",
+                "The bearer token has expired".to_string(),
+            )),
+            Err(e) => Err(invalid(e)),
+        }
+    }
+
+    /// Returns the URL of the key set: the configured one, or the one advertised by the
+    /// issuer's OpenID Connect discovery document.
+    async fn jwks_url(&self, http: &reqwest::Client) -> Result<String, String> {
+        #[derive(Deserialize)]
+        struct Discovery {
+            jwks_uri: String,
+        }
+
+        if let Some(url) = &self.config.jwks_url {
+            return Ok(url.clone());
+        }
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.config.issuer.trim_end_matches('/')
+        );
+        let discovery: Discovery = get_json(http, &url).await?;
+        Ok(discovery.jwks_uri)
+    }
+
+    async fn fetch_keys(&self, http: &reqwest::Client) -> Result<usize, String> {
+        let url = self.jwks_url(http).await?;
+        let jwks: JwkSet = get_json(http, &url).await?;
+        Ok(self.set_keys(&jwks))
+    }
+
+    /// Fetches the key set every `jwks_refresh_interval`, or sooner when a token is signed by
+    /// an unknown key, but never more often than every `MIN_REFRESH_INTERVAL`. Failed fetches
+    /// are retried after `MIN_REFRESH_INTERVAL`, the previous keys being kept meanwhile.
+    async fn refresh_keys(self: Arc<Self>) {
+        let http = reqwest::Client::builder()
+            .timeout(FETCH_TIMEOUT)
+            .build()
+            .expect("the HTTP client configuration is valid");
+        loop {
+            let fetched = self.fetch_keys(&http).await;
+            match &fetched {
+                Ok(count) => info!("Fetched {} JWT signing keys", count),
+                Err(e) => warn!("Failed to fetch the JWT signing keys: {}", e),
+            }
+            tokio::time::sleep(MIN_REFRESH_INTERVAL).await;
+            if fetched.is_ok() {
+                let wait = self
+                    .config
+                    .jwks_refresh_interval
+                    .saturating_sub(MIN_REFRESH_INTERVAL);
+                let _ = tokio::time::timeout(wait, self.refresh.notified()).await;
+            }
+        }
+    }
+}
+
+/// The `JwtInterceptor` struct is a tonic interceptor rejecting calls without a valid JWT in
+/// their `authorization: Bearer <token>` metadata with `UNAUTHENTICATED`, when `[jwt]` is
+/// enabled. Tokens must be signed by a key of the issuer's key set, which is fetched in the
+/// background and refreshed to follow key rotations, carry the configured issuer and one of the
+/// configured audiences, and be neither expired nor not yet valid.
+///
+/// The claims of a valid token are attached to its call as `JwtClaims`, and its identity and
+/// tenant claims to its `RequestContext`.
+#[derive(Clone)]
+pub struct JwtInterceptor {
+    validator: Option<Arc<JwtValidator>>,
+}
+
+impl JwtInterceptor {
+    /// Creates the interceptor, starting to fetch the key set when enabled.
+    pub fn new(config: &JwtConfig) -> Self {
+        if !config.enabled {
+            return Self { validator: None };
+        }
+        let validator = Arc::new(JwtValidator::new(config));
+        tokio::spawn(validator.clone().refresh_keys());
+        Self {
+            validator: Some(validator),
+        }
+    }
+}
+
+impl Interceptor for JwtInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let Some(validator) = &self.validator else {
+            return Ok(request);
+        };
+        let token = request
+            .metadata()
+            .get(AUTHORIZATION_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| reject("missing_token", "A bearer token is required".to_string()))?;
+        let claims = validator.validate(token.trim())?;
+
+        let claim = |name: &str| claims.get(name).and_then(Value::as_str).map(str::to_string);
+        let identity = claim(&validator.config.identity_claim);
+        let tenant = validator.config.tenant_claim.as_deref().and_then(claim);
+        update_context(&mut request, |context| {
+            if context.identity.is_none() {
+                context.identity = identity;
+            }
+            if tenant.is_some() {
+                context.tenant = tenant;
+            }
+        });
+        request.extensions_mut().insert(JwtClaims(claims));
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tonic::Code;
+
+    use crate::services::context::RequestContext;
+
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/jwt")
+            .join(name)
+    }
+
+    fn config() -> JwtConfig {
+        JwtConfig {
+            enabled: true,
+            issuer: "https://issuer.example.com".to_string(),
+            audience: vec!["mighty-grpc".to_string()],
+            tenant_claim: Some("tenant".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn jwks() -> JwkSet {
+        serde_json::from_slice(&std::fs::read(fixture("jwks.json")).unwrap()).unwrap()
+    }
+
+    /// Returns a token signed by the fixture key, valid for `ttl` seconds from now.
+    fn token(kid: &str, audience: &str, ttl: i64) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let claims = json!({
+            "iss": "https://issuer.example.com",
+            "aud": audience,
+            "sub": "search-indexer",
+            "tenant": "search",
+            "exp": now + ttl,
+        });
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.to_string());
+        let key = EncodingKey::from_rsa_pem(&std::fs::read(fixture("signing.key")).unwrap());
+        jsonwebtoken::encode(&header, &claims, &key.unwrap()).unwrap()
+    }
+
+    fn rejection(validator: &JwtValidator, token: &str) -> Code {
+        validator.validate(token).unwrap_err().code()
+    }
+
+    #[test]
+    fn test_tokens_are_validated() {
+        let validator = JwtValidator::new(&config());
+        assert_eq!(validator.set_keys(&jwks()), 1);
+
+        let claims = validator.validate(&token("test-key", "mighty-grpc", 60));
+        assert_eq!(claims.unwrap()["sub"], "search-indexer");
+        for token in [
+            token("test-key", "mighty-grpc", -3600),
+            token("test-key", "another-service", 60),
+            token("rotated-key", "mighty-grpc", 60),
+            "not.a.token".to_string(),
+        ] {
+            assert_eq!(rejection(&validator, &token), Code::Unauthenticated);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_claims_are_attached_to_calls() {
+        let validator = JwtValidator::new(&config());
+        validator.set_keys(&jwks());
+        let mut interceptor = JwtInterceptor {
+            validator: Some(Arc::new(validator)),
+        };
+        let mut request = Request::new(());
+        let bearer = format!("Bearer {}", token("test-key", "mighty-grpc", 60));
+        request
+            .metadata_mut()
+            .insert(AUTHORIZATION_HEADER, bearer.parse().unwrap());
+
+        let request = interceptor.call(request).unwrap();
+        let context = RequestContext::get(&request).unwrap();
+        assert_eq!(context.identity.as_deref(), Some("search-indexer"));
+        assert_eq!(context.tenant.as_deref(), Some("search"));
+        assert_eq!(JwtClaims::get(&request).unwrap().0["tenant"], "search");
+
+        let status = interceptor.call(Request::new(())).unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn test_key_sets_are_discovered_from_the_issuer() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let discovery = json!({ "jwks_uri": format!("{}/jwks.json", issuer) }).to_string();
+        let jwks = std::fs::read_to_string(fixture("jwks.json")).unwrap();
+        // Serves the discovery document, then the key set
+        tokio::spawn(async move {
+            for body in [discovery, jwks] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let _ = stream.read(&mut [0; 4096]).await.unwrap();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let validator = JwtValidator::new(&JwtConfig { issuer, ..config() });
+        let count = validator.fetch_keys(&reqwest::Client::new()).await;
+        assert_eq!(count, Ok(1));
+    }
+}
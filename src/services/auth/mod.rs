@@ -0,0 +1,198 @@
+//! Authentication of the calls made to the gRPC server.
+//!
+//! Authenticated calls carry the identity of their caller in their `RequestContext`, which the
+//! inference server completes rather than replaces, so decorators and backends can make
+//! identity-aware decisions.
+
+use std::sync::Arc;
+
+use log::debug;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use crate::config::{ApiKeysConfig, AppSettings};
+use crate::services::context::RequestContext;
+use crate::services::metrics::Metrics;
+use crate::startup::StartupError;
+
+#[cfg(feature = "jwt")]
+pub mod jwt;
+
+/// Metadata key carrying the API key of the caller.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Counter of calls authenticated by API key, by key name.
+const AUTHENTICATED_METRIC: &str = "mighty_api_key_requests_total";
+
+/// Counter of calls rejected for lack of valid credentials, by `reason`: `missing` or `invalid`
+/// API keys, or `missing_token`, `invalid_token`, `expired_token` or `unknown_key` JWTs.
+const REJECTED_METRIC: &str = "mighty_unauthenticated_requests_total";
+
+/// Compares two keys in a time independent of the position of their first difference, so keys
+/// can't be guessed byte by byte from response times.
+fn keys_match(expected: &[u8], actual: &[u8]) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .iter()
+            .zip(actual)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Counts a call rejected for `reason`, returning the status it fails with.
+pub(crate) fn reject(reason: &str, message: String) -> Status {
+    Metrics::global()
+        .counter(REJECTED_METRIC, &[("reason", reason)])
+        .increment(1);
+    Status::unauthenticated(message)
+}
+
+fn invalid_key() -> String {
+    format!("A valid `{}` is required", API_KEY_HEADER)
+}
+
+/// Updates the `RequestContext` attached to `request`, attaching one if needed.
+pub(crate) fn update_context<T>(
+    request: &mut Request<T>,
+    update: impl FnOnce(&mut RequestContext),
+) {
+    let extensions = request.extensions_mut();
+    let mut context = extensions.remove::<RequestContext>().unwrap_or_default();
+    update(&mut context);
+    extensions.insert(context);
+}
+
+/// The `ApiKeyInterceptor` struct is a tonic interceptor rejecting calls without a valid
+/// `x-api-key` metadata entry with `UNAUTHENTICATED`, when `[api_keys]` is enabled. The name
+/// of the key a call was made with becomes its caller identity, is logged at the debug level and
+/// counted in the `mighty_api_key_requests_total` metric, for per-key attribution.
+#[derive(Clone)]
+pub struct ApiKeyInterceptor {
+    enabled: bool,
+    keys: Arc<Vec<(String, Vec<u8>)>>,
+}
+
+impl ApiKeyInterceptor {
+    pub fn new(config: &ApiKeysConfig) -> Self {
+        let keys = config
+            .keys
+            .iter()
+            .map(|key| (key.name.clone(), key.key.expose().as_bytes().to_vec()))
+            .collect();
+        Self {
+            enabled: config.enabled,
+            keys: Arc::new(keys),
+        }
+    }
+}
+
+impl Interceptor for ApiKeyInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if !self.enabled {
+            return Ok(request);
+        }
+        let key = request
+            .metadata()
+            .get(API_KEY_HEADER)
+            .ok_or_else(|| reject("missing", invalid_key()))?;
+        let name = self
+            .keys
+            .iter()
+            .find(|(_, expected)| keys_match(expected, key.as_bytes()))
+            .map(|(name, _)| name.clone())
+            .ok_or_else(|| reject("invalid", invalid_key()))?;
+        Metrics::global()
+            .counter(AUTHENTICATED_METRIC, &[("key", &name)])
+            .increment(1);
+        debug!("Call authenticated with the {} API key", name);
+        // An identity established by an earlier layer is kept
+        update_context(&mut request, |context| {
+            context.identity.get_or_insert(name);
+        });
+        Ok(request)
+    }
+}
+
+/// The `AuthInterceptor` struct is a tonic interceptor chaining the configured authentication
+/// schemes: JWT bearer tokens (with the `jwt` feature), then API keys. Calls must satisfy every
+/// enabled scheme, the identity established by the first one being kept.
+#[derive(Clone)]
+pub struct AuthInterceptor {
+    #[cfg(feature = "jwt")]
+    jwt: jwt::JwtInterceptor,
+    api_keys: ApiKeyInterceptor,
+}
+
+impl AuthInterceptor {
+    /// Creates the interceptor, failing when a scheme is enabled without its feature.
+    pub fn new(settings: &AppSettings) -> Result<Self, StartupError> {
+        #[cfg(not(feature = "jwt"))]
+        if settings.jwt.enabled {
+            return Err(StartupError::FeatureMismatch(
+                "jwt requires `--features jwt`".to_string(),
+            ));
+        }
+        Ok(Self {
+            #[cfg(feature = "jwt")]
+            jwt: jwt::JwtInterceptor::new(&settings.jwt),
+            api_keys: ApiKeyInterceptor::new(&settings.api_keys),
+        })
+    }
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        #[cfg(feature = "jwt")]
+        let request = self.jwt.call(request)?;
+        self.api_keys.call(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use crate::config::secrets::Secret;
+    use crate::config::ApiKeyConfig;
+
+    use super::*;
+
+    fn with_key(key: Option<&str>) -> Request<()> {
+        let mut request = Request::new(());
+        if let Some(key) = key {
+            request
+                .metadata_mut()
+                .insert(API_KEY_HEADER, key.parse().unwrap());
+        }
+        request
+    }
+
+    #[test]
+    fn test_calls_are_authenticated_by_api_key() {
+        let mut interceptor = ApiKeyInterceptor::new(&ApiKeysConfig {
+            enabled: true,
+            keys: vec![ApiKeyConfig {
+                name: "search-indexer".to_string(),
+                key: Secret::new("0123456789abcdef"),
+            }],
+        });
+
+        let request = interceptor
+            .call(with_key(Some("0123456789abcdef")))
+            .unwrap();
+        let context = RequestContext::get(&request).unwrap();
+        assert_eq!(context.identity.as_deref(), Some("search-indexer"));
+
+        for key in [None, Some("0123456789abcdeX"), Some("0123")] {
+            let status = interceptor.call(with_key(key)).unwrap_err();
+            assert_eq!(status.code(), Code::Unauthenticated);
+        }
+    }
+
+    #[test]
+    fn test_calls_pass_through_when_disabled() {
+        let mut interceptor = ApiKeyInterceptor::new(&ApiKeysConfig::default());
+        let request = interceptor.call(with_key(None)).unwrap();
+        assert!(RequestContext::get(&request).is_none());
+    }
+}
@@ -0,0 +1,228 @@
+//! Per-client rate limiting of the calls made to the gRPC server, so a noisy client can't starve
+//! the others.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::debug;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use crate::config::{ClientQuotaConfig, ClientRateLimitConfig};
+use crate::services::clients::rate_limit::TokenBucket;
+use crate::services::context::RequestContext;
+use crate::services::metrics::Metrics;
+
+/// Metadata key of rejected calls carrying the number of seconds to wait before retrying.
+pub const RETRY_AFTER_HEADER: &str = "retry-after";
+
+/// Counter of calls rejected for exceeding the budget of their client, by `client`: its identity
+/// when it has a budget of its own in `[[client_rate_limit.clients]]`, else `authenticated`, or
+/// `anonymous` for clients told apart by IP address, so the series are bounded by the config.
+const LIMITED_METRIC: &str = "mighty_client_rate_limited_requests_total";
+
+/// A client of the gRPC server, identified by the authentication layers or its certificate,
+/// else by its IP address, or by the user ID of its process over a Unix domain socket.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Client {
+    Identity(String),
+    Address(Option<IpAddr>),
+    LocalUser(Option<u32>),
+}
+
+impl Client {
+    fn of(request: &Request<()>) -> Self {
+        let identity = RequestContext::get(request).and_then(|context| context.identity.clone());
+        #[cfg(feature = "tls")]
+        let identity = identity.or_else(|| crate::services::tls::peer_identity(request));
+        #[cfg(unix)]
+        if identity.is_none() && crate::services::unix_socket::is_unix_peer(request) {
+            return Client::LocalUser(crate::services::unix_socket::peer_uid(request));
+        }
+        match identity {
+            Some(identity) => Client::Identity(identity),
+            None => Client::Address(request.remote_addr().map(|addr| addr.ip())),
+        }
+    }
+
+    /// The name of the client in the statuses of its rejected calls.
+    fn name(&self) -> String {
+        match self {
+            Client::Identity(identity) => identity.clone(),
+            Client::LocalUser(Some(uid)) => format!("local user {}", uid),
+            Client::Address(_) | Client::LocalUser(None) => "anonymous".to_string(),
+        }
+    }
+}
+
+/// The token buckets of the clients seen, created on their first call.
+struct ClientBuckets {
+    config: ClientRateLimitConfig,
+    buckets: Mutex<HashMap<Client, TokenBucket>>,
+}
+
+impl ClientBuckets {
+    /// Takes a token from the bucket of `client`, or returns how long until one is available.
+    fn take(&self, client: &Client) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if !buckets.contains_key(client) && buckets.len() >= self.config.max_clients {
+            // Idle clients are forgotten, their buckets being recreated full anyway
+            buckets.retain(|_, bucket| !bucket.is_full());
+        }
+        buckets
+            .entry(client.clone())
+            .or_insert_with(|| self.bucket(client))
+            .try_take()
+    }
+
+    /// The configured budget of `client`, if it has one of its own.
+    fn quota(&self, client: &Client) -> Option<&ClientQuotaConfig> {
+        match client {
+            Client::Identity(identity) => self
+                .config
+                .clients
+                .iter()
+                .find(|quota| &quota.identity == identity),
+            Client::Address(_) | Client::LocalUser(_) => None,
+        }
+    }
+
+    /// The label of `client` in metrics, bounded to the configured identities.
+    fn label<'a>(&'a self, client: &'a Client) -> &'a str {
+        match (client, self.quota(client)) {
+            (Client::Identity(_), Some(quota)) => &quota.identity,
+            (Client::Identity(_), None) => "authenticated",
+            (Client::Address(_) | Client::LocalUser(_), _) => "anonymous",
+        }
+    }
+
+    fn bucket(&self, client: &Client) -> TokenBucket {
+        let quota = self.quota(client);
+        let (requests_per_second, burst) = quota.map_or(
+            (self.config.requests_per_second, self.config.burst),
+            |quota| (quota.requests_per_second, quota.burst),
+        );
+        TokenBucket::new(requests_per_second, burst.max(1) as f64)
+    }
+}
+
+/// The `ClientRateLimitInterceptor` struct is a tonic interceptor bounding the calls of each
+/// client to its `requests_per_second` (with bursts of up to `burst` calls), when
+/// `[client_rate_limit]` is enabled. Calls past the budget of their client are rejected with
+/// `RESOURCE_EXHAUSTED`, a `retry-after` metadata entry telling when to retry, and counted in the
+/// `mighty_client_rate_limited_requests_total` metric.
+///
+/// Clients are told apart by the identity established by the authentication layers it must be
+/// layered after (API key name, token subject), else by client certificate, else by IP address.
+#[derive(Clone)]
+pub struct ClientRateLimitInterceptor {
+    buckets: Option<Arc<ClientBuckets>>,
+}
+
+impl ClientRateLimitInterceptor {
+    pub fn new(config: &ClientRateLimitConfig) -> Self {
+        let buckets = config.enabled.then(|| {
+            Arc::new(ClientBuckets {
+                config: config.clone(),
+                buckets: Mutex::new(HashMap::new()),
+            })
+        });
+        Self { buckets }
+    }
+}
+
+impl Interceptor for ClientRateLimitInterceptor {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let Some(buckets) = &self.buckets else {
+            return Ok(request);
+        };
+        let client = Client::of(&request);
+        if let Err(wait) = buckets.take(&client) {
+            Metrics::global()
+                .counter(LIMITED_METRIC, &[("client", buckets.label(&client))])
+                .increment(1);
+            debug!("Call of {:?} rate limited for {:?}", client, wait);
+            // Whole seconds, as HTTP's Retry-After, rounded up so retries aren't rejected again
+            let retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+            let mut status = Status::resource_exhausted(format!(
+                "The rate limit of {} was exceeded, retry in {}s",
+                client.name(),
+                retry_after
+            ));
+            status
+                .metadata_mut()
+                .insert(RETRY_AFTER_HEADER, retry_after.into());
+            return Err(status);
+        }
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use super::*;
+
+    fn from(identity: &str) -> Request<()> {
+        let mut request = Request::new(());
+        request.extensions_mut().insert(RequestContext {
+            identity: Some(identity.to_string()),
+            ..Default::default()
+        });
+        request
+    }
+
+    #[test]
+    fn test_calls_are_limited_per_client() {
+        let mut interceptor = ClientRateLimitInterceptor::new(&ClientRateLimitConfig {
+            enabled: true,
+            requests_per_second: 0.5,
+            burst: 2,
+            clients: vec![ClientQuotaConfig {
+                identity: "search-indexer".to_string(),
+                requests_per_second: 0.5,
+                burst: 3,
+            }],
+            ..Default::default()
+        });
+
+        for _ in 0..2 {
+            interceptor.call(from("noisy")).unwrap();
+        }
+        let status = interceptor.call(from("noisy")).unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        let retry_after = status.metadata().get(RETRY_AFTER_HEADER).unwrap();
+        assert_eq!(retry_after.to_str().unwrap(), "2");
+
+        for _ in 0..3 {
+            interceptor.call(from("search-indexer")).unwrap();
+        }
+        assert!(interceptor.call(from("search-indexer")).is_err());
+        assert!(interceptor.call(Request::new(())).is_ok());
+    }
+
+    #[test]
+    fn test_metric_labels_are_bounded_to_configured_identities() {
+        let buckets = ClientBuckets {
+            config: ClientRateLimitConfig {
+                clients: vec![ClientQuotaConfig {
+                    identity: "search-indexer".to_string(),
+                    requests_per_second: 1.0,
+                    burst: 1,
+                }],
+                ..Default::default()
+            },
+            buckets: Mutex::new(HashMap::new()),
+        };
+        let labels = [
+            Client::Identity("search-indexer".to_string()),
+            Client::Identity("tenant-4521".to_string()),
+            Client::Address(None),
+        ]
+        .map(|client| buckets.label(&client).to_string());
+        assert_eq!(labels, ["search-indexer", "authenticated", "anonymous"]);
+    }
+}
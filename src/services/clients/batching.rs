@@ -0,0 +1,608 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::future::join_all;
+use log::debug;
+use tokio::sync::{mpsc, oneshot};
+use tonic::{Request, Response, Status};
+
+use crate::config::MicroBatchingConfig;
+use crate::proto::mighty_proto::{
+    EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse, QuestionAnswerRequest,
+    QuestionAnswerResponse, RerankRequest, RerankResponse, SentenceTransformersResponse,
+    SequenceClassificationResponse, TextRequest, TokenClassificationResponse,
+};
+use crate::services::context::RequestContext;
+use crate::services::metrics::Metrics;
+
+use super::MightyClient;
+
+/// Counter of upstream batch calls made by the `BatchingClient`, by task.
+const BATCHES_METRIC: &str = "mighty_micro_batches_total";
+
+/// Counter of the texts sent through upstream batch calls, by task.
+const BATCHED_TEXTS_METRIC: &str = "mighty_micro_batched_texts_total";
+
+/// The response of a task whose single-text calls are batched, along with the calls serving it.
+#[async_trait]
+trait Batched: Send + Sized + 'static {
+    /// The name of the task, in metrics and messages.
+    const TASK: &'static str;
+
+    async fn single(
+        inner: &dyn MightyClient,
+        request: Request<TextRequest>,
+    ) -> Result<Response<Self>, Status>;
+
+    async fn batch(
+        inner: &dyn MightyClient,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<Self>>, Status>;
+}
+
+#[async_trait]
+impl Batched for EmbeddingsResponse {
+    const TASK: &'static str = "embeddings";
+
+    async fn single(
+        inner: &dyn MightyClient,
+        request: Request<TextRequest>,
+    ) -> Result<Response<Self>, Status> {
+        inner.embeddings(request).await
+    }
+
+    async fn batch(
+        inner: &dyn MightyClient,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<Self>>, Status> {
+        inner.embeddings_batch(request).await
+    }
+}
+
+#[async_trait]
+impl Batched for TokenClassificationResponse {
+    const TASK: &'static str = "token_classification";
+
+    async fn single(
+        inner: &dyn MightyClient,
+        request: Request<TextRequest>,
+    ) -> Result<Response<Self>, Status> {
+        inner.token_classification(request).await
+    }
+
+    async fn batch(
+        inner: &dyn MightyClient,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<Self>>, Status> {
+        inner.token_classification_batch(request).await
+    }
+}
+
+/// A call waiting to be batched.
+struct Pending<T> {
+    text: String,
+    context: Option<RequestContext>,
+    reply: oneshot::Sender<Result<T, Status>>,
+}
+
+/// The `BatchingClient` struct is a `MightyClient` decorator aggregating concurrent
+/// single-text `embeddings` and `token_classification` calls into upstream batch calls
+/// (`embeddings_batch` and `token_classification_batch`), then handing each caller its own
+/// response, as upstreams serve batches far more efficiently than single texts.
+///
+/// A batch is sent once it holds `max_batch_size` texts or `window` after its first text
+/// arrived, whichever comes first; a batch of a single text is sent as a plain single-text
+/// call. Only calls of the same task and caller context share an upstream call, sent under the
+/// earliest of their deadlines. An upstream error fails every call of the batch. Calls asking
+/// for the raw upstream JSON aren't batched, and other tasks are forwarded as is. Up to
+/// `max_queue` calls of each task wait to be batched, further calls being shed with
+/// `RESOURCE_EXHAUSTED`.
+///
+/// The client should wrap the backend directly, as decorators in between would split batches
+/// back into single calls.
+pub struct BatchingClient {
+    inner: Arc<dyn MightyClient>,
+    embeddings: mpsc::Sender<Pending<EmbeddingsResponse>>,
+    token_classification: mpsc::Sender<Pending<TokenClassificationResponse>>,
+}
+
+impl BatchingClient {
+    /// Creates the client, spawning the tasks collecting batches, which run until the client
+    /// is dropped.
+    pub fn new(inner: Box<dyn MightyClient>, config: &MicroBatchingConfig) -> Self {
+        let inner: Arc<dyn MightyClient> = Arc::from(inner);
+        Self {
+            embeddings: spawn_collector(&inner, config),
+            token_classification: spawn_collector(&inner, config),
+            inner,
+        }
+    }
+}
+
+/// Spawns the task collecting the calls sent to the returned queue into batches.
+fn spawn_collector<T: Batched>(
+    inner: &Arc<dyn MightyClient>,
+    config: &MicroBatchingConfig,
+) -> mpsc::Sender<Pending<T>> {
+    let (queue, pending) = mpsc::channel(config.max_queue.max(1));
+    tokio::spawn(collect(inner.clone(), pending, config.clone()));
+    queue
+}
+
+/// Collects pending calls into batches, dispatching each batch concurrently with the
+/// collection of the next one.
+async fn collect<T: Batched>(
+    inner: Arc<dyn MightyClient>,
+    mut pending: mpsc::Receiver<Pending<T>>,
+    config: MicroBatchingConfig,
+) {
+    while let Some(first) = pending.recv().await {
+        let mut batch = vec![first];
+        let window = tokio::time::sleep(config.window);
+        tokio::pin!(window);
+        while batch.len() < config.max_batch_size {
+            tokio::select! {
+                call = pending.recv() => match call {
+                    Some(call) => batch.push(call),
+                    None => break,
+                },
+                _ = &mut window => break,
+            }
+        }
+        tokio::spawn(dispatch_all(inner.clone(), batch));
+    }
+}
+
+/// Whether the calls of contexts `a` and `b` may share an upstream call, i.e. their contexts
+/// only differ in what is specific to each call.
+fn same_caller(a: &Option<RequestContext>, b: &Option<RequestContext>) -> bool {
+    let caller = |context: &Option<RequestContext>| {
+        context.as_ref().map(|context| RequestContext {
+            request_id: String::new(),
+            deadline: None,
+            peer_addr: None,
+            ..context.clone()
+        })
+    };
+    caller(a) == caller(b)
+}
+
+/// Splits `batch` by caller context, making the upstream calls of the parts concurrently.
+async fn dispatch_all<T: Batched>(inner: Arc<dyn MightyClient>, batch: Vec<Pending<T>>) {
+    let mut parts: Vec<Vec<Pending<T>>> = Vec::new();
+    for call in batch {
+        match parts
+            .iter_mut()
+            .find(|part| same_caller(&part[0].context, &call.context))
+        {
+            Some(part) => part.push(call),
+            None => parts.push(vec![call]),
+        }
+    }
+    join_all(parts.into_iter().map(|part| dispatch(inner.as_ref(), part))).await;
+}
+
+/// Makes the upstream call of `batch`, whose calls share a caller context, replying to each of
+/// its calls.
+async fn dispatch<T: Batched>(inner: &dyn MightyClient, batch: Vec<Pending<T>>) {
+    if batch.len() == 1 {
+        let call = batch.into_iter().next().unwrap();
+        let mut request = Request::new(TextRequest { text: call.text });
+        if let Some(context) = call.context {
+            request.extensions_mut().insert(context);
+        }
+        let result = T::single(inner, request).await;
+        let _ = call.reply.send(result.map(Response::into_inner));
+        return;
+    }
+
+    debug!("Sending a {} batch of {} texts", T::TASK, batch.len());
+    let labels = [("task", T::TASK)];
+    Metrics::global()
+        .counter(BATCHES_METRIC, &labels)
+        .increment(1);
+    Metrics::global()
+        .counter(BATCHED_TEXTS_METRIC, &labels)
+        .increment(batch.len() as u64);
+    let context = batch_context(&batch);
+    let (texts, replies): (Vec<_>, Vec<_>) = batch
+        .into_iter()
+        .map(|call| (call.text, call.reply))
+        .unzip();
+    let mut request = Request::new(texts);
+    if let Some(context) = context {
+        request.extensions_mut().insert(context);
+    }
+    let responses = T::batch(inner, request)
+        .await
+        .map(Response::into_inner)
+        .and_then(|responses| {
+            if responses.len() == replies.len() {
+                Ok(responses)
+            } else {
+                Err(Status::internal(format!(
+                    "The {} batch response has {} responses for {} texts",
+                    T::TASK,
+                    responses.len(),
+                    replies.len()
+                )))
+            }
+        });
+    match responses {
+        Ok(responses) => {
+            for (reply, response) in replies.into_iter().zip(responses) {
+                let _ = reply.send(Ok(response));
+            }
+        }
+        Err(status) => {
+            for reply in replies {
+                let _ = reply.send(Err(status.clone()));
+            }
+        }
+    }
+}
+
+/// Returns the context of a batch call: that of its first call, with the earliest deadline of
+/// its calls, so no caller waits past its own deadline.
+fn batch_context<T>(batch: &[Pending<T>]) -> Option<RequestContext> {
+    let mut context = batch.first()?.context.clone()?;
+    context.deadline = batch
+        .iter()
+        .filter_map(|call| call.context.as_ref().and_then(|context| context.deadline))
+        .min();
+    Some(context)
+}
+
+/// Queues the call of `request` on `queue`, then waits for its response, unless it asks for
+/// the raw upstream JSON.
+async fn batched<T: Batched>(
+    inner: &dyn MightyClient,
+    queue: &mpsc::Sender<Pending<T>>,
+    request: Request<TextRequest>,
+) -> Result<Response<T>, Status> {
+    let context = RequestContext::get(&request).cloned();
+    if context.as_ref().is_some_and(|context| context.raw_json) {
+        return T::single(inner, request).await;
+    }
+    let (reply, response) = oneshot::channel();
+    let call = Pending {
+        text: request.into_inner().text,
+        context,
+        reply,
+    };
+    queue.try_send(call).map_err(|e| match e {
+        mpsc::error::TrySendError::Full(_) => Status::resource_exhausted(format!(
+            "Too many {} calls are waiting to be batched",
+            T::TASK
+        )),
+        mpsc::error::TrySendError::Closed(_) => {
+            Status::unavailable(format!("The {} batching task has stopped", T::TASK))
+        }
+    })?;
+    let response = response
+        .await
+        .map_err(|_| Status::internal(format!("The {} batch was dropped", T::TASK)))??;
+    Ok(Response::new(response))
+}
+
+#[async_trait]
+impl MightyClient for BatchingClient {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        self.inner.health_check(request).await
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        batched(self.inner.as_ref(), &self.embeddings, request).await
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        self.inner.question_answering(request).await
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        self.inner.sentence_transformers(request).await
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        self.inner.sequence_classification(request).await
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        batched(self.inner.as_ref(), &self.token_classification, request).await
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        self.inner.metadata(request).await
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        self.inner.rerank(request).await
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        self.inner.embeddings_batch(request).await
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        self.inner.token_classification_batch(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    use futures::future::join_all;
+
+    use super::*;
+
+    /// A batch embedded: its size, single texts being batches of one, and its context.
+    type Batch = (usize, Option<RequestContext>);
+
+    /// Records the batches embedded.
+    struct RecordingClient(Arc<Mutex<Vec<Batch>>>);
+
+    #[async_trait]
+    impl MightyClient for RecordingClient {
+        async fn health_check(
+            &self,
+            _request: Request<Empty>,
+        ) -> Result<Response<HealthcheckResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn embeddings(
+            &self,
+            request: Request<TextRequest>,
+        ) -> Result<Response<EmbeddingsResponse>, Status> {
+            let context = RequestContext::get(&request).cloned();
+            self.0.lock().unwrap().push((1, context));
+            Ok(Response::new(EmbeddingsResponse {
+                text: request.into_inner().text,
+                ..Default::default()
+            }))
+        }
+
+        async fn question_answering(
+            &self,
+            _request: Request<QuestionAnswerRequest>,
+        ) -> Result<Response<QuestionAnswerResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn sentence_transformers(
+            &self,
+            _request: Request<TextRequest>,
+        ) -> Result<Response<SentenceTransformersResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn sequence_classification(
+            &self,
+            _request: Request<TextRequest>,
+        ) -> Result<Response<SequenceClassificationResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn token_classification(
+            &self,
+            request: Request<TextRequest>,
+        ) -> Result<Response<TokenClassificationResponse>, Status> {
+            let context = RequestContext::get(&request).cloned();
+            self.0.lock().unwrap().push((1, context));
+            Ok(Response::new(TokenClassificationResponse {
+                text: request.into_inner().text,
+                ..Default::default()
+            }))
+        }
+
+        async fn metadata(
+            &self,
+            _request: Request<Empty>,
+        ) -> Result<Response<MetadataResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn embeddings_batch(
+            &self,
+            request: Request<Vec<String>>,
+        ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+            let context = RequestContext::get(&request).cloned();
+            let texts = request.into_inner();
+            self.0.lock().unwrap().push((texts.len(), context));
+            if texts.iter().any(String::is_empty) {
+                return Err(Status::invalid_argument("empty text"));
+            }
+            Ok(Response::new(
+                texts
+                    .into_iter()
+                    .map(|text| EmbeddingsResponse {
+                        text,
+                        ..Default::default()
+                    })
+                    .collect(),
+            ))
+        }
+
+        async fn token_classification_batch(
+            &self,
+            request: Request<Vec<String>>,
+        ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+            let context = RequestContext::get(&request).cloned();
+            let texts = request.into_inner();
+            self.0.lock().unwrap().push((texts.len(), context));
+            Ok(Response::new(
+                texts
+                    .into_iter()
+                    .map(|text| TokenClassificationResponse {
+                        text,
+                        ..Default::default()
+                    })
+                    .collect(),
+            ))
+        }
+    }
+
+    fn config() -> MicroBatchingConfig {
+        MicroBatchingConfig {
+            enabled: true,
+            window: Duration::from_millis(20),
+            max_batch_size: 3,
+            max_queue: 16,
+        }
+    }
+
+    fn sizes(batches: &Mutex<Vec<Batch>>) -> Vec<usize> {
+        batches
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(size, _)| *size)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_calls_are_batched_and_demultiplexed() {
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let config = config();
+        let client = BatchingClient::new(Box::new(RecordingClient(batches.clone())), &config);
+        let embed = |text: &str| {
+            client.embeddings(Request::new(TextRequest {
+                text: text.to_string(),
+            }))
+        };
+
+        let responses = join_all(["a", "b", "c", "d"].map(embed)).await;
+        let texts: Vec<_> = responses
+            .into_iter()
+            .map(|response| response.unwrap().into_inner().text)
+            .collect();
+        assert_eq!(texts, ["a", "b", "c", "d"]);
+        assert_eq!(sizes(&batches), [3, 1]);
+
+        let responses = join_all(["e", ""].map(embed)).await;
+        assert!(responses.iter().all(|response| response
+            .as_ref()
+            .is_err_and(|status| status.code() == tonic::Code::InvalidArgument)));
+    }
+
+    #[tokio::test]
+    async fn test_token_classification_calls_are_batched() {
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let config = config();
+        let client = BatchingClient::new(Box::new(RecordingClient(batches.clone())), &config);
+        let classify = |text: &str| {
+            client.token_classification(Request::new(TextRequest {
+                text: text.to_string(),
+            }))
+        };
+
+        let responses = join_all(["a", "b", "c", "d"].map(classify)).await;
+        let texts: Vec<_> = responses
+            .into_iter()
+            .map(|response| response.unwrap().into_inner().text)
+            .collect();
+        assert_eq!(texts, ["a", "b", "c", "d"]);
+        assert_eq!(sizes(&batches), [3, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_batches_are_split_by_caller_under_the_earliest_deadline() {
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let config = MicroBatchingConfig {
+            max_batch_size: 4,
+            ..config()
+        };
+        let client = BatchingClient::new(Box::new(RecordingClient(batches.clone())), &config);
+        let now = Instant::now();
+        let embed = |identity: &str, timeout: u64| {
+            let mut request = Request::new(TextRequest {
+                text: identity.to_string(),
+            });
+            request.extensions_mut().insert(RequestContext {
+                request_id: format!("{}-{}", identity, timeout),
+                identity: Some(identity.to_string()),
+                deadline: Some(now + Duration::from_secs(timeout)),
+                ..Default::default()
+            });
+            client.embeddings(request)
+        };
+
+        let responses = join_all([
+            embed("alice", 30),
+            embed("bob", 30),
+            embed("alice", 10),
+            embed("alice", 20),
+        ])
+        .await;
+        assert!(responses.iter().all(Result::is_ok));
+        let batches = batches.lock().unwrap();
+        let contexts: Vec<_> = batches
+            .iter()
+            .map(|(size, context)| {
+                let context = context.as_ref().unwrap();
+                (*size, context.identity.clone().unwrap(), context.deadline)
+            })
+            .collect();
+        assert_eq!(
+            contexts,
+            [
+                (3, "alice".to_string(), Some(now + Duration::from_secs(10))),
+                (1, "bob".to_string(), Some(now + Duration::from_secs(30))),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_calls_beyond_the_queue_are_shed() {
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let config = MicroBatchingConfig {
+            max_queue: 2,
+            ..config()
+        };
+        let client = BatchingClient::new(Box::new(RecordingClient(batches.clone())), &config);
+        let embed = |text: &str| {
+            client.embeddings(Request::new(TextRequest {
+                text: text.to_string(),
+            }))
+        };
+
+        // The calls are all queued before the batching task gets to run
+        let responses = join_all(["a", "b", "c"].map(embed)).await;
+        assert!(responses[..2].iter().all(Result::is_ok));
+        let status = responses[2].as_ref().unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert_eq!(sizes(&batches), [2]);
+    }
+}
@@ -1,31 +1,359 @@
+use std::io;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
 use async_trait::async_trait;
+use futures::future;
+use log::{error, info, warn};
+use tokio::process::{Child, Command};
+use tokio::sync::{watch, Mutex};
+use tokio::task::JoinHandle;
 use tonic::{Request, Response, Status};
 
+use crate::config::{BinaryConfig, HealthCheckConfig};
 use crate::proto::mighty_proto::{
     EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse, QuestionAnswerRequest,
     QuestionAnswerResponse, SentenceTransformersResponse, SequenceClassificationResponse,
     TextRequest, TokenClassificationResponse,
 };
 
-use super::MightyClient;
+use crate::services::metrics::Metrics;
+
+use super::rest::MightyServerRestClient;
+use super::{MightyClient, ModelUpgrade};
+
+const UPGRADES_METRIC: &str = "mighty_binary_upgrades_total";
+
+/// The interval between health check probes while a worker starts, and the shortest interval
+/// between probes of a ready worker.
+const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A Mighty worker subprocess, along with the task supervising it.
+struct Worker {
+    client: Arc<MightyServerRestClient>,
+    ready: watch::Receiver<bool>,
+    supervisor: JoinHandle<()>,
+}
+
+impl Worker {
+    fn spawn(index: u16, port: u16, config: Arc<BinaryConfig>) -> Self {
+        let base_url = format!("http://127.0.0.1:{}", port);
+        let client = Arc::new(MightyServerRestClient::new(base_url));
+        let (ready_tx, ready) = watch::channel(false);
+        let supervisor = tokio::spawn(supervise(index, port, config, client.clone(), ready_tx));
+        Self {
+            client,
+            ready,
+            supervisor,
+        }
+    }
+
+    fn is_ready(&self) -> bool {
+        *self.ready.borrow()
+    }
+
+    /// Waits until the worker is ready. Returns `false` if its supervisor stopped.
+    async fn wait_ready(&self) -> bool {
+        self.ready.clone().wait_for(|ready| *ready).await.is_ok()
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        // Dropping the supervisor's child process kills it
+        self.supervisor.abort();
+    }
+}
+
+/// A set of Mighty workers running the same model version.
+struct WorkerSet {
+    workers: Vec<Worker>,
+    /// Incremented by every model upgrade, the initial workers being generation 0.
+    generation: u64,
+}
+
+impl WorkerSet {
+    fn spawn(config: Arc<BinaryConfig>, generation: u64) -> Self {
+        let port = base_port(&config, generation);
+        let workers = (0..config.workers.max(1))
+            .map(|index| Worker::spawn(index, port.saturating_add(index), config.clone()))
+            .collect();
+        Self {
+            workers,
+            generation,
+        }
+    }
+
+    /// Returns the index of the next ready worker in round robin order, if any.
+    fn select(&self, next: &AtomicUsize) -> Option<usize> {
+        let len = self.workers.len();
+        let offset = next.fetch_add(1, Ordering::Relaxed);
+        (0..len)
+            .map(|i| (offset + i) % len)
+            .find(|&index| self.workers[index].is_ready())
+    }
+
+    /// Returns the index of the next ready worker, waiting up to `timeout` for one to become
+    /// ready if needed.
+    async fn ready_worker(&self, next: &AtomicUsize, timeout: Duration) -> Result<usize, Status> {
+        if let Some(index) = self.select(next) {
+            return Ok(index);
+        }
+        let waits = self
+            .workers
+            .iter()
+            .map(|worker| Box::pin(worker.wait_ready()));
+        match tokio::time::timeout(timeout, future::select_all(waits)).await {
+            Ok((true, index, _)) => Ok(index),
+            _ => Err(Status::unavailable("No Mighty worker subprocess is ready")),
+        }
+    }
+
+    /// Waits until every worker is ready. Returns `false` if a supervisor stopped.
+    async fn wait_all_ready(&self) -> bool {
+        future::join_all(self.workers.iter().map(Worker::wait_ready))
+            .await
+            .into_iter()
+            .all(|ready| ready)
+    }
+}
+
+/// Returns the port of the first worker of `generation`. Consecutive generations alternate
+/// between two port ranges, so standby workers can start while the current ones serve.
+fn base_port(config: &BinaryConfig, generation: u64) -> u16 {
+    match generation % 2 {
+        0 => config.port,
+        _ => config.port.saturating_add(config.workers.max(1)),
+    }
+}
+
+/// Returns the ports of the workers of `generation` already in use on the loopback interface,
+/// e.g. by the workers of a previous generation still completing their calls.
+fn ports_in_use(config: &BinaryConfig, generation: u64) -> Vec<u16> {
+    let port = base_port(config, generation);
+    (0..config.workers.max(1))
+        .map(|index| port.saturating_add(index))
+        .filter(|&port| std::net::TcpListener::bind(("127.0.0.1", port)).is_err())
+        .collect()
+}
 
-/// The `BinaryClient` struct is a placeholder implementation of the `MightyClient` trait.
-/// This client is intended to interface with a binary executable for making inference requests.
+/// The `BinaryClient` struct implements the `MightyClient` trait by running a pool of Mighty
+/// server executables as managed worker subprocesses and issuing requests to them over HTTP on
+/// their loopback ports. As a worker saturates a single core, the pool lets the gateway scale
+/// inference across cores.
+///
+/// A background task supervises each worker: it is considered ready once its health check
+/// passes, and killed and restarted if that doesn't happen within `startup_timeout`, if it fails
+/// consecutive health checks once ready, or whenever it exits. Restarts are delayed by
+/// `restart_delay`, doubled while the worker keeps failing before becoming ready.
+///
+/// Calls are distributed round robin across the ready workers. Calls made while no worker is
+/// ready wait for one, up to `startup_timeout`, and then fail with `UNAVAILABLE`. The health
+/// check reports failure without waiting.
+///
+/// Model upgrades (see `ModelUpgrade`) start a standby set of workers with the new model
+/// version on the alternate port range (the `workers` ports following those of the current
+/// set), while the current set keeps serving. Once every standby worker is ready, traffic is
+/// switched to the standby set at once, so calls never mix model versions, and the previous
+/// workers are killed as soon as their in-flight calls complete. The memory spike is thus
+/// bounded to one extra worker set for the duration of the upgrade, without any cold-start gap.
+/// If the standby workers aren't ready within `startup_timeout`, they are killed and the
+/// current ones keep serving.
 ///
-/// Currently, it provides dummy implementations for all required methods in the `MightyClient`
-/// trait. These methods should be updated to invoke the actual binary and handle responses
-/// accordingly.
-pub struct BinaryClient;
+/// The subprocesses are killed when the client is dropped.
+pub struct BinaryClient {
+    current: RwLock<Arc<WorkerSet>>,
+    /// The configuration of the current workers, locked for the duration of an upgrade.
+    config: Mutex<Arc<BinaryConfig>>,
+    next: AtomicUsize,
+    startup_timeout: Duration,
+}
 
 impl BinaryClient {
-    pub fn new() -> Self {
-        Self
+    /// Starts the Mighty worker subprocesses described by `config` along with their supervisors.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn spawn(config: BinaryConfig) -> Self {
+        let startup_timeout = config.startup_timeout;
+        let config = Arc::new(config);
+        Self {
+            current: RwLock::new(Arc::new(WorkerSet::spawn(config.clone(), 0))),
+            config: Mutex::new(config),
+            next: AtomicUsize::new(0),
+            startup_timeout,
+        }
+    }
+
+    /// Starts the workers like `spawn`, unless one of their ports is already in use, e.g. by the
+    /// workers of the binary backend serving when switching to a new one.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn try_spawn(config: BinaryConfig) -> Result<Self, String> {
+        let in_use = ports_in_use(&config, 0);
+        if !in_use.is_empty() {
+            return Err(format!("the Mighty worker ports {:?} are in use", in_use));
+        }
+        Ok(Self::spawn(config))
     }
+
+    /// Returns the workers currently serving. Holding them keeps them running after an upgrade.
+    fn current(&self) -> Arc<WorkerSet> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Returns the client of the next ready worker, waiting for one to become ready if needed.
+    async fn ready_client(&self) -> Result<ReadyWorker, Status> {
+        let workers = self.current();
+        let index = workers
+            .ready_worker(&self.next, self.startup_timeout)
+            .await?;
+        Ok(ReadyWorker { workers, index })
+    }
+}
+
+/// The client of a ready worker, which keeps the worker's set running while in use, so calls in
+/// flight during an upgrade complete before the previous workers are killed.
+struct ReadyWorker {
+    workers: Arc<WorkerSet>,
+    index: usize,
 }
 
-impl Default for BinaryClient {
-    fn default() -> Self {
-        Self::new()
+impl Deref for ReadyWorker {
+    type Target = MightyServerRestClient;
+
+    fn deref(&self) -> &Self::Target {
+        &self.workers.workers[self.index].client
+    }
+}
+
+/// Returns the arguments of a worker listening on `port`, with `{port}` and `{model_dir}`
+/// substituted.
+fn args(config: &BinaryConfig, port: u16) -> Vec<String> {
+    let port = port.to_string();
+    let model_dir = config
+        .model_dir
+        .as_ref()
+        .map(|dir| dir.display().to_string())
+        .unwrap_or_default();
+    config
+        .args
+        .iter()
+        .map(|arg| {
+            arg.replace("{port}", &port)
+                .replace("{model_dir}", &model_dir)
+        })
+        .collect()
+}
+
+/// Probes the worker's health check, failing if it takes longer than `timeout`.
+async fn is_healthy(client: &MightyServerRestClient, timeout: Duration) -> bool {
+    tokio::time::timeout(timeout, client.health_check(Request::new(Empty {})))
+        .await
+        .is_ok_and(|result| result.is_ok_and(|response| response.get_ref().success))
+}
+
+/// Waits until the worker reports itself healthy.
+async fn wait_healthy(client: &MightyServerRestClient, timeout: Duration) {
+    while !is_healthy(client, timeout).await {
+        tokio::time::sleep(PROBE_INTERVAL).await;
+    }
+}
+
+/// Probes a ready worker until it fails `unhealthy_threshold` consecutive health checks. Never
+/// returns when health checks are disabled.
+async fn monitor(client: &MightyServerRestClient, config: &HealthCheckConfig) {
+    if !config.enabled {
+        return future::pending().await;
+    }
+    let mut failures = 0;
+    while failures < config.unhealthy_threshold {
+        tokio::time::sleep(config.interval.max(PROBE_INTERVAL)).await;
+        failures = match is_healthy(client, config.timeout).await {
+            true => 0,
+            false => failures + 1,
+        };
+    }
+}
+
+async fn kill(child: &mut Child) -> io::Result<ExitStatus> {
+    child.kill().await?;
+    child.wait().await
+}
+
+/// Starts a worker and restarts it whenever it exits or turns unhealthy, publishing its
+/// readiness, until the task is aborted.
+async fn supervise(
+    index: u16,
+    port: u16,
+    config: Arc<BinaryConfig>,
+    client: Arc<MightyServerRestClient>,
+    ready: watch::Sender<bool>,
+) {
+    let name = format!(
+        "Mighty worker {} ({} on port {})",
+        index,
+        config.path.display(),
+        port
+    );
+    let mut delay = config.restart_delay;
+    loop {
+        let spawned = Command::new(&config.path)
+            .args(args(&config, port))
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .spawn();
+        match spawned {
+            Ok(mut child) => {
+                info!("Started {} (pid {:?})", name, child.id());
+                let started = tokio::time::timeout(
+                    config.startup_timeout,
+                    wait_healthy(&client, config.health_check.timeout),
+                );
+                let outcome = tokio::select! {
+                    status = child.wait() => Err(status),
+                    healthy = started => Ok(healthy.is_ok()),
+                };
+                let status = match outcome {
+                    Err(status) => status,
+                    Ok(true) => {
+                        info!("{} is ready", name);
+                        ready.send_replace(true);
+                        delay = config.restart_delay;
+                        let exited = tokio::select! {
+                            status = child.wait() => Some(status),
+                            _ = monitor(&client, &config.health_check) => None,
+                        };
+                        match exited {
+                            Some(status) => status,
+                            None => {
+                                warn!("{} is failing its health checks, killing it", name);
+                                kill(&mut child).await
+                            }
+                        }
+                    }
+                    Ok(false) => {
+                        warn!(
+                            "{} isn't healthy after {:?}, killing it",
+                            name, config.startup_timeout
+                        );
+                        kill(&mut child).await
+                    }
+                };
+                ready.send_replace(false);
+                match status {
+                    Ok(status) => warn!("{} exited with {}", name, status),
+                    Err(e) => warn!("Failed to wait for {}: {}", name, e),
+                }
+            }
+            Err(e) => error!("Failed to start {}: {}", name, e),
+        }
+        info!("Restarting {} in {:?}", name, delay);
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(config.max_restart_delay.max(config.restart_delay));
     }
 }
 
@@ -33,48 +361,247 @@ impl Default for BinaryClient {
 impl MightyClient for BinaryClient {
     async fn health_check(
         &self,
-        _request: Request<Empty>,
+        request: Request<Empty>,
     ) -> Result<Response<HealthcheckResponse>, Status> {
-        unimplemented!()
+        let workers = self.current();
+        match workers.select(&self.next) {
+            Some(index) => workers.workers[index].client.health_check(request).await,
+            None => Ok(Response::new(HealthcheckResponse { success: false })),
+        }
     }
+
     async fn embeddings(
         &self,
-        _request: Request<TextRequest>,
+        request: Request<TextRequest>,
     ) -> Result<Response<EmbeddingsResponse>, Status> {
-        unimplemented!()
+        self.ready_client().await?.embeddings(request).await
     }
 
     async fn question_answering(
         &self,
-        _request: Request<QuestionAnswerRequest>,
+        request: Request<QuestionAnswerRequest>,
     ) -> Result<Response<QuestionAnswerResponse>, Status> {
-        unimplemented!()
+        self.ready_client().await?.question_answering(request).await
     }
 
     async fn sentence_transformers(
         &self,
-        _request: Request<TextRequest>,
+        request: Request<TextRequest>,
     ) -> Result<Response<SentenceTransformersResponse>, Status> {
-        unimplemented!()
+        self.ready_client()
+            .await?
+            .sentence_transformers(request)
+            .await
     }
 
     async fn sequence_classification(
         &self,
-        _request: Request<TextRequest>,
+        request: Request<TextRequest>,
     ) -> Result<Response<SequenceClassificationResponse>, Status> {
-        unimplemented!()
+        self.ready_client()
+            .await?
+            .sequence_classification(request)
+            .await
     }
 
     async fn token_classification(
         &self,
-        _request: Request<TextRequest>,
+        request: Request<TextRequest>,
     ) -> Result<Response<TokenClassificationResponse>, Status> {
-        unimplemented!()
+        self.ready_client()
+            .await?
+            .token_classification(request)
+            .await
     }
+
     async fn metadata(
         &self,
-        _request: Request<Empty>,
+        request: Request<Empty>,
     ) -> Result<Response<MetadataResponse>, Status> {
-        unimplemented!()
+        self.ready_client().await?.metadata(request).await
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        self.ready_client().await?.embeddings_batch(request).await
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        self.ready_client()
+            .await?
+            .token_classification_batch(request)
+            .await
+    }
+}
+
+#[async_trait]
+impl ModelUpgrade for BinaryClient {
+    async fn upgrade(&self, model_dir: Option<PathBuf>) -> Result<u64, Status> {
+        let mut config = self
+            .config
+            .try_lock()
+            .map_err(|_| Status::failed_precondition("A model upgrade is already in progress"))?;
+        let mut standby_config = BinaryConfig::clone(&config);
+        if model_dir.is_some() {
+            standby_config.model_dir = model_dir;
+        }
+        let standby_config = Arc::new(standby_config);
+        let serving = self.current().generation;
+        let generation = serving + 1;
+        let in_use = ports_in_use(&standby_config, generation);
+        if !in_use.is_empty() {
+            return Err(Status::failed_precondition(format!(
+                "The ports {:?} of the standby Mighty workers are in use, e.g. by workers of a \
+                 previous generation completing their calls",
+                in_use
+            )));
+        }
+        info!(
+            "Starting standby Mighty workers of generation {} from port {}",
+            generation,
+            base_port(&standby_config, generation)
+        );
+
+        let standby = WorkerSet::spawn(standby_config.clone(), generation);
+        let ready = tokio::time::timeout(self.startup_timeout, standby.wait_all_ready()).await;
+        if !ready.unwrap_or(false) {
+            // Dropping the standby workers kills them
+            Metrics::global()
+                .counter(UPGRADES_METRIC, &[("outcome", "failed")])
+                .increment(1);
+            return Err(Status::unavailable(format!(
+                "The standby Mighty workers aren't ready after {:?}, generation {} keeps serving",
+                self.startup_timeout, serving
+            )));
+        }
+
+        *self.current.write().unwrap() = Arc::new(standby);
+        *config = standby_config;
+        Metrics::global()
+            .counter(UPGRADES_METRIC, &[("outcome", "switched")])
+            .increment(1);
+        info!(
+            "Switched to the Mighty workers of generation {}, retiring generation {} once its calls complete",
+            generation, serving
+        );
+        Ok(generation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn test_args_substitution() {
+        let config = BinaryConfig {
+            args: vec![
+                "--port".to_string(),
+                "{port}".to_string(),
+                "--model={model_dir}/embeddings".to_string(),
+            ],
+            model_dir: Some(PathBuf::from("/models")),
+            ..BinaryConfig::default()
+        };
+        assert_eq!(
+            args(&config, 5055),
+            vec!["--port", "5055", "--model=/models/embeddings"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_round_robin_skips_workers_not_ready() {
+        let worker = |ready| {
+            let (ready_tx, ready_rx) = watch::channel(ready);
+            Worker {
+                client: Arc::new(MightyServerRestClient::new(String::new())),
+                ready: ready_rx,
+                supervisor: tokio::spawn(async move { ready_tx.closed().await }),
+            }
+        };
+        let workers = WorkerSet {
+            workers: vec![worker(true), worker(false), worker(true)],
+            generation: 0,
+        };
+        let next = AtomicUsize::new(0);
+        let picks = (0..4)
+            .map(|_| workers.select(&next).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(picks, vec![0, 2, 2, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_calls_fail_until_ready() {
+        let config = BinaryConfig {
+            path: PathBuf::from("/nonexistent/mighty-server"),
+            workers: 2,
+            startup_timeout: Duration::from_millis(50),
+            ..BinaryConfig::default()
+        };
+        let client = BinaryClient::spawn(config);
+        let health = client.health_check(Request::new(Empty {})).await.unwrap();
+        assert!(!health.get_ref().success);
+        let status = client
+            .embeddings(Request::new(TextRequest {
+                text: "hello".to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+    }
+
+    #[test]
+    fn test_generations_alternate_port_ranges() {
+        let config = BinaryConfig {
+            workers: 4,
+            port: 5050,
+            ..BinaryConfig::default()
+        };
+        assert_eq!(base_port(&config, 0), 5050);
+        assert_eq!(base_port(&config, 1), 5054);
+        assert_eq!(base_port(&config, 2), 5050);
+    }
+
+    #[tokio::test]
+    async fn test_upgrades_fail_on_ports_in_use() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config = BinaryConfig {
+            path: PathBuf::from("/nonexistent/mighty-server"),
+            port: port - 1,
+            ..BinaryConfig::default()
+        };
+        assert_eq!(ports_in_use(&config, 1), vec![port]);
+
+        let client = BinaryClient::spawn(config.clone());
+        let status = client.upgrade(None).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        assert_eq!(client.current().generation, 0);
+        let config = BinaryConfig { port, ..config };
+        assert!(BinaryClient::try_spawn(config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failed_upgrade_keeps_current_workers() {
+        let config = BinaryConfig {
+            path: PathBuf::from("/nonexistent/mighty-server"),
+            startup_timeout: Duration::from_millis(50),
+            ..BinaryConfig::default()
+        };
+        let client = BinaryClient::spawn(config);
+        let status = client
+            .upgrade(Some(PathBuf::from("/models/v2")))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        assert_eq!(client.current().generation, 0);
+        assert_eq!(client.config.lock().await.model_dir, None);
     }
 }
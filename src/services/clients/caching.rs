@@ -0,0 +1,1001 @@
+use std::collections::{BTreeMap, HashMap};
+use std::mem::size_of;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use log::{debug, info, warn};
+use prost::Message;
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::{Request, Response, Status};
+
+use crate::config::{CacheConfig, Task};
+use crate::proto::mighty_proto::{
+    EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse, QuestionAnswerRequest,
+    QuestionAnswerResponse, RerankRequest, RerankResponse, SentenceTransformersResponse,
+    SequenceClassificationResponse, TextRequest, TokenClassificationResponse,
+};
+use crate::services::context::{RequestContext, CACHE_HEADER};
+use crate::services::metrics::{Gauge, Metrics};
+
+use super::{CacheFlush, MightyClient};
+
+/// Counter of cacheable calls, labelled by task and `result` (`hit` or `miss`).
+const CACHE_REQUESTS_METRIC: &str = "mighty_cache_requests_total";
+
+/// Counter of responses not cached for being over `max_entry_size`, by task.
+const CACHE_OVERSIZED_METRIC: &str = "mighty_cache_oversized_responses_total";
+
+/// Counter of evicted responses, labelled by `reason` (`capacity`, `expired` or `flush`).
+const CACHE_EVICTIONS_METRIC: &str = "mighty_cache_evictions_total";
+
+/// Gauge of the estimated memory held by in-process caches, in bytes, labelled by `cache`.
+pub const CACHE_MEMORY_METRIC: &str = "mighty_cache_memory_bytes";
+
+/// Gauge of the entries of in-process caches, labelled by `cache`.
+pub const CACHE_ENTRIES_METRIC: &str = "mighty_cache_entries";
+
+/// How often a call waiting on another replica's computation of its response checks the cache.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(25);
+
+/// The inputs a cached response was computed from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    /// The backend the response comes from and the generation of its model, e.g. `primary@v2`,
+    /// so backends sharing a cache don't serve each other's responses.
+    pub scope: String,
+    pub task: Task,
+    /// The text, or the question of question answering calls.
+    pub text: String,
+    /// The context of question answering calls, empty otherwise.
+    pub context: String,
+}
+
+impl CacheKey {
+    pub fn text(task: Task, text: &str) -> Self {
+        Self {
+            scope: String::new(),
+            task,
+            text: text.to_string(),
+            context: String::new(),
+        }
+    }
+}
+
+/// A claim on the computation of a key's response, released with `CacheBackend::unlock`.
+#[derive(Debug, Default)]
+pub struct CacheLock {
+    /// The value identifying the holder of the lock, empty for locks local to the process.
+    pub token: String,
+}
+
+/// A store of encoded responses shared by the calls of a `CachingClient`.
+#[async_trait]
+pub trait CacheBackend: Send + Sync {
+    /// Returns the encoded response cached for `key`, if any.
+    async fn get(&self, key: &CacheKey) -> Option<Vec<u8>>;
+
+    /// Caches the encoded response `value` for `key`, for `ttl`.
+    async fn insert(&self, key: &CacheKey, value: Vec<u8>, ttl: Duration);
+
+    /// Evicts every cached response, returning the number evicted.
+    async fn flush(&self) -> Result<u64, Status>;
+
+    /// Claims the computation of `key`'s response among the processes sharing the cache, for
+    /// `timeout` at most, returning `None` while another process holds the claim.
+    async fn lock(&self, _key: &CacheKey, _timeout: Duration) -> Option<CacheLock> {
+        Some(CacheLock::default())
+    }
+
+    /// Releases a claim returned by `lock`.
+    async fn unlock(&self, _key: &CacheKey, _lock: CacheLock) {}
+}
+
+struct Entry {
+    /// The encoded response message.
+    value: Vec<u8>,
+    /// The estimated memory held by the entry (see `entry_size`).
+    size: usize,
+    expires: Instant,
+    /// The tick of the last access, the entry's position in the recency order.
+    used: u64,
+}
+
+struct LruState {
+    entries: HashMap<CacheKey, Entry>,
+    /// The keys of the entries by tick of last access, least recently used first.
+    recency: BTreeMap<u64, CacheKey>,
+    tick: u64,
+    /// The estimated memory held by the entries.
+    bytes: usize,
+}
+
+/// Returns an estimate of the memory held by the entry of `value` for `key`: the value, the key,
+/// kept both in the map and in the recency order, and their bookkeeping.
+fn entry_size(key: &CacheKey, value: &[u8]) -> usize {
+    let key_size = size_of::<CacheKey>() + key.scope.len() + key.text.len() + key.context.len();
+    value.len() + 2 * key_size + size_of::<Entry>() + size_of::<u64>()
+}
+
+/// A bounded map of encoded responses evicting the least recently used entries once full, and
+/// expired entries on access. Its estimated memory and entries are published by the
+/// `mighty_cache_memory_bytes` and `mighty_cache_entries` gauges.
+pub struct LruCache {
+    capacity: usize,
+    state: Mutex<LruState>,
+    memory: Arc<Gauge>,
+    entries: Arc<Gauge>,
+}
+
+impl LruCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(LruState {
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                tick: 0,
+                bytes: 0,
+            }),
+            memory: Metrics::global().gauge(CACHE_MEMORY_METRIC, &[("cache", "responses")]),
+            entries: Metrics::global().gauge(CACHE_ENTRIES_METRIC, &[("cache", "responses")]),
+        }
+    }
+
+    /// Removes the entry of `key`, if any, returning it.
+    fn remove(&self, state: &mut LruState, key: &CacheKey) -> Option<Entry> {
+        let entry = state.entries.remove(key)?;
+        state.recency.remove(&entry.used);
+        state.bytes -= entry.size;
+        self.memory.add(-(entry.size as i64));
+        self.entries.add(-1);
+        Some(entry)
+    }
+
+    /// Returns the value cached for `key`, unless it expired, marking it as recently used.
+    pub fn get(&self, key: &CacheKey) -> Option<Vec<u8>> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        state.tick += 1;
+        let entry = state.entries.get_mut(key)?;
+        if entry.expires <= Instant::now() {
+            self.remove(state, key);
+            evicted("expired", 1);
+            return None;
+        }
+        state.recency.remove(&entry.used);
+        entry.used = state.tick;
+        state.recency.insert(state.tick, key.clone());
+        Some(entry.value.clone())
+    }
+
+    /// Caches `value` for `key` for `ttl`, evicting the least recently used entry when full.
+    pub fn insert(&self, key: CacheKey, value: Vec<u8>, ttl: Duration) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        state.tick += 1;
+        let tick = state.tick;
+        if self.remove(state, &key).is_none() && state.entries.len() >= self.capacity {
+            if let Some(oldest) = state.recency.first_key_value().map(|(_, key)| key.clone()) {
+                self.remove(state, &oldest);
+                evicted("capacity", 1);
+            }
+        }
+        let size = entry_size(&key, &value);
+        state.bytes += size;
+        self.memory.add(size as i64);
+        self.entries.add(1);
+        state.recency.insert(tick, key.clone());
+        state.entries.insert(
+            key,
+            Entry {
+                value,
+                size,
+                expires: Instant::now() + ttl,
+                used: tick,
+            },
+        );
+    }
+
+    /// Evicts every entry, returning the number evicted.
+    pub fn clear(&self) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        let cleared = state.entries.len() as u64;
+        self.memory.add(-(state.bytes as i64));
+        self.entries.add(-(cleared as i64));
+        state.entries.clear();
+        state.recency.clear();
+        state.bytes = 0;
+        cleared
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the estimated memory held by the entries, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        self.state.lock().unwrap().bytes
+    }
+}
+
+impl Drop for LruCache {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[async_trait]
+impl CacheBackend for LruCache {
+    async fn get(&self, key: &CacheKey) -> Option<Vec<u8>> {
+        LruCache::get(self, key)
+    }
+
+    async fn insert(&self, key: &CacheKey, value: Vec<u8>, ttl: Duration) {
+        LruCache::insert(self, key.clone(), value, ttl)
+    }
+
+    async fn flush(&self) -> Result<u64, Status> {
+        Ok(self.clear())
+    }
+}
+
+/// The `CachingClient` struct is a `MightyClient` decorator serving repeated inference calls
+/// from a cache of responses, keyed on the task and the input (the text, or the question and
+/// context), so duplicate texts don't reach the upstream. Responses are cached in memory, in a
+/// bounded LRU, or in another `CacheBackend` such as Redis.
+///
+/// Cached responses carry `x-mighty-cache: hit` in their metadata. Only successful responses
+/// are cached, for the TTL of their task, and only when their encoding is at most
+/// `max_entry_size` bytes long; calls asking for the raw upstream JSON (`x-mighty-debug:
+/// raw-json`) bypass the cache, as do health checks, metadata and rerank calls.
+///
+/// Keys are scoped to the backend the client fronts and to the configured `generation` of its
+/// model (see `with_scope`), so the primary, canary and routed backends sharing a Redis server,
+/// or successive model versions, don't serve each other's responses.
+///
+/// Concurrent misses of the same key are coalesced: the first call computes the response while
+/// the others wait for it, locally and, with a shared backend, across gateway replicas, for
+/// `lock_timeout` at most and never past their own deadline, failing with `DEADLINE_EXCEEDED`
+/// once it passes.
+///
+/// The cache is flushed by the `FlushCache` admin RPC, through `CacheFlush`.
+pub struct CachingClient {
+    inner: Box<dyn MightyClient>,
+    backend: Box<dyn CacheBackend>,
+    config: CacheConfig,
+    /// The scope of the keys of the responses cached by this client.
+    scope: String,
+    /// The locks of the keys being computed by this process.
+    locks: Locks,
+}
+
+type Locks = Mutex<HashMap<CacheKey, Arc<tokio::sync::Mutex<()>>>>;
+
+/// A call's share of the local lock of a key, removing the lock from `locks` once the last call
+/// sharing it is done, even when cancelled.
+struct LocalClaim<'a> {
+    locks: &'a Locks,
+    key: CacheKey,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<'a> LocalClaim<'a> {
+    fn new(locks: &'a Locks, key: &CacheKey) -> Self {
+        let lock = locks
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        Self {
+            locks,
+            key: key.clone(),
+            lock,
+        }
+    }
+}
+
+impl Drop for LocalClaim<'_> {
+    fn drop(&mut self) {
+        let mut locks = self.locks.lock().unwrap();
+        // Held by the map and this claim only
+        if Arc::strong_count(&self.lock) == 2 {
+            locks.remove(&self.key);
+        }
+    }
+}
+
+fn deadline_exceeded(task: &str) -> Status {
+    Status::deadline_exceeded(format!(
+        "The deadline passed waiting for the {} response being computed",
+        task
+    ))
+}
+
+impl CachingClient {
+    /// Creates a client caching responses in an in-memory LRU.
+    pub fn new(inner: Box<dyn MightyClient>, config: &CacheConfig) -> Self {
+        let backend = LruCache::new(config.capacity);
+        Self::with_backend(inner, Box::new(backend), config)
+    }
+
+    /// Creates a client caching responses in `backend`.
+    pub fn with_backend(
+        inner: Box<dyn MightyClient>,
+        backend: Box<dyn CacheBackend>,
+        config: &CacheConfig,
+    ) -> Self {
+        Self {
+            inner,
+            backend,
+            config: config.clone(),
+            scope: config.generation.clone(),
+            locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Scopes the cached responses to `backend`, e.g. `primary`, along with the configured
+    /// `generation`.
+    pub fn with_scope(mut self, backend: &str) -> Self {
+        self.scope = match self.config.generation.as_str() {
+            "" => backend.to_string(),
+            generation => format!("{}@{}", backend, generation),
+        };
+        self
+    }
+
+    /// Returns the response cached for `key`, if any.
+    async fn lookup<T: Message + Default>(&self, key: &CacheKey) -> Option<Response<T>> {
+        let value = self.backend.get(key).await?;
+        match T::decode(value.as_slice()) {
+            Ok(message) => {
+                debug!("Serving {} from the cache", key.task.as_str());
+                let mut response = Response::new(message);
+                response
+                    .metadata_mut()
+                    .insert(CACHE_HEADER, MetadataValue::from_static("hit"));
+                Some(response)
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to decode a cached {} response: {}",
+                    key.task.as_str(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Serves the call for `key` from the cache, or through `call` and caches its response.
+    async fn cached<R, T, F>(
+        &self,
+        mut key: CacheKey,
+        request: Request<R>,
+        call: F,
+    ) -> Result<Response<T>, Status>
+    where
+        R: Send,
+        T: Message + Default,
+        F: for<'c> FnOnce(
+            &'c dyn MightyClient,
+            Request<R>,
+        ) -> futures::future::BoxFuture<'c, Result<Response<T>, Status>>,
+    {
+        if RequestContext::get(&request).is_some_and(|context| context.raw_json) {
+            return call(self.inner.as_ref(), request).await;
+        }
+        key.scope.clone_from(&self.scope);
+        let task = key.task.as_str();
+        if let Some(response) = self.lookup(&key).await {
+            record(task, "hit");
+            return Ok(response);
+        }
+
+        // Wait for the calls of this process computing the same response
+        let claim = LocalClaim::new(&self.locks, &key);
+        let deadline = RequestContext::get(&request).and_then(|context| context.deadline);
+        let _guard = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline.into(), claim.lock.lock())
+                .await
+                .map_err(|_| deadline_exceeded(task))?,
+            None => claim.lock.lock().await,
+        };
+        self.compute(&key, deadline, request, call).await
+    }
+
+    /// Serves the call for `key` while holding its local lock, from the cache when a concurrent
+    /// call computed its response meanwhile.
+    async fn compute<R, T, F>(
+        &self,
+        key: &CacheKey,
+        deadline: Option<Instant>,
+        request: Request<R>,
+        call: F,
+    ) -> Result<Response<T>, Status>
+    where
+        R: Send,
+        T: Message + Default,
+        F: for<'c> FnOnce(
+            &'c dyn MightyClient,
+            Request<R>,
+        ) -> futures::future::BoxFuture<'c, Result<Response<T>, Status>>,
+    {
+        let task = key.task.as_str();
+        if let Some(response) = self.lookup(key).await {
+            record(task, "hit");
+            return Ok(response);
+        }
+
+        // Wait for another replica computing the same response, up to the lock timeout
+        let started = Instant::now();
+        let lock = loop {
+            if let Some(lock) = self.backend.lock(key, self.config.lock_timeout).await {
+                break Some(lock);
+            }
+            let now = Instant::now();
+            if deadline.is_some_and(|deadline| deadline <= now) {
+                return Err(deadline_exceeded(task));
+            }
+            if started.elapsed() >= self.config.lock_timeout {
+                debug!("Timed out waiting for the cached {} response", task);
+                break None;
+            }
+            let poll = deadline.map_or(LOCK_POLL_INTERVAL, |deadline| {
+                (deadline - now).min(LOCK_POLL_INTERVAL)
+            });
+            tokio::time::sleep(poll).await;
+            if let Some(response) = self.lookup(key).await {
+                record(task, "hit");
+                return Ok(response);
+            }
+        };
+
+        record(task, "miss");
+        let result = call(self.inner.as_ref(), request).await;
+        if let Ok(response) = &result {
+            self.store(key, response.get_ref()).await;
+        }
+        if let Some(lock) = lock {
+            self.backend.unlock(key, lock).await;
+        }
+        result
+    }
+
+    /// Caches `response` for `key`, unless it is larger than `max_entry_size`.
+    async fn store<T: Message>(&self, key: &CacheKey, response: &T) {
+        let task = key.task.as_str();
+        let value = response.encode_to_vec();
+        match self.config.max_entry_size {
+            Some(max_size) if value.len() as u64 > max_size => {
+                debug!("Not caching a {} byte {} response", value.len(), task);
+                Metrics::global()
+                    .counter(CACHE_OVERSIZED_METRIC, &[("task", task)])
+                    .increment(1);
+            }
+            _ => {
+                let ttl = self.config.ttl(key.task);
+                self.backend.insert(key, value, ttl).await;
+            }
+        }
+    }
+
+    /// Serves the texts of a batch call of `task` from the cache, sending those missing from it
+    /// through `call` as one batch and caching their responses. Batches aren't coalesced with
+    /// concurrent calls.
+    async fn cached_batch<T, F>(
+        &self,
+        task: Task,
+        request: Request<Vec<String>>,
+        call: F,
+    ) -> Result<Response<Vec<T>>, Status>
+    where
+        T: Message + Default,
+        F: for<'c> FnOnce(
+            &'c dyn MightyClient,
+            Request<Vec<String>>,
+        )
+            -> futures::future::BoxFuture<'c, Result<Response<Vec<T>>, Status>>,
+    {
+        if RequestContext::get(&request).is_some_and(|context| context.raw_json) {
+            return call(self.inner.as_ref(), request).await;
+        }
+        let (metadata, extensions, texts) = request.into_parts();
+        let mut responses = Vec::with_capacity(texts.len());
+        let mut missing = Vec::new();
+        for (index, text) in texts.into_iter().enumerate() {
+            let key = CacheKey {
+                scope: self.scope.clone(),
+                ..CacheKey::text(task, &text)
+            };
+            match self.lookup::<T>(&key).await {
+                Some(response) => {
+                    record(task.as_str(), "hit");
+                    responses.push(Some(response.into_inner()));
+                }
+                None => {
+                    record(task.as_str(), "miss");
+                    responses.push(None);
+                    missing.push((index, key));
+                }
+            }
+        }
+        if missing.is_empty() {
+            let mut response = Response::new(responses.into_iter().flatten().collect());
+            response
+                .metadata_mut()
+                .insert(CACHE_HEADER, MetadataValue::from_static("hit"));
+            return Ok(response);
+        }
+        let texts = missing.iter().map(|(_, key)| key.text.clone()).collect();
+        let request = Request::from_parts(metadata, extensions, texts);
+        // The extensions of the upstream response, e.g. the `ServedBy` upstream, are kept
+        let (_, computed, extensions) = call(self.inner.as_ref(), request).await?.into_parts();
+        if computed.len() != missing.len() {
+            return Err(Status::internal(format!(
+                "The {} batch response has {} responses for {} texts",
+                task.as_str(),
+                computed.len(),
+                missing.len()
+            )));
+        }
+        for ((index, key), response) in missing.into_iter().zip(computed) {
+            self.store(&key, &response).await;
+            responses[index] = Some(response);
+        }
+        Ok(Response::from_parts(
+            MetadataMap::new(),
+            responses.into_iter().flatten().collect(),
+            extensions,
+        ))
+    }
+}
+
+fn record(task: &str, result: &str) {
+    Metrics::global()
+        .counter(CACHE_REQUESTS_METRIC, &[("task", task), ("result", result)])
+        .increment(1);
+}
+
+fn evicted(reason: &str, count: u64) {
+    Metrics::global()
+        .counter(CACHE_EVICTIONS_METRIC, &[("reason", reason)])
+        .increment(count);
+}
+
+#[async_trait]
+impl CacheFlush for CachingClient {
+    async fn flush(&self) -> Result<u64, Status> {
+        let flushed = self.backend.flush().await?;
+        info!("Flushed {} cached responses", flushed);
+        evicted("flush", flushed);
+        Ok(flushed)
+    }
+}
+
+#[async_trait]
+impl MightyClient for CachingClient {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        self.inner.health_check(request).await
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        let key = CacheKey::text(Task::Embeddings, &request.get_ref().text);
+        self.cached(key, request, |client, request| client.embeddings(request))
+            .await
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        let key = CacheKey {
+            scope: String::new(),
+            task: Task::QuestionAnswering,
+            text: request.get_ref().question.clone(),
+            context: request.get_ref().context.clone(),
+        };
+        self.cached(key, request, |client, request| {
+            client.question_answering(request)
+        })
+        .await
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        let key = CacheKey::text(Task::SentenceTransformers, &request.get_ref().text);
+        self.cached(key, request, |client, request| {
+            client.sentence_transformers(request)
+        })
+        .await
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        let key = CacheKey::text(Task::SequenceClassification, &request.get_ref().text);
+        self.cached(key, request, |client, request| {
+            client.sequence_classification(request)
+        })
+        .await
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        let key = CacheKey::text(Task::TokenClassification, &request.get_ref().text);
+        self.cached(key, request, |client, request| {
+            client.token_classification(request)
+        })
+        .await
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        self.inner.metadata(request).await
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        self.inner.rerank(request).await
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        self.cached_batch(Task::Embeddings, request, |client, request| {
+            client.embeddings_batch(request)
+        })
+        .await
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        self.cached_batch(Task::TokenClassification, request, |client, request| {
+            client.token_classification_batch(request)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn test_lru_evicts_least_recently_used() {
+        let ttl = Duration::from_secs(60);
+        let cache = LruCache::new(2);
+        let key = |text: &str| CacheKey::text(Task::Embeddings, text);
+        cache.insert(key("a"), vec![1], ttl);
+        cache.insert(key("b"), vec![2], ttl);
+        assert_eq!(cache.get(&key("a")), Some(vec![1]));
+        cache.insert(key("c"), vec![3], ttl);
+
+        assert_eq!(cache.get(&key("b")), None);
+        assert_eq!(cache.get(&key("a")), Some(vec![1]));
+        assert_eq!(cache.get(&key("c")), Some(vec![3]));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(
+            cache.memory_usage(),
+            entry_size(&key("a"), &[1]) + entry_size(&key("c"), &[3])
+        );
+
+        cache.insert(key("d"), vec![4], Duration::ZERO);
+        assert_eq!(cache.get(&key("d")), None);
+        assert_eq!(cache.len(), 1);
+
+        assert_eq!(cache.clear(), 1);
+        assert!(cache.is_empty());
+        assert_eq!(cache.memory_usage(), 0);
+    }
+
+    /// Counts embeddings calls, each taking 20ms.
+    struct CountingClient(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl MightyClient for CountingClient {
+        async fn health_check(
+            &self,
+            _request: Request<Empty>,
+        ) -> Result<Response<HealthcheckResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn embeddings(
+            &self,
+            request: Request<TextRequest>,
+        ) -> Result<Response<EmbeddingsResponse>, Status> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Ok(Response::new(EmbeddingsResponse {
+                text: request.into_inner().text,
+                took: 7,
+                ..Default::default()
+            }))
+        }
+
+        async fn question_answering(
+            &self,
+            _request: Request<QuestionAnswerRequest>,
+        ) -> Result<Response<QuestionAnswerResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn sentence_transformers(
+            &self,
+            _request: Request<TextRequest>,
+        ) -> Result<Response<SentenceTransformersResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn sequence_classification(
+            &self,
+            _request: Request<TextRequest>,
+        ) -> Result<Response<SequenceClassificationResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn token_classification(
+            &self,
+            _request: Request<TextRequest>,
+        ) -> Result<Response<TokenClassificationResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn metadata(
+            &self,
+            _request: Request<Empty>,
+        ) -> Result<Response<MetadataResponse>, Status> {
+            unimplemented!()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_texts_are_served_from_the_cache() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let client = CachingClient::new(
+            Box::new(CountingClient(calls.clone())),
+            &CacheConfig::default(),
+        );
+        let request = |text: &str| {
+            Request::new(TextRequest {
+                text: text.to_string(),
+            })
+        };
+
+        let miss = client.embeddings(request("hello")).await.unwrap();
+        assert!(miss.metadata().get(CACHE_HEADER).is_none());
+        let hit = client.embeddings(request("hello")).await.unwrap();
+        assert_eq!(hit.metadata().get(CACHE_HEADER).unwrap(), "hit");
+        assert_eq!(hit.get_ref(), miss.get_ref());
+        client.embeddings(request("world")).await.unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_misses_are_coalesced() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let client = CachingClient::new(
+            Box::new(CountingClient(calls.clone())),
+            &CacheConfig::default(),
+        );
+        let responses = futures::future::join_all((0..5).map(|_| {
+            client.embeddings(Request::new(TextRequest {
+                text: "hello".to_string(),
+            }))
+        }))
+        .await;
+
+        assert!(responses.iter().all(Result::is_ok));
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert!(client.locks.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_calls_release_their_locks() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let client = Arc::new(CachingClient::new(
+            Box::new(CountingClient(calls.clone())),
+            &CacheConfig::default(),
+        ));
+        let call = tokio::spawn({
+            let client = client.clone();
+            async move {
+                client
+                    .embeddings(Request::new(TextRequest {
+                        text: "hello".to_string(),
+                    }))
+                    .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(client.locks.lock().unwrap().len(), 1);
+
+        call.abort();
+        assert!(call.await.unwrap_err().is_cancelled());
+        assert!(client.locks.lock().unwrap().is_empty());
+    }
+
+    /// A shared backend whose locks are always held by another replica.
+    struct LockedCache;
+
+    #[async_trait]
+    impl CacheBackend for LockedCache {
+        async fn get(&self, _key: &CacheKey) -> Option<Vec<u8>> {
+            None
+        }
+
+        async fn insert(&self, _key: &CacheKey, _value: Vec<u8>, _ttl: Duration) {}
+
+        async fn flush(&self) -> Result<u64, Status> {
+            Ok(0)
+        }
+
+        async fn lock(&self, _key: &CacheKey, _timeout: Duration) -> Option<CacheLock> {
+            None
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lock_waits_end_at_the_deadline() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let client = CachingClient::with_backend(
+            Box::new(CountingClient(calls.clone())),
+            Box::new(LockedCache),
+            &CacheConfig::default(),
+        );
+        let mut request = Request::new(TextRequest {
+            text: "hello".to_string(),
+        });
+        request.extensions_mut().insert(RequestContext {
+            deadline: Some(Instant::now() + Duration::from_millis(60)),
+            ..Default::default()
+        });
+
+        let started = Instant::now();
+        let status = client.embeddings(request).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::DeadlineExceeded);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(calls.load(Ordering::Relaxed), 0);
+        assert!(client.locks.lock().unwrap().is_empty());
+    }
+
+    /// Shares an in-memory cache between clients.
+    #[async_trait]
+    impl CacheBackend for Arc<LruCache> {
+        async fn get(&self, key: &CacheKey) -> Option<Vec<u8>> {
+            LruCache::get(self, key)
+        }
+
+        async fn insert(&self, key: &CacheKey, value: Vec<u8>, ttl: Duration) {
+            LruCache::insert(self, key.clone(), value, ttl)
+        }
+
+        async fn flush(&self) -> Result<u64, Status> {
+            Ok(self.clear())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_responses_are_scoped_to_their_backend() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let cache = Arc::new(LruCache::new(10));
+        let config = CacheConfig {
+            generation: "v2".to_string(),
+            ..Default::default()
+        };
+        let client = |scope: &str| {
+            CachingClient::with_backend(
+                Box::new(CountingClient(calls.clone())),
+                Box::new(cache.clone()),
+                &config,
+            )
+            .with_scope(scope)
+        };
+        let request = || {
+            Request::new(TextRequest {
+                text: "hello".to_string(),
+            })
+        };
+
+        client("primary").embeddings(request()).await.unwrap();
+        client("canary").embeddings(request()).await.unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+        let hit = client("primary").embeddings(request()).await.unwrap();
+        assert_eq!(hit.metadata().get(CACHE_HEADER).unwrap(), "hit");
+        assert!(LruCache::get(
+            &cache,
+            &CacheKey {
+                scope: "primary@v2".to_string(),
+                ..CacheKey::text(Task::Embeddings, "hello")
+            }
+        )
+        .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_responses_are_not_cached_until_flushed() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let config = CacheConfig {
+            max_entry_size: Some(8),
+            ..Default::default()
+        };
+        let client = CachingClient::new(Box::new(CountingClient(calls.clone())), &config);
+        let request = |text: &str| {
+            Request::new(TextRequest {
+                text: text.to_string(),
+            })
+        };
+
+        for _ in 0..2 {
+            client.embeddings(request("hi")).await.unwrap();
+            client
+                .embeddings(request("an oversized text"))
+                .await
+                .unwrap();
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+
+        assert_eq!(client.flush().await.unwrap(), 1);
+        client.embeddings(request("hi")).await.unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 4);
+    }
+
+    #[tokio::test]
+    async fn test_batches_only_send_the_texts_missing_from_the_cache() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let client = CachingClient::new(
+            Box::new(CountingClient(calls.clone())),
+            &CacheConfig::default(),
+        );
+        let texts =
+            |texts: &[&str]| Request::new(texts.iter().map(|text| text.to_string()).collect());
+
+        client.embeddings_batch(texts(&["a", "b"])).await.unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+        let responses = client
+            .embeddings_batch(texts(&["b", "c", "a"]))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+        let responses: Vec<_> = responses
+            .iter()
+            .map(|response| response.text.as_str())
+            .collect();
+        assert_eq!(responses, ["b", "c", "a"]);
+
+        let hit = client.embeddings_batch(texts(&["c", "a"])).await.unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+        assert_eq!(hit.metadata().get(CACHE_HEADER).unwrap(), "hit");
+    }
+}
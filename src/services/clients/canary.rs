@@ -0,0 +1,289 @@
+use std::time::Instant;
+
+use async_trait::async_trait;
+use rand::Rng;
+use tonic::{Request, Response, Status};
+
+use crate::config::{CanaryConfig, Task};
+use crate::proto::mighty_proto::{
+    EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse, QuestionAnswerRequest,
+    QuestionAnswerResponse, RerankRequest, RerankResponse, SentenceTransformersResponse,
+    SequenceClassificationResponse, TextRequest, TokenClassificationResponse,
+};
+use crate::services::context::RequestContext;
+use crate::services::metrics::{Metrics, DURATION_BUCKETS};
+use crate::services::hash::reference;
+
+use super::MightyClient;
+
+/// Counter of split calls, labelled by task, `backend` (`stable` or `canary`) and `result` (`ok`
+/// or `error`).
+const CANARY_REQUESTS_METRIC: &str = "mighty_canary_requests_total";
+
+/// Histogram of the durations of split calls, labelled by task and `backend`.
+const CANARY_DURATION_METRIC: &str = "mighty_canary_request_duration_seconds";
+
+/// The `CanaryClient` struct is a `MightyClient` splitting inference calls between a stable and
+/// a canary backend by weight, e.g. 95/5, so a new model version is rolled out gradually: each
+/// call of a task is served by the canary with the probability configured for the task.
+///
+/// The draw is sticky per caller: callers with an identity or a tenant are assigned a backend by
+/// hashing it, so each sees a single model version, e.g. comparable embeddings, while the weight
+/// stays put. Anonymous calls are drawn at random.
+///
+/// Calls are counted and timed per backend, so the canary's error rate and latency can be
+/// compared with the stable backend's before its weight is raised. Health checks and metadata
+/// are those of the stable backend, so a failing canary doesn't take the gateway out of
+/// rotation.
+pub struct CanaryClient {
+    stable: Box<dyn MightyClient>,
+    canary: Box<dyn MightyClient>,
+    config: CanaryConfig,
+}
+
+impl CanaryClient {
+    pub fn new(
+        stable: Box<dyn MightyClient>,
+        canary: Box<dyn MightyClient>,
+        config: &CanaryConfig,
+    ) -> Self {
+        Self {
+            stable,
+            canary,
+            config: config.clone(),
+        }
+    }
+
+    /// Makes the `call` of `task` on the backend drawn by weight, recording its statistics.
+    async fn split<R, T, F>(
+        &self,
+        task: Task,
+        request: Request<R>,
+        call: F,
+    ) -> Result<Response<T>, Status>
+    where
+        F: for<'c> FnOnce(
+            &'c dyn MightyClient,
+            Request<R>,
+        ) -> futures::future::BoxFuture<'c, Result<Response<T>, Status>>,
+    {
+        let (backend, client) = if is_canary(&request, self.config.weight(task)) {
+            ("canary", self.canary.as_ref())
+        } else {
+            ("stable", self.stable.as_ref())
+        };
+
+        let started = Instant::now();
+        let result = call(client, request).await;
+        let metrics = Metrics::global();
+        let labels = [("task", task.as_str()), ("backend", backend)];
+        metrics
+            .histogram(CANARY_DURATION_METRIC, &labels, DURATION_BUCKETS)
+            .observe(started.elapsed().as_secs_f64());
+        let outcome = if result.is_ok() { "ok" } else { "error" };
+        metrics
+            .counter(
+                CANARY_REQUESTS_METRIC,
+                &[
+                    ("task", task.as_str()),
+                    ("backend", backend),
+                    ("result", outcome),
+                ],
+            )
+            .increment(1);
+        result
+    }
+}
+
+/// Whether the call of `request` is served by the canary, `weight` being the percentage of the
+/// calls served by the canary.
+fn is_canary<R>(request: &Request<R>, weight: f64) -> bool {
+    let weight = weight.clamp(0.0, 100.0);
+    let caller = RequestContext::get(request)
+        .and_then(|context| context.identity.as_ref().or(context.tenant.as_ref()));
+    match caller {
+        // The caller's bucket, in hundredths of a percent
+        Some(caller) => ((reference(caller) % 10_000) as f64) < weight * 100.0,
+        None => rand::thread_rng().gen_bool(weight / 100.0),
+    }
+}
+
+#[async_trait]
+impl MightyClient for CanaryClient {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        self.stable.health_check(request).await
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        self.split(Task::Embeddings, request, |client, request| {
+            client.embeddings(request)
+        })
+        .await
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        self.split(Task::QuestionAnswering, request, |client, request| {
+            client.question_answering(request)
+        })
+        .await
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        self.split(Task::SentenceTransformers, request, |client, request| {
+            client.sentence_transformers(request)
+        })
+        .await
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        self.split(Task::SequenceClassification, request, |client, request| {
+            client.sequence_classification(request)
+        })
+        .await
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        self.split(Task::TokenClassification, request, |client, request| {
+            client.token_classification(request)
+        })
+        .await
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        self.stable.metadata(request).await
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        self.split(Task::Rerank, request, |client, request| {
+            client.rerank(request)
+        })
+        .await
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        self.split(Task::Embeddings, request, |client, request| {
+            client.embeddings_batch(request)
+        })
+        .await
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        self.split(Task::TokenClassification, request, |client, request| {
+            client.token_classification_batch(request)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::services::clients::mock::MockMightyClient;
+
+    use super::*;
+
+    fn boxed(client: &Arc<MockMightyClient>) -> Box<dyn MightyClient> {
+        let client: Arc<dyn MightyClient> = client.clone();
+        Box::new(client)
+    }
+
+    fn text() -> Request<TextRequest> {
+        Request::new(TextRequest {
+            text: "hello".to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_calls_are_split_by_task_weight() {
+        let stable = Arc::new(MockMightyClient::new());
+        let canary = Arc::new(MockMightyClient::new());
+        let config = CanaryConfig {
+            enabled: true,
+            backend: None,
+            weight: 10.0,
+            weights: HashMap::from([
+                (Task::TokenClassification, 100.0),
+                (Task::SequenceClassification, 0.0),
+            ]),
+        };
+        let client = CanaryClient::new(boxed(&stable), boxed(&canary), &config);
+
+        for _ in 0..1000 {
+            client.embeddings(text()).await.unwrap();
+        }
+        client.token_classification(text()).await.unwrap();
+        client.sequence_classification(text()).await.unwrap();
+
+        let canary_share = canary.calls(Task::Embeddings);
+        assert!((50..=150).contains(&canary_share), "{}", canary_share);
+        assert_eq!(stable.calls(Task::Embeddings), 1000 - canary_share);
+        assert_eq!(canary.calls(Task::TokenClassification), 1);
+        assert_eq!(stable.calls(Task::SequenceClassification), 1);
+    }
+
+    #[tokio::test]
+    async fn test_callers_stick_to_a_backend() {
+        let stable = Arc::new(MockMightyClient::new());
+        let canary = Arc::new(MockMightyClient::new());
+        let config = CanaryConfig {
+            enabled: true,
+            backend: None,
+            weight: 50.0,
+            weights: HashMap::new(),
+        };
+        let client = CanaryClient::new(boxed(&stable), boxed(&canary), &config);
+        let call = |identity: usize| {
+            let mut request = text();
+            request.extensions_mut().insert(RequestContext {
+                identity: Some(format!("caller-{}", identity)),
+                ..Default::default()
+            });
+            client.embeddings(request)
+        };
+
+        for identity in 0..100 {
+            let before = canary.calls(Task::Embeddings);
+            call(identity).await.unwrap();
+            let on_canary = canary.calls(Task::Embeddings) > before;
+            for _ in 0..5 {
+                let before = canary.calls(Task::Embeddings);
+                call(identity).await.unwrap();
+                assert_eq!(canary.calls(Task::Embeddings) > before, on_canary);
+            }
+        }
+        let canary_share = canary.calls(Task::Embeddings);
+        assert!((150..=450).contains(&canary_share), "{}", canary_share);
+    }
+}
@@ -0,0 +1,321 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use log::warn;
+use rand::Rng;
+use serde_json::Value;
+use tonic::{Request, Response, Status};
+
+use crate::config::{ChaosConfig, ChaosError, Task};
+use crate::proto::mighty_proto::{
+    Embedding, EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse,
+    QuestionAnswerRequest, QuestionAnswerResponse, RerankRequest, RerankResponse,
+    SentenceTransformersResponse, SequenceClassificationResponse, TextRequest,
+    TokenClassificationResponse,
+};
+use crate::services::metrics::Metrics;
+
+use super::{FaultInjection, MightyClient};
+
+/// Counter of injected faults, by task and `fault`: `error`, `truncated` or `malformed_json`.
+const FAULTS_METRIC: &str = "mighty_chaos_faults_total";
+
+/// Returns the error of an upstream call failing with `error`.
+fn injected_error(error: ChaosError, task: Task) -> Status {
+    let message = format!("Injected {} failure", task.as_str());
+    match error {
+        ChaosError::Unavailable => Status::unavailable(message),
+        ChaosError::Internal => Status::internal(message),
+        ChaosError::DeadlineExceeded => Status::deadline_exceeded(message),
+        ChaosError::ResourceExhausted => Status::resource_exhausted(message),
+    }
+}
+
+/// Returns the error of an upstream call answered with malformed JSON, as reported by the REST
+/// client.
+fn malformed_json(task: Task) -> Status {
+    let error = serde_json::from_str::<Value>(r#"{"outputs": [[0.1, 0.2"#)
+        .expect_err("the JSON is truncated");
+    Status::internal(format!(
+        "Failed to parse /{} JSON: {}",
+        task.as_str(),
+        error
+    ))
+}
+
+/// Keeps the first half of `values`, as if the upstream response was cut short.
+fn truncate<T>(values: &mut Vec<T>) {
+    values.truncate(values.len() / 2);
+}
+
+fn truncate_embeddings(embeddings: &mut [Embedding]) {
+    for embedding in embeddings {
+        truncate(&mut embedding.values);
+    }
+}
+
+/// The `ChaosClient` struct is a `MightyClient` decorator injecting faults into upstream calls,
+/// to check that consumers of the gateway cope with its misbehavior: errors, added latency with
+/// jitter, truncated responses (half of the embedding values, logits, entities or results) and
+/// malformed upstream JSON, each at a configurable rate. It is available with the `chaos`
+/// feature, and must never be enabled in production.
+///
+/// Injection can be turned on and off while serving through the `SetChaos` admin RPC. Health
+/// checks and metadata calls are left untouched, so the gateway stays ready.
+pub struct ChaosClient {
+    inner: Box<dyn MightyClient>,
+    config: ChaosConfig,
+    enabled: AtomicBool,
+}
+
+impl ChaosClient {
+    /// Creates the client, injecting faults from the start when `config.enabled` is set.
+    pub fn new(inner: Box<dyn MightyClient>, config: &ChaosConfig) -> Self {
+        Self {
+            inner,
+            config: config.clone(),
+            enabled: AtomicBool::new(config.enabled),
+        }
+    }
+
+    /// Draws whether a fault occurring at `rate` is injected.
+    fn draw(rate: f64) -> bool {
+        rate > 0.0 && rand::thread_rng().gen_bool(rate.min(1.0))
+    }
+
+    fn record(task: Task, fault: &str) {
+        Metrics::global()
+            .counter(FAULTS_METRIC, &[("task", task.as_str()), ("fault", fault)])
+            .increment(1);
+    }
+
+    /// Makes the `call` of `task`, injecting faults, and truncating its response with
+    /// `truncate_response` when drawn.
+    async fn call<R, T, F>(
+        &self,
+        task: Task,
+        request: Request<R>,
+        call: F,
+        truncate_response: fn(&mut T),
+    ) -> Result<Response<T>, Status>
+    where
+        F: for<'c> FnOnce(
+            &'c dyn MightyClient,
+            Request<R>,
+        ) -> futures::future::BoxFuture<'c, Result<Response<T>, Status>>,
+    {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return call(self.inner.as_ref(), request).await;
+        }
+        let jitter = if self.config.jitter.is_zero() {
+            Duration::ZERO
+        } else {
+            rand::thread_rng().gen_range(Duration::ZERO..=self.config.jitter)
+        };
+        let delay = self.config.latency + jitter;
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        if Self::draw(self.config.error_rate) {
+            Self::record(task, "error");
+            return Err(injected_error(self.config.error, task));
+        }
+        if Self::draw(self.config.malformed_json_rate) {
+            Self::record(task, "malformed_json");
+            return Err(malformed_json(task));
+        }
+        let mut response = call(self.inner.as_ref(), request).await?;
+        if Self::draw(self.config.truncation_rate) {
+            Self::record(task, "truncated");
+            truncate_response(response.get_mut());
+        }
+        Ok(response)
+    }
+}
+
+impl FaultInjection for ChaosClient {
+    fn set_enabled(&self, enabled: bool) {
+        warn!(
+            "Fault injection {}",
+            if enabled { "enabled" } else { "disabled" }
+        );
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl MightyClient for ChaosClient {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        self.inner.health_check(request).await
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        self.call(
+            Task::Embeddings,
+            request,
+            |client, request| client.embeddings(request),
+            |response| truncate_embeddings(&mut response.embeddings),
+        )
+        .await
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        self.call(
+            Task::QuestionAnswering,
+            request,
+            |client, request| client.question_answering(request),
+            |response| {
+                let half = response.answer.chars().count() / 2;
+                response.answer = response.answer.chars().take(half).collect();
+            },
+        )
+        .await
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        self.call(
+            Task::SentenceTransformers,
+            request,
+            |client, request| client.sentence_transformers(request),
+            |response| truncate_embeddings(&mut response.embeddings),
+        )
+        .await
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        self.call(
+            Task::SequenceClassification,
+            request,
+            |client, request| client.sequence_classification(request),
+            |response| truncate(&mut response.logits),
+        )
+        .await
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        self.call(
+            Task::TokenClassification,
+            request,
+            |client, request| client.token_classification(request),
+            |response| truncate(&mut response.entities),
+        )
+        .await
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        self.inner.metadata(request).await
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        self.call(
+            Task::Rerank,
+            request,
+            |client, request| client.rerank(request),
+            |response| truncate(&mut response.results),
+        )
+        .await
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        self.call(
+            Task::Embeddings,
+            request,
+            |client, request| client.embeddings_batch(request),
+            |responses| {
+                for response in responses {
+                    truncate_embeddings(&mut response.embeddings);
+                }
+            },
+        )
+        .await
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        self.call(
+            Task::TokenClassification,
+            request,
+            |client, request| client.token_classification_batch(request),
+            |responses| {
+                for response in responses {
+                    truncate(&mut response.entities);
+                }
+            },
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use crate::services::clients::mock::MockMightyClient;
+
+    use super::*;
+
+    fn text() -> Request<TextRequest> {
+        Request::new(TextRequest {
+            text: "a".to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_faults_are_injected_while_enabled() {
+        let config = ChaosConfig {
+            enabled: true,
+            truncation_rate: 1.0,
+            ..Default::default()
+        };
+        let mock = MockMightyClient::new().with_dimension(8);
+        let client = ChaosClient::new(Box::new(mock), &config);
+
+        let response = client.embeddings(text()).await.unwrap().into_inner();
+        assert_eq!(response.embeddings[0].values.len(), 4);
+
+        let client = ChaosClient::new(
+            Box::new(MockMightyClient::new()),
+            &ChaosConfig {
+                error_rate: 1.0,
+                error: ChaosError::DeadlineExceeded,
+                ..config
+            },
+        );
+        let status = client.embeddings(text()).await.unwrap_err();
+        assert_eq!(status.code(), Code::DeadlineExceeded);
+        assert!(client.health_check(Request::new(Empty {})).await.is_ok());
+
+        client.set_enabled(false);
+        assert!(client.embeddings(text()).await.is_ok());
+    }
+}
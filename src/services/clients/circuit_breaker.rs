@@ -0,0 +1,455 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use async_trait::async_trait;
+use log::{info, warn};
+use tonic::{Request, Response, Status};
+
+use crate::config::{BreakerThresholds, CircuitBreakerConfig, Task};
+use crate::proto::mighty_proto::{
+    EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse, QuestionAnswerRequest,
+    QuestionAnswerResponse, RerankRequest, RerankResponse, SentenceTransformersResponse,
+    SequenceClassificationResponse, TextRequest, TokenClassificationResponse,
+};
+use crate::services::metrics::Metrics;
+
+use super::{is_upstream_failure, MightyClient};
+
+/// Counter incremented whenever a breaker changes state, labelled with the task and new state.
+const TRANSITIONS_METRIC: &str = "mighty_circuit_breaker_transitions_total";
+
+/// Counter incremented for every call failed fast by an open breaker.
+const REJECTED_METRIC: &str = "mighty_circuit_breaker_rejected_total";
+
+/// The state of a circuit breaker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Calls go through; consecutive upstream failures are counted.
+    Closed,
+    /// Calls fail fast with `UNAVAILABLE` until the open duration elapses.
+    Open,
+    /// A limited number of probe calls go through to decide whether to close or reopen.
+    HalfOpen,
+}
+
+impl BreakerState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BreakerState::Closed => "closed",
+            BreakerState::Open => "open",
+            BreakerState::HalfOpen => "half_open",
+        }
+    }
+}
+
+/// A point-in-time view of a task's breaker, as reported by the admin `Stats` RPC.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BreakerSnapshot {
+    pub task: Task,
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+    pub rejected: u64,
+}
+
+#[derive(Debug)]
+struct BreakerInner {
+    state: BreakerState,
+    consecutive_failures: u32,
+    opened_at: Instant,
+    probes_in_flight: u32,
+    probe_successes: u32,
+}
+
+/// The circuit breaker of a single task.
+#[derive(Debug)]
+struct Breaker {
+    task: Task,
+    thresholds: BreakerThresholds,
+    inner: Mutex<BreakerInner>,
+    rejected: AtomicU64,
+}
+
+/// Admission of a call by a breaker. Releases the probe slot of a half-open breaker if the call
+/// is cancelled before its outcome is recorded.
+struct Permit<'a> {
+    breaker: &'a Breaker,
+    probe: bool,
+}
+
+impl Permit<'_> {
+    fn record(mut self, success: bool) {
+        let probe = std::mem::take(&mut self.probe);
+        self.breaker.record(probe, success);
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if self.probe {
+            self.breaker.inner.lock().unwrap().probes_in_flight -= 1;
+        }
+    }
+}
+
+impl Breaker {
+    fn new(task: Task, thresholds: BreakerThresholds) -> Self {
+        Self {
+            task,
+            thresholds,
+            inner: Mutex::new(BreakerInner {
+                state: BreakerState::Closed,
+                consecutive_failures: 0,
+                opened_at: Instant::now(),
+                probes_in_flight: 0,
+                probe_successes: 0,
+            }),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    fn transition(&self, inner: &mut BreakerInner, state: BreakerState) {
+        inner.state = state;
+        match state {
+            BreakerState::Open => {
+                inner.opened_at = Instant::now();
+                warn!(
+                    "Circuit breaker of {} opened after {} consecutive failures",
+                    self.task.as_str(),
+                    inner.consecutive_failures
+                );
+            }
+            BreakerState::HalfOpen => {
+                inner.probe_successes = 0;
+                info!("Circuit breaker of {} is half-open", self.task.as_str());
+            }
+            BreakerState::Closed => {
+                inner.consecutive_failures = 0;
+                info!("Circuit breaker of {} closed", self.task.as_str());
+            }
+        }
+        Metrics::global()
+            .counter(
+                TRANSITIONS_METRIC,
+                &[("task", self.task.as_str()), ("state", state.as_str())],
+            )
+            .increment(1);
+    }
+
+    /// Admits a call, or fails it fast with `UNAVAILABLE` while the breaker is open.
+    fn acquire(&self) -> Result<Permit<'_>, Status> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.state == BreakerState::Open
+            && inner.opened_at.elapsed() >= self.thresholds.open_duration
+        {
+            self.transition(&mut inner, BreakerState::HalfOpen);
+        }
+        let probe = match inner.state {
+            BreakerState::Closed => false,
+            BreakerState::HalfOpen if inner.probes_in_flight < self.thresholds.half_open_probes => {
+                inner.probes_in_flight += 1;
+                true
+            }
+            BreakerState::Open | BreakerState::HalfOpen => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                Metrics::global()
+                    .counter(REJECTED_METRIC, &[("task", self.task.as_str())])
+                    .increment(1);
+                return Err(Status::unavailable(format!(
+                    "The circuit breaker of {} is open",
+                    self.task.as_str()
+                )));
+            }
+        };
+        Ok(Permit {
+            breaker: self,
+            probe,
+        })
+    }
+
+    fn record(&self, probe: bool, success: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        if probe {
+            inner.probes_in_flight -= 1;
+        }
+        if success {
+            inner.consecutive_failures = 0;
+        } else {
+            inner.consecutive_failures += 1;
+        }
+        match inner.state {
+            BreakerState::Closed
+                if inner.consecutive_failures >= self.thresholds.failure_threshold =>
+            {
+                self.transition(&mut inner, BreakerState::Open);
+            }
+            BreakerState::HalfOpen if !success => {
+                self.transition(&mut inner, BreakerState::Open);
+            }
+            BreakerState::HalfOpen if probe => {
+                inner.probe_successes += 1;
+                if inner.probe_successes >= self.thresholds.half_open_probes {
+                    self.transition(&mut inner, BreakerState::Closed);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn snapshot(&self) -> BreakerSnapshot {
+        let inner = self.inner.lock().unwrap();
+        BreakerSnapshot {
+            task: self.task,
+            state: inner.state,
+            consecutive_failures: inner.consecutive_failures,
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The `CircuitBreakers` struct holds one circuit breaker per task, so a failing task (e.g.
+/// question answering) trips independently while the others keep being served. It is shared
+/// between the `CircuitBreakerClient` and the admin service reporting breaker states.
+#[derive(Debug, Default)]
+pub struct CircuitBreakers {
+    breakers: Vec<Breaker>,
+}
+
+impl CircuitBreakers {
+    /// Creates a breaker per task with its configured thresholds, or none when disabled.
+    pub fn new(config: &CircuitBreakerConfig) -> Self {
+        if !config.enabled {
+            return Self::default();
+        }
+        let breakers = Task::ALL
+            .into_iter()
+            .map(|task| Breaker::new(task, config.thresholds(task)))
+            .collect();
+        Self { breakers }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.breakers.is_empty()
+    }
+
+    fn get(&self, task: Task) -> Option<&Breaker> {
+        self.breakers.iter().find(|breaker| breaker.task == task)
+    }
+
+    /// Returns the current state of every breaker.
+    pub fn snapshots(&self) -> Vec<BreakerSnapshot> {
+        self.breakers.iter().map(Breaker::snapshot).collect()
+    }
+}
+
+/// The `CircuitBreakerClient` struct is a `MightyClient` decorator guarding each task with its
+/// own circuit breaker (see `CircuitBreakers`).
+///
+/// A breaker opens after `failure_threshold` consecutive upstream failures of its task (see
+/// `is_upstream_failure`), failing that task's calls fast with `UNAVAILABLE` for
+/// `open_duration`. It then turns half-open, letting up to `half_open_probes` probe calls
+/// through: the breaker closes once they all succeed and reopens as soon as one fails.
+///
+/// Health checks and metadata calls are not guarded.
+pub struct CircuitBreakerClient {
+    inner: Box<dyn MightyClient>,
+    breakers: Arc<CircuitBreakers>,
+}
+
+impl CircuitBreakerClient {
+    pub fn new(inner: Box<dyn MightyClient>, breakers: Arc<CircuitBreakers>) -> Self {
+        Self { inner, breakers }
+    }
+
+    async fn guard<T>(
+        &self,
+        task: Task,
+        call: impl Future<Output = Result<T, Status>>,
+    ) -> Result<T, Status> {
+        let Some(breaker) = self.breakers.get(task) else {
+            return call.await;
+        };
+        let permit = breaker.acquire()?;
+        let result = call.await;
+        permit.record(!matches!(&result, Err(status) if is_upstream_failure(status)));
+        result
+    }
+}
+
+#[async_trait]
+impl MightyClient for CircuitBreakerClient {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        self.inner.health_check(request).await
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        self.guard(Task::Embeddings, self.inner.embeddings(request))
+            .await
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        self.guard(
+            Task::QuestionAnswering,
+            self.inner.question_answering(request),
+        )
+        .await
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        self.guard(
+            Task::SentenceTransformers,
+            self.inner.sentence_transformers(request),
+        )
+        .await
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        self.guard(
+            Task::SequenceClassification,
+            self.inner.sequence_classification(request),
+        )
+        .await
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        self.guard(
+            Task::TokenClassification,
+            self.inner.token_classification(request),
+        )
+        .await
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        self.inner.metadata(request).await
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        self.guard(Task::Rerank, self.inner.rerank(request)).await
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        self.guard(Task::Embeddings, self.inner.embeddings_batch(request))
+            .await
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        self.guard(
+            Task::TokenClassification,
+            self.inner.token_classification_batch(request),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use crate::config::BreakerOverrides;
+
+    use super::*;
+
+    fn breakers() -> CircuitBreakers {
+        let config = CircuitBreakerConfig {
+            enabled: true,
+            thresholds: BreakerThresholds {
+                failure_threshold: 2,
+                open_duration: Duration::from_secs(60),
+                half_open_probes: 1,
+            },
+            tasks: HashMap::from([
+                (
+                    Task::QuestionAnswering,
+                    BreakerOverrides {
+                        open_duration: Some(Duration::ZERO),
+                        ..BreakerOverrides::default()
+                    },
+                ),
+                (
+                    Task::Rerank,
+                    BreakerOverrides {
+                        failure_threshold: Some(1),
+                        ..BreakerOverrides::default()
+                    },
+                ),
+            ]),
+        };
+        CircuitBreakers::new(&config)
+    }
+
+    fn fail(breaker: &Breaker) {
+        breaker.acquire().unwrap().record(false);
+    }
+
+    #[test]
+    fn test_tasks_trip_independently() {
+        let breakers = breakers();
+        let embeddings = breakers.get(Task::Embeddings).unwrap();
+        fail(embeddings);
+        assert_eq!(embeddings.snapshot().state, BreakerState::Closed);
+        fail(embeddings);
+        assert_eq!(embeddings.snapshot().state, BreakerState::Open);
+        assert!(embeddings.acquire().is_err());
+        assert_eq!(embeddings.snapshot().rejected, 1);
+
+        let token_classification = breakers.get(Task::TokenClassification).unwrap();
+        assert!(token_classification.acquire().is_ok());
+
+        // Per task overrides apply to their task only
+        let rerank = breakers.get(Task::Rerank).unwrap();
+        fail(rerank);
+        assert_eq!(rerank.snapshot().state, BreakerState::Open);
+    }
+
+    #[test]
+    fn test_half_open_probes_close_or_reopen() {
+        let breakers = breakers();
+        let qa = breakers.get(Task::QuestionAnswering).unwrap();
+        fail(qa);
+        fail(qa);
+
+        // The open duration elapsed: a single probe goes through
+        let probe = qa.acquire().unwrap();
+        assert_eq!(qa.snapshot().state, BreakerState::HalfOpen);
+        assert!(qa.acquire().is_err());
+        probe.record(false);
+        assert_eq!(qa.snapshot().state, BreakerState::Open);
+
+        // A cancelled probe frees its slot
+        drop(qa.acquire().unwrap());
+        qa.acquire().unwrap().record(true);
+        assert_eq!(qa.snapshot().state, BreakerState::Closed);
+    }
+}
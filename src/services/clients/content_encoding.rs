@@ -0,0 +1,286 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{self, Read};
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+
+/// The `Accept-Encoding` value advertised to the upstream server.
+pub const ACCEPT_ENCODING: &str = "gzip, deflate, br";
+
+/// The error reported when a response body exceeds the configured maximum size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodyTooLarge {
+    pub limit: u64,
+}
+
+impl fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "body exceeds the maximum size of {} bytes", self.limit)
+    }
+}
+
+impl std::error::Error for BodyTooLarge {}
+
+impl BodyTooLarge {
+    /// Returns whether `error` reports a body exceeding its maximum size.
+    pub fn is(error: &io::Error) -> bool {
+        error
+            .get_ref()
+            .is_some_and(|inner| inner.is::<BodyTooLarge>())
+    }
+}
+
+impl From<BodyTooLarge> for io::Error {
+    fn from(error: BodyTooLarge) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
+
+/// Decodes a response body according to its `Content-Encoding` header value.
+///
+/// Supports `gzip`, `deflate` and `br` (brotli) as well as stacked encodings, which are
+/// undone in reverse order of application. A missing or `identity` encoding borrows the body
+/// unchanged.
+///
+/// # Errors
+///
+/// Returns an `io::Error` if the encoding is unsupported or the body cannot be decoded.
+pub fn decode_body<'a>(
+    content_encoding: Option<&str>,
+    body: &'a [u8],
+) -> io::Result<Cow<'a, [u8]>> {
+    decode_body_limited(content_encoding, body, None)
+}
+
+/// Decodes a response body like `decode_body`, failing with a `BodyTooLarge` error as soon as
+/// the decoded body exceeds `max_size` bytes, which guards against decompression bombs.
+pub fn decode_body_limited<'a>(
+    content_encoding: Option<&str>,
+    body: &'a [u8],
+    max_size: Option<u64>,
+) -> io::Result<Cow<'a, [u8]>> {
+    let Some(content_encoding) = content_encoding else {
+        return Ok(Cow::Borrowed(body));
+    };
+
+    content_encoding
+        .split(',')
+        .map(str::trim)
+        .filter(|encoding| !encoding.is_empty())
+        .rev()
+        .try_fold(Cow::Borrowed(body), |body, encoding| {
+            decode(encoding, body, max_size)
+        })
+}
+
+/// Returns whether a body with the `Content-Encoding` header value `content_encoding` is sent as
+/// is, i.e. can be read without being decoded.
+pub fn is_identity(content_encoding: Option<&str>) -> bool {
+    content_encoding.is_none_or(|content_encoding| {
+        content_encoding
+            .split(',')
+            .map(str::trim)
+            .all(|encoding| encoding.is_empty() || encoding.eq_ignore_ascii_case("identity"))
+    })
+}
+
+/// Returns a reader decoding a response body like `decode_body_limited`, as it's read rather
+/// than into a buffer, so large bodies can be deserialized straight from their encoded bytes.
+///
+/// # Errors
+///
+/// Returns an `io::Error` if the encoding is unsupported.
+pub fn decoding_reader<'a>(
+    content_encoding: Option<&str>,
+    body: &'a [u8],
+    max_size: Option<u64>,
+) -> io::Result<DecodingReader<Box<dyn Read + 'a>>> {
+    let mut reader: Box<dyn Read + 'a> = Box::new(body);
+    for encoding in content_encoding
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|encoding| !encoding.is_empty())
+        .rev()
+    {
+        reader = match encoding.to_ascii_lowercase().as_str() {
+            "identity" => reader,
+            "gzip" | "x-gzip" => Box::new(GzDecoder::new(reader)),
+            "deflate" => Box::new(ZlibDecoder::new(reader)),
+            "br" => Box::new(brotli::Decompressor::new(reader, 4096)),
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unsupported content encoding: {}", other),
+                ))
+            }
+        };
+    }
+    Ok(DecodingReader {
+        inner: reader,
+        max_size,
+        read: 0,
+        failed: false,
+    })
+}
+
+/// The `DecodingReader` struct is a reader of a decoded body, failing with a `BodyTooLarge`
+/// error once it exceeds `max_size` bytes. It tells read errors, i.e. bodies that couldn't be
+/// decoded, apart from the errors of its consumer.
+pub struct DecodingReader<R> {
+    inner: R,
+    max_size: Option<u64>,
+    read: u64,
+    failed: bool,
+}
+
+impl<R> DecodingReader<R> {
+    /// Returns the error of a decoded body exceeding its maximum size, if it did.
+    pub fn exceeded(&self) -> Option<BodyTooLarge> {
+        self.max_size
+            .filter(|&limit| self.read > limit)
+            .map(|limit| BodyTooLarge { limit })
+    }
+
+    /// Returns whether the body couldn't be decoded.
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+}
+
+impl<R: Read> Read for DecodingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(error) = self.exceeded() {
+            return Err(error.into());
+        }
+        let read = self.inner.read(buf).inspect_err(|_| self.failed = true)?;
+        self.read += read as u64;
+        match self.exceeded() {
+            Some(error) => Err(error.into()),
+            None => Ok(read),
+        }
+    }
+}
+
+fn decode<'a>(
+    encoding: &str,
+    body: Cow<'a, [u8]>,
+    max_size: Option<u64>,
+) -> io::Result<Cow<'a, [u8]>> {
+    let encoding = encoding.to_ascii_lowercase();
+    if encoding == "identity" {
+        return Ok(body);
+    }
+
+    // Read at most one byte past the limit to detect oversized bodies without buffering them
+    let read_limit = max_size.map_or(u64::MAX, |limit| limit.saturating_add(1));
+    let mut decoded = Vec::with_capacity(body.len() * 4);
+    match encoding.as_str() {
+        "gzip" | "x-gzip" => GzDecoder::new(&*body)
+            .take(read_limit)
+            .read_to_end(&mut decoded)?,
+        "deflate" => ZlibDecoder::new(&*body)
+            .take(read_limit)
+            .read_to_end(&mut decoded)?,
+        "br" => brotli::Decompressor::new(&*body, 4096)
+            .take(read_limit)
+            .read_to_end(&mut decoded)?,
+        other => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported content encoding: {}", other),
+            ))
+        }
+    };
+    if let Some(limit) = max_size.filter(|&limit| decoded.len() as u64 > limit) {
+        return Err(BodyTooLarge { limit }.into());
+    }
+    Ok(Cow::Owned(decoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+
+    use super::*;
+
+    const BODY: &[u8] = br#"{"outputs": [[0.1, 0.2, 0.3]], "took": 9}"#;
+
+    #[test]
+    fn test_decode_identity() {
+        assert_eq!(decode_body(None, BODY).unwrap(), BODY);
+        assert_eq!(decode_body(Some("identity"), BODY).unwrap(), BODY);
+    }
+
+    #[test]
+    fn test_decode_gzip() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(BODY).unwrap();
+        let encoded = encoder.finish().unwrap();
+
+        assert_eq!(decode_body(Some("gzip"), &encoded).unwrap(), BODY);
+    }
+
+    #[test]
+    fn test_decode_deflate() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(BODY).unwrap();
+        let encoded = encoder.finish().unwrap();
+
+        assert_eq!(decode_body(Some("deflate"), &encoded).unwrap(), BODY);
+    }
+
+    #[test]
+    fn test_decode_brotli() {
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = brotli::CompressorWriter::new(&mut encoded, 4096, 5, 22);
+            encoder.write_all(BODY).unwrap();
+        }
+
+        assert_eq!(decode_body(Some("br"), &encoded).unwrap(), BODY);
+    }
+
+    #[test]
+    fn test_decode_limited() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[b' '; 4096]).unwrap();
+        let encoded = encoder.finish().unwrap();
+
+        assert!(decode_body_limited(Some("gzip"), &encoded, Some(4096)).is_ok());
+        let error = decode_body_limited(Some("gzip"), &encoded, Some(1024)).unwrap_err();
+        assert!(BodyTooLarge::is(&error));
+    }
+
+    #[test]
+    fn test_decoding_reader() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(BODY).unwrap();
+        let encoded = encoder.finish().unwrap();
+        assert!(!is_identity(Some("gzip")));
+        assert!(is_identity(Some("identity")));
+
+        let mut decoded = Vec::new();
+        let mut reader = decoding_reader(Some("gzip"), &encoded, None).unwrap();
+        reader.read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, BODY);
+
+        let mut reader = decoding_reader(Some("gzip"), &encoded, Some(16)).unwrap();
+        let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(BodyTooLarge::is(&error));
+        assert_eq!(reader.exceeded(), Some(BodyTooLarge { limit: 16 }));
+
+        let mut reader = decoding_reader(Some("gzip"), BODY, None).unwrap();
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+        assert!(reader.failed());
+        assert!(decoding_reader(Some("compress"), BODY, None).is_err());
+    }
+
+    #[test]
+    fn test_decode_unsupported() {
+        assert!(decode_body(Some("compress"), BODY).is_err());
+    }
+}
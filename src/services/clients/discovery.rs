@@ -0,0 +1,98 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use reqwest::Url;
+
+use super::MightyClient;
+
+/// Creates the client used to reach a single discovered upstream endpoint.
+pub type ClientFactory = Arc<dyn Fn(&Endpoint) -> Box<dyn MightyClient> + Send + Sync>;
+
+/// An upstream endpoint: a configured base URL, optionally pinned to one of the addresses its
+/// hostname resolved to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Endpoint {
+    /// The configured base URL.
+    pub base_url: String,
+    /// The resolved address requests are sent to, or `None` to let the HTTP client resolve the
+    /// hostname itself.
+    pub addr: Option<SocketAddr>,
+}
+
+impl Endpoint {
+    pub fn new(base_url: String) -> Self {
+        Self {
+            base_url,
+            addr: None,
+        }
+    }
+
+    /// A unique, human-readable name for the endpoint used in logs.
+    pub fn name(&self) -> String {
+        match self.addr {
+            Some(addr) => format!("{} ({})", self.base_url, addr),
+            None => self.base_url.clone(),
+        }
+    }
+}
+
+/// Resolves the hostname of `base_url` into one endpoint per address, e.g. one per pod behind
+/// a headless Kubernetes service. Base URLs whose host is an IP literal, and Unix domain socket
+/// base URLs, resolve to themselves.
+///
+/// # Errors
+///
+/// Returns an `io::Error` if the base URL is invalid or the hostname cannot be resolved.
+pub async fn resolve(base_url: &str) -> io::Result<Vec<Endpoint>> {
+    if base_url.starts_with("unix://") {
+        return Ok(vec![Endpoint::new(base_url.to_string())]);
+    }
+    let url = Url::parse(base_url).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let host = url.host_str().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Base URL {} has no host", base_url),
+        )
+    })?;
+    if host.trim_matches(['[', ']']).parse::<IpAddr>().is_ok() {
+        return Ok(vec![Endpoint::new(base_url.to_string())]);
+    }
+
+    let port = url.port_or_known_default().unwrap_or(80);
+    let mut addrs = tokio::net::lookup_host((host, port))
+        .await?
+        .collect::<Vec<_>>();
+    addrs.sort();
+    addrs.dedup();
+    Ok(addrs
+        .into_iter()
+        .map(|addr| Endpoint {
+            base_url: base_url.to_string(),
+            addr: Some(addr),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resolve_ip_literal() {
+        let endpoints = resolve("http://127.0.0.1:5050").await.unwrap();
+        assert_eq!(
+            endpoints,
+            vec![Endpoint::new("http://127.0.0.1:5050".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_hostname() {
+        let endpoints = resolve("http://localhost:5050").await.unwrap();
+        assert!(!endpoints.is_empty());
+        assert!(endpoints
+            .iter()
+            .all(|endpoint| endpoint.addr.unwrap().port() == 5050));
+    }
+}
@@ -0,0 +1,289 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use log::warn;
+use tonic::{Code, Request, Response, Status};
+
+use crate::proto::mighty_proto::{
+    EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse, QuestionAnswerRequest,
+    QuestionAnswerResponse, RerankRequest, RerankResponse, SentenceTransformersResponse,
+    SequenceClassificationResponse, TextRequest, TokenClassificationResponse,
+};
+use crate::services::context::RequestContext;
+use crate::services::metrics::Metrics;
+
+use super::MightyClient;
+
+/// Counter of calls answered by a fallback backend, by call and backend.
+const FALLBACK_METRIC: &str = "mighty_fallback_requests_total";
+
+/// Returns whether a call failing with `status` is retried against the next backend.
+fn should_fall_back(status: &Status) -> bool {
+    matches!(status.code(), Code::Unavailable | Code::DeadlineExceeded)
+}
+
+/// The `FallbackClient` struct is a `MightyClient` decorator trying a chain of backends in
+/// order, e.g. a remote Mighty server, then a local ONNX model: a call failing with
+/// `UNAVAILABLE` or `DEADLINE_EXCEEDED` on a backend, or taking longer than the attempt
+/// timeout, is transparently retried against the next one. Other errors, such as invalid
+/// arguments, are returned as is, as are the errors of the last backend.
+///
+/// No backend is tried once the deadline of the call has passed.
+pub struct FallbackClient {
+    /// The backends, the primary first, along with their names.
+    backends: Vec<(String, Box<dyn MightyClient>)>,
+    attempt_timeout: Option<Duration>,
+}
+
+impl FallbackClient {
+    /// Creates a client trying `backends` in order, each attempt being bounded by
+    /// `attempt_timeout` when set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `backends` is empty.
+    pub fn new(
+        backends: Vec<(String, Box<dyn MightyClient>)>,
+        attempt_timeout: Option<Duration>,
+    ) -> Self {
+        assert!(!backends.is_empty(), "a fallback chain needs a backend");
+        Self {
+            backends,
+            attempt_timeout,
+        }
+    }
+
+    /// Makes the `call` named `name` against each backend in turn, until one doesn't fail with
+    /// an error worth falling back from.
+    async fn call<R, T, F>(
+        &self,
+        name: &str,
+        request: Request<R>,
+        call: F,
+    ) -> Result<Response<T>, Status>
+    where
+        R: Clone + Send,
+        F: for<'c> Fn(
+            &'c dyn MightyClient,
+            Request<R>,
+        ) -> futures::future::BoxFuture<'c, Result<Response<T>, Status>>,
+    {
+        let context = RequestContext::get(&request).cloned();
+        let message = request.into_inner();
+        let mut last_error = None;
+        for (index, (backend, client)) in self.backends.iter().enumerate() {
+            if let Some(status) = &last_error {
+                if context
+                    .as_ref()
+                    .and_then(RequestContext::remaining)
+                    .is_some_and(|remaining| remaining.is_zero())
+                {
+                    break;
+                }
+                warn!(
+                    "{} call failed on {}, falling back to {}: {}",
+                    name,
+                    self.backends[index - 1].0,
+                    backend,
+                    status
+                );
+            }
+            let mut request = Request::new(message.clone());
+            if let Some(context) = &context {
+                request.extensions_mut().insert(context.clone());
+            }
+            let attempt = call(client.as_ref(), request);
+            let result = match self.attempt_timeout {
+                Some(timeout) => {
+                    tokio::time::timeout(timeout, attempt)
+                        .await
+                        .unwrap_or_else(|_| {
+                            Err(Status::deadline_exceeded(format!(
+                                "{} timed out after {:?}",
+                                backend, timeout
+                            )))
+                        })
+                }
+                None => attempt.await,
+            };
+            match result {
+                Err(status) if should_fall_back(&status) => last_error = Some(status),
+                result => {
+                    if index > 0 {
+                        Metrics::global()
+                            .counter(FALLBACK_METRIC, &[("call", name), ("backend", backend)])
+                            .increment(1);
+                    }
+                    return result;
+                }
+            }
+        }
+        Err(last_error.expect("the chain has a backend"))
+    }
+}
+
+#[async_trait]
+impl MightyClient for FallbackClient {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        self.call("health_check", request, |client, request| {
+            client.health_check(request)
+        })
+        .await
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        self.call("embeddings", request, |client, request| {
+            client.embeddings(request)
+        })
+        .await
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        self.call("question_answering", request, |client, request| {
+            client.question_answering(request)
+        })
+        .await
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        self.call("sentence_transformers", request, |client, request| {
+            client.sentence_transformers(request)
+        })
+        .await
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        self.call("sequence_classification", request, |client, request| {
+            client.sequence_classification(request)
+        })
+        .await
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        self.call("token_classification", request, |client, request| {
+            client.token_classification(request)
+        })
+        .await
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        self.call("metadata", request, |client, request| {
+            client.metadata(request)
+        })
+        .await
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        self.call("rerank", request, |client, request| client.rerank(request))
+            .await
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        self.call("embeddings_batch", request, |client, request| {
+            client.embeddings_batch(request)
+        })
+        .await
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        self.call("token_classification_batch", request, |client, request| {
+            client.token_classification_batch(request)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::config::Task;
+    use crate::services::clients::mock::MockMightyClient;
+
+    use super::*;
+
+    fn text(text: &str) -> Request<TextRequest> {
+        Request::new(TextRequest {
+            text: text.to_string(),
+        })
+    }
+
+    fn chain(backends: &[Arc<MockMightyClient>]) -> FallbackClient {
+        let backends = backends
+            .iter()
+            .enumerate()
+            .map(|(index, backend)| {
+                let client: Arc<dyn MightyClient> = backend.clone();
+                (index.to_string(), Box::new(client) as Box<dyn MightyClient>)
+            })
+            .collect();
+        FallbackClient::new(backends, Some(Duration::from_millis(20)))
+    }
+
+    #[tokio::test]
+    async fn test_unavailable_backends_fall_back_in_order() {
+        let primary = Arc::new(
+            MockMightyClient::new()
+                .with_failures(Task::Embeddings, 1, Status::unavailable("down"))
+                .with_error(Task::TokenClassification, Status::invalid_argument("bad")),
+        );
+        let secondary = Arc::new(MockMightyClient::new());
+        let client = chain(&[primary.clone(), secondary.clone()]);
+
+        assert!(client.embeddings(text("a")).await.is_ok());
+        assert!(client.embeddings(text("a")).await.is_ok());
+        assert_eq!(primary.calls(Task::Embeddings), 2);
+        assert_eq!(secondary.calls(Task::Embeddings), 1);
+
+        // Errors caused by the request aren't retried
+        let status = client.token_classification(text("a")).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(secondary.calls(Task::TokenClassification), 0);
+    }
+
+    #[tokio::test]
+    async fn test_slow_attempts_fall_back() {
+        let slow = Arc::new(MockMightyClient::new().with_latency(Duration::from_millis(100)));
+        let local = Arc::new(MockMightyClient::new().with_dimension(8));
+        let client = chain(&[slow.clone(), slow, local]);
+
+        let response = client.embeddings(text("a")).await.unwrap().into_inner();
+        assert_eq!(response.embeddings[0].values.len(), 8);
+
+        let client = chain(&[Arc::new(
+            MockMightyClient::new().with_latency(Duration::from_millis(100)),
+        )]);
+        let status = client.embeddings(text("a")).await.unwrap_err();
+        assert_eq!(status.code(), Code::DeadlineExceeded);
+    }
+}
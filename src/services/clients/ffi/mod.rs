@@ -0,0 +1,178 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use log::debug;
+use serde_json::{json, Value};
+use tonic::{Request, Response, Status};
+
+use crate::config::FfiConfig;
+use crate::proto::mighty_proto::{
+    EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse, QuestionAnswerRequest,
+    QuestionAnswerResponse, SentenceTransformersResponse, SequenceClassificationResponse,
+    TextRequest, TokenClassificationResponse,
+};
+use crate::services::clients::json_response_converters::{
+    json_to_embeddings_batch_response, json_to_embeddings_response, json_to_metadata_response,
+    json_to_question_answer_response, json_to_sentence_transformers_response,
+    json_to_sequence_classification_response, json_to_token_classification_batch_response,
+    json_to_token_classification_response,
+};
+
+use super::MightyClient;
+
+mod sys;
+
+/// The `FfiClient` struct implements the `MightyClient` trait by calling into the Mighty shared
+/// library in-process, avoiding both a separate process and HTTP round trips for minimum
+/// latency.
+///
+/// All unsafe code lives in the `sys` module; this client only deals with its safe `Library`
+/// wrapper. Inference calls block, so they run on Tokio's blocking thread pool. The library
+/// answers in the JSON format of the Mighty REST API, converted like REST responses, strictly
+/// with `strict_responses`.
+pub struct FfiClient {
+    library: Arc<sys::Library>,
+    strict_responses: bool,
+}
+
+impl FfiClient {
+    /// Loads the shared library configured in `config` and initializes its models.
+    pub fn open(config: &FfiConfig) -> Result<Self, String> {
+        let library = sys::Library::open(&config.library, config.model_dir.as_deref())?;
+        Ok(Self {
+            library: Arc::new(library),
+            strict_responses: config.strict_responses,
+        })
+    }
+
+    /// Runs `task` on `input` in the library and parses its JSON response.
+    async fn infer(&self, task: &'static str, input: Value) -> Result<Value, Status> {
+        debug!("Running {} in the Mighty library", task);
+        let library = self.library.clone();
+        let output = tokio::task::spawn_blocking(move || library.infer(task, &input.to_string()))
+            .await
+            .map_err(|e| Status::internal(format!("Mighty library call panicked: {}", e)))?
+            .map_err(Status::internal)?;
+        parse(&output)
+    }
+
+    /// Runs `task` on each of `texts` in turn, in a single blocking call, and returns the JSON
+    /// array of their responses.
+    async fn infer_batch(&self, task: &'static str, texts: Vec<String>) -> Result<Value, Status> {
+        debug!(
+            "Running {} in the Mighty library on {} texts",
+            task,
+            texts.len()
+        );
+        let library = self.library.clone();
+        let outputs = tokio::task::spawn_blocking(move || {
+            texts
+                .iter()
+                .map(|text| library.infer(task, &json!({ "text": text }).to_string()))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .await
+        .map_err(|e| Status::internal(format!("Mighty library call panicked: {}", e)))?
+        .map_err(Status::internal)?;
+        outputs.iter().map(|output| parse(output)).collect()
+    }
+}
+
+fn parse(output: &str) -> Result<Value, Status> {
+    serde_json::from_str(output).map_err(|e| {
+        Status::internal(format!(
+            "Invalid JSON returned by the Mighty library: {}",
+            e
+        ))
+    })
+}
+
+#[async_trait]
+impl MightyClient for FfiClient {
+    async fn health_check(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        // The library is initialized once loaded
+        Ok(Response::new(HealthcheckResponse { success: true }))
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        let text = request.into_inner().text;
+        let json = self.infer("embeddings", json!({ "text": text })).await?;
+        json_to_embeddings_response(&json, self.strict_responses).map(Response::new)
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        let req = request.into_inner();
+        let input = json!({ "question": req.question, "context": req.context });
+        let json = self.infer("question-answering", input).await?;
+        json_to_question_answer_response(&json, req.question, req.context, self.strict_responses)
+            .map(Response::new)
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        let text = request.into_inner().text;
+        let json = self
+            .infer("sentence-transformers", json!({ "text": text }))
+            .await?;
+        json_to_sentence_transformers_response(&json, self.strict_responses).map(Response::new)
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        let text = request.into_inner().text;
+        let json = self
+            .infer("sequence-classification", json!({ "text": text }))
+            .await?;
+        json_to_sequence_classification_response(&json, self.strict_responses).map(Response::new)
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        let text = request.into_inner().text;
+        let json = self
+            .infer("token-classification", json!({ "text": text }))
+            .await?;
+        json_to_token_classification_response(&json, self.strict_responses).map(Response::new)
+    }
+
+    async fn metadata(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        let json = self.infer("metadata", json!({})).await?;
+        json_to_metadata_response(&json).map(Response::new)
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        let json = self.infer_batch("embeddings", request.into_inner()).await?;
+        json_to_embeddings_batch_response(&json, self.strict_responses).map(Response::new)
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        let json = self
+            .infer_batch("token-classification", request.into_inner())
+            .await?;
+        json_to_token_classification_batch_response(&json, self.strict_responses).map(Response::new)
+    }
+}
@@ -0,0 +1,172 @@
+//! The unsafe boundary with the Mighty shared library.
+//!
+//! Every call into the library goes through this module, which loads it at runtime with
+//! `dlopen` (so builds don't require the library to be present) and exposes a safe `Library`
+//! wrapper. The library is expected to export the following C ABI:
+//!
+//! ```c
+//! // Loads the models from `model_dir` (may be NULL); returns NULL on failure.
+//! void *mighty_init(const char *model_dir);
+//! // Runs `task` (e.g. "embeddings", "question-answering") on the JSON `input` and stores a
+//! // NUL-terminated JSON response (on success, returning 0) or error message (otherwise) in
+//! // `output`, to be released with `mighty_free_string`. Must be safe to call concurrently.
+//! int mighty_infer(void *engine, const char *task, const char *input, char **output);
+//! void mighty_free_string(char *string);
+//! void mighty_free(void *engine);
+//! ```
+//!
+//! Responses use the same JSON format as the Mighty REST API.
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::ptr;
+
+type InitFn = unsafe extern "C" fn(*const c_char) -> *mut c_void;
+type InferFn =
+    unsafe extern "C" fn(*mut c_void, *const c_char, *const c_char, *mut *mut c_char) -> c_int;
+type FreeStringFn = unsafe extern "C" fn(*mut c_char);
+type FreeFn = unsafe extern "C" fn(*mut c_void);
+
+/// A loaded Mighty shared library along with its initialized inference engine.
+pub struct Library {
+    handle: *mut c_void,
+    engine: *mut c_void,
+    infer: InferFn,
+    free_string: FreeStringFn,
+    free: FreeFn,
+}
+
+// SAFETY: the engine is only used through `mighty_infer`, which the ABI requires to be safe to
+// call concurrently, and is released once, on drop.
+unsafe impl Send for Library {}
+unsafe impl Sync for Library {}
+
+fn c_string(value: &[u8]) -> Result<CString, String> {
+    CString::new(value).map_err(|_| "Unexpected NUL byte in FFI argument".to_string())
+}
+
+/// Returns the message of the last `dlopen`/`dlsym` error.
+fn dl_error() -> String {
+    // SAFETY: `dlerror` returns NULL or a NUL-terminated string valid until the next call
+    unsafe {
+        let error = libc::dlerror();
+        if error.is_null() {
+            "unknown dynamic linker error".to_string()
+        } else {
+            CStr::from_ptr(error).to_string_lossy().into_owned()
+        }
+    }
+}
+
+/// Resolves `name` in the library `handle`.
+///
+/// # Safety
+///
+/// `T` must be the function pointer type matching the symbol's C signature.
+unsafe fn symbol<T: Copy>(handle: *mut c_void, name: &CStr) -> Result<T, String> {
+    let symbol = libc::dlsym(handle, name.as_ptr());
+    if symbol.is_null() {
+        return Err(format!(
+            "Missing symbol {}: {}",
+            name.to_string_lossy(),
+            dl_error()
+        ));
+    }
+    Ok(std::mem::transmute_copy(&symbol))
+}
+
+impl Library {
+    /// Loads the library at `path` and initializes its engine with the models of `model_dir`.
+    pub fn open(path: &Path, model_dir: Option<&Path>) -> Result<Self, String> {
+        let path = c_string(path.as_os_str().as_bytes())?;
+        let model_dir = model_dir
+            .map(|dir| c_string(dir.as_os_str().as_bytes()))
+            .transpose()?;
+
+        // SAFETY: the symbols are resolved with the signatures of the documented ABI, and the
+        // library handle is closed if initialization fails
+        unsafe {
+            let handle = libc::dlopen(path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+            if handle.is_null() {
+                return Err(format!("Failed to load the Mighty library: {}", dl_error()));
+            }
+            let resolved = (|| {
+                Ok::<_, String>((
+                    symbol::<InitFn>(handle, c"mighty_init")?,
+                    symbol::<InferFn>(handle, c"mighty_infer")?,
+                    symbol::<FreeStringFn>(handle, c"mighty_free_string")?,
+                    symbol::<FreeFn>(handle, c"mighty_free")?,
+                ))
+            })();
+            let (init, infer, free_string, free) = match resolved {
+                Ok(symbols) => symbols,
+                Err(e) => {
+                    libc::dlclose(handle);
+                    return Err(e);
+                }
+            };
+
+            let engine = init(model_dir.as_ref().map_or(ptr::null(), |dir| dir.as_ptr()));
+            if engine.is_null() {
+                libc::dlclose(handle);
+                return Err("The Mighty library failed to initialize".to_string());
+            }
+            Ok(Self {
+                handle,
+                engine,
+                infer,
+                free_string,
+                free,
+            })
+        }
+    }
+
+    /// Runs `task` on the JSON `input`, returning the JSON response or the library's error
+    /// message.
+    pub fn infer(&self, task: &str, input: &str) -> Result<String, String> {
+        let task = c_string(task.as_bytes())?;
+        let input = c_string(input.as_bytes())?;
+        let mut output: *mut c_char = ptr::null_mut();
+
+        // SAFETY: the engine is alive for the lifetime of `self`, the arguments are valid
+        // NUL-terminated strings, and `output` is copied before being released by the library
+        unsafe {
+            let code = (self.infer)(self.engine, task.as_ptr(), input.as_ptr(), &mut output);
+            let message = if output.is_null() {
+                String::new()
+            } else {
+                let message = CStr::from_ptr(output).to_string_lossy().into_owned();
+                (self.free_string)(output);
+                message
+            };
+            match code {
+                0 => Ok(message),
+                code => Err(format!("Mighty library error {}: {}", code, message)),
+            }
+        }
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        // SAFETY: the engine and handle are valid and released exactly once
+        unsafe {
+            (self.free)(self.engine);
+            libc::dlclose(self.handle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_missing_library_fails() {
+        let error = Library::open(Path::new("/nonexistent/libmighty.so"), None)
+            .err()
+            .unwrap();
+        assert!(error.starts_with("Failed to load the Mighty library"));
+    }
+}
@@ -0,0 +1,441 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use log::warn;
+use tonic::codegen::http::Uri;
+use tonic::metadata::MetadataValue;
+use tonic::transport::{Channel, Endpoint as ChannelEndpoint};
+use tonic::{Code, Request, Response, Status};
+
+use crate::config::MightyServerConfig;
+use crate::proto::mighty_proto::mighty_inference_client::MightyInferenceClient;
+use crate::proto::mighty_proto::{
+    EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse, QuestionAnswerRequest,
+    QuestionAnswerResponse, RerankRequest, RerankResponse, SentenceTransformersResponse,
+    SequenceClassificationResponse, TextRequest, TokenClassificationResponse,
+};
+use crate::services::clients::discovery::Endpoint;
+use crate::services::clients::rest::user_agent;
+use crate::services::context::{
+    RequestContext, DEBUG_HEADER, PRIORITY_HEADER, REQUEST_ID_HEADER, TENANT_HEADER,
+};
+use crate::services::metrics::Metrics;
+
+use super::{one_by_one, MightyClient};
+
+/// The scheme of base URLs served by a `MightyGrpcUpstreamClient`.
+pub const GRPC_SCHEME: &str = "grpc://";
+
+/// Counter of channels ejected from a pool after consecutive failures.
+const CHANNEL_EJECTIONS_METRIC: &str = "mighty_grpc_upstream_channel_ejections_total";
+
+/// Metadata key carrying the chain of client addresses.
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Returns whether `base_url` designates a gRPC upstream, e.g. `grpc://central-gateway:50051`.
+pub fn is_grpc_url(base_url: &str) -> bool {
+    base_url.starts_with(GRPC_SCHEME)
+}
+
+/// One HTTP/2 channel of the pool, along with its health.
+struct PooledChannel {
+    client: MightyInferenceClient<Channel>,
+    consecutive_failures: AtomicU32,
+    ejected_until: Mutex<Option<Instant>>,
+}
+
+impl PooledChannel {
+    fn is_ejected(&self, now: Instant) -> bool {
+        self.ejected_until
+            .lock()
+            .unwrap()
+            .is_some_and(|until| now < until)
+    }
+}
+
+/// The `MightyGrpcUpstreamClient` struct implements the `MightyClient` trait by calling another
+/// mighty-grpc instance, or any server implementing `MightyInference`, over gRPC. It enables
+/// tiered deployments, e.g. edge gateways adding authentication and caching in front of a
+/// central inference gateway.
+///
+/// Calls are spread round-robin over a pool of `pool.grpc_channels` HTTP/2 channels, as a
+/// single connection's flow-control window limits the throughput of large embedding payloads.
+/// A channel failing `health_check.unhealthy_threshold` consecutive calls with `UNAVAILABLE` is
+/// skipped for `health_check.interval`, after which it is tried again.
+///
+/// The request id, tenant, priority, debug flags and remaining deadline of each call are
+/// forwarded upstream, along with `x-forwarded-for` when `forward_client_address` is set.
+///
+/// Upstreams are called in plaintext HTTP/2, without credentials: `grpc://` base URLs are meant
+/// for trusted networks, e.g. between the tiers of a deployment, and `grpcs://` isn't supported.
+pub struct MightyGrpcUpstreamClient {
+    base_url: String,
+    channels: Vec<PooledChannel>,
+    next: AtomicUsize,
+    unhealthy_threshold: u32,
+    ejection: Duration,
+    forward_client_address: bool,
+}
+
+impl MightyGrpcUpstreamClient {
+    /// Creates a client for the `grpc://host:port` upstream at `base_url`. Channels connect
+    /// lazily, on their first call.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `base_url` isn't a valid `grpc://` URL, which the configuration lint reports at
+    /// startup. Must be called from within a Tokio runtime.
+    pub fn with_config(base_url: String, config: &MightyServerConfig) -> Self {
+        Self::connect(base_url, config, None)
+    }
+
+    /// Creates a client for a discovered endpoint, whose channels connect to its resolved
+    /// address if any while keeping the base URL host as the `:authority`.
+    pub fn for_endpoint(endpoint: &Endpoint, config: &MightyServerConfig) -> Self {
+        Self::connect(endpoint.base_url.clone(), config, endpoint.addr)
+    }
+
+    fn connect(
+        base_url: String,
+        config: &MightyServerConfig,
+        addr: Option<std::net::SocketAddr>,
+    ) -> Self {
+        let authority = base_url.strip_prefix(GRPC_SCHEME).unwrap_or_else(|| {
+            panic!(
+                "gRPC upstream URL {} must start with {}",
+                base_url, GRPC_SCHEME
+            )
+        });
+        let origin = format!("http://{}", authority);
+        let target = match addr {
+            Some(addr) => format!("http://{}", addr),
+            None => origin.clone(),
+        };
+        let mut endpoint = ChannelEndpoint::from_shared(target)
+            .unwrap_or_else(|e| panic!("Invalid gRPC upstream URL {}: {}", base_url, e))
+            .http2_adaptive_window(true);
+        if addr.is_some() {
+            let origin: Uri = origin
+                .parse()
+                .unwrap_or_else(|e| panic!("Invalid gRPC upstream URL {}: {}", base_url, e));
+            endpoint = endpoint.origin(origin);
+        }
+        endpoint = match endpoint.clone().user_agent(user_agent(config)) {
+            Ok(endpoint) => endpoint,
+            Err(e) => {
+                warn!("Invalid user agent, using the default: {}", e);
+                endpoint
+            }
+        };
+
+        let channels = (0..config.pool.grpc_channels.unwrap_or(1).max(1))
+            .map(|_| {
+                let mut client = MightyInferenceClient::new(endpoint.connect_lazy());
+                if let Some(limit) = config.max_body_size {
+                    client = client.max_decoding_message_size(limit as usize);
+                }
+                PooledChannel {
+                    client,
+                    consecutive_failures: AtomicU32::new(0),
+                    ejected_until: Mutex::new(None),
+                }
+            })
+            .collect();
+        Self {
+            base_url,
+            channels,
+            next: AtomicUsize::new(0),
+            unhealthy_threshold: config.health_check.unhealthy_threshold.max(1),
+            ejection: config.health_check.interval,
+            forward_client_address: config.forward_client_address,
+        }
+    }
+
+    /// Returns the index of the next channel in round-robin order that isn't ejected, or of
+    /// the next channel at all when every channel is ejected.
+    fn pick(&self) -> usize {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        (0..self.channels.len())
+            .map(|offset| (start + offset) % self.channels.len())
+            .find(|&index| !self.channels[index].is_ejected(now))
+            .unwrap_or(start % self.channels.len())
+    }
+
+    /// Records the outcome of a call on the channel at `index`, ejecting the channel after
+    /// `unhealthy_threshold` consecutive `UNAVAILABLE` failures.
+    fn record(&self, index: usize, code: Code) {
+        let channel = &self.channels[index];
+        if code != Code::Unavailable {
+            channel.consecutive_failures.store(0, Ordering::Relaxed);
+            return;
+        }
+        let failures = channel.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= self.unhealthy_threshold {
+            channel.consecutive_failures.store(0, Ordering::Relaxed);
+            *channel.ejected_until.lock().unwrap() = Some(Instant::now() + self.ejection);
+            warn!(
+                "Channel {} to {} failed {} consecutive calls, skipping it for {:?}",
+                index, self.base_url, failures, self.ejection
+            );
+            Metrics::global()
+                .counter(CHANNEL_EJECTIONS_METRIC, &[("upstream", &self.base_url)])
+                .increment(1);
+        }
+    }
+
+    /// Builds the upstream request for `request`, forwarding its context as metadata.
+    fn outgoing<T>(&self, request: Request<T>) -> Request<T> {
+        let context = RequestContext::get(&request).cloned();
+        let forwarded_for = request
+            .metadata()
+            .get(X_FORWARDED_FOR)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let mut outgoing = Request::new(request.into_inner());
+        let Some(context) = context else {
+            return outgoing;
+        };
+
+        let metadata = outgoing.metadata_mut();
+        let mut insert = |key: &'static str, value: &str| {
+            if let Ok(value) = MetadataValue::try_from(value) {
+                metadata.insert(key, value);
+            }
+        };
+        insert(REQUEST_ID_HEADER, &context.request_id);
+        if let Some(tenant) = &context.tenant {
+            insert(TENANT_HEADER, tenant);
+        }
+        insert(
+            PRIORITY_HEADER,
+            &format!("{:?}", context.priority).to_ascii_lowercase(),
+        );
+        if context.raw_json {
+            insert(DEBUG_HEADER, "raw-json");
+        }
+        if self.forward_client_address {
+            let peer = context.peer_addr.map(|peer| peer.ip().to_string());
+            let value = match (forwarded_for, peer) {
+                (Some(forwarded_for), Some(peer)) => Some(format!("{}, {}", forwarded_for, peer)),
+                (forwarded_for, peer) => forwarded_for.or(peer),
+            };
+            if let Some(value) = value {
+                insert(X_FORWARDED_FOR, &value);
+            }
+        }
+        if let Some(remaining) = context.remaining() {
+            outgoing.set_timeout(remaining);
+        }
+        outgoing
+    }
+
+    /// Sends `request` through the next channel of the pool, recording the outcome.
+    async fn call<T, R, F, Fut>(&self, request: Request<T>, call: F) -> Result<Response<R>, Status>
+    where
+        F: FnOnce(MightyInferenceClient<Channel>, Request<T>) -> Fut,
+        Fut: Future<Output = Result<Response<R>, Status>>,
+    {
+        let index = self.pick();
+        let result = call(self.channels[index].client.clone(), self.outgoing(request)).await;
+        self.record(
+            index,
+            result.as_ref().map_or_else(Status::code, |_| Code::Ok),
+        );
+        result
+    }
+
+    /// Sends one request per text of `request` concurrently through the next channel of the pool,
+    /// recording the outcome of the batch as a whole.
+    async fn call_batch<R, F, Fut>(
+        &self,
+        request: Request<Vec<String>>,
+        call: F,
+    ) -> Result<Response<Vec<R>>, Status>
+    where
+        F: Fn(MightyInferenceClient<Channel>, Request<TextRequest>) -> Fut,
+        Fut: Future<Output = Result<Response<R>, Status>>,
+    {
+        let index = self.pick();
+        let client = &self.channels[index].client;
+        let result = one_by_one(request, |request| {
+            call(client.clone(), self.outgoing(request))
+        })
+        .await;
+        self.record(
+            index,
+            result.as_ref().map_or_else(Status::code, |_| Code::Ok),
+        );
+        result
+    }
+}
+
+#[async_trait]
+impl MightyClient for MightyGrpcUpstreamClient {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        self.call(request, |mut client, request| async move {
+            client.health_check(request).await
+        })
+        .await
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        self.call(request, |mut client, request| async move {
+            client.embeddings(request).await
+        })
+        .await
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        self.call(request, |mut client, request| async move {
+            client.question_answering(request).await
+        })
+        .await
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        self.call(request, |mut client, request| async move {
+            client.sentence_transformers(request).await
+        })
+        .await
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        self.call(request, |mut client, request| async move {
+            client.sequence_classification(request).await
+        })
+        .await
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        self.call(request, |mut client, request| async move {
+            client.token_classification(request).await
+        })
+        .await
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        self.call(request, |mut client, request| async move {
+            client.metadata(request).await
+        })
+        .await
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        self.call(request, |mut client, request| async move {
+            client.rerank(request).await
+        })
+        .await
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        self.call_batch(request, |mut client, request| async move {
+            client.embeddings(request).await
+        })
+        .await
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        self.call_batch(request, |mut client, request| async move {
+            client.token_classification(request).await
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::context::Priority;
+
+    use super::*;
+
+    fn client(channels: usize) -> MightyGrpcUpstreamClient {
+        let mut config = MightyServerConfig::default();
+        config.pool.grpc_channels = Some(channels);
+        config.health_check.unhealthy_threshold = 2;
+        config.forward_client_address = true;
+        MightyGrpcUpstreamClient::with_config("grpc://127.0.0.1:1".to_string(), &config)
+    }
+
+    #[tokio::test]
+    async fn test_round_robin_skips_ejected_channels() {
+        let client = client(3);
+        assert_eq!(
+            (0..4).map(|_| client.pick()).collect::<Vec<_>>(),
+            vec![0, 1, 2, 0]
+        );
+
+        client.record(1, Code::Unavailable);
+        client.record(1, Code::InvalidArgument);
+        client.record(1, Code::Unavailable);
+        assert!(!client.channels[1].is_ejected(Instant::now()));
+        client.record(1, Code::Unavailable);
+        assert!(client.channels[1].is_ejected(Instant::now()));
+        assert_eq!(
+            (0..4).map(|_| client.pick()).collect::<Vec<_>>(),
+            vec![2, 2, 0, 2]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_outgoing_forwards_the_context() {
+        let client = client(1);
+        let mut request = Request::new(Empty {});
+        request
+            .metadata_mut()
+            .insert(X_FORWARDED_FOR, "10.0.0.1".parse().unwrap());
+        request
+            .metadata_mut()
+            .insert("authorization", "Bearer secret".parse().unwrap());
+        request.extensions_mut().insert(RequestContext {
+            request_id: "req-1".to_string(),
+            tenant: Some("search".to_string()),
+            priority: Priority::High,
+            peer_addr: Some("10.0.0.2:4000".parse().unwrap()),
+            deadline: Some(Instant::now() + Duration::from_secs(5)),
+            ..Default::default()
+        });
+
+        let outgoing = client.outgoing(request);
+        let metadata = outgoing.metadata();
+        assert_eq!(metadata.get(REQUEST_ID_HEADER).unwrap(), "req-1");
+        assert_eq!(metadata.get(TENANT_HEADER).unwrap(), "search");
+        assert_eq!(metadata.get(PRIORITY_HEADER).unwrap(), "high");
+        assert_eq!(metadata.get(X_FORWARDED_FOR).unwrap(), "10.0.0.1, 10.0.0.2");
+        assert!(metadata.get("grpc-timeout").is_some());
+        assert!(metadata.get("authorization").is_none());
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::config::HedgingConfig;
+
+/// The number of latency samples required before the hedging delay is derived from observed
+/// latencies rather than `max_delay`.
+const MIN_SAMPLES: usize = 20;
+
+/// The `HedgingPolicy` struct decides how long the `LoadBalancedClient` waits for an upstream
+/// before firing a duplicate (hedged) call at another one.
+///
+/// The delay is the configured percentile of a sliding window of recent call latencies, clamped
+/// to `[min_delay, max_delay]`, so only calls slower than usual are hedged and the extra
+/// load stays bounded by roughly `100 - percentile` percent.
+#[derive(Debug)]
+pub struct HedgingPolicy {
+    config: HedgingConfig,
+    latencies: Mutex<VecDeque<Duration>>,
+}
+
+impl HedgingPolicy {
+    pub fn new(config: HedgingConfig) -> Self {
+        Self {
+            latencies: Mutex::new(VecDeque::with_capacity(config.window_size)),
+            config,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Records the latency of a successful call.
+    pub fn record(&self, latency: Duration) {
+        let mut latencies = self.latencies.lock().unwrap();
+        if latencies.len() >= self.config.window_size.max(1) {
+            latencies.pop_front();
+        }
+        latencies.push_back(latency);
+    }
+
+    /// Returns how long to wait for the first upstream before hedging.
+    pub fn delay(&self) -> Duration {
+        let min = self.config.min_delay;
+        let max = self.config.max_delay.max(min);
+        let mut latencies = self
+            .latencies
+            .lock()
+            .unwrap()
+            .iter()
+            .copied()
+            .collect::<Vec<_>>();
+        if latencies.len() < MIN_SAMPLES {
+            return max;
+        }
+        latencies.sort_unstable();
+        let rank = self.config.percentile.clamp(0.0, 100.0) / 100.0 * (latencies.len() - 1) as f64;
+        latencies[rank.round() as usize].clamp(min, max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> HedgingPolicy {
+        HedgingPolicy::new(HedgingConfig {
+            enabled: true,
+            percentile: 90.0,
+            min_delay: Duration::from_millis(5),
+            max_delay: Duration::from_millis(500),
+            window_size: 100,
+        })
+    }
+
+    #[test]
+    fn test_delay_defaults_to_max_until_enough_samples() {
+        let policy = policy();
+        policy.record(Duration::from_millis(10));
+        assert_eq!(policy.delay(), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_delay_follows_percentile_within_bounds() {
+        let policy = policy();
+        for ms in 1..=100 {
+            policy.record(Duration::from_millis(ms));
+        }
+        assert_eq!(policy.delay(), Duration::from_millis(90));
+
+        for _ in 0..100 {
+            policy.record(Duration::from_millis(1));
+        }
+        assert_eq!(policy.delay(), Duration::from_millis(5));
+
+        for _ in 0..100 {
+            policy.record(Duration::from_secs(2));
+        }
+        assert_eq!(policy.delay(), Duration::from_millis(500));
+    }
+}
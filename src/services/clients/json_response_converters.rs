@@ -11,9 +11,15 @@
  * `serde_json` crate for JSON deserialization and `tonic` crate for gRPC status handling.
  *
  * # Features
- * - Converts JSON strings into Rust structs for various response types.
- * - Provides detailed error handling for deserialization failures.
- * - Ensures data integrity by validating the JSON structure against expected types.
+ * - Deserializes each upstream response shape into a typed struct, locating mistyped fields as
+ *   they're deserialized rather than from an intermediate `Value`.
+ * - Rejects responses with missing or mistyped fields in strict mode, and substitutes zeros and
+ *   empty strings for them otherwise.
+ * - Reports the JSON pointer of the value at fault with the expected and actual JSON types, in
+ *   the message and the `google.rpc.ErrorInfo` details of the `Status`.
+ * - Renames the fields of upstreams naming them differently before conversion.
+ * - Converts responses deserialized straight from their body with `JsonResponse`, or held in a
+ *   `Value`, e.g. once their fields are renamed.
  *
  * # Dependencies
  * - `serde_json`: For handling JSON data.
@@ -32,7 +38,7 @@
  *     "shape": [1, 3]
  * });
  *
- * let response = json_to_embeddings_response(&json_response).unwrap();
+ * let response = json_to_embeddings_response(&json_response, true).unwrap();
  * println!("{:?}", response);
  * ```
  *
@@ -40,8 +46,16 @@
  * structures, making it easier to work with data from external sources in a type-safe manner.
  */
 
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use prost::Message;
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{self, DeserializeOwned, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
 use serde_json::Value;
-use tonic::Status;
+use tonic::{Code, Status};
 
 use crate::proto::mighty_proto::{
     Embedding, EmbeddingsResponse, Entity, MetadataResponse, QuestionAnswerResponse,
@@ -49,200 +63,739 @@ use crate::proto::mighty_proto::{
     TokenClassificationResponse,
 };
 
-static EMPTY_VEC: Vec<Value> = Vec::new();
-
-/// Converts a JSON response to an `EmbeddingsResponse` struct.
-pub fn json_to_embeddings_response(json: &Value) -> Result<EmbeddingsResponse, Status> {
-    let embeddings = json
-        .get("outputs")
-        .and_then(|o| o.as_array())
-        .unwrap_or(&EMPTY_VEC)
-        .iter()
-        .map(|arr| Embedding {
-            values: arr
-                .as_array()
-                .unwrap_or(&EMPTY_VEC)
-                .iter()
-                .map(|v| v.as_f64().unwrap_or_default() as f32)
-                .collect(),
+/// The domain of the `ErrorInfo` details of the errors of the gateway.
+const ERROR_DOMAIN: &str = "mighty-grpc";
+
+/// The reason of the `ErrorInfo` details of conversions failing on an unexpected response.
+const INVALID_RESPONSE_REASON: &str = "INVALID_UPSTREAM_RESPONSE";
+
+/// The type URL of `google.rpc.ErrorInfo` details.
+const ERROR_INFO_TYPE_URL: &str = "type.googleapis.com/google.rpc.ErrorInfo";
+
+/// Where and how an upstream response departs from the shape expected: the JSON pointer of the
+/// value at fault (empty for the whole response), the JSON type expected there and the type
+/// found, `missing` for a missing field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub pointer: String,
+    pub expected: &'static str,
+    pub actual: &'static str,
+}
+
+impl Mismatch {
+    /// Returns the mismatch located within the member `token` of its parent value.
+    fn within(mut self, token: impl std::fmt::Display) -> Self {
+        self.pointer = format!("/{}{}", token, self.pointer);
+        self
+    }
+}
+
+impl From<Mismatch> for Status {
+    /// Returns an `INTERNAL` status describing the mismatch, with an `ErrorInfo` in its details
+    /// holding its `pointer`, `expected` and `actual` types, so callers see what broke without
+    /// re-running the request with trace logging.
+    fn from(mismatch: Mismatch) -> Self {
+        let found = match mismatch.actual {
+            "missing" => "nothing",
+            actual => actual,
+        };
+        let message = match mismatch.pointer.as_str() {
+            "" => format!(
+                "Unexpected upstream response: expected {}, found {}",
+                mismatch.expected, found
+            ),
+            pointer => format!(
+                "Unexpected upstream response at {}: expected {}, found {}",
+                pointer, mismatch.expected, found
+            ),
+        };
+        let info = ErrorInfo {
+            reason: INVALID_RESPONSE_REASON.to_string(),
+            domain: ERROR_DOMAIN.to_string(),
+            metadata: HashMap::from([
+                ("pointer".to_string(), mismatch.pointer),
+                ("expected".to_string(), mismatch.expected.to_string()),
+                ("actual".to_string(), mismatch.actual.to_string()),
+            ]),
+        };
+        let details = RpcStatus {
+            code: Code::Internal as i32,
+            message: message.clone(),
+            details: vec![prost_types::Any {
+                type_url: ERROR_INFO_TYPE_URL.to_string(),
+                value: info.encode_to_vec(),
+            }],
+        };
+        Status::with_details(Code::Internal, message, details.encode_to_vec().into())
+    }
+}
+
+/// The `google.rpc.Status` message, the details of a `Status` in the gRPC rich error model.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RpcStatus {
+    #[prost(int32, tag = "1")]
+    pub code: i32,
+    #[prost(string, tag = "2")]
+    pub message: String,
+    #[prost(message, repeated, tag = "3")]
+    pub details: Vec<prost_types::Any>,
+}
+
+/// The `google.rpc.ErrorInfo` message, the reason of an error and its context.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ErrorInfo {
+    #[prost(string, tag = "1")]
+    pub reason: String,
+    #[prost(string, tag = "2")]
+    pub domain: String,
+    #[prost(map = "string, string", tag = "3")]
+    pub metadata: HashMap<String, String>,
+}
+
+/// A type of the fields of upstream responses, deserialized from a value of any JSON type so that
+/// mismatches are located as the body is deserialized, without an intermediate `Value`.
+trait JsonType: Sized + Default {
+    /// The JSON type expected, e.g. `integer`.
+    const EXPECTED: &'static str;
+
+    /// Returns the mismatch of a value of the JSON type `actual`, e.g. `missing` for a missing
+    /// field.
+    fn mismatch(actual: &'static str) -> Mismatch {
+        Mismatch {
+            pointer: String::new(),
+            expected: Self::EXPECTED,
+            actual,
+        }
+    }
+
+    /// Converts an integer, unless this type isn't one.
+    fn from_integer(_value: i128) -> Option<Self> {
+        None
+    }
+
+    /// Converts a floating point number, unless this type isn't one.
+    fn from_float(_value: f64) -> Option<Self> {
+        None
+    }
+
+    /// Converts a string, unless this type isn't one.
+    fn from_string(_value: Cow<str>) -> Option<Self> {
+        None
+    }
+
+    /// Deserializes an array, which is skipped unless this type is one.
+    fn from_seq<'de, A: SeqAccess<'de>>(mut seq: A) -> Result<Field<Self>, A::Error> {
+        while seq.next_element::<IgnoredAny>()?.is_some() {}
+        Ok(Field::Invalid(Self::mismatch("array")))
+    }
+
+    /// Deserializes an object, which is skipped unless this type is one.
+    fn from_map<'de, A: MapAccess<'de>>(mut map: A) -> Result<Field<Self>, A::Error> {
+        while map.next_entry::<IgnoredAny, IgnoredAny>()?.is_some() {}
+        Ok(Field::Invalid(Self::mismatch("object")))
+    }
+}
+
+impl JsonType for String {
+    const EXPECTED: &'static str = "string";
+
+    // Texts echoed by the upstream are moved out of the body when it's deserialized from a `Value`
+    fn from_string(value: Cow<str>) -> Option<Self> {
+        Some(value.into_owned())
+    }
+}
+
+impl JsonType for i32 {
+    const EXPECTED: &'static str = "integer";
+
+    fn from_integer(value: i128) -> Option<Self> {
+        i32::try_from(value).ok()
+    }
+}
+
+impl JsonType for f32 {
+    const EXPECTED: &'static str = "number";
+
+    fn from_integer(value: i128) -> Option<Self> {
+        Some(value as f32)
+    }
+
+    fn from_float(value: f64) -> Option<Self> {
+        Some(value as f32)
+    }
+}
+
+impl<T: JsonType> JsonType for Vec<T> {
+    const EXPECTED: &'static str = "array";
+
+    fn from_seq<'de, A: SeqAccess<'de>>(mut seq: A) -> Result<Field<Self>, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element::<Field<T>>()? {
+            match item.valid() {
+                Ok(item) => items.push(item),
+                Err(mismatch) => {
+                    let mismatch = mismatch.within(items.len());
+                    while seq.next_element::<IgnoredAny>()?.is_some() {}
+                    return Ok(Field::Invalid(mismatch));
+                }
+            }
+        }
+        Ok(Field::Present(items))
+    }
+}
+
+impl JsonType for [i32; 2] {
+    const EXPECTED: &'static str = "array of 2 integers";
+
+    fn from_seq<'de, A: SeqAccess<'de>>(mut seq: A) -> Result<Field<Self>, A::Error> {
+        let mut items = [0; 2];
+        let mut len = 0;
+        let mut mismatch = None;
+        while let Some(item) = seq.next_element::<Field<i32>>()? {
+            match item.valid() {
+                Ok(item) if len < items.len() => items[len] = item,
+                Ok(_) => {}
+                Err(item) => {
+                    mismatch.get_or_insert(item.within(len));
+                }
+            }
+            len += 1;
+        }
+        Ok(match mismatch {
+            _ if len != items.len() => Field::Invalid(Self::mismatch("array")),
+            Some(mismatch) => Field::Invalid(mismatch),
+            None => Field::Present(items),
         })
-        .collect::<Vec<_>>();
+    }
+}
 
-    Ok(EmbeddingsResponse {
-        embeddings,
-        took: extract_took(json),
-        text: extract_string_value(json, "text"),
-        shape: extract_shape(json),
-    })
+/// Metadata responses, whose values are free-form.
+impl JsonType for HashMap<String, Value> {
+    const EXPECTED: &'static str = "object";
+
+    fn from_map<'de, A: MapAccess<'de>>(map: A) -> Result<Field<Self>, A::Error> {
+        object(map)
+    }
 }
 
-/// Converts a JSON response to a `QuestionAnswerResponse` struct.
+/// Implements `JsonType` for structs deserialized from JSON objects.
+macro_rules! object {
+    ($($json:ty),*) => {
+        $(impl JsonType for $json {
+            const EXPECTED: &'static str = "object";
+
+            fn from_map<'de, A: MapAccess<'de>>(map: A) -> Result<Field<Self>, A::Error> {
+                object(map)
+            }
+        })*
+    };
+}
+
+object!(
+    OutputsJson,
+    QuestionAnswerJson,
+    SequenceClassificationJson,
+    TokenClassificationJson,
+    EntityJson
+);
+
+/// Deserializes an object into a `T` holding its fields.
+fn object<'de, T: Deserialize<'de>, A: MapAccess<'de>>(map: A) -> Result<Field<T>, A::Error> {
+    T::deserialize(MapAccessDeserializer::new(map)).map(Field::Present)
+}
+
+/// A field of an upstream response, kept whether it's missing, mistyped or valid, so that strict
+/// conversions can reject it and lenient ones substitute its default value. Mistyped fields only
+/// keep where and how they depart from their type, their value being skipped.
+#[derive(Debug, Default)]
+enum Field<T> {
+    #[default]
+    Missing,
+    Invalid(Mismatch),
+    Present(T),
+}
+
+impl<'de, T: JsonType> Deserialize<'de> for Field<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(FieldVisitor(PhantomData))
+    }
+}
+
+/// Deserializes a `Field<T>` from a value of any JSON type.
+struct FieldVisitor<T>(PhantomData<T>);
+
+impl<T: JsonType> FieldVisitor<T> {
+    fn field<E>(value: Option<T>, actual: &'static str) -> Result<Field<T>, E> {
+        Ok(match value {
+            Some(value) => Field::Present(value),
+            None => Field::Invalid(T::mismatch(actual)),
+        })
+    }
+}
+
+impl<'de, T: JsonType> Visitor<'de> for FieldVisitor<T> {
+    type Value = Field<T>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str(T::EXPECTED)
+    }
+
+    fn visit_bool<E: de::Error>(self, _value: bool) -> Result<Field<T>, E> {
+        Self::field(None, "boolean")
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Field<T>, E> {
+        self.visit_i128(value.into())
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Field<T>, E> {
+        self.visit_i128(value.into())
+    }
+
+    fn visit_i128<E: de::Error>(self, value: i128) -> Result<Field<T>, E> {
+        Self::field(T::from_integer(value), "number")
+    }
+
+    fn visit_u128<E: de::Error>(self, value: u128) -> Result<Field<T>, E> {
+        match i128::try_from(value) {
+            Ok(value) => self.visit_i128(value),
+            Err(_) => self.visit_f64(value as f64),
+        }
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Field<T>, E> {
+        Self::field(T::from_float(value), "number")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Field<T>, E> {
+        Self::field(T::from_string(Cow::Borrowed(value)), "string")
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Field<T>, E> {
+        Self::field(T::from_string(Cow::Owned(value)), "string")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Field<T>, E> {
+        Self::field(None, "null")
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Field<T>, E> {
+        Self::field(None, "null")
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Field<T>, D::Error> {
+        Field::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Field<T>, A::Error> {
+        T::from_seq(seq)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Field<T>, A::Error> {
+        T::from_map(map)
+    }
+}
+
+impl<T: JsonType> Field<T> {
+    /// Returns the value of the field, or where and how it departs from its type.
+    fn valid(self) -> Result<T, Mismatch> {
+        match self {
+            Field::Present(value) => Ok(value),
+            Field::Invalid(mismatch) => Err(mismatch),
+            Field::Missing => Err(T::mismatch("missing")),
+        }
+    }
+
+    /// Returns the value of the field, or its default value unless `strict`.
+    fn or_default(self, strict: bool) -> Result<T, Mismatch> {
+        match self.valid() {
+            Err(mismatch) if strict => Err(mismatch),
+            result => Ok(result.unwrap_or_default()),
+        }
+    }
+
+    /// Returns the value of the field `key`, or its default value unless `strict`.
+    fn take(self, key: &str, strict: bool) -> Result<T, Mismatch> {
+        self.or_default(strict)
+            .map_err(|mismatch| mismatch.within(key))
+    }
+
+    /// Returns the value of the optional field `key`, or `None` if it's mistyped unless
+    /// `strict`.
+    fn optional(self, key: &str, strict: bool) -> Result<Option<T>, Mismatch> {
+        match self {
+            Field::Missing => Ok(None),
+            field => field.take(key, strict).map(Some),
+        }
+    }
+}
+
+/// The responses of a batch response, each kept whether it's valid or not, to be converted one
+/// by one.
+#[derive(Debug, Default)]
+pub struct Responses<T>(Vec<Field<T>>);
+
+impl<T: JsonType> JsonType for Responses<T> {
+    const EXPECTED: &'static str = "array";
+
+    fn from_seq<'de, A: SeqAccess<'de>>(mut seq: A) -> Result<Field<Self>, A::Error> {
+        let mut responses = Vec::new();
+        while let Some(response) = seq.next_element()? {
+            responses.push(response);
+        }
+        Ok(Field::Present(Responses(responses)))
+    }
+}
+
+/// An upstream response as deserialized from its body, kept whether it has the shape expected or
+/// not.
+#[derive(Debug)]
+pub struct Body<T>(Field<T>);
+
+impl<'de, T: JsonType> Deserialize<'de> for Body<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Field::deserialize(deserializer).map(Body)
+    }
+}
+
+/// Renames the fields of `json`, at any depth, named like a key of `aliases` to its value, e.g.
+/// `embeddings` to `outputs` for upstreams naming the fields of their responses differently. A
+/// field is left alone if its object already has a field of the target name. Fields are renamed
+/// in place, without copying the response.
+pub fn rename_fields(mut json: Value, aliases: &HashMap<String, String>) -> Value {
+    if !aliases.is_empty() {
+        rename(&mut json, aliases);
+    }
+    json
+}
+
+fn rename(json: &mut Value, aliases: &HashMap<String, String>) {
+    match json {
+        Value::Object(fields) => {
+            let renamed: Vec<(String, String)> = fields
+                .keys()
+                .filter_map(|name| Some((name.clone(), aliases.get(name)?.clone())))
+                .filter(|(_, target)| !fields.contains_key(target))
+                .collect();
+            for (name, target) in renamed {
+                if let Some(value) = fields.remove(&name) {
+                    fields.insert(target, value);
+                }
+            }
+            fields.values_mut().for_each(|value| rename(value, aliases));
+        }
+        Value::Array(values) => values.iter_mut().for_each(|value| rename(value, aliases)),
+        _ => {}
+    }
+}
+
+/// The response of the embeddings and sentence transformers endpoints.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct OutputsJson {
+    outputs: Field<Vec<Vec<f32>>>,
+    took: Field<i32>,
+    text: Field<String>,
+    shape: Field<Vec<i32>>,
+}
+
+/// The response of the question answering endpoint.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct QuestionAnswerJson {
+    answer: Field<String>,
+    start_idx: Field<i32>,
+    end_idx: Field<i32>,
+    took: Field<i32>,
+}
+
+/// The response of the sequence classification endpoint.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SequenceClassificationJson {
+    logits: Field<Vec<Vec<f32>>>,
+    took: Field<i32>,
+    text: Field<String>,
+    shape: Field<Vec<i32>>,
+}
+
+/// The response of the token classification endpoint.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct TokenClassificationJson {
+    entities: Field<Vec<EntityJson>>,
+    took: Field<i32>,
+    text: Field<String>,
+    shape: Field<Vec<i32>>,
+}
+
+/// An entity of a token classification response, with its start and end `offsets`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct EntityJson {
+    id: Field<String>,
+    label: Field<String>,
+    text: Field<String>,
+    score: Field<f32>,
+    offsets: Field<[i32; 2]>,
+}
+
+/// A response converted from an upstream response deserialized straight from its body, e.g. with
+/// `response_format::deserialize`, or from a `Value` with `json_to_response`.
+pub trait JsonResponse: Sized {
+    /// The upstream response, as deserialized from its body.
+    type Json: DeserializeOwned;
+
+    /// Converts the upstream response, rejecting missing or mistyped fields when `strict`.
+    fn convert(json: Self::Json, strict: bool) -> Result<Self, Mismatch>;
+}
+
+impl JsonResponse for EmbeddingsResponse {
+    type Json = Body<OutputsJson>;
+
+    fn convert(json: Body<OutputsJson>, strict: bool) -> Result<Self, Mismatch> {
+        let json = json.0.or_default(strict)?;
+        Ok(EmbeddingsResponse {
+            embeddings: embeddings(json.outputs, strict)?,
+            took: json.took.take("took", strict)?,
+            text: json.text.take("text", strict)?,
+            shape: shape(json.shape, strict)?,
+        })
+    }
+}
+
+/// The `question` and `context` of the request aren't echoed by the upstream, and are left empty.
+impl JsonResponse for QuestionAnswerResponse {
+    type Json = Body<QuestionAnswerJson>;
+
+    fn convert(json: Body<QuestionAnswerJson>, strict: bool) -> Result<Self, Mismatch> {
+        let json = json.0.or_default(strict)?;
+        Ok(QuestionAnswerResponse {
+            answer: json.answer.take("answer", strict)?,
+            start_idx: json.start_idx.take("start_idx", strict)?,
+            end_idx: json.end_idx.take("end_idx", strict)?,
+            took: json.took.take("took", strict)?,
+            ..Default::default()
+        })
+    }
+}
+
+impl JsonResponse for SequenceClassificationResponse {
+    type Json = Body<SequenceClassificationJson>;
+
+    fn convert(json: Body<SequenceClassificationJson>, strict: bool) -> Result<Self, Mismatch> {
+        let json = json.0.or_default(strict)?;
+        // Flatten the logits array of arrays into a single Vec<f32>
+        let logits = json.logits.take("logits", strict)?.concat();
+
+        Ok(SequenceClassificationResponse {
+            text: json.text.take("text", strict)?,
+            logits,
+            took: json.took.take("took", strict)?,
+            shape: shape(json.shape, strict)?,
+        })
+    }
+}
+
+impl JsonResponse for TokenClassificationResponse {
+    type Json = Body<TokenClassificationJson>;
+
+    fn convert(json: Body<TokenClassificationJson>, strict: bool) -> Result<Self, Mismatch> {
+        let json = json.0.or_default(strict)?;
+        let entities = json
+            .entities
+            .take("entities", strict)?
+            .into_iter()
+            .enumerate()
+            .map(|(i, entity)| {
+                let convert = || {
+                    let [start_offset, end_offset] = entity.offsets.take("offsets", strict)?;
+                    Ok(Entity {
+                        id: entity.id.take("id", strict)?,
+                        label: entity.label.take("label", strict)?,
+                        text: entity.text.take("text", strict)?,
+                        score: entity.score.take("score", strict)?,
+                        start_offset,
+                        end_offset,
+                    })
+                };
+                convert().map_err(|mismatch: Mismatch| mismatch.within(i).within("entities"))
+            })
+            .collect::<Result<Vec<_>, Mismatch>>()?;
+
+        Ok(TokenClassificationResponse {
+            took: json.took.take("took", strict)?,
+            text: json.text.take("text", strict)?,
+            entities,
+            shape: shape(json.shape, strict)?,
+        })
+    }
+}
+
+impl JsonResponse for SentenceTransformersResponse {
+    type Json = Body<OutputsJson>;
+
+    fn convert(json: Body<OutputsJson>, strict: bool) -> Result<Self, Mismatch> {
+        let json = json.0.or_default(strict)?;
+        Ok(SentenceTransformersResponse {
+            embeddings: embeddings(json.outputs, strict)?,
+            took: json.took.take("took", strict)?,
+            text: json.text.take("text", strict)?,
+            shape: shape(json.shape, strict)?,
+        })
+    }
+}
+
+/// Metadata values are free-form: strings are kept as is, other values rendered as JSON. The
+/// response must be an object, even when not `strict`.
+impl JsonResponse for MetadataResponse {
+    type Json = Body<HashMap<String, Value>>;
+
+    fn convert(json: Body<HashMap<String, Value>>, _strict: bool) -> Result<Self, Mismatch> {
+        let metadata = json
+            .0
+            .valid()?
+            .into_iter()
+            .map(|(key, value)| {
+                // `Value`'s `Display` is compact, so arrays are rendered without spaces already
+                let value = match value {
+                    Value::String(string) => string,
+                    value => value.to_string(),
+                };
+                (key, value)
+            })
+            .collect();
+
+        Ok(MetadataResponse { metadata })
+    }
+}
+
+/// Batch responses, holding one response per text, in order. The batch must be an array, even
+/// when not `strict`, as its responses are matched with the texts of the batch.
+impl<J: JsonType, T: JsonResponse<Json = Body<J>>> JsonResponse for Vec<T> {
+    type Json = Body<Responses<J>>;
+
+    fn convert(json: Body<Responses<J>>, strict: bool) -> Result<Self, Mismatch> {
+        let Responses(responses) = json.0.valid()?;
+        responses
+            .into_iter()
+            .enumerate()
+            .map(|(i, response)| {
+                T::convert(Body(response), strict).map_err(|mismatch| mismatch.within(i))
+            })
+            .collect()
+    }
+}
+
+/// Converts an upstream response held in a `Value`, e.g. once its fields are renamed, like one
+/// deserialized from its body, rejecting missing or mistyped fields when `strict`.
+pub fn json_to_response<'de, T: JsonResponse>(
+    json: impl Deserializer<'de>,
+    strict: bool,
+) -> Result<T, Status> {
+    // Fields are deserialized from values of any type, so this only fails on invalid `Value`s
+    let json = T::Json::deserialize(json).map_err(|e| {
+        Status::internal(format!(
+            "Failed to deserialize the upstream response: {}",
+            e
+        ))
+    })?;
+    Ok(T::convert(json, strict)?)
+}
+
+/// Converts a JSON response to an `EmbeddingsResponse` struct, rejecting missing or mistyped
+/// fields when `strict`.
+pub fn json_to_embeddings_response(
+    json: &Value,
+    strict: bool,
+) -> Result<EmbeddingsResponse, Status> {
+    json_to_response(json, strict)
+}
+
+/// Converts a JSON response holding one embeddings response per text, in order, as returned by
+/// batch endpoints, to `EmbeddingsResponse` structs, rejecting missing or mistyped fields when
+/// `strict`.
+pub fn json_to_embeddings_batch_response(
+    json: &Value,
+    strict: bool,
+) -> Result<Vec<EmbeddingsResponse>, Status> {
+    json_to_response(json, strict)
+}
+
+/// Converts a JSON response to a `QuestionAnswerResponse` struct, rejecting missing or mistyped
+/// fields when `strict`.
 pub fn json_to_question_answer_response(
     json: &Value,
     question: String,
     context: String,
+    strict: bool,
 ) -> Result<QuestionAnswerResponse, Status> {
+    let response = json_to_response(json, strict)?;
     Ok(QuestionAnswerResponse {
-        answer: extract_string_value(json, "answer"),
-        start_idx: json
-            .get("start_idx")
-            .and_then(|s| s.as_u64())
-            .unwrap_or_default() as i32,
-        end_idx: json
-            .get("end_idx")
-            .and_then(|s| s.as_u64())
-            .unwrap_or_default() as i32,
         question,
         context,
-        took: extract_took(json),
+        ..response
     })
 }
 
-/// Converts a JSON response to a `SequenceClassificationResponse` struct.
+/// Converts a JSON response to a `SequenceClassificationResponse` struct, rejecting missing or
+/// mistyped fields when `strict`.
 pub fn json_to_sequence_classification_response(
     json: &Value,
+    strict: bool,
 ) -> Result<SequenceClassificationResponse, Status> {
-    // Flatten the logits array of arrays into a single Vec<f32>
-    let logits = json
-        .get("logits")
-        .and_then(|v| v.as_array())
-        .unwrap_or(&EMPTY_VEC)
-        .iter()
-        .flat_map(|inner_array| inner_array.as_array().unwrap_or(&EMPTY_VEC))
-        .map(|v| v.as_f64().unwrap_or_default() as f32)
-        .collect::<Vec<f32>>();
-
-    Ok(SequenceClassificationResponse {
-        text: extract_string_value(json, "text"),
-        logits,
-        took: extract_took(json),
-        shape: extract_shape(json),
-    })
+    json_to_response(json, strict)
 }
 
-/// Converts a JSON response to a `TokenClassificationResponse` struct.
+/// Converts a JSON response to a `TokenClassificationResponse` struct, rejecting missing or
+/// mistyped fields when `strict`.
 pub fn json_to_token_classification_response(
     json: &Value,
+    strict: bool,
 ) -> Result<TokenClassificationResponse, Status> {
-    let entities = json
-        .get("entities")
-        .and_then(|values| values.as_array())
-        .unwrap_or(&EMPTY_VEC)
-        .iter()
-        .map(|value| Entity {
-            id: extract_string_value(value, "id"),
-            label: extract_string_value(value, "label"),
-            text: extract_string_value(value, "text"),
-            score: value
-                .get("score")
-                .and_then(|score| score.as_f64().map(|s| s as f32))
-                .unwrap_or_default(),
-            start_offset: value
-                .get("offsets")
-                .and_then(|offsets| {
-                    offsets
-                        .as_array()
-                        .and_then(|arr| arr.get(0).and_then(|start| start.as_i64()))
-                })
-                .unwrap_or_default() as i32,
-            end_offset: value
-                .get("offsets")
-                .and_then(|offsets| {
-                    offsets
-                        .as_array()
-                        .and_then(|arr| arr.get(1).and_then(|end| end.as_i64()))
-                })
-                .unwrap_or_default() as i32,
-        })
-        .collect::<Vec<_>>();
+    json_to_response(json, strict)
+}
 
-    Ok(TokenClassificationResponse {
-        took: extract_took(json),
-        text: extract_string_value(json, "text"),
-        entities,
-        shape: extract_shape(json),
-    })
+/// Converts a JSON response holding one token classification response per text, in order, as
+/// returned by batch endpoints, to `TokenClassificationResponse` structs, rejecting missing or
+/// mistyped fields when `strict`.
+pub fn json_to_token_classification_batch_response(
+    json: &Value,
+    strict: bool,
+) -> Result<Vec<TokenClassificationResponse>, Status> {
+    json_to_response(json, strict)
 }
 
-/// Converts a JSON response to a `SentenceTransformersResponse` struct.
+/// Converts a JSON response to a `SentenceTransformersResponse` struct, rejecting missing or
+/// mistyped fields when `strict`.
 pub fn json_to_sentence_transformers_response(
     json: &Value,
+    strict: bool,
 ) -> Result<SentenceTransformersResponse, Status> {
-    let embeddings = json
-        .get("outputs")
-        .and_then(|o| o.as_array())
-        .unwrap_or(&EMPTY_VEC)
-        .iter()
-        .map(|arr| Embedding {
-            values: arr
-                .as_array()
-                .unwrap_or(&EMPTY_VEC)
-                .iter()
-                .map(|v| v.as_f64().unwrap_or_default() as f32)
-                .collect(),
-        })
-        .collect::<Vec<_>>();
-
-    Ok(SentenceTransformersResponse {
-        embeddings,
-        took: extract_took(json),
-        text: extract_string_value(json, "text"),
-        shape: extract_shape(json),
-    })
+    json_to_response(json, strict)
 }
 
-/// Converts a JSON response to a `MetadataResponse` struct.
+/// Converts a JSON response to a `MetadataResponse` struct. Metadata values are free-form:
+/// strings are kept as is, other values rendered as JSON.
 pub fn json_to_metadata_response(json: &Value) -> Result<MetadataResponse, Status> {
-    let metadata_map = json
-        .as_object()
-        .ok_or_else(|| Status::internal("Failed to parse metadata JSON as object"))?
-        .iter()
-        .map(|(k, v)| {
-            let value = if v.is_array() {
-                v.to_string().replace(", ", ",") // Ensure consistent formatting for arrays
-            } else if v.is_string() {
-                v.as_str().unwrap().to_string()
-            } else {
-                v.to_string()
-            };
-            (k.clone(), value)
-        })
-        .collect::<std::collections::HashMap<String, String>>();
-
-    Ok(MetadataResponse {
-        metadata: metadata_map,
-    })
+    json_to_response(json, true)
 }
 
-/// Extracts a string value from a JSON object by key.
-fn extract_string_value(json: &Value, key: &str) -> String {
-    json.get(key)
-        .and_then(|t| t.as_str())
-        .unwrap_or_default()
-        .to_string()
-}
-
-/// Extracts a shape value from a JSON object.
-pub fn extract_shape(json: &Value) -> Option<Shape> {
-    json.get("shape")
-        .and_then(|s| s.as_array())
-        .map(|array| Shape {
-            dim1: array
-                .get(0)
-                .and_then(|dim| dim.as_i64())
-                .unwrap_or_default() as i32,
-            dim2: array
-                .get(1)
-                .and_then(|dim| dim.as_i64())
-                .unwrap_or_default() as i32,
-        })
+/// Returns the embeddings of the `outputs` of a response.
+fn embeddings(outputs: Field<Vec<Vec<f32>>>, strict: bool) -> Result<Vec<Embedding>, Mismatch> {
+    Ok(outputs
+        .take("outputs", strict)?
+        .into_iter()
+        .map(|values| Embedding { values })
+        .collect())
 }
 
-/// Extracts the 'took' value from a JSON object.
-fn extract_took(json: &Value) -> i32 {
-    json.get("took")
-        .and_then(|t| t.as_i64())
-        .unwrap_or_default() as i32
+/// Returns the optional shape of a response, its first two dimensions.
+fn shape(shape: Field<Vec<i32>>, strict: bool) -> Result<Option<Shape>, Mismatch> {
+    Ok(shape.optional("shape", strict)?.map(|dims| Shape {
+        dim1: dims.first().copied().unwrap_or_default(),
+        dim2: dims.get(1).copied().unwrap_or_default(),
+    }))
 }
 
 #[cfg(test)]
@@ -265,7 +818,7 @@ mod tests {
         "#;
 
         let json: Value = serde_json::from_str(json_data).unwrap();
-        let response = json_to_embeddings_response(&json).unwrap();
+        let response = json_to_embeddings_response(&json, true).unwrap();
 
         let expected_shape = Some(Shape { dim1: 1, dim2: 3 });
 
@@ -298,7 +851,7 @@ mod tests {
         "#;
 
         let json: Value = serde_json::from_str(json_data).unwrap();
-        let response = json_to_sentence_transformers_response(&json).unwrap();
+        let response = json_to_sentence_transformers_response(&json, true).unwrap();
 
         let expected_shape = Some(Shape { dim1: 1, dim2: 3 });
 
@@ -334,7 +887,8 @@ mod tests {
         let question = "What is the answer?".to_string();
         let context = "This is the context.".to_string();
         let response =
-            json_to_question_answer_response(&json, question.clone(), context.clone()).unwrap();
+            json_to_question_answer_response(&json, question.clone(), context.clone(), true)
+                .unwrap();
 
         let expected_response = QuestionAnswerResponse {
             answer: "This is the answer.".to_string(),
@@ -360,7 +914,7 @@ mod tests {
         "#;
 
         let json: Value = serde_json::from_str(json_data).unwrap();
-        let response = json_to_sequence_classification_response(&json).unwrap();
+        let response = json_to_sequence_classification_response(&json, true).unwrap();
 
         let expected_shape = Some(Shape { dim1: 1, dim2: 3 });
 
@@ -401,7 +955,7 @@ mod tests {
         "#;
 
         let json: Value = serde_json::from_str(json_data).unwrap();
-        let response = json_to_token_classification_response(&json).unwrap();
+        let response = json_to_token_classification_response(&json, true).unwrap();
 
         let expected_entities = vec![
             Entity {
@@ -460,4 +1014,183 @@ mod tests {
 
         assert_eq!(result, expected_metadata);
     }
+
+    #[test]
+    fn test_strict_conversions_reject_missing_and_mistyped_fields() {
+        let renamed = json!({
+            "took": 9,
+            "text": "Sample text",
+            "embeddings": [[0.1, 0.2, 0.3]],
+            "shape": [1, 3]
+        });
+        let status = json_to_embeddings_response(&renamed, true).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Internal);
+        assert_eq!(
+            status.message(),
+            "Unexpected upstream response at /outputs: expected array, found nothing"
+        );
+
+        let mistyped = json!({
+            "took": 5,
+            "text": "John Doe",
+            "entities": [{ "id": "1", "label": "Person", "text": "John Doe", "score": "high", "offsets": [0, 8] }]
+        });
+        let status = json_to_token_classification_response(&mistyped, true).unwrap_err();
+        assert_eq!(
+            status.message(),
+            "Unexpected upstream response at /entities/0/score: expected number, found string"
+        );
+
+        // Lenient conversions substitute default values
+        let response = json_to_embeddings_response(&renamed, false).unwrap();
+        assert!(response.embeddings.is_empty());
+        assert_eq!(response.took, 9);
+        let response = json_to_token_classification_response(&mistyped, false).unwrap();
+        assert_eq!(response.entities[0].score, 0.0);
+        assert_eq!(response.entities[0].end_offset, 8);
+
+        // The shape is optional either way
+        let unshaped = json!({ "took": 1, "text": "", "logits": [[0.5]] });
+        let response = json_to_sequence_classification_response(&unshaped, true).unwrap();
+        assert_eq!(response.shape, None);
+    }
+
+    #[test]
+    fn test_mismatches_are_located_in_the_status_details() {
+        let json = json!({ "took": 1, "text": "", "logits": [[0.5], [0.25, null]] });
+        let status = json_to_sequence_classification_response(&json, true).unwrap_err();
+
+        let details = RpcStatus::decode(status.details()).unwrap();
+        assert_eq!(details.code, tonic::Code::Internal as i32);
+        assert_eq!(details.details[0].type_url, ERROR_INFO_TYPE_URL);
+        let info = ErrorInfo::decode(details.details[0].value.as_slice()).unwrap();
+        assert_eq!(info.reason, INVALID_RESPONSE_REASON);
+        assert_eq!(info.metadata["pointer"], "/logits/1/1");
+        assert_eq!(info.metadata["expected"], "number");
+        assert_eq!(info.metadata["actual"], "null");
+
+        // Conversions fail on responses which aren't objects either way
+        let status = json_to_metadata_response(&json!(["model"])).unwrap_err();
+        assert_eq!(
+            status.message(),
+            "Unexpected upstream response: expected object, found array"
+        );
+    }
+
+    #[test]
+    fn test_fields_are_deserialized_from_the_body() {
+        let body = br#"{ "took": 1, "text": "a", "logits": [[0.5], [0.25, null]], "extra": [{}] }"#;
+        let json: SequenceClassificationJson = serde_json::from_slice::<Field<_>>(body)
+            .unwrap()
+            .valid()
+            .unwrap();
+        assert_eq!(json.text.take("text", true).unwrap(), "a");
+        let mismatch = json.logits.take("logits", true).unwrap_err();
+        assert_eq!(mismatch.pointer, "/logits/1/1");
+        assert_eq!((mismatch.expected, mismatch.actual), ("number", "null"));
+
+        let body = br#"{ "took": 2.5, "text": "Paris", "entities": [{ "offsets": [0, 5, 9] }] }"#;
+        let json: TokenClassificationJson = serde_json::from_slice::<Field<_>>(body)
+            .unwrap()
+            .valid()
+            .unwrap();
+        let mismatch = json.took.take("took", true).unwrap_err();
+        assert_eq!((mismatch.expected, mismatch.actual), ("integer", "number"));
+        let entity = json.entities.take("entities", true).unwrap().remove(0);
+        let mismatch = entity.offsets.take("offsets", true).unwrap_err();
+        assert_eq!(mismatch.pointer, "/offsets");
+        assert_eq!(mismatch.actual, "array");
+
+        let json = serde_json::from_slice::<Field<OutputsJson>>(b"[1]").unwrap();
+        assert_eq!(json.valid().unwrap_err().actual, "array");
+    }
+
+    #[test]
+    fn test_aliased_fields_are_renamed() {
+        let aliases = HashMap::from([
+            ("embeddings".to_string(), "outputs".to_string()),
+            ("latency_ms".to_string(), "took".to_string()),
+            ("tag".to_string(), "label".to_string()),
+        ]);
+        let json = json!({ "latency_ms": 9, "text": "a", "embeddings": [[0.5]], "shape": [1, 1] });
+        let response = json_to_embeddings_response(&rename_fields(json, &aliases), true).unwrap();
+        assert_eq!(response.took, 9);
+        assert_eq!(response.embeddings[0].values, [0.5]);
+
+        // Entities are renamed too, and fields already present win
+        let json = json!({
+            "took": 2,
+            "latency_ms": 7,
+            "text": "Paris",
+            "entities": [{ "id": "1", "tag": "Location", "text": "Paris", "score": 0.9, "offsets": [0, 5] }]
+        });
+        let response =
+            json_to_token_classification_response(&rename_fields(json, &aliases), true).unwrap();
+        assert_eq!(response.took, 2);
+        assert_eq!(response.entities[0].label, "Location");
+    }
+
+    #[test]
+    fn test_batch_responses_are_converted() {
+        let json = json!([
+            { "took": 4, "text": "a", "outputs": [[0.5]], "shape": [1, 1] },
+            { "took": 4, "text": "b", "outputs": [[0.25]], "shape": [1, 1] }
+        ]);
+        let responses = json_to_embeddings_batch_response(&json, true).unwrap();
+        let texts: Vec<_> = responses.iter().map(|response| &response.text).collect();
+        assert_eq!(texts, ["a", "b"]);
+        assert_eq!(responses[1].embeddings[0].values, [0.25]);
+
+        let json = json!([
+            { "took": 2, "text": "John", "entities": [] },
+            { "took": 2, "text": "Paris", "entities": [{ "id": "1", "label": "Location", "text": "Paris", "score": 0.9 }] }
+        ]);
+        let status = json_to_token_classification_batch_response(&json, true).unwrap_err();
+        assert_eq!(
+            status.message(),
+            "Unexpected upstream response at /1/entities/0/offsets: expected array of 2 integers, found nothing"
+        );
+        let responses = json_to_token_classification_batch_response(&json, false).unwrap();
+        assert_eq!(responses[1].entities[0].label, "Location");
+
+        // A batch response must be an array either way
+        let status = json_to_embeddings_batch_response(&json!({}), false).unwrap_err();
+        assert_eq!(
+            status.message(),
+            "Unexpected upstream response: expected array, found object"
+        );
+    }
+
+    #[test]
+    fn test_responses_are_converted_from_their_body_like_values() {
+        fn from_body<T: JsonResponse>(json: &Value, strict: bool) -> Result<T, Mismatch> {
+            let body = serde_json::to_vec(json).unwrap();
+            T::convert(serde_json::from_slice(&body).unwrap(), strict)
+        }
+
+        let json = json!([
+            { "took": 2, "text": "John", "entities": [] },
+            { "took": 2, "text": "Paris", "entities": [{ "id": "1", "label": "Location", "text": "Paris", "score": 0.9 }] }
+        ]);
+        let mismatch = from_body::<Vec<TokenClassificationResponse>>(&json, true).unwrap_err();
+        assert_eq!(mismatch.pointer, "/1/entities/0/offsets");
+        assert_eq!(
+            from_body::<Vec<TokenClassificationResponse>>(&json, false).unwrap(),
+            json_to_token_classification_batch_response(&json, false).unwrap()
+        );
+
+        let json = json!({ "took": "4", "text": "a", "outputs": [[0.5]], "shape": [1, 1] });
+        assert_eq!(
+            from_body::<EmbeddingsResponse>(&json, false).unwrap(),
+            json_to_embeddings_response(&json, false).unwrap()
+        );
+        let mismatch = from_body::<EmbeddingsResponse>(&json, true).unwrap_err();
+        assert_eq!(mismatch.pointer, "/took");
+
+        // Batches must be arrays and metadata objects either way
+        let mismatch = from_body::<Vec<EmbeddingsResponse>>(&json, false).unwrap_err();
+        assert_eq!((mismatch.expected, mismatch.actual), ("array", "object"));
+        let mismatch = from_body::<MetadataResponse>(&json!("model"), false).unwrap_err();
+        assert_eq!((mismatch.expected, mismatch.actual), ("object", "string"));
+    }
 }
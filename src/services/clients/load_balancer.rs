@@ -0,0 +1,1117 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock, Weak};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use futures::future::{self, BoxFuture, Either};
+use log::{info, trace, warn};
+use rand::Rng;
+use tonic::{Request, Response, Status};
+
+use crate::config::{
+    DiscoveryConfig, HealthCheckConfig, HedgingConfig, LoadBalancingStrategy, RampConfig, Task,
+};
+use crate::proto::mighty_proto::{
+    EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse, QuestionAnswerRequest,
+    QuestionAnswerResponse, RerankRequest, RerankResponse, SentenceTransformersResponse,
+    SequenceClassificationResponse, TextRequest, TokenClassificationResponse,
+};
+use crate::services::context::ServedBy;
+use crate::services::metrics::Metrics;
+use crate::services::hash::{fnv1a, FNV_OFFSET_BASIS};
+
+use super::discovery::{self, ClientFactory, Endpoint};
+use super::hedging::HedgingPolicy;
+use super::ramp::{Ramp, RampEvent};
+use super::{duplicate, is_upstream_failure, MightyClient, UpstreamUpdate};
+
+/// A single upstream instance behind the `LoadBalancedClient`.
+struct Upstream {
+    endpoint: Endpoint,
+    name: String,
+    client: Box<dyn MightyClient>,
+    outstanding: AtomicUsize,
+    healthy: AtomicBool,
+    consecutive_failures: AtomicU32,
+    consecutive_successes: AtomicU32,
+    ramp: Option<Ramp>,
+}
+
+impl Upstream {
+    fn new(endpoint: Endpoint, client: Box<dyn MightyClient>) -> Self {
+        Self {
+            name: endpoint.name(),
+            endpoint,
+            client,
+            outstanding: AtomicUsize::new(0),
+            healthy: AtomicBool::new(true),
+            consecutive_failures: AtomicU32::new(0),
+            consecutive_successes: AtomicU32::new(0),
+            ramp: None,
+        }
+    }
+
+    fn with_ramp(mut self, config: Arc<RampConfig>) -> Self {
+        self.ramp = Some(Ramp::new(config));
+        self
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the upstream's ramp, if any, admits it for the next call.
+    fn admits(&self) -> bool {
+        self.ramp.as_ref().is_none_or(Ramp::admits)
+    }
+
+    /// Records the outcome of a call against the upstream's ramp, if any, and reports ramp
+    /// step changes and rollbacks.
+    fn record_ramp(&self, success: bool) {
+        let Some(ramp) = &self.ramp else {
+            return;
+        };
+        if ramp.record(success) {
+            warn!(
+                "Upstream {} exceeded the error rate allowed while ramping up, rolling back its ramp",
+                self.name
+            );
+            Metrics::global()
+                .counter(RAMP_ROLLBACKS_METRIC, &[("upstream", &self.name)])
+                .increment(1);
+        }
+        match ramp.poll_event() {
+            Some(RampEvent::Step { percent }) => {
+                info!(
+                    "Upstream {} ramped up to {}% of its traffic share after {:?}",
+                    self.name,
+                    percent,
+                    ramp.elapsed()
+                );
+                Metrics::global()
+                    .counter(
+                        RAMP_STEPS_METRIC,
+                        &[("upstream", &self.name), ("percent", &percent.to_string())],
+                    )
+                    .increment(1);
+            }
+            Some(RampEvent::Completed) => {
+                info!("Upstream {} completed its ramp-up", self.name);
+                Metrics::global()
+                    .counter(
+                        RAMP_STEPS_METRIC,
+                        &[("upstream", &self.name), ("percent", "100")],
+                    )
+                    .increment(1);
+            }
+            None => {}
+        }
+    }
+
+    /// Records the outcome of a call or probe, ejecting the upstream after `unhealthy_threshold`
+    /// consecutive failures and re-admitting it after `healthy_threshold` consecutive successes.
+    fn record(&self, success: bool, thresholds: &HealthCheckConfig) {
+        if success {
+            self.consecutive_failures.store(0, Ordering::Relaxed);
+            let successes = self.consecutive_successes.fetch_add(1, Ordering::Relaxed) + 1;
+            if successes >= thresholds.healthy_threshold
+                && !self.healthy.swap(true, Ordering::Relaxed)
+            {
+                info!("Upstream {} is healthy again, re-admitting it", self.name);
+            }
+        } else {
+            self.consecutive_successes.store(0, Ordering::Relaxed);
+            let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+            if failures >= thresholds.unhealthy_threshold
+                && self.healthy.swap(false, Ordering::Relaxed)
+            {
+                warn!(
+                    "Upstream {} failed {} consecutive times, ejecting it",
+                    self.name, failures
+                );
+            }
+        }
+    }
+}
+
+/// The current set of upstreams, shared with the background health check and discovery tasks.
+type Upstreams = RwLock<Vec<Arc<Upstream>>>;
+
+/// Decrements an upstream's outstanding call count when the call completes or is cancelled.
+struct OutstandingGuard<'a>(&'a AtomicUsize);
+
+impl Drop for OutstandingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The shortest interval between health check probes or DNS resolutions.
+const MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Counter incremented for every hedged call, labelled with the call that won the race.
+const HEDGED_REQUESTS_METRIC: &str = "mighty_hedged_requests_total";
+
+/// Counter incremented whenever a ramping upstream moves to another step of its schedule.
+const RAMP_STEPS_METRIC: &str = "mighty_upstream_ramp_steps_total";
+
+/// Counter incremented whenever a ramping upstream is rolled back for exceeding its error rate.
+const RAMP_ROLLBACKS_METRIC: &str = "mighty_upstream_ramp_rollbacks_total";
+
+/// Returns the 64-bit FNV-1a hash of `parts`, each ended by a NUL byte, like the `reference` of
+/// texts: unlike `DefaultHasher`, it is the same across Rust releases, so gateway replicas of
+/// different builds route a key to the same upstream.
+fn hash<'a>(parts: impl IntoIterator<Item = &'a [u8]>) -> u64 {
+    parts.into_iter().fold(FNV_OFFSET_BASIS, |hash, part| {
+        fnv1a(fnv1a(hash, part), &[0])
+    })
+}
+
+/// Requests that can be routed by consistent hashing on their input text.
+trait RoutingKey {
+    /// Returns the hash of the request's input, or `None` for requests without input.
+    fn routing_key(&self) -> Option<u64>;
+}
+
+impl RoutingKey for TextRequest {
+    fn routing_key(&self) -> Option<u64> {
+        Some(hash([self.text.as_bytes()]))
+    }
+}
+
+impl RoutingKey for QuestionAnswerRequest {
+    fn routing_key(&self) -> Option<u64> {
+        Some(hash([self.question.as_bytes(), self.context.as_bytes()]))
+    }
+}
+
+impl RoutingKey for RerankRequest {
+    fn routing_key(&self) -> Option<u64> {
+        let texts = self.texts.iter().map(String::as_bytes);
+        Some(hash(std::iter::once(self.query.as_bytes()).chain(texts)))
+    }
+}
+
+impl RoutingKey for Vec<String> {
+    fn routing_key(&self) -> Option<u64> {
+        Some(hash(self.iter().map(String::as_bytes)))
+    }
+}
+
+impl RoutingKey for Empty {
+    fn routing_key(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// How a call is routed: the load balancing strategy and, for consistent hashing, the hash of
+/// the request input.
+#[derive(Debug, Clone, Copy)]
+struct Route {
+    strategy: LoadBalancingStrategy,
+    key: Option<u64>,
+}
+
+/// The `LoadBalancedClient` struct implements the `MightyClient` trait by distributing each call
+/// across several upstream clients (typically one `MightyServerRestClient` per Mighty replica)
+/// according to a `LoadBalancingStrategy`, which can be overridden per task (see
+/// `with_task_strategies`).
+///
+/// Upstreams are health checked passively, by counting consecutive failed calls, and optionally
+/// actively, by periodically probing each upstream's health check (see `with_health_checks`).
+/// Unhealthy upstreams are ejected from selection until they recover. Should every upstream be
+/// ejected, calls are spread across all of them rather than failing outright.
+///
+/// The upstream set can also be refreshed periodically from DNS (see `with_discovery`), so a
+/// hostname backed by several addresses, such as a headless Kubernetes service, is followed as
+/// instances come and go.
+///
+/// Slow calls can be hedged (see `with_hedging`): once a call has been outstanding for longer
+/// than usual, a duplicate is sent to another upstream and the first successful response wins.
+/// Tasks can opt out of hedging (see `with_unhedged_tasks`), e.g. when their calls are costly.
+///
+/// Upstreams discovered or added by a configuration reload (see `update_upstreams`) while others
+/// are serving can be ramped up gradually (see `with_ramp`), receiving a growing share of their
+/// traffic and none at all should their error rate regress.
+pub struct LoadBalancedClient {
+    upstreams: Arc<Upstreams>,
+    base_urls: Arc<RwLock<Vec<String>>>,
+    factory: Option<ClientFactory>,
+    strategy: LoadBalancingStrategy,
+    task_strategies: HashMap<Task, LoadBalancingStrategy>,
+    health_check: HealthCheckConfig,
+    hedging: HedgingPolicy,
+    unhedged_tasks: HashSet<Task>,
+    ramp: Option<Arc<RampConfig>>,
+    next: AtomicUsize,
+}
+
+impl LoadBalancedClient {
+    /// Creates a load balancer over the given named upstream clients.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `upstreams` is empty.
+    pub fn new(
+        upstreams: Vec<(String, Box<dyn MightyClient>)>,
+        strategy: LoadBalancingStrategy,
+    ) -> Self {
+        assert!(!upstreams.is_empty(), "At least one upstream is required");
+        let base_urls = upstreams
+            .iter()
+            .map(|(base_url, _)| base_url.clone())
+            .collect();
+        let upstreams = upstreams
+            .into_iter()
+            .map(|(base_url, client)| Arc::new(Upstream::new(Endpoint::new(base_url), client)))
+            .collect();
+        Self {
+            upstreams: Arc::new(RwLock::new(upstreams)),
+            base_urls: Arc::new(RwLock::new(base_urls)),
+            factory: None,
+            strategy,
+            task_strategies: HashMap::new(),
+            health_check: HealthCheckConfig::default(),
+            hedging: HedgingPolicy::new(HedgingConfig::default()),
+            unhedged_tasks: HashSet::new(),
+            ramp: None,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Overrides the load balancing strategy for the given tasks, e.g. consistent hashing for
+    /// embeddings while other tasks keep the default strategy.
+    pub fn with_task_strategies(
+        mut self,
+        task_strategies: HashMap<Task, LoadBalancingStrategy>,
+    ) -> Self {
+        self.task_strategies = task_strategies;
+        self
+    }
+
+    /// Applies the health check thresholds and, when enabled, spawns a background task probing
+    /// every upstream at the configured interval. The task stops once the client is dropped.
+    ///
+    /// Must be called from within a Tokio runtime when active health checks are enabled.
+    pub fn with_health_checks(mut self, config: HealthCheckConfig) -> Self {
+        if config.enabled {
+            let upstreams = Arc::downgrade(&self.upstreams);
+            tokio::spawn(probe_upstreams(upstreams, config.clone()));
+        }
+        self.health_check = config;
+        self
+    }
+
+    /// Applies the hedging policy: when enabled, a call still outstanding after the configured
+    /// latency percentile is duplicated to another upstream, and the loser is cancelled.
+    pub fn with_hedging(mut self, config: HedgingConfig) -> Self {
+        self.hedging = HedgingPolicy::new(config);
+        self
+    }
+
+    /// Never hedges the calls of the given tasks, whatever the hedging policy.
+    pub fn with_unhedged_tasks(mut self, tasks: HashSet<Task>) -> Self {
+        self.unhedged_tasks = tasks;
+        self
+    }
+
+    /// Applies the ramp-up schedule: when enabled, upstreams discovered or added while others
+    /// are serving start with a small share of their traffic, growing along the schedule, and are
+    /// rolled back if their error rate exceeds the configured limit.
+    ///
+    /// Must be called before `with_discovery`.
+    pub fn with_ramp(mut self, config: RampConfig) -> Self {
+        self.ramp = config.enabled.then(|| Arc::new(config));
+        self
+    }
+
+    /// Creates the clients of upstreams added later through `factory`. When discovery is
+    /// enabled, spawns a background task re-resolving the hostnames of `base_urls` at the
+    /// configured interval and replacing the upstream set with one upstream per resolved
+    /// address. Upstreams whose address is still resolved keep their health state; a failed
+    /// resolution leaves that base URL's upstreams untouched. The task stops once the client is
+    /// dropped.
+    ///
+    /// Must be called from within a Tokio runtime when discovery is enabled.
+    pub fn with_discovery(
+        mut self,
+        base_urls: Vec<String>,
+        config: &DiscoveryConfig,
+        factory: ClientFactory,
+    ) -> Self {
+        *self.base_urls.write().unwrap() = base_urls;
+        if config.enabled {
+            let upstreams = Arc::downgrade(&self.upstreams);
+            let interval = config.refresh_interval.max(MIN_INTERVAL);
+            tokio::spawn(discover_upstreams(
+                upstreams,
+                self.base_urls.clone(),
+                interval,
+                factory.clone(),
+                self.ramp.clone(),
+            ));
+        }
+        self.factory = Some(factory);
+        self
+    }
+
+    /// Returns how a call for `task` with the given request message should be routed.
+    fn route(&self, task: Option<Task>, request: &impl RoutingKey) -> Route {
+        let strategy = task
+            .and_then(|task| self.task_strategies.get(&task))
+            .copied()
+            .unwrap_or(self.strategy);
+        let key = match strategy {
+            LoadBalancingStrategy::ConsistentHash => request.routing_key(),
+            _ => None,
+        };
+        Route { strategy, key }
+    }
+
+    /// Returns the upstream that should serve the next call, or `UNAVAILABLE` should there be
+    /// none.
+    fn select(&self, route: Route) -> Result<Arc<Upstream>, Status> {
+        self.select_excluding(route, None)
+            .ok_or_else(|| Status::unavailable("No upstream is available"))
+    }
+
+    /// Returns the upstream that should serve the next call other than `excluded`, if any.
+    fn select_excluding(
+        &self,
+        route: Route,
+        excluded: Option<&Arc<Upstream>>,
+    ) -> Option<Arc<Upstream>> {
+        let upstreams = self.upstreams.read().unwrap();
+        let len = upstreams.len();
+        let candidate =
+            |i: &usize| excluded.is_none_or(|excluded| !Arc::ptr_eq(&upstreams[*i], excluded));
+        // Probes flip health concurrently; read it once per selection so the fallback to all
+        // upstreams and the filter below agree
+        let is_healthy = upstreams
+            .iter()
+            .map(|upstream| upstream.is_healthy())
+            .collect::<Vec<_>>();
+        let any_healthy = (0..len).filter(candidate).any(|i| is_healthy[i]);
+        let healthy = |i: &usize| candidate(i) && (!any_healthy || is_healthy[*i]);
+        // Ramping upstreams are admitted probabilistically; draw once per selection so every
+        // strategy sees the same admissions, and ignore ramps should none be admitted
+        let admitted = upstreams
+            .iter()
+            .map(|upstream| upstream.admits())
+            .collect::<Vec<_>>();
+        let any_admitted = (0..len).filter(healthy).any(|i| admitted[i]);
+        let eligible = |i: &usize| healthy(i) && (!any_admitted || admitted[*i]);
+
+        let round_robin = || {
+            let offset = self.next.fetch_add(1, Ordering::Relaxed);
+            (0..len).map(|i| (offset + i) % len).find(eligible)
+        };
+
+        let index = match route.strategy {
+            LoadBalancingStrategy::RoundRobin => round_robin(),
+            LoadBalancingStrategy::LeastOutstanding => {
+                // Start the scan at a rotating offset so ties don't always favor the first upstream
+                let offset = self.next.fetch_add(1, Ordering::Relaxed);
+                (0..len)
+                    .map(|i| (offset + i) % len)
+                    .filter(eligible)
+                    .min_by_key(|&i| upstreams[i].outstanding.load(Ordering::Relaxed))
+            }
+            LoadBalancingStrategy::Random => {
+                let candidates = (0..len).filter(eligible).count();
+                (candidates > 0)
+                    .then(|| rand::thread_rng().gen_range(0..candidates))
+                    .and_then(|pick| (0..len).filter(eligible).nth(pick))
+            }
+            // Rendezvous hashing: the upstream scoring highest for the key wins, so only the
+            // keys of an added or removed upstream move, and hedges go to the runner-up
+            LoadBalancingStrategy::ConsistentHash => match route.key {
+                Some(key) => (0..len)
+                    .filter(eligible)
+                    .max_by_key(|&i| hash([upstreams[i].name.as_bytes(), &key.to_le_bytes()])),
+                None => round_robin(),
+            },
+        };
+        index.map(|index| upstreams[index].clone())
+    }
+
+    async fn dispatch<R, T>(
+        &self,
+        task: Option<Task>,
+        request: Request<R>,
+        call: impl for<'c> Fn(
+                &'c dyn MightyClient,
+                Request<R>,
+            ) -> BoxFuture<'c, Result<Response<T>, Status>>
+            + Sync,
+    ) -> Result<Response<T>, Status>
+    where
+        R: Clone + RoutingKey,
+    {
+        let route = self.route(task, request.get_ref());
+        let primary = self.select(route)?;
+        let unhedged = task.is_some_and(|task| self.unhedged_tasks.contains(&task));
+        if !self.hedging.is_enabled() || unhedged {
+            return self.attempt(&primary, request, &call).await;
+        }
+
+        let hedge_request = duplicate(&request);
+        let first = self.attempt(&primary, request, &call);
+        tokio::pin!(first);
+        tokio::select! {
+            result = &mut first => return result,
+            _ = tokio::time::sleep(self.hedging.delay()) => {}
+        }
+        let Some(hedge) = self.select_excluding(route, Some(&primary)) else {
+            return first.await;
+        };
+
+        trace!(
+            "Upstream {} is slow, hedging call to upstream {}",
+            primary.name,
+            hedge.name
+        );
+        let second = self.attempt(&hedge, hedge_request, &call);
+        tokio::pin!(second);
+        // The first successful response wins; dropping the other call cancels it
+        let (result, winner) = match future::select(first, second).await {
+            Either::Left((Err(status), second)) if is_upstream_failure(&status) => {
+                (second.await, "hedge")
+            }
+            Either::Right((Err(status), first)) if is_upstream_failure(&status) => {
+                (first.await, "primary")
+            }
+            Either::Left((result, _)) => (result, "primary"),
+            Either::Right((result, _)) => (result, "hedge"),
+        };
+        Metrics::global()
+            .counter(HEDGED_REQUESTS_METRIC, &[("winner", winner)])
+            .increment(1);
+        result
+    }
+
+    /// Sends `request` to `upstream`, tracking its outstanding calls, health and latency, and
+    /// names the upstream in the response extensions (see `ServedBy`).
+    async fn attempt<R, T>(
+        &self,
+        upstream: &Upstream,
+        request: Request<R>,
+        call: &impl for<'c> Fn(
+            &'c dyn MightyClient,
+            Request<R>,
+        ) -> BoxFuture<'c, Result<Response<T>, Status>>,
+    ) -> Result<Response<T>, Status> {
+        trace!("Dispatching call to upstream {}", upstream.name);
+        upstream.outstanding.fetch_add(1, Ordering::Relaxed);
+        let _guard = OutstandingGuard(&upstream.outstanding);
+
+        let start = Instant::now();
+        let result = call(upstream.client.as_ref(), request).await;
+        let failed = matches!(&result, Err(status) if is_upstream_failure(status));
+        upstream.record(!failed, &self.health_check);
+        upstream.record_ramp(!failed);
+        if result.is_ok() {
+            self.hedging.record(start.elapsed());
+        }
+        result.map(|mut response| {
+            response
+                .extensions_mut()
+                .insert(ServedBy(upstream.name.clone()));
+            response
+        })
+    }
+}
+
+/// Periodically probes every upstream's health check, a probe taking longer than `timeout`
+/// failing, until the load balancer is dropped.
+async fn probe_upstreams(upstreams: Weak<Upstreams>, config: HealthCheckConfig) {
+    let mut interval = tokio::time::interval(config.interval.max(MIN_INTERVAL));
+    loop {
+        interval.tick().await;
+        let Some(upstreams) = upstreams.upgrade() else {
+            return;
+        };
+        let snapshot = upstreams.read().unwrap().clone();
+        let probes = snapshot.iter().map(|upstream| async {
+            let probe = upstream.client.health_check(Request::new(Empty {}));
+            let healthy = tokio::time::timeout(config.timeout, probe)
+                .await
+                .is_ok_and(|result| result.is_ok_and(|response| response.into_inner().success));
+            upstream.record(healthy, &config);
+        });
+        futures::future::join_all(probes).await;
+    }
+}
+
+/// Periodically re-resolves the `configured` base URLs, as last updated, and reconciles the
+/// upstream set with the resolved endpoints until the load balancer is dropped.
+async fn discover_upstreams(
+    upstreams: Weak<Upstreams>,
+    configured: Arc<RwLock<Vec<String>>>,
+    interval: Duration,
+    factory: ClientFactory,
+    ramp: Option<Arc<RampConfig>>,
+) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        let base_urls = configured.read().unwrap().clone();
+        let mut resolved = Vec::with_capacity(base_urls.len());
+        for base_url in &base_urls {
+            match discovery::resolve(base_url).await {
+                Ok(endpoints) if !endpoints.is_empty() => {
+                    resolved.push((base_url, Some(endpoints)))
+                }
+                Ok(_) => {
+                    warn!(
+                        "{} resolved to no addresses, keeping current upstreams",
+                        base_url
+                    );
+                    resolved.push((base_url, None));
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to resolve {}, keeping current upstreams: {}",
+                        base_url, e
+                    );
+                    resolved.push((base_url, None));
+                }
+            }
+        }
+
+        let Some(upstreams) = upstreams.upgrade() else {
+            return;
+        };
+        // The base URLs were updated while resolving; resolve them again at the next tick
+        if *configured.read().unwrap() != base_urls {
+            continue;
+        }
+        reconcile(&upstreams, resolved, &factory, ramp.as_ref());
+    }
+}
+
+/// Replaces the upstream set with one upstream per resolved endpoint, reusing existing upstreams
+/// (and their health state) for endpoints that are still present. Base URLs that failed to
+/// resolve (`None`) keep their current upstreams.
+///
+/// New upstreams are ramped up according to `ramp` when some current upstreams keep serving,
+/// so the initial resolution, or a complete replacement, doesn't starve the whole set.
+fn reconcile(
+    upstreams: &Upstreams,
+    resolved: Vec<(&String, Option<Vec<Endpoint>>)>,
+    factory: &ClientFactory,
+    ramp: Option<&Arc<RampConfig>>,
+) {
+    let current = upstreams.read().unwrap().clone();
+    let mut existing = current
+        .iter()
+        .map(|upstream| (upstream.endpoint.clone(), upstream.clone()))
+        .collect::<HashMap<_, _>>();
+    let serving = resolved
+        .iter()
+        .any(|(base_url, endpoints)| match endpoints {
+            Some(endpoints) => endpoints
+                .iter()
+                .any(|endpoint| existing.contains_key(endpoint)),
+            None => current
+                .iter()
+                .any(|upstream| &upstream.endpoint.base_url == *base_url),
+        });
+    let ramp = ramp.filter(|_| serving);
+
+    let mut next = Vec::new();
+    for (base_url, endpoints) in resolved {
+        match endpoints {
+            Some(endpoints) => {
+                for endpoint in endpoints {
+                    let upstream = existing.remove(&endpoint).unwrap_or_else(|| {
+                        info!("Discovered upstream {}", endpoint.name());
+                        let upstream = Upstream::new(endpoint.clone(), factory(&endpoint));
+                        Arc::new(match ramp {
+                            Some(ramp) => upstream.with_ramp(ramp.clone()),
+                            None => upstream,
+                        })
+                    });
+                    next.push(upstream);
+                }
+            }
+            None => next.extend(
+                current
+                    .iter()
+                    .filter(|upstream| &upstream.endpoint.base_url == base_url)
+                    .cloned(),
+            ),
+        }
+    }
+
+    for (endpoint, _) in existing {
+        if next.iter().all(|upstream| upstream.endpoint != endpoint) {
+            info!("Removing upstream {}", endpoint.name());
+        }
+    }
+    if !next.is_empty() {
+        *upstreams.write().unwrap() = next;
+    }
+}
+
+impl UpstreamUpdate for LoadBalancedClient {
+    /// Serves the upstreams of `base_urls`: those of the base URLs already served keep serving,
+    /// as discovered, and the others are created through the factory given to `with_discovery`,
+    /// ramped up according to `with_ramp`.
+    fn update_upstreams(&self, base_urls: &[String]) -> bool {
+        let Some(factory) = &self.factory else {
+            return false;
+        };
+        let served = self
+            .upstreams
+            .read()
+            .unwrap()
+            .iter()
+            .map(|upstream| upstream.endpoint.base_url.clone())
+            .collect::<HashSet<_>>();
+        if !base_urls.iter().any(|base_url| served.contains(base_url)) {
+            return false;
+        }
+        // Upstreams of base URLs already served are kept as is, new ones are added as configured
+        // until the next discovery resolves them
+        let resolved = base_urls
+            .iter()
+            .map(|base_url| {
+                let endpoints =
+                    (!served.contains(base_url)).then(|| vec![Endpoint::new(base_url.clone())]);
+                (base_url, endpoints)
+            })
+            .collect();
+        *self.base_urls.write().unwrap() = base_urls.to_vec();
+        reconcile(&self.upstreams, resolved, factory, self.ramp.as_ref());
+        true
+    }
+}
+
+#[async_trait]
+impl MightyClient for LoadBalancedClient {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        self.dispatch(None, request, |client, request| {
+            client.health_check(request)
+        })
+        .await
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        self.dispatch(Some(Task::Embeddings), request, |client, request| {
+            client.embeddings(request)
+        })
+        .await
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        self.dispatch(Some(Task::QuestionAnswering), request, |client, request| {
+            client.question_answering(request)
+        })
+        .await
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        self.dispatch(
+            Some(Task::SentenceTransformers),
+            request,
+            |client, request| client.sentence_transformers(request),
+        )
+        .await
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        self.dispatch(
+            Some(Task::SequenceClassification),
+            request,
+            |client, request| client.sequence_classification(request),
+        )
+        .await
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        self.dispatch(
+            Some(Task::TokenClassification),
+            request,
+            |client, request| client.token_classification(request),
+        )
+        .await
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        self.dispatch(None, request, |client, request| client.metadata(request))
+            .await
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        self.dispatch(Some(Task::Rerank), request, |client, request| {
+            client.rerank(request)
+        })
+        .await
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        self.dispatch(Some(Task::Embeddings), request, |client, request| {
+            client.embeddings_batch(request)
+        })
+        .await
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        self.dispatch(
+            Some(Task::TokenClassification),
+            request,
+            |client, request| client.token_classification_batch(request),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::clients::mock::MockMightyClient;
+    use crate::services::clients::rest::MightyServerRestClient;
+
+    use super::*;
+
+    const URLS: [&str; 3] = ["http://a:5050", "http://b:5050", "http://c:5050"];
+
+    fn upstream(lb: &LoadBalancedClient, index: usize) -> Arc<Upstream> {
+        lb.upstreams.read().unwrap()[index].clone()
+    }
+
+    fn select(lb: &LoadBalancedClient) -> Arc<Upstream> {
+        lb.select(lb.route(None, &Empty {})).unwrap()
+    }
+
+    fn load_balancer(strategy: LoadBalancingStrategy) -> LoadBalancedClient {
+        let upstreams = URLS
+            .into_iter()
+            .map(|url| {
+                let client: Box<dyn MightyClient> =
+                    Box::new(MightyServerRestClient::new(url.to_string()));
+                (url.to_string(), client)
+            })
+            .collect();
+        LoadBalancedClient::new(upstreams, strategy)
+    }
+
+    #[test]
+    fn test_round_robin_cycles_through_upstreams() {
+        let lb = load_balancer(LoadBalancingStrategy::RoundRobin);
+        let picks = (0..6).map(|_| select(&lb).name.clone()).collect::<Vec<_>>();
+        assert_eq!(
+            picks,
+            vec![URLS[0], URLS[1], URLS[2], URLS[0], URLS[1], URLS[2]]
+        );
+    }
+
+    #[test]
+    fn test_least_outstanding_prefers_idle_upstream() {
+        let lb = load_balancer(LoadBalancingStrategy::LeastOutstanding);
+        upstream(&lb, 0).outstanding.store(3, Ordering::Relaxed);
+        upstream(&lb, 1).outstanding.store(1, Ordering::Relaxed);
+        upstream(&lb, 2).outstanding.store(2, Ordering::Relaxed);
+        assert!((0..5).all(|_| select(&lb).name == URLS[1]));
+    }
+
+    #[test]
+    fn test_unhealthy_upstreams_are_skipped() {
+        let lb = load_balancer(LoadBalancingStrategy::RoundRobin);
+        let ejected = upstream(&lb, 1);
+        for _ in 0..lb.health_check.unhealthy_threshold {
+            ejected.record(false, &lb.health_check);
+        }
+        assert!(!ejected.is_healthy());
+        assert!((0..6).all(|_| select(&lb).name != URLS[1]));
+
+        ejected.record(true, &lb.health_check);
+        assert!(ejected.is_healthy());
+    }
+
+    #[tokio::test]
+    async fn test_probes_time_out() {
+        let hung = MockMightyClient::new().with_latency(Duration::from_secs(60));
+        let upstreams: Arc<Upstreams> = Arc::new(RwLock::new(vec![Arc::new(Upstream::new(
+            Endpoint::new(URLS[0].to_string()),
+            Box::new(hung),
+        ))]));
+        let config = HealthCheckConfig {
+            interval: MIN_INTERVAL,
+            timeout: Duration::from_millis(10),
+            unhealthy_threshold: 1,
+            ..Default::default()
+        };
+        let probes = tokio::spawn(probe_upstreams(Arc::downgrade(&upstreams), config));
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!upstreams.read().unwrap()[0].is_healthy());
+        probes.abort();
+    }
+
+    #[tokio::test]
+    async fn test_upstream_is_named_off_the_wire() {
+        let client: Box<dyn MightyClient> = Box::new(MockMightyClient::new());
+        let lb = LoadBalancedClient::new(
+            vec![(URLS[0].to_string(), client)],
+            LoadBalancingStrategy::RoundRobin,
+        );
+        let response = lb
+            .embeddings(Request::new(TextRequest {
+                text: "hello".to_string(),
+            }))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response.extensions().get::<ServedBy>(),
+            Some(&ServedBy(URLS[0].to_string()))
+        );
+        assert!(response.metadata().is_empty());
+    }
+
+    #[test]
+    fn test_all_unhealthy_falls_back_to_all_upstreams() {
+        let lb = load_balancer(LoadBalancingStrategy::Random);
+        for upstream in lb.upstreams.read().unwrap().iter() {
+            upstream.healthy.store(false, Ordering::Relaxed);
+        }
+        assert!((0..100).all(|_| URLS.contains(&select(&lb).name.as_str())));
+    }
+
+    #[test]
+    fn test_no_upstream_is_unavailable() {
+        let lb = load_balancer(LoadBalancingStrategy::RoundRobin);
+        lb.upstreams.write().unwrap().clear();
+        let status = lb.select(lb.route(None, &Empty {})).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+    }
+
+    #[test]
+    fn test_select_excluding_skips_excluded_upstream() {
+        let lb = load_balancer(LoadBalancingStrategy::Random);
+        let excluded = upstream(&lb, 0);
+        assert!((0..100).all(|_| lb
+            .select_excluding(lb.route(None, &Empty {}), Some(&excluded))
+            .unwrap()
+            .name
+            != URLS[0]));
+
+        let single = LoadBalancedClient::new(
+            vec![(
+                URLS[0].to_string(),
+                Box::new(MightyServerRestClient::new(URLS[0].to_string())),
+            )],
+            LoadBalancingStrategy::RoundRobin,
+        );
+        assert!(single
+            .select_excluding(single.route(None, &Empty {}), Some(&upstream(&single, 0)))
+            .is_none());
+    }
+
+    #[test]
+    fn test_consistent_hash_routes_identical_text_to_same_upstream() {
+        let lb =
+            load_balancer(LoadBalancingStrategy::RoundRobin).with_task_strategies(HashMap::from([
+                (Task::Embeddings, LoadBalancingStrategy::ConsistentHash),
+            ]));
+        let request = |text: &str| TextRequest {
+            text: text.to_string(),
+        };
+        let pick = |text: &str| {
+            lb.select(lb.route(Some(Task::Embeddings), &request(text)))
+                .unwrap()
+                .name
+                .clone()
+        };
+        let texts = (0..30).map(|i| format!("text {}", i)).collect::<Vec<_>>();
+        let picks = texts.iter().map(|text| pick(text)).collect::<Vec<_>>();
+        assert!(texts
+            .iter()
+            .zip(&picks)
+            .all(|(text, name)| &pick(text) == name));
+        assert!(URLS.iter().all(|url| picks.iter().any(|name| name == url)));
+
+        // Ejecting an upstream only moves the texts it served
+        upstream(&lb, 0).healthy.store(false, Ordering::Relaxed);
+        for (text, name) in texts.iter().zip(&picks) {
+            if name != URLS[0] {
+                assert_eq!(&pick(text), name);
+            }
+        }
+
+        // Other tasks keep the default strategy
+        let route = lb.route(Some(Task::TokenClassification), &request("text 0"));
+        assert_eq!(route.strategy, LoadBalancingStrategy::RoundRobin);
+        assert_eq!(route.key, None);
+    }
+
+    #[test]
+    fn test_routing_hash_is_stable() {
+        assert_eq!(hash([b"a".as_slice()]), 0x089be207b544f1e4);
+        assert_eq!(hash([b"a".as_slice(), b"b".as_slice()]), 0xab40d7820d408076);
+    }
+
+    #[test]
+    fn test_random_stays_in_range() {
+        let lb = load_balancer(LoadBalancingStrategy::Random);
+        assert!((0..100).all(|_| URLS.contains(&select(&lb).name.as_str())));
+    }
+
+    #[test]
+    fn test_reconcile_keeps_known_and_unresolved_upstreams() {
+        let lb = load_balancer(LoadBalancingStrategy::RoundRobin);
+        let kept = upstream(&lb, 1);
+        let factory: ClientFactory =
+            Arc::new(|endpoint| Box::new(MightyServerRestClient::new(endpoint.base_url.clone())));
+        let (a, b, c) = (
+            URLS[0].to_string(),
+            URLS[1].to_string(),
+            URLS[2].to_string(),
+        );
+        let pinned = Endpoint {
+            base_url: a.clone(),
+            addr: Some("10.0.0.1:5050".parse().unwrap()),
+        };
+
+        reconcile(
+            &lb.upstreams,
+            vec![
+                (&a, Some(vec![pinned.clone()])),
+                (&b, Some(vec![Endpoint::new(b.clone())])),
+                (&c, None),
+            ],
+            &factory,
+            None,
+        );
+
+        let upstreams = lb.upstreams.read().unwrap();
+        let endpoints = upstreams
+            .iter()
+            .map(|upstream| upstream.endpoint.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            endpoints,
+            vec![pinned, Endpoint::new(b.clone()), Endpoint::new(c.clone())]
+        );
+        assert!(Arc::ptr_eq(&upstreams[1], &kept));
+    }
+
+    #[test]
+    fn test_reconcile_ramps_new_upstreams_while_others_serve() {
+        let lb = load_balancer(LoadBalancingStrategy::RoundRobin);
+        let factory: ClientFactory =
+            Arc::new(|endpoint| Box::new(MightyServerRestClient::new(endpoint.base_url.clone())));
+        let ramp = Arc::new(RampConfig {
+            enabled: true,
+            ..RampConfig::default()
+        });
+        let (a, b, c, d) = (
+            URLS[0].to_string(),
+            URLS[1].to_string(),
+            URLS[2].to_string(),
+            "http://d:5050".to_string(),
+        );
+
+        reconcile(
+            &lb.upstreams,
+            vec![
+                (&a, Some(vec![Endpoint::new(a.clone())])),
+                (&b, Some(vec![Endpoint::new(b.clone())])),
+                (&c, Some(vec![Endpoint::new(c.clone())])),
+                (&d, Some(vec![Endpoint::new(d.clone())])),
+            ],
+            &factory,
+            Some(&ramp),
+        );
+        let ramping = upstream(&lb, 3);
+        assert!(ramping.ramp.is_some());
+        assert!((0..3).all(|i| upstream(&lb, i).ramp.is_none()));
+        // At 1% of its share the new upstream is rarely selected
+        let picks = (0..300)
+            .filter(|_| Arc::ptr_eq(&select(&lb), &ramping))
+            .count();
+        assert!(picks < 30, "ramping upstream picked {} times", picks);
+
+        // Replacing the whole set doesn't ramp, as nothing else would serve
+        reconcile(
+            &lb.upstreams,
+            vec![(&a, Some(vec![Endpoint::new("http://e:5050".to_string())]))],
+            &factory,
+            Some(&ramp),
+        );
+        assert!(upstream(&lb, 0).ramp.is_none());
+    }
+
+    #[test]
+    fn test_updated_upstreams_are_ramped() {
+        let factory: ClientFactory =
+            Arc::new(|endpoint| Box::new(MightyServerRestClient::new(endpoint.base_url.clone())));
+        let lb = load_balancer(LoadBalancingStrategy::RoundRobin)
+            .with_ramp(RampConfig {
+                enabled: true,
+                ..RampConfig::default()
+            })
+            .with_discovery(
+                URLS.map(str::to_string).to_vec(),
+                &DiscoveryConfig::default(),
+                factory,
+            );
+        let kept = upstream(&lb, 1);
+        let (b, c, d) = (
+            URLS[1].to_string(),
+            URLS[2].to_string(),
+            "http://d:5050".to_string(),
+        );
+
+        assert!(lb.update_upstreams(&[b, c, d.clone()]));
+        let upstreams = lb.upstreams.read().unwrap().clone();
+        let names = upstreams
+            .iter()
+            .map(|upstream| upstream.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec![URLS[1], URLS[2], d.as_str()]);
+        assert!(Arc::ptr_eq(&upstreams[0], &kept));
+        assert!(upstreams[0].ramp.is_none());
+        assert!(upstreams[2].ramp.is_some());
+        assert_eq!(
+            *lb.base_urls.read().unwrap(),
+            vec![URLS[1], URLS[2], d.as_str()]
+        );
+
+        // Nothing would keep serving while a whole new set ramps up
+        assert!(!lb.update_upstreams(&["http://e:5050".to_string()]));
+        assert_eq!(lb.upstreams.read().unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,278 @@
+use std::time::Instant;
+
+use async_trait::async_trait;
+use tonic::{Code, Request, Response, Status};
+
+use crate::proto::mighty_proto::{
+    EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse, QuestionAnswerRequest,
+    QuestionAnswerResponse, RerankRequest, RerankResponse, SentenceTransformersResponse,
+    SequenceClassificationResponse, TextRequest, TokenClassificationResponse,
+};
+use crate::services::access_log::AccessRecord;
+use crate::services::metrics::{Metrics, DURATION_BUCKETS};
+
+use super::MightyClient;
+
+/// Counter of upstream calls, by method.
+const CALLS_METRIC: &str = "mighty_upstream_calls_total";
+
+/// Counter of failed upstream calls, by method and status `class`.
+const ERRORS_METRIC: &str = "mighty_upstream_errors_total";
+
+/// Histogram of upstream call durations as measured by the gateway, by method.
+const DURATION_METRIC: &str = "mighty_upstream_call_duration_seconds";
+
+/// Histogram of inference durations as reported by the upstream (`took`), by method.
+const INFERENCE_METRIC: &str = "mighty_upstream_inference_duration_seconds";
+
+/// Returns the class of an upstream error: `timeout`, `server` when the upstream failed, or
+/// `client` when the request was rejected.
+fn error_class(status: &Status) -> &'static str {
+    match status.code() {
+        Code::DeadlineExceeded | Code::Cancelled => "timeout",
+        Code::Unavailable
+        | Code::Internal
+        | Code::Unknown
+        | Code::DataLoss
+        | Code::Unimplemented
+        | Code::ResourceExhausted => "server",
+        _ => "client",
+    }
+}
+
+/// The `MeteredClient` struct is a `MightyClient` decorator recording statistics of the calls
+/// made to the wrapped client: their count, their errors by status class, their duration as
+/// measured by the gateway and, for inference calls, the inference duration reported by the
+/// upstream in `took`. The difference between both durations is the time spent outside of
+/// inference (network, queueing, serialization), which matters for capacity planning. The
+/// inference duration is also reported to the access log line of the call, if any.
+pub struct MeteredClient {
+    inner: Box<dyn MightyClient>,
+}
+
+impl MeteredClient {
+    pub fn new(inner: Box<dyn MightyClient>) -> Self {
+        Self { inner }
+    }
+
+    /// Makes the `call` of `method`, recording its statistics, and the inference duration
+    /// extracted from its response by `took`, in milliseconds.
+    async fn metered<R, T, F>(
+        &self,
+        method: &'static str,
+        request: Request<R>,
+        call: F,
+        took: fn(&T) -> Option<i32>,
+    ) -> Result<Response<T>, Status>
+    where
+        F: for<'c> FnOnce(
+            &'c dyn MightyClient,
+            Request<R>,
+        ) -> futures::future::BoxFuture<'c, Result<Response<T>, Status>>,
+    {
+        let metrics = Metrics::global();
+        let labels = [("method", method)];
+        metrics.counter(CALLS_METRIC, &labels).increment(1);
+        let access_record = AccessRecord::of(&request);
+        let started = Instant::now();
+        let result = call(self.inner.as_ref(), request).await;
+        metrics
+            .histogram(DURATION_METRIC, &labels, DURATION_BUCKETS)
+            .observe(started.elapsed().as_secs_f64());
+        match &result {
+            Ok(response) => {
+                if let Some(took) = took(response.get_ref()) {
+                    metrics
+                        .histogram(INFERENCE_METRIC, &labels, DURATION_BUCKETS)
+                        .observe(f64::from(took) / 1000.0);
+                    if let Some(access_record) = &access_record {
+                        access_record.set_upstream_took(took);
+                    }
+                }
+            }
+            Err(status) => {
+                metrics
+                    .counter(
+                        ERRORS_METRIC,
+                        &[("method", method), ("class", error_class(status))],
+                    )
+                    .increment(1);
+            }
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl MightyClient for MeteredClient {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        self.metered(
+            "health_check",
+            request,
+            |client, request| client.health_check(request),
+            |_| None,
+        )
+        .await
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        self.metered(
+            "embeddings",
+            request,
+            |client, request| client.embeddings(request),
+            |response| Some(response.took),
+        )
+        .await
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        self.metered(
+            "question_answering",
+            request,
+            |client, request| client.question_answering(request),
+            |response| Some(response.took),
+        )
+        .await
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        self.metered(
+            "sentence_transformers",
+            request,
+            |client, request| client.sentence_transformers(request),
+            |response| Some(response.took),
+        )
+        .await
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        self.metered(
+            "sequence_classification",
+            request,
+            |client, request| client.sequence_classification(request),
+            |response| Some(response.took),
+        )
+        .await
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        self.metered(
+            "token_classification",
+            request,
+            |client, request| client.token_classification(request),
+            |response| Some(response.took),
+        )
+        .await
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        self.metered(
+            "metadata",
+            request,
+            |client, request| client.metadata(request),
+            |_| None,
+        )
+        .await
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        self.metered(
+            "rerank",
+            request,
+            |client, request| client.rerank(request),
+            |response| Some(response.took),
+        )
+        .await
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        // The texts of a batch are embedded by a single inference reporting the same `took`
+        self.metered(
+            "embeddings_batch",
+            request,
+            |client, request| client.embeddings_batch(request),
+            |responses| responses.iter().map(|response| response.took).max(),
+        )
+        .await
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        self.metered(
+            "token_classification_batch",
+            request,
+            |client, request| client.token_classification_batch(request),
+            |responses| responses.iter().map(|response| response.took).max(),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Task;
+    use crate::services::clients::mock::MockMightyClient;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_calls_errors_and_durations_are_recorded() {
+        let mock = MockMightyClient::new()
+            .with_sequence_classification(SequenceClassificationResponse {
+                took: 250,
+                ..Default::default()
+            })
+            .with_failures(Task::TokenClassification, 1, Status::unavailable("down"));
+        let client = MeteredClient::new(Box::new(mock));
+        let metrics = Metrics::global();
+        let labels = [("method", "sequence_classification")];
+        let inference = metrics.histogram(INFERENCE_METRIC, &labels, DURATION_BUCKETS);
+        let (calls, sum) = (inference.count(), inference.sum());
+        let errors = metrics.counter(
+            ERRORS_METRIC,
+            &[("method", "token_classification"), ("class", "server")],
+        );
+        let failures = errors.get();
+
+        let text = || {
+            Request::new(TextRequest {
+                text: "a".to_string(),
+            })
+        };
+        client.sequence_classification(text()).await.unwrap();
+        assert!(client.token_classification(text()).await.is_err());
+
+        assert_eq!(inference.count(), calls + 1);
+        assert_eq!(inference.sum() - sum, 0.25);
+        assert_eq!(errors.get(), failures + 1);
+    }
+}
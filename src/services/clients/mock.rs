@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tonic::{Request, Response, Status};
+
+use crate::config::Task;
+use crate::proto::mighty_proto::{
+    Embedding, EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse,
+    QuestionAnswerRequest, QuestionAnswerResponse, RankedText, RerankRequest, RerankResponse,
+    SentenceTransformersResponse, SequenceClassificationResponse, Shape, TextRequest,
+    TokenClassificationResponse,
+};
+use crate::services::hash::reference;
+
+use super::MightyClient;
+
+/// An error injected into the calls of a task.
+struct InjectedError {
+    status: Status,
+    /// The number of calls still failing, every call failing when `None`.
+    remaining: Option<usize>,
+}
+
+/// The `MockMightyClient` struct is a `MightyClient` serving canned responses without any
+/// upstream, for unit tests of code embedding the gateway. It is available with the `mock`
+/// feature, e.g. as a dev-dependency:
+///
+/// ```toml
+/// [dev-dependencies]
+/// mighty-grpc = { version = "0.1", features = ["mock"] }
+/// ```
+///
+/// Unless a canned response is set, calls succeed with a deterministic response echoing their
+/// input: embeddings are pseudo-random vectors of `dimension` values derived from the text, so
+/// identical texts get identical vectors, and reranking keeps the texts' order. Latency and
+/// errors can be injected, and the calls of each task are counted.
+///
+/// ```
+/// use mighty_grpc::config::Task;
+/// use mighty_grpc::services::clients::mock::MockMightyClient;
+/// use tonic::Status;
+///
+/// let client = MockMightyClient::new()
+///     .with_dimension(384)
+///     .with_failures(Task::QuestionAnswering, 2, Status::unavailable("warming up"));
+/// assert_eq!(client.calls(Task::Embeddings), 0);
+/// ```
+pub struct MockMightyClient {
+    dimension: usize,
+    latency: Duration,
+    embeddings: Option<EmbeddingsResponse>,
+    question_answering: Option<QuestionAnswerResponse>,
+    sentence_transformers: Option<SentenceTransformersResponse>,
+    sequence_classification: Option<SequenceClassificationResponse>,
+    token_classification: Option<TokenClassificationResponse>,
+    metadata: HashMap<String, String>,
+    rerank: Option<RerankResponse>,
+    errors: Mutex<HashMap<Task, InjectedError>>,
+    calls: HashMap<Task, AtomicUsize>,
+}
+
+impl Default for MockMightyClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockMightyClient {
+    /// Creates a client answering every call, with 4-dimensional embeddings.
+    pub fn new() -> Self {
+        Self {
+            dimension: 4,
+            latency: Duration::ZERO,
+            embeddings: None,
+            question_answering: None,
+            sentence_transformers: None,
+            sequence_classification: None,
+            token_classification: None,
+            metadata: HashMap::from([("backend".to_string(), "mock".to_string())]),
+            rerank: None,
+            errors: Mutex::new(HashMap::new()),
+            calls: Task::ALL
+                .into_iter()
+                .map(|task| (task, AtomicUsize::new(0)))
+                .collect(),
+        }
+    }
+
+    /// Sets the dimension of the generated embeddings.
+    pub fn with_dimension(mut self, dimension: usize) -> Self {
+        self.dimension = dimension;
+        self
+    }
+
+    /// Delays every call, health checks and metadata included, by `latency`.
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Fails every call of `task` with `status`.
+    pub fn with_error(self, task: Task, status: Status) -> Self {
+        self.inject(task, status, None)
+    }
+
+    /// Fails the next `count` calls of `task` with `status`, e.g. to test retries.
+    pub fn with_failures(self, task: Task, count: usize, status: Status) -> Self {
+        self.inject(task, status, Some(count))
+    }
+
+    fn inject(self, task: Task, status: Status, remaining: Option<usize>) -> Self {
+        self.errors
+            .lock()
+            .unwrap()
+            .insert(task, InjectedError { status, remaining });
+        self
+    }
+
+    pub fn with_embeddings(mut self, response: EmbeddingsResponse) -> Self {
+        self.embeddings = Some(response);
+        self
+    }
+
+    pub fn with_question_answering(mut self, response: QuestionAnswerResponse) -> Self {
+        self.question_answering = Some(response);
+        self
+    }
+
+    pub fn with_sentence_transformers(mut self, response: SentenceTransformersResponse) -> Self {
+        self.sentence_transformers = Some(response);
+        self
+    }
+
+    pub fn with_sequence_classification(
+        mut self,
+        response: SequenceClassificationResponse,
+    ) -> Self {
+        self.sequence_classification = Some(response);
+        self
+    }
+
+    pub fn with_token_classification(mut self, response: TokenClassificationResponse) -> Self {
+        self.token_classification = Some(response);
+        self
+    }
+
+    pub fn with_metadata(mut self, metadata: HashMap<String, String>) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    pub fn with_rerank(mut self, response: RerankResponse) -> Self {
+        self.rerank = Some(response);
+        self
+    }
+
+    /// Returns the number of calls of `task` made so far, failed ones included.
+    pub fn calls(&self, task: Task) -> usize {
+        self.calls[&task].load(Ordering::Relaxed)
+    }
+
+    /// Counts a call of `task`, applying the injected latency and error, if any.
+    async fn call(&self, task: Option<Task>) -> Result<(), Status> {
+        if !self.latency.is_zero() {
+            tokio::time::sleep(self.latency).await;
+        }
+        let Some(task) = task else {
+            return Ok(());
+        };
+        self.calls[&task].fetch_add(1, Ordering::Relaxed);
+        let mut errors = self.errors.lock().unwrap();
+        let Some(error) = errors.get_mut(&task) else {
+            return Ok(());
+        };
+        let status = error.status.clone();
+        match &mut error.remaining {
+            None => Err(status),
+            Some(0) => Ok(()),
+            Some(remaining) => {
+                *remaining -= 1;
+                Err(status)
+            }
+        }
+    }
+
+    /// Returns the generated embedding of `text`, with values in [-1, 1).
+    fn embedding(&self, text: &str) -> Embedding {
+        let mut state = reference(text);
+        let values = (0..self.dimension)
+            .map(|_| {
+                // A 64-bit linear congruential generator seeded with the text's hash
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+            })
+            .collect();
+        Embedding { values }
+    }
+
+    fn shape(&self) -> Option<Shape> {
+        Some(Shape {
+            dim1: 1,
+            dim2: self.dimension as i32,
+        })
+    }
+}
+
+#[async_trait]
+impl MightyClient for MockMightyClient {
+    async fn health_check(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        self.call(None).await?;
+        Ok(Response::new(HealthcheckResponse { success: true }))
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        self.call(Some(Task::Embeddings)).await?;
+        let text = request.into_inner().text;
+        let response = self
+            .embeddings
+            .clone()
+            .unwrap_or_else(|| EmbeddingsResponse {
+                embeddings: vec![self.embedding(&text)],
+                took: 0,
+                text,
+                shape: self.shape(),
+            });
+        Ok(Response::new(response))
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        self.call(Some(Task::QuestionAnswering)).await?;
+        let request = request.into_inner();
+        let response = self
+            .question_answering
+            .clone()
+            .unwrap_or_else(|| QuestionAnswerResponse {
+                question: request.question,
+                context: request.context,
+                ..Default::default()
+            });
+        Ok(Response::new(response))
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        self.call(Some(Task::SentenceTransformers)).await?;
+        let text = request.into_inner().text;
+        let response = self.sentence_transformers.clone().unwrap_or_else(|| {
+            let mut embedding = self.embedding(&text);
+            let norm = embedding
+                .values
+                .iter()
+                .map(|value| value * value)
+                .sum::<f32>()
+                .sqrt();
+            if norm > 0.0 {
+                embedding.values.iter_mut().for_each(|value| *value /= norm);
+            }
+            SentenceTransformersResponse {
+                took: 0,
+                text,
+                embeddings: vec![embedding],
+                shape: self.shape(),
+            }
+        });
+        Ok(Response::new(response))
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        self.call(Some(Task::SequenceClassification)).await?;
+        let response = self.sequence_classification.clone().unwrap_or_else(|| {
+            SequenceClassificationResponse {
+                text: request.into_inner().text,
+                ..Default::default()
+            }
+        });
+        Ok(Response::new(response))
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        self.call(Some(Task::TokenClassification)).await?;
+        let response = self.token_classification.clone().unwrap_or_else(|| {
+            TokenClassificationResponse {
+                text: request.into_inner().text,
+                ..Default::default()
+            }
+        });
+        Ok(Response::new(response))
+    }
+
+    async fn metadata(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        self.call(None).await?;
+        Ok(Response::new(MetadataResponse {
+            metadata: self.metadata.clone(),
+        }))
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        self.call(Some(Task::Rerank)).await?;
+        let request = request.into_inner();
+        let response = self.rerank.clone().unwrap_or_else(|| RerankResponse {
+            took: 0,
+            results: (0..request.texts.len())
+                .map(|index| RankedText {
+                    index: index as u32,
+                    score: 1.0 / (index + 1) as f32,
+                })
+                .collect(),
+            query: request.query,
+        });
+        Ok(Response::new(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use super::*;
+
+    fn text(text: &str) -> Request<TextRequest> {
+        Request::new(TextRequest {
+            text: text.to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_generated_embeddings_are_deterministic() {
+        let client = MockMightyClient::new().with_dimension(8);
+        let hello = client.embeddings(text("hello")).await.unwrap().into_inner();
+        let again = client.embeddings(text("hello")).await.unwrap().into_inner();
+        let world = client.embeddings(text("world")).await.unwrap().into_inner();
+
+        assert_eq!(hello, again);
+        assert_ne!(hello.embeddings, world.embeddings);
+        assert_eq!(hello.embeddings[0].values.len(), 8);
+        assert!(hello.embeddings[0]
+            .values
+            .iter()
+            .all(|value| (-1.0..1.0).contains(value)));
+        assert_eq!(client.calls(Task::Embeddings), 3);
+    }
+
+    #[tokio::test]
+    async fn test_injected_failures_and_canned_responses() {
+        let client = MockMightyClient::new()
+            .with_failures(Task::Embeddings, 2, Status::unavailable("down"))
+            .with_error(Task::TokenClassification, Status::internal("broken"))
+            .with_sequence_classification(SequenceClassificationResponse {
+                logits: vec![0.25, 0.75],
+                ..Default::default()
+            });
+
+        for _ in 0..2 {
+            let status = client.embeddings(text("a")).await.unwrap_err();
+            assert_eq!(status.code(), Code::Unavailable);
+        }
+        assert!(client.embeddings(text("a")).await.is_ok());
+        for _ in 0..2 {
+            let status = client.token_classification(text("a")).await.unwrap_err();
+            assert_eq!(status.code(), Code::Internal);
+        }
+        let response = client.sequence_classification(text("a")).await.unwrap();
+        assert_eq!(response.into_inner().logits, [0.25, 0.75]);
+    }
+}
@@ -1,27 +1,119 @@
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+
 use async_trait::async_trait;
-use tonic::{Request, Response, Status};
+use futures::future::try_join_all;
+use reqwest::StatusCode;
+use tonic::{Code, Request, Response, Status};
 
+use crate::config::BackendConfig;
 use crate::proto::mighty_proto::{
     EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse, QuestionAnswerRequest,
-    QuestionAnswerResponse, SentenceTransformersResponse, SequenceClassificationResponse,
-    TextRequest, TokenClassificationResponse,
+    QuestionAnswerResponse, RerankRequest, RerankResponse, SentenceTransformersResponse,
+    SequenceClassificationResponse, TextRequest, TokenClassificationResponse,
 };
+use crate::services::context::RequestContext;
 
+pub mod batching;
 #[cfg(feature = "binary")]
 pub mod binary;
+pub mod caching;
+pub mod canary;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod circuit_breaker;
+pub mod content_encoding;
+pub mod discovery;
+pub mod fallback;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(any(feature = "rest", feature = "binary"))]
+pub mod grpc_upstream;
+pub mod hedging;
 pub mod json_response_converters;
-#[cfg(feature = "rest")]
+pub mod load_balancer;
+pub mod metered;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+#[cfg(any(feature = "onnx", feature = "edge"))]
+pub mod onnx;
+#[cfg(feature = "openai")]
+pub mod openai;
+pub mod quota;
+pub mod ramp;
+pub mod rate_limit;
+#[cfg(feature = "redis")]
+pub mod redis_cache;
+pub mod response_format;
+#[cfg(any(feature = "rest", feature = "binary"))]
 pub mod rest;
+pub mod retrying;
+pub mod routing;
+pub mod shadow;
+pub mod single_flight;
+pub mod switchable;
+pub mod task_routing;
+#[cfg(feature = "tei")]
+pub mod tei;
+pub mod traced;
+#[cfg(any(feature = "rest", feature = "binary"))]
+pub mod unix_socket;
+pub mod validating;
+pub mod vcr;
+
+/// Returns whether a call error indicates a problem with the upstream itself, as opposed to a
+/// problem with the request.
+pub fn is_upstream_failure(status: &Status) -> bool {
+    matches!(
+        status.code(),
+        Code::Unavailable | Code::Internal | Code::DeadlineExceeded | Code::Unknown
+    )
+}
+
+/// Copies a request, including its metadata and `RequestContext`, so it can be sent again, e.g.
+/// to a second upstream. Other extensions are not carried over.
+pub fn duplicate<R: Clone>(request: &Request<R>) -> Request<R> {
+    let mut duplicate = Request::new(request.get_ref().clone());
+    *duplicate.metadata_mut() = request.metadata().clone();
+    if let Some(context) = RequestContext::get(request) {
+        duplicate.extensions_mut().insert(context.clone());
+    }
+    duplicate
+}
+
+/// Maps an upstream HTTP error status to the closest gRPC status, e.g. `429 Too Many Requests`
+/// to `RESOURCE_EXHAUSTED`, for backends reporting errors through HTTP statuses.
+pub fn status_from_http(status: StatusCode, message: String) -> Status {
+    match status {
+        StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => {
+            Status::invalid_argument(message)
+        }
+        StatusCode::UNAUTHORIZED => Status::unauthenticated(message),
+        StatusCode::FORBIDDEN => Status::permission_denied(message),
+        StatusCode::NOT_FOUND => Status::not_found(message),
+        StatusCode::PAYLOAD_TOO_LARGE | StatusCode::TOO_MANY_REQUESTS => {
+            Status::resource_exhausted(message)
+        }
+        StatusCode::REQUEST_TIMEOUT | StatusCode::GATEWAY_TIMEOUT => {
+            Status::deadline_exceeded(message)
+        }
+        status if status.is_server_error() => Status::unavailable(message),
+        _ => Status::internal(message),
+    }
+}
 
 /// The `MightyClient` trait defines a set of asynchronous methods for interacting with a variety of
 /// natural language processing (NLP) services. Implementations of this trait are expected to provide
 /// methods for health checking, obtaining embeddings, answering questions, performing sentence
 /// transformations, sequence classification, token classification, and fetching metadata.
 ///
-/// Each method takes a specific request type and returns a corresponding response type wrapped in
-/// a `Result`. The `Result` type contains either a successful `Response` or a `Status` indicating an
-/// error. The trait requires implementations to be both `Send` and `Sync` to ensure thread safety in
-/// asynchronous contexts.
+/// Each method takes a specific request type and returns a corresponding response type wrapped in a
+/// `Result`. The `Result` type contains either a successful `Response` or a `Status` indicating an
+/// error. Requests arriving through `MightyInferenceServerProxy` carry a
+/// [`RequestContext`](crate::services::context::RequestContext) in their extensions, available via
+/// `RequestContext::get(&request)`. The trait requires implementations to be both `Send` and `Sync`
+/// to ensure thread safety in asynchronous contexts.
 ///
 /// # Methods
 ///
@@ -32,6 +124,13 @@ pub mod rest;
 /// * `sequence_classification`: Returns output probabilities (logits - unnormalized final scores of your model).
 /// * `token_classification`: Identifies and classifies tokens (e.g., named entities) within a text.
 /// * `metadata`: Fetches The Mighty Inference Server configuration and model metadata.
+/// * `rerank`: Scores texts by relevance to a query. Only some backends serve rerankers, so the
+///   default implementation fails with `UNIMPLEMENTED`; decorators forward it.
+/// * `embeddings_batch`: Retrieves the embeddings of several texts, in order. The default
+///   implementation makes one `embeddings` call per text, concurrently; backends with a batch
+///   API (e.g. TEI) override it to make a single upstream call.
+/// * `token_classification_batch`: Classifies the tokens of several texts, in order, like
+///   `embeddings_batch`.
 #[async_trait]
 pub trait MightyClient: Send + Sync {
     async fn health_check(
@@ -68,4 +167,174 @@ pub trait MightyClient: Send + Sync {
         &self,
         _request: Request<Empty>,
     ) -> Result<Response<MetadataResponse>, Status>;
+
+    async fn rerank(
+        &self,
+        _request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        Err(Status::unimplemented("The backend doesn't serve rerank"))
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        one_by_one(request, |request| self.embeddings(request)).await
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        one_by_one(request, |request| self.token_classification(request)).await
+    }
+}
+
+/// Serves a batch call with one `call` per text, concurrently, each carrying the
+/// `RequestContext` of the batch, for backends without a batch API.
+pub async fn one_by_one<T, F>(
+    request: Request<Vec<String>>,
+    call: impl Fn(Request<TextRequest>) -> F,
+) -> Result<Response<Vec<T>>, Status>
+where
+    F: Future<Output = Result<Response<T>, Status>>,
+{
+    let context = RequestContext::get(&request).cloned();
+    let calls = request.into_inner().into_iter().map(|text| {
+        let mut request = Request::new(TextRequest { text });
+        if let Some(context) = &context {
+            request.extensions_mut().insert(context.clone());
+        }
+        call(request)
+    });
+    let responses = try_join_all(calls).await?;
+    Ok(Response::new(
+        responses.into_iter().map(Response::into_inner).collect(),
+    ))
+}
+
+/// A backend able to switch to another model version while serving, e.g. the `BinaryClient`.
+#[async_trait]
+pub trait ModelUpgrade: Send + Sync {
+    /// Loads the model version in `model_dir` (the current one when `None`) and switches traffic
+    /// to it once loaded, returning the generation now serving. The current version keeps
+    /// serving if loading fails.
+    async fn upgrade(&self, model_dir: Option<PathBuf>) -> Result<u64, Status>;
+}
+
+/// A client whose backend can be replaced while serving, e.g. the `SwitchableClient`.
+#[async_trait]
+pub trait BackendSwitch: Send + Sync {
+    /// Creates a backend of the given kind and switches traffic to it once healthy, returning
+    /// the generation now serving. The current backend keeps serving if the new one fails.
+    async fn switch(&self, backend: &BackendConfig) -> Result<u64, Status>;
+}
+
+/// A client whose upstream instances can be replaced while serving, e.g. the
+/// `LoadBalancedClient`.
+pub trait UpstreamUpdate: Send + Sync {
+    /// Serves the upstreams of `base_urls`, keeping those already served, with their health
+    /// state, and ramping up the others. Returns `false`, leaving the upstreams untouched, when
+    /// none of `base_urls` is served already: nothing would keep serving while the new upstreams
+    /// ramp up, so they should rather be switched to once healthy (see `BackendSwitch`).
+    fn update_upstreams(&self, base_urls: &[String]) -> bool;
+}
+
+/// A client injecting faults into upstream calls, e.g. the `ChaosClient`.
+pub trait FaultInjection: Send + Sync {
+    /// Starts or stops injecting faults.
+    fn set_enabled(&self, enabled: bool);
+}
+
+/// A client caching responses, e.g. the `CachingClient`.
+#[async_trait]
+pub trait CacheFlush: Send + Sync {
+    /// Evicts every cached response, returning the number evicted.
+    async fn flush(&self) -> Result<u64, Status>;
+}
+
+/// Flushes several caches, e.g. those of the primary backend and of the routed models.
+#[async_trait]
+impl CacheFlush for Vec<Arc<dyn CacheFlush>> {
+    async fn flush(&self) -> Result<u64, Status> {
+        let mut flushed = 0;
+        for cache in self {
+            flushed += cache.flush().await?;
+        }
+        Ok(flushed)
+    }
+}
+
+/// Allows a client to be shared, e.g. between the inference server and background tasks.
+#[async_trait]
+impl MightyClient for Arc<dyn MightyClient> {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        self.as_ref().health_check(request).await
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        self.as_ref().embeddings(request).await
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        self.as_ref().question_answering(request).await
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        self.as_ref().sentence_transformers(request).await
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        self.as_ref().sequence_classification(request).await
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        self.as_ref().token_classification(request).await
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        self.as_ref().metadata(request).await
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        self.as_ref().rerank(request).await
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        self.as_ref().embeddings_batch(request).await
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        self.as_ref().token_classification_batch(request).await
+    }
 }
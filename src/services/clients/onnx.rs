@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::Instant;
+
+use async_trait::async_trait;
+use log::debug;
+use ort::{GraphOptimizationLevel, Session, Tensor};
+use tokenizers::{Tokenizer, TruncationParams};
+use tonic::{Request, Response, Status};
+
+use crate::config::{OnnxConfig, Task};
+use crate::proto::mighty_proto::{
+    Embedding, EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse,
+    QuestionAnswerRequest, QuestionAnswerResponse, SentenceTransformersResponse,
+    SequenceClassificationResponse, Shape, TextRequest, TokenClassificationResponse,
+};
+
+use super::MightyClient;
+
+/// The tasks an ONNX model can be configured to serve.
+pub const SUPPORTED_TASKS: [Task; 2] = [Task::Embeddings, Task::SequenceClassification];
+
+/// The model output for one text, as a flat vector along with the inference time.
+struct Inference {
+    values: Vec<f32>,
+    took: i32,
+}
+
+/// The `OnnxClient` struct implements the `MightyClient` trait by running an ONNX model
+/// in-process through ONNX Runtime, which removes the need for a separate Mighty process in
+/// simple deployments.
+///
+/// The model serves a single task, configured along with its Hugging Face tokenizer:
+/// - `embeddings` models (e.g. sentence transformers exported with Optimum) serve the
+///   `embeddings` and `sentence_transformers` tasks, their token embeddings being mean pooled
+///   over the attention mask (and normalized for sentence transformers);
+/// - `sequence_classification` models return their logits.
+///
+/// Other tasks fail with `UNIMPLEMENTED`. Inference calls block, so they run on Tokio's
+/// blocking thread pool.
+pub struct OnnxClient {
+    session: Arc<Session>,
+    tokenizer: Arc<Tokenizer>,
+    task: Task,
+    metadata: HashMap<String, String>,
+}
+
+impl OnnxClient {
+    /// Loads the ONNX Runtime library, the model and the tokenizer configured in `config`.
+    pub fn open(config: &OnnxConfig) -> Result<Self, String> {
+        if !SUPPORTED_TASKS.contains(&config.task) {
+            return Err(format!(
+                "ONNX models can't serve the {} task",
+                config.task.as_str()
+            ));
+        }
+
+        let mut tokenizer = Tokenizer::from_file(&config.tokenizer)
+            .map_err(|e| format!("Failed to load {}: {}", config.tokenizer.display(), e))?;
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: config.max_length,
+                ..Default::default()
+            }))
+            .map_err(|e| format!("Invalid max_length: {}", e))?;
+
+        // ONNX Runtime panics when its library can't be loaded, so the panic is turned into a
+        // startup error
+        let session = panic::catch_unwind(AssertUnwindSafe(|| Self::session(config)))
+            .map_err(|_| "Failed to load the ONNX Runtime library".to_string())?
+            .map_err(|e| format!("Failed to load {}: {}", config.model.display(), e))?;
+
+        let metadata = HashMap::from([
+            ("backend".to_string(), "onnx".to_string()),
+            ("model".to_string(), config.model.display().to_string()),
+            ("task".to_string(), config.task.as_str().to_string()),
+            ("max_length".to_string(), config.max_length.to_string()),
+        ]);
+        Ok(Self {
+            session: Arc::new(session),
+            tokenizer: Arc::new(tokenizer),
+            task: config.task,
+            metadata,
+        })
+    }
+
+    fn session(config: &OnnxConfig) -> ort::Result<Session> {
+        // Edge bundles link ONNX Runtime statically, so only `onnx` builds load the library
+        #[cfg(feature = "onnx")]
+        if let Some(library) = &config.runtime_library {
+            ort::init_from(library.display().to_string()).commit()?;
+        }
+        let mut builder =
+            Session::builder()?.with_optimization_level(GraphOptimizationLevel::Level3)?;
+        if let Some(threads) = config.intra_threads {
+            builder = builder.with_intra_threads(threads)?;
+        }
+        builder.commit_from_file(&config.model)
+    }
+
+    /// Fails unless the model serves `task`.
+    fn serves(&self, task: Task) -> Result<(), Status> {
+        let served = match self.task {
+            Task::Embeddings => matches!(task, Task::Embeddings | Task::SentenceTransformers),
+            served => served == task,
+        };
+        match served {
+            true => Ok(()),
+            false => Err(self.unsupported(task)),
+        }
+    }
+
+    fn unsupported(&self, task: Task) -> Status {
+        Status::unimplemented(format!(
+            "The ONNX model serves {}, not {}",
+            self.task.as_str(),
+            task.as_str()
+        ))
+    }
+
+    /// Tokenizes `text` and runs the model on it, pooling token embeddings if any.
+    async fn infer(&self, text: String) -> Result<Inference, Status> {
+        debug!("Running the ONNX model on {} characters", text.len());
+        let session = self.session.clone();
+        let tokenizer = self.tokenizer.clone();
+        tokio::task::spawn_blocking(move || run(&session, &tokenizer, &text))
+            .await
+            .map_err(|e| Status::internal(format!("ONNX inference panicked: {}", e)))?
+    }
+
+    /// Runs the model on each of `texts` in turn, in a single blocking task.
+    async fn infer_batch(&self, texts: Vec<String>) -> Result<Vec<Inference>, Status> {
+        debug!("Running the ONNX model on {} texts", texts.len());
+        let session = self.session.clone();
+        let tokenizer = self.tokenizer.clone();
+        tokio::task::spawn_blocking(move || {
+            texts
+                .iter()
+                .map(|text| run(&session, &tokenizer, text))
+                .collect()
+        })
+        .await
+        .map_err(|e| Status::internal(format!("ONNX inference panicked: {}", e)))?
+    }
+}
+
+fn run(session: &Session, tokenizer: &Tokenizer, text: &str) -> Result<Inference, Status> {
+    let started = Instant::now();
+    let encoding = tokenizer
+        .encode(text, true)
+        .map_err(|e| Status::invalid_argument(format!("Failed to tokenize the text: {}", e)))?;
+    let to_i64 = |values: &[u32]| values.iter().map(|&v| i64::from(v)).collect::<Vec<_>>();
+    let mask = to_i64(encoding.get_attention_mask());
+    let shape = vec![1, mask.len() as i64];
+
+    let mut inputs = Vec::with_capacity(session.inputs.len());
+    for input in &session.inputs {
+        let values = match input.name.as_str() {
+            "input_ids" => to_i64(encoding.get_ids()),
+            "attention_mask" => mask.clone(),
+            "token_type_ids" => to_i64(encoding.get_type_ids()),
+            name => {
+                return Err(Status::internal(format!(
+                    "Unsupported ONNX model input {}",
+                    name
+                )))
+            }
+        };
+        let tensor = Tensor::from_array((shape.clone(), values)).map_err(onnx_error)?;
+        inputs.push((input.name.as_str(), tensor.into_dyn()));
+    }
+
+    let outputs = session.run(inputs).map_err(onnx_error)?;
+    let (dimensions, values) = outputs[0]
+        .try_extract_raw_tensor::<f32>()
+        .map_err(onnx_error)?;
+    let values = match dimensions.as_slice() {
+        // Token embeddings, mean pooled over the attention mask
+        [1, tokens, hidden] => mean_pool(values, &mask, *tokens as usize, *hidden as usize),
+        [1, _] => values.to_vec(),
+        dimensions => {
+            return Err(Status::internal(format!(
+                "Unexpected ONNX model output shape {:?}",
+                dimensions
+            )))
+        }
+    };
+    Ok(Inference {
+        values,
+        took: started.elapsed().as_millis() as i32,
+    })
+}
+
+fn onnx_error(error: ort::Error) -> Status {
+    Status::internal(format!("ONNX Runtime error: {}", error))
+}
+
+/// Averages the `tokens` embeddings of size `hidden` laid out in `values`, over the tokens the
+/// attention `mask` keeps.
+fn mean_pool(values: &[f32], mask: &[i64], tokens: usize, hidden: usize) -> Vec<f32> {
+    let mut pooled = vec![0.0; hidden];
+    let mut kept = 0.0;
+    for (token, embedding) in values.chunks_exact(hidden).take(tokens).enumerate() {
+        if mask.get(token).copied().unwrap_or(0) == 0 {
+            continue;
+        }
+        kept += 1.0;
+        for (sum, value) in pooled.iter_mut().zip(embedding) {
+            *sum += value;
+        }
+    }
+    if kept > 0.0 {
+        pooled.iter_mut().for_each(|sum| *sum /= kept);
+    }
+    pooled
+}
+
+fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|value| *value /= norm);
+    }
+    vector
+}
+
+fn shape(values: &[f32]) -> Option<Shape> {
+    Some(Shape {
+        dim1: 1,
+        dim2: values.len() as i32,
+    })
+}
+
+#[async_trait]
+impl MightyClient for OnnxClient {
+    async fn health_check(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        // The model is loaded once the client is created
+        Ok(Response::new(HealthcheckResponse { success: true }))
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        self.serves(Task::Embeddings)?;
+        let text = request.into_inner().text;
+        let Inference { values, took } = self.infer(text.clone()).await?;
+        Ok(Response::new(EmbeddingsResponse {
+            shape: shape(&values),
+            embeddings: vec![Embedding { values }],
+            took,
+            text,
+        }))
+    }
+
+    async fn question_answering(
+        &self,
+        _request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        Err(self.unsupported(Task::QuestionAnswering))
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        self.serves(Task::SentenceTransformers)?;
+        let text = request.into_inner().text;
+        let Inference { values, took } = self.infer(text.clone()).await?;
+        let values = normalize(values);
+        Ok(Response::new(SentenceTransformersResponse {
+            shape: shape(&values),
+            embeddings: vec![Embedding { values }],
+            took,
+            text,
+        }))
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        self.serves(Task::SequenceClassification)?;
+        let text = request.into_inner().text;
+        let Inference { values, took } = self.infer(text.clone()).await?;
+        Ok(Response::new(SequenceClassificationResponse {
+            shape: shape(&values),
+            logits: values,
+            took,
+            text,
+        }))
+    }
+
+    async fn token_classification(
+        &self,
+        _request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        Err(self.unsupported(Task::TokenClassification))
+    }
+
+    async fn metadata(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        Ok(Response::new(MetadataResponse {
+            metadata: self.metadata.clone(),
+        }))
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        self.serves(Task::Embeddings)?;
+        let texts = request.into_inner();
+        let inferences = self.infer_batch(texts.clone()).await?;
+        let responses = texts
+            .into_iter()
+            .zip(inferences)
+            .map(|(text, Inference { values, took })| EmbeddingsResponse {
+                shape: shape(&values),
+                embeddings: vec![Embedding { values }],
+                took,
+                text,
+            })
+            .collect();
+        Ok(Response::new(responses))
+    }
+
+    async fn token_classification_batch(
+        &self,
+        _request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        Err(self.unsupported(Task::TokenClassification))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mean_pool_skips_masked_tokens() {
+        let values = [1.0, 2.0, 3.0, 4.0, 100.0, 100.0];
+        assert_eq!(mean_pool(&values, &[1, 1, 0], 3, 2), vec![2.0, 3.0]);
+        assert_eq!(normalize(vec![3.0, 4.0]), vec![0.6, 0.8]);
+    }
+}
@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::env;
+use std::slice;
+use std::time::Instant;
+
+use async_trait::async_trait;
+use log::{debug, error};
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde::{Deserialize, Serialize};
+use tonic::{Request, Response, Status};
+
+use crate::config::OpenAiConfig;
+use crate::proto::mighty_proto::{
+    Embedding, EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse,
+    QuestionAnswerRequest, QuestionAnswerResponse, SentenceTransformersResponse,
+    SequenceClassificationResponse, Shape, TextRequest, TokenClassificationResponse,
+};
+use crate::services::context::RequestContext;
+use crate::services::telemetry::trace_context_headers;
+
+use super::{status_from_http, MightyClient};
+
+/// The body of a `POST /embeddings` request.
+#[derive(Debug, Serialize)]
+struct EmbeddingsRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+    encoding_format: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dimensions: Option<u32>,
+}
+
+/// The body of a `POST /embeddings` response, of which only the vectors and their input index
+/// are used.
+#[derive(Debug, Deserialize)]
+struct EmbeddingsApiResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+/// The body of an error response, e.g. `{"error": {"message": "Invalid API key"}}`.
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: ErrorDetail,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorDetail {
+    message: String,
+}
+
+/// The `OpenAiClient` struct implements the `MightyClient` trait against the OpenAI embeddings
+/// API (`POST /embeddings`), also spoken by many self-hosted inference servers, so the gRPC
+/// contract stays the same when migrating between backends.
+///
+/// The `embeddings` and `sentence_transformers` tasks are translated to embeddings requests,
+/// sentence transformers embeddings being normalized, and `embeddings_batch` embeds all its
+/// texts in one request; other tasks fail with `UNIMPLEMENTED`.
+/// Upstream HTTP errors are mapped to the closest gRPC status, e.g. `429 Too Many Requests` to
+/// `RESOURCE_EXHAUSTED`.
+pub struct OpenAiClient {
+    client: Client,
+    config: OpenAiConfig,
+    api_key: Option<String>,
+}
+
+impl OpenAiClient {
+    /// Creates a client for the API configured in `config`, reading the API key from the
+    /// `api_key` setting, else from the `api_key_env` environment variable.
+    pub fn new(config: OpenAiConfig) -> Self {
+        let api_key = config
+            .api_key
+            .as_ref()
+            .map(|key| key.expose().to_string())
+            .or_else(|| env::var(&config.api_key_env).ok())
+            .filter(|key| !key.is_empty());
+        let client = Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .expect("Failed to build HTTP client");
+        Self {
+            client,
+            config,
+            api_key,
+        }
+    }
+
+    /// Adds the API key, the caller's remaining deadline, if any, and the trace context to an
+    /// upstream request.
+    fn prepare<T>(&self, builder: RequestBuilder, request: &Request<T>) -> RequestBuilder {
+        let mut builder = match &self.api_key {
+            Some(api_key) => builder.bearer_auth(api_key),
+            None => builder,
+        };
+        if let Some(remaining) = RequestContext::get(request).and_then(RequestContext::remaining) {
+            builder = builder.timeout(remaining.min(self.config.timeout));
+        }
+        builder.headers(trace_context_headers())
+    }
+
+    /// Embeds `texts` in one call, returning their vectors along with the call duration.
+    async fn embed<T>(
+        &self,
+        request: &Request<T>,
+        texts: &[String],
+    ) -> Result<(Vec<Vec<f32>>, i32), Status> {
+        let started = Instant::now();
+        let body = EmbeddingsRequest {
+            model: &self.config.model,
+            input: texts,
+            encoding_format: "float",
+            dimensions: self.config.dimensions,
+        };
+        let url = format!("{}/embeddings", self.config.base_url);
+        let res = self
+            .prepare(self.client.post(&url).json(&body), request)
+            .send()
+            .await
+            .map_err(send_error)?;
+        let status = res.status();
+        let body = res.bytes().await.map_err(|e| {
+            Status::unavailable(format!("Error reading embeddings response: {}", e))
+        })?;
+        let embeddings = parse_embeddings(status, &body, texts.len())?;
+        Ok((embeddings, started.elapsed().as_millis() as i32))
+    }
+
+    /// Embeds the text of `request`, returning the vector along with the call duration.
+    async fn embed_text(&self, request: &Request<TextRequest>) -> Result<(Vec<f32>, i32), Status> {
+        let texts = slice::from_ref(&request.get_ref().text);
+        let (mut embeddings, took) = self.embed(request, texts).await?;
+        Ok((embeddings.remove(0), took))
+    }
+}
+
+fn send_error(error: reqwest::Error) -> Status {
+    if error.is_timeout() {
+        Status::deadline_exceeded(format!("Embeddings request timed out: {}", error))
+    } else {
+        Status::unavailable(format!("Error fetching embeddings: {}", error))
+    }
+}
+
+/// Extracts the `expected` embeddings of an embeddings response in input order, or maps an
+/// error response to a `Status`.
+fn parse_embeddings(
+    status: StatusCode,
+    body: &[u8],
+    expected: usize,
+) -> Result<Vec<Vec<f32>>, Status> {
+    if !status.is_success() {
+        let message = serde_json::from_slice::<ErrorResponse>(body)
+            .map(|response| response.error.message)
+            .unwrap_or_else(|_| String::from_utf8_lossy(body).into_owned());
+        let message = format!("Upstream responded {}: {}", status, message);
+        error!("{}", message);
+        return Err(status_from_http(status, message));
+    }
+    let mut response: EmbeddingsApiResponse = serde_json::from_slice(body)
+        .map_err(|e| Status::internal(format!("Failed to parse embeddings JSON: {}", e)))?;
+    if response.data.len() != expected {
+        return Err(Status::internal(format!(
+            "The embeddings response has {} embeddings for {} texts",
+            response.data.len(),
+            expected
+        )));
+    }
+    response.data.sort_by_key(|data| data.index);
+    Ok(response
+        .data
+        .into_iter()
+        .map(|data| data.embedding)
+        .collect())
+}
+
+fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|value| *value /= norm);
+    }
+    vector
+}
+
+fn shape(values: &[f32]) -> Option<Shape> {
+    Some(Shape {
+        dim1: 1,
+        dim2: values.len() as i32,
+    })
+}
+
+fn unsupported(task: &str) -> Status {
+    Status::unimplemented(format!("The OpenAI embeddings API doesn't serve {}", task))
+}
+
+#[async_trait]
+impl MightyClient for OpenAiClient {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        let url = format!("{}/models", self.config.base_url);
+        let res = self
+            .prepare(self.client.get(&url), &request)
+            .send()
+            .await
+            .map_err(send_error)?;
+        if !res.status().is_success() {
+            error!("Health check response status is {}", res.status());
+            return Err(Status::internal("Healthcheck failed"));
+        }
+        Ok(Response::new(HealthcheckResponse { success: true }))
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        debug!("Received embeddings request: {:?}", request);
+        let (values, took) = self.embed_text(&request).await?;
+        Ok(Response::new(EmbeddingsResponse {
+            shape: shape(&values),
+            embeddings: vec![Embedding { values }],
+            took,
+            text: request.into_inner().text,
+        }))
+    }
+
+    async fn question_answering(
+        &self,
+        _request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        Err(unsupported("question_answering"))
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        debug!("Received sentence_transformers request: {:?}", request);
+        let (values, took) = self.embed_text(&request).await?;
+        let values = normalize(values);
+        Ok(Response::new(SentenceTransformersResponse {
+            shape: shape(&values),
+            embeddings: vec![Embedding { values }],
+            took,
+            text: request.into_inner().text,
+        }))
+    }
+
+    async fn sequence_classification(
+        &self,
+        _request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        Err(unsupported("sequence_classification"))
+    }
+
+    async fn token_classification(
+        &self,
+        _request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        Err(unsupported("token_classification"))
+    }
+
+    async fn metadata(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        let mut metadata = HashMap::from([
+            ("backend".to_string(), "openai".to_string()),
+            ("base_url".to_string(), self.config.base_url.clone()),
+            ("model".to_string(), self.config.model.clone()),
+        ]);
+        if let Some(dimensions) = self.config.dimensions {
+            metadata.insert("dimensions".to_string(), dimensions.to_string());
+        }
+        Ok(Response::new(MetadataResponse { metadata }))
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        debug!(
+            "Received embeddings batch of {} texts",
+            request.get_ref().len()
+        );
+        let (embeddings, took) = self.embed(&request, request.get_ref()).await?;
+        let responses = request
+            .into_inner()
+            .into_iter()
+            .zip(embeddings)
+            .map(|(text, values)| EmbeddingsResponse {
+                shape: shape(&values),
+                embeddings: vec![Embedding { values }],
+                took,
+                text,
+            })
+            .collect();
+        Ok(Response::new(responses))
+    }
+
+    async fn token_classification_batch(
+        &self,
+        _request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        Err(unsupported("token_classification"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_embeddings() {
+        let body = br#"{"object": "list", "model": "text-embedding-3-small",
+            "data": [{"object": "embedding", "index": 0, "embedding": [0.5, -0.25]}],
+            "usage": {"prompt_tokens": 2, "total_tokens": 2}}"#;
+        assert_eq!(
+            parse_embeddings(StatusCode::OK, body, 1).unwrap(),
+            vec![vec![0.5, -0.25]]
+        );
+        assert_eq!(
+            parse_embeddings(StatusCode::OK, br#"{"data": []}"#, 1)
+                .unwrap_err()
+                .code(),
+            Code::Internal
+        );
+
+        let batch =
+            br#"{"data": [{"index": 1, "embedding": [2.0]}, {"index": 0, "embedding": [1.0]}]}"#;
+        assert_eq!(
+            parse_embeddings(StatusCode::OK, batch, 2).unwrap(),
+            vec![vec![1.0], vec![2.0]]
+        );
+    }
+
+    #[test]
+    fn test_error_responses_are_mapped() {
+        let status = parse_embeddings(
+            StatusCode::TOO_MANY_REQUESTS,
+            br#"{"error": {"message": "Rate limit reached", "type": "requests"}}"#,
+            1,
+        )
+        .unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert!(status.message().ends_with("Rate limit reached"));
+
+        let status = parse_embeddings(StatusCode::BAD_GATEWAY, b"upstream down", 1).unwrap_err();
+        assert_eq!(status.code(), Code::Unavailable);
+        assert!(status.message().ends_with("upstream down"));
+    }
+}
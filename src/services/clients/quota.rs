@@ -0,0 +1,374 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use log::debug;
+use tonic::{Request, Response, Status};
+
+use crate::config::QuotasConfig;
+use crate::proto::mighty_proto::{
+    EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse, QuestionAnswerRequest,
+    QuestionAnswerResponse, RerankRequest, RerankResponse, SentenceTransformersResponse,
+    SequenceClassificationResponse, TextRequest, TokenClassificationResponse,
+};
+use crate::services::context::RequestContext;
+use crate::services::metrics::Metrics;
+
+use super::MightyClient;
+
+/// Counter of the inference calls accounted, by caller `identity`.
+const QUOTA_REQUESTS_METRIC: &str = "mighty_quota_requests_total";
+
+/// Counter of the tokens embedded, by caller `identity`.
+const QUOTA_TOKENS_METRIC: &str = "mighty_quota_tokens_total";
+
+/// Counter of calls rejected for exhausting a quota, by caller `identity` and `quota`
+/// (`requests` or `tokens`).
+const QUOTA_EXHAUSTED_METRIC: &str = "mighty_quota_exhausted_requests_total";
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Returns the number of days since the Unix epoch, in UTC.
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / SECONDS_PER_DAY
+}
+
+/// Returns the number of tokens of `text` accounted against quotas: its whitespace-separated
+/// words, a tokenizer-independent approximation.
+pub fn count_tokens(text: &str) -> u64 {
+    text.split_whitespace().count() as u64
+}
+
+/// The usage of a caller on a given day.
+#[derive(Debug, Default, Clone, Copy)]
+struct DailyUsage {
+    day: u64,
+    requests: u64,
+    tokens: u64,
+}
+
+/// The usage of the day and the daily quotas of a caller, as reported by the admin service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaUsage {
+    pub identity: String,
+    pub requests: u64,
+    pub tokens: u64,
+    pub daily_requests: Option<u64>,
+    pub daily_tokens: Option<u64>,
+}
+
+/// The `Quotas` struct accounts the inference calls and embedded tokens of each authenticated
+/// caller over the current UTC day, for the lifetime of the process, and enforces their daily
+/// quotas.
+#[derive(Debug, Default)]
+pub struct Quotas {
+    config: QuotasConfig,
+    usage: Mutex<HashMap<String, DailyUsage>>,
+}
+
+impl Quotas {
+    pub fn new(config: &QuotasConfig) -> Self {
+        Self {
+            config: config.clone(),
+            usage: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Accounts `requests` calls embedding `tokens` tokens to `identity`, unless they would
+    /// exceed one of its daily quotas, failing with `RESOURCE_EXHAUSTED` then.
+    pub fn charge(&self, identity: &str, requests: u64, tokens: u64) -> Result<(), Status> {
+        self.charge_on(today(), identity, requests, tokens)
+    }
+
+    fn charge_on(
+        &self,
+        day: u64,
+        identity: &str,
+        requests: u64,
+        tokens: u64,
+    ) -> Result<(), Status> {
+        let (daily_requests, daily_tokens) = self.config.limits(identity);
+        let mut usage = self.usage.lock().unwrap();
+        let usage = usage.entry(identity.to_string()).or_default();
+        if usage.day != day {
+            *usage = DailyUsage {
+                day,
+                ..Default::default()
+            };
+        }
+        let exhausted = [
+            ("requests", usage.requests + requests, daily_requests),
+            ("tokens", usage.tokens + tokens, daily_tokens),
+        ]
+        .into_iter()
+        .find(|(_, used, limit)| limit.is_some_and(|limit| *used > limit));
+        if let Some((quota, _, Some(limit))) = exhausted {
+            debug!("{} exhausted its daily {} quota", identity, quota);
+            Metrics::global()
+                .counter(
+                    QUOTA_EXHAUSTED_METRIC,
+                    &[("identity", identity), ("quota", quota)],
+                )
+                .increment(1);
+            return Err(Status::resource_exhausted(format!(
+                "The daily {} quota of {} ({}) is exhausted; it resets at midnight UTC",
+                quota, identity, limit
+            )));
+        }
+        usage.requests += requests;
+        usage.tokens += tokens;
+        let metrics = Metrics::global();
+        metrics
+            .counter(QUOTA_REQUESTS_METRIC, &[("identity", identity)])
+            .increment(requests);
+        metrics
+            .counter(QUOTA_TOKENS_METRIC, &[("identity", identity)])
+            .increment(tokens);
+        Ok(())
+    }
+
+    /// Returns the usage of the day of `identity`, or of every caller seen today or with a quota
+    /// of its own, by identity.
+    pub fn usage(&self, identity: Option<&str>) -> Vec<QuotaUsage> {
+        self.usage_on(today(), identity)
+    }
+
+    fn usage_on(&self, day: u64, identity: Option<&str>) -> Vec<QuotaUsage> {
+        let usage = self.usage.lock().unwrap();
+        let mut callers: BTreeMap<&str, DailyUsage> = match identity {
+            Some(identity) => BTreeMap::from([(identity, DailyUsage::default())]),
+            None => self
+                .config
+                .callers
+                .iter()
+                .map(|caller| (caller.identity.as_str(), DailyUsage::default()))
+                .chain(
+                    usage
+                        .keys()
+                        .map(|identity| (identity.as_str(), DailyUsage::default())),
+                )
+                .collect(),
+        };
+        for (identity, daily) in callers.iter_mut() {
+            if let Some(used) = usage.get(*identity).filter(|used| used.day == day) {
+                *daily = *used;
+            }
+        }
+        callers
+            .into_iter()
+            .map(|(identity, daily)| {
+                let (daily_requests, daily_tokens) = self.config.limits(identity);
+                QuotaUsage {
+                    identity: identity.to_string(),
+                    requests: daily.requests,
+                    tokens: daily.tokens,
+                    daily_requests,
+                    daily_tokens,
+                }
+            })
+            .collect()
+    }
+}
+
+/// The `QuotaClient` struct is a `MightyClient` decorator accounting the inference calls of
+/// authenticated callers, and the tokens of the texts they embed, against their daily quotas in
+/// `Quotas`. Calls past a quota fail with `RESOURCE_EXHAUSTED` without reaching the upstream.
+///
+/// Callers are told apart by the identity in the `RequestContext`; calls without one aren't
+/// accounted. Each text of a batch counts as a call.
+pub struct QuotaClient {
+    inner: Box<dyn MightyClient>,
+    quotas: Arc<Quotas>,
+}
+
+impl QuotaClient {
+    pub fn new(inner: Box<dyn MightyClient>, quotas: Arc<Quotas>) -> Self {
+        Self { inner, quotas }
+    }
+
+    /// Accounts the calls and tokens of `request` to its caller, if authenticated.
+    fn charge<T>(&self, request: &Request<T>, requests: u64, tokens: u64) -> Result<(), Status> {
+        match RequestContext::get(request).and_then(|context| context.identity.as_deref()) {
+            Some(identity) => self.quotas.charge(identity, requests, tokens),
+            None => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl MightyClient for QuotaClient {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        self.inner.health_check(request).await
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        self.charge(&request, 1, count_tokens(&request.get_ref().text))?;
+        self.inner.embeddings(request).await
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        self.charge(&request, 1, 0)?;
+        self.inner.question_answering(request).await
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        self.charge(&request, 1, count_tokens(&request.get_ref().text))?;
+        self.inner.sentence_transformers(request).await
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        self.charge(&request, 1, 0)?;
+        self.inner.sequence_classification(request).await
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        self.charge(&request, 1, 0)?;
+        self.inner.token_classification(request).await
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        self.inner.metadata(request).await
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        self.charge(&request, 1, 0)?;
+        self.inner.rerank(request).await
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        let texts = request.get_ref();
+        let tokens = texts.iter().map(|text| count_tokens(text)).sum();
+        self.charge(&request, texts.len() as u64, tokens)?;
+        self.inner.embeddings_batch(request).await
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        self.charge(&request, request.get_ref().len() as u64, 0)?;
+        self.inner.token_classification_batch(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use crate::config::{CallerQuotaConfig, Task};
+    use crate::services::clients::mock::MockMightyClient;
+
+    use super::*;
+
+    fn config() -> QuotasConfig {
+        QuotasConfig {
+            enabled: true,
+            daily_requests: Some(2),
+            daily_tokens: None,
+            callers: vec![CallerQuotaConfig {
+                identity: "indexer".to_string(),
+                daily_requests: None,
+                daily_tokens: Some(5),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_quotas_reset_daily() {
+        let quotas = Quotas::new(&config());
+        assert!(quotas.charge_on(1, "search", 2, 100).is_ok());
+        let status = quotas.charge_on(1, "search", 1, 0).unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert_eq!(
+            status.message(),
+            "The daily requests quota of search (2) is exhausted; it resets at midnight UTC"
+        );
+        assert!(quotas.charge_on(1, "indexer", 2, 4).is_ok());
+        assert!(quotas.charge_on(1, "indexer", 0, 2).is_err());
+
+        assert_eq!(
+            quotas.usage_on(1, None),
+            vec![
+                QuotaUsage {
+                    identity: "indexer".to_string(),
+                    requests: 2,
+                    tokens: 4,
+                    daily_requests: Some(2),
+                    daily_tokens: Some(5),
+                },
+                QuotaUsage {
+                    identity: "search".to_string(),
+                    requests: 2,
+                    tokens: 100,
+                    daily_requests: Some(2),
+                    daily_tokens: None,
+                },
+            ]
+        );
+        assert!(quotas.charge_on(2, "search", 1, 0).is_ok());
+        assert_eq!(quotas.usage_on(2, Some("indexer"))[0].requests, 0);
+    }
+
+    #[tokio::test]
+    async fn test_calls_past_their_quota_are_rejected() {
+        let inner = Arc::new(MockMightyClient::new());
+        let boxed: Arc<dyn MightyClient> = inner.clone();
+        let client = QuotaClient::new(Box::new(boxed), Arc::new(Quotas::new(&config())));
+        let request = |identity: Option<&str>| {
+            let mut request = Request::new(TextRequest {
+                text: "two words".to_string(),
+            });
+            request.extensions_mut().insert(RequestContext {
+                identity: identity.map(str::to_string),
+                ..Default::default()
+            });
+            request
+        };
+
+        client.embeddings(request(Some("indexer"))).await.unwrap();
+        client.embeddings(request(Some("indexer"))).await.unwrap();
+        let status = client
+            .embeddings(request(Some("indexer")))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        client.embeddings(request(None)).await.unwrap();
+        assert_eq!(inner.calls(Task::Embeddings), 3);
+    }
+}
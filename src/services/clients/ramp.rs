@@ -0,0 +1,175 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::config::RampConfig;
+
+/// The `Ramp` struct tracks the traffic ramp-up of an upstream added while others were already
+/// serving, e.g. one discovered through DNS.
+///
+/// The upstream receives a growing percentage of the traffic share it would get at full weight,
+/// following the configured schedule (e.g. 1% → 10% → 50% → 100% over 30 minutes). The ramp is
+/// rolled back, i.e. the upstream stops receiving traffic, when its error rate exceeds
+/// `max_error_rate` once `min_requests` calls have been made.
+#[derive(Debug)]
+pub struct Ramp {
+    config: Arc<RampConfig>,
+    started: Instant,
+    calls: AtomicU64,
+    failures: AtomicU64,
+    rolled_back: AtomicBool,
+    announced_step: AtomicUsize,
+}
+
+/// A change of the ramp state worth reporting.
+#[derive(Debug, PartialEq)]
+pub enum RampEvent {
+    /// The ramp moved to the step admitting `percent` percent of the traffic.
+    Step { percent: f64 },
+    /// The ramp completed; the upstream receives its full share.
+    Completed,
+}
+
+impl Ramp {
+    pub fn new(config: Arc<RampConfig>) -> Self {
+        Self::started_at(config, Instant::now())
+    }
+
+    fn started_at(config: Arc<RampConfig>, started: Instant) -> Self {
+        Self {
+            config,
+            started,
+            calls: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            rolled_back: AtomicBool::new(false),
+            announced_step: AtomicUsize::new(usize::MAX),
+        }
+    }
+
+    /// Returns the index of the current schedule step, or `None` once the ramp is complete.
+    fn step(&self) -> Option<usize> {
+        let steps = self.config.steps.len();
+        if steps == 0 {
+            return None;
+        }
+        let step_duration = self.config.duration / steps as u32;
+        let elapsed = self.started.elapsed();
+        let step = if step_duration.is_zero() {
+            steps
+        } else {
+            (elapsed.as_nanos() / step_duration.as_nanos()) as usize
+        };
+        (step < steps).then_some(step)
+    }
+
+    /// Returns the fraction, in `[0, 1]`, of its full traffic share the upstream currently gets.
+    pub fn weight(&self) -> f64 {
+        if self.is_rolled_back() {
+            return 0.0;
+        }
+        match self.step() {
+            Some(step) => (self.config.steps[step] / 100.0).clamp(0.0, 1.0),
+            None => 1.0,
+        }
+    }
+
+    /// Returns whether the upstream should be considered for the next call.
+    pub fn admits(&self) -> bool {
+        let weight = self.weight();
+        weight >= 1.0 || (weight > 0.0 && rand::thread_rng().gen_bool(weight))
+    }
+
+    pub fn is_complete(&self) -> bool {
+        !self.is_rolled_back() && self.step().is_none()
+    }
+
+    pub fn is_rolled_back(&self) -> bool {
+        self.rolled_back.load(Ordering::Relaxed)
+    }
+
+    /// Returns the event to report if the ramp moved to another step since the last call.
+    pub fn poll_event(&self) -> Option<RampEvent> {
+        if self.is_rolled_back() {
+            return None;
+        }
+        let step = self.step().unwrap_or(self.config.steps.len());
+        if self.announced_step.swap(step, Ordering::Relaxed) == step {
+            return None;
+        }
+        Some(match self.config.steps.get(step) {
+            Some(&percent) => RampEvent::Step { percent },
+            None => RampEvent::Completed,
+        })
+    }
+
+    /// Records the outcome of a call made during the ramp. Returns `true` if the call caused
+    /// the ramp to be rolled back.
+    pub fn record(&self, success: bool) -> bool {
+        if self.is_complete() || self.is_rolled_back() {
+            return false;
+        }
+        let calls = self.calls.fetch_add(1, Ordering::Relaxed) + 1;
+        let failures = if success {
+            self.failures.load(Ordering::Relaxed)
+        } else {
+            self.failures.fetch_add(1, Ordering::Relaxed) + 1
+        };
+        calls >= self.config.min_requests
+            && failures as f64 / calls as f64 > self.config.max_error_rate
+            && !self.rolled_back.swap(true, Ordering::Relaxed)
+    }
+
+    /// Returns how long the ramp has been running.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Arc<RampConfig> {
+        Arc::new(RampConfig {
+            enabled: true,
+            steps: vec![1.0, 10.0, 50.0, 100.0],
+            duration: Duration::from_secs(40 * 60),
+            max_error_rate: 0.2,
+            min_requests: 10,
+        })
+    }
+
+    #[test]
+    fn test_weight_follows_schedule() {
+        let now = Instant::now();
+        let ramp = Ramp::started_at(config(), now);
+        assert_eq!(ramp.weight(), 0.01);
+        assert_eq!(ramp.poll_event(), Some(RampEvent::Step { percent: 1.0 }));
+        assert_eq!(ramp.poll_event(), None);
+
+        let ramp = Ramp::started_at(config(), now - Duration::from_secs(25 * 60));
+        assert_eq!(ramp.weight(), 0.5);
+
+        let ramp = Ramp::started_at(config(), now - Duration::from_secs(41 * 60));
+        assert_eq!(ramp.weight(), 1.0);
+        assert!(ramp.is_complete());
+        assert_eq!(ramp.poll_event(), Some(RampEvent::Completed));
+    }
+
+    #[test]
+    fn test_error_rate_regression_rolls_back() {
+        let ramp = Ramp::new(config());
+        for _ in 0..8 {
+            assert!(!ramp.record(true));
+        }
+        assert!(!ramp.record(false));
+        assert!(!ramp.record(false)); // 2 failures out of 10 calls is within the limit
+        assert!(ramp.record(false));
+        assert!(ramp.is_rolled_back());
+        assert_eq!(ramp.weight(), 0.0);
+        assert!(!ramp.admits());
+        assert!(!ramp.record(false));
+    }
+}
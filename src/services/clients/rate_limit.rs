@@ -0,0 +1,440 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tonic::{Request, Response, Status};
+
+use crate::config::{AppSettings, RateLimitConfig, Task};
+use crate::proto::mighty_proto::{
+    EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse, QuestionAnswerRequest,
+    QuestionAnswerResponse, RerankRequest, RerankResponse, SentenceTransformersResponse,
+    SequenceClassificationResponse, TextRequest, TokenClassificationResponse,
+};
+use crate::services::context::RequestContext;
+use crate::services::metrics::{Metrics, DURATION_BUCKETS};
+use crate::services::reload::Reloadable;
+
+use super::MightyClient;
+
+/// Counter of calls shed by the rate limiter, by task and `reason`: `queue_full` or `timeout`.
+const SHED_METRIC: &str = "mighty_rate_limited_requests_total";
+
+/// Histogram of the time calls spent in the queue before being sent or shed, by task.
+const QUEUE_WAIT_METRIC: &str = "mighty_upstream_queue_wait_seconds";
+
+/// Histogram of the number of calls already queued when a call arrives, by task.
+const QUEUE_DEPTH_METRIC: &str = "mighty_upstream_queue_depth";
+
+/// The buckets of `QUEUE_DEPTH_METRIC`.
+const DEPTH_BUCKETS: &[f64] = &[
+    0.0, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0, 256.0, 512.0, 1024.0,
+];
+
+/// A token bucket refilled at a steady rate. Tokens are reserved ahead of time, so the bucket
+/// may go negative, each caller waiting for its own token in arrival order.
+pub(crate) struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket; a rate that isn't positive never refills it.
+    pub(crate) fn new(rate: f64, burst: f64) -> Self {
+        Self {
+            rate: rate.max(0.0),
+            burst,
+            tokens: burst,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Reserves a token, returning how long to wait until it is available, or `None` without
+    /// reserving it if that would be longer than `max_wait`.
+    pub(crate) fn reserve(&mut self, max_wait: Duration) -> Option<Duration> {
+        self.refill();
+        let wait = self.wait();
+        if wait > max_wait {
+            return None;
+        }
+        self.tokens -= 1.0;
+        Some(wait)
+    }
+
+    /// Takes a token if one is available, or returns how long until one is.
+    pub(crate) fn try_take(&mut self) -> Result<(), Duration> {
+        match self.reserve(Duration::ZERO) {
+            Some(_) => Ok(()),
+            None => Err(self.wait()),
+        }
+    }
+
+    /// Whether the bucket has refilled to its burst, i.e. its caller has been idle.
+    pub(crate) fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.burst
+    }
+
+    /// How long until a token is available, as of the last refill, `Duration::MAX` if never.
+    fn wait(&self) -> Duration {
+        let missing = (1.0 - self.tokens).max(0.0);
+        if missing == 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::try_from_secs_f64(missing / self.rate).unwrap_or(Duration::MAX)
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.refilled_at = now;
+    }
+}
+
+/// The `RateLimitingClient` struct is a `MightyClient` decorator bounding the load sent
+/// upstream to `requests_per_second` calls (with bursts of up to `burst` calls) and
+/// `max_concurrency` calls in flight, so bursts of traffic can't overwhelm the Mighty server.
+///
+/// Excess calls wait in a queue of up to `max_queue` calls, for up to `queue_timeout` or the call's
+/// deadline, whichever comes first; calls that can't be queued or would wait longer are shed with
+/// `RESOURCE_EXHAUSTED`. The queue depth seen by arriving calls and the time they spend queued are
+/// recorded, so overload shows before calls time out. Inference and rerank calls are limited, an
+/// embeddings batch counting as a single call; health checks and metadata calls aren't.
+pub struct RateLimitingClient {
+    inner: Box<dyn MightyClient>,
+    bucket: Mutex<TokenBucket>,
+    concurrency: RwLock<Arc<Semaphore>>,
+    queued: AtomicUsize,
+    config: RwLock<RateLimitConfig>,
+}
+
+/// A call queued for admission, leaving the queue when dropped.
+struct Queued<'a>(&'a AtomicUsize);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl RateLimitingClient {
+    pub fn new(inner: Box<dyn MightyClient>, config: &RateLimitConfig) -> Self {
+        Self {
+            inner,
+            bucket: Mutex::new(TokenBucket::new(
+                config.requests_per_second,
+                config.burst.max(1) as f64,
+            )),
+            concurrency: RwLock::new(Arc::new(Semaphore::new(config.max_concurrency))),
+            queued: AtomicUsize::new(0),
+            config: RwLock::new(config.clone()),
+        }
+    }
+
+    /// Waits for a call of `task` to be admitted upstream, returning the permit it holds while
+    /// in flight, or the status it is shed with.
+    async fn admit<R>(
+        &self,
+        task: Task,
+        request: &Request<R>,
+    ) -> Result<OwnedSemaphorePermit, Status> {
+        let depth = self.queued.fetch_add(1, Ordering::Relaxed);
+        let labels = [("task", task.as_str())];
+        Metrics::global()
+            .histogram(QUEUE_DEPTH_METRIC, &labels, DEPTH_BUCKETS)
+            .observe(depth as f64);
+        if depth >= self.config.read().unwrap().max_queue {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+            return Err(shed(task, "queue_full"));
+        }
+        let _queued = Queued(&self.queued);
+        let queued_at = Instant::now();
+        let result = self.wait(task, request).await;
+        Metrics::global()
+            .histogram(QUEUE_WAIT_METRIC, &labels, DURATION_BUCKETS)
+            .observe(queued_at.elapsed().as_secs_f64());
+        result
+    }
+
+    /// Waits for a token and a concurrency permit, for up to the queue timeout or the call's
+    /// deadline.
+    async fn wait<R>(
+        &self,
+        task: Task,
+        request: &Request<R>,
+    ) -> Result<OwnedSemaphorePermit, Status> {
+        let queue_timeout = self.config.read().unwrap().queue_timeout;
+        let max_wait = RequestContext::get(request)
+            .and_then(RequestContext::remaining)
+            .map_or(queue_timeout, |remaining| remaining.min(queue_timeout));
+        let deadline = Instant::now() + max_wait;
+        let wait = self.bucket.lock().unwrap().reserve(max_wait);
+        let Some(wait) = wait else {
+            return Err(shed(task, "timeout"));
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        let concurrency = self.concurrency.read().unwrap().clone();
+        let permit = concurrency.acquire_owned();
+        match tokio::time::timeout_at(deadline.into(), permit).await {
+            Ok(permit) => Ok(permit.expect("the semaphore is never closed")),
+            Err(_) => Err(shed(task, "timeout")),
+        }
+    }
+}
+
+/// Applies the reloaded limits to the calls admitted afterwards; calls in flight keep the
+/// permits of the previous `max_concurrency`. Turning the limiter on or off applies on restart.
+#[async_trait]
+impl Reloadable for RateLimitingClient {
+    fn section(&self) -> &'static str {
+        "rate_limit"
+    }
+
+    async fn reload(&self, settings: &AppSettings) -> Result<bool, String> {
+        let reloaded = &settings.rate_limit;
+        let mut config = self.config.write().unwrap();
+        if *config == *reloaded {
+            return Ok(false);
+        }
+        if (config.requests_per_second, config.burst)
+            != (reloaded.requests_per_second, reloaded.burst)
+        {
+            *self.bucket.lock().unwrap() =
+                TokenBucket::new(reloaded.requests_per_second, reloaded.burst.max(1) as f64);
+        }
+        if config.max_concurrency != reloaded.max_concurrency {
+            *self.concurrency.write().unwrap() = Arc::new(Semaphore::new(reloaded.max_concurrency));
+        }
+        *config = reloaded.clone();
+        Ok(true)
+    }
+}
+
+fn shed(task: Task, reason: &str) -> Status {
+    Metrics::global()
+        .counter(SHED_METRIC, &[("task", task.as_str()), ("reason", reason)])
+        .increment(1);
+    Status::resource_exhausted(format!(
+        "The {} call was shed by the upstream rate limiter ({})",
+        task.as_str(),
+        reason
+    ))
+}
+
+#[async_trait]
+impl MightyClient for RateLimitingClient {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        self.inner.health_check(request).await
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        let _permit = self.admit(Task::Embeddings, &request).await?;
+        self.inner.embeddings(request).await
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        let _permit = self.admit(Task::QuestionAnswering, &request).await?;
+        self.inner.question_answering(request).await
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        let _permit = self.admit(Task::SentenceTransformers, &request).await?;
+        self.inner.sentence_transformers(request).await
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        let _permit = self.admit(Task::SequenceClassification, &request).await?;
+        self.inner.sequence_classification(request).await
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        let _permit = self.admit(Task::TokenClassification, &request).await?;
+        self.inner.token_classification(request).await
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        self.inner.metadata(request).await
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        let _permit = self.admit(Task::Rerank, &request).await?;
+        self.inner.rerank(request).await
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        let _permit = self.admit(Task::Embeddings, &request).await?;
+        self.inner.embeddings_batch(request).await
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        let _permit = self.admit(Task::TokenClassification, &request).await?;
+        self.inner.token_classification_batch(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::{Config, File, FileFormat};
+    use futures::future::join_all;
+    use tonic::Code;
+
+    use crate::services::clients::mock::MockMightyClient;
+
+    use super::*;
+
+    fn config() -> RateLimitConfig {
+        RateLimitConfig {
+            enabled: true,
+            requests_per_second: 1000.0,
+            burst: 1000,
+            max_concurrency: 2,
+            max_queue: 1,
+            queue_timeout: Duration::from_secs(1),
+        }
+    }
+
+    fn embed(client: &RateLimitingClient) -> impl std::future::Future<Output = Code> + '_ {
+        let request = Request::new(TextRequest {
+            text: "a".to_string(),
+        });
+        async move {
+            match client.embeddings(request).await {
+                Ok(_) => Code::Ok,
+                Err(status) => status.code(),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_excess_concurrent_calls_are_queued_then_shed() {
+        let mock = MockMightyClient::new().with_latency(Duration::from_millis(20));
+        let client = RateLimitingClient::new(Box::new(mock), &config());
+
+        let codes = join_all((0..5).map(|_| embed(&client))).await;
+        assert_eq!(codes[..3], [Code::Ok; 3]);
+        assert_eq!(codes[3..], [Code::ResourceExhausted; 2]);
+        assert_eq!(embed(&client).await, Code::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_calls_beyond_the_rate_are_delayed_or_shed() {
+        let config = RateLimitConfig {
+            requests_per_second: 20.0,
+            burst: 1,
+            max_queue: 3,
+            queue_timeout: Duration::from_millis(120),
+            ..config()
+        };
+        let client = RateLimitingClient::new(Box::new(MockMightyClient::new()), &config);
+
+        let start = Instant::now();
+        let codes = join_all((0..3).map(|_| embed(&client))).await;
+        assert_eq!(codes, [Code::Ok; 3]);
+        assert!(start.elapsed() >= Duration::from_millis(90));
+
+        let codes = join_all((0..3).map(|_| embed(&client))).await;
+        assert_eq!(codes[..2], [Code::Ok; 2]);
+        assert_eq!(codes[2], Code::ResourceExhausted);
+    }
+
+    #[test]
+    fn test_buckets_without_a_positive_rate_never_refill() {
+        for rate in [0.0, -1.0, f64::NAN] {
+            let mut bucket = TokenBucket::new(rate, 1.0);
+            assert_eq!(bucket.try_take(), Ok(()));
+            assert_eq!(bucket.try_take(), Err(Duration::MAX));
+            assert_eq!(bucket.reserve(Duration::from_secs(3600)), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_queue_depth_and_wait_are_recorded() {
+        let mock = MockMightyClient::new().with_latency(Duration::from_millis(20));
+        let config = RateLimitConfig {
+            max_concurrency: 1,
+            ..config()
+        };
+        let client = RateLimitingClient::new(Box::new(mock), &config);
+        let labels = [("task", "rerank")];
+        let waits = Metrics::global().histogram(QUEUE_WAIT_METRIC, &labels, DURATION_BUCKETS);
+        let depths = Metrics::global().histogram(QUEUE_DEPTH_METRIC, &labels, DEPTH_BUCKETS);
+
+        let codes = join_all((0..3).map(|_| async {
+            match client.rerank(Request::new(RerankRequest::default())).await {
+                Ok(_) => Code::Ok,
+                Err(status) => status.code(),
+            }
+        }))
+        .await;
+        assert_eq!(codes, [Code::Ok, Code::Ok, Code::ResourceExhausted]);
+        assert_eq!(depths.count(), 3);
+        assert_eq!(waits.count(), 2);
+        assert!(waits.sum() >= 0.015);
+    }
+
+    #[tokio::test]
+    async fn test_reloaded_limits_apply_to_later_calls() {
+        let mock = MockMightyClient::new().with_latency(Duration::from_millis(20));
+        let client = RateLimitingClient::new(Box::new(mock), &config());
+        let settings: AppSettings = Config::builder()
+            .add_source(File::from_str(
+                r#"
+                grpc_server = { address = "127.0.0.1", port = 5051 }
+                logging = { level = "info" }
+
+                [rate_limit]
+                enabled = true
+                requests_per_second = 1000.0
+                burst = 1000
+                max_concurrency = 4
+                max_queue = 1
+                "#,
+                FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        assert!(client.reload(&settings).await.unwrap());
+        assert!(!client.reload(&settings).await.unwrap());
+        let codes = join_all((0..5).map(|_| embed(&client))).await;
+        assert_eq!(codes, [Code::Ok; 5]);
+    }
+}
@@ -0,0 +1,251 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use log::warn;
+use rand::Rng;
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, RedisError, Script};
+use sha2::{Digest, Sha256};
+use tonic::Status;
+
+use crate::services::metrics::Metrics;
+
+use super::caching::{CacheBackend, CacheKey, CacheLock};
+
+/// Counter of failed Redis commands, labelled by `command`. Failures are treated as misses.
+const REDIS_ERRORS_METRIC: &str = "mighty_cache_redis_errors_total";
+
+/// The tag following the prefix in the keys of cached responses, which flushes match.
+const RESPONSE_TAG: &str = "response:";
+/// The tag following the prefix in the keys of computation locks, which flushes leave alone.
+const LOCK_TAG: &str = "lock:";
+
+/// Deletes a lock only if it is still held by the caller, as it may have expired and been
+/// claimed by another replica meanwhile.
+const UNLOCK_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+end
+return 0
+"#;
+
+/// The `RedisCache` struct is a `CacheBackend` storing encoded responses in Redis, so gateway
+/// replicas share one cache which survives restarts.
+///
+/// Responses are stored under `{prefix}response:{task}:{sha256 of the input}` and expire after
+/// the TTL of their task; flushes delete every response under the prefix. Computations are
+/// claimed with a `SET NX` lock under `{prefix}lock:{task}:{sha256 of the input}`, which flushes
+/// don't match, so computations in flight on other replicas stay guarded. Redis
+/// failures are logged and the calls served by the upstream, so an unavailable Redis server
+/// doesn't fail inference calls.
+pub struct RedisCache {
+    connection: ConnectionManager,
+    prefix: String,
+}
+
+impl RedisCache {
+    /// Connects to the Redis server at `url`, e.g. `redis://cache:6379`.
+    pub async fn connect(url: &str, prefix: &str) -> Result<Self, RedisError> {
+        let connection = redis::Client::open(url)?.get_connection_manager().await?;
+        Ok(Self {
+            connection,
+            prefix: prefix.to_string(),
+        })
+    }
+
+    fn key(&self, key: &CacheKey) -> String {
+        redis_key(&self.prefix, RESPONSE_TAG, key)
+    }
+
+    fn lock_key(&self, key: &CacheKey) -> String {
+        redis_key(&self.prefix, LOCK_TAG, key)
+    }
+}
+
+/// Returns the Redis key of `key` tagged `tag`, hashing the scope and the input so keys stay
+/// short whatever the texts. Each part is prefixed with its length, so no two keys hash the same
+/// parts.
+fn redis_key(prefix: &str, tag: &str, key: &CacheKey) -> String {
+    let mut hasher = Sha256::new();
+    for part in [&key.scope, &key.text, &key.context] {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part.as_bytes());
+    }
+    let digest = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    format!("{}{}{}:{}", prefix, tag, key.task.as_str(), digest)
+}
+
+/// Returns the `SCAN` pattern matching the keys of the responses under `prefix`, escaping its
+/// glob characters.
+fn scan_pattern(prefix: &str) -> String {
+    let mut pattern = String::with_capacity(prefix.len() + RESPONSE_TAG.len() + 1);
+    for c in prefix.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push_str(RESPONSE_TAG);
+    pattern.push('*');
+    pattern
+}
+
+fn failed(command: &str, error: RedisError) {
+    warn!("Redis {} failed: {}", command, error);
+    Metrics::global()
+        .counter(REDIS_ERRORS_METRIC, &[("command", command)])
+        .increment(1);
+}
+
+#[async_trait]
+impl CacheBackend for RedisCache {
+    async fn get(&self, key: &CacheKey) -> Option<Vec<u8>> {
+        let mut connection = self.connection.clone();
+        match connection.get(self.key(key)).await {
+            Ok(value) => value,
+            Err(e) => {
+                failed("get", e);
+                None
+            }
+        }
+    }
+
+    async fn insert(&self, key: &CacheKey, value: Vec<u8>, ttl: Duration) {
+        let mut connection = self.connection.clone();
+        let ttl = ttl.as_millis().max(1) as u64;
+        let result: Result<(), RedisError> = redis::cmd("SET")
+            .arg(self.key(key))
+            .arg(value)
+            .arg("PX")
+            .arg(ttl)
+            .query_async(&mut connection)
+            .await;
+        if let Err(e) = result {
+            failed("set", e);
+        }
+    }
+
+    async fn flush(&self) -> Result<u64, Status> {
+        let mut connection = self.connection.clone();
+        let pattern = scan_pattern(&self.prefix);
+        let mut cursor = 0u64;
+        let mut flushed = 0;
+        loop {
+            let result: Result<(u64, Vec<String>), RedisError> = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(1000)
+                .query_async(&mut connection)
+                .await;
+            let (next, keys) = result.map_err(|e| {
+                failed("scan", e);
+                Status::unavailable("The Redis cache is unavailable")
+            })?;
+            if !keys.is_empty() {
+                let deleted: u64 = connection.del(&keys).await.map_err(|e| {
+                    failed("del", e);
+                    Status::unavailable("The Redis cache is unavailable")
+                })?;
+                flushed += deleted;
+            }
+            if next == 0 {
+                return Ok(flushed);
+            }
+            cursor = next;
+        }
+    }
+
+    async fn lock(&self, key: &CacheKey, timeout: Duration) -> Option<CacheLock> {
+        let mut connection = self.connection.clone();
+        let token = format!("{:016x}", rand::thread_rng().gen::<u64>());
+        let result: Result<Option<String>, RedisError> = redis::cmd("SET")
+            .arg(self.lock_key(key))
+            .arg(&token)
+            .arg("NX")
+            .arg("PX")
+            .arg(timeout.as_millis().max(1) as u64)
+            .query_async(&mut connection)
+            .await;
+        match result {
+            Ok(Some(_)) => Some(CacheLock { token }),
+            Ok(None) => None,
+            Err(e) => {
+                // Compute the response rather than wait for a lock that can't be taken
+                failed("lock", e);
+                Some(CacheLock::default())
+            }
+        }
+    }
+
+    async fn unlock(&self, key: &CacheKey, lock: CacheLock) {
+        if lock.token.is_empty() {
+            return;
+        }
+        let mut connection = self.connection.clone();
+        let result: Result<i64, RedisError> = Script::new(UNLOCK_SCRIPT)
+            .key(self.lock_key(key))
+            .arg(lock.token)
+            .invoke_async(&mut connection)
+            .await;
+        if let Err(e) = result {
+            failed("unlock", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Task;
+
+    use super::*;
+
+    #[test]
+    fn test_redis_keys_hash_the_input() {
+        let key = CacheKey::text(Task::Embeddings, "hello");
+        assert_eq!(
+            redis_key("mighty:", RESPONSE_TAG, &key),
+            "mighty:response:embeddings:75d927feaf07475477bb1b2e7119e0f14b0d873dd755cf48167c812e74bd97e7"
+        );
+        // Flushes only match responses, leaving the locks of computations in flight
+        assert!(redis_key("mighty:", LOCK_TAG, &key).starts_with("mighty:lock:"));
+
+        let question = CacheKey {
+            scope: String::new(),
+            task: Task::QuestionAnswering,
+            text: "a".to_string(),
+            context: "bc".to_string(),
+        };
+        let other = CacheKey {
+            scope: String::new(),
+            task: Task::QuestionAnswering,
+            text: "ab".to_string(),
+            context: "c".to_string(),
+        };
+        assert_ne!(
+            redis_key("", RESPONSE_TAG, &question),
+            redis_key("", RESPONSE_TAG, &other)
+        );
+
+        let scoped = CacheKey {
+            scope: "primary".to_string(),
+            ..CacheKey::text(Task::Embeddings, "hello")
+        };
+        let unscoped = CacheKey::text(Task::Embeddings, "primaryhello");
+        assert_ne!(
+            redis_key("", RESPONSE_TAG, &scoped),
+            redis_key("", RESPONSE_TAG, &unscoped)
+        );
+    }
+
+    #[test]
+    fn test_scan_patterns_escape_the_prefix() {
+        assert_eq!(scan_pattern("mighty:"), "mighty:response:*");
+        assert_eq!(scan_pattern("a*b[1]:"), "a\\*b\\[1\\]:response:*");
+    }
+}
@@ -0,0 +1,147 @@
+//! Negotiation and decoding of the body format of upstream responses: JSON, or MessagePack and
+//! CBOR with the `msgpack` and `cbor` features, whose floats are decoded from their binary form
+//! instead of being parsed from text.
+
+use std::io::{BufReader, Read};
+
+use reqwest::header::HeaderValue;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::config::ResponseFormat;
+
+/// Returns the `Accept` header requesting `format`, JSON remaining acceptable for upstreams that
+/// don't support it, or `None` for JSON itself or a format the gateway can't decode.
+pub fn accept(format: ResponseFormat) -> Option<HeaderValue> {
+    if !format.is_supported() {
+        return None;
+    }
+    match format {
+        ResponseFormat::Json => None,
+        ResponseFormat::Msgpack => Some(HeaderValue::from_static(
+            "application/msgpack, application/json;q=0.5",
+        )),
+        ResponseFormat::Cbor => Some(HeaderValue::from_static(
+            "application/cbor, application/json;q=0.5",
+        )),
+    }
+}
+
+/// An upstream response body that couldn't be decoded in its format.
+#[derive(Debug)]
+pub struct ParseError {
+    pub format: &'static str,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(format: &'static str, error: impl std::fmt::Display) -> Self {
+        Self {
+            format,
+            message: error.to_string(),
+        }
+    }
+}
+
+/// Returns the format named by the `Content-Type` of a response, JSON by default or when the
+/// gateway can't decode it.
+fn format(content_type: Option<&str>) -> ResponseFormat {
+    let mime = content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|mime| mime.trim().to_ascii_lowercase());
+    let format = match mime.as_deref() {
+        Some("application/msgpack" | "application/x-msgpack") => ResponseFormat::Msgpack,
+        Some("application/cbor") => ResponseFormat::Cbor,
+        _ => ResponseFormat::Json,
+    };
+    if format.is_supported() {
+        format
+    } else {
+        ResponseFormat::Json
+    }
+}
+
+/// Decodes a response body in the format named by its `content_type`, JSON by default.
+pub fn parse(content_type: Option<&str>, body: &[u8]) -> Result<Value, ParseError> {
+    deserialize(content_type, body)
+}
+
+/// Deserializes a response body like `parse`, straight into a `T` rather than a `Value`.
+pub fn deserialize<T: DeserializeOwned>(
+    content_type: Option<&str>,
+    body: &[u8],
+) -> Result<T, ParseError> {
+    match format(content_type) {
+        #[cfg(feature = "msgpack")]
+        ResponseFormat::Msgpack => {
+            rmp_serde::from_slice(body).map_err(|e| ParseError::new("MessagePack", e))
+        }
+        #[cfg(feature = "cbor")]
+        ResponseFormat::Cbor => ciborium::from_reader(body).map_err(|e| ParseError::new("CBOR", e)),
+        _ => serde_json::from_slice(body).map_err(|e| ParseError::new("JSON", e)),
+    }
+}
+
+/// Decodes a response body like `parse` as it's read from `reader`, e.g. while decompressing
+/// it, without buffering it first.
+pub fn parse_reader(content_type: Option<&str>, reader: impl Read) -> Result<Value, ParseError> {
+    deserialize_reader(content_type, reader)
+}
+
+/// Deserializes a response body like `parse_reader`, straight into a `T` rather than a `Value`.
+pub fn deserialize_reader<T: DeserializeOwned>(
+    content_type: Option<&str>,
+    reader: impl Read,
+) -> Result<T, ParseError> {
+    let reader = BufReader::new(reader);
+    match format(content_type) {
+        #[cfg(feature = "msgpack")]
+        ResponseFormat::Msgpack => {
+            rmp_serde::from_read(reader).map_err(|e| ParseError::new("MessagePack", e))
+        }
+        #[cfg(feature = "cbor")]
+        ResponseFormat::Cbor => {
+            ciborium::from_reader(reader).map_err(|e| ParseError::new("CBOR", e))
+        }
+        _ => serde_json::from_reader(reader).map_err(|e| ParseError::new("JSON", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_bodies_are_decoded_in_their_format() {
+        let response =
+            json!({ "took": 4, "text": "a", "outputs": [[0.5, -0.25]], "shape": [1, 2] });
+        let body = serde_json::to_vec(&response).unwrap();
+        assert_eq!(parse(None, &body).unwrap(), response);
+        assert_eq!(
+            parse(Some("application/json; charset=utf-8"), &body).unwrap(),
+            response
+        );
+        let error = parse(None, b"{").unwrap_err();
+        assert_eq!(error.format, "JSON");
+        assert_eq!(parse_reader(None, body.as_slice()).unwrap(), response);
+
+        #[cfg(feature = "msgpack")]
+        {
+            let body = rmp_serde::to_vec_named(&response).unwrap();
+            assert_eq!(parse(Some("application/msgpack"), &body).unwrap(), response);
+            let reader = body.as_slice();
+            assert_eq!(
+                parse_reader(Some("application/msgpack"), reader).unwrap(),
+                response
+            );
+        }
+        #[cfg(feature = "cbor")]
+        {
+            let mut body = Vec::new();
+            ciborium::into_writer(&response, &mut body).unwrap();
+            assert_eq!(parse(Some("application/cbor"), &body).unwrap(), response);
+        }
+    }
+}
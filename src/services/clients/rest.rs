@@ -1,48 +1,617 @@
-use std::error::Error;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::future::Future;
+use std::time::Duration;
 
 use async_trait::async_trait;
+use hyper::body::Bytes;
 use log::{debug, error, trace};
-use reqwest::Client;
-use serde_json::Value;
-use tonic::{Request, Response, Status};
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCEPT, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE,
+    USER_AGENT,
+};
+use reqwest::{Client, ClientBuilder, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use tonic::metadata::MetadataValue;
+use tonic::{Code, Request, Response, Status};
+use tracing::field::display;
+use tracing::Span;
 
+use crate::config::{HttpVersion, MightyServerConfig, ResilienceConfig};
 use crate::proto::mighty_proto::{
     EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse, QuestionAnswerRequest,
     QuestionAnswerResponse, SentenceTransformersResponse, SequenceClassificationResponse,
     TextRequest, TokenClassificationResponse,
 };
+use crate::services::clients::content_encoding::{self, BodyTooLarge};
+use crate::services::clients::discovery::{ClientFactory, Endpoint};
+use crate::services::clients::grpc_upstream::{is_grpc_url, MightyGrpcUpstreamClient};
 use crate::services::clients::json_response_converters::{
-    json_to_embeddings_response, json_to_metadata_response, json_to_question_answer_response,
-    json_to_sentence_transformers_response, json_to_sequence_classification_response,
-    json_to_token_classification_response,
+    json_to_response, rename_fields, JsonResponse,
 };
+use crate::services::clients::response_format;
+use crate::services::clients::unix_socket::{self, UnixSocketClient};
+use crate::services::context::{RequestContext, RAW_JSON_METADATA};
+use crate::services::telemetry::trace_context_headers;
+
+use super::load_balancer::LoadBalancedClient;
+use super::{one_by_one, MightyClient, UpstreamUpdate};
+
+/// Creates the REST client for the configured Mighty server: a single `MightyServerRestClient`
+/// for one base URL, or a `LoadBalancedClient` over one client per upstream instance when
+/// several base URLs are configured, DNS discovery is enabled or new upstreams are ramped up.
+/// Base URLs of the form `grpc://host:port` are served by a `MightyGrpcUpstreamClient` instead.
+/// The tasks `resilience` doesn't hedge are never hedged by the load balancer.
+///
+/// # Panics
+///
+/// Panics if no base URL is configured or a `grpc://` base URL is invalid. Must be called from
+/// within a Tokio runtime when load balancing or calling gRPC upstreams, as health checks,
+/// discovery and gRPC channels run as background tasks.
+pub fn create_rest_client(
+    config: &MightyServerConfig,
+    resilience: &ResilienceConfig,
+) -> Box<dyn MightyClient> {
+    create_rest_backend(config, resilience).0
+}
+
+/// Creates the REST client for the configured Mighty server like `create_rest_client`, along
+/// with a handle to update its upstream instances while serving when it load balances them.
+///
+/// # Panics
+///
+/// Panics like `create_rest_client`.
+pub fn create_rest_backend(
+    config: &MightyServerConfig,
+    resilience: &ResilienceConfig,
+) -> (Box<dyn MightyClient>, Option<Arc<dyn UpstreamUpdate>>) {
+    let base_urls = &config.base_url;
+    assert!(
+        !base_urls.is_empty(),
+        "Base URL for Mighty Server is missing"
+    );
+    let balanced = config.discovery.enabled || config.ramp.enabled;
+    if let ([base_url], false) = (base_urls.as_slice(), balanced) {
+        return (upstream_client(base_url, config), None);
+    }
 
-use super::MightyClient;
+    let upstreams = base_urls
+        .iter()
+        .map(|base_url| {
+            (base_url.clone(), upstream_client(base_url, config))
+        })
+        .collect();
+    let factory_config = config.clone();
+    let factory: ClientFactory = Arc::new(move |endpoint: &Endpoint| {
+        if is_grpc_url(&endpoint.base_url) {
+            return Box::new(MightyGrpcUpstreamClient::for_endpoint(
+                endpoint,
+                &factory_config,
+            ));
+        }
+        Box::new(MightyServerRestClient::for_endpoint(
+            endpoint,
+            &factory_config,
+        ))
+    });
+    let client = Arc::new(
+        LoadBalancedClient::new(upstreams, config.load_balancing)
+            .with_task_strategies(config.task_load_balancing.clone())
+            .with_health_checks(config.health_check.clone())
+            .with_hedging(config.hedging.clone())
+            .with_unhedged_tasks(resilience.unhedged_tasks())
+            .with_ramp(config.ramp.clone())
+            .with_discovery(base_urls.clone(), &config.discovery, factory),
+    );
+    let upstream_update: Arc<dyn UpstreamUpdate> = client.clone();
+    (
+        Box::new(client as Arc<dyn MightyClient>),
+        Some(upstream_update),
+    )
+}
+
+/// Creates the client of a single upstream instance: a `MightyGrpcUpstreamClient` for
+/// `grpc://` base URLs, a `MightyServerRestClient` otherwise.
+fn upstream_client(base_url: &str, config: &MightyServerConfig) -> Box<dyn MightyClient> {
+    if is_grpc_url(base_url) {
+        return Box::new(MightyGrpcUpstreamClient::with_config(
+            base_url.to_string(),
+            config,
+        ));
+    }
+    Box::new(MightyServerRestClient::with_config(
+        base_url.to_string(),
+        config,
+    ))
+}
 
 /// The `MightyServerRestClient` struct implements the `MightyClient` trait and provides a client that
 /// makes HTTP requests to the Mighty Inference Server REST API endpoints.
 ///
 /// It leverages the `reqwest` library to perform asynchronous HTTP requests and handles
-/// responses, converting them into appropriate gRPC responses.
+/// responses, converting them into appropriate gRPC responses. Compressed upstream responses
+/// (`gzip`, `deflate` and `br`) are negotiated via `Accept-Encoding` and decoded transparently,
+/// as are MessagePack and CBOR responses requested with `response_format`.
+///
+/// Base URLs of the form `unix:///path/to/mighty.sock` are served over a Unix domain socket
+/// through a `UnixSocketClient` instead.
+///
+/// Response bodies larger than the configured `max_body_size`, before or after decompression,
+/// are rejected with `RESOURCE_EXHAUSTED` without being buffered in full. With
+/// `strict_responses`, so are responses missing a field or of an unexpected type, with `INTERNAL`.
+///
+/// Fields of upstream responses named like a key of `field_aliases` are renamed to its value
+/// before conversion, for upstreams whose responses name fields differently.
+///
+/// With `batch_endpoints`, batch calls POST all their texts in one request, as the `texts` array
+/// of a JSON body, and expect a JSON array of one response per text, in order.
 #[derive(Debug, Default)]
 pub struct MightyServerRestClient {
     client: Client,
     base_url: String,
+    unix_socket: Option<UnixSocketClient>,
+    max_body_size: Option<u64>,
+    forward_client_address: bool,
+    strict_responses: bool,
+    batch_endpoints: bool,
+    field_aliases: HashMap<String, String>,
+}
+
+/// The product token identifying the gateway in the `User-Agent` sent upstream.
+pub const USER_AGENT_PRODUCT: &str = concat!("mighty-grpc/", env!("CARGO_PKG_VERSION"));
+
+/// The header (and caller metadata key) carrying the chain of client addresses.
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// Returns the `User-Agent` sent upstream, prefixed with the configured deployment identifier.
+pub(crate) fn user_agent(config: &MightyServerConfig) -> String {
+    match &config.user_agent {
+        Some(identifier) => format!("{} {}", identifier, USER_AGENT_PRODUCT),
+        None => USER_AGENT_PRODUCT.to_string(),
+    }
+}
+
+/// Returns the headers sent with every upstream request.
+fn default_headers(config: &MightyServerConfig) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        ACCEPT_ENCODING,
+        HeaderValue::from_static(content_encoding::ACCEPT_ENCODING),
+    );
+    if let Some(accept) = response_format::accept(config.response_format) {
+        headers.insert(ACCEPT, accept);
+    }
+    match HeaderValue::try_from(user_agent(config)) {
+        Ok(value) => headers.insert(USER_AGENT, value),
+        Err(e) => {
+            error!("Invalid user agent, using the default: {}", e);
+            headers.insert(USER_AGENT, HeaderValue::from_static(USER_AGENT_PRODUCT))
+        }
+    };
+    headers
+}
+
+/// Returns how long the caller of `request` is still willing to wait, if it set a deadline.
+fn remaining<T>(request: &Request<T>) -> Option<Duration> {
+    RequestContext::get(request).and_then(RequestContext::remaining)
+}
+
+/// Returns whether the request asked for the raw upstream JSON.
+fn wants_raw_json<T>(request: &Request<T>) -> bool {
+    RequestContext::get(request).is_some_and(|context| context.raw_json)
+}
+
+/// Wraps a converted upstream response, attaching `raw_json`, if any, to the response (or error)
+/// metadata, so callers can see what the upstream actually returned when conversion fails, drops
+/// fields or produces zeros.
+fn attach_raw_json<T>(
+    result: Result<T, Status>,
+    raw_json: Option<&Value>,
+) -> Result<Response<T>, Status> {
+    let mut result = result.map(Response::new);
+    if let Some(json) = raw_json {
+        let metadata = match &mut result {
+            Ok(response) => response.metadata_mut(),
+            Err(status) => status.metadata_mut(),
+        };
+        match serde_json::to_vec(json) {
+            Ok(body) => {
+                metadata.insert_bin(RAW_JSON_METADATA, MetadataValue::from_bytes(&body));
+            }
+            Err(e) => error!("Failed to serialize raw upstream JSON: {}", e),
+        }
+    }
+    result
+}
+
+/// Returns the value of the response header `name`, if any.
+fn header(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+fn body_too_large(path: &str, error: impl std::fmt::Display) -> Status {
+    Status::resource_exhausted(format!("{} response {}", path, error))
+}
+
+/// An upstream response whose body hasn't been decoded yet.
+struct RawResponse {
+    status: StatusCode,
+    content_type: Option<String>,
+    content_encoding: Option<String>,
+    body: Bytes,
 }
 
 impl MightyServerRestClient {
     pub fn new(base_url: String) -> Self {
+        Self::with_config(base_url, &MightyServerConfig::default())
+    }
+
+    /// Creates a client for `base_url` whose HTTP connections are tuned according to the
+    /// Mighty server configuration (connection pool size, idle timeout and HTTP version).
+    pub fn with_config(base_url: String, config: &MightyServerConfig) -> Self {
+        let client = Self::builder(config)
+            .build()
+            .expect("Failed to build HTTP client");
+        let unix_socket = unix_socket::socket_path(&base_url).map(|path| {
+            UnixSocketClient::new(path)
+                .with_headers(default_headers(config))
+                .with_max_body_size(config.max_body_size)
+        });
+        MightyServerRestClient {
+            base_url,
+            client,
+            unix_socket,
+            max_body_size: config.max_body_size,
+            forward_client_address: config.forward_client_address,
+            strict_responses: config.strict_responses,
+            batch_endpoints: config.batch_endpoints,
+            field_aliases: config.field_aliases.clone(),
+        }
+    }
+
+    /// Creates a client like `with_config` whose connections to the `base_url` host go to
+    /// `addr`, bypassing DNS resolution. Used to address one instance among several behind a
+    /// single hostname while keeping the `Host` header (and TLS server name) intact.
+    pub fn pinned(base_url: String, config: &MightyServerConfig, addr: SocketAddr) -> Self {
+        let mut builder = Self::builder(config);
+        if let Some(host) = Url::parse(&base_url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+        {
+            builder = builder.resolve(&host, addr);
+        }
+        let client = builder.build().expect("Failed to build HTTP client");
         MightyServerRestClient {
             base_url,
-            client: Client::new(),
+            client,
+            unix_socket: None,
+            max_body_size: config.max_body_size,
+            forward_client_address: config.forward_client_address,
+            strict_responses: config.strict_responses,
+            batch_endpoints: config.batch_endpoints,
+            field_aliases: config.field_aliases.clone(),
         }
     }
 
-    async fn fetch_json(&self, url: &str) -> Result<Value, Box<dyn Error>> {
-        let res = self.client.get(url).send().await?.text().await?;
-        let json: Value = serde_json::from_str(&res)?;
+    /// Creates a client for a discovered endpoint, pinned to its resolved address if any.
+    pub fn for_endpoint(endpoint: &Endpoint, config: &MightyServerConfig) -> Self {
+        match endpoint.addr {
+            Some(addr) => Self::pinned(endpoint.base_url.clone(), config, addr),
+            None => Self::with_config(endpoint.base_url.clone(), config),
+        }
+    }
+
+    fn builder(config: &MightyServerConfig) -> ClientBuilder {
+        let mut builder = Client::builder().default_headers(default_headers(config));
+
+        let pool = &config.pool;
+        if let Some(max_idle) = pool.max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+        if let Some(idle_timeout) = pool.idle_timeout {
+            builder = builder.pool_idle_timeout(idle_timeout);
+        }
+        match pool.http_version {
+            HttpVersion::Auto => builder,
+            HttpVersion::Http1 => builder.http1_only(),
+            HttpVersion::Http2 => builder.http2_prior_knowledge(),
+        }
+    }
+
+    /// Returns the per-request headers sent upstream: `X-Forwarded-For` when forwarding the
+    /// client address is enabled.
+    fn request_headers<T>(&self, request: &Request<T>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if !self.forward_client_address {
+            return headers;
+        }
+        let forwarded_for = request
+            .metadata()
+            .get(X_FORWARDED_FOR.as_str())
+            .and_then(|value| value.to_str().ok());
+        let peer = RequestContext::get(request).and_then(|context| context.peer_addr);
+        let value = match (forwarded_for, peer) {
+            (Some(forwarded_for), Some(peer)) => format!("{}, {}", forwarded_for, peer.ip()),
+            (Some(forwarded_for), None) => forwarded_for.to_string(),
+            (None, Some(peer)) => peer.ip().to_string(),
+            (None, None) => return headers,
+        };
+        if let Ok(value) = HeaderValue::try_from(value) {
+            headers.insert(X_FORWARDED_FOR, value);
+        }
+        headers
+    }
+
+    /// Issues a GET request against `path` on the upstream, bounded by `timeout`, the time left
+    /// before the deadline of the caller: requests still running then are abandoned with
+    /// `DEADLINE_EXCEEDED`, and none is sent once it has passed.
+    async fn get(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+        headers: HeaderMap,
+        timeout: Option<Duration>,
+    ) -> Result<RawResponse, Status> {
+        self.bounded(path, timeout, self.send(path, query, None, headers))
+            .await
+    }
+
+    /// Issues a POST request of the JSON `body` against `path`, bounded as `get` is.
+    async fn post_json(
+        &self,
+        path: &str,
+        body: Bytes,
+        headers: HeaderMap,
+        timeout: Option<Duration>,
+    ) -> Result<RawResponse, Status> {
+        self.bounded(path, timeout, self.send(path, &[], Some(body), headers))
+            .await
+    }
+
+    /// Runs the request `send` to `path` for up to `timeout`.
+    async fn bounded(
+        &self,
+        path: &str,
+        timeout: Option<Duration>,
+        send: impl Future<Output = Result<RawResponse, Status>>,
+    ) -> Result<RawResponse, Status> {
+        let Some(timeout) = timeout else {
+            return send.await;
+        };
+        let exceeded =
+            || Status::deadline_exceeded(format!("{} exceeded the caller's deadline", path));
+        if timeout.is_zero() {
+            return Err(exceeded());
+        }
+        tokio::time::timeout(timeout, send)
+            .await
+            .map_err(|_| exceeded())?
+    }
+
+    /// Sends a request against `path` to the upstream, over HTTP or the Unix domain socket: a
+    /// GET request, or a POST request of the JSON `body` when there is one.
+    ///
+    /// Query parameters are percent-encoded and the trace context is propagated. Errors are mapped
+    /// straight to a `Status` so that the request path allocates a single error message at most.
+    async fn send(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+        body: Option<Bytes>,
+        mut headers: HeaderMap,
+    ) -> Result<RawResponse, Status> {
+        headers.extend(trace_context_headers());
+        let Some(unix_socket) = &self.unix_socket else {
+            let url = format!("{}{}", self.base_url, path);
+            let request = match body {
+                Some(body) => self
+                    .client
+                    .post(&url)
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(body),
+                None => self.client.get(&url),
+            };
+            let mut res = request
+                .query(query)
+                .headers(headers)
+                .send()
+                .await
+                .map_err(|e| Status::unavailable(format!("Error fetching {}: {}", path, e)))?;
+            let status = res.status();
+            let content_type = header(res.headers(), CONTENT_TYPE);
+            let content_encoding = header(res.headers(), CONTENT_ENCODING);
+            let read_error = |e: reqwest::Error| {
+                Status::internal(format!("Error reading {} response: {}", path, e))
+            };
+            let body = match self.max_body_size {
+                Some(limit) => {
+                    if res.content_length().is_some_and(|length| length > limit) {
+                        return Err(body_too_large(path, BodyTooLarge { limit }));
+                    }
+                    let mut body = Vec::new();
+                    while let Some(chunk) = res.chunk().await.map_err(read_error)? {
+                        if (body.len() + chunk.len()) as u64 > limit {
+                            return Err(body_too_large(path, BodyTooLarge { limit }));
+                        }
+                        body.extend_from_slice(&chunk);
+                    }
+                    Bytes::from(body)
+                }
+                None => res.bytes().await.map_err(read_error)?,
+            };
+            return Ok(RawResponse {
+                status,
+                content_type,
+                content_encoding,
+                body,
+            });
+        };
+
+        let mut url = Url::parse("http://localhost").expect("Valid URL");
+        url.set_path(path);
+        if !query.is_empty() {
+            url.query_pairs_mut().extend_pairs(query);
+        }
+        let path_and_query = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        let res = match body {
+            Some(body) => unix_socket.post_json(&path_and_query, &headers, body).await,
+            None => unix_socket.get(&path_and_query, &headers).await,
+        };
+        let res = res
+            .map_err(|e| {
+                if BodyTooLarge::is(&e) {
+                    body_too_large(path, e)
+                } else {
+                    Status::unavailable(format!("Error fetching {}: {}", path, e))
+                }
+            })?;
+        Ok(RawResponse {
+            status: res.status,
+            content_type: header(&res.headers, CONTENT_TYPE),
+            content_encoding: header(&res.headers, CONTENT_ENCODING),
+            body: res.body,
+        })
+    }
+
+    /// Issues a GET request against `path` on the upstream and converts its (decoded) body, in
+    /// JSON or the binary format the upstream answered with, once its aliased fields are renamed.
+    async fn fetch<T: JsonResponse>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+        headers: HeaderMap,
+        timeout: Option<Duration>,
+        raw_json: bool,
+    ) -> Result<Response<T>, Status> {
+        let res = self.get(path, query, headers, timeout).await?;
+        self.decode(path, res, raw_json, &self.field_aliases)
+    }
+
+    /// Converts the response `res` of `path` once the fields named like a key of `aliases` are
+    /// renamed, attaching the JSON as received when `raw_json` is set.
+    ///
+    /// Unless fields are renamed or the raw JSON is requested, the body is decoded once, straight
+    /// into the typed fields of `T`; otherwise it's parsed into a `Value`, whose fields are
+    /// renamed in place.
+    fn decode<T: JsonResponse>(
+        &self,
+        path: &str,
+        res: RawResponse,
+        raw_json: bool,
+        aliases: &HashMap<String, String>,
+    ) -> Result<Response<T>, Status> {
+        let strict = self.strict_responses;
+        if aliases.is_empty() && !raw_json {
+            let json = self.deserialize::<T::Json>(path, &res)?;
+            return Ok(Response::new(T::convert(json, strict)?));
+        }
+        let json = self.parse_json(path, res)?;
+        if aliases.is_empty() {
+            return attach_raw_json(json_to_response(&json, strict), Some(&json));
+        }
+        // Fields are renamed in place, so the JSON as received is only copied when attached
+        let received = raw_json.then(|| json.clone());
+        let renamed = rename_fields(json, aliases);
+        attach_raw_json(json_to_response(renamed, strict), received.as_ref())
+    }
+
+    /// Parses the (decoded) body of the response `res` of `path` as JSON, or in the binary format
+    /// the upstream answered with.
+    fn parse_json(&self, path: &str, res: RawResponse) -> Result<Value, Status> {
+        let json = self.deserialize(path, &res)?;
+
+        trace!("Parsed JSON: {:?}", json);
         Ok(json)
     }
+
+    /// Deserializes the (decoded) body of the response `res` of `path` like `parse_json`,
+    /// straight into a `T`.
+    fn deserialize<T: DeserializeOwned>(&self, path: &str, res: &RawResponse) -> Result<T, Status> {
+        // Recorded on the span of the `TracedClient`, if any
+        Span::current()
+            .record(
+                "upstream",
+                display(format_args!("{}{}", self.base_url, path)),
+            )
+            .record("http.status", res.status.as_u16());
+        let content_type = res.content_type.as_deref();
+        let parse_error = |e: response_format::ParseError| {
+            Status::internal(format!(
+                "Failed to parse {} {}: {}",
+                path, e.format, e.message
+            ))
+        };
+        if content_encoding::is_identity(res.content_encoding.as_deref()) {
+            response_format::deserialize(content_type, &res.body).map_err(parse_error)
+        } else {
+            // Deserialized as it's decompressed, so the decompressed body is never buffered
+            let decoding_error = |e: &dyn std::fmt::Display| {
+                Status::internal(format!("Error decoding {} response: {}", path, e))
+            };
+            let mut reader = content_encoding::decoding_reader(
+                res.content_encoding.as_deref(),
+                &res.body,
+                self.max_body_size,
+            )
+            .map_err(|e| decoding_error(&e))?;
+            response_format::deserialize_reader(content_type, &mut reader).map_err(|e| {
+                if let Some(error) = reader.exceeded() {
+                    body_too_large(path, error)
+                } else if reader.failed() {
+                    decoding_error(&e.message)
+                } else {
+                    parse_error(e)
+                }
+            })
+        }
+    }
+
+    /// Issues a batch call of all the texts of `request` against `path` and converts its JSON
+    /// array of responses, failing unless there is one response per text.
+    async fn fetch_batch<T>(
+        &self,
+        path: &str,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<T>>, Status>
+    where
+        Vec<T>: JsonResponse,
+    {
+        let raw_json = wants_raw_json(&request);
+        let headers = self.request_headers(&request);
+        let timeout = remaining(&request);
+        let texts = request.into_inner();
+        if texts.is_empty() {
+            return Ok(Response::new(Vec::new()));
+        }
+        let body = serde_json::to_vec(&json!({ "texts": texts }))
+            .map_err(|e| Status::internal(format!("Failed to encode the batch: {}", e)))?;
+        let res = self
+            .post_json(path, Bytes::from(body), headers, timeout)
+            .await?;
+        let mut response = self.decode::<Vec<T>>(path, res, raw_json, &self.field_aliases)?;
+        if response.get_ref().len() != texts.len() {
+            // The raw JSON attached to the response, if any, is kept
+            return Err(Status::with_metadata(
+                Code::Internal,
+                format!(
+                    "{} returned {} responses for {} texts",
+                    path,
+                    response.get_ref().len(),
+                    texts.len()
+                ),
+                std::mem::take(response.metadata_mut()),
+            ));
+        }
+        Ok(response)
+    }
 }
 
 #[async_trait]
@@ -52,14 +621,14 @@ impl MightyClient for MightyServerRestClient {
         _request: Request<Empty>,
     ) -> Result<Response<HealthcheckResponse>, Status> {
         debug!("Received health check request: {:?}", _request);
-        let url = format!("{}/healthcheck", self.base_url);
-
-        let res = self.client.get(&url).send().await.map_err(|e| {
-            error!("HTTP request error: {}", e);
-            Status::internal(format!("HTTP request error: {}", e))
-        })?;
+        let headers = self.request_headers(&_request);
+        let timeout = remaining(&_request);
+        let res = self
+            .get("/healthcheck", &[], headers, timeout)
+            .await
+            .inspect_err(|status| error!("HTTP request error: {}", status.message()))?;
 
-        if res.status() == reqwest::StatusCode::OK {
+        if res.status == StatusCode::OK {
             debug!("Healthcheck response status is OK");
             let response = HealthcheckResponse { success: true };
             return Ok(Response::new(response));
@@ -74,18 +643,18 @@ impl MightyClient for MightyServerRestClient {
         request: Request<TextRequest>,
     ) -> Result<Response<EmbeddingsResponse>, Status> {
         debug!("Received embeddings request: {:?}", request);
+        let raw_json = wants_raw_json(&request);
+        let headers = self.request_headers(&request);
+        let timeout = remaining(&request);
         let text = request.into_inner().text;
-        let url = format!("{}/embeddings?text={}", self.base_url, text);
-        let json = self
-            .fetch_json(&url)
-            .await
-            .map_err(|e| Status::internal(format!("Error fetching embeddings: {}", e)))?;
-
-        trace!("Parsed JSON: {:?}", json);
-
-        json_to_embeddings_response(&json)
-            .map(Response::new)
-            .map_err(|e| Status::internal(format!("Error creating response: {}", e)))
+        self.fetch(
+            "/embeddings",
+            &[("text", &text)],
+            headers,
+            timeout,
+            raw_json,
+        )
+        .await
     }
 
     async fn question_answering(
@@ -93,22 +662,23 @@ impl MightyClient for MightyServerRestClient {
         request: Request<QuestionAnswerRequest>,
     ) -> Result<Response<QuestionAnswerResponse>, Status> {
         debug!("Received question answering request: {:?}", request);
+        let raw_json = wants_raw_json(&request);
+        let headers = self.request_headers(&request);
+        let timeout = remaining(&request);
         let req = request.into_inner();
-        let url = format!(
-            "{}/question-answering?question={}&context={}",
-            self.base_url, req.question, req.context
-        );
-
-        let json: Value = self
-            .fetch_json(&url)
-            .await
-            .map_err(|e| Status::internal(format!("Failed to parse JSON: {}", e)))?;
-
-        trace!("Parsed JSON: {:?}", json);
-
-        json_to_question_answer_response(&json, req.question, req.context)
-            .map(Response::new)
-            .map_err(|e| Status::internal(format!("Error creating response: {}", e)))
+        let mut response: Response<QuestionAnswerResponse> = self
+            .fetch(
+                "/question-answering",
+                &[("question", &req.question), ("context", &req.context)],
+                headers,
+                timeout,
+                raw_json,
+            )
+            .await?;
+        let answer = response.get_mut();
+        answer.question = req.question;
+        answer.context = req.context;
+        Ok(response)
     }
 
     async fn sentence_transformers(
@@ -116,19 +686,18 @@ impl MightyClient for MightyServerRestClient {
         request: Request<TextRequest>,
     ) -> Result<Response<SentenceTransformersResponse>, Status> {
         debug!("Received sentence_transformers request: {:?}", request);
+        let raw_json = wants_raw_json(&request);
+        let headers = self.request_headers(&request);
+        let timeout = remaining(&request);
         let text = request.into_inner().text;
-        let url = format!("{}/sentence-transformers?text={}", self.base_url, text);
-
-        let json: Value = self
-            .fetch_json(&url)
-            .await
-            .map_err(|e| Status::internal(format!("Failed to parse JSON: {}", e)))?;
-
-        trace!("Parsed JSON: {:?}", json);
-
-        json_to_sentence_transformers_response(&json)
-            .map(Response::new)
-            .map_err(|e| Status::internal(format!("Error creating response: {}", e)))
+        self.fetch(
+            "/sentence-transformers",
+            &[("text", &text)],
+            headers,
+            timeout,
+            raw_json,
+        )
+        .await
     }
 
     async fn sequence_classification(
@@ -136,19 +705,18 @@ impl MightyClient for MightyServerRestClient {
         request: Request<TextRequest>,
     ) -> Result<Response<SequenceClassificationResponse>, Status> {
         debug!("Received sequence_classification request: {:?}", request);
+        let raw_json = wants_raw_json(&request);
+        let headers = self.request_headers(&request);
+        let timeout = remaining(&request);
         let text = request.into_inner().text;
-        let url = format!("{}/sequence-classification?text={}", self.base_url, text);
-
-        let json: Value = self
-            .fetch_json(&url)
-            .await
-            .map_err(|e| Status::internal(format!("Failed to parse JSON: {}", e)))?;
-
-        trace!("Parsed JSON: {:?}", json);
-
-        json_to_sequence_classification_response(&json)
-            .map(Response::new)
-            .map_err(|e| Status::internal(format!("Error creating response: {}", e)))
+        self.fetch(
+            "/sequence-classification",
+            &[("text", &text)],
+            headers,
+            timeout,
+            raw_json,
+        )
+        .await
     }
 
     async fn token_classification(
@@ -156,34 +724,171 @@ impl MightyClient for MightyServerRestClient {
         request: Request<TextRequest>,
     ) -> Result<Response<TokenClassificationResponse>, Status> {
         debug!("Received token_classification request: {:?}", request);
+        let raw_json = wants_raw_json(&request);
+        let headers = self.request_headers(&request);
+        let timeout = remaining(&request);
         let text = request.into_inner().text;
-        let url = format!("{}/token-classification?text={}", self.base_url, text);
-
-        let json: Value = self
-            .fetch_json(&url)
-            .await
-            .map_err(|e| Status::internal(format!("Failed to parse JSON: {}", e)))?;
-
-        trace!("Parsed JSON: {:?}", json);
-
-        json_to_token_classification_response(&json)
-            .map(Response::new)
-            .map_err(|e| Status::internal(format!("Error creating response: {}", e)))
+        self.fetch(
+            "/token-classification",
+            &[("text", &text)],
+            headers,
+            timeout,
+            raw_json,
+        )
+        .await
     }
+
     async fn metadata(
         &self,
-        _request: Request<Empty>,
+        request: Request<Empty>,
     ) -> Result<Response<MetadataResponse>, Status> {
         debug!("Received metadata request");
-        let url = format!("{}/metadata", self.base_url);
-        let json: Value = self
-            .fetch_json(&url)
-            .await
-            .map_err(|e| Status::internal(format!("Failed to fetch metadata JSON: {}", e)))?;
+        let raw_json = wants_raw_json(&request);
+        let headers = self.request_headers(&request);
+        let timeout = remaining(&request);
+        let res = self.get("/metadata", &[], headers, timeout).await?;
+        // Metadata fields are never renamed
+        self.decode("/metadata", res, raw_json, &HashMap::new())
+    }
 
-        trace!("Parsed JSON: {:?}", json);
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        debug!("Received embeddings batch request: {:?}", request);
+        if !self.batch_endpoints {
+            return one_by_one(request, |request| self.embeddings(request)).await;
+        }
+        self.fetch_batch("/embeddings", request).await
+    }
 
-        let metadata_response = json_to_metadata_response(&json)?;
-        Ok(Response::new(metadata_response))
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        debug!("Received token_classification batch request: {:?}", request);
+        if !self.batch_endpoints {
+            return one_by_one(request, |request| self.token_classification(request)).await;
+        }
+        self.fetch_batch("/token-classification", request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_agent() {
+        let mut config = MightyServerConfig::default();
+        assert_eq!(user_agent(&config), USER_AGENT_PRODUCT);
+
+        config.user_agent = Some("search-gateway-eu1".to_string());
+        assert_eq!(
+            user_agent(&config),
+            format!(
+                "search-gateway-eu1 mighty-grpc/{}",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+
+    #[test]
+    fn test_forwarded_for_appends_peer() {
+        let config = MightyServerConfig {
+            forward_client_address: true,
+            ..Default::default()
+        };
+        let client =
+            MightyServerRestClient::with_config("http://localhost:5050".to_string(), &config);
+        let mut request = Request::new(Empty {});
+        request
+            .metadata_mut()
+            .insert("x-forwarded-for", "203.0.113.7".parse().unwrap());
+        request.extensions_mut().insert(RequestContext {
+            peer_addr: Some("10.0.0.2:41234".parse().unwrap()),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            client.request_headers(&request)[X_FORWARDED_FOR],
+            "203.0.113.7, 10.0.0.2"
+        );
+        assert!(
+            MightyServerRestClient::new("http://localhost:5050".to_string())
+                .request_headers(&request)
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upstream_calls_are_bounded_by_the_caller_deadline() {
+        // Accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let client = MightyServerRestClient::new(base_url);
+        let with_deadline = |timeout: Duration| {
+            let mut request = Request::new(TextRequest {
+                text: "hello".to_string(),
+            });
+            request.extensions_mut().insert(RequestContext {
+                deadline: Some(std::time::Instant::now() + timeout),
+                ..Default::default()
+            });
+            request
+        };
+
+        for timeout in [Duration::ZERO, Duration::from_millis(50)] {
+            let status = client.embeddings(with_deadline(timeout)).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::DeadlineExceeded);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batches_are_sent_in_one_call() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            // The headers and the body may arrive apart
+            let mut request = Vec::new();
+            while !request.ends_with(b"]}") {
+                let mut buf = [0; 4096];
+                let len = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..len]);
+            }
+            let _ = sender.send(String::from_utf8_lossy(&request).into_owned());
+            let body = serde_json::json!([
+                { "text": "a", "outputs": [[0.5]], "shape": [1, 1] },
+                { "text": "b", "outputs": [[0.25]], "shape": [1, 1] }
+            ])
+            .to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+        let config = MightyServerConfig {
+            batch_endpoints: true,
+            ..Default::default()
+        };
+        let client = MightyServerRestClient::with_config(base_url, &config);
+
+        let texts = vec!["a".to_string(), "b".to_string()];
+        let responses = client
+            .embeddings_batch(Request::new(texts))
+            .await
+            .unwrap()
+            .into_inner();
+        let request = receiver.await.unwrap();
+        assert!(request.starts_with("POST /embeddings "));
+        assert!(request.ends_with("\r\n\r\n{\"texts\":[\"a\",\"b\"]}"));
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[1].embeddings[0].values, [0.25]);
     }
 }
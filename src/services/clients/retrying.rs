@@ -0,0 +1,316 @@
+use std::future::Future;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use log::debug;
+use rand::Rng;
+use tonic::{Code, Request, Response, Status};
+
+use crate::config::{ResilienceConfig, ResiliencePolicy, Task};
+use crate::proto::mighty_proto::{
+    EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse, QuestionAnswerRequest,
+    QuestionAnswerResponse, RerankRequest, RerankResponse, SentenceTransformersResponse,
+    SequenceClassificationResponse, TextRequest, TokenClassificationResponse,
+};
+use crate::services::context::RequestContext;
+use crate::services::metrics::Metrics;
+
+use super::{duplicate, MightyClient};
+
+/// Counter of the retries of failed upstream calls, by task.
+const RETRIES_METRIC: &str = "mighty_upstream_retries_total";
+
+/// The `RetryingClient` struct is a `MightyClient` decorator applying each task's resilience
+/// policy (see `ResilienceConfig`) to the calls of the wrapped client.
+///
+/// Each attempt is bounded by `attempt_timeout`, failing with `DEADLINE_EXCEEDED` once it
+/// elapses. Attempts failing transiently upstream (see `is_retryable`) are retried up to
+/// `max_attempts` in total, after an exponential backoff with jitter: a random delay between
+/// half and all of `initial_backoff * multiplier ^ retry`, bounded by `max_backoff`. A call isn't
+/// retried if its caller's deadline would pass during the backoff, the last failure being
+/// returned instead.
+///
+/// Health checks and metadata calls are not retried.
+pub struct RetryingClient {
+    inner: Box<dyn MightyClient>,
+    policies: Vec<(Task, ResiliencePolicy)>,
+}
+
+impl RetryingClient {
+    pub fn new(inner: Box<dyn MightyClient>, config: &ResilienceConfig) -> Self {
+        let policies = Task::ALL
+            .into_iter()
+            .map(|task| (task, config.policy(task)))
+            .collect();
+        Self { inner, policies }
+    }
+
+    /// Whether any task's calls are retried or their attempts bounded, i.e. whether the
+    /// decorator is needed at all.
+    pub fn is_needed(config: &ResilienceConfig) -> bool {
+        Task::ALL.into_iter().any(|task| {
+            let policy = config.policy(task);
+            policy.max_attempts > 1 || policy.attempt_timeout.is_some()
+        })
+    }
+
+    fn policy(&self, task: Task) -> &ResiliencePolicy {
+        let (_, policy) = self
+            .policies
+            .iter()
+            .find(|(candidate, _)| *candidate == task)
+            .expect("Every task has a policy");
+        policy
+    }
+
+    async fn retry<R, T, F>(
+        &self,
+        task: Task,
+        request: Request<R>,
+        call: impl Fn(Request<R>) -> F,
+    ) -> Result<Response<T>, Status>
+    where
+        R: Clone,
+        F: Future<Output = Result<Response<T>, Status>>,
+    {
+        let policy = self.policy(task);
+        let remaining = || RequestContext::get(&request).and_then(RequestContext::remaining);
+        let mut attempt = 1;
+        loop {
+            let result = match policy.attempt_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, call(duplicate(&request)))
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(Status::deadline_exceeded(format!(
+                            "The upstream didn't respond within {:?}",
+                            timeout
+                        )))
+                    }),
+                None => call(duplicate(&request)).await,
+            };
+            let status = match result {
+                Err(status) if is_retryable(&status) && attempt < policy.max_attempts => status,
+                result => return result,
+            };
+            let backoff = jitter(policy.backoff(attempt));
+            if remaining().is_some_and(|remaining| remaining <= backoff) {
+                return Err(status);
+            }
+            debug!(
+                "Retrying {} call in {:?} after attempt {} failed: {}",
+                task.as_str(),
+                backoff,
+                attempt,
+                status.message()
+            );
+            Metrics::global()
+                .counter(RETRIES_METRIC, &[("task", task.as_str())])
+                .increment(1);
+            tokio::time::sleep(backoff).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Returns whether a failed attempt may succeed if sent again, i.e. whether the upstream failed
+/// transiently. `INTERNAL` errors, e.g. malformed upstream responses, would fail again.
+fn is_retryable(status: &Status) -> bool {
+    matches!(
+        status.code(),
+        Code::Unavailable | Code::DeadlineExceeded | Code::Unknown
+    )
+}
+
+/// Returns a random delay between half and all of `backoff`, so clients failing together don't
+/// retry together.
+fn jitter(backoff: Duration) -> Duration {
+    if backoff.is_zero() {
+        return backoff;
+    }
+    rand::thread_rng().gen_range(backoff / 2..=backoff)
+}
+
+#[async_trait]
+impl MightyClient for RetryingClient {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        self.inner.health_check(request).await
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        self.retry(Task::Embeddings, request, |request| {
+            self.inner.embeddings(request)
+        })
+        .await
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        self.retry(Task::QuestionAnswering, request, |request| {
+            self.inner.question_answering(request)
+        })
+        .await
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        self.retry(Task::SentenceTransformers, request, |request| {
+            self.inner.sentence_transformers(request)
+        })
+        .await
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        self.retry(Task::SequenceClassification, request, |request| {
+            self.inner.sequence_classification(request)
+        })
+        .await
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        self.retry(Task::TokenClassification, request, |request| {
+            self.inner.token_classification(request)
+        })
+        .await
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        self.inner.metadata(request).await
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        self.retry(Task::Rerank, request, |request| self.inner.rerank(request))
+            .await
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        self.retry(Task::Embeddings, request, |request| {
+            self.inner.embeddings_batch(request)
+        })
+        .await
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        self.retry(Task::TokenClassification, request, |request| {
+            self.inner.token_classification_batch(request)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::config::ResilienceOverrides;
+    use crate::services::clients::mock::MockMightyClient;
+
+    use super::*;
+
+    fn text() -> Request<TextRequest> {
+        Request::new(TextRequest {
+            text: "text".to_string(),
+        })
+    }
+
+    fn retrying(mock: &Arc<MockMightyClient>) -> RetryingClient {
+        let client: Arc<dyn MightyClient> = mock.clone();
+        let config = ResilienceConfig {
+            policy: ResiliencePolicy {
+                max_attempts: 3,
+                initial_backoff: Duration::from_millis(1),
+                ..ResiliencePolicy::default()
+            },
+            tasks: HashMap::from([(
+                Task::TokenClassification,
+                ResilienceOverrides {
+                    max_attempts: Some(1),
+                    ..ResilienceOverrides::default()
+                },
+            )]),
+        };
+        RetryingClient::new(Box::new(client), &config)
+    }
+
+    #[tokio::test]
+    async fn test_upstream_failures_are_retried() {
+        let mock = Arc::new(
+            MockMightyClient::new()
+                .with_failures(Task::Embeddings, 2, Status::unavailable("down"))
+                .with_failures(Task::SentenceTransformers, 5, Status::unavailable("down"))
+                .with_failures(Task::TokenClassification, 1, Status::unavailable("down"))
+                .with_error(
+                    Task::SequenceClassification,
+                    Status::invalid_argument("bad"),
+                )
+                .with_error(Task::QuestionAnswering, Status::internal("malformed")),
+        );
+        let client = retrying(&mock);
+
+        assert!(client.embeddings(text()).await.is_ok());
+        assert_eq!(mock.calls(Task::Embeddings), 3);
+
+        // Attempts are bounded by max_attempts, overridable per task
+        assert!(client.sentence_transformers(text()).await.is_err());
+        assert_eq!(mock.calls(Task::SentenceTransformers), 3);
+        assert!(client.token_classification(text()).await.is_err());
+        assert_eq!(mock.calls(Task::TokenClassification), 1);
+
+        // Rejected requests aren't retried
+        assert!(client.sequence_classification(text()).await.is_err());
+        assert_eq!(mock.calls(Task::SequenceClassification), 1);
+        // Neither are calls failing for good upstream
+        let request = Request::new(QuestionAnswerRequest::default());
+        assert!(client.question_answering(request).await.is_err());
+        assert_eq!(mock.calls(Task::QuestionAnswering), 1);
+    }
+
+    #[tokio::test]
+    async fn test_slow_attempts_time_out() {
+        let mock = MockMightyClient::new().with_latency(Duration::from_millis(200));
+        let client: Arc<dyn MightyClient> = Arc::new(mock);
+        let config = ResilienceConfig {
+            policy: ResiliencePolicy {
+                max_attempts: 2,
+                initial_backoff: Duration::ZERO,
+                attempt_timeout: Some(Duration::from_millis(10)),
+                ..ResiliencePolicy::default()
+            },
+            ..ResilienceConfig::default()
+        };
+        let client = RetryingClient::new(Box::new(client), &config);
+
+        let start = std::time::Instant::now();
+        let status = client.embeddings(text()).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::DeadlineExceeded);
+        assert!(start.elapsed() < Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use tonic::{Request, Response, Status};
+
+use crate::proto::mighty_proto::{
+    EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse, QuestionAnswerRequest,
+    QuestionAnswerResponse, RerankRequest, RerankResponse, SentenceTransformersResponse,
+    SequenceClassificationResponse, TextRequest, TokenClassificationResponse,
+};
+use crate::services::context::{RequestContext, MODEL_HEADER};
+use crate::services::metrics::Metrics;
+
+use super::MightyClient;
+
+/// Counter of calls routed to a named model, by model.
+const ROUTED_METRIC: &str = "mighty_routed_requests_total";
+
+/// The `RoutingClient` struct is a `MightyClient` serving several models from one gateway, e.g.
+/// embedding, NER and QA Mighty servers: each call is sent to the backend of the model named
+/// by its `x-model` metadata entry (see `RequestContext::model`).
+///
+/// Calls without a model are sent to the default route when one is set, else to the primary
+/// backend. Calls naming a model without a route fail with `NOT_FOUND`, listing the models
+/// served.
+pub struct RoutingClient {
+    primary: Box<dyn MightyClient>,
+    routes: HashMap<String, Box<dyn MightyClient>>,
+    default: Option<String>,
+}
+
+impl RoutingClient {
+    /// Creates a client routing calls to `routes` by model, calls without a model being sent to
+    /// the `default` route if set, else to `primary`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `default` names a model without a route.
+    pub fn new(
+        primary: Box<dyn MightyClient>,
+        routes: Vec<(String, Box<dyn MightyClient>)>,
+        default: Option<String>,
+    ) -> Self {
+        let routes: HashMap<_, _> = routes.into_iter().collect();
+        if let Some(default) = &default {
+            assert!(
+                routes.contains_key(default),
+                "the default model {} has no route",
+                default
+            );
+        }
+        Self {
+            primary,
+            routes,
+            default,
+        }
+    }
+
+    /// Returns the backend serving the model selected by `request`.
+    fn route<T>(&self, request: &Request<T>) -> Result<&dyn MightyClient, Status> {
+        let model = RequestContext::get(request)
+            .and_then(|context| context.model.as_deref())
+            .or(self.default.as_deref());
+        let Some(model) = model else {
+            return Ok(self.primary.as_ref());
+        };
+        let client = self.routes.get(model).ok_or_else(|| {
+            let mut served: Vec<_> = self.routes.keys().map(String::as_str).collect();
+            served.sort_unstable();
+            Status::not_found(format!(
+                "No model {} is served; `{}` must be one of: {}",
+                model,
+                MODEL_HEADER,
+                served.join(", ")
+            ))
+        })?;
+        Metrics::global()
+            .counter(ROUTED_METRIC, &[("model", model)])
+            .increment(1);
+        Ok(client.as_ref())
+    }
+}
+
+#[async_trait]
+impl MightyClient for RoutingClient {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        self.route(&request)?.health_check(request).await
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        self.route(&request)?.embeddings(request).await
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        self.route(&request)?.question_answering(request).await
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        self.route(&request)?.sentence_transformers(request).await
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        self.route(&request)?.sequence_classification(request).await
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        self.route(&request)?.token_classification(request).await
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        self.route(&request)?.metadata(request).await
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        self.route(&request)?.rerank(request).await
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        self.route(&request)?.embeddings_batch(request).await
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        self.route(&request)?
+            .token_classification_batch(request)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tonic::Code;
+
+    use crate::config::Task;
+    use crate::services::clients::mock::MockMightyClient;
+
+    use super::*;
+
+    fn for_model(model: Option<&str>) -> Request<TextRequest> {
+        let mut request = Request::new(TextRequest {
+            text: "hello".to_string(),
+        });
+        request.extensions_mut().insert(RequestContext {
+            model: model.map(str::to_string),
+            ..Default::default()
+        });
+        request
+    }
+
+    fn boxed(client: &Arc<MockMightyClient>) -> Box<dyn MightyClient> {
+        let client: Arc<dyn MightyClient> = client.clone();
+        Box::new(client)
+    }
+
+    #[tokio::test]
+    async fn test_calls_are_routed_by_model() {
+        let primary = Arc::new(MockMightyClient::new());
+        let embeddings = Arc::new(MockMightyClient::new());
+        let ner = Arc::new(MockMightyClient::new());
+        let routes = vec![
+            ("embeddings".to_string(), boxed(&embeddings)),
+            ("ner".to_string(), boxed(&ner)),
+        ];
+        let client = RoutingClient::new(boxed(&primary), routes, None);
+
+        client
+            .embeddings(for_model(Some("embeddings")))
+            .await
+            .unwrap();
+        client
+            .token_classification(for_model(Some("ner")))
+            .await
+            .unwrap();
+        client.embeddings(for_model(None)).await.unwrap();
+        assert_eq!(embeddings.calls(Task::Embeddings), 1);
+        assert_eq!(ner.calls(Task::TokenClassification), 1);
+        assert_eq!(primary.calls(Task::Embeddings), 1);
+
+        let status = client.embeddings(for_model(Some("qa"))).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+        assert_eq!(
+            status.message(),
+            "No model qa is served; `x-model` must be one of: embeddings, ner"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_calls_without_a_model_take_the_default_route() {
+        let primary = Arc::new(MockMightyClient::new());
+        let embeddings = Arc::new(MockMightyClient::new());
+        let client = RoutingClient::new(
+            boxed(&primary),
+            vec![("embeddings".to_string(), boxed(&embeddings))],
+            Some("embeddings".to_string()),
+        );
+
+        client.embeddings(for_model(None)).await.unwrap();
+        assert_eq!(embeddings.calls(Task::Embeddings), 1);
+        assert_eq!(primary.calls(Task::Embeddings), 0);
+    }
+}
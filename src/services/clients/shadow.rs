@@ -0,0 +1,564 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use log::{debug, warn};
+use rand::Rng;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tonic::{Request, Response, Status};
+
+use crate::config::{ShadowConfig, Task};
+use crate::proto::mighty_proto::{
+    Embedding, EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse,
+    QuestionAnswerRequest, QuestionAnswerResponse, RerankRequest, RerankResponse,
+    SentenceTransformersResponse, SequenceClassificationResponse, TextRequest,
+    TokenClassificationResponse,
+};
+use crate::services::context::RequestContext;
+use crate::services::metrics::Metrics;
+
+use super::MightyClient;
+
+/// Counter of mirrored calls by task and `result`: `match`, `diverged`, or `skipped` when too
+/// many mirrored calls were in flight.
+const SHADOW_METRIC: &str = "mighty_shadow_comparisons_total";
+
+/// Compares the primary and shadow responses of a call, describing their divergence, if any.
+type Compare<T> = fn(&T, &T, &ShadowConfig) -> Option<String>;
+
+/// The `ShadowClient` struct is a `MightyClient` decorator mirroring inference calls to a
+/// secondary (shadow) backend, e.g. a new model version, and reporting where its responses
+/// diverge from the primary's, to validate it before cutover.
+///
+/// Callers always receive the primary response; the shadow call is made in the background once the
+/// primary one completed, for a `sample_rate` fraction of calls, and skipped while `max_in_flight`
+/// shadow calls are pending. It has its own `timeout`, rather than what is left of the caller's
+/// deadline once the primary call completed. Divergences (embeddings further apart than
+/// `max_cosine_distance`, another top label or answer, or a different outcome) are logged with the
+/// request id and counted by `mighty_shadow_comparisons_total`.
+pub struct ShadowClient {
+    inner: Box<dyn MightyClient>,
+    shadow: Arc<dyn MightyClient>,
+    config: Arc<ShadowConfig>,
+    in_flight: Arc<Semaphore>,
+}
+
+impl ShadowClient {
+    pub fn new(
+        inner: Box<dyn MightyClient>,
+        shadow: Box<dyn MightyClient>,
+        config: &ShadowConfig,
+    ) -> Self {
+        Self {
+            inner,
+            shadow: Arc::from(shadow),
+            config: Arc::new(config.clone()),
+            in_flight: Arc::new(Semaphore::new(config.max_in_flight)),
+        }
+    }
+
+    /// Copies `request` if it is sampled for mirroring, along with a permit bounding the
+    /// mirrored calls in flight.
+    fn sample<R: Clone>(
+        &self,
+        task: Task,
+        request: &Request<R>,
+    ) -> Option<(Request<R>, OwnedSemaphorePermit)> {
+        if !rand::thread_rng().gen_bool(self.config.sample_rate.clamp(0.0, 1.0)) {
+            return None;
+        }
+        let Ok(permit) = self.in_flight.clone().try_acquire_owned() else {
+            record(task, "skipped");
+            return None;
+        };
+        let mut copy = Request::new(request.get_ref().clone());
+        if let Some(context) = RequestContext::get(request) {
+            let mut context = context.clone();
+            context.raw_json = false;
+            // The shadow call has its own deadline, set once it starts
+            context.deadline = None;
+            copy.extensions_mut().insert(context);
+        }
+        Some((copy, permit))
+    }
+
+    /// Makes `call` against the shadow in the background, comparing its outcome to `primary`.
+    fn mirror<R, T, F>(
+        &self,
+        task: Task,
+        sampled: Option<(Request<R>, OwnedSemaphorePermit)>,
+        primary: &Result<Response<T>, Status>,
+        call: F,
+        compare: Compare<T>,
+    ) where
+        R: Send + 'static,
+        T: Clone + Send + 'static,
+        F: FnOnce(
+                Arc<dyn MightyClient>,
+                Request<R>,
+            ) -> BoxFuture<'static, Result<Response<T>, Status>>
+            + Send
+            + 'static,
+    {
+        let Some((request, permit)) = sampled else {
+            return;
+        };
+        let primary = match primary {
+            Ok(response) => Ok(response.get_ref().clone()),
+            Err(status) => Err(status.clone()),
+        };
+        let request_id = RequestContext::get(&request)
+            .map(|context| context.request_id.clone())
+            .unwrap_or_default();
+        let shadow = self.shadow.clone();
+        let config = self.config.clone();
+        tokio::spawn(async move {
+            let mut request = request;
+            let timeout = config.timeout;
+            if let Some(context) = request.extensions_mut().get_mut::<RequestContext>() {
+                context.deadline = Some(Instant::now() + timeout);
+            }
+            let shadow = match tokio::time::timeout(timeout, call(shadow, request)).await {
+                Ok(result) => result.map(Response::into_inner),
+                Err(_) => Err(Status::deadline_exceeded(format!(
+                    "the shadow call timed out after {:?}",
+                    timeout
+                ))),
+            };
+            drop(permit);
+            let divergence = match (&primary, &shadow) {
+                (Ok(primary), Ok(shadow)) => compare(primary, shadow, &config),
+                (Err(primary), Err(shadow)) if primary.code() == shadow.code() => None,
+                (Ok(_), Err(shadow)) => Some(format!("the shadow failed: {}", shadow)),
+                (Err(primary), Ok(_)) => Some(format!("only the primary failed: {}", primary)),
+                (Err(primary), Err(shadow)) => Some(format!(
+                    "the primary failed with {:?}, the shadow with {:?}",
+                    primary.code(),
+                    shadow.code()
+                )),
+            };
+            match divergence {
+                Some(divergence) => {
+                    warn!(
+                        "Shadow divergence on {} (request {}): {}",
+                        task.as_str(),
+                        request_id,
+                        divergence
+                    );
+                    record(task, "diverged");
+                }
+                None => {
+                    debug!(
+                        "Shadow {} response matches (request {})",
+                        task.as_str(),
+                        request_id
+                    );
+                    record(task, "match");
+                }
+            }
+        });
+    }
+}
+
+fn record(task: Task, result: &str) {
+    Metrics::global()
+        .counter(
+            SHADOW_METRIC,
+            &[("task", task.as_str()), ("result", result)],
+        )
+        .increment(1);
+}
+
+/// Returns the cosine distance (1 - cosine similarity) between two vectors.
+fn cosine_distance(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        return if a == b { 0.0 } else { 1.0 };
+    }
+    1.0 - dot / norms
+}
+
+fn compare_vectors(
+    primary: &[Embedding],
+    shadow: &[Embedding],
+    max_distance: f32,
+) -> Option<String> {
+    if primary.len() != shadow.len() {
+        return Some(format!(
+            "{} embeddings vs {} from the shadow",
+            primary.len(),
+            shadow.len()
+        ));
+    }
+    for (i, (primary, shadow)) in primary.iter().zip(shadow).enumerate() {
+        if primary.values.len() != shadow.values.len() {
+            return Some(format!(
+                "embedding {} has {} dimensions vs {} from the shadow",
+                i,
+                primary.values.len(),
+                shadow.values.len()
+            ));
+        }
+        let distance = cosine_distance(&primary.values, &shadow.values);
+        if distance > max_distance {
+            return Some(format!(
+                "embedding {} is at cosine distance {:.4}",
+                i, distance
+            ));
+        }
+    }
+    None
+}
+
+fn compare_embeddings(
+    primary: &EmbeddingsResponse,
+    shadow: &EmbeddingsResponse,
+    config: &ShadowConfig,
+) -> Option<String> {
+    compare_vectors(
+        &primary.embeddings,
+        &shadow.embeddings,
+        config.max_cosine_distance,
+    )
+}
+
+fn compare_sentence_transformers(
+    primary: &SentenceTransformersResponse,
+    shadow: &SentenceTransformersResponse,
+    config: &ShadowConfig,
+) -> Option<String> {
+    compare_vectors(
+        &primary.embeddings,
+        &shadow.embeddings,
+        config.max_cosine_distance,
+    )
+}
+
+fn compare_answers(
+    primary: &QuestionAnswerResponse,
+    shadow: &QuestionAnswerResponse,
+    _config: &ShadowConfig,
+) -> Option<String> {
+    (primary.answer != shadow.answer).then(|| {
+        format!(
+            "answer {:?} vs {:?} from the shadow",
+            primary.answer, shadow.answer
+        )
+    })
+}
+
+/// Returns the index of the highest logit, the predicted label.
+fn top_label(logits: &[f32]) -> Option<usize> {
+    logits
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(index, _)| index)
+}
+
+fn compare_labels(
+    primary: &SequenceClassificationResponse,
+    shadow: &SequenceClassificationResponse,
+    _config: &ShadowConfig,
+) -> Option<String> {
+    let (primary, shadow) = (top_label(&primary.logits), top_label(&shadow.logits));
+    (primary != shadow).then(|| format!("label {:?} vs {:?} from the shadow", primary, shadow))
+}
+
+fn compare_entities(
+    primary: &TokenClassificationResponse,
+    shadow: &TokenClassificationResponse,
+    _config: &ShadowConfig,
+) -> Option<String> {
+    let labels = |response: &TokenClassificationResponse| {
+        response
+            .entities
+            .iter()
+            .map(|entity| (entity.label.clone(), entity.start_offset, entity.end_offset))
+            .collect::<Vec<_>>()
+    };
+    let (primary, shadow) = (labels(primary), labels(shadow));
+    (primary != shadow).then(|| format!("entities {:?} vs {:?} from the shadow", primary, shadow))
+}
+
+/// Compares the responses of a batch call one text at a time with `compare`.
+fn compare_batches<T>(
+    primary: &[T],
+    shadow: &[T],
+    config: &ShadowConfig,
+    compare: Compare<T>,
+) -> Option<String> {
+    if primary.len() != shadow.len() {
+        return Some(format!(
+            "{} responses vs {} from the shadow",
+            primary.len(),
+            shadow.len()
+        ));
+    }
+    primary
+        .iter()
+        .zip(shadow)
+        .enumerate()
+        .find_map(|(i, (primary, shadow))| {
+            compare(primary, shadow, config).map(|divergence| format!("text {}: {}", i, divergence))
+        })
+}
+
+#[async_trait]
+impl MightyClient for ShadowClient {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        self.inner.health_check(request).await
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        let sampled = self.sample(Task::Embeddings, &request);
+        let result = self.inner.embeddings(request).await;
+        self.mirror(
+            Task::Embeddings,
+            sampled,
+            &result,
+            |shadow, request| Box::pin(async move { shadow.embeddings(request).await }),
+            compare_embeddings,
+        );
+        result
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        let sampled = self.sample(Task::QuestionAnswering, &request);
+        let result = self.inner.question_answering(request).await;
+        self.mirror(
+            Task::QuestionAnswering,
+            sampled,
+            &result,
+            |shadow, request| Box::pin(async move { shadow.question_answering(request).await }),
+            compare_answers,
+        );
+        result
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        let sampled = self.sample(Task::SentenceTransformers, &request);
+        let result = self.inner.sentence_transformers(request).await;
+        self.mirror(
+            Task::SentenceTransformers,
+            sampled,
+            &result,
+            |shadow, request| Box::pin(async move { shadow.sentence_transformers(request).await }),
+            compare_sentence_transformers,
+        );
+        result
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        let sampled = self.sample(Task::SequenceClassification, &request);
+        let result = self.inner.sequence_classification(request).await;
+        self.mirror(
+            Task::SequenceClassification,
+            sampled,
+            &result,
+            |shadow, request| {
+                Box::pin(async move { shadow.sequence_classification(request).await })
+            },
+            compare_labels,
+        );
+        result
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        let sampled = self.sample(Task::TokenClassification, &request);
+        let result = self.inner.token_classification(request).await;
+        self.mirror(
+            Task::TokenClassification,
+            sampled,
+            &result,
+            |shadow, request| Box::pin(async move { shadow.token_classification(request).await }),
+            compare_entities,
+        );
+        result
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        self.inner.metadata(request).await
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        self.inner.rerank(request).await
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        let sampled = self.sample(Task::Embeddings, &request);
+        let result = self.inner.embeddings_batch(request).await;
+        self.mirror(
+            Task::Embeddings,
+            sampled,
+            &result,
+            |shadow, request| Box::pin(async move { shadow.embeddings_batch(request).await }),
+            |primary, shadow, config| compare_batches(primary, shadow, config, compare_embeddings),
+        );
+        result
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        let sampled = self.sample(Task::TokenClassification, &request);
+        let result = self.inner.token_classification_batch(request).await;
+        self.mirror(
+            Task::TokenClassification,
+            sampled,
+            &result,
+            |shadow, request| {
+                Box::pin(async move { shadow.token_classification_batch(request).await })
+            },
+            |primary, shadow, config| compare_batches(primary, shadow, config, compare_entities),
+        );
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::services::clients::mock::MockMightyClient;
+
+    use super::*;
+
+    #[test]
+    fn test_divergences() {
+        let config = ShadowConfig::default();
+        let embeddings = |values: Vec<f32>| EmbeddingsResponse {
+            embeddings: vec![Embedding { values }],
+            ..Default::default()
+        };
+        let primary = embeddings(vec![1.0, 0.0]);
+        assert_eq!(
+            compare_embeddings(&primary, &embeddings(vec![2.0, 0.01]), &config),
+            None
+        );
+        assert!(
+            compare_embeddings(&primary, &embeddings(vec![0.0, 1.0]), &config)
+                .unwrap()
+                .contains("cosine distance 1.0000")
+        );
+        assert!(
+            compare_embeddings(&primary, &embeddings(vec![1.0]), &config)
+                .unwrap()
+                .contains("dimensions")
+        );
+        let batch = [embeddings(vec![1.0, 0.0]), embeddings(vec![0.0, 1.0])];
+        assert_eq!(
+            compare_batches(&batch, &batch, &config, compare_embeddings),
+            None
+        );
+        assert!(compare_batches(
+            &batch,
+            &[embeddings(vec![1.0, 0.0]), embeddings(vec![1.0, 0.0])],
+            &config,
+            compare_embeddings
+        )
+        .unwrap()
+        .starts_with("text 1: "));
+        assert_eq!(
+            compare_batches(&batch, &batch[..1], &config, compare_embeddings).unwrap(),
+            "2 responses vs 1 from the shadow"
+        );
+
+        let logits = |logits: Vec<f32>| SequenceClassificationResponse {
+            logits,
+            ..Default::default()
+        };
+        let primary = logits(vec![0.1, 0.9]);
+        assert_eq!(
+            compare_labels(&primary, &logits(vec![0.3, 0.7]), &config),
+            None
+        );
+        assert_eq!(
+            compare_labels(&primary, &logits(vec![0.6, 0.4]), &config).unwrap(),
+            "label Some(1) vs Some(0) from the shadow"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_calls_are_mirrored_in_the_background() {
+        let shadow = Arc::new(MockMightyClient::new().with_latency(Duration::from_millis(50)));
+        let client = ShadowClient {
+            inner: Box::new(MockMightyClient::new().with_dimension(8)),
+            shadow: shadow.clone(),
+            config: Arc::new(ShadowConfig::default()),
+            in_flight: Arc::new(Semaphore::new(1)),
+        };
+        let embed = || {
+            client.embeddings(Request::new(TextRequest {
+                text: "hello".to_string(),
+            }))
+        };
+
+        let response = embed().await.unwrap();
+        assert_eq!(response.get_ref().embeddings[0].values.len(), 8);
+        // The second call is not mirrored while the first mirrored call is in flight
+        embed().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(shadow.calls(Task::Embeddings), 1);
+    }
+
+    #[tokio::test]
+    async fn test_mirrored_calls_have_their_own_timeout() {
+        let shadow = Arc::new(MockMightyClient::new().with_latency(Duration::from_millis(200)));
+        let client = ShadowClient {
+            inner: Box::new(MockMightyClient::new()),
+            shadow: shadow.clone(),
+            config: Arc::new(ShadowConfig {
+                timeout: Duration::from_millis(20),
+                ..Default::default()
+            }),
+            in_flight: Arc::new(Semaphore::new(1)),
+        };
+        // The caller's deadline has passed by the time the call is mirrored
+        let mut request = RequestContext::attach(Request::new(TextRequest {
+            text: "hello".to_string(),
+        }));
+        request
+            .extensions_mut()
+            .get_mut::<RequestContext>()
+            .unwrap()
+            .deadline = Some(Instant::now());
+
+        client.embeddings(request).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // The mirrored call timed out before completing, releasing its permit
+        assert_eq!(client.in_flight.available_permits(), 1);
+        assert_eq!(shadow.calls(Task::Embeddings), 0);
+    }
+}
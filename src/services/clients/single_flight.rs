@@ -0,0 +1,416 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use log::debug;
+use tokio::sync::oneshot;
+use tonic::metadata::MetadataMap;
+use tonic::{Code, Request, Response, Status};
+
+use crate::config::Task;
+use crate::proto::mighty_proto::{
+    EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse, QuestionAnswerRequest,
+    QuestionAnswerResponse, RerankRequest, RerankResponse, SentenceTransformersResponse,
+    SequenceClassificationResponse, TextRequest, TokenClassificationResponse,
+};
+use crate::services::context::{RequestContext, ServedBy};
+use crate::services::metrics::Metrics;
+
+use super::caching::CacheKey;
+use super::MightyClient;
+
+/// Counter of calls served by the upstream call of an identical concurrent call, by task.
+const COALESCED_METRIC: &str = "mighty_coalesced_requests_total";
+
+/// The outcome of an upstream call shared with the calls waiting on it, the response message
+/// being of the type returned for the key's task, with the upstream that served it, if known.
+type Shared = Result<(Arc<dyn Any + Send + Sync>, MetadataMap, Option<ServedBy>), Status>;
+
+type Flights = Mutex<HashMap<CacheKey, Vec<oneshot::Sender<Shared>>>>;
+
+/// Whether `status` ended a call for reasons of its caller, its deadline or cancellation, rather
+/// than of the upstream, so isn't shared with the calls waiting on it.
+fn is_callers_own(status: &Status) -> bool {
+    matches!(status.code(), Code::DeadlineExceeded | Code::Cancelled)
+}
+
+/// An upstream call in flight, whose waiters are dropped if the call is cancelled, so they make
+/// their own call.
+struct Flight<'a> {
+    flights: &'a Flights,
+    key: Option<CacheKey>,
+}
+
+impl Flight<'_> {
+    /// Ends the flight, returning the calls waiting on it.
+    fn land(mut self) -> Vec<oneshot::Sender<Shared>> {
+        let key = self.key.take().expect("flight already landed");
+        self.flights
+            .lock()
+            .unwrap()
+            .remove(&key)
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for Flight<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.flights.lock().unwrap().remove(&key);
+        }
+    }
+}
+
+/// The `SingleFlightClient` struct is a `MightyClient` decorator coalescing concurrent
+/// identical inference calls (same task and input) into one upstream call, every waiting call
+/// receiving a clone of its response or error, so bursts of duplicate texts, e.g. from fan-out
+/// search queries, don't multiply upstream load. A call ending with DEADLINE_EXCEEDED or
+/// CANCELLED, its own caller's deadline or cancellation, isn't shared: its waiters make their
+/// own call.
+///
+/// Unlike the response cache, nothing outlives the upstream call: calls arriving after it
+/// completed make a new one. Calls asking for the raw upstream JSON bypass coalescing, as do
+/// health checks, metadata, rerank and batch calls.
+pub struct SingleFlightClient {
+    inner: Box<dyn MightyClient>,
+    flights: Flights,
+}
+
+impl SingleFlightClient {
+    pub fn new(inner: Box<dyn MightyClient>) -> Self {
+        Self {
+            inner,
+            flights: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Makes the call for `key` through `call`, unless an identical call is in flight, whose
+    /// outcome is then shared.
+    async fn coalesced<R, T, F>(
+        &self,
+        key: CacheKey,
+        request: Request<R>,
+        call: F,
+    ) -> Result<Response<T>, Status>
+    where
+        R: Send,
+        T: Clone + Send + Sync + 'static,
+        F: for<'c> FnOnce(
+            &'c dyn MightyClient,
+            Request<R>,
+        ) -> futures::future::BoxFuture<'c, Result<Response<T>, Status>>,
+    {
+        if RequestContext::get(&request).is_some_and(|context| context.raw_json) {
+            return call(self.inner.as_ref(), request).await;
+        }
+        let waiting = {
+            let mut flights = self.flights.lock().unwrap();
+            match flights.get_mut(&key) {
+                Some(waiters) => {
+                    let (sender, receiver) = oneshot::channel();
+                    waiters.push(sender);
+                    Some(receiver)
+                }
+                None => {
+                    flights.insert(key.clone(), Vec::new());
+                    None
+                }
+            }
+        };
+
+        if let Some(receiver) = waiting {
+            let task = key.task.as_str();
+            match receiver.await {
+                // The call in flight ran out of its own caller's time, which this call may
+                // still have
+                Ok(Err(status)) if is_callers_own(&status) => {
+                    debug!(
+                        "Coalesced {} call ended with {:?}, calling upstream",
+                        task,
+                        status.code()
+                    );
+                    return call(self.inner.as_ref(), request).await;
+                }
+                Ok(shared) => {
+                    Metrics::global()
+                        .counter(COALESCED_METRIC, &[("task", task)])
+                        .increment(1);
+                    let (message, metadata, served_by) = shared?;
+                    let message = message
+                        .downcast_ref::<T>()
+                        .expect("coalesced calls of a task share their response type")
+                        .clone();
+                    let mut response = Response::new(message);
+                    *response.metadata_mut() = metadata;
+                    if let Some(served_by) = served_by {
+                        response.extensions_mut().insert(served_by);
+                    }
+                    return Ok(response);
+                }
+                // The call in flight was cancelled
+                Err(_) => {
+                    debug!("Coalesced {} call cancelled, calling upstream", task);
+                    return call(self.inner.as_ref(), request).await;
+                }
+            }
+        }
+
+        let flight = Flight {
+            flights: &self.flights,
+            key: Some(key),
+        };
+        let result = call(self.inner.as_ref(), request).await;
+        let waiters = flight.land();
+        if !waiters.is_empty() {
+            let shared: Shared = match &result {
+                Ok(response) => Ok((
+                    Arc::new(response.get_ref().clone()),
+                    response.metadata().clone(),
+                    response.extensions().get::<ServedBy>().cloned(),
+                )),
+                Err(status) => Err(status.clone()),
+            };
+            for waiter in waiters {
+                let _ = waiter.send(shared.clone());
+            }
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl MightyClient for SingleFlightClient {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        self.inner.health_check(request).await
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        let key = CacheKey::text(Task::Embeddings, &request.get_ref().text);
+        self.coalesced(key, request, |client, request| client.embeddings(request))
+            .await
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        let key = CacheKey {
+            scope: String::new(),
+            task: Task::QuestionAnswering,
+            text: request.get_ref().question.clone(),
+            context: request.get_ref().context.clone(),
+        };
+        self.coalesced(key, request, |client, request| {
+            client.question_answering(request)
+        })
+        .await
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        let key = CacheKey::text(Task::SentenceTransformers, &request.get_ref().text);
+        self.coalesced(key, request, |client, request| {
+            client.sentence_transformers(request)
+        })
+        .await
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        let key = CacheKey::text(Task::SequenceClassification, &request.get_ref().text);
+        self.coalesced(key, request, |client, request| {
+            client.sequence_classification(request)
+        })
+        .await
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        let key = CacheKey::text(Task::TokenClassification, &request.get_ref().text);
+        self.coalesced(key, request, |client, request| {
+            client.token_classification(request)
+        })
+        .await
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        self.inner.metadata(request).await
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        self.inner.rerank(request).await
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        self.inner.embeddings_batch(request).await
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        self.inner.token_classification_batch(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Counts embeddings calls, each taking 20ms, failing those of empty texts and the first call
+    /// made, if of the text "late", with DEADLINE_EXCEEDED.
+    struct SlowClient(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl MightyClient for SlowClient {
+        async fn health_check(
+            &self,
+            _request: Request<Empty>,
+        ) -> Result<Response<HealthcheckResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn embeddings(
+            &self,
+            request: Request<TextRequest>,
+        ) -> Result<Response<EmbeddingsResponse>, Status> {
+            let previous_calls = self.0.fetch_add(1, Ordering::Relaxed);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let text = request.into_inner().text;
+            if text.is_empty() {
+                return Err(Status::invalid_argument("empty text"));
+            }
+            if text == "late" && previous_calls == 0 {
+                return Err(Status::deadline_exceeded("deadline exceeded"));
+            }
+            Ok(Response::new(EmbeddingsResponse {
+                text,
+                ..Default::default()
+            }))
+        }
+
+        async fn question_answering(
+            &self,
+            _request: Request<QuestionAnswerRequest>,
+        ) -> Result<Response<QuestionAnswerResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn sentence_transformers(
+            &self,
+            _request: Request<TextRequest>,
+        ) -> Result<Response<SentenceTransformersResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn sequence_classification(
+            &self,
+            _request: Request<TextRequest>,
+        ) -> Result<Response<SequenceClassificationResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn token_classification(
+            &self,
+            _request: Request<TextRequest>,
+        ) -> Result<Response<TokenClassificationResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn metadata(
+            &self,
+            _request: Request<Empty>,
+        ) -> Result<Response<MetadataResponse>, Status> {
+            unimplemented!()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_identical_calls_share_one_upstream_call() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let client = SingleFlightClient::new(Box::new(SlowClient(calls.clone())));
+        let embed = |text: &str| {
+            client.embeddings(Request::new(TextRequest {
+                text: text.to_string(),
+            }))
+        };
+
+        let responses =
+            futures::future::join_all([embed("a"), embed("a"), embed("b"), embed("a")]).await;
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+        let texts: Vec<_> = responses
+            .into_iter()
+            .map(|response| response.unwrap().into_inner().text)
+            .collect();
+        assert_eq!(texts, ["a", "a", "b", "a"]);
+        assert!(client.flights.lock().unwrap().is_empty());
+
+        let errors = futures::future::join_all([embed(""), embed("")]).await;
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+        assert!(errors
+            .iter()
+            .all(|error| error.as_ref().unwrap_err().code() == Code::InvalidArgument));
+    }
+
+    #[tokio::test]
+    async fn test_waiters_call_upstream_when_the_flight_is_cancelled() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let client = SingleFlightClient::new(Box::new(SlowClient(calls.clone())));
+        let embed = || {
+            client.embeddings(Request::new(TextRequest {
+                text: "a".to_string(),
+            }))
+        };
+
+        let mut leader = Box::pin(embed());
+        assert!(futures::poll!(leader.as_mut()).is_pending());
+        let mut follower = Box::pin(embed());
+        assert!(futures::poll!(follower.as_mut()).is_pending());
+        drop(leader);
+        assert_eq!(follower.await.unwrap().into_inner().text, "a");
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_waiters_call_upstream_when_the_flight_runs_out_of_time() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let client = SingleFlightClient::new(Box::new(SlowClient(calls.clone())));
+        let embed = || {
+            client.embeddings(Request::new(TextRequest {
+                text: "late".to_string(),
+            }))
+        };
+
+        let mut leader = Box::pin(embed());
+        assert!(futures::poll!(leader.as_mut()).is_pending());
+        let (leader, follower) = futures::join!(leader, embed());
+        assert_eq!(leader.unwrap_err().code(), Code::DeadlineExceeded);
+        assert_eq!(follower.unwrap().into_inner().text, "late");
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,267 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use log::info;
+use tokio::time::Instant;
+use tonic::{Request, Response, Status};
+
+use crate::config::BackendConfig;
+use crate::proto::mighty_proto::{
+    EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse, QuestionAnswerRequest,
+    QuestionAnswerResponse, RerankRequest, RerankResponse, SentenceTransformersResponse,
+    SequenceClassificationResponse, TextRequest, TokenClassificationResponse,
+};
+
+use super::{BackendSwitch, MightyClient, UpstreamUpdate};
+
+/// The interval between the health checks of a backend being switched to.
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The client of a backend, along with a handle to update its upstream instances while serving
+/// when it load balances them.
+pub type Backend = (Box<dyn MightyClient>, Option<Arc<dyn UpstreamUpdate>>);
+
+/// Creates a backend, e.g. from the settings of its kind, or describes why it can't be created.
+pub type BackendFactory = Box<dyn Fn(&BackendConfig) -> Result<Backend, String> + Send + Sync>;
+
+/// The `SwitchableClient` struct is a `MightyClient` forwarding calls to a backend that can be
+/// replaced while serving, e.g. from a Mighty server to local ONNX inference or to another base
+/// URL, without restarting the gRPC server or dropping client connections.
+///
+/// A new backend only starts serving once its health check succeeds; calls in flight complete
+/// on the previous backend, which is dropped afterwards. Switches run one at a time, a switch
+/// requested while another one is in progress failing with `FAILED_PRECONDITION`.
+///
+/// The upstream instances of the backend serving can also be updated in place, when it load
+/// balances them (see `UpstreamUpdate`), so upstreams added while others keep serving ramp up.
+pub struct SwitchableClient {
+    current: RwLock<Arc<dyn MightyClient>>,
+    upstreams: RwLock<Option<Arc<dyn UpstreamUpdate>>>,
+    generation: AtomicU64,
+    switching: tokio::sync::Mutex<()>,
+    factory: BackendFactory,
+    ready_timeout: Duration,
+}
+
+impl SwitchableClient {
+    /// Creates a client serving `initial`, switching to backends created by `factory`, which
+    /// must report themselves healthy within `ready_timeout`.
+    pub fn new(
+        initial: Box<dyn MightyClient>,
+        factory: BackendFactory,
+        ready_timeout: Duration,
+    ) -> Self {
+        Self {
+            current: RwLock::new(Arc::from(initial)),
+            upstreams: RwLock::new(None),
+            generation: AtomicU64::new(0),
+            switching: tokio::sync::Mutex::new(()),
+            factory,
+            ready_timeout,
+        }
+    }
+
+    /// Updates the upstream instances of `initial` through `upstreams` until the next switch.
+    pub fn with_upstreams(self, upstreams: Arc<dyn UpstreamUpdate>) -> Self {
+        *self.upstreams.write().unwrap() = Some(upstreams);
+        self
+    }
+
+    /// Returns the backend currently serving. Holding it keeps it alive after a switch.
+    fn current(&self) -> Arc<dyn MightyClient> {
+        self.current.read().unwrap().clone()
+    }
+}
+
+/// Waits for `client` to report itself healthy, for up to `timeout`.
+async fn wait_ready(client: &dyn MightyClient, timeout: Duration) -> Result<(), Status> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let last_error = match client.health_check(Request::new(Empty {})).await {
+            Ok(response) if response.get_ref().success => return Ok(()),
+            Ok(_) => "health check reported failure".to_string(),
+            Err(status) => status.message().to_string(),
+        };
+        if Instant::now() + READY_POLL_INTERVAL > deadline {
+            return Err(Status::unavailable(format!(
+                "The new backend isn't ready after {:?}: {}",
+                timeout, last_error
+            )));
+        }
+        tokio::time::sleep(READY_POLL_INTERVAL).await;
+    }
+}
+
+#[async_trait]
+impl BackendSwitch for SwitchableClient {
+    async fn switch(&self, backend: &BackendConfig) -> Result<u64, Status> {
+        let _switching = self
+            .switching
+            .try_lock()
+            .map_err(|_| Status::failed_precondition("A backend switch is already in progress"))?;
+        let (client, upstreams) = (self.factory)(backend).map_err(Status::failed_precondition)?;
+        wait_ready(client.as_ref(), self.ready_timeout).await?;
+        *self.current.write().unwrap() = Arc::from(client);
+        *self.upstreams.write().unwrap() = upstreams;
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed) + 1;
+        info!(
+            "Switched to the {} backend{} (generation {})",
+            backend.kind.as_str(),
+            backend
+                .base_url
+                .as_ref()
+                .map(|url| format!(" at {}", url))
+                .unwrap_or_default(),
+            generation
+        );
+        Ok(generation)
+    }
+}
+
+impl UpstreamUpdate for SwitchableClient {
+    /// Updates the upstreams of the backend serving, unless it doesn't load balance upstreams or
+    /// a switch is in progress.
+    fn update_upstreams(&self, base_urls: &[String]) -> bool {
+        let Ok(_switching) = self.switching.try_lock() else {
+            return false;
+        };
+        let upstreams = self.upstreams.read().unwrap().clone();
+        upstreams.is_some_and(|upstreams| upstreams.update_upstreams(base_urls))
+    }
+}
+
+#[async_trait]
+impl MightyClient for SwitchableClient {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        self.current().health_check(request).await
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        self.current().embeddings(request).await
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        self.current().question_answering(request).await
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        self.current().sentence_transformers(request).await
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        self.current().sequence_classification(request).await
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        self.current().token_classification(request).await
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        self.current().metadata(request).await
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        self.current().rerank(request).await
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        self.current().embeddings_batch(request).await
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        self.current().token_classification_batch(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use crate::config::BackendKind;
+    use crate::services::clients::mock::MockMightyClient;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_calls_are_served_by_the_backend_switched_to() {
+        let factory: BackendFactory = Box::new(|backend| match backend.kind {
+            BackendKind::Onnx => Ok((Box::new(MockMightyClient::new().with_dimension(8)), None)),
+            _ => Err("unsupported backend".to_string()),
+        });
+        let client = SwitchableClient::new(
+            Box::new(MockMightyClient::new()),
+            factory,
+            Duration::from_secs(1),
+        );
+        let dimension = || async {
+            let request = Request::new(TextRequest {
+                text: "a".to_string(),
+            });
+            let response = client.embeddings(request).await.unwrap().into_inner();
+            response.embeddings[0].values.len()
+        };
+        let backend = |kind| BackendConfig {
+            kind,
+            base_url: None,
+        };
+
+        assert_eq!(dimension().await, 4);
+        let status = client.switch(&backend(BackendKind::Tei)).await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert_eq!(dimension().await, 4);
+        assert_eq!(client.switch(&backend(BackendKind::Onnx)).await.unwrap(), 1);
+        assert_eq!(dimension().await, 8);
+    }
+
+    #[tokio::test]
+    async fn test_switches_run_one_at_a_time() {
+        let factory: BackendFactory = Box::new(|_| Ok((Box::new(MockMightyClient::new()), None)));
+        let client = SwitchableClient::new(
+            Box::new(MockMightyClient::new()),
+            factory,
+            Duration::from_secs(1),
+        );
+        let backend = BackendConfig {
+            kind: BackendKind::Onnx,
+            base_url: None,
+        };
+
+        let switching = client.switching.lock().await;
+        let status = client.switch(&backend).await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        drop(switching);
+        assert_eq!(client.switch(&backend).await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use tonic::{Request, Response, Status};
+
+use crate::config::Task;
+use crate::proto::mighty_proto::{
+    EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse, QuestionAnswerRequest,
+    QuestionAnswerResponse, RerankRequest, RerankResponse, SentenceTransformersResponse,
+    SequenceClassificationResponse, TextRequest, TokenClassificationResponse,
+};
+use crate::services::context::RequestContext;
+
+use super::MightyClient;
+
+/// The `TaskRoutingClient` struct is a `MightyClient` serving each task from the backend
+/// configured for it in `[backends]`, e.g. embeddings from one Mighty server and question
+/// answering from another, the tasks without a backend of their own being served by the
+/// primary backend.
+///
+/// Health checks succeed when every backend is healthy; metadata is that of the primary backend.
+pub struct TaskRoutingClient {
+    primary: Box<dyn MightyClient>,
+    tasks: HashMap<Task, Box<dyn MightyClient>>,
+}
+
+impl TaskRoutingClient {
+    pub fn new(
+        primary: Box<dyn MightyClient>,
+        tasks: HashMap<Task, Box<dyn MightyClient>>,
+    ) -> Self {
+        Self { primary, tasks }
+    }
+
+    /// Returns the backend serving `task`.
+    fn client(&self, task: Task) -> &dyn MightyClient {
+        self.tasks.get(&task).unwrap_or(&self.primary).as_ref()
+    }
+}
+
+#[async_trait]
+impl MightyClient for TaskRoutingClient {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        for client in self.tasks.values() {
+            let mut task_request = Request::new(Empty {});
+            if let Some(context) = RequestContext::get(&request) {
+                task_request.extensions_mut().insert(context.clone());
+            }
+            let response = client.health_check(task_request).await?;
+            if !response.get_ref().success {
+                return Ok(response);
+            }
+        }
+        self.primary.health_check(request).await
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        self.client(Task::Embeddings).embeddings(request).await
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        self.client(Task::QuestionAnswering)
+            .question_answering(request)
+            .await
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        self.client(Task::SentenceTransformers)
+            .sentence_transformers(request)
+            .await
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        self.client(Task::SequenceClassification)
+            .sequence_classification(request)
+            .await
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        self.client(Task::TokenClassification)
+            .token_classification(request)
+            .await
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        self.primary.metadata(request).await
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        self.client(Task::Rerank).rerank(request).await
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        self.client(Task::Embeddings)
+            .embeddings_batch(request)
+            .await
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        self.client(Task::TokenClassification)
+            .token_classification_batch(request)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::services::clients::mock::MockMightyClient;
+
+    use super::*;
+
+    fn boxed(client: &Arc<MockMightyClient>) -> Box<dyn MightyClient> {
+        let client: Arc<dyn MightyClient> = client.clone();
+        Box::new(client)
+    }
+
+    fn text() -> Request<TextRequest> {
+        Request::new(TextRequest {
+            text: "hello".to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_tasks_are_served_by_their_backend() {
+        let primary = Arc::new(MockMightyClient::new());
+        let embeddings = Arc::new(MockMightyClient::new());
+        let client = TaskRoutingClient::new(
+            boxed(&primary),
+            HashMap::from([(Task::Embeddings, boxed(&embeddings))]),
+        );
+
+        client.embeddings(text()).await.unwrap();
+        client.token_classification(text()).await.unwrap();
+        assert_eq!(embeddings.calls(Task::Embeddings), 1);
+        assert_eq!(primary.calls(Task::Embeddings), 0);
+        assert_eq!(primary.calls(Task::TokenClassification), 1);
+        assert_eq!(embeddings.calls(Task::TokenClassification), 0);
+    }
+}
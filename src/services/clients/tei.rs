@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+use std::env;
+use std::slice;
+use std::time::Instant;
+
+use async_trait::async_trait;
+use log::{debug, error};
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tonic::{Request, Response, Status};
+
+use crate::config::TeiConfig;
+use crate::proto::mighty_proto::{
+    Embedding, EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse,
+    QuestionAnswerRequest, QuestionAnswerResponse, RankedText, RerankRequest, RerankResponse,
+    SentenceTransformersResponse, SequenceClassificationResponse, Shape, TextRequest,
+    TokenClassificationResponse,
+};
+use crate::services::context::RequestContext;
+use crate::services::telemetry::trace_context_headers;
+
+use super::{status_from_http, MightyClient};
+
+/// The body of a `POST /embed` request.
+#[derive(Debug, Serialize)]
+struct EmbedRequest<'a> {
+    inputs: &'a [String],
+    normalize: bool,
+    truncate: bool,
+}
+
+/// The body of a `POST /rerank` request.
+#[derive(Debug, Serialize)]
+struct RerankBody<'a> {
+    query: &'a str,
+    texts: &'a [String],
+    truncate: bool,
+}
+
+/// One entry of a `POST /rerank` response.
+#[derive(Debug, Deserialize)]
+struct Rank {
+    index: u32,
+    score: f32,
+}
+
+/// The body of an error response, e.g. `{"error": "Input validation error", "error_type":
+/// "Validation"}`.
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+/// The `TeiClient` struct implements the `MightyClient` trait against a Hugging Face Text
+/// Embeddings Inference (TEI) server, so models served by TEI and by Mighty share one gateway
+/// API.
+///
+/// - `embeddings` and `sentence_transformers` call `POST /embed`, the latter asking TEI to
+///   normalize the embeddings, and `embeddings_batch` embeds all its texts in one call;
+/// - `rerank` calls `POST /rerank`, on servers running a reranker model;
+/// - `metadata` returns the server's `GET /info`, and `health_check` its `GET /health`.
+///
+/// Other tasks fail with `UNIMPLEMENTED`. Upstream HTTP errors are mapped to the closest gRPC
+/// status, e.g. `413 Payload Too Large` to `RESOURCE_EXHAUSTED`.
+pub struct TeiClient {
+    client: Client,
+    config: TeiConfig,
+    api_key: Option<String>,
+}
+
+impl TeiClient {
+    /// Creates a client for the TEI server configured in `config`, reading the API key from the
+    /// `api_key` setting, else from the `api_key_env` environment variable.
+    pub fn new(config: TeiConfig) -> Self {
+        let api_key = config
+            .api_key
+            .as_ref()
+            .map(|key| key.expose().to_string())
+            .or_else(|| env::var(&config.api_key_env).ok())
+            .filter(|key| !key.is_empty());
+        let client = Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .expect("Failed to build HTTP client");
+        Self {
+            client,
+            config,
+            api_key,
+        }
+    }
+
+    /// Adds the API key, the caller's remaining deadline, if any, and the trace context to an
+    /// upstream request.
+    fn prepare<T>(&self, builder: RequestBuilder, request: &Request<T>) -> RequestBuilder {
+        let mut builder = match &self.api_key {
+            Some(api_key) => builder.bearer_auth(api_key),
+            None => builder,
+        };
+        if let Some(remaining) = RequestContext::get(request).and_then(RequestContext::remaining) {
+            builder = builder.timeout(remaining.min(self.config.timeout));
+        }
+        builder.headers(trace_context_headers())
+    }
+
+    /// Sends an upstream request for `path`, parsing the JSON response.
+    async fn fetch<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        builder: RequestBuilder,
+    ) -> Result<T, Status> {
+        let res = builder.send().await.map_err(|e| {
+            if e.is_timeout() {
+                Status::deadline_exceeded(format!("{} request timed out: {}", path, e))
+            } else {
+                Status::unavailable(format!("Error fetching {}: {}", path, e))
+            }
+        })?;
+        let status = res.status();
+        let body = res
+            .bytes()
+            .await
+            .map_err(|e| Status::unavailable(format!("Error reading {} response: {}", path, e)))?;
+        parse(path, status, &body)
+    }
+
+    /// Embeds `texts` in one call, returning their vectors along with the call duration.
+    async fn embed<T>(
+        &self,
+        request: &Request<T>,
+        texts: &[String],
+        normalize: bool,
+    ) -> Result<(Vec<Vec<f32>>, i32), Status> {
+        let started = Instant::now();
+        let body = EmbedRequest {
+            inputs: texts,
+            normalize,
+            truncate: self.config.truncate,
+        };
+        let url = format!("{}/embed", self.config.base_url);
+        let builder = self.prepare(self.client.post(&url).json(&body), request);
+        let embeddings: Vec<Vec<f32>> = self.fetch("/embed", builder).await?;
+        if embeddings.len() != texts.len() {
+            return Err(Status::internal(format!(
+                "The /embed response has {} embeddings for {} texts",
+                embeddings.len(),
+                texts.len()
+            )));
+        }
+        Ok((embeddings, started.elapsed().as_millis() as i32))
+    }
+
+    /// Embeds the text of `request`, returning the vector along with the call duration.
+    async fn embed_text(
+        &self,
+        request: &Request<TextRequest>,
+        normalize: bool,
+    ) -> Result<(Vec<f32>, i32), Status> {
+        let texts = slice::from_ref(&request.get_ref().text);
+        let (mut embeddings, took) = self.embed(request, texts, normalize).await?;
+        Ok((embeddings.remove(0), took))
+    }
+}
+
+/// Parses a successful response body, or maps an error response to a `Status`.
+fn parse<T: DeserializeOwned>(path: &str, status: StatusCode, body: &[u8]) -> Result<T, Status> {
+    if !status.is_success() {
+        let message = serde_json::from_slice::<ErrorResponse>(body)
+            .map(|response| response.error)
+            .unwrap_or_else(|_| String::from_utf8_lossy(body).into_owned());
+        let message = format!("{} responded {}: {}", path, status, message);
+        error!("{}", message);
+        return Err(status_from_http(status, message));
+    }
+    serde_json::from_slice(body)
+        .map_err(|e| Status::internal(format!("Failed to parse {} JSON: {}", path, e)))
+}
+
+/// Flattens the `GET /info` response into metadata, nested values being kept as JSON.
+fn info_to_metadata(info: Value) -> HashMap<String, String> {
+    let mut metadata = HashMap::from([("backend".to_string(), "tei".to_string())]);
+    if let Value::Object(info) = info {
+        for (key, value) in info {
+            let value = match value {
+                Value::Null => continue,
+                Value::String(value) => value,
+                value => value.to_string(),
+            };
+            metadata.insert(key, value);
+        }
+    }
+    metadata
+}
+
+fn shape(values: &[f32]) -> Option<Shape> {
+    Some(Shape {
+        dim1: 1,
+        dim2: values.len() as i32,
+    })
+}
+
+fn unsupported(task: &str) -> Status {
+    Status::unimplemented(format!("TEI servers don't serve {}", task))
+}
+
+#[async_trait]
+impl MightyClient for TeiClient {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        let url = format!("{}/health", self.config.base_url);
+        let res = self
+            .prepare(self.client.get(&url), &request)
+            .send()
+            .await
+            .map_err(|e| Status::unavailable(format!("Error fetching /health: {}", e)))?;
+        if !res.status().is_success() {
+            error!("Health check response status is {}", res.status());
+            return Err(Status::internal("Healthcheck failed"));
+        }
+        Ok(Response::new(HealthcheckResponse { success: true }))
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        debug!("Received embeddings request: {:?}", request);
+        let (values, took) = self.embed_text(&request, false).await?;
+        Ok(Response::new(EmbeddingsResponse {
+            shape: shape(&values),
+            embeddings: vec![Embedding { values }],
+            took,
+            text: request.into_inner().text,
+        }))
+    }
+
+    async fn question_answering(
+        &self,
+        _request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        Err(unsupported("question_answering"))
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        debug!("Received sentence_transformers request: {:?}", request);
+        let (values, took) = self.embed_text(&request, true).await?;
+        Ok(Response::new(SentenceTransformersResponse {
+            shape: shape(&values),
+            embeddings: vec![Embedding { values }],
+            took,
+            text: request.into_inner().text,
+        }))
+    }
+
+    async fn sequence_classification(
+        &self,
+        _request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        Err(unsupported("sequence_classification"))
+    }
+
+    async fn token_classification(
+        &self,
+        _request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        Err(unsupported("token_classification"))
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        let url = format!("{}/info", self.config.base_url);
+        let builder = self.prepare(self.client.get(&url), &request);
+        let info: Value = self.fetch("/info", builder).await?;
+        Ok(Response::new(MetadataResponse {
+            metadata: info_to_metadata(info),
+        }))
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        debug!("Received rerank request: {:?}", request);
+        let started = Instant::now();
+        let body = RerankBody {
+            query: &request.get_ref().query,
+            texts: &request.get_ref().texts,
+            truncate: self.config.truncate,
+        };
+        let url = format!("{}/rerank", self.config.base_url);
+        let builder = self.prepare(self.client.post(&url).json(&body), &request);
+        let mut ranks: Vec<Rank> = self.fetch("/rerank", builder).await?;
+        ranks.sort_by(|a, b| b.score.total_cmp(&a.score));
+        Ok(Response::new(RerankResponse {
+            took: started.elapsed().as_millis() as i32,
+            query: request.into_inner().query,
+            results: ranks
+                .into_iter()
+                .map(|rank| RankedText {
+                    index: rank.index,
+                    score: rank.score,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        debug!(
+            "Received embeddings batch of {} texts",
+            request.get_ref().len()
+        );
+        let (embeddings, took) = self.embed(&request, request.get_ref(), false).await?;
+        let responses = request
+            .into_inner()
+            .into_iter()
+            .zip(embeddings)
+            .map(|(text, values)| EmbeddingsResponse {
+                shape: shape(&values),
+                embeddings: vec![Embedding { values }],
+                took,
+                text,
+            })
+            .collect();
+        Ok(Response::new(responses))
+    }
+
+    async fn token_classification_batch(
+        &self,
+        _request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        Err(unsupported("token_classification"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tonic::Code;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_responses() {
+        let embeddings: Vec<Vec<f32>> = parse("/embed", StatusCode::OK, b"[[0.5, -0.25]]").unwrap();
+        assert_eq!(embeddings, vec![vec![0.5, -0.25]]);
+
+        let ranks: Vec<Rank> = parse(
+            "/rerank",
+            StatusCode::OK,
+            br#"[{"index": 1, "score": 0.9}, {"index": 0, "score": 0.1}]"#,
+        )
+        .unwrap();
+        assert_eq!((ranks[0].index, ranks[0].score), (1, 0.9));
+
+        let status = parse::<Vec<Rank>>(
+            "/rerank",
+            StatusCode::PAYLOAD_TOO_LARGE,
+            br#"{"error": "batch size 2000 > maximum allowed batch size 1000", "error_type": "Validation"}"#,
+        )
+        .unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert!(status
+            .message()
+            .ends_with("maximum allowed batch size 1000"));
+    }
+
+    #[test]
+    fn test_info_to_metadata() {
+        let metadata = info_to_metadata(json!({
+            "model_id": "BAAI/bge-reranker-base",
+            "model_type": { "reranker": { "id2label": { "0": "LABEL_0" } } },
+            "max_input_length": 512,
+            "sha": null,
+        }));
+        assert_eq!(metadata["backend"], "tei");
+        assert_eq!(metadata["model_id"], "BAAI/bge-reranker-base");
+        assert_eq!(metadata["max_input_length"], "512");
+        assert_eq!(
+            metadata["model_type"],
+            r#"{"reranker":{"id2label":{"0":"LABEL_0"}}}"#
+        );
+        assert!(!metadata.contains_key("sha"));
+    }
+}
@@ -0,0 +1,328 @@
+use async_trait::async_trait;
+use tonic::{Request, Response, Status};
+use tracing::field::Empty as EmptyField;
+use tracing::{debug_span, Instrument, Span};
+
+use crate::proto::mighty_proto::{
+    EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse, QuestionAnswerRequest,
+    QuestionAnswerResponse, RerankRequest, RerankResponse, SentenceTransformersResponse,
+    SequenceClassificationResponse, TextRequest, TokenClassificationResponse,
+};
+use crate::services::context::RequestContext;
+
+use super::MightyClient;
+
+/// Creates the span of an upstream call of `task`, whose `upstream` and `http.status` fields
+/// are recorded by the HTTP clients, and `took` or `grpc.code` once the call completed.
+fn call_span<R>(task: &'static str, request: &Request<R>, text_len: Option<usize>) -> Span {
+    let request_id = RequestContext::get(request)
+        .map(|context| context.request_id.as_str())
+        .unwrap_or_default();
+    let span = debug_span!(
+        "upstream_call",
+        task,
+        request_id,
+        text_len = EmptyField,
+        upstream = EmptyField,
+        http.status = EmptyField,
+        took = EmptyField,
+        grpc.code = EmptyField,
+    );
+    if let Some(text_len) = text_len {
+        span.record("text_len", text_len);
+    }
+    span
+}
+
+/// The `TracedClient` struct is a `MightyClient` decorator running each call in a `tracing`
+/// span named `upstream_call`, with fields for the task, the request id, the length of the
+/// input text in bytes, the upstream URL and HTTP status (recorded by the REST client), and the
+/// inference duration (`took`) or error code. The span is a child of the span of the incoming
+/// gRPC request, so the flow of each request can be followed under concurrency.
+///
+/// Without a `tracing` subscriber, spans are logged through `log` at the debug level.
+pub struct TracedClient {
+    inner: Box<dyn MightyClient>,
+}
+
+impl TracedClient {
+    pub fn new(inner: Box<dyn MightyClient>) -> Self {
+        Self { inner }
+    }
+
+    /// Makes `call` within `span`, recording the inference duration extracted from its
+    /// response by `took`, or its error code.
+    async fn traced<R, T, F>(
+        &self,
+        span: Span,
+        request: Request<R>,
+        call: F,
+        took: fn(&T) -> Option<i32>,
+    ) -> Result<Response<T>, Status>
+    where
+        F: for<'c> FnOnce(
+            &'c dyn MightyClient,
+            Request<R>,
+        ) -> futures::future::BoxFuture<'c, Result<Response<T>, Status>>,
+    {
+        let result = call(self.inner.as_ref(), request)
+            .instrument(span.clone())
+            .await;
+        match &result {
+            Ok(response) => {
+                if let Some(took) = took(response.get_ref()) {
+                    span.record("took", took);
+                }
+            }
+            Err(status) => {
+                span.record("grpc.code", tracing::field::debug(status.code()));
+            }
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl MightyClient for TracedClient {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        let span = call_span("health_check", &request, None);
+        self.traced(
+            span,
+            request,
+            |client, request| client.health_check(request),
+            |_| None,
+        )
+        .await
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        let span = call_span("embeddings", &request, Some(request.get_ref().text.len()));
+        self.traced(
+            span,
+            request,
+            |client, request| client.embeddings(request),
+            |response| Some(response.took),
+        )
+        .await
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        let QuestionAnswerRequest { question, context } = request.get_ref();
+        let span = call_span(
+            "question_answering",
+            &request,
+            Some(question.len() + context.len()),
+        );
+        self.traced(
+            span,
+            request,
+            |client, request| client.question_answering(request),
+            |response| Some(response.took),
+        )
+        .await
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        let span = call_span(
+            "sentence_transformers",
+            &request,
+            Some(request.get_ref().text.len()),
+        );
+        self.traced(
+            span,
+            request,
+            |client, request| client.sentence_transformers(request),
+            |response| Some(response.took),
+        )
+        .await
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        let span = call_span(
+            "sequence_classification",
+            &request,
+            Some(request.get_ref().text.len()),
+        );
+        self.traced(
+            span,
+            request,
+            |client, request| client.sequence_classification(request),
+            |response| Some(response.took),
+        )
+        .await
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        let span = call_span(
+            "token_classification",
+            &request,
+            Some(request.get_ref().text.len()),
+        );
+        self.traced(
+            span,
+            request,
+            |client, request| client.token_classification(request),
+            |response| Some(response.took),
+        )
+        .await
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        let span = call_span("metadata", &request, None);
+        self.traced(
+            span,
+            request,
+            |client, request| client.metadata(request),
+            |_| None,
+        )
+        .await
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        let RerankRequest { query, texts } = request.get_ref();
+        let text_len = query.len() + texts.iter().map(String::len).sum::<usize>();
+        let span = call_span("rerank", &request, Some(text_len));
+        self.traced(
+            span,
+            request,
+            |client, request| client.rerank(request),
+            |response| Some(response.took),
+        )
+        .await
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        let text_len = request.get_ref().iter().map(String::len).sum();
+        let span = call_span("embeddings_batch", &request, Some(text_len));
+        self.traced(
+            span,
+            request,
+            |client, request| client.embeddings_batch(request),
+            |responses| responses.iter().map(|response| response.took).max(),
+        )
+        .await
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        let text_len = request.get_ref().iter().map(String::len).sum();
+        let span = call_span("token_classification_batch", &request, Some(text_len));
+        self.traced(
+            span,
+            request,
+            |client, request| client.token_classification_batch(request),
+            |responses| responses.iter().map(|response| response.took).max(),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    use crate::config::Task;
+    use crate::services::clients::mock::MockMightyClient;
+
+    use super::*;
+
+    /// Records the fields of every span, as `name=value` strings.
+    #[derive(Clone, Default)]
+    struct FieldRecorder(Arc<Mutex<Vec<String>>>);
+
+    impl Visit for FieldRecorder {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            let field = format!("{}={:?}", field.name(), value);
+            self.0.lock().unwrap().push(field);
+        }
+    }
+
+    impl Subscriber for FieldRecorder {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            span.record(&mut self.clone());
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _span: &Id, values: &Record<'_>) {
+            values.record(&mut self.clone());
+        }
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, _event: &Event<'_>) {}
+
+        fn enter(&self, _span: &Id) {}
+
+        fn exit(&self, _span: &Id) {}
+    }
+
+    #[tokio::test]
+    async fn test_calls_are_traced() {
+        let recorder = FieldRecorder::default();
+        let _subscriber = tracing::subscriber::set_default(recorder.clone());
+        let mock = MockMightyClient::new()
+            .with_embeddings(EmbeddingsResponse {
+                took: 12,
+                ..Default::default()
+            })
+            .with_error(Task::TokenClassification, Status::unavailable("down"));
+        let client = TracedClient::new(Box::new(mock));
+        let text = || {
+            Request::new(TextRequest {
+                text: "hello".to_string(),
+            })
+        };
+
+        client.embeddings(text()).await.unwrap();
+        assert!(client.token_classification(text()).await.is_err());
+
+        let fields = recorder.0.lock().unwrap();
+        for field in [
+            "task=\"embeddings\"",
+            "text_len=5",
+            "took=12",
+            "task=\"token_classification\"",
+            "grpc.code=Unavailable",
+        ] {
+            assert!(fields.iter().any(|recorded| recorded == field), "{}", field);
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use http_body_util::{BodyExt, Either, Empty, Full, LengthLimitError, Limited};
+use hyper::body::Bytes;
+use hyper::client::conn::http1;
+use hyper::header::{HeaderValue, ACCEPT_ENCODING, CONTENT_TYPE, HOST};
+use hyper::{HeaderMap, Method, Request, StatusCode};
+use hyper_util::rt::TokioIo;
+use log::debug;
+use tokio::net::UnixStream;
+
+use super::content_encoding::{self, BodyTooLarge};
+
+/// The scheme prefix of base URLs addressing a Unix domain socket, e.g.
+/// `unix:///var/run/mighty.sock`.
+pub const UNIX_SCHEME: &str = "unix://";
+
+/// Returns the socket path of a `unix://` base URL, or `None` for any other base URL.
+pub fn socket_path(base_url: &str) -> Option<&Path> {
+    base_url
+        .strip_prefix(UNIX_SCHEME)
+        .filter(|path| !path.is_empty())
+        .map(Path::new)
+}
+
+/// A response received over a Unix domain socket.
+#[derive(Debug)]
+pub struct UnixSocketResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+/// The `UnixSocketClient` struct issues HTTP/1.1 GET (and batch POST) requests to a Mighty server
+/// listening on a Unix domain socket, which avoids loopback TCP overhead and port management when
+/// the gateway and Mighty run side by side (e.g. in the same pod).
+///
+/// Connecting to a Unix domain socket is cheap, so a connection is opened per request rather
+/// than pooled.
+#[derive(Debug, Clone)]
+pub struct UnixSocketClient {
+    path: PathBuf,
+    headers: HeaderMap,
+    max_body_size: Option<u64>,
+}
+
+impl UnixSocketClient {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            ACCEPT_ENCODING,
+            HeaderValue::from_static(content_encoding::ACCEPT_ENCODING),
+        );
+        Self {
+            path: path.into(),
+            headers,
+            max_body_size: None,
+        }
+    }
+
+    /// Sets the headers sent with every request, e.g. `Accept-Encoding` and `User-Agent`.
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Limits the size of response bodies; larger bodies fail with a `BodyTooLarge` error.
+    pub fn with_max_body_size(mut self, max_body_size: Option<u64>) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Issues a GET request for `path_and_query` (e.g. `/embeddings?text=hello`) with the
+    /// default headers plus `headers`, and reads the whole response body.
+    pub async fn get(
+        &self,
+        path_and_query: &str,
+        headers: &HeaderMap,
+    ) -> io::Result<UnixSocketResponse> {
+        self.send(Method::GET, path_and_query, headers, None).await
+    }
+
+    /// Issues a POST request of the JSON `body` to `path`, as `get` does.
+    pub async fn post_json(
+        &self,
+        path: &str,
+        headers: &HeaderMap,
+        body: Bytes,
+    ) -> io::Result<UnixSocketResponse> {
+        self.send(Method::POST, path, headers, Some(body)).await
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        path_and_query: &str,
+        headers: &HeaderMap,
+        body: Option<Bytes>,
+    ) -> io::Result<UnixSocketResponse> {
+        let stream = UnixStream::connect(&self.path).await?;
+        let (mut sender, connection) = http1::handshake(TokioIo::new(stream))
+            .await
+            .map_err(io::Error::other)?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                debug!("Unix socket connection closed with error: {}", e);
+            }
+        });
+
+        let builder = Request::builder()
+            .method(method)
+            .uri(path_and_query)
+            .header(HOST, "localhost");
+        let request = match body {
+            Some(body) => builder
+                .header(CONTENT_TYPE, "application/json")
+                .body(Either::Right(Full::new(body))),
+            None => builder.body(Either::Left(Empty::<Bytes>::new())),
+        };
+        let mut request =
+            request.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        request.headers_mut().extend(self.headers.clone());
+        request.headers_mut().extend(headers.clone());
+        let response = sender
+            .send_request(request)
+            .await
+            .map_err(io::Error::other)?;
+        let (parts, body) = response.into_parts();
+        let body = match self.max_body_size {
+            Some(limit) => Limited::new(body, usize::try_from(limit).unwrap_or(usize::MAX))
+                .collect()
+                .await
+                .map_err(|e| match e.is::<LengthLimitError>() {
+                    true => BodyTooLarge { limit }.into(),
+                    false => io::Error::other(e),
+                })?
+                .to_bytes(),
+            None => body.collect().await.map_err(io::Error::other)?.to_bytes(),
+        };
+
+        Ok(UnixSocketResponse {
+            status: parts.status,
+            headers: parts.headers,
+            body,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixListener;
+
+    use super::*;
+
+    #[test]
+    fn test_socket_path() {
+        assert_eq!(
+            socket_path("unix:///var/run/mighty.sock"),
+            Some(Path::new("/var/run/mighty.sock"))
+        );
+        assert_eq!(socket_path("unix://"), None);
+        assert_eq!(socket_path("http://localhost:5050"), None);
+    }
+
+    /// Answers one request with `{}` on a socket at a path named after `name`, returning the
+    /// path and the request received.
+    fn serve_once(name: &str) -> (PathBuf, tokio::task::JoinHandle<String>) {
+        let path = std::env::temp_dir().join(format!(
+            "mighty-grpc-{}-{}.sock",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 1024];
+            let read = stream.read(&mut buf).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n{}")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&buf[..read]).to_string()
+        });
+        (path, server)
+    }
+
+    #[tokio::test]
+    async fn test_get_over_unix_socket() {
+        let (path, server) = serve_once("get");
+        let response = UnixSocketClient::new(&path)
+            .get("/embeddings?text=hi", &HeaderMap::new())
+            .await
+            .unwrap();
+        let request = server.await.unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body, "{}");
+        assert!(request.starts_with("GET /embeddings?text=hi HTTP/1.1\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_post_over_unix_socket() {
+        let (path, server) = serve_once("post");
+        let response = UnixSocketClient::new(&path)
+            .post_json("/embeddings", &HeaderMap::new(), Bytes::from(r#"["hi"]"#))
+            .await
+            .unwrap();
+        let request = server.await.unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(request.starts_with("POST /embeddings HTTP/1.1\r\n"));
+        assert!(request.contains("content-type: application/json\r\n"));
+        assert!(request.ends_with("\r\n\r\n[\"hi\"]"));
+    }
+}
@@ -0,0 +1,447 @@
+use async_trait::async_trait;
+use log::warn;
+use tokio::sync::OnceCell;
+use tonic::{Request, Response, Status};
+
+use crate::config::ValidationConfig;
+use crate::proto::mighty_proto::{
+    Embedding, EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse,
+    QuestionAnswerRequest, QuestionAnswerResponse, RerankRequest, RerankResponse,
+    SentenceTransformersResponse, SequenceClassificationResponse, TextRequest,
+    TokenClassificationResponse,
+};
+use crate::services::metrics::Metrics;
+
+use super::MightyClient;
+
+/// Metadata keys that may carry the model's hidden size (the embedding dimensionality).
+const HIDDEN_SIZE_KEYS: &[&str] = &["hidden_size", "dim", "embedding_size"];
+
+/// Counter incremented whenever an upstream response violates a validation rule.
+const INVALID_RESPONSES_METRIC: &str = "mighty_upstream_invalid_responses_total";
+
+/// A violated validation rule, with the rule name used as metric label and a precise reason.
+#[derive(Debug, PartialEq)]
+pub struct Violation {
+    pub rule: &'static str,
+    pub reason: String,
+}
+
+impl Violation {
+    fn new(rule: &'static str, reason: String) -> Self {
+        Self { rule, reason }
+    }
+}
+
+/// The `ValidatingClient` struct is a `MightyClient` decorator applying the configured
+/// validation rules to upstream responses before they are returned.
+///
+/// A violation results in an `INTERNAL` status naming the rule and the offending value, and
+/// increments the `mighty_upstream_invalid_responses_total` metric, so silent upstream model
+/// misconfiguration (wrong model loaded, broken post-processing) is caught at the gateway.
+pub struct ValidatingClient {
+    inner: Box<dyn MightyClient>,
+    config: ValidationConfig,
+    metadata_dimension: OnceCell<Option<usize>>,
+}
+
+impl ValidatingClient {
+    pub fn new(inner: Box<dyn MightyClient>, config: ValidationConfig) -> Self {
+        Self {
+            inner,
+            config,
+            metadata_dimension: OnceCell::new(),
+        }
+    }
+
+    /// Returns the expected embedding dimensionality, either configured explicitly or read
+    /// (once) from the upstream model metadata. Failing to read the metadata skips the check,
+    /// the metadata being read again on the next call.
+    async fn expected_dimension(&self) -> Option<usize> {
+        if self.config.expected_dimension.is_some() {
+            return self.config.expected_dimension;
+        }
+        if !self.config.dimension_from_metadata {
+            return None;
+        }
+        let dimension = self
+            .metadata_dimension
+            .get_or_try_init(|| async {
+                let response = self.inner.metadata(Request::new(Empty {})).await?;
+                Ok::<_, Status>(hidden_size(&response.into_inner()))
+            })
+            .await;
+        match dimension {
+            Ok(dimension) => *dimension,
+            Err(status) => {
+                warn!("Unable to fetch metadata for validation: {}", status);
+                None
+            }
+        }
+    }
+
+    async fn check_embeddings(&self, task: &str, embeddings: &[Embedding]) -> Result<(), Status> {
+        let expected_dimension = self.expected_dimension().await;
+        let violation = self
+            .config
+            .non_empty_outputs
+            .then(|| check_non_empty_embeddings(embeddings))
+            .flatten()
+            .or_else(|| {
+                expected_dimension.and_then(|dimension| check_dimension(embeddings, dimension))
+            });
+        self.reject(task, violation)
+    }
+
+    fn check_entities(&self, response: &TokenClassificationResponse) -> Result<(), Status> {
+        let violation = self
+            .config
+            .score_range
+            .then(|| check_scores(response.entities.iter().map(|e| e.score)))
+            .flatten();
+        self.reject("token_classification", violation)
+    }
+
+    fn reject(&self, task: &str, violation: Option<Violation>) -> Result<(), Status> {
+        let Some(violation) = violation else {
+            return Ok(());
+        };
+        Metrics::global()
+            .counter(
+                INVALID_RESPONSES_METRIC,
+                &[("task", task), ("rule", violation.rule)],
+            )
+            .increment(1);
+        warn!(
+            "Upstream {} response failed validation: {}",
+            task, violation.reason
+        );
+        Err(Status::internal(format!(
+            "Upstream response failed validation ({}): {}",
+            violation.rule, violation.reason
+        )))
+    }
+}
+
+/// Extracts the hidden size from the model metadata, if reported.
+fn hidden_size(metadata: &MetadataResponse) -> Option<usize> {
+    HIDDEN_SIZE_KEYS
+        .iter()
+        .find_map(|key| metadata.metadata.get(*key))
+        .and_then(|value| value.trim().parse().ok())
+}
+
+/// Checks that at least one embedding was returned and that none of them is empty.
+pub fn check_non_empty_embeddings(embeddings: &[Embedding]) -> Option<Violation> {
+    if embeddings.is_empty() {
+        return Some(Violation::new(
+            "non_empty_outputs",
+            "no embeddings returned".to_string(),
+        ));
+    }
+    embeddings
+        .iter()
+        .position(|embedding| embedding.values.is_empty())
+        .map(|index| Violation::new("non_empty_outputs", format!("embedding {} is empty", index)))
+}
+
+/// Checks that every embedding has the expected dimensionality.
+pub fn check_dimension(embeddings: &[Embedding], expected: usize) -> Option<Violation> {
+    embeddings
+        .iter()
+        .enumerate()
+        .find(|(_, embedding)| embedding.values.len() != expected)
+        .map(|(index, embedding)| {
+            Violation::new(
+                "embedding_dimension",
+                format!(
+                    "embedding {} has dimension {}, expected {}",
+                    index,
+                    embedding.values.len(),
+                    expected
+                ),
+            )
+        })
+}
+
+/// Checks that every score lies within `[0, 1]`.
+pub fn check_scores(scores: impl IntoIterator<Item = f32>) -> Option<Violation> {
+    scores
+        .into_iter()
+        .enumerate()
+        .find(|(_, score)| !(0.0..=1.0).contains(score))
+        .map(|(index, score)| {
+            Violation::new(
+                "score_range",
+                format!("score {} at index {} is outside [0, 1]", score, index),
+            )
+        })
+}
+
+#[async_trait]
+impl MightyClient for ValidatingClient {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        self.inner.health_check(request).await
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        let response = self.inner.embeddings(request).await?;
+        self.check_embeddings("embeddings", &response.get_ref().embeddings)
+            .await?;
+        Ok(response)
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        let response = self.inner.question_answering(request).await?;
+        let violation = (self.config.non_empty_outputs && response.get_ref().answer.is_empty())
+            .then(|| Violation::new("non_empty_outputs", "no answer returned".to_string()));
+        self.reject("question_answering", violation)?;
+        Ok(response)
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        let response = self.inner.sentence_transformers(request).await?;
+        self.check_embeddings("sentence_transformers", &response.get_ref().embeddings)
+            .await?;
+        Ok(response)
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        let response = self.inner.sequence_classification(request).await?;
+        let violation = (self.config.non_empty_outputs && response.get_ref().logits.is_empty())
+            .then(|| Violation::new("non_empty_outputs", "no logits returned".to_string()));
+        self.reject("sequence_classification", violation)?;
+        Ok(response)
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        let response = self.inner.token_classification(request).await?;
+        self.check_entities(response.get_ref())?;
+        Ok(response)
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        self.inner.metadata(request).await
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        let texts = request.get_ref().texts.len();
+        let response = self.inner.rerank(request).await?;
+        let violation =
+            (self.config.non_empty_outputs && texts > 0 && response.get_ref().results.is_empty())
+                .then(|| Violation::new("non_empty_outputs", "no results returned".to_string()));
+        self.reject("rerank", violation)?;
+        Ok(response)
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        let response = self.inner.embeddings_batch(request).await?;
+        for embeddings in response.get_ref() {
+            self.check_embeddings("embeddings", &embeddings.embeddings)
+                .await?;
+        }
+        Ok(response)
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        let response = self.inner.token_classification_batch(request).await?;
+        for entities in response.get_ref() {
+            self.check_entities(entities)?;
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use crate::services::clients::mock::MockMightyClient;
+
+    use super::*;
+
+    /// Serves calls from a `MockMightyClient` of 4-dimensional embeddings and a hidden size of
+    /// 4, failing the first metadata call.
+    struct FlakyMetadata {
+        inner: MockMightyClient,
+        failed: AtomicBool,
+    }
+
+    #[async_trait]
+    impl MightyClient for FlakyMetadata {
+        async fn health_check(
+            &self,
+            request: Request<Empty>,
+        ) -> Result<Response<HealthcheckResponse>, Status> {
+            self.inner.health_check(request).await
+        }
+
+        async fn embeddings(
+            &self,
+            request: Request<TextRequest>,
+        ) -> Result<Response<EmbeddingsResponse>, Status> {
+            self.inner.embeddings(request).await
+        }
+
+        async fn question_answering(
+            &self,
+            request: Request<QuestionAnswerRequest>,
+        ) -> Result<Response<QuestionAnswerResponse>, Status> {
+            self.inner.question_answering(request).await
+        }
+
+        async fn sentence_transformers(
+            &self,
+            request: Request<TextRequest>,
+        ) -> Result<Response<SentenceTransformersResponse>, Status> {
+            self.inner.sentence_transformers(request).await
+        }
+
+        async fn sequence_classification(
+            &self,
+            request: Request<TextRequest>,
+        ) -> Result<Response<SequenceClassificationResponse>, Status> {
+            self.inner.sequence_classification(request).await
+        }
+
+        async fn token_classification(
+            &self,
+            request: Request<TextRequest>,
+        ) -> Result<Response<TokenClassificationResponse>, Status> {
+            self.inner.token_classification(request).await
+        }
+
+        async fn metadata(
+            &self,
+            request: Request<Empty>,
+        ) -> Result<Response<MetadataResponse>, Status> {
+            if !self.failed.swap(true, Ordering::Relaxed) {
+                return Err(Status::unavailable("not ready"));
+            }
+            self.inner.metadata(request).await
+        }
+    }
+
+    fn embedding(values: &[f32]) -> Embedding {
+        Embedding {
+            values: values.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_check_non_empty_embeddings() {
+        assert!(check_non_empty_embeddings(&[embedding(&[0.1])]).is_none());
+        assert_eq!(
+            check_non_empty_embeddings(&[]).unwrap().rule,
+            "non_empty_outputs"
+        );
+        assert_eq!(
+            check_non_empty_embeddings(&[embedding(&[0.1]), embedding(&[])])
+                .unwrap()
+                .reason,
+            "embedding 1 is empty"
+        );
+    }
+
+    #[test]
+    fn test_check_dimension() {
+        let embeddings = [embedding(&[0.1, 0.2, 0.3]), embedding(&[0.1, 0.2])];
+        assert!(check_dimension(&embeddings[..1], 3).is_none());
+        assert_eq!(
+            check_dimension(&embeddings, 3).unwrap().reason,
+            "embedding 1 has dimension 2, expected 3"
+        );
+    }
+
+    #[test]
+    fn test_check_scores() {
+        assert!(check_scores([0.0, 0.5, 1.0]).is_none());
+        assert_eq!(check_scores([0.5, 1.2]).unwrap().rule, "score_range");
+        assert!(check_scores([f32::NAN]).is_some());
+    }
+
+    #[test]
+    fn test_hidden_size_from_metadata() {
+        let metadata = MetadataResponse {
+            metadata: [("hidden_size".to_string(), "768".to_string())]
+                .into_iter()
+                .collect(),
+        };
+        assert_eq!(hidden_size(&metadata), Some(768));
+        assert_eq!(hidden_size(&MetadataResponse::default()), None);
+    }
+
+    #[tokio::test]
+    async fn test_batch_responses_are_validated() {
+        let config = |expected_dimension| ValidationConfig {
+            expected_dimension: Some(expected_dimension),
+            ..Default::default()
+        };
+        let texts = || Request::new(vec!["a".to_string(), "b".to_string()]);
+        let mock = || Box::new(MockMightyClient::new().with_dimension(4));
+
+        let client = ValidatingClient::new(mock(), config(4));
+        assert_eq!(
+            client
+                .embeddings_batch(texts())
+                .await
+                .unwrap()
+                .get_ref()
+                .len(),
+            2
+        );
+        let client = ValidatingClient::new(mock(), config(8));
+        let status = client.embeddings_batch(texts()).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Internal);
+    }
+
+    #[tokio::test]
+    async fn test_metadata_is_read_again_after_failing() {
+        let inner = MockMightyClient::new()
+            .with_dimension(4)
+            .with_metadata([("hidden_size".to_string(), "4".to_string())].into());
+        let client = ValidatingClient::new(
+            Box::new(FlakyMetadata {
+                inner,
+                failed: AtomicBool::new(false),
+            }),
+            ValidationConfig {
+                dimension_from_metadata: true,
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(client.expected_dimension().await, None);
+        assert_eq!(client.expected_dimension().await, Some(4));
+    }
+}
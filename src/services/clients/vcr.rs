@@ -0,0 +1,453 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tonic::metadata::MetadataValue;
+use tonic::{Request, Response, Status};
+
+use crate::proto::mighty_proto::{
+    EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse, QuestionAnswerRequest,
+    QuestionAnswerResponse, RerankRequest, RerankResponse, SentenceTransformersResponse,
+    SequenceClassificationResponse, TextRequest, TokenClassificationResponse,
+};
+use crate::services::context::{RequestContext, RAW_JSON_METADATA};
+use crate::services::hash::reference;
+
+use super::json_response_converters::{
+    json_to_embeddings_batch_response, json_to_embeddings_response, json_to_metadata_response,
+    json_to_question_answer_response, json_to_sentence_transformers_response,
+    json_to_sequence_classification_response, json_to_token_classification_batch_response,
+    json_to_token_classification_response,
+};
+use super::MightyClient;
+
+/// A recorded upstream exchange, stored as `{task}-{hash of the request}.json`.
+#[derive(Debug, Serialize, Deserialize)]
+struct Fixture {
+    task: String,
+    /// The inputs of the call, e.g. `{"text": "hello"}`.
+    request: Value,
+    /// The JSON returned by the upstream.
+    response: Value,
+}
+
+/// The `VcrClient` struct is a `MightyClient` decorator recording upstream JSON responses to
+/// fixture files, then replaying them without any upstream, so test suites run deterministically
+/// offline, e.g. in CI.
+///
+/// - In record mode, calls are forwarded to the wrapped client, asking it for the raw upstream
+///   JSON (as with `x-mighty-debug: raw-json`), which is saved along with the call's inputs.
+///   Only the REST client reports raw JSON, and only successful calls are recorded.
+/// - In replay mode, calls are answered from the fixture recorded for their inputs, converted
+///   like lenient live responses, so hand-edited fixtures may omit fields; calls without a
+///   fixture fail with `NOT_FOUND`. Health checks succeed
+///   and rerank calls fail with `UNIMPLEMENTED`.
+pub struct VcrClient {
+    /// The client recorded, `None` when replaying.
+    inner: Option<Box<dyn MightyClient>>,
+    fixtures: PathBuf,
+}
+
+impl VcrClient {
+    /// Creates a client recording the responses of `inner` to the `fixtures` directory,
+    /// creating it if needed.
+    pub fn record(inner: Box<dyn MightyClient>, fixtures: &Path) -> io::Result<Self> {
+        std::fs::create_dir_all(fixtures)?;
+        info!("Recording upstream responses to {}", fixtures.display());
+        Ok(Self {
+            inner: Some(inner),
+            fixtures: fixtures.to_path_buf(),
+        })
+    }
+
+    /// Creates a client replaying the responses recorded in the `fixtures` directory.
+    pub fn replay(fixtures: &Path) -> Self {
+        info!("Replaying upstream responses from {}", fixtures.display());
+        Self {
+            inner: None,
+            fixtures: fixtures.to_path_buf(),
+        }
+    }
+
+    /// Returns the path of the fixture of a `task` call with the given inputs.
+    fn fixture_path(&self, task: &str, inputs: &Value) -> PathBuf {
+        let hash = reference(&inputs.to_string());
+        self.fixtures.join(format!("{}-{:016x}.json", task, hash))
+    }
+
+    /// Records or replays a `task` call with the given inputs, converting the upstream JSON
+    /// with `convert` when replaying.
+    async fn exchange<R, T, F, C>(
+        &self,
+        task: &str,
+        inputs: Value,
+        request: Request<R>,
+        call: F,
+        convert: C,
+    ) -> Result<Response<T>, Status>
+    where
+        R: Send,
+        C: FnOnce(&Value) -> Result<T, Status>,
+        F: for<'c> FnOnce(
+            &'c dyn MightyClient,
+            Request<R>,
+        ) -> futures::future::BoxFuture<'c, Result<Response<T>, Status>>,
+    {
+        let raw_json = RequestContext::get(&request).is_some_and(|context| context.raw_json);
+        let path = self.fixture_path(task, &inputs);
+        let Some(inner) = &self.inner else {
+            let fixture = read_fixture(&path, &inputs).await?;
+            let mut response = Response::new(convert(&fixture.response)?);
+            if raw_json {
+                let body = fixture.response.to_string();
+                response.metadata_mut().insert_bin(
+                    RAW_JSON_METADATA,
+                    MetadataValue::from_bytes(body.as_bytes()),
+                );
+            }
+            return Ok(response);
+        };
+
+        let mut request = request;
+        let mut context = RequestContext::get(&request).cloned().unwrap_or_default();
+        context.raw_json = true;
+        request.extensions_mut().insert(context);
+        let mut response = call(inner.as_ref(), request).await?;
+        let recorded = response
+            .metadata()
+            .get_bin(RAW_JSON_METADATA)
+            .and_then(|value| value.to_bytes().ok())
+            .and_then(|body| serde_json::from_slice::<Value>(&body).ok());
+        match recorded {
+            Some(json) => write_fixture(&path, task, inputs, json).await,
+            None => warn!("The upstream returned no raw JSON to record for {}", task),
+        }
+        if !raw_json {
+            response.metadata_mut().remove_bin(RAW_JSON_METADATA);
+        }
+        Ok(response)
+    }
+}
+
+async fn read_fixture(path: &Path, inputs: &Value) -> Result<Fixture, Status> {
+    let missing = || {
+        Status::not_found(format!(
+            "No fixture recorded for {} at {}",
+            inputs,
+            path.display()
+        ))
+    };
+    let body = tokio::fs::read(path).await.map_err(|_| missing())?;
+    let fixture: Fixture = serde_json::from_slice(&body)
+        .map_err(|e| Status::internal(format!("Invalid fixture {}: {}", path.display(), e)))?;
+    // Guard against hash collisions between inputs
+    if fixture.request != *inputs {
+        return Err(missing());
+    }
+    Ok(fixture)
+}
+
+async fn write_fixture(path: &Path, task: &str, request: Value, response: Value) {
+    let fixture = Fixture {
+        task: task.to_string(),
+        request,
+        response,
+    };
+    let result = match serde_json::to_vec_pretty(&fixture) {
+        Ok(body) => tokio::fs::write(path, body).await,
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = result {
+        warn!("Failed to record fixture {}: {}", path.display(), e);
+    }
+}
+
+fn text_inputs(request: &Request<TextRequest>) -> Value {
+    json!({ "text": request.get_ref().text })
+}
+
+fn batch_inputs(request: &Request<Vec<String>>) -> Value {
+    json!({ "texts": request.get_ref() })
+}
+
+#[async_trait]
+impl MightyClient for VcrClient {
+    async fn health_check(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HealthcheckResponse>, Status> {
+        match &self.inner {
+            Some(inner) => inner.health_check(request).await,
+            None => Ok(Response::new(HealthcheckResponse { success: true })),
+        }
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        let inputs = text_inputs(&request);
+        self.exchange(
+            "embeddings",
+            inputs,
+            request,
+            |client, request| client.embeddings(request),
+            |json| json_to_embeddings_response(json, false),
+        )
+        .await
+    }
+
+    async fn question_answering(
+        &self,
+        request: Request<QuestionAnswerRequest>,
+    ) -> Result<Response<QuestionAnswerResponse>, Status> {
+        let QuestionAnswerRequest { question, context } = request.get_ref().clone();
+        let inputs = json!({ "question": question, "context": context });
+        self.exchange(
+            "question_answering",
+            inputs,
+            request,
+            |client, request| client.question_answering(request),
+            |json| json_to_question_answer_response(json, question, context, false),
+        )
+        .await
+    }
+
+    async fn sentence_transformers(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SentenceTransformersResponse>, Status> {
+        let inputs = text_inputs(&request);
+        self.exchange(
+            "sentence_transformers",
+            inputs,
+            request,
+            |client, request| client.sentence_transformers(request),
+            |json| json_to_sentence_transformers_response(json, false),
+        )
+        .await
+    }
+
+    async fn sequence_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<SequenceClassificationResponse>, Status> {
+        let inputs = text_inputs(&request);
+        self.exchange(
+            "sequence_classification",
+            inputs,
+            request,
+            |client, request| client.sequence_classification(request),
+            |json| json_to_sequence_classification_response(json, false),
+        )
+        .await
+    }
+
+    async fn token_classification(
+        &self,
+        request: Request<TextRequest>,
+    ) -> Result<Response<TokenClassificationResponse>, Status> {
+        let inputs = text_inputs(&request);
+        self.exchange(
+            "token_classification",
+            inputs,
+            request,
+            |client, request| client.token_classification(request),
+            |json| json_to_token_classification_response(json, false),
+        )
+        .await
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        self.exchange(
+            "metadata",
+            json!({}),
+            request,
+            |client, request| client.metadata(request),
+            json_to_metadata_response,
+        )
+        .await
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        match &self.inner {
+            Some(inner) => inner.rerank(request).await,
+            None => Err(Status::unimplemented("Rerank calls aren't replayed")),
+        }
+    }
+
+    async fn embeddings_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+        let inputs = batch_inputs(&request);
+        self.exchange(
+            "embeddings_batch",
+            inputs,
+            request,
+            |client, request| client.embeddings_batch(request),
+            |json| json_to_embeddings_batch_response(json, false),
+        )
+        .await
+    }
+
+    async fn token_classification_batch(
+        &self,
+        request: Request<Vec<String>>,
+    ) -> Result<Response<Vec<TokenClassificationResponse>>, Status> {
+        let inputs = batch_inputs(&request);
+        self.exchange(
+            "token_classification_batch",
+            inputs,
+            request,
+            |client, request| client.token_classification_batch(request),
+            |json| json_to_token_classification_batch_response(json, false),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use super::*;
+
+    /// Answers embeddings calls like the REST client asked for the raw JSON.
+    struct JsonClient;
+
+    #[async_trait]
+    impl MightyClient for JsonClient {
+        async fn health_check(
+            &self,
+            _request: Request<Empty>,
+        ) -> Result<Response<HealthcheckResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn embeddings(
+            &self,
+            request: Request<TextRequest>,
+        ) -> Result<Response<EmbeddingsResponse>, Status> {
+            assert!(RequestContext::get(&request).unwrap().raw_json);
+            let json = json!({
+                "outputs": [[0.5, -0.25]],
+                "took": 3,
+                "text": request.get_ref().text,
+                "shape": [1, 2],
+            });
+            let mut response = Response::new(json_to_embeddings_response(&json, true)?);
+            response.metadata_mut().insert_bin(
+                RAW_JSON_METADATA,
+                MetadataValue::from_bytes(json.to_string().as_bytes()),
+            );
+            Ok(response)
+        }
+
+        async fn question_answering(
+            &self,
+            _request: Request<QuestionAnswerRequest>,
+        ) -> Result<Response<QuestionAnswerResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn sentence_transformers(
+            &self,
+            _request: Request<TextRequest>,
+        ) -> Result<Response<SentenceTransformersResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn sequence_classification(
+            &self,
+            _request: Request<TextRequest>,
+        ) -> Result<Response<SequenceClassificationResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn token_classification(
+            &self,
+            _request: Request<TextRequest>,
+        ) -> Result<Response<TokenClassificationResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn metadata(
+            &self,
+            _request: Request<Empty>,
+        ) -> Result<Response<MetadataResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn embeddings_batch(
+            &self,
+            request: Request<Vec<String>>,
+        ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+            assert!(RequestContext::get(&request).unwrap().raw_json);
+            let json: Value = request
+                .get_ref()
+                .iter()
+                .map(|text| json!({ "outputs": [[0.5]], "text": text, "shape": [1, 1], "took": 1 }))
+                .collect();
+            let mut response = Response::new(json_to_embeddings_batch_response(&json, true)?);
+            response.metadata_mut().insert_bin(
+                RAW_JSON_METADATA,
+                MetadataValue::from_bytes(json.to_string().as_bytes()),
+            );
+            Ok(response)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recorded_responses_are_replayed() {
+        let fixtures = std::env::temp_dir().join(format!("mighty-vcr-{}", std::process::id()));
+        let text = |text: &str| {
+            Request::new(TextRequest {
+                text: text.to_string(),
+            })
+        };
+
+        let recorder = VcrClient::record(Box::new(JsonClient), &fixtures).unwrap();
+        let recorded = recorder.embeddings(text("hello")).await.unwrap();
+        assert!(recorded.metadata().get_bin(RAW_JSON_METADATA).is_none());
+
+        let player = VcrClient::replay(&fixtures);
+        let replayed = player.embeddings(text("hello")).await.unwrap();
+        assert_eq!(replayed.into_inner(), recorded.into_inner());
+        let status = player.embeddings(text("world")).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        std::fs::remove_dir_all(&fixtures).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recorded_batches_are_replayed() {
+        let fixtures =
+            std::env::temp_dir().join(format!("mighty-vcr-batch-{}", std::process::id()));
+        let texts =
+            |texts: &[&str]| Request::new(texts.iter().map(|text| text.to_string()).collect());
+
+        let recorder = VcrClient::record(Box::new(JsonClient), &fixtures).unwrap();
+        let recorded = recorder.embeddings_batch(texts(&["a", "b"])).await.unwrap();
+
+        let player = VcrClient::replay(&fixtures);
+        let replayed = player.embeddings_batch(texts(&["a", "b"])).await.unwrap();
+        assert_eq!(replayed.into_inner(), recorded.into_inner());
+        let status = player
+            .embeddings_batch(texts(&["b", "a"]))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        std::fs::remove_dir_all(&fixtures).unwrap();
+    }
+}
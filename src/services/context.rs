@@ -0,0 +1,222 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use tonic::metadata::MetadataMap;
+use tonic::Request;
+
+/// Metadata key carrying the caller-supplied request id.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+/// Metadata key carrying the tenant the call is made on behalf of.
+pub const TENANT_HEADER: &str = "x-tenant-id";
+/// Metadata key carrying the model the call is routed to, when several are served.
+pub const MODEL_HEADER: &str = "x-model";
+/// Metadata key carrying the call priority (`low`, `normal` or `high`).
+pub const PRIORITY_HEADER: &str = "x-priority";
+/// Metadata key carrying the gRPC deadline, as sent by gRPC clients.
+pub const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+/// Metadata key requesting debug output (`raw-json` to receive the raw upstream response).
+pub const DEBUG_HEADER: &str = "x-mighty-debug";
+/// Response metadata key set to `hit` when the response was served from the gateway cache.
+pub const CACHE_HEADER: &str = "x-mighty-cache";
+/// Binary response metadata key carrying the raw upstream JSON when requested through
+/// `x-mighty-debug: raw-json`.
+pub const RAW_JSON_METADATA: &str = "x-mighty-raw-json-bin";
+/// Response metadata key giving the full size of the raw upstream JSON, when the JSON attached
+/// under `x-mighty-raw-json-bin` was truncated.
+pub const RAW_JSON_SIZE_HEADER: &str = "x-mighty-raw-json-size";
+
+/// Response extension naming the upstream instance that served the call, when load balancing.
+/// Kept off the wire, as upstream addresses are internal; callers only see it in the
+/// `Provenance` of `StreamEmbeddings` results, when they request it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServedBy(pub String);
+
+/// The priority of a call, used by backends and decorators to order or shed work.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "low" => Some(Priority::Low),
+            "normal" => Some(Priority::Normal),
+            "high" => Some(Priority::High),
+            _ => None,
+        }
+    }
+}
+
+/// Per-call context attached to every request as a request extension before it reaches a
+/// `MightyClient`, so backends and decorators can make identity-aware decisions without
+/// re-parsing gRPC metadata in every layer.
+///
+/// The context is derived from the incoming metadata by `RequestContext::attach`. Layers that
+/// run earlier (e.g. authentication interceptors) may insert a partially populated context of
+/// their own, such as one carrying the authenticated `identity`; it is completed rather than
+/// replaced.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RequestContext {
+    /// The request id, taken from `x-request-id` or generated when absent.
+    pub request_id: String,
+    /// The tenant the call is made on behalf of, from `x-tenant-id`.
+    pub tenant: Option<String>,
+    /// The authenticated identity of the caller, when an authentication layer established one,
+    /// or from its client certificate over mutual TLS.
+    pub identity: Option<String>,
+    /// The point in time after which the caller is no longer waiting, from `grpc-timeout`.
+    pub deadline: Option<Instant>,
+    /// The model the call is routed to, from `x-model`.
+    pub model: Option<String>,
+    /// The call priority, from `x-priority`.
+    pub priority: Priority,
+    /// The address of the calling peer, when known.
+    pub peer_addr: Option<SocketAddr>,
+    /// Whether the raw upstream response should be attached to the response metadata, from
+    /// `x-mighty-debug: raw-json`. The server proxy clears it for callers not allowed raw JSON.
+    pub raw_json: bool,
+}
+
+impl RequestContext {
+    /// Returns the context attached to `request`, if any.
+    pub fn get<T>(request: &Request<T>) -> Option<&RequestContext> {
+        request.extensions().get::<RequestContext>()
+    }
+
+    /// Builds the context for `request` from its metadata, completing any context already
+    /// attached by an earlier layer, and attaches it to the request.
+    pub fn attach<T>(mut request: Request<T>) -> Request<T> {
+        let existing = request.extensions_mut().remove::<RequestContext>();
+        let mut context = Self::from_metadata(request.metadata(), existing.unwrap_or_default());
+        if context.peer_addr.is_none() {
+            context.peer_addr = request.remote_addr();
+        }
+        #[cfg(feature = "tls")]
+        if context.identity.is_none() {
+            context.identity = crate::services::tls::peer_identity(&request);
+        }
+        request.extensions_mut().insert(context);
+        request
+    }
+
+    /// Returns how much time is left before the deadline, if one is set.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    fn from_metadata(metadata: &MetadataMap, mut context: RequestContext) -> Self {
+        let header = |key: &str| {
+            metadata
+                .get(key)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+        };
+
+        if context.request_id.is_empty() {
+            context.request_id = header(REQUEST_ID_HEADER)
+                .map(str::to_string)
+                .unwrap_or_else(generate_request_id);
+        }
+        if context.tenant.is_none() {
+            context.tenant = header(TENANT_HEADER).map(str::to_string);
+        }
+        if context.deadline.is_none() {
+            context.deadline = header(GRPC_TIMEOUT_HEADER)
+                .and_then(parse_grpc_timeout)
+                .map(|timeout| Instant::now() + timeout);
+        }
+        if context.model.is_none() {
+            context.model = header(MODEL_HEADER).map(str::to_string);
+        }
+        if let Some(priority) = header(PRIORITY_HEADER).and_then(Priority::parse) {
+            context.priority = priority;
+        }
+        if let Some(debug) = header(DEBUG_HEADER) {
+            context.raw_json |= debug
+                .split(',')
+                .any(|flag| flag.trim().eq_ignore_ascii_case("raw-json"));
+        }
+        context
+    }
+}
+
+/// Parses a `grpc-timeout` header value (`<digits><unit>`, e.g. `250m` or `5S`).
+pub fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    let (digits, unit) = value.split_at(value.len().checked_sub(1)?);
+    let amount = digits.parse::<u64>().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(amount.saturating_mul(3600))),
+        "M" => Some(Duration::from_secs(amount.saturating_mul(60))),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+pub(crate) fn generate_request_id() -> String {
+    format!("{:032x}", rand::thread_rng().gen::<u128>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_grpc_timeout() {
+        assert_eq!(parse_grpc_timeout("250m"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_grpc_timeout("5S"), Some(Duration::from_secs(5)));
+        assert_eq!(parse_grpc_timeout("2M"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_grpc_timeout("10x"), None);
+        assert_eq!(parse_grpc_timeout(""), None);
+    }
+
+    #[test]
+    fn test_attach_reads_metadata() {
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert(REQUEST_ID_HEADER, "req-1".parse().unwrap());
+        request
+            .metadata_mut()
+            .insert(TENANT_HEADER, "search".parse().unwrap());
+        request
+            .metadata_mut()
+            .insert(PRIORITY_HEADER, "high".parse().unwrap());
+        request
+            .metadata_mut()
+            .insert(DEBUG_HEADER, "raw-json".parse().unwrap());
+
+        let request = RequestContext::attach(request);
+        let context = RequestContext::get(&request).unwrap();
+
+        assert_eq!(context.request_id, "req-1");
+        assert_eq!(context.tenant.as_deref(), Some("search"));
+        assert_eq!(context.priority, Priority::High);
+        assert!(context.deadline.is_none());
+        assert!(context.raw_json);
+    }
+
+    #[test]
+    fn test_attach_completes_existing_context() {
+        let mut request = Request::new(());
+        request.extensions_mut().insert(RequestContext {
+            identity: Some("svc-a".to_string()),
+            ..Default::default()
+        });
+
+        let request = RequestContext::attach(request);
+        let context = RequestContext::get(&request).unwrap();
+
+        assert_eq!(context.identity.as_deref(), Some("svc-a"));
+        assert_eq!(context.request_id.len(), 32);
+    }
+}
@@ -0,0 +1,36 @@
+//! The 64-bit FNV-1a hash, behind the references of texts and the routing of keys to upstreams.
+//!
+//! Unlike `DefaultHasher`, it is the same across Rust releases and simple enough for clients to
+//! compute references themselves.
+
+/// Returns the reference of `text`: the 64-bit FNV-1a hash of its UTF-8 bytes, which clients
+/// can compute themselves.
+///
+/// FNV is not collision resistant: two texts with the same reference are indistinguishable, so
+/// in delta mode a text colliding with an acknowledged one is omitted, and the client silently
+/// reuses the vectors of the other text.
+pub fn reference(text: &str) -> u64 {
+    fnv1a(FNV_OFFSET_BASIS, text.as_bytes())
+}
+
+/// The initial value of 64-bit FNV-1a hashes.
+pub const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+
+/// Returns the 64-bit FNV-1a `hash` of some bytes, continued with `bytes`.
+pub fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reference_is_fnv1a() {
+        assert_eq!(reference(""), 0xcbf29ce484222325);
+        assert_eq!(reference("a"), 0xaf63dc4c8601ec8c);
+        assert_ne!(reference("hello"), reference("hello "));
+    }
+}
@@ -0,0 +1,287 @@
+//! The standard gRPC health service (`grpc.health.v1.Health`), reporting the liveness of the
+//! gateway process apart from its readiness to serve traffic, for orchestrator probes.
+//!
+//! Orchestrators probe without credentials, so `HealthLayer` serves the health service ahead
+//! of the authentication and client rate limiting interceptors.
+
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::stream::{self, BoxStream};
+use tonic::body::BoxBody;
+use tonic::codegen::{http, Body, StdError};
+use tonic::{Request, Response, Status};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::proto::health_proto::health_check_response::ServingStatus;
+use crate::proto::health_proto::health_server::{Health, HealthServer};
+use crate::proto::health_proto::{HealthCheckRequest, HealthCheckResponse};
+use crate::services::readiness::Readiness;
+
+/// The service name of liveness probes, also reported for the empty service name.
+pub const LIVENESS_SERVICE: &str = "liveness";
+
+/// The service name of readiness probes, also reported for the inference service.
+pub const READINESS_SERVICE: &str = "readiness";
+
+/// The name of the inference service, served when the gateway is ready.
+const INFERENCE_SERVICE: &str = "mighty_inference_server.MightyInference";
+
+/// The path prefix of the calls to the health service.
+const HEALTH_PATH_PREFIX: &str = "/grpc.health.v1.Health/";
+
+/// The interval between the checks of the status watched by `Watch` calls.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Returns the status of `service`, or `None` for services the gateway doesn't report on.
+fn status(readiness: &Readiness, service: &str) -> Option<ServingStatus> {
+    match service {
+        "" | LIVENESS_SERVICE => Some(ServingStatus::Serving),
+        READINESS_SERVICE | INFERENCE_SERVICE if readiness.is_ready() => {
+            Some(ServingStatus::Serving)
+        }
+        READINESS_SERVICE | INFERENCE_SERVICE => Some(ServingStatus::NotServing),
+        _ => None,
+    }
+}
+
+fn response(status: ServingStatus) -> HealthCheckResponse {
+    HealthCheckResponse {
+        status: status as i32,
+    }
+}
+
+/// The `HealthService` struct implements the standard gRPC health service on top of the
+/// `Readiness` of the gateway:
+///
+/// - the empty service and `liveness` are `SERVING` as long as the process serves calls;
+/// - `readiness` and `mighty_inference_server.MightyInference` are `SERVING` once the readiness
+///   checks passed, and `NOT_SERVING` before or while the upstream health check keeps failing.
+pub struct HealthService {
+    readiness: Arc<Readiness>,
+}
+
+impl HealthService {
+    pub fn new(readiness: Arc<Readiness>) -> Self {
+        Self { readiness }
+    }
+}
+
+#[tonic::async_trait]
+impl Health for HealthService {
+    async fn check(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<HealthCheckResponse>, Status> {
+        let service = request.into_inner().service;
+        match status(&self.readiness, &service) {
+            Some(status) => Ok(Response::new(response(status))),
+            None => Err(Status::not_found(format!("Unknown service {:?}", service))),
+        }
+    }
+
+    type WatchStream = BoxStream<'static, Result<HealthCheckResponse, Status>>;
+
+    async fn watch(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let service = request.into_inner().service;
+        let state = (self.readiness.clone(), None);
+        let statuses = stream::unfold(state, move |(readiness, last)| {
+            let service = service.clone();
+            async move {
+                loop {
+                    let current =
+                        status(&readiness, &service).unwrap_or(ServingStatus::ServiceUnknown);
+                    if Some(current) != last {
+                        return Some((Ok(response(current)), (readiness, Some(current))));
+                    }
+                    tokio::time::sleep(WATCH_INTERVAL).await;
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(statuses)))
+    }
+}
+
+pub fn create_health_server(readiness: Arc<Readiness>) -> HealthServer<HealthService> {
+    HealthServer::new(HealthService::new(readiness))
+}
+
+/// A tower layer answering the calls to the health service itself, so the layers and
+/// interceptors below it (authentication, client rate limiting) never see probes.
+#[derive(Clone)]
+pub struct HealthLayer {
+    health: HealthServer<HealthService>,
+}
+
+impl HealthLayer {
+    pub fn new(readiness: Arc<Readiness>) -> Self {
+        Self {
+            health: create_health_server(readiness),
+        }
+    }
+}
+
+impl<S> Layer<S> for HealthLayer {
+    type Service = HealthBypass<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HealthBypass {
+            inner,
+            health: self.health.clone(),
+        }
+    }
+}
+
+/// The service produced by `HealthLayer`.
+#[derive(Clone)]
+pub struct HealthBypass<S> {
+    inner: S,
+    health: HealthServer<HealthService>,
+}
+
+impl<S, B> Service<http::Request<B>> for HealthBypass<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>>,
+    S::Future: Send + 'static,
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        if !request.uri().path().starts_with(HEALTH_PATH_PREFIX) {
+            return Box::pin(self.inner.call(request));
+        }
+        let response = self.health.call(request);
+        Box::pin(async move {
+            let response: Result<_, Infallible> = response.await;
+            Ok(response.unwrap_or_else(|never| match never {}))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::{ready, Ready};
+
+    use futures::StreamExt;
+    use tonic::Code;
+
+    use crate::config::ReadinessConfig;
+
+    use super::*;
+
+    fn check(service: &str) -> Request<HealthCheckRequest> {
+        Request::new(HealthCheckRequest {
+            service: service.to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_liveness_is_independent_of_the_upstream() {
+        let readiness = Arc::new(Readiness::new(ReadinessConfig {
+            upstream_failure_threshold: 1,
+            ..Default::default()
+        }));
+        let health = HealthService::new(readiness.clone());
+        let serving = response(ServingStatus::Serving);
+        assert_eq!(
+            health
+                .check(check(READINESS_SERVICE))
+                .await
+                .unwrap()
+                .into_inner(),
+            serving
+        );
+
+        readiness.record_upstream_health(false);
+        let not_serving = response(ServingStatus::NotServing);
+        for service in [READINESS_SERVICE, INFERENCE_SERVICE] {
+            assert_eq!(
+                health.check(check(service)).await.unwrap().into_inner(),
+                not_serving
+            );
+        }
+        for service in ["", LIVENESS_SERVICE] {
+            assert_eq!(
+                health.check(check(service)).await.unwrap().into_inner(),
+                serving
+            );
+        }
+        let status = health.check(check("other")).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        let mut watched = health
+            .watch(check(READINESS_SERVICE))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(watched.next().await.unwrap().unwrap(), not_serving);
+        readiness.record_upstream_health(true);
+        assert_eq!(watched.next().await.unwrap().unwrap(), serving);
+    }
+
+    /// Rejects every call, as the authentication interceptor does without credentials.
+    struct Unauthenticated;
+
+    impl Service<http::Request<BoxBody>> for Unauthenticated {
+        type Response = http::Response<BoxBody>;
+        type Error = Infallible;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _request: http::Request<BoxBody>) -> Self::Future {
+            ready(Ok(Status::unauthenticated("No credentials").to_http()))
+        }
+    }
+
+    fn call(path: &str) -> http::Request<BoxBody> {
+        http::Request::builder()
+            .method("POST")
+            .uri(format!("http://localhost{}", path))
+            .header("content-type", "application/grpc")
+            .body(tonic::body::empty_body())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_health_calls_bypass_the_inner_layers() {
+        let readiness = Arc::new(Readiness::new(ReadinessConfig::default()));
+        let mut service = HealthLayer::new(readiness).layer(Unauthenticated);
+        let unauthenticated = (Code::Unauthenticated as i32).to_string();
+
+        let response = service
+            .call(call("/grpc.health.v1.Health/Check"))
+            .await
+            .unwrap();
+        assert_ne!(
+            response.headers().get("grpc-status").unwrap(),
+            &unauthenticated
+        );
+
+        let response = service
+            .call(call("/mighty_inference_server.MightyInference/Embeddings"))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers().get("grpc-status").unwrap(),
+            &unauthenticated
+        );
+    }
+}
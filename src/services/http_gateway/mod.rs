@@ -0,0 +1,607 @@
+//! HTTP endpoints served next to the gRPC services, for clients better served by plain HTTP.
+
+use std::future::Future;
+use std::sync::Arc;
+
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use tonic::codegen::http::HeaderMap;
+use tonic::metadata::MetadataMap;
+use tonic::service::Interceptor;
+use tonic::transport::server::TcpConnectInfo;
+use tonic::{Code, Request, Response, Status};
+
+use crate::proto::mighty_proto::mighty_inference_server::MightyInference;
+use crate::proto::mighty_proto::{
+    Empty, Provenance, QuestionAnswerRequest, RecentlySimilarRequest, RerankRequest,
+    StreamEmbeddingsRequest, StreamEmbeddingsResponse, TextRequest,
+};
+use crate::services::auth::AuthInterceptor;
+use crate::services::client_rate_limit::{ClientRateLimitInterceptor, RETRY_AFTER_HEADER};
+use crate::services::network_acl::NetworkAclInterceptor;
+use crate::services::npz::NpzWriter;
+use crate::services::readiness::Readiness;
+use crate::services::server_proxy::MightyInferenceServerProxy;
+
+use openapi::{openapi_document, SWAGGER_UI};
+
+pub mod openapi;
+
+/// The default maximum size of a JSON request body, that of gRPC request messages.
+const DEFAULT_MAX_REQUEST_SIZE: usize = 4 * 1024 * 1024;
+
+/// The state shared by the HTTP endpoints.
+///
+/// Every RPC of the inference service is mirrored by a JSON endpoint named after it, e.g.
+/// `POST /embeddings` with a `TextRequest` body answers with an `EmbeddingsResponse`, and
+/// `GET /metadata` with a `MetadataResponse`. The endpoints are served by the same
+/// `MightyInferenceServerProxy` as the gRPC server, and so by the same client and decorators:
+/// calls are checked against the network ACL, authenticated and rate limited by the interceptors of
+/// the gRPC server, and carry a `RequestContext` built from their headers (`x-request-id`,
+/// `x-tenant-id`, `grpc-timeout`...).
+/// `StreamEmbeddings` is mirrored by `POST /stream_embeddings`, embedding a single batch.
+///
+/// Errors are answered with the closest HTTP status and a `{"error": "..."}` body. The endpoints
+/// are described by an OpenAPI document on `GET /openapi.json`, browsable on `GET /docs`.
+///
+/// Orchestrators probe the liveness of the gateway on `GET /livez` and its readiness on
+/// `GET /readyz`, answered with `503 Service Unavailable` and the pending checks when it isn't
+/// ready. Probes aren't authenticated.
+pub struct HttpGateway {
+    proxy: Arc<MightyInferenceServerProxy>,
+    network_acl: NetworkAclInterceptor,
+    auth: AuthInterceptor,
+    rate_limit: ClientRateLimitInterceptor,
+    readiness: Arc<Readiness>,
+    max_request_size: usize,
+    openapi: serde_json::Value,
+}
+
+impl HttpGateway {
+    /// Creates the gateway, sharing the state of `auth` and `rate_limit` (e.g. client budgets)
+    /// with the gRPC server they are also layered on.
+    pub fn new(
+        proxy: Arc<MightyInferenceServerProxy>,
+        auth: AuthInterceptor,
+        rate_limit: ClientRateLimitInterceptor,
+    ) -> Self {
+        Self {
+            proxy,
+            network_acl: NetworkAclInterceptor::new(&Default::default()),
+            auth,
+            rate_limit,
+            readiness: Arc::default(),
+            max_request_size: DEFAULT_MAX_REQUEST_SIZE,
+            openapi: openapi_document(),
+        }
+    }
+
+    /// Sets the network ACL the peer addresses of calls are checked against, like those of the
+    /// gRPC server; calls from every address are served otherwise.
+    pub fn with_network_acl(mut self, network_acl: NetworkAclInterceptor) -> Self {
+        self.network_acl = network_acl;
+        self
+    }
+
+    /// Sets the readiness reported by `GET /readyz`; the gateway is always ready otherwise.
+    pub fn with_readiness(mut self, readiness: Arc<Readiness>) -> Self {
+        self.readiness = readiness;
+        self
+    }
+
+    /// Sets the maximum size of a JSON request body, 4 MiB by default.
+    pub fn with_max_request_size(mut self, max_request_size: usize) -> Self {
+        self.max_request_size = max_request_size;
+        self
+    }
+
+    /// Registers the endpoints, e.g. with
+    /// `App::new().configure(|config| gateway.configure(config))`.
+    pub fn configure(self: &Arc<Self>, config: &mut web::ServiceConfig) {
+        config
+            .app_data(web::Data::from(self.clone()))
+            .app_data(web::JsonConfig::default().limit(self.max_request_size))
+            .service(web::resource("/embeddings").route(web::post().to(embeddings)))
+            .service(web::resource("/question_answering").route(web::post().to(question_answering)))
+            .service(
+                web::resource("/sentence_transformers")
+                    .route(web::post().to(sentence_transformers)),
+            )
+            .service(
+                web::resource("/sequence_classification")
+                    .route(web::post().to(sequence_classification)),
+            )
+            .service(
+                web::resource("/token_classification").route(web::post().to(token_classification)),
+            )
+            .service(web::resource("/metadata").route(web::get().to(metadata)))
+            .service(web::resource("/health_check").route(web::get().to(health_check)))
+            .service(web::resource("/healthcheck").route(web::get().to(health_check)))
+            .service(web::resource("/stream_embeddings").route(web::post().to(stream_embeddings)))
+            .service(web::resource("/recently_similar").route(web::post().to(recently_similar)))
+            .service(web::resource("/rerank").route(web::post().to(rerank)))
+            .service(web::resource("/embeddings.npz").route(web::post().to(embeddings_npz)))
+            .service(web::resource("/livez").route(web::get().to(livez)))
+            .service(web::resource("/readyz").route(web::get().to(readyz)))
+            .service(web::resource("/openapi.json").route(web::get().to(openapi_json)))
+            .service(web::resource("/docs").route(web::get().to(docs)));
+    }
+
+    /// Turns an HTTP call into a gRPC request of `message`, with the headers of the call as
+    /// metadata, checked against the network ACL, authenticated and rate limited like gRPC calls.
+    fn request<T>(&self, http: &HttpRequest, message: T) -> Result<Request<T>, Status> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in http.headers() {
+            headers.append(name.clone(), value.clone());
+        }
+        let mut request = Request::new(());
+        *request.metadata_mut() = MetadataMap::from_headers(headers);
+        request.extensions_mut().insert(TcpConnectInfo {
+            local_addr: Some(http.app_config().local_addr()),
+            remote_addr: http.peer_addr(),
+        });
+        let request = self.network_acl.clone().call(request)?;
+        let request = self.auth.clone().call(request)?;
+        let request = self.rate_limit.clone().call(request)?;
+        let (metadata, extensions, ()) = request.into_parts();
+        Ok(Request::from_parts(metadata, extensions, message))
+    }
+
+    /// Answers an HTTP call with the JSON response of the method `call` invokes on the proxy,
+    /// its metadata becoming headers.
+    async fn respond<T, R, F, Fut>(&self, http: &HttpRequest, message: T, call: F) -> HttpResponse
+    where
+        F: FnOnce(Arc<MightyInferenceServerProxy>, Request<T>) -> Fut,
+        Fut: Future<Output = Result<Response<R>, Status>>,
+        R: Serialize,
+    {
+        let response = match self.request(http, message) {
+            Ok(request) => call(self.proxy.clone(), request).await,
+            Err(status) => Err(status),
+        };
+        match response {
+            Ok(response) => {
+                let mut builder = HttpResponse::Ok();
+                for (name, value) in &response.metadata().clone().into_headers() {
+                    builder.append_header((name.clone(), value.clone()));
+                }
+                builder.json(response.get_ref())
+            }
+            Err(status) => error_response(&status),
+        }
+    }
+}
+
+async fn embeddings(
+    gateway: web::Data<HttpGateway>,
+    http: HttpRequest,
+    body: web::Json<TextRequest>,
+) -> HttpResponse {
+    gateway
+        .respond(&http, body.into_inner(), |proxy, request| async move {
+            proxy.embeddings(request).await
+        })
+        .await
+}
+
+async fn question_answering(
+    gateway: web::Data<HttpGateway>,
+    http: HttpRequest,
+    body: web::Json<QuestionAnswerRequest>,
+) -> HttpResponse {
+    gateway
+        .respond(&http, body.into_inner(), |proxy, request| async move {
+            proxy.question_answering(request).await
+        })
+        .await
+}
+
+async fn sentence_transformers(
+    gateway: web::Data<HttpGateway>,
+    http: HttpRequest,
+    body: web::Json<TextRequest>,
+) -> HttpResponse {
+    gateway
+        .respond(&http, body.into_inner(), |proxy, request| async move {
+            proxy.sentence_transformers(request).await
+        })
+        .await
+}
+
+async fn sequence_classification(
+    gateway: web::Data<HttpGateway>,
+    http: HttpRequest,
+    body: web::Json<TextRequest>,
+) -> HttpResponse {
+    gateway
+        .respond(&http, body.into_inner(), |proxy, request| async move {
+            proxy.sequence_classification(request).await
+        })
+        .await
+}
+
+async fn token_classification(
+    gateway: web::Data<HttpGateway>,
+    http: HttpRequest,
+    body: web::Json<TextRequest>,
+) -> HttpResponse {
+    gateway
+        .respond(&http, body.into_inner(), |proxy, request| async move {
+            proxy.token_classification(request).await
+        })
+        .await
+}
+
+async fn metadata(gateway: web::Data<HttpGateway>, http: HttpRequest) -> HttpResponse {
+    gateway
+        .respond(&http, Empty {}, |proxy, request| async move {
+            proxy.metadata(request).await
+        })
+        .await
+}
+
+async fn health_check(gateway: web::Data<HttpGateway>, http: HttpRequest) -> HttpResponse {
+    gateway
+        .respond(&http, Empty {}, |proxy, request| async move {
+            proxy.health_check(request).await
+        })
+        .await
+}
+
+async fn stream_embeddings(
+    gateway: web::Data<HttpGateway>,
+    http: HttpRequest,
+    body: web::Json<StreamEmbeddingsRequest>,
+) -> HttpResponse {
+    gateway
+        .respond(&http, body.into_inner(), |proxy, request| async move {
+            proxy.embed_batch(request).await
+        })
+        .await
+}
+
+async fn recently_similar(
+    gateway: web::Data<HttpGateway>,
+    http: HttpRequest,
+    body: web::Json<RecentlySimilarRequest>,
+) -> HttpResponse {
+    gateway
+        .respond(&http, body.into_inner(), |proxy, request| async move {
+            proxy.recently_similar(request).await
+        })
+        .await
+}
+
+async fn rerank(
+    gateway: web::Data<HttpGateway>,
+    http: HttpRequest,
+    body: web::Json<RerankRequest>,
+) -> HttpResponse {
+    gateway
+        .respond(&http, body.into_inner(), |proxy, request| async move {
+            proxy.rerank(request).await
+        })
+        .await
+}
+
+/// The body of probe responses.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Probe {
+    /// `SERVING` or `NOT_SERVING`, as reported by the gRPC health service.
+    pub status: String,
+    /// The readiness checks that haven't passed yet.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pending: Vec<String>,
+}
+
+async fn livez() -> HttpResponse {
+    HttpResponse::Ok().json(Probe {
+        status: "SERVING".to_string(),
+        pending: Vec::new(),
+    })
+}
+
+async fn readyz(gateway: web::Data<HttpGateway>) -> HttpResponse {
+    let pending = gateway.readiness.pending();
+    if pending.is_empty() {
+        return livez().await;
+    }
+    HttpResponse::ServiceUnavailable().json(Probe {
+        status: "NOT_SERVING".to_string(),
+        pending,
+    })
+}
+
+async fn openapi_json(gateway: web::Data<HttpGateway>) -> HttpResponse {
+    HttpResponse::Ok().json(&gateway.openapi)
+}
+
+async fn docs() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(SWAGGER_UI)
+}
+
+/// The body of a batch embeddings request.
+#[derive(Debug, Deserialize)]
+pub struct EmbeddingsBatch {
+    pub texts: Vec<String>,
+    /// Whether the preprocessing applied to each text is reported.
+    #[serde(default)]
+    pub provenance: bool,
+}
+
+/// `POST /embeddings.npz` embeds a batch of texts, preprocessed like `StreamEmbeddings` batches,
+/// and returns the results as an `.npz` archive with the arrays:
+/// - `embeddings`: `float32` of shape `(rows, dim)`, every vector of every text;
+/// - `text_index`: `int64` of shape `(rows,)`, the index of the text of each vector;
+/// - `reference`: `uint64` of shape `(texts,)`, the reference of each text;
+/// - `deduplicated`, `cache_hit`, `normalized` and `truncated`: `bool` of shape `(texts,)`,
+///   when `provenance` is requested.
+async fn embeddings_npz(
+    gateway: web::Data<HttpGateway>,
+    http: HttpRequest,
+    batch: web::Json<EmbeddingsBatch>,
+) -> HttpResponse {
+    let EmbeddingsBatch { texts, provenance } = batch.into_inner();
+    let request = StreamEmbeddingsRequest {
+        texts,
+        provenance,
+        ..Default::default()
+    };
+    let response = match gateway.request(&http, request) {
+        Ok(request) => gateway.proxy.embed_batch(request).await,
+        Err(status) => Err(status),
+    };
+    let archive = response.and_then(|response| to_npz(response.get_ref(), provenance));
+    match archive {
+        Ok(archive) => HttpResponse::Ok()
+            .content_type("application/zip")
+            .insert_header(ContentDisposition {
+                disposition: DispositionType::Attachment,
+                parameters: vec![DispositionParam::Filename("embeddings.npz".to_string())],
+            })
+            .body(archive),
+        Err(status) => error_response(&status),
+    }
+}
+
+fn to_npz(response: &StreamEmbeddingsResponse, provenance: bool) -> Result<Vec<u8>, Status> {
+    let results = &response.results;
+    let dim = results
+        .iter()
+        .flat_map(|result| &result.embeddings)
+        .map(|embedding| embedding.values.len())
+        .next()
+        .unwrap_or(0);
+    let mut values = Vec::new();
+    let mut text_index = Vec::new();
+    for (index, result) in results.iter().enumerate() {
+        for embedding in &result.embeddings {
+            if embedding.values.len() != dim {
+                return Err(Status::internal(format!(
+                    "Embeddings of different dimensions ({} and {}) can't form an array",
+                    dim,
+                    embedding.values.len()
+                )));
+            }
+            values.extend_from_slice(&embedding.values);
+            text_index.push(index as i64);
+        }
+    }
+    let references: Vec<u64> = results.iter().map(|result| result.reference).collect();
+
+    let mut writer = NpzWriter::new();
+    writer
+        .add("embeddings", &[text_index.len(), dim], &values)
+        .add("text_index", &[text_index.len()], &text_index)
+        .add("reference", &[references.len()], &references);
+    if provenance {
+        let provenances: Vec<Provenance> = results
+            .iter()
+            .map(|result| result.provenance.clone().unwrap_or_default())
+            .collect();
+        let field = |get: fn(&Provenance) -> bool| provenances.iter().map(get).collect::<Vec<_>>();
+        let texts = [provenances.len()];
+        writer
+            .add("deduplicated", &texts, &field(|p| p.deduplicated))
+            .add("cache_hit", &texts, &field(|p| p.cache_hit))
+            .add("normalized", &texts, &field(|p| p.normalized))
+            .add("truncated", &texts, &field(|p| p.truncated));
+    }
+    writer.finish().map_err(Status::resource_exhausted)
+}
+
+/// Answers a failed call with the closest HTTP status, passing on when to retry rate limited
+/// calls.
+fn error_response(status: &Status) -> HttpResponse {
+    let retry_after = status.metadata().get(RETRY_AFTER_HEADER);
+    let code = match (status.code(), retry_after) {
+        (Code::ResourceExhausted, Some(_)) => StatusCode::TOO_MANY_REQUESTS,
+        (code, _) => http_status(code),
+    };
+    let mut builder = HttpResponse::build(code);
+    if let Some(retry_after) = retry_after.and_then(|value| value.to_str().ok()) {
+        builder.insert_header((RETRY_AFTER_HEADER, retry_after));
+    }
+    builder.json(serde_json::json!({ "error": status.message() }))
+}
+
+/// Maps a gRPC status code to the closest HTTP status.
+fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => {
+            StatusCode::BAD_REQUEST
+        }
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::ResourceExhausted => StatusCode::PAYLOAD_TOO_LARGE,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        Code::Cancelled => StatusCode::from_u16(499).unwrap_or(StatusCode::BAD_REQUEST),
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{call_and_read_body_json, call_service, init_service, TestRequest};
+    use actix_web::App;
+    use config::{Config, File, FileFormat};
+
+    use crate::config::{AppSettings, IpRange, NetworkAclConfig, ReadinessConfig};
+    use crate::proto::mighty_proto::{Embedding, EmbeddingsResponse, TextEmbeddings};
+    use crate::services::auth::API_KEY_HEADER;
+    use crate::services::clients::mock::MockMightyClient;
+    use crate::services::server_proxy::create_mighty_inference_proxy;
+
+    use super::*;
+
+    fn gateway() -> Arc<HttpGateway> {
+        let settings: AppSettings = Config::builder()
+            .add_source(File::from_str(
+                r#"
+                grpc_server = { address = "127.0.0.1", port = 50051 }
+                logging = { level = "info" }
+
+                [api_keys]
+                enabled = true
+                keys = [{ name = "search-indexer", key = "0123456789abcdef" }]
+                "#,
+                FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        let proxy = create_mighty_inference_proxy(Box::new(MockMightyClient::new()), &settings);
+        Arc::new(HttpGateway::new(
+            Arc::new(proxy),
+            AuthInterceptor::new(&settings).unwrap(),
+            ClientRateLimitInterceptor::new(&settings.client_rate_limit),
+        ))
+    }
+
+    #[actix_web::test]
+    async fn test_rpcs_are_mirrored_as_authenticated_json_endpoints() {
+        let gateway = gateway();
+        let app = init_service(App::new().configure(|config| gateway.configure(config))).await;
+
+        let request = TestRequest::post()
+            .uri("/embeddings")
+            .insert_header((API_KEY_HEADER, "0123456789abcdef"))
+            .set_json(serde_json::json!({ "text": "hello" }))
+            .to_request();
+        let response: EmbeddingsResponse = call_and_read_body_json(&app, request).await;
+        assert_eq!(response.text, "hello");
+        assert_eq!(response.embeddings[0].values.len(), 4);
+
+        let request = TestRequest::get().uri("/metadata").to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn test_calls_are_checked_against_the_network_acl() {
+        let acl = NetworkAclConfig {
+            allow: vec![IpRange::try_from("10.0.0.0/8".to_string()).unwrap()],
+            deny: Vec::new(),
+        };
+        let gateway = Arc::into_inner(gateway()).unwrap();
+        let gateway = Arc::new(gateway.with_network_acl(NetworkAclInterceptor::new(&acl)));
+        let app = init_service(App::new().configure(|config| gateway.configure(config))).await;
+        let from = |peer: &str| {
+            TestRequest::post()
+                .uri("/embeddings")
+                .peer_addr(peer.parse().unwrap())
+                .insert_header((API_KEY_HEADER, "0123456789abcdef"))
+                .set_json(serde_json::json!({ "text": "hello" }))
+                .to_request()
+        };
+
+        let response = call_service(&app, from("192.168.0.1:40000")).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = call_service(&app, from("10.0.0.1:40000")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn test_documented_endpoints_are_served() {
+        let gateway = gateway();
+        let app = init_service(App::new().configure(|config| gateway.configure(config))).await;
+        let request = TestRequest::get().uri("/openapi.json").to_request();
+        let document: serde_json::Value = call_and_read_body_json(&app, request).await;
+
+        let paths = document["paths"].as_object().unwrap();
+        assert_eq!(paths.len(), openapi::ENDPOINTS.len() + 3);
+        for (path, operations) in paths {
+            for http_method in operations.as_object().unwrap().keys() {
+                let request = TestRequest::default()
+                    .method(http_method.to_uppercase().parse().unwrap())
+                    .uri(path)
+                    .to_request();
+                let status = call_service(&app, request).await.status();
+                assert_ne!(status, StatusCode::NOT_FOUND, "{} {}", http_method, path);
+                assert_ne!(
+                    status,
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    "{} {}",
+                    http_method,
+                    path
+                );
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_probes_report_liveness_and_readiness() {
+        let readiness = Arc::new(Readiness::new(ReadinessConfig {
+            upstream_failure_threshold: 1,
+            ..Default::default()
+        }));
+        let gateway = Arc::into_inner(gateway()).unwrap();
+        let gateway = Arc::new(gateway.with_readiness(readiness.clone()));
+        let app = init_service(App::new().configure(|config| gateway.configure(config))).await;
+        let probe = |path| TestRequest::get().uri(path).to_request();
+
+        assert_eq!(
+            call_service(&app, probe("/readyz")).await.status(),
+            StatusCode::OK
+        );
+        readiness.record_upstream_health(false);
+        let response = call_service(&app, probe("/readyz")).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: Probe = actix_web::test::read_body_json(response).await;
+        assert_eq!(body.status, "NOT_SERVING");
+        assert_eq!(
+            body.pending,
+            ["upstream health check failed 1 consecutive times"]
+        );
+        assert_eq!(
+            call_service(&app, probe("/livez")).await.status(),
+            StatusCode::OK
+        );
+    }
+
+    #[test]
+    fn test_to_npz_rejects_mixed_dimensions() {
+        let result = |values: Vec<f32>| TextEmbeddings {
+            embeddings: vec![Embedding { values }],
+            ..Default::default()
+        };
+        let response = StreamEmbeddingsResponse {
+            results: vec![result(vec![1.0, 2.0]), result(vec![3.0, 4.0])],
+        };
+        assert!(to_npz(&response, true).is_ok());
+
+        let response = StreamEmbeddingsResponse {
+            results: vec![result(vec![1.0, 2.0]), result(vec![3.0])],
+        };
+        assert_eq!(to_npz(&response, false).unwrap_err().code(), Code::Internal);
+    }
+}
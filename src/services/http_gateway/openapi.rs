@@ -0,0 +1,313 @@
+//! The OpenAPI 3 document of the HTTP gateway, served on `GET /openapi.json` with a Swagger UI on
+//! `GET /docs`, so HTTP consumers can discover and try the API without the proto file.
+//!
+//! The schemas of the JSON endpoints are derived from `FILE_DESCRIPTOR_SET`, the messages they
+//! take and return being those of the RPCs they mirror, so the document can't drift from them.
+
+use prost::Message;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{DescriptorProto, FieldDescriptorProto, FileDescriptorSet};
+use serde_json::{json, Map, Value};
+
+use crate::proto::FILE_DESCRIPTOR_SET;
+use crate::services::auth::API_KEY_HEADER;
+
+/// The service whose RPCs the gateway mirrors.
+const SERVICE: &str = "MightyInference";
+
+/// The JSON endpoints of the gateway: path, HTTP method and the RPC mirrored.
+pub(super) const ENDPOINTS: [(&str, &str, &str); 10] = [
+    ("/embeddings", "post", "Embeddings"),
+    ("/question_answering", "post", "QuestionAnswering"),
+    ("/sentence_transformers", "post", "SentenceTransformers"),
+    ("/sequence_classification", "post", "SequenceClassification"),
+    ("/token_classification", "post", "TokenClassification"),
+    ("/metadata", "get", "Metadata"),
+    ("/health_check", "get", "HealthCheck"),
+    ("/stream_embeddings", "post", "StreamEmbeddings"),
+    ("/recently_similar", "post", "RecentlySimilar"),
+    ("/rerank", "post", "Rerank"),
+];
+
+/// The Swagger UI page, loading the document and the UI assets from a CDN.
+pub(super) const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>Mighty Inference gateway</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
+
+/// Returns the reference to the schema of `type_name`, a fully qualified message name such as
+/// `.mighty_inference_server.TextRequest`.
+fn reference(type_name: &str) -> Value {
+    let name = type_name.rsplit('.').next().unwrap_or(type_name);
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn scalar(field: &FieldDescriptorProto) -> Value {
+    match field.r#type() {
+        Type::Double => json!({ "type": "number", "format": "double" }),
+        Type::Float => json!({ "type": "number", "format": "float" }),
+        Type::Int32 | Type::Sint32 | Type::Sfixed32 => {
+            json!({ "type": "integer", "format": "int32" })
+        }
+        Type::Uint32 | Type::Fixed32 => {
+            json!({ "type": "integer", "format": "int32", "minimum": 0 })
+        }
+        Type::Int64 | Type::Sint64 | Type::Sfixed64 => {
+            json!({ "type": "integer", "format": "int64" })
+        }
+        Type::Uint64 | Type::Fixed64 => {
+            json!({ "type": "integer", "format": "int64", "minimum": 0 })
+        }
+        Type::Bool => json!({ "type": "boolean" }),
+        Type::String => json!({ "type": "string" }),
+        // Serialized by serde as arrays of byte values
+        Type::Bytes => {
+            json!({ "type": "array", "items": { "type": "integer", "format": "int32" } })
+        }
+        Type::Message | Type::Enum | Type::Group => reference(field.type_name()),
+    }
+}
+
+/// Returns the schema of `field` of `message`, maps being objects rather than arrays of entries.
+fn field_schema(message: &DescriptorProto, field: &FieldDescriptorProto) -> Value {
+    if field.label() != Label::Repeated {
+        return scalar(field);
+    }
+    let entry_name = field.type_name().rsplit('.').next().unwrap_or_default();
+    let map_entry = message.nested_type.iter().find(|nested| {
+        nested.name() == entry_name
+            && nested.options.as_ref().and_then(|o| o.map_entry) == Some(true)
+    });
+    match map_entry.and_then(|entry| entry.field.iter().find(|field| field.number() == 2)) {
+        Some(value) => json!({ "type": "object", "additionalProperties": scalar(value) }),
+        None => json!({ "type": "array", "items": scalar(field) }),
+    }
+}
+
+/// Adds the schemas of `message` and of its nested messages, but map entries, to `schemas`.
+fn collect_schemas(message: &DescriptorProto, schemas: &mut Map<String, Value>) {
+    let properties: Map<String, Value> = message
+        .field
+        .iter()
+        .map(|field| (field.name().to_string(), field_schema(message, field)))
+        .collect();
+    schemas.insert(
+        message.name().to_string(),
+        json!({ "type": "object", "properties": properties }),
+    );
+    for nested in &message.nested_type {
+        if nested.options.as_ref().and_then(|o| o.map_entry) != Some(true) {
+            collect_schemas(nested, schemas);
+        }
+    }
+}
+
+fn json_content(schema: Value) -> Value {
+    json!({ "application/json": { "schema": schema } })
+}
+
+fn error_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": json_content(json!({ "$ref": "#/components/schemas/Error" })),
+    })
+}
+
+/// The responses of every endpoint, but its successful one.
+fn error_responses() -> Map<String, Value> {
+    [
+        ("400", "The request is invalid."),
+        ("401", "The request lacks valid credentials."),
+        (
+            "429",
+            "The client exceeded its rate limit; retry after `Retry-After` seconds.",
+        ),
+        ("503", "The upstream is unavailable."),
+        (
+            "default",
+            "The call failed, with the closest HTTP status to its gRPC status.",
+        ),
+    ]
+    .into_iter()
+    .map(|(code, description)| (code.to_string(), error_response(description)))
+    .collect()
+}
+
+fn operation(rpc: &str, request: Option<&str>, response: (&str, Value)) -> Value {
+    let mut responses = error_responses();
+    let (description, content) = response;
+    responses.insert(
+        "200".to_string(),
+        json!({ "description": description, "content": content }),
+    );
+    let mut operation = json!({
+        "operationId": rpc,
+        "summary": format!("Mirrors the `{}` RPC.", rpc),
+        "responses": responses,
+    });
+    if let Some(request) = request {
+        operation["requestBody"] =
+            json!({ "required": true, "content": json_content(reference(request)) });
+    }
+    operation
+}
+
+/// Builds the OpenAPI document of the gateway from `FILE_DESCRIPTOR_SET`.
+pub fn openapi_document() -> Value {
+    let descriptor_set = FileDescriptorSet::decode(FILE_DESCRIPTOR_SET).unwrap_or_default();
+    let mut schemas = Map::new();
+    let mut paths = Map::new();
+    for file in &descriptor_set.file {
+        for message in &file.message_type {
+            collect_schemas(message, &mut schemas);
+        }
+        let methods = file
+            .service
+            .iter()
+            .filter(|service| service.name() == SERVICE)
+            .flat_map(|service| &service.method);
+        for method in methods {
+            let Some((path, http_method, rpc)) =
+                ENDPOINTS.iter().find(|(_, _, rpc)| *rpc == method.name())
+            else {
+                continue;
+            };
+            let request = (*http_method == "post").then(|| method.input_type());
+            let response = (
+                "The response of the RPC.",
+                json_content(reference(method.output_type())),
+            );
+            let mut operation = operation(rpc, request, response);
+            if method.client_streaming() {
+                operation["description"] =
+                    json!("Embeds a single batch, like one message of the stream.");
+            }
+            paths.insert(path.to_string(), json!({ *http_method: operation }));
+        }
+    }
+    schemas.insert(
+        "Error".to_string(),
+        json!({ "type": "object", "properties": { "error": { "type": "string" } } }),
+    );
+    schemas.insert(
+        "EmbeddingsBatch".to_string(),
+        json!({
+            "type": "object",
+            "required": ["texts"],
+            "properties": {
+                "texts": { "type": "array", "items": { "type": "string" } },
+                "provenance": { "type": "boolean" },
+            },
+        }),
+    );
+    let archive =
+        json!({ "application/zip": { "schema": { "type": "string", "format": "binary" } } });
+    let mut npz = operation(
+        "EmbeddingsNpz",
+        Some("EmbeddingsBatch"),
+        ("The embeddings as a NumPy `.npz` archive.", archive),
+    );
+    npz["summary"] = json!("Embeds a batch of texts into a NumPy `.npz` archive.");
+    paths.insert("/embeddings.npz".to_string(), json!({ "post": npz }));
+
+    schemas.insert(
+        "Probe".to_string(),
+        json!({
+            "type": "object",
+            "properties": {
+                "status": { "type": "string", "enum": ["SERVING", "NOT_SERVING"] },
+                "pending": { "type": "array", "items": { "type": "string" } },
+            },
+        }),
+    );
+    let probes = [
+        (
+            "/livez",
+            "Liveness",
+            "Reports whether the gateway process is up.",
+        ),
+        (
+            "/readyz",
+            "Readiness",
+            "Reports whether the gateway is ready to serve traffic.",
+        ),
+    ];
+    for (path, operation_id, summary) in probes {
+        let probe = json_content(reference("Probe"));
+        let mut responses = Map::new();
+        responses.insert(
+            "200".to_string(),
+            json!({ "description": "The gateway is serving.", "content": probe }),
+        );
+        if path == "/readyz" {
+            responses.insert(
+                "503".to_string(),
+                json!({ "description": "The gateway isn't ready.", "content": probe }),
+            );
+        }
+        let operation = json!({
+            "operationId": operation_id,
+            "summary": summary,
+            "responses": responses,
+            "security": [],
+        });
+        paths.insert(path.to_string(), json!({ "get": operation }));
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Mighty Inference gateway",
+            "description": "JSON endpoints mirroring the RPCs of the MightyInference gRPC service.",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "securitySchemes": {
+                "apiKey": { "type": "apiKey", "in": "header", "name": API_KEY_HEADER },
+                "bearer": { "type": "http", "scheme": "bearer", "bearerFormat": "JWT" },
+            },
+        },
+        "security": [{}, { "apiKey": [] }, { "bearer": [] }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document_covers_every_endpoint() {
+        let document = openapi_document();
+        for (path, http_method, _) in ENDPOINTS {
+            assert!(
+                document["paths"][path][http_method].is_object(),
+                "{} {} is undocumented",
+                http_method,
+                path
+            );
+        }
+        let schemas = &document["components"]["schemas"];
+        assert_eq!(
+            schemas["MetadataResponse"]["properties"]["metadata"],
+            json!({ "type": "object", "additionalProperties": { "type": "string" } })
+        );
+        assert_eq!(
+            schemas["EmbeddingsResponse"]["properties"]["embeddings"]["items"],
+            json!({ "$ref": "#/components/schemas/Embedding" })
+        );
+    }
+}
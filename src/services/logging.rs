@@ -0,0 +1,167 @@
+//! The logger of the server binaries, configured by `[logging]`: a level per target, text or
+//! JSON lines, written to the standard error or to a log file rotated by size.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use env_logger::{Builder, Target, WriteStyle};
+use log::LevelFilter;
+
+use crate::config::{LogFormat, LoggingConfig};
+use crate::services::access_log::{format_json, ACCESS_LOG_TARGET};
+use crate::services::reload::LoggerFactory;
+
+/// Returns the factory of the loggers configured by `config`, each built at the level it's
+/// given, e.g. that of a reloaded configuration. Target levels that can't be parsed are ignored;
+/// they are reported by `validate`.
+///
+/// # Errors
+///
+/// Returns an error if the log file can't be opened.
+pub fn logger_factory(config: &LoggingConfig) -> io::Result<LoggerFactory> {
+    let file = match &config.file {
+        Some(path) => Some(LogFile::open(path, config.max_file_size, config.max_files)?),
+        None => None,
+    };
+    let targets = target_levels(&config.targets);
+    let (access_log, format) = (config.access_log, config.format);
+    Ok(Arc::new(move |level: &str| {
+        let mut builder = Builder::new();
+        builder.parse_filters(level);
+        for (target, level) in &targets {
+            builder.filter(Some(target), *level);
+        }
+        if access_log {
+            builder.filter(Some(ACCESS_LOG_TARGET), LevelFilter::Info);
+        }
+        if format == LogFormat::Json {
+            builder.format(format_json);
+        }
+        if let Some(file) = &file {
+            builder
+                .target(Target::Pipe(Box::new(file.clone())))
+                .write_style(WriteStyle::Never);
+        }
+        builder.build()
+    }))
+}
+
+/// Returns the levels of `targets` that can be parsed.
+fn target_levels(targets: &HashMap<String, String>) -> Vec<(String, LevelFilter)> {
+    targets
+        .iter()
+        .filter_map(|(target, level)| Some((target.clone(), level.parse().ok()?)))
+        .collect()
+}
+
+/// The `LogFile` struct is a log file rotated once it would grow past `max_size`: the file is
+/// renamed with the suffix `.1`, previously rotated files being shifted to `.2`, `.3`... up to
+/// `max_files`, and writing resumes in a new file. Its clones share the file.
+#[derive(Debug, Clone)]
+pub struct LogFile {
+    state: Arc<Mutex<LogFileState>>,
+}
+
+#[derive(Debug)]
+struct LogFileState {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl LogFile {
+    /// Opens the log file at `path`, appending to it if it exists.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be opened.
+    pub fn open(path: &Path, max_size: u64, max_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        let state = LogFileState {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_size,
+            max_files,
+        };
+        Ok(Self {
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+}
+
+/// Returns the path of the `index`th rotated file of `path`, e.g. `mighty-grpc.log.1`.
+fn rotated(path: &Path, index: usize) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+impl LogFileState {
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files > 0 {
+            for index in (1..self.max_files).rev() {
+                let from = rotated(&self.path, index);
+                if from.exists() {
+                    fs::rename(from, rotated(&self.path, index + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated(&self.path, 1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.size > 0 && state.size + buf.len() as u64 > state.max_size {
+            state.rotate()?;
+        }
+        state.file.write_all(buf)?;
+        state.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.state.lock().unwrap().file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_file_is_rotated() {
+        let dir = std::env::temp_dir().join(format!("mighty-grpc-logs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("mighty-grpc.log");
+
+        let mut file = LogFile::open(&path, 10, 2).unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(fs::read_to_string(rotated(&path, 1)).unwrap(), "third\n");
+        assert_eq!(fs::read_to_string(rotated(&path, 2)).unwrap(), "second\n");
+        assert!(!rotated(&path, 3).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
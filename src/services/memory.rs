@@ -0,0 +1,102 @@
+//! Memory metrics of the process, sampled whenever metrics are rendered, so the gateway can be
+//! checked against the memory limit of its container and leaks told apart from growing caches.
+//!
+//! The resident set size is read from `/proc/self/status` on Linux. With the `jemalloc` feature,
+//! the server binaries allocate with jemalloc and its statistics are exported too: `allocated`
+//! bytes are held by the gateway, `resident` bytes by the allocator, the difference being
+//! fragmentation and memory not yet returned to the system. The estimated memory of in-process
+//! caches is exported by the caches themselves (see `CACHE_MEMORY_METRIC`).
+
+use crate::services::metrics::Metrics;
+
+/// Gauge of the resident set size of the process, in bytes.
+const RESIDENT_MEMORY_METRIC: &str = "mighty_process_resident_memory_bytes";
+
+/// Gauge of the peak resident set size of the process, in bytes.
+const PEAK_RESIDENT_MEMORY_METRIC: &str = "mighty_process_peak_resident_memory_bytes";
+
+/// Gauge of the statistics of the jemalloc allocator, in bytes, labelled by `stat`
+/// (`allocated`, `active`, `resident`, `mapped` or `retained`).
+#[cfg(feature = "jemalloc")]
+const ALLOCATOR_MEMORY_METRIC: &str = "mighty_allocator_memory_bytes";
+
+/// Samples the memory of the process into `metrics`. Statistics that can't be read on this
+/// platform or build are left out.
+pub fn record_memory_metrics(metrics: &Metrics) {
+    if let Ok(status) = std::fs::read_to_string("/proc/self/status") {
+        let sizes = [
+            (RESIDENT_MEMORY_METRIC, "VmRSS"),
+            (PEAK_RESIDENT_MEMORY_METRIC, "VmHWM"),
+        ];
+        for (metric, field) in sizes {
+            if let Some(size) = status_size(&status, field) {
+                metrics.gauge(metric, &[]).set(size as i64);
+            }
+        }
+    }
+    #[cfg(feature = "jemalloc")]
+    record_allocator_metrics(metrics);
+}
+
+/// Returns the size of `field` in the contents of `/proc/self/status`, in bytes, e.g. from
+/// `VmRSS:     20480 kB`.
+fn status_size(status: &str, field: &str) -> Option<u64> {
+    let line = status
+        .lines()
+        .find_map(|line| line.strip_prefix(field)?.strip_prefix(':'))?;
+    let kib = line.trim().strip_suffix("kB")?.trim().parse::<u64>().ok()?;
+    Some(kib * 1024)
+}
+
+#[cfg(feature = "jemalloc")]
+fn record_allocator_metrics(metrics: &Metrics) {
+    use log::debug;
+    use tikv_jemalloc_ctl::{epoch, stats};
+
+    // The statistics are cached by jemalloc until the epoch advances
+    if let Err(e) = epoch::advance() {
+        debug!("Can't refresh the jemalloc statistics: {}", e);
+        return;
+    }
+    let stats = [
+        ("allocated", stats::allocated::read()),
+        ("active", stats::active::read()),
+        ("resident", stats::resident::read()),
+        ("mapped", stats::mapped::read()),
+        ("retained", stats::retained::read()),
+    ];
+    for (stat, value) in stats {
+        match value {
+            Ok(value) => metrics
+                .gauge(ALLOCATOR_MEMORY_METRIC, &[("stat", stat)])
+                .set(value as i64),
+            Err(e) => debug!("Can't read the jemalloc {} statistic: {}", stat, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_sizes_are_parsed() {
+        let status = "Name:\tgrpc\nVmHWM:\t   40960 kB\nVmRSS:\t   20480 kB\nThreads:\t8\n";
+        assert_eq!(status_size(status, "VmRSS"), Some(20480 * 1024));
+        assert_eq!(status_size(status, "VmHWM"), Some(40960 * 1024));
+        assert_eq!(status_size(status, "Threads"), None);
+        assert_eq!(status_size(status, "VmSwap"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_resident_memory_is_recorded() {
+        let metrics = Metrics::default();
+        record_memory_metrics(&metrics);
+        assert!(metrics.gauge(RESIDENT_MEMORY_METRIC, &[]).get() > 0);
+        assert!(
+            metrics.gauge(PEAK_RESIDENT_MEMORY_METRIC, &[]).get()
+                >= metrics.gauge(RESIDENT_MEMORY_METRIC, &[]).get()
+        );
+    }
+}
@@ -0,0 +1,298 @@
+//! A minimal in-process metrics registry.
+//!
+//! Metrics (counters, gauges and histograms) are identified by a name and a set of label pairs and
+//! live for the lifetime of the process. The registry can be rendered in the Prometheus text
+//! exposition format.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+
+/// The default histogram buckets for durations in seconds, from 5ms to 10s.
+pub const DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// A monotonically increasing counter.
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn increment(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value going up and down, e.g. a number of bytes in use.
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    /// Adds `delta`, negative to decrease the gauge.
+    pub fn add(&self, delta: i64) {
+        self.0.fetch_add(delta, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A distribution of observed values, counted in buckets of fixed upper bounds.
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    /// The number of observations per bucket, the last one counting those above every bound.
+    buckets: Vec<AtomicU64>,
+    sum: Mutex<f64>,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: Mutex::new(0.0),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let bucket = self.bounds.partition_point(|bound| *bound < value);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        *self.sum.lock().unwrap() += value;
+    }
+
+    /// Returns the number of observations.
+    pub fn count(&self) -> u64 {
+        self.buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Returns the sum of the observed values.
+    pub fn sum(&self) -> f64 {
+        *self.sum.lock().unwrap()
+    }
+}
+
+/// Identifies a metric by name and sorted label pairs.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct MetricKey {
+    name: &'static str,
+    labels: Vec<(&'static str, String)>,
+}
+
+impl MetricKey {
+    fn new(name: &'static str, labels: &[(&'static str, &str)]) -> Self {
+        let mut labels = labels
+            .iter()
+            .map(|(key, value)| (*key, value.to_string()))
+            .collect::<Vec<_>>();
+        labels.sort();
+        Self { name, labels }
+    }
+}
+
+/// The registry holding every metric recorded by the process.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    counters: RwLock<BTreeMap<MetricKey, Arc<Counter>>>,
+    gauges: RwLock<BTreeMap<MetricKey, Arc<Gauge>>>,
+    histograms: RwLock<BTreeMap<MetricKey, Arc<Histogram>>>,
+}
+
+impl Metrics {
+    /// Returns the process-wide registry.
+    pub fn global() -> &'static Metrics {
+        static METRICS: OnceLock<Metrics> = OnceLock::new();
+        METRICS.get_or_init(Metrics::default)
+    }
+
+    /// Returns the counter identified by `name` and `labels`, registering it on first use.
+    pub fn counter(&self, name: &'static str, labels: &[(&'static str, &str)]) -> Arc<Counter> {
+        let key = MetricKey::new(name, labels);
+        if let Some(counter) = self.counters.read().unwrap().get(&key) {
+            return counter.clone();
+        }
+        self.counters
+            .write()
+            .unwrap()
+            .entry(key)
+            .or_default()
+            .clone()
+    }
+
+    /// Returns the gauge identified by `name` and `labels`, registering it at 0 on first use.
+    pub fn gauge(&self, name: &'static str, labels: &[(&'static str, &str)]) -> Arc<Gauge> {
+        let key = MetricKey::new(name, labels);
+        if let Some(gauge) = self.gauges.read().unwrap().get(&key) {
+            return gauge.clone();
+        }
+        self.gauges
+            .write()
+            .unwrap()
+            .entry(key)
+            .or_default()
+            .clone()
+    }
+
+    /// Returns the histogram identified by `name` and `labels`, registering it with the given
+    /// bucket bounds on first use.
+    pub fn histogram(
+        &self,
+        name: &'static str,
+        labels: &[(&'static str, &str)],
+        bounds: &'static [f64],
+    ) -> Arc<Histogram> {
+        let key = MetricKey::new(name, labels);
+        if let Some(histogram) = self.histograms.read().unwrap().get(&key) {
+            return histogram.clone();
+        }
+        self.histograms
+            .write()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| Arc::new(Histogram::new(bounds)))
+            .clone()
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut last_name = "";
+        for (key, counter) in self.counters.read().unwrap().iter() {
+            if key.name != last_name {
+                let _ = writeln!(out, "# TYPE {} counter", key.name);
+                last_name = key.name;
+            }
+            let _ = writeln!(
+                out,
+                "{}{} {}",
+                key.name,
+                render_labels(&key.labels),
+                counter.get()
+            );
+        }
+        for (key, gauge) in self.gauges.read().unwrap().iter() {
+            if key.name != last_name {
+                let _ = writeln!(out, "# TYPE {} gauge", key.name);
+                last_name = key.name;
+            }
+            let _ = writeln!(
+                out,
+                "{}{} {}",
+                key.name,
+                render_labels(&key.labels),
+                gauge.get()
+            );
+        }
+        for (key, histogram) in self.histograms.read().unwrap().iter() {
+            if key.name != last_name {
+                let _ = writeln!(out, "# TYPE {} histogram", key.name);
+                last_name = key.name;
+            }
+            let mut cumulative = 0;
+            let bounds = histogram.bounds.iter().map(|bound| bound.to_string());
+            for (bound, bucket) in bounds
+                .chain(["+Inf".to_string()])
+                .zip(&histogram.buckets)
+            {
+                cumulative += bucket.load(Ordering::Relaxed);
+                let mut labels = key.labels.clone();
+                labels.push(("le", bound));
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{} {}",
+                    key.name,
+                    render_labels(&labels),
+                    cumulative
+                );
+            }
+            let labels = render_labels(&key.labels);
+            let _ = writeln!(out, "{}_sum{} {}", key.name, labels, histogram.sum());
+            let _ = writeln!(out, "{}_count{} {}", key.name, labels, cumulative);
+        }
+        out
+    }
+}
+
+fn render_labels(labels: &[(&'static str, String)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels = labels
+        .iter()
+        .map(|(key, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", key, value)
+        })
+        .collect::<Vec<_>>();
+    format!("{{{}}}", labels.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters_are_shared_and_rendered() {
+        let metrics = Metrics::default();
+        metrics
+            .counter("requests_total", &[("task", "embeddings"), ("code", "ok")])
+            .increment(2);
+        metrics
+            .counter("requests_total", &[("code", "ok"), ("task", "embeddings")])
+            .increment(1);
+
+        assert_eq!(
+            metrics.render(),
+            "# TYPE requests_total counter\nrequests_total{code=\"ok\",task=\"embeddings\"} 3\n"
+        );
+    }
+
+    #[test]
+    fn test_gauges_are_rendered() {
+        let metrics = Metrics::default();
+        let gauge = metrics.gauge("memory_bytes", &[("cache", "responses")]);
+        gauge.set(1024);
+        gauge.add(-24);
+
+        assert_eq!(
+            metrics.render(),
+            "# TYPE memory_bytes gauge\nmemory_bytes{cache=\"responses\"} 1000\n"
+        );
+    }
+
+    #[test]
+    fn test_histograms_are_rendered_cumulatively() {
+        let metrics = Metrics::default();
+        let histogram = metrics.histogram("latency_seconds", &[("task", "qa")], &[0.25, 1.0]);
+        for value in [0.125, 0.25, 0.5, 2.0] {
+            histogram.observe(value);
+        }
+
+        assert_eq!(histogram.count(), 4);
+        assert_eq!(
+            metrics.render(),
+            "# TYPE latency_seconds histogram\n\
+             latency_seconds_bucket{task=\"qa\",le=\"0.25\"} 2\n\
+             latency_seconds_bucket{task=\"qa\",le=\"1\"} 3\n\
+             latency_seconds_bucket{task=\"qa\",le=\"+Inf\"} 4\n\
+             latency_seconds_sum{task=\"qa\"} 2.875\n\
+             latency_seconds_count{task=\"qa\"} 4\n"
+        );
+    }
+}
@@ -1,2 +1,24 @@
+pub mod access_log;
+pub mod admin;
+pub mod aliases;
+pub mod auth;
+pub mod client_rate_limit;
 pub mod clients;
+pub mod context;
+pub mod hash;
+pub mod health;
+pub mod http_gateway;
+pub mod logging;
+pub mod memory;
+pub mod metrics;
+pub mod network_acl;
+pub mod npz;
+pub mod panic_recovery;
+pub mod readiness;
+pub mod reload;
 pub mod server_proxy;
+pub mod telemetry;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(unix)]
+pub mod unix_socket;
@@ -0,0 +1,131 @@
+//! Perimeter control of the gRPC server, rejecting the calls of peers outside the configured IP
+//! address ranges, for gateways bound to every interface.
+
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use log::debug;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use crate::config::NetworkAclConfig;
+use crate::services::metrics::Metrics;
+
+/// Counter of calls rejected by the network ACL, by `reason`: `denied` for peers in a `deny`
+/// range, `not_allowed` for peers outside the `allow` ranges.
+const REJECTED_METRIC: &str = "mighty_network_acl_rejected_requests_total";
+
+/// The `NetworkAclInterceptor` struct is a tonic interceptor rejecting the calls of peers in a
+/// `deny` range of `[network_acl]`, or outside its `allow` ranges when any is set, with
+/// `PERMISSION_DENIED`. IPv4 peers connected over IPv6 match IPv4 ranges.
+///
+/// It should be the outermost interceptor, so rejected peers don't reach authentication. Calls
+/// over Unix domain sockets are left to the permissions of the socket file; other calls whose
+/// peer address is unknown are only rejected when `allow` ranges are set.
+#[derive(Clone)]
+pub struct NetworkAclInterceptor {
+    config: Arc<NetworkAclConfig>,
+}
+
+impl NetworkAclInterceptor {
+    pub fn new(config: &NetworkAclConfig) -> Self {
+        Self {
+            config: Arc::new(config.clone()),
+        }
+    }
+
+    /// Returns why the calls of `peer` are rejected, if they are.
+    fn rejection(&self, peer: Option<IpAddr>) -> Option<&'static str> {
+        let denied =
+            peer.is_some_and(|peer| self.config.deny.iter().any(|range| range.contains(peer)));
+        let allowed = self.config.allow.is_empty()
+            || peer.is_some_and(|peer| self.config.allow.iter().any(|range| range.contains(peer)));
+        if denied {
+            Some("denied")
+        } else if !allowed {
+            Some("not_allowed")
+        } else {
+            None
+        }
+    }
+}
+
+impl Interceptor for NetworkAclInterceptor {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        if !self.config.is_enabled() {
+            return Ok(request);
+        }
+        #[cfg(unix)]
+        if crate::services::unix_socket::is_unix_peer(&request) {
+            return Ok(request);
+        }
+        let peer = request.remote_addr().map(|addr| addr.ip());
+        let Some(reason) = self.rejection(peer) else {
+            return Ok(request);
+        };
+        let peer = peer.map_or("an unknown address".to_string(), |peer| peer.to_string());
+        debug!("Rejected a call from {}: {}", peer, reason);
+        Metrics::global()
+            .counter(REJECTED_METRIC, &[("reason", reason)])
+            .increment(1);
+        Err(Status::permission_denied(format!(
+            "Calls from {} aren't allowed",
+            peer
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tonic::transport::server::TcpConnectInfo;
+    use tonic::Code;
+
+    use crate::config::IpRange;
+
+    use super::*;
+
+    fn range(value: &str) -> IpRange {
+        IpRange::try_from(value.to_string()).unwrap()
+    }
+
+    fn from(peer: &str) -> Request<()> {
+        let mut request = Request::new(());
+        request.extensions_mut().insert(TcpConnectInfo {
+            local_addr: None,
+            remote_addr: Some(SocketAddr::new(peer.parse().unwrap(), 40000)),
+        });
+        request
+    }
+
+    #[test]
+    fn test_ip_ranges() {
+        let private = range("10.0.0.0/8");
+        assert!(private.contains("10.1.2.3".parse().unwrap()));
+        assert!(private.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!private.contains("11.0.0.1".parse().unwrap()));
+        assert!(range("0.0.0.0/0").contains("8.8.8.8".parse().unwrap()));
+        assert!(range("fd00::/8").contains("fd12::1".parse().unwrap()));
+        assert!(!range("192.168.1.1").contains("192.168.1.2".parse().unwrap()));
+
+        assert!(IpRange::try_from("10.0.0.0/33".to_string()).is_err());
+        assert!(IpRange::try_from("localhost".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_calls_outside_the_allowed_ranges_are_rejected() {
+        let mut acl = NetworkAclInterceptor::new(&NetworkAclConfig {
+            allow: vec![range("10.0.0.0/8")],
+            deny: vec![range("10.0.0.13")],
+        });
+        assert!(acl.call(from("10.0.0.12")).is_ok());
+
+        let status = acl.call(from("10.0.0.13")).unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        assert_eq!(status.message(), "Calls from 10.0.0.13 aren't allowed");
+        let status = acl.call(from("192.168.0.1")).unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        assert!(acl.call(Request::new(())).is_err());
+    }
+}
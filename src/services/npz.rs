@@ -0,0 +1,232 @@
+//! Encoding of NumPy `.npz` archives, letting HTTP clients load results straight into
+//! NumPy (`numpy.load`) or PyTorch without lossy JSON float round trips.
+//!
+//! An `.npz` archive is a ZIP archive of `.npy` files, one per array. Arrays are stored
+//! uncompressed, as embeddings barely compress, in the little-endian `.npy` format version 1.0.
+
+use flate2::Crc;
+
+/// The largest archive the ZIP format supports without its ZIP64 extensions.
+const MAX_ARCHIVE_SIZE: usize = u32::MAX as usize;
+
+/// An element type of `.npy` arrays.
+pub trait Element: Copy {
+    /// The NumPy type descriptor, e.g. `<f4` for little-endian 32-bit floats.
+    const DESCR: &'static str;
+
+    /// Appends the little-endian representation of the element to `out`.
+    fn write_le(self, out: &mut Vec<u8>);
+}
+
+macro_rules! element {
+    ($type:ty, $descr:literal) => {
+        impl Element for $type {
+            const DESCR: &'static str = $descr;
+
+            fn write_le(self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+        }
+    };
+}
+
+element!(f32, "<f4");
+element!(i32, "<i4");
+element!(i64, "<i8");
+element!(u64, "<u8");
+
+impl Element for bool {
+    const DESCR: &'static str = "|b1";
+
+    fn write_le(self, out: &mut Vec<u8>) {
+        out.push(u8::from(self));
+    }
+}
+
+/// Encodes `values`, laid out in row-major order, as a `.npy` array of the given `shape`.
+pub fn npy<T: Element>(shape: &[usize], values: &[T]) -> Vec<u8> {
+    debug_assert_eq!(shape.iter().product::<usize>(), values.len());
+    let shape = match shape {
+        [dim] => format!("({},)", dim),
+        dims => format!(
+            "({})",
+            dims.iter()
+                .map(usize::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        T::DESCR,
+        shape
+    );
+    // The magic string, version and header length take 10 bytes, and the header ends with a
+    // newline, padded so the data starts on a 64 byte boundary
+    let unpadded = 10 + header.len() + 1;
+    header.extend(std::iter::repeat_n(' ', (64 - unpadded % 64) % 64));
+    header.push('\n');
+
+    let mut out = Vec::with_capacity(10 + header.len() + values.len() * 8);
+    out.extend_from_slice(b"\x93NUMPY\x01\x00");
+    out.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    out.extend_from_slice(header.as_bytes());
+    for value in values {
+        value.write_le(&mut out);
+    }
+    out
+}
+
+/// A file recorded in the central directory of the archive.
+struct Entry {
+    name: String,
+    crc: u32,
+    size: u32,
+    offset: u32,
+}
+
+/// The `NpzWriter` struct builds an `.npz` archive in memory, one named array at a time.
+#[derive(Default)]
+pub struct NpzWriter {
+    buffer: Vec<u8>,
+    entries: Vec<Entry>,
+    too_large: bool,
+}
+
+impl NpzWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the array `name` (loaded as `archive[name]`) of the given `shape`, with `values`
+    /// laid out in row-major order.
+    pub fn add<T: Element>(&mut self, name: &str, shape: &[usize], values: &[T]) -> &mut Self {
+        let data = npy(shape, values);
+        let name = format!("{}.npy", name);
+        let offset = self.buffer.len();
+        if offset + data.len() + 2 * name.len() + 128 > MAX_ARCHIVE_SIZE {
+            self.too_large = true;
+            return self;
+        }
+        let mut crc = Crc::new();
+        crc.update(&data);
+        let entry = Entry {
+            name,
+            crc: crc.sum(),
+            size: data.len() as u32,
+            offset: offset as u32,
+        };
+
+        // Local file header: stored (uncompressed), dated 1980-01-01
+        put_u32(&mut self.buffer, 0x04034b50);
+        put_u16(&mut self.buffer, 20); // version needed to extract
+        put_u16(&mut self.buffer, 0); // flags
+        put_u16(&mut self.buffer, 0); // compression method
+        put_u16(&mut self.buffer, 0); // modification time
+        put_u16(&mut self.buffer, DOS_EPOCH);
+        put_u32(&mut self.buffer, entry.crc);
+        put_u32(&mut self.buffer, entry.size); // compressed size
+        put_u32(&mut self.buffer, entry.size); // uncompressed size
+        put_u16(&mut self.buffer, entry.name.len() as u16);
+        put_u16(&mut self.buffer, 0); // extra field length
+        self.buffer.extend_from_slice(entry.name.as_bytes());
+        self.buffer.extend_from_slice(&data);
+        self.entries.push(entry);
+        self
+    }
+
+    /// Returns the archive, or an error if it exceeds the 4 GiB supported.
+    pub fn finish(mut self) -> Result<Vec<u8>, String> {
+        if self.too_large {
+            return Err("The .npz archive would exceed 4 GiB".to_string());
+        }
+        let directory_offset = self.buffer.len() as u32;
+        for entry in &self.entries {
+            put_u32(&mut self.buffer, 0x02014b50);
+            put_u16(&mut self.buffer, 20); // version made by
+            put_u16(&mut self.buffer, 20); // version needed to extract
+            put_u16(&mut self.buffer, 0); // flags
+            put_u16(&mut self.buffer, 0); // compression method
+            put_u16(&mut self.buffer, 0); // modification time
+            put_u16(&mut self.buffer, DOS_EPOCH);
+            put_u32(&mut self.buffer, entry.crc);
+            put_u32(&mut self.buffer, entry.size);
+            put_u32(&mut self.buffer, entry.size);
+            put_u16(&mut self.buffer, entry.name.len() as u16);
+            put_u16(&mut self.buffer, 0); // extra field length
+            put_u16(&mut self.buffer, 0); // comment length
+            put_u16(&mut self.buffer, 0); // disk number
+            put_u16(&mut self.buffer, 0); // internal attributes
+            put_u32(&mut self.buffer, 0); // external attributes
+            put_u32(&mut self.buffer, entry.offset);
+            self.buffer.extend_from_slice(entry.name.as_bytes());
+        }
+        let directory_size = self.buffer.len() as u32 - directory_offset;
+
+        // End of central directory record
+        let count = self.entries.len() as u16;
+        put_u32(&mut self.buffer, 0x06054b50);
+        put_u16(&mut self.buffer, 0); // disk number
+        put_u16(&mut self.buffer, 0); // disk with the central directory
+        put_u16(&mut self.buffer, count);
+        put_u16(&mut self.buffer, count);
+        put_u32(&mut self.buffer, directory_size);
+        put_u32(&mut self.buffer, directory_offset);
+        put_u16(&mut self.buffer, 0); // comment length
+        Ok(self.buffer)
+    }
+}
+
+/// The MS-DOS date of 1980-01-01, the earliest ZIP timestamp.
+const DOS_EPOCH: u16 = (1 << 5) | 1;
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_npy_header() {
+        let array = npy(&[2, 3], &[1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let header_len = u16::from_le_bytes([array[8], array[9]]) as usize;
+        assert_eq!(&array[..8], b"\x93NUMPY\x01\x00");
+        assert_eq!((10 + header_len) % 64, 0);
+        let header = std::str::from_utf8(&array[10..10 + header_len]).unwrap();
+        assert!(header.starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': (2, 3), }"));
+        assert!(header.ends_with('\n'));
+        assert_eq!(array.len(), 10 + header_len + 6 * 4);
+        assert_eq!(&array[10 + header_len..][..4], &1.0f32.to_le_bytes());
+
+        let array = npy(&[2], &[true, false]);
+        assert!(String::from_utf8_lossy(&array)
+            .contains("'descr': '|b1', 'fortran_order': False, 'shape': (2,)"));
+    }
+
+    #[test]
+    fn test_npz_archive_layout() {
+        let mut writer = NpzWriter::new();
+        writer
+            .add("reference", &[2], &[7u64, 9])
+            .add("text_index", &[1], &[0i64]);
+        let archive = writer.finish().unwrap();
+
+        assert_eq!(&archive[..4], &0x04034b50u32.to_le_bytes());
+        let end = &archive[archive.len() - 22..];
+        assert_eq!(&end[..4], &0x06054b50u32.to_le_bytes());
+        assert_eq!(u16::from_le_bytes([end[10], end[11]]), 2);
+        let directory_offset = u32::from_le_bytes(end[16..20].try_into().unwrap()) as usize;
+        assert_eq!(
+            &archive[directory_offset..][..4],
+            &0x02014b50u32.to_le_bytes()
+        );
+        let name = &archive[30..30 + "reference.npy".len()];
+        assert_eq!(name, b"reference.npy");
+    }
+}
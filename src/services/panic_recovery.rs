@@ -0,0 +1,86 @@
+//! Recovery from panics in the handling of calls, so a panicking `MightyClient` or converter fails
+//! its call with `INTERNAL` rather than tearing down the connection or stream it was served on.
+
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+
+use futures::FutureExt;
+use log::error;
+use tonic::Status;
+
+use crate::services::metrics::Metrics;
+
+/// Counter of calls whose handling panicked, by `method`.
+const PANICS_METRIC: &str = "mighty_handler_panics_total";
+
+/// Logs panics with their location and backtrace at the error level, rather than printing them to
+/// stderr, so they reach the log pipeline along with the calls they failed.
+pub fn install_panic_hook() {
+    panic::set_hook(Box::new(|info| {
+        let location = info
+            .location()
+            .map(|location| location.to_string())
+            .unwrap_or_else(|| "an unknown location".to_string());
+        error!(
+            "Panicked at {}: {}\n{}",
+            location,
+            payload_message(info.payload()),
+            Backtrace::force_capture()
+        );
+    }));
+}
+
+/// Returns the message a panic was raised with, if any.
+fn payload_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>")
+}
+
+/// Runs `future`, the handling of a call of `method`, failing the call with `INTERNAL` if it
+/// panics.
+pub async fn recover<T, F>(method: &str, future: F) -> Result<T, Status>
+where
+    F: Future<Output = Result<T, Status>>,
+{
+    match AssertUnwindSafe(future).catch_unwind().await {
+        Ok(result) => result,
+        Err(payload) => {
+            Metrics::global()
+                .counter(PANICS_METRIC, &[("method", method)])
+                .increment(1);
+            error!(
+                "The {} call panicked: {}",
+                method,
+                payload_message(payload.as_ref())
+            );
+            Err(Status::internal(format!(
+                "The {} call failed unexpectedly",
+                method
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_panics_fail_the_call() {
+        let status = recover::<(), _>("embeddings", async { panic!("boom") })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Internal);
+        assert_eq!(status.message(), "The embeddings call failed unexpectedly");
+
+        let result = recover("embeddings", async { Ok(1) }).await;
+        assert_eq!(result.unwrap(), 1);
+    }
+}
@@ -0,0 +1,373 @@
+//! Readiness tracking for the gateway.
+//!
+//! The gateway is ready once every check required by the `[readiness]` configuration has passed:
+//! the startup warm-up of the upstream has completed, the model metadata has been fetched, the
+//! response cache has been warmed up and a number of canary inferences have succeeded. Until then
+//! the `MightyAdmin/Readiness` RPC reports the pending checks, so orchestrators only route traffic
+//! to a gateway able to serve it at the expected latency. A ready gateway turns not ready while the
+//! upstream health check keeps failing, and ready again once it passes.
+//!
+//! Readiness is distinct from liveness: the gateway is live as long as its process serves calls,
+//! whatever the state of the upstream, so orchestrators don't restart it during upstream outages.
+
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+use futures::future::join_all;
+use log::{debug, info, warn};
+use tonic::{Request, Status};
+
+use crate::config::{ReadinessConfig, Task};
+use crate::proto::mighty_proto::{Empty, TextRequest};
+use crate::services::clients::MightyClient;
+
+/// The `Readiness` struct tracks the progress of the readiness checks.
+#[derive(Debug, Default)]
+pub struct Readiness {
+    config: ReadinessConfig,
+    warmed_up: AtomicBool,
+    metadata_fetched: AtomicBool,
+    cache_warmed: AtomicBool,
+    canary_successes: AtomicU32,
+    upstream_failures: AtomicU32,
+}
+
+impl Readiness {
+    pub fn new(config: ReadinessConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    pub fn mark_metadata_fetched(&self) {
+        self.metadata_fetched.store(true, Ordering::Relaxed);
+    }
+
+    /// Marks the response cache as warmed up. Called once every call of the startup warm-up
+    /// succeeded, or at startup when no cache is configured.
+    pub fn mark_cache_warmed(&self) {
+        self.cache_warmed.store(true, Ordering::Relaxed);
+    }
+
+    pub fn record_canary_success(&self) {
+        self.canary_successes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records the outcome of an upstream health check, returning whether it changed the
+    /// readiness of the gateway.
+    pub fn record_upstream_health(&self, healthy: bool) -> bool {
+        let was_ready = self.is_ready();
+        if healthy {
+            self.upstream_failures.store(0, Ordering::Relaxed);
+        } else {
+            self.upstream_failures.fetch_add(1, Ordering::Relaxed);
+        }
+        was_ready != self.is_ready()
+    }
+
+    /// Returns a description of every required check that hasn't passed yet.
+    pub fn pending(&self) -> Vec<String> {
+        let mut pending = Vec::new();
+        if self.config.warm_up.enabled && !self.warmed_up.load(Ordering::Relaxed) {
+            pending.push("startup warm-up not completed".to_string());
+        }
+        if self.config.require_metadata && !self.metadata_fetched.load(Ordering::Relaxed) {
+            pending.push("model metadata not fetched".to_string());
+        }
+        if self.config.require_cache_warmup && !self.cache_warmed.load(Ordering::Relaxed) {
+            pending.push("cache warmup not completed".to_string());
+        }
+        let successes = self.canary_successes.load(Ordering::Relaxed);
+        if successes < self.config.canary_inferences {
+            pending.push(format!(
+                "{} of {} canary inferences succeeded",
+                successes, self.config.canary_inferences
+            ));
+        }
+        let threshold = self.config.upstream_failure_threshold;
+        let failures = self.upstream_failures.load(Ordering::Relaxed);
+        if threshold > 0 && failures >= threshold {
+            pending.push(format!(
+                "upstream health check failed {} consecutive times",
+                failures
+            ));
+        }
+        pending
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.pending().is_empty()
+    }
+
+    /// Runs the startup warm-up, then the metadata fetch and canary inferences required by the
+    /// configuration against `client`, retrying failed attempts at the configured interval until
+    /// they pass. The response cache is warmed up once every warm-up call succeeded, the failed
+    /// ones being retried likewise when the cache warmup is required.
+    pub async fn warm_up(&self, client: &dyn MightyClient) {
+        let retry_interval = self.config.retry_interval.max(Duration::from_millis(1));
+        if self.config.warm_up.enabled {
+            let mut failed = self.warm_up_upstream(client).await;
+            while !failed.is_empty() && self.config.require_cache_warmup {
+                tokio::time::sleep(retry_interval).await;
+                failed = warm_up_calls(client, failed).await;
+            }
+            if failed.is_empty() {
+                self.mark_cache_warmed();
+            }
+        }
+
+        while self.config.require_metadata && !self.metadata_fetched.load(Ordering::Relaxed) {
+            match client.metadata(Request::new(Empty {})).await {
+                Ok(_) => self.mark_metadata_fetched(),
+                Err(status) => {
+                    warn!("Readiness metadata fetch failed: {}", status);
+                    tokio::time::sleep(retry_interval).await;
+                }
+            }
+        }
+
+        while self.canary_successes.load(Ordering::Relaxed) < self.config.canary_inferences {
+            let request = Request::new(TextRequest {
+                text: self.config.canary_text.clone(),
+            });
+            match client.embeddings(request).await {
+                Ok(_) => self.record_canary_success(),
+                Err(status) => {
+                    warn!("Readiness canary inference failed: {}", status);
+                    tokio::time::sleep(retry_interval).await;
+                }
+            }
+        }
+
+        if self.is_ready() {
+            info!("Gateway is ready");
+        }
+    }
+
+    /// Opens the configured number of upstream connections, fetches the model metadata and sends
+    /// the warm-up texts, within the warm-up timeout, returning the calls sending them that
+    /// failed or didn't complete in time. Failed calls aren't retried.
+    async fn warm_up_upstream(&self, client: &dyn MightyClient) -> Vec<(Task, &str)> {
+        let config = &self.config.warm_up;
+        let start = Instant::now();
+        let calls = config
+            .tasks
+            .iter()
+            .flat_map(|task| config.texts.iter().map(|text| (*task, text.as_str())))
+            .collect::<Vec<_>>();
+        let mut failed = calls.clone();
+        let warm_up = async {
+            let connections =
+                (0..config.connections).map(|_| client.health_check(Request::new(Empty {})));
+            for result in join_all(connections).await {
+                if let Err(status) = result {
+                    warn!("Warm-up health check failed: {}", status);
+                }
+            }
+            if config.metadata {
+                match client.metadata(Request::new(Empty {})).await {
+                    Ok(_) => self.mark_metadata_fetched(),
+                    Err(status) => warn!("Warm-up metadata fetch failed: {}", status),
+                }
+            }
+            failed = warm_up_calls(client, calls).await;
+        };
+        match tokio::time::timeout(config.timeout, warm_up).await {
+            Ok(()) => info!("Warmed up the upstream in {:?}", start.elapsed()),
+            Err(_) => warn!(
+                "The upstream warm-up didn't complete within {:?}",
+                config.timeout
+            ),
+        }
+        self.warmed_up.store(true, Ordering::Relaxed);
+        failed
+    }
+
+    /// Runs the upstream health check against `client` at the configured interval, for as long
+    /// as the gateway runs, unless `upstream_failure_threshold` is 0. Checks taking longer than
+    /// the interval fail.
+    pub async fn monitor_upstream(&self, client: &dyn MightyClient) {
+        if self.config.upstream_failure_threshold == 0 {
+            return;
+        }
+        let interval = self
+            .config
+            .upstream_check_interval
+            .max(Duration::from_millis(1));
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            let check = client.health_check(Request::new(Empty {}));
+            let healthy = match tokio::time::timeout(interval, check).await {
+                Ok(Ok(response)) => response.get_ref().success,
+                Ok(Err(status)) => {
+                    debug!("Upstream health check failed: {}", status);
+                    false
+                }
+                Err(_) => false,
+            };
+            if !self.record_upstream_health(healthy) {
+                continue;
+            }
+            if self.is_ready() {
+                info!("Gateway is ready again: the upstream health check passed");
+            } else {
+                warn!("Gateway isn't ready: {}", self.pending().join(", "));
+            }
+        }
+    }
+}
+
+/// Sends each text to its task through `client`, returning the calls that failed.
+async fn warm_up_calls<'a>(
+    client: &dyn MightyClient,
+    calls: Vec<(Task, &'a str)>,
+) -> Vec<(Task, &'a str)> {
+    let mut failed = Vec::new();
+    for (task, text) in calls {
+        if let Err(status) = infer(client, task, text).await {
+            warn!("Warm-up {} call failed: {}", task.as_str(), status);
+            failed.push((task, text));
+        }
+    }
+    failed
+}
+
+/// Sends `text` to `task` through `client`, discarding the response.
+async fn infer(client: &dyn MightyClient, task: Task, text: &str) -> Result<(), Status> {
+    let request = Request::new(TextRequest {
+        text: text.to_string(),
+    });
+    match task {
+        Task::Embeddings => client.embeddings(request).await.map(drop),
+        Task::SentenceTransformers => client.sentence_transformers(request).await.map(drop),
+        Task::SequenceClassification => client.sequence_classification(request).await.map(drop),
+        Task::TokenClassification => client.token_classification(request).await.map(drop),
+        Task::QuestionAnswering | Task::Rerank => Err(Status::invalid_argument(format!(
+            "{} doesn't take a single text",
+            task.as_str()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::WarmUpConfig;
+    use crate::services::clients::mock::MockMightyClient;
+
+    use super::*;
+
+    #[test]
+    fn test_pending_checks() {
+        let readiness = Readiness::new(ReadinessConfig {
+            require_metadata: true,
+            require_cache_warmup: true,
+            canary_inferences: 2,
+            ..Default::default()
+        });
+        assert_eq!(
+            readiness.pending(),
+            vec![
+                "model metadata not fetched",
+                "cache warmup not completed",
+                "0 of 2 canary inferences succeeded",
+            ]
+        );
+
+        readiness.mark_metadata_fetched();
+        readiness.mark_cache_warmed();
+        readiness.record_canary_success();
+        assert_eq!(
+            readiness.pending(),
+            vec!["1 of 2 canary inferences succeeded"]
+        );
+
+        readiness.record_canary_success();
+        assert!(readiness.is_ready());
+    }
+
+    #[test]
+    fn test_ready_without_required_checks() {
+        assert!(Readiness::new(ReadinessConfig::default()).is_ready());
+    }
+
+    #[test]
+    fn test_not_ready_while_the_upstream_keeps_failing() {
+        let readiness = Readiness::new(ReadinessConfig {
+            upstream_failure_threshold: 2,
+            ..Default::default()
+        });
+        assert!(!readiness.record_upstream_health(false));
+        assert!(readiness.record_upstream_health(false));
+        assert_eq!(
+            readiness.pending(),
+            vec!["upstream health check failed 2 consecutive times"]
+        );
+        assert!(!readiness.record_upstream_health(false));
+        assert!(readiness.record_upstream_health(true));
+        assert!(readiness.is_ready());
+    }
+
+    #[tokio::test]
+    async fn test_startup_warm_up() {
+        let mock = MockMightyClient::new().with_failures(
+            Task::TokenClassification,
+            1,
+            Status::unavailable("loading"),
+        );
+        let readiness = Readiness::new(ReadinessConfig {
+            require_metadata: true,
+            require_cache_warmup: true,
+            retry_interval: Duration::from_millis(1),
+            upstream_failure_threshold: 0,
+            warm_up: WarmUpConfig {
+                enabled: true,
+                texts: vec!["first".to_string(), "second".to_string()],
+                tasks: vec![Task::Embeddings, Task::TokenClassification],
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        assert_eq!(
+            readiness.pending(),
+            vec![
+                "startup warm-up not completed",
+                "model metadata not fetched",
+                "cache warmup not completed",
+            ]
+        );
+
+        readiness.warm_up(&mock).await;
+
+        // The failed warm-up call is retried, as the cache warmup is required
+        assert!(readiness.is_ready());
+        assert_eq!(mock.calls(Task::Embeddings), 2);
+        assert_eq!(mock.calls(Task::TokenClassification), 3);
+    }
+
+    #[tokio::test]
+    async fn test_failed_warm_up_leaves_the_cache_cold() {
+        let mock = MockMightyClient::new().with_failures(
+            Task::Embeddings,
+            1,
+            Status::unavailable("loading"),
+        );
+        let readiness = Readiness::new(ReadinessConfig {
+            upstream_failure_threshold: 0,
+            warm_up: WarmUpConfig {
+                enabled: true,
+                texts: vec!["first".to_string()],
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+        readiness.warm_up(&mock).await;
+
+        // Failed warm-up calls aren't retried, nor delay readiness, unless the cache is required
+        assert!(readiness.is_ready());
+        assert_eq!(mock.calls(Task::Embeddings), 1);
+        assert!(!readiness.cache_warmed.load(Ordering::Relaxed));
+    }
+}
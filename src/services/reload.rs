@@ -0,0 +1,573 @@
+//! The reload of the configuration while serving, so the logging level, the upstream URLs, the
+//! timeouts and the rate limits change without restarting the gateway or dropping connections.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use config::ConfigError;
+use log::{error, info, warn, Log, Metadata, Record};
+use tonic::Status;
+
+use crate::config::lint::lint;
+use crate::config::{AppSettings, BackendConfig, BackendKind};
+use crate::services::clients::{BackendSwitch, UpstreamUpdate};
+use crate::services::metrics::Metrics;
+
+/// Counter of configuration reloads, by `result`: `ok` or `error`.
+const RELOADS_METRIC: &str = "mighty_config_reloads_total";
+
+/// A component applying the settings of a reloaded configuration, e.g. the
+/// `RateLimitingClient`.
+#[async_trait]
+pub trait Reloadable: Send + Sync {
+    /// The section of the configuration applied, e.g. `"rate_limit"`.
+    fn section(&self) -> &'static str;
+
+    /// Applies the settings of its section, returning whether they changed since they were last
+    /// applied. Settings that fail to apply leave the previous ones in place.
+    async fn reload(&self, settings: &AppSettings) -> Result<bool, String>;
+}
+
+/// Loads the configuration, e.g. `AppSettings::new`.
+pub type SettingsLoader = Box<dyn Fn() -> Result<AppSettings, ConfigError> + Send + Sync>;
+
+/// The `ConfigReloader` struct reloads the configuration on demand, on `SIGHUP` or whenever the
+/// configuration file changes, and applies it to the components registered with `add`. It holds
+/// the latest settings, e.g. for backends created after a reload.
+pub struct ConfigReloader {
+    settings: RwLock<Arc<AppSettings>>,
+    load: SettingsLoader,
+    targets: Mutex<Vec<Arc<dyn Reloadable>>>,
+    reloading: tokio::sync::Mutex<()>,
+}
+
+impl ConfigReloader {
+    /// Creates a reloader of the `settings` loaded at startup, reloading them with `load`.
+    pub fn new(settings: Arc<AppSettings>, load: SettingsLoader) -> Self {
+        Self {
+            settings: RwLock::new(settings),
+            load,
+            targets: Mutex::new(Vec::new()),
+            reloading: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Returns the latest settings.
+    pub fn settings(&self) -> Arc<AppSettings> {
+        self.settings.read().unwrap().clone()
+    }
+
+    /// Registers a component applying reloaded settings.
+    pub fn add(&self, target: Arc<dyn Reloadable>) {
+        self.targets.lock().unwrap().push(target);
+    }
+
+    /// Reloads the configuration and applies it, returning the sections whose settings changed.
+    ///
+    /// Configurations that can't be loaded, fail validation or break lint rules the current
+    /// settings don't are ignored. Configurations are applied all or nothing: when a section
+    /// fails to apply, the sections already applied are reverted to the current settings, which
+    /// stay in place.
+    pub async fn reload(&self) -> Result<Vec<&'static str>, Status> {
+        let _reloading = self.reloading.lock().await;
+        let current = self.settings();
+        let settings = match (self.load)().and_then(|settings| {
+            settings.validate()?;
+            Ok(settings)
+        }) {
+            Ok(settings) => Arc::new(settings),
+            Err(e) => {
+                count("error");
+                return Err(Status::invalid_argument(format!(
+                    "The configuration can't be loaded: {}",
+                    e
+                )));
+            }
+        };
+        let known = lint(&current);
+        let problems: Vec<_> = lint(&settings)
+            .into_iter()
+            .filter(|problem| !known.contains(problem))
+            .collect();
+        if !problems.is_empty() {
+            count("error");
+            return Err(Status::invalid_argument(format!(
+                "The configuration breaks lint rules: {}",
+                problems.join("; ")
+            )));
+        }
+
+        // Backends created while applying the settings, e.g. on upstream switches, read them
+        *self.settings.write().unwrap() = settings.clone();
+        let targets = self.targets.lock().unwrap().clone();
+        let mut applied = Vec::new();
+        for target in &targets {
+            match target.reload(&settings).await {
+                Ok(true) => applied.push(target.clone()),
+                Ok(false) => {}
+                Err(e) => {
+                    count("error");
+                    *self.settings.write().unwrap() = current.clone();
+                    for target in applied {
+                        if let Err(e) = target.reload(&current).await {
+                            error!("Can't revert the {} settings: {}", target.section(), e);
+                        }
+                    }
+                    return Err(Status::failed_precondition(format!(
+                        "The configuration wasn't applied: {}: {}",
+                        target.section(),
+                        e
+                    )));
+                }
+            }
+        }
+        let applied: Vec<_> = applied.iter().map(|target| target.section()).collect();
+        count("ok");
+        info!("Reloaded the configuration, applying {:?}", applied);
+        Ok(applied)
+    }
+
+    /// Reloads the configuration whenever the process receives `SIGHUP`.
+    #[cfg(unix)]
+    pub fn reload_on_hangup(self: &Arc<Self>) -> std::io::Result<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangups = signal(SignalKind::hangup())?;
+        let reloader = self.clone();
+        tokio::spawn(async move {
+            while hangups.recv().await.is_some() {
+                info!("Reloading the configuration on SIGHUP");
+                if let Err(status) = reloader.reload().await {
+                    error!("{}", status.message());
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Reloads the configuration whenever the modification time of the file at `path` changes,
+    /// checking it every `interval`.
+    pub fn watch(self: &Arc<Self>, path: PathBuf, interval: Duration) {
+        let reloader = self.clone();
+        tokio::spawn(async move {
+            let mut modified = modified_at(&path);
+            let mut ticks = tokio::time::interval(interval);
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                let latest = modified_at(&path);
+                if latest == modified {
+                    continue;
+                }
+                modified = latest;
+                info!("Reloading the configuration: {} changed", path.display());
+                if let Err(status) = reloader.reload().await {
+                    error!("{}", status.message());
+                }
+            }
+        });
+    }
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn count(result: &str) {
+    Metrics::global()
+        .counter(RELOADS_METRIC, &[("result", result)])
+        .increment(1);
+}
+
+/// Builds the logger of a logging level, e.g. `"info,mighty_grpc=debug"`.
+pub type LoggerFactory = Arc<dyn Fn(&str) -> env_logger::Logger + Send + Sync>;
+
+/// The `ReloadableLogger` struct is the global logger, rebuilt when the logging level of the
+/// configuration changes. Its clones share the logger they forward to.
+#[derive(Clone)]
+pub struct ReloadableLogger {
+    logger: Arc<RwLock<env_logger::Logger>>,
+    level: Arc<Mutex<String>>,
+    build: LoggerFactory,
+}
+
+impl ReloadableLogger {
+    /// Creates a logger at `level`, built by `build`.
+    pub fn new(level: &str, build: LoggerFactory) -> Self {
+        Self {
+            logger: Arc::new(RwLock::new(build(level))),
+            level: Arc::new(Mutex::new(level.to_string())),
+            build,
+        }
+    }
+
+    /// Installs the logger as the global logger.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a global logger is already installed.
+    pub fn init(self) -> Self {
+        log::set_max_level(self.logger.read().unwrap().filter());
+        log::set_boxed_logger(Box::new(self.clone())).expect("the logger is only installed once");
+        self
+    }
+}
+
+impl Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.logger.read().unwrap().enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.logger.read().unwrap().log(record)
+    }
+
+    fn flush(&self) {
+        self.logger.read().unwrap().flush()
+    }
+}
+
+#[async_trait]
+impl Reloadable for ReloadableLogger {
+    fn section(&self) -> &'static str {
+        "logging"
+    }
+
+    async fn reload(&self, settings: &AppSettings) -> Result<bool, String> {
+        let level = &settings.logging.level;
+        let mut current = self.level.lock().unwrap();
+        if *current == *level {
+            return Ok(false);
+        }
+        let logger = (self.build)(level);
+        log::set_max_level(logger.filter());
+        *self.logger.write().unwrap() = logger;
+        *current = level.clone();
+        Ok(true)
+    }
+}
+
+/// The `UpstreamReload` struct applies the base URLs of `[mighty_server]` by updating the
+/// upstreams of the primary `rest` backend in place, when it load balances some of the reloaded
+/// ones already (see `with_upstreams`), so the upstreams added ramp up while the others keep
+/// serving. Otherwise, it switches the backend to a client of the reloaded ones, once healthy.
+///
+/// It also forwards the switches of the `SwitchBackend` RPC, which take precedence: once the
+/// RPC switched to another backend than the configured `rest` one, reloads leave that backend
+/// serving, until the RPC switches back to the configured one.
+pub struct UpstreamReload {
+    switch: Arc<dyn BackendSwitch>,
+    upstreams: Option<Arc<dyn UpstreamUpdate>>,
+    base_url: Mutex<Vec<String>>,
+    /// Whether the `SwitchBackend` RPC switched away from the configured backend.
+    pinned: AtomicBool,
+}
+
+impl UpstreamReload {
+    /// Creates the reload of the backend switched by `switch`, serving `settings` at startup.
+    pub fn new(switch: Arc<dyn BackendSwitch>, settings: &AppSettings) -> Self {
+        Self {
+            switch,
+            upstreams: None,
+            base_url: Mutex::new(base_url(settings)),
+            pinned: AtomicBool::new(false),
+        }
+    }
+
+    /// Updates the upstreams of the backend serving through `upstreams` when possible, rather
+    /// than switching backends, e.g. through the `SwitchableClient` being switched.
+    pub fn with_upstreams(mut self, upstreams: Arc<dyn UpstreamUpdate>) -> Self {
+        self.upstreams = Some(upstreams);
+        self
+    }
+}
+
+#[async_trait]
+impl BackendSwitch for UpstreamReload {
+    async fn switch(&self, backend: &BackendConfig) -> Result<u64, Status> {
+        let generation = self.switch.switch(backend).await?;
+        let configured = backend.kind == BackendKind::Rest && backend.base_url.is_none();
+        self.pinned.store(!configured, Ordering::Relaxed);
+        Ok(generation)
+    }
+}
+
+fn base_url(settings: &AppSettings) -> Vec<String> {
+    settings
+        .mighty_server
+        .as_ref()
+        .map(|config| config.base_url.clone())
+        .unwrap_or_default()
+}
+
+#[async_trait]
+impl Reloadable for UpstreamReload {
+    fn section(&self) -> &'static str {
+        "mighty_server"
+    }
+
+    async fn reload(&self, settings: &AppSettings) -> Result<bool, String> {
+        let reloaded = base_url(settings);
+        if *self.base_url.lock().unwrap() == reloaded {
+            return Ok(false);
+        }
+        if self.pinned.load(Ordering::Relaxed) {
+            warn!("The backend switched to by SwitchBackend keeps serving over the reloaded base URLs");
+            return Ok(false);
+        }
+        if let Some(upstreams) = &self.upstreams {
+            if upstreams.update_upstreams(&reloaded) {
+                info!("Updated the upstreams to {:?}", reloaded);
+                *self.base_url.lock().unwrap() = reloaded;
+                return Ok(true);
+            }
+        }
+        let backend = BackendConfig {
+            kind: BackendKind::Rest,
+            base_url: None,
+        };
+        self.switch
+            .switch(&backend)
+            .await
+            .map_err(|status| status.message().to_string())?;
+        *self.base_url.lock().unwrap() = reloaded;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::{Config, File, FileFormat};
+    use log::Level;
+    use tonic::Code;
+
+    use super::*;
+
+    fn settings(toml: &str) -> AppSettings {
+        Config::builder()
+            .add_source(File::from_str(toml, FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    fn at_level(level: &str) -> String {
+        format!(
+            r#"
+            grpc_server = {{ address = "127.0.0.1", port = 5051 }}
+            logging = {{ level = "{}" }}
+            "#,
+            level
+        )
+    }
+
+    fn logger() -> ReloadableLogger {
+        ReloadableLogger::new(
+            "info",
+            Arc::new(|level| {
+                env_logger::Builder::new()
+                    .parse_filters(level)
+                    .is_test(true)
+                    .build()
+            }),
+        )
+    }
+
+    fn enabled(logger: &ReloadableLogger, level: Level) -> bool {
+        logger.enabled(&Metadata::builder().level(level).target("mighty").build())
+    }
+
+    #[tokio::test]
+    async fn test_reloaded_settings_are_applied() {
+        let level = Arc::new(Mutex::new("info"));
+        let reloader = ConfigReloader::new(
+            Arc::new(settings(&at_level("info"))),
+            Box::new({
+                let level = level.clone();
+                move || {
+                    let level = *level.lock().unwrap();
+                    Config::builder()
+                        .add_source(File::from_str(&at_level(level), FileFormat::Toml))
+                        .build()?
+                        .try_deserialize()
+                }
+            }),
+        );
+        let logger = logger();
+        reloader.add(Arc::new(logger.clone()));
+        assert!(!enabled(&logger, Level::Debug));
+
+        assert_eq!(reloader.reload().await.unwrap(), Vec::<&str>::new());
+        *level.lock().unwrap() = "debug";
+        assert_eq!(reloader.reload().await.unwrap(), vec!["logging"]);
+        assert!(enabled(&logger, Level::Debug));
+        assert_eq!(reloader.settings().logging.level, "debug");
+
+        *level.lock().unwrap() = "\" broken";
+        let status = reloader.reload().await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(reloader.settings().logging.level, "debug");
+    }
+
+    /// A section failing to apply the `debug` logging level.
+    struct RejectingDebug;
+
+    #[async_trait]
+    impl Reloadable for RejectingDebug {
+        fn section(&self) -> &'static str {
+            "rejecting_debug"
+        }
+
+        async fn reload(&self, settings: &AppSettings) -> Result<bool, String> {
+            match settings.logging.level.as_str() {
+                "debug" => Err("debug isn't supported".to_string()),
+                _ => Ok(false),
+            }
+        }
+    }
+
+    fn reloader_of(toml: &Arc<Mutex<String>>) -> ConfigReloader {
+        ConfigReloader::new(
+            Arc::new(settings(&toml.lock().unwrap())),
+            Box::new({
+                let toml = toml.clone();
+                move || {
+                    Config::builder()
+                        .add_source(File::from_str(&toml.lock().unwrap(), FileFormat::Toml))
+                        .build()?
+                        .try_deserialize()
+                }
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_reloads_are_all_or_nothing() {
+        let toml = Arc::new(Mutex::new(at_level("info")));
+        let reloader = reloader_of(&toml);
+        let logger = logger();
+        reloader.add(Arc::new(logger.clone()));
+        reloader.add(Arc::new(RejectingDebug));
+
+        *toml.lock().unwrap() = at_level("debug");
+        let status = reloader.reload().await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert!(!enabled(&logger, Level::Debug));
+        assert_eq!(reloader.settings().logging.level, "info");
+    }
+
+    #[tokio::test]
+    async fn test_reloads_breaking_lint_rules_are_rejected() {
+        let toml = Arc::new(Mutex::new(at_level("info")));
+        let reloader = reloader_of(&toml);
+
+        toml.lock().unwrap().push_str(
+            r#"
+            api_keys = { enabled = true, keys = [{ name = "ops", key = "short" }] }
+            "#,
+        );
+        let status = reloader.reload().await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(!reloader.settings().api_keys.enabled);
+    }
+
+    #[derive(Default)]
+    struct CountingSwitch(Mutex<u64>);
+
+    #[async_trait]
+    impl BackendSwitch for CountingSwitch {
+        async fn switch(&self, _backend: &BackendConfig) -> Result<u64, Status> {
+            let mut switches = self.0.lock().unwrap();
+            *switches += 1;
+            Ok(*switches)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_backends_switched_to_prevail_over_reloads() {
+        let with_base_url = |base_url: &str| {
+            settings(&format!(
+                "mighty_server = {{ base_url = \"{}\" }}",
+                base_url
+            ))
+        };
+        let switch = Arc::new(CountingSwitch::default());
+        let upstream = UpstreamReload::new(switch.clone(), &with_base_url("http://mighty-a"));
+
+        assert!(upstream
+            .reload(&with_base_url("http://mighty-b"))
+            .await
+            .unwrap());
+        assert_eq!(*switch.0.lock().unwrap(), 1);
+
+        let onnx = BackendConfig {
+            kind: BackendKind::Onnx,
+            base_url: None,
+        };
+        upstream.switch(&onnx).await.unwrap();
+        assert!(!upstream
+            .reload(&with_base_url("http://mighty-c"))
+            .await
+            .unwrap());
+        assert_eq!(*switch.0.lock().unwrap(), 2);
+
+        let configured = BackendConfig {
+            kind: BackendKind::Rest,
+            base_url: None,
+        };
+        upstream.switch(&configured).await.unwrap();
+        assert!(upstream
+            .reload(&with_base_url("http://mighty-c"))
+            .await
+            .unwrap());
+        assert_eq!(*switch.0.lock().unwrap(), 4);
+    }
+
+    /// Serves the base URLs last updated to, refusing those sharing none with them.
+    struct RecordingUpstreams(Mutex<Vec<String>>);
+
+    impl UpstreamUpdate for RecordingUpstreams {
+        fn update_upstreams(&self, base_urls: &[String]) -> bool {
+            let mut served = self.0.lock().unwrap();
+            if !base_urls.iter().any(|base_url| served.contains(base_url)) {
+                return false;
+            }
+            *served = base_urls.to_vec();
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reloads_update_served_upstreams_in_place() {
+        let with_base_urls =
+            |base_urls: &str| settings(&format!("mighty_server = {{ base_url = {} }}", base_urls));
+        let switch = Arc::new(CountingSwitch::default());
+        let upstreams = Arc::new(RecordingUpstreams(Mutex::new(vec![
+            "http://mighty-a".to_string()
+        ])));
+        let upstream =
+            UpstreamReload::new(switch.clone(), &with_base_urls(r#"["http://mighty-a"]"#))
+                .with_upstreams(upstreams.clone());
+
+        assert!(upstream
+            .reload(&with_base_urls(r#"["http://mighty-a", "http://mighty-b"]"#))
+            .await
+            .unwrap());
+        assert_eq!(
+            *upstreams.0.lock().unwrap(),
+            vec!["http://mighty-a", "http://mighty-b"]
+        );
+        assert_eq!(*switch.0.lock().unwrap(), 0);
+
+        // A whole new set is switched to once healthy
+        assert!(upstream
+            .reload(&with_base_urls(r#"["http://mighty-c"]"#))
+            .await
+            .unwrap());
+        assert_eq!(*switch.0.lock().unwrap(), 1);
+    }
+}
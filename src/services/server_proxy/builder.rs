@@ -0,0 +1,175 @@
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tonic::server::NamedService;
+use tower_layer::{Identity, Layer, Stack};
+use tower_service::Service;
+
+use crate::config::AppSettings;
+use crate::proto::mighty_proto::mighty_inference_server::MightyInferenceServer;
+use crate::services::clients::MightyClient;
+
+use super::{
+    create_mighty_inference_proxy, create_mighty_inference_server_with_proxy,
+    MightyInferenceServerProxy,
+};
+
+/// The `MightyInferenceProxyBuilder` struct assembles a `MightyInferenceServerProxy` and the
+/// inference server serving it for crates embedding the gateway, with their own middleware:
+///
+/// - client decorators (see `decorate`) wrap the client before the proxy is created, e.g. to
+///   transform requests or record metrics per backend call;
+/// - tower layers (see `layer`) wrap the inference server, e.g. to authenticate calls or
+///   transform their metadata, the first added being the outermost.
+///
+/// # Example
+/// ```rust
+/// use mighty_grpc::services::clients::rest::MightyServerRestClient;
+/// use mighty_grpc::services::clients::traced::TracedClient;
+/// use mighty_grpc::services::server_proxy::MightyInferenceServerProxy;
+/// use tonic::{Request, Status};
+///
+/// let client = MightyServerRestClient::new("http://localhost:5050".to_string());
+/// let server = MightyInferenceServerProxy::builder(Box::new(client))
+///     .decorate(|client| Box::new(TracedClient::new(client)))
+///     .layer(tonic::service::interceptor(|request: Request<()>| {
+///         match request.metadata().get("x-tenant") {
+///             Some(_) => Ok(request),
+///             None => Err(Status::invalid_argument("x-tenant is required")),
+///         }
+///     }))
+///     .build_server();
+/// // Serve it with `tonic::transport::Server::builder().add_service(server)`
+/// ```
+pub struct MightyInferenceProxyBuilder<'a, L = Identity> {
+    client: Box<dyn MightyClient>,
+    settings: Option<&'a AppSettings>,
+    layers: L,
+}
+
+impl MightyInferenceServerProxy {
+    /// Returns a builder of the proxy serving the inference service on top of `client`.
+    pub fn builder<'a>(client: Box<dyn MightyClient>) -> MightyInferenceProxyBuilder<'a> {
+        MightyInferenceProxyBuilder {
+            client,
+            settings: None,
+            layers: Identity::new(),
+        }
+    }
+}
+
+impl<'a, L> MightyInferenceProxyBuilder<'a, L> {
+    /// Configures the proxy and the server from `settings`, as the gateway binaries do.
+    pub fn settings(mut self, settings: &'a AppSettings) -> Self {
+        self.settings = Some(settings);
+        self
+    }
+
+    /// Wraps the client in `decorator`, within the decorators added before.
+    pub fn decorate(
+        mut self,
+        decorator: impl FnOnce(Box<dyn MightyClient>) -> Box<dyn MightyClient>,
+    ) -> Self {
+        self.client = decorator(self.client);
+        self
+    }
+
+    /// Wraps the inference server in `layer`, within the layers added before.
+    pub fn layer<T>(self, layer: T) -> MightyInferenceProxyBuilder<'a, Stack<T, L>> {
+        MightyInferenceProxyBuilder {
+            client: self.client,
+            settings: self.settings,
+            layers: Stack::new(layer, self.layers),
+        }
+    }
+
+    /// Builds the proxy, without the layers, e.g. to share it with the `HttpGateway`.
+    pub fn build(self) -> MightyInferenceServerProxy {
+        match self.settings {
+            Some(settings) => create_mighty_inference_proxy(self.client, settings),
+            None => MightyInferenceServerProxy::new(self.client),
+        }
+    }
+
+    /// Builds the inference server, wrapped in the layers.
+    pub fn build_server(self) -> LayeredServer<L::Service>
+    where
+        L: Layer<MightyInferenceServer<MightyInferenceServerProxy>>,
+    {
+        let Self {
+            client,
+            settings,
+            layers,
+        } = self;
+        let server = match settings {
+            Some(settings) => {
+                let proxy = create_mighty_inference_proxy(client, settings);
+                create_mighty_inference_server_with_proxy(Arc::new(proxy), settings)
+            }
+            None => MightyInferenceServer::new(MightyInferenceServerProxy::new(client)),
+        };
+        LayeredServer(layers.layer(server))
+    }
+}
+
+/// The inference server wrapped in the layers of a `MightyInferenceProxyBuilder`, still routed
+/// to by the name of the inference service.
+#[derive(Debug, Clone)]
+pub struct LayeredServer<S>(S);
+
+impl<S> NamedService for LayeredServer<S> {
+    const NAME: &'static str =
+        <MightyInferenceServer<MightyInferenceServerProxy> as NamedService>::NAME;
+}
+
+impl<S: Service<R>, R> Service<R> for LayeredServer<S> {
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        self.0.call(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::codegen::http;
+    use tonic::{Code, Request, Status};
+
+    use crate::services::clients::mock::MockMightyClient;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_builder_applies_decorators_and_layers() {
+        let mut decorated = false;
+        let mut server = MightyInferenceServerProxy::builder(Box::new(MockMightyClient::new()))
+            .decorate(|client| {
+                decorated = true;
+                client
+            })
+            .layer(tonic::service::interceptor(|_: Request<()>| {
+                Err(Status::permission_denied("Rejected by the layer"))
+            }))
+            .build_server();
+        assert!(decorated);
+        assert_eq!(
+            <LayeredServer<()> as NamedService>::NAME,
+            "mighty_inference_server.MightyInference"
+        );
+
+        let request = http::Request::builder()
+            .uri("/mighty_inference_server.MightyInference/Metadata")
+            .header("content-type", "application/grpc")
+            .body(tonic::body::empty_body())
+            .unwrap();
+        let response = server.call(request).await.unwrap();
+        let status = Status::from_header_map(response.headers()).unwrap();
+        assert_eq!(status.code(), Code::PermissionDenied);
+    }
+}
@@ -0,0 +1,329 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use tonic::{Request, Status};
+
+use crate::config::BatchConfig;
+use crate::proto::mighty_proto::{
+    Provenance, StreamEmbeddingsRequest, StreamEmbeddingsResponse, TextEmbeddings,
+};
+use crate::services::clients::MightyClient;
+use crate::services::context::{RequestContext, ServedBy, CACHE_HEADER};
+use crate::services::hash::reference;
+
+use super::preprocessing::{preprocess, Preprocessed};
+
+/// The maximum number of acknowledged references kept per stream, bounding session memory.
+const MAX_ACKNOWLEDGED: usize = 100_000;
+
+/// The `EmbeddingsSession` struct holds the state of one `StreamEmbeddings` stream: the
+/// references of the texts whose vectors the client acknowledged having kept.
+///
+/// In delta mode, texts already acknowledged are answered with their reference only, so clients
+/// embedding overlapping windows of a document only receive (and the upstream only computes)
+/// vectors for the new texts of each window. The other texts of a batch are embedded upstream
+/// in a single batch call.
+///
+/// Texts are preprocessed according to the `BatchConfig` (normalization, truncation and
+/// deduplication within a batch), and callers can request a `Provenance` report per text to
+/// audit what was applied; as texts are embedded together, their vectors are only reported as
+/// cache hits when those of every text of the batch were cached.
+#[derive(Debug, Default)]
+pub struct EmbeddingsSession {
+    config: Arc<BatchConfig>,
+    acknowledged: HashSet<u64>,
+}
+
+impl EmbeddingsSession {
+    pub fn new(config: Arc<BatchConfig>) -> Self {
+        Self {
+            config,
+            acknowledged: HashSet::new(),
+        }
+    }
+
+    /// Embeds the texts of one batch, omitting acknowledged vectors in delta mode.
+    pub async fn respond(
+        &mut self,
+        client: &dyn MightyClient,
+        context: &RequestContext,
+        request: StreamEmbeddingsRequest,
+    ) -> Result<StreamEmbeddingsResponse, Status> {
+        let room = MAX_ACKNOWLEDGED.saturating_sub(self.acknowledged.len());
+        self.acknowledged
+            .extend(request.acknowledged.into_iter().take(room));
+
+        // The texts embedded upstream in one batch call, and the index in `batch` of each
+        // preprocessed text of the batch when deduplicating
+        let mut batch = Vec::new();
+        let mut embedded = HashMap::new();
+        // The index in `batch` of the vectors of each result not omitted
+        let mut sources = Vec::new();
+        let mut results: Vec<TextEmbeddings> = Vec::with_capacity(request.texts.len());
+        for text in request.texts {
+            let reference = reference(&text);
+            if request.delta && self.acknowledged.contains(&reference) {
+                results.push(TextEmbeddings {
+                    reference,
+                    embeddings: Vec::new(),
+                    omitted: true,
+                    provenance: Some(Provenance::default()),
+                });
+                continue;
+            }
+
+            let Preprocessed {
+                text,
+                normalized,
+                truncated,
+            } = preprocess(&self.config, text);
+            let mut provenance = Provenance {
+                normalized,
+                truncated,
+                ..Provenance::default()
+            };
+            let source = match embedded.get(&text) {
+                Some(&source) => {
+                    provenance.deduplicated = true;
+                    source
+                }
+                None => {
+                    if self.config.deduplicate {
+                        embedded.insert(text.clone(), batch.len());
+                    }
+                    batch.push(text);
+                    batch.len() - 1
+                }
+            };
+            sources.push((results.len(), source));
+            results.push(TextEmbeddings {
+                reference,
+                embeddings: Vec::new(),
+                omitted: false,
+                provenance: Some(provenance),
+            });
+        }
+
+        if !batch.is_empty() {
+            let count = batch.len();
+            let mut inner = Request::new(batch);
+            inner.extensions_mut().insert(context.clone());
+            let response = client.embeddings_batch(inner).await?;
+            let cache_hit = response
+                .metadata()
+                .get(CACHE_HEADER)
+                .is_some_and(|value| value == "hit");
+            let upstream = response
+                .extensions()
+                .get::<ServedBy>()
+                .map(|served_by| served_by.0.clone())
+                .unwrap_or_default();
+            let mut embeddings: Vec<_> = response
+                .into_inner()
+                .into_iter()
+                .map(|response| response.embeddings)
+                .collect();
+            if embeddings.len() != count {
+                return Err(Status::internal(format!(
+                    "The embeddings batch response has {} responses for {} texts",
+                    embeddings.len(),
+                    count
+                )));
+            }
+            // Duplicates follow the text they copy, which takes the vectors last
+            for (index, source) in sources.into_iter().rev() {
+                let result = &mut results[index];
+                let provenance = result.provenance.get_or_insert_with(Provenance::default);
+                result.embeddings = if provenance.deduplicated {
+                    embeddings[source].clone()
+                } else {
+                    std::mem::take(&mut embeddings[source])
+                };
+                provenance.cache_hit = cache_hit;
+                provenance.upstream = upstream.clone();
+            }
+        }
+
+        if !request.provenance {
+            for result in &mut results {
+                result.provenance = None;
+            }
+        }
+        Ok(StreamEmbeddingsResponse { results })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+    use tonic::Response;
+
+    use super::*;
+    use crate::proto::mighty_proto::{
+        Embedding, EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse,
+        QuestionAnswerRequest, QuestionAnswerResponse, SentenceTransformersResponse,
+        SequenceClassificationResponse, TextRequest, TokenClassificationResponse,
+    };
+
+    /// Embeds texts into their length, recording the texts of each batch call.
+    #[derive(Default)]
+    struct LengthClient(Mutex<Vec<Vec<String>>>);
+
+    #[async_trait]
+    impl MightyClient for LengthClient {
+        async fn health_check(
+            &self,
+            _request: Request<Empty>,
+        ) -> Result<Response<HealthcheckResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn embeddings(
+            &self,
+            _request: Request<TextRequest>,
+        ) -> Result<Response<EmbeddingsResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn question_answering(
+            &self,
+            _request: Request<QuestionAnswerRequest>,
+        ) -> Result<Response<QuestionAnswerResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn sentence_transformers(
+            &self,
+            _request: Request<TextRequest>,
+        ) -> Result<Response<SentenceTransformersResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn sequence_classification(
+            &self,
+            _request: Request<TextRequest>,
+        ) -> Result<Response<SequenceClassificationResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn token_classification(
+            &self,
+            _request: Request<TextRequest>,
+        ) -> Result<Response<TokenClassificationResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn metadata(
+            &self,
+            _request: Request<Empty>,
+        ) -> Result<Response<MetadataResponse>, Status> {
+            unimplemented!()
+        }
+
+        async fn embeddings_batch(
+            &self,
+            request: Request<Vec<String>>,
+        ) -> Result<Response<Vec<EmbeddingsResponse>>, Status> {
+            let texts = request.into_inner();
+            self.0.lock().unwrap().push(texts.clone());
+            let responses = texts
+                .into_iter()
+                .map(|text| EmbeddingsResponse {
+                    embeddings: vec![Embedding {
+                        values: vec![text.len() as f32],
+                    }],
+                    text,
+                    ..Default::default()
+                })
+                .collect();
+            Ok(Response::new(responses))
+        }
+    }
+
+    fn request(texts: &[&str], delta: bool, acknowledged: &[&str]) -> StreamEmbeddingsRequest {
+        StreamEmbeddingsRequest {
+            texts: texts.iter().map(|text| text.to_string()).collect(),
+            delta,
+            acknowledged: acknowledged.iter().map(|text| reference(text)).collect(),
+            provenance: false,
+        }
+    }
+
+    fn omitted(response: &StreamEmbeddingsResponse) -> Vec<bool> {
+        response
+            .results
+            .iter()
+            .map(|result| result.omitted)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_acknowledged_texts_are_omitted_in_delta_mode() {
+        let client = LengthClient::default();
+        let context = RequestContext::default();
+        let mut session = EmbeddingsSession::new(Arc::default());
+        let response = session
+            .respond(
+                &client,
+                &context,
+                request(&["a", "bb", "ccc"], true, &["bb"]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(omitted(&response), [false, true, false]);
+        assert_eq!(response.results[1].reference, reference("bb"));
+        assert!(response.results[1].embeddings.is_empty());
+        assert_eq!(response.results[2].embeddings[0].values, [3.0]);
+        // The texts not omitted are embedded in one batch call
+        assert_eq!(*client.0.lock().unwrap(), [vec!["a", "ccc"]]);
+
+        // Acknowledgements hold for the rest of the stream
+        let response = session
+            .respond(&client, &context, request(&["bb", "dddd"], true, &[]))
+            .await
+            .unwrap();
+        assert_eq!(omitted(&response), [true, false]);
+    }
+
+    #[tokio::test]
+    async fn test_acknowledgements_are_ignored_without_delta_mode() {
+        let client = LengthClient::default();
+        let mut session = EmbeddingsSession::new(Arc::default());
+        let response = session
+            .respond(
+                &client,
+                &RequestContext::default(),
+                request(&["a", "bb"], false, &["a", "bb"]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(omitted(&response), [false, false]);
+        assert_eq!(response.results[1].embeddings[0].values, [2.0]);
+        assert_eq!(*client.0.lock().unwrap(), [vec!["a", "bb"]]);
+    }
+
+    #[tokio::test]
+    async fn test_acknowledged_references_are_capped() {
+        let client = LengthClient::default();
+        let mut session = EmbeddingsSession::new(Arc::default());
+        let mut request = request(&[], true, &[]);
+        request.acknowledged = (0..MAX_ACKNOWLEDGED as u64 + 10).collect();
+        session
+            .respond(&client, &RequestContext::default(), request.clone())
+            .await
+            .unwrap();
+        assert_eq!(session.acknowledged.len(), MAX_ACKNOWLEDGED);
+
+        request.acknowledged = vec![u64::MAX];
+        session
+            .respond(&client, &RequestContext::default(), request)
+            .await
+            .unwrap();
+        assert_eq!(session.acknowledged.len(), MAX_ACKNOWLEDGED);
+        assert!(!session.acknowledged.contains(&u64::MAX));
+        // Empty batches are not sent upstream
+        assert!(client.0.lock().unwrap().is_empty());
+    }
+}
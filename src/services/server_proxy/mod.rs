@@ -1,12 +1,51 @@
-use tonic::{Request, Response, Status};
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
+use futures::stream::{self, BoxStream};
+use prost::Message;
+use tonic::codec::CompressionEncoding;
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::config::{
+    AdminConfig, AppSettings, BatchConfig, CompressionConfig, CompressionKind, DebugConfig,
+    EndpointsConfig, RecentlySimilarConfig, RequestValidationConfig, TimeoutsConfig,
+};
 use crate::proto::mighty_proto::{
     EmbeddingsResponse, Empty, HealthcheckResponse, MetadataResponse, QuestionAnswerRequest,
-    QuestionAnswerResponse, SentenceTransformersResponse, SequenceClassificationResponse,
-    TextRequest, TokenClassificationResponse,
+    QuestionAnswerResponse, RecentlySimilarRequest, RecentlySimilarResponse, RerankRequest,
+    RerankResponse, SentenceTransformersResponse, SequenceClassificationResponse, StreamEmbeddingsRequest,
+    StreamEmbeddingsResponse, TextRequest, TokenClassificationResponse,
 };
 use crate::proto::mighty_proto::mighty_inference_server::{MightyInference, MightyInferenceServer};
+use crate::services::access_log::AccessRecord;
 use crate::services::clients::MightyClient;
+use crate::services::context::{RequestContext, RAW_JSON_METADATA, RAW_JSON_SIZE_HEADER};
+use crate::services::metrics::Metrics;
+use crate::services::panic_recovery::recover;
+use crate::services::reload::Reloadable;
+
+pub use builder::{LayeredServer, MightyInferenceProxyBuilder};
+use embeddings_stream::EmbeddingsSession;
+use recently_similar::{mean_pool, SimilarityWindow};
+use validation::Validate;
+
+pub mod builder;
+pub mod embeddings_stream;
+pub mod preprocessing;
+pub mod recently_similar;
+pub mod validation;
+
+/// Counter of calls that timed out server-side, by `method`.
+const TIMEOUTS_METRIC: &str = "mighty_server_timeouts_total";
+
+/// Counter of calls rejected for invalid texts before reaching the client, by `method`.
+const INVALID_REQUESTS_METRIC: &str = "mighty_invalid_requests_total";
+
+/// The most raw upstream JSON returned to a caller, in bytes, so the response metadata stays
+/// within the header list size limits of HTTP/2 clients (16 KiB by default) once base64 encoded.
+const MAX_RAW_JSON_SIZE: usize = 8 * 1024;
 
 /// The `MightyInferenceServerProxy` struct acts as a proxy to interact with the Mighty Inference
 /// Services.
@@ -20,6 +59,31 @@ use crate::services::clients::MightyClient;
 /// - Supports multiple client implementations.
 /// - Provides a unified interface to interact with various inference services.
 /// - Easily extendable to add new service methods or client types.
+/// - Attaches a `RequestContext` (request id, tenant, identity, deadline, priority) to every
+///   request before it reaches the client.
+/// - Serves `StreamEmbeddings` on top of the client's `embeddings`, omitting vectors the caller
+///   already acknowledged within the stream in delta mode (see `EmbeddingsSession`), and
+///   preprocessing batch texts according to the `BatchConfig` (see `with_batch_config`).
+/// - Serves `RecentlySimilar` by embedding texts and comparing them to a bounded window of
+///   recent embeddings (see `SimilarityWindow` and `with_recently_similar_config`).
+/// - Leaves the responses of the methods configured so uncompressed (see
+///   `with_compression_config`).
+/// - Fails the calls of the methods disabled by the `EndpointsConfig` with `UNIMPLEMENTED`,
+///   without reaching the client (see `with_endpoints_config`).
+/// - Rejects requests with empty or control character laden texts, or texts over the configured
+///   limits, with `INVALID_ARGUMENT` rather than forwarding them (see
+///   `with_request_validation_config`).
+/// - Fails calls running past the timeout of their method with `DEADLINE_EXCEEDED`, shortening
+///   their deadline so their upstream calls are bounded too (see `with_timeouts_config`).
+/// - Only honors `x-mighty-debug: raw-json` for the admins allowed raw upstream JSON by the
+///   `DebugConfig`, so other callers can't bypass the cache (see `with_debug_config`), and
+///   truncates the raw JSON returned to `MAX_RAW_JSON_SIZE`.
+/// - Fails calls whose client panics with `INTERNAL`, rather than tearing down their connection
+///   or stream (see `recover`).
+/// - Can be assembled with client decorators and tower layers of the embedding crate (see
+///   `MightyInferenceServerProxy::builder`).
+/// - Forwards client responses and errors untouched, preserving the `Status` code reported by
+///   the client and avoiding per-request re-formatting of error messages.
 ///
 /// # Example
 /// ```rust
@@ -32,13 +96,261 @@ use crate::services::clients::MightyClient;
 /// // Use the proxy to interact with inference services
 /// ```
 pub struct MightyInferenceServerProxy {
-    client: Box<dyn MightyClient>,
+    client: Arc<dyn MightyClient>,
+    batch: Arc<BatchConfig>,
+    similarity_window: Arc<SimilarityWindow>,
+    similarity_threshold: f32,
+    compression: Arc<CompressionConfig>,
+    timeouts: RwLock<Arc<TimeoutsConfig>>,
+    request_validation: Arc<RequestValidationConfig>,
+    endpoints: Arc<EndpointsConfig>,
+    raw_json_identities: Arc<Vec<String>>,
 }
 
 impl MightyInferenceServerProxy {
     pub fn new(client: Box<dyn MightyClient>) -> Self {
-        Self { client }
+        Self {
+            client: Arc::from(client),
+            batch: Arc::default(),
+            similarity_window: Arc::new(SimilarityWindow::new(&RecentlySimilarConfig::default())),
+            similarity_threshold: RecentlySimilarConfig::default().threshold,
+            compression: Arc::default(),
+            timeouts: RwLock::default(),
+            request_validation: Arc::default(),
+            endpoints: Arc::default(),
+            raw_json_identities: Arc::default(),
+        }
+    }
+
+    /// Sets the preprocessing applied to the texts of `StreamEmbeddings` batches.
+    pub fn with_batch_config(mut self, batch: BatchConfig) -> Self {
+        self.batch = Arc::new(batch);
+        self
+    }
+
+    /// Sets the size, TTL and default threshold of the window `RecentlySimilar` checks against.
+    pub fn with_recently_similar_config(mut self, config: &RecentlySimilarConfig) -> Self {
+        self.similarity_window = Arc::new(SimilarityWindow::new(config));
+        self.similarity_threshold = config.threshold;
+        self
+    }
+
+    /// Sets the methods whose responses are compressed, when the caller accepts it.
+    pub fn with_compression_config(mut self, config: &CompressionConfig) -> Self {
+        self.compression = Arc::new(config.clone());
+        self
     }
+
+    /// Sets the timeouts of the calls of each method.
+    pub fn with_timeouts_config(self, config: &TimeoutsConfig) -> Self {
+        *self.timeouts.write().unwrap() = Arc::new(config.clone());
+        self
+    }
+
+    /// Returns the timeouts of the calls of each method, as last reloaded.
+    fn timeouts(&self) -> Arc<TimeoutsConfig> {
+        self.timeouts.read().unwrap().clone()
+    }
+
+    /// Sets the limits on the length of the texts of requests.
+    pub fn with_request_validation_config(mut self, config: &RequestValidationConfig) -> Self {
+        self.request_validation = Arc::new(config.clone());
+        self
+    }
+
+    /// Sets the methods served, the others failing with `UNIMPLEMENTED`.
+    pub fn with_endpoints_config(mut self, config: &EndpointsConfig) -> Self {
+        self.endpoints = Arc::new(config.clone());
+        self
+    }
+
+    /// Sets the callers whose `x-mighty-debug: raw-json` is honored: the identities of `admin`
+    /// when `debug` allows raw upstream JSON, none otherwise.
+    pub fn with_debug_config(mut self, debug: &DebugConfig, admin: &AdminConfig) -> Self {
+        let identities = match debug.raw_json {
+            true => admin.identities.clone(),
+            false => Vec::new(),
+        };
+        self.raw_json_identities = Arc::new(identities);
+        self
+    }
+
+    /// Fails the calls of `method` if it isn't served.
+    fn check_served(&self, method: &str) -> Result<(), Status> {
+        if self.endpoints.serves(method) {
+            return Ok(());
+        }
+        Err(Status::unimplemented(format!(
+            "{} is disabled on this gateway",
+            method
+        )))
+    }
+
+    /// Embeds a single batch of texts like a `StreamEmbeddings` batch, outside of any stream.
+    pub(crate) async fn embed_batch(
+        &self,
+        request: Request<StreamEmbeddingsRequest>,
+    ) -> Result<Response<StreamEmbeddingsResponse>, Status> {
+        self.serve("stream_embeddings", request, |request| async move {
+            let context = RequestContext::get(&request).cloned().unwrap_or_default();
+            EmbeddingsSession::new(self.batch.clone())
+                .respond(self.client.as_ref(), &context, request.into_inner())
+                .await
+                .map(Response::new)
+        })
+        .await
+    }
+
+    /// Checks whether the text of `request` is a near-duplicate of a recent one, then remembers it.
+    async fn check_recently_similar(
+        &self,
+        request: Request<RecentlySimilarRequest>,
+    ) -> Result<Response<RecentlySimilarResponse>, Status> {
+        let context = RequestContext::get(&request).cloned().unwrap_or_default();
+        // Windows are per caller, so texts are never compared with those of other callers
+        let caller = context.identity.clone();
+        let RecentlySimilarRequest { text, threshold } = request.into_inner();
+        let threshold = match threshold {
+            0.0 => self.similarity_threshold,
+            threshold => threshold,
+        };
+
+        let reference = reference(&text);
+        let mut embeddings_request = Request::new(TextRequest { text });
+        embeddings_request.extensions_mut().insert(context);
+        let embeddings = self.client.embeddings(embeddings_request).await?;
+        let vector = mean_pool(embeddings.get_ref())
+            .ok_or_else(|| Status::internal("No embeddings returned for the text"))?;
+
+        let closest =
+            self.similarity_window
+                .check_and_insert(caller.as_deref(), &vector, reference);
+        let response = match closest {
+            Some(closest) if closest.similarity >= threshold => RecentlySimilarResponse {
+                similar: true,
+                similarity: closest.similarity,
+                reference: closest.reference,
+            },
+            Some(closest) => RecentlySimilarResponse {
+                similarity: closest.similarity,
+                ..Default::default()
+            },
+            None => RecentlySimilarResponse::default(),
+        };
+        Ok(Response::new(response))
+    }
+
+    /// Serves a unary call of `method`, unless disabled: attaches the `RequestContext` of
+    /// `request`, validates its texts, has `call` answer it within the timeout of the method,
+    /// failing it with `INTERNAL` if it panics, and compresses the response as configured.
+    async fn serve<T, R, F, Fut>(
+        &self,
+        method: &str,
+        request: Request<T>,
+        call: F,
+    ) -> Result<Response<R>, Status>
+    where
+        T: Message + Validate,
+        F: FnOnce(Request<T>) -> Fut,
+        Fut: Future<Output = Result<Response<R>, Status>>,
+    {
+        self.check_served(method)?;
+        let mut request = attach(request);
+        request
+            .get_ref()
+            .validate(&self.request_validation)
+            .map_err(|status| invalid(method, status))?;
+        let timeout = self.timeouts().timeout(method);
+        if let Some(context) = request.extensions_mut().get_mut::<RequestContext>() {
+            restrict_raw_json(context, &self.raw_json_identities);
+            if let Some(timeout) = timeout {
+                shorten_deadline(context, timeout);
+            }
+        }
+        let response = recover(method, call(request));
+        let response = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, response)
+                .await
+                .unwrap_or_else(|_| Err(timed_out(method, timeout))),
+            None => response.await,
+        };
+        self.compressed(method, truncate_raw_json(response))
+    }
+
+    /// Disables the compression of the response of `method` if it isn't compressed.
+    fn compressed<T>(
+        &self,
+        method: &str,
+        result: Result<Response<T>, Status>,
+    ) -> Result<Response<T>, Status> {
+        result.map(|mut response| {
+            if !self.compression.compresses(method) {
+                response.disable_compression();
+            }
+            response
+        })
+    }
+}
+
+/// Clears the raw JSON request of `context` unless its caller is one of `identities`.
+fn restrict_raw_json(context: &mut RequestContext, identities: &[String]) {
+    context.raw_json &= context
+        .identity
+        .as_ref()
+        .is_some_and(|identity| identities.contains(identity));
+}
+
+/// Truncates the raw upstream JSON attached to a response (or error) over `MAX_RAW_JSON_SIZE`,
+/// giving its full size in `x-mighty-raw-json-size`.
+fn truncate_raw_json<T>(mut result: Result<Response<T>, Status>) -> Result<Response<T>, Status> {
+    let metadata: &mut MetadataMap = match &mut result {
+        Ok(response) => response.metadata_mut(),
+        Err(status) => status.metadata_mut(),
+    };
+    let Some(body) = metadata
+        .get_bin(RAW_JSON_METADATA)
+        .and_then(|value| value.to_bytes().ok())
+    else {
+        return result;
+    };
+    if body.len() > MAX_RAW_JSON_SIZE {
+        metadata.insert(RAW_JSON_SIZE_HEADER, MetadataValue::from(body.len()));
+        metadata.insert_bin(
+            RAW_JSON_METADATA,
+            MetadataValue::from_bytes(&body[..MAX_RAW_JSON_SIZE]),
+        );
+    }
+    result
+}
+
+/// Brings the deadline of `context` forward to `timeout` from now, if later.
+fn shorten_deadline(context: &mut RequestContext, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    context.deadline = Some(context.deadline.map_or(deadline, |d| d.min(deadline)));
+}
+
+/// Counts a call of `method` that timed out after `timeout`, returning the status it fails with.
+fn timed_out(method: &str, timeout: Duration) -> Status {
+    Metrics::global()
+        .counter(TIMEOUTS_METRIC, &[("method", method)])
+        .increment(1);
+    Status::deadline_exceeded(format!("{} timed out after {:?}", method, timeout))
+}
+
+/// Counts a call of `method` rejected for invalid texts, returning the status it fails with.
+fn invalid(method: &str, status: Status) -> Status {
+    Metrics::global()
+        .counter(INVALID_REQUESTS_METRIC, &[("method", method)])
+        .increment(1);
+    status
+}
+
+/// Attaches the `RequestContext` of a unary `request`, reporting its size to its access log line.
+fn attach<T: Message>(request: Request<T>) -> Request<T> {
+    if let Some(access_record) = AccessRecord::of(&request) {
+        access_record.set_request_size(request.get_ref().encoded_len());
+    }
+    RequestContext::attach(request)
 }
 
 #[tonic::async_trait]
@@ -47,93 +359,324 @@ impl MightyInference for MightyInferenceServerProxy {
         &self,
         request: Request<TextRequest>,
     ) -> Result<Response<EmbeddingsResponse>, Status> {
-        let embeddings = self
-            .client
-            .embeddings(request)
-            .await
-            .map_err(|e| Status::internal(format!("Error fetching embeddings: {}", e)))?;
-        Ok(embeddings)
+        self.serve("embeddings", request, |request| {
+            self.client.embeddings(request)
+        })
+        .await
     }
 
     async fn question_answering(
         &self,
         request: Request<QuestionAnswerRequest>,
     ) -> Result<Response<QuestionAnswerResponse>, Status> {
-        let answer = self
-            .client
-            .question_answering(request)
-            .await
-            .map_err(|e| Status::internal(format!("Error fetching question answering: {}", e)))?;
-        Ok(answer)
+        self.serve("question_answering", request, |request| {
+            self.client.question_answering(request)
+        })
+        .await
     }
 
     async fn sentence_transformers(
         &self,
         request: Request<TextRequest>,
     ) -> Result<Response<SentenceTransformersResponse>, Status> {
-        let response = self
-            .client
-            .sentence_transformers(request)
-            .await
-            .map_err(|e| {
-                Status::internal(format!("Error fetching sentence transformers: {}", e))
-            })?;
-        Ok(response)
+        self.serve("sentence_transformers", request, |request| {
+            self.client.sentence_transformers(request)
+        })
+        .await
     }
 
     async fn sequence_classification(
         &self,
         request: Request<TextRequest>,
     ) -> Result<Response<SequenceClassificationResponse>, Status> {
-        let response = self
-            .client
-            .sequence_classification(request)
-            .await
-            .map_err(|e| {
-                Status::internal(format!("Error fetching sequence classification: {}", e))
-            })?;
-        Ok(response)
+        self.serve("sequence_classification", request, |request| {
+            self.client.sequence_classification(request)
+        })
+        .await
     }
 
     async fn token_classification(
         &self,
         request: Request<TextRequest>,
     ) -> Result<Response<TokenClassificationResponse>, Status> {
-        let response = self
-            .client
-            .token_classification(request)
-            .await
-            .map_err(|e| Status::internal(format!("Error fetching token classification: {}", e)))?;
-        Ok(response)
+        self.serve("token_classification", request, |request| {
+            self.client.token_classification(request)
+        })
+        .await
     }
 
     async fn metadata(
         &self,
         request: Request<Empty>,
     ) -> Result<Response<MetadataResponse>, Status> {
-        let response = self
-            .client
-            .metadata(request)
+        self.serve("metadata", request, |request| self.client.metadata(request))
             .await
-            .map_err(|e| Status::internal(format!("Error fetching metadata: {}", e)))?;
-        Ok(response)
     }
 
     async fn health_check(
         &self,
         request: Request<Empty>,
     ) -> Result<Response<HealthcheckResponse>, Status> {
-        let response = self
-            .client
-            .health_check(request)
+        self.serve("health_check", request, |request| {
+            self.client.health_check(request)
+        })
+        .await
+    }
+
+    async fn rerank(
+        &self,
+        request: Request<RerankRequest>,
+    ) -> Result<Response<RerankResponse>, Status> {
+        self.serve("rerank", request, |request| self.client.rerank(request))
             .await
-            .map_err(|e| Status::internal(format!("Error getting healthcheck: {}", e)))?;
-        Ok(response)
+    }
+
+    type StreamEmbeddingsStream = BoxStream<'static, Result<StreamEmbeddingsResponse, Status>>;
+
+    async fn stream_embeddings(
+        &self,
+        request: Request<Streaming<StreamEmbeddingsRequest>>,
+    ) -> Result<Response<Self::StreamEmbeddingsStream>, Status> {
+        self.check_served("stream_embeddings")?;
+        let request = RequestContext::attach(request);
+        let mut context = RequestContext::get(&request).cloned().unwrap_or_default();
+        restrict_raw_json(&mut context, &self.raw_json_identities);
+        let timeout = self.timeouts().timeout("stream_embeddings");
+        let request_validation = self.request_validation.clone();
+        let state = (
+            request.into_inner(),
+            self.client.clone(),
+            EmbeddingsSession::new(self.batch.clone()),
+        );
+        let responses = stream::unfold(state, move |(mut inbound, client, mut session)| {
+            let mut context = context.clone();
+            let request_validation = request_validation.clone();
+            async move {
+                let batch = match inbound.message().await {
+                    Ok(Some(batch)) => batch,
+                    Ok(None) => return None,
+                    Err(status) => return Some((Err(status), (inbound, client, session))),
+                };
+                if let Err(status) = batch.validate(&request_validation) {
+                    let status = invalid("stream_embeddings", status);
+                    return Some((Err(status), (inbound, client, session)));
+                }
+                // Each batch is bounded by the timeout, not the whole stream
+                if let Some(timeout) = timeout {
+                    shorten_deadline(&mut context, timeout);
+                }
+                let response = recover(
+                    "stream_embeddings",
+                    session.respond(client.as_ref(), &context, batch),
+                );
+                let response = match timeout {
+                    Some(timeout) => tokio::time::timeout(timeout, response)
+                        .await
+                        .unwrap_or_else(|_| Err(timed_out("stream_embeddings", timeout))),
+                    None => response.await,
+                };
+                Some((response, (inbound, client, session)))
+            }
+        });
+        self.compressed("stream_embeddings", Ok(Response::new(Box::pin(responses))))
+    }
+
+    async fn recently_similar(
+        &self,
+        request: Request<RecentlySimilarRequest>,
+    ) -> Result<Response<RecentlySimilarResponse>, Status> {
+        self.serve("recently_similar", request, |request| {
+            self.check_recently_similar(request)
+        })
+        .await
     }
 }
 
+#[tonic::async_trait]
+impl Reloadable for MightyInferenceServerProxy {
+    fn section(&self) -> &'static str {
+        "timeouts"
+    }
+
+    async fn reload(&self, settings: &AppSettings) -> Result<bool, String> {
+        let mut timeouts = self.timeouts.write().unwrap();
+        if **timeouts == settings.timeouts {
+            return Ok(false);
+        }
+        *timeouts = Arc::new(settings.timeouts.clone());
+        Ok(true)
+    }
+}
+
+/// Creates the proxy serving the inference service on top of `client`, as configured.
+pub fn create_mighty_inference_proxy(
+    client: Box<dyn MightyClient>,
+    settings: &AppSettings,
+) -> MightyInferenceServerProxy {
+    MightyInferenceServerProxy::new(client)
+        .with_batch_config(settings.batch.clone())
+        .with_recently_similar_config(&settings.recently_similar)
+        .with_compression_config(&settings.compression)
+        .with_timeouts_config(&settings.timeouts)
+        .with_request_validation_config(&settings.request_validation)
+        .with_endpoints_config(&settings.endpoints)
+        .with_debug_config(&settings.debug, &settings.admin)
+}
+
 pub fn create_mighty_inference_server(
     client: Box<dyn MightyClient>,
+    settings: &AppSettings,
 ) -> MightyInferenceServer<MightyInferenceServerProxy> {
-    MightyInferenceServer::new(MightyInferenceServerProxy::new(client))
+    let proxy = create_mighty_inference_proxy(client, settings);
+    create_mighty_inference_server_with_proxy(Arc::new(proxy), settings)
+}
+
+/// Creates the inference server on top of a `proxy` also serving other endpoints, e.g. those of
+/// the `HttpGateway`, so they share its state (e.g. the `RecentlySimilar` window).
+pub fn create_mighty_inference_server_with_proxy(
+    proxy: Arc<MightyInferenceServerProxy>,
+    settings: &AppSettings,
+) -> MightyInferenceServer<MightyInferenceServerProxy> {
+    let compression = &settings.compression;
+    let mut server = MightyInferenceServer::from_arc(proxy);
+    for &kind in &compression.accept {
+        server = server.accept_compressed(encoding(kind));
+    }
+    for &kind in &compression.send {
+        server = server.send_compressed(encoding(kind));
+    }
+    if let Some(size) = settings.grpc_server.max_receive_message_size {
+        server = server.max_decoding_message_size(size as usize);
+    }
+    if let Some(size) = settings.grpc_server.max_send_message_size {
+        server = server.max_encoding_message_size(size as usize);
+    }
+    server
+}
+
+fn encoding(kind: CompressionKind) -> CompressionEncoding {
+    match kind {
+        CompressionKind::Gzip => CompressionEncoding::Gzip,
+        CompressionKind::Zstd => CompressionEncoding::Zstd,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tonic::Code;
+
+    use crate::config::Task;
+    use crate::services::clients::mock::MockMightyClient;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_calls_time_out_per_method() {
+        let client = MockMightyClient::new().with_latency(Duration::from_millis(200));
+        let proxy = MightyInferenceServerProxy::new(Box::new(client)).with_timeouts_config(
+            &TimeoutsConfig {
+                default: None,
+                methods: HashMap::from([("embeddings".to_string(), Duration::from_millis(20))]),
+            },
+        );
+        let text = || {
+            Request::new(TextRequest {
+                text: "hello".to_string(),
+            })
+        };
+
+        let status = proxy.embeddings(text()).await.unwrap_err();
+        assert_eq!(status.code(), Code::DeadlineExceeded);
+        assert!(proxy.sentence_transformers(text()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_recently_similar_serves_anonymous_callers() {
+        let proxy = MightyInferenceServerProxy::new(Box::new(MockMightyClient::new()));
+        let check = |text: &str| {
+            proxy.recently_similar(Request::new(RecentlySimilarRequest {
+                text: text.to_string(),
+                threshold: 0.0,
+            }))
+        };
+
+        let response = check("hello").await.unwrap().into_inner();
+        assert!(!response.similar);
+        let response = check("hello").await.unwrap().into_inner();
+        assert!(response.similar);
+        assert_eq!(response.reference, reference("hello"));
+    }
+
+    #[tokio::test]
+    async fn test_disabled_methods_are_unimplemented() {
+        let client = MockMightyClient::new().with_error(
+            Task::TokenClassification,
+            Status::internal("no NER model loaded"),
+        );
+        let proxy = MightyInferenceServerProxy::new(Box::new(client)).with_endpoints_config(
+            &EndpointsConfig {
+                methods: HashMap::from([("token_classification".to_string(), false)]),
+            },
+        );
+        let text = || {
+            Request::new(TextRequest {
+                text: "hello".to_string(),
+            })
+        };
+
+        let status = proxy.token_classification(text()).await.unwrap_err();
+        assert_eq!(status.code(), Code::Unimplemented);
+        assert_eq!(
+            status.message(),
+            "token_classification is disabled on this gateway"
+        );
+        assert!(proxy.embeddings(text()).await.is_ok());
+    }
+
+    #[test]
+    fn test_raw_json_is_reserved_to_allowed_identities() {
+        let identities = vec!["ops".to_string()];
+        let context = |identity: Option<&str>| RequestContext {
+            identity: identity.map(str::to_string),
+            raw_json: true,
+            ..Default::default()
+        };
+
+        let mut admin = context(Some("ops"));
+        restrict_raw_json(&mut admin, &identities);
+        assert!(admin.raw_json);
+
+        for mut other in [context(Some("search")), context(None)] {
+            restrict_raw_json(&mut other, &identities);
+            assert!(!other.raw_json);
+        }
+        let mut disabled = context(Some("ops"));
+        restrict_raw_json(&mut disabled, &[]);
+        assert!(!disabled.raw_json);
+    }
+
+    #[test]
+    fn test_raw_json_is_truncated() {
+        let with_raw_json = |size: usize| {
+            let mut response = Response::new(Empty {});
+            response.metadata_mut().insert_bin(
+                RAW_JSON_METADATA,
+                MetadataValue::from_bytes(&vec![b' '; size]),
+            );
+            Ok(response)
+        };
+
+        let small = truncate_raw_json(with_raw_json(100)).unwrap();
+        let raw_json = small.metadata().get_bin(RAW_JSON_METADATA).unwrap();
+        assert_eq!(raw_json.to_bytes().unwrap().len(), 100);
+        assert!(small.metadata().get(RAW_JSON_SIZE_HEADER).is_none());
+
+        let large = truncate_raw_json(with_raw_json(20_000)).unwrap();
+        let raw_json = large.metadata().get_bin(RAW_JSON_METADATA).unwrap();
+        assert_eq!(raw_json.to_bytes().unwrap().len(), MAX_RAW_JSON_SIZE);
+        let size = large.metadata().get(RAW_JSON_SIZE_HEADER).unwrap();
+        assert_eq!(size.to_str().unwrap(), "20000");
+    }
 }
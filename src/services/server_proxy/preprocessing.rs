@@ -0,0 +1,72 @@
+use crate::config::BatchConfig;
+
+/// A batch text after the configured preprocessing, with what was applied to it.
+#[derive(Debug, PartialEq)]
+pub struct Preprocessed {
+    pub text: String,
+    /// Whether normalization changed the text.
+    pub normalized: bool,
+    /// Whether the text was truncated to `max_text_chars`.
+    pub truncated: bool,
+}
+
+/// Applies the preprocessing configured in `config` to `text`: whitespace normalization first,
+/// then truncation, so the length limit applies to the normalized text.
+pub fn preprocess(config: &BatchConfig, text: String) -> Preprocessed {
+    let mut normalized = false;
+    let mut text = if config.normalize {
+        let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        normalized = collapsed != text;
+        collapsed
+    } else {
+        text
+    };
+
+    let mut truncated = false;
+    if let Some(max_chars) = config.max_text_chars {
+        if let Some((end, _)) = text.char_indices().nth(max_chars) {
+            text.truncate(end);
+            truncated = true;
+        }
+    }
+
+    Preprocessed {
+        text,
+        normalized,
+        truncated,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preprocess() {
+        let config = BatchConfig {
+            normalize: true,
+            deduplicate: false,
+            max_text_chars: Some(7),
+        };
+        assert_eq!(
+            preprocess(&config, "  héllo \n world ".to_string()),
+            Preprocessed {
+                text: "héllo w".to_string(),
+                normalized: true,
+                truncated: true,
+            }
+        );
+        assert_eq!(
+            preprocess(&config, "hello".to_string()),
+            Preprocessed {
+                text: "hello".to_string(),
+                normalized: false,
+                truncated: false,
+            }
+        );
+        assert_eq!(
+            preprocess(&BatchConfig::default(), " as is ".to_string()).text,
+            " as is "
+        );
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::{HashMap, VecDeque};
+use std::mem::size_of;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::RecentlySimilarConfig;
+use crate::proto::mighty_proto::EmbeddingsResponse;
+use crate::services::clients::caching::{CACHE_ENTRIES_METRIC, CACHE_MEMORY_METRIC};
+use crate::services::metrics::{Gauge, Metrics};
+
+/// The closest recent text to a checked embedding.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Match {
+    /// The cosine similarity between both embeddings.
+    pub similarity: f32,
+    /// The reference of the recent text (see `hash::reference`).
+    pub reference: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// The unit-normalized embedding, so cosine similarity is a dot product.
+    vector: Vec<f32>,
+    reference: u64,
+    inserted: Instant,
+}
+
+impl Entry {
+    /// Returns an estimate of the memory held by the entry.
+    fn size(&self) -> i64 {
+        (size_of::<Entry>() + self.vector.len() * size_of::<f32>()) as i64
+    }
+}
+
+/// The entries held by the windows of every caller.
+#[derive(Debug)]
+struct Usage {
+    entries: AtomicUsize,
+    memory_gauge: Arc<Gauge>,
+    entries_gauge: Arc<Gauge>,
+}
+
+/// The recent embeddings of a caller, in insertion order.
+#[derive(Debug)]
+struct Window {
+    entries: VecDeque<Entry>,
+    usage: Arc<Usage>,
+}
+
+impl Window {
+    fn push(&mut self, entry: Entry) {
+        self.usage.memory_gauge.add(entry.size());
+        self.usage.entries_gauge.add(1);
+        self.usage.entries.fetch_add(1, Ordering::Relaxed);
+        self.entries.push_back(entry);
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some(entry) = self.entries.pop_front() {
+            self.usage.memory_gauge.add(-entry.size());
+            self.usage.entries_gauge.add(-1);
+            self.usage.entries.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Evicts the entries inserted `ttl` or longer before `now`.
+    fn evict_expired(&mut self, now: Instant, ttl: Duration) {
+        while self
+            .entries
+            .front()
+            .is_some_and(|entry| now.duration_since(entry.inserted) >= ttl)
+        {
+            self.evict_oldest();
+        }
+    }
+
+    fn clear(&mut self) {
+        while !self.entries.is_empty() {
+            self.evict_oldest();
+        }
+    }
+}
+
+impl Drop for Window {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[derive(Debug)]
+struct Caller {
+    window: Arc<Mutex<Window>>,
+    /// When the caller last checked a text.
+    used: Instant,
+}
+
+#[derive(Debug)]
+struct Callers {
+    /// The windows of callers by identity, anonymous callers sharing the window of `None`.
+    windows: HashMap<Option<String>, Caller>,
+    /// When expired and empty windows were last dropped.
+    pruned: Instant,
+}
+
+/// The `SimilarityWindow` struct is a bounded, index-free memory of recent embeddings, backing
+/// the `RecentlySimilar` RPC.
+///
+/// Each caller has a window of its own, so texts are never compared with, nor revealed to, other
+/// callers; callers without an identity, e.g. when no authentication is configured, share a single
+/// anonymous window. Embeddings are kept in insertion order and compared by brute force, which is
+/// fast enough for windows of a few thousand entries and needs no vector index; checks of a caller
+/// are serialized, so concurrent identical texts of a caller match each other. Entries older than
+/// the configured TTL are evicted on insertion, and the oldest entries make room once the window is
+/// full. Once per TTL, the windows left empty by expiry are dropped, and the windows of the least
+/// recently active callers are dropped once the entries of every caller exceed `max_entries`. The
+/// estimated memory and entries of every window are published by the `mighty_cache_memory_bytes`
+/// and `mighty_cache_entries` gauges.
+#[derive(Debug)]
+pub struct SimilarityWindow {
+    capacity: usize,
+    max_entries: usize,
+    ttl: Duration,
+    callers: Mutex<Callers>,
+    usage: Arc<Usage>,
+}
+
+impl SimilarityWindow {
+    pub fn new(config: &RecentlySimilarConfig) -> Self {
+        Self {
+            capacity: config.window_size.min(config.max_entries),
+            max_entries: config.max_entries,
+            ttl: config.ttl,
+            callers: Mutex::new(Callers {
+                windows: HashMap::new(),
+                pruned: Instant::now(),
+            }),
+            usage: Arc::new(Usage {
+                entries: AtomicUsize::new(0),
+                memory_gauge: Metrics::global()
+                    .gauge(CACHE_MEMORY_METRIC, &[("cache", "recently_similar")]),
+                entries_gauge: Metrics::global()
+                    .gauge(CACHE_ENTRIES_METRIC, &[("cache", "recently_similar")]),
+            }),
+        }
+    }
+
+    /// Returns the window of `caller`, dropping expired and empty windows once per TTL.
+    fn window(&self, caller: Option<&str>, now: Instant) -> Arc<Mutex<Window>> {
+        let mut callers = self.callers.lock().unwrap();
+        if now.duration_since(callers.pruned) >= self.ttl {
+            callers.pruned = now;
+            // Windows being checked are busy, hence not empty, and are left for the next pruning
+            callers
+                .windows
+                .retain(|_, caller| match caller.window.try_lock() {
+                    Ok(mut window) => {
+                        window.evict_expired(now, self.ttl);
+                        !window.entries.is_empty()
+                    }
+                    Err(_) => true,
+                });
+        }
+        let caller = caller.map(str::to_string);
+        if !callers.windows.contains_key(&caller) {
+            let window = Window {
+                entries: VecDeque::new(),
+                usage: self.usage.clone(),
+            };
+            callers.windows.insert(
+                caller.clone(),
+                Caller {
+                    window: Arc::new(Mutex::new(window)),
+                    used: now,
+                },
+            );
+        }
+        let caller = callers.windows.get_mut(&caller).unwrap();
+        caller.used = now;
+        caller.window.clone()
+    }
+
+    /// Drops the windows of the least recently active callers but `caller` until the entries of
+    /// every caller fit in `max_entries`.
+    fn shed(&self, caller: Option<&str>) {
+        let mut callers = self.callers.lock().unwrap();
+        let mut idle: Vec<_> = callers
+            .windows
+            .iter()
+            .filter(|(name, _)| name.as_deref() != caller)
+            .map(|(name, caller)| (caller.used, name.clone()))
+            .collect();
+        idle.sort();
+        for (_, name) in idle {
+            if self.usage.entries.load(Ordering::Relaxed) <= self.max_entries {
+                break;
+            }
+            if let Some(caller) = callers.windows.remove(&name) {
+                caller.window.lock().unwrap().clear();
+            }
+        }
+    }
+
+    /// Returns the closest embedding to `vector` among the recent ones of `caller`, or of the
+    /// anonymous callers if `None`, then remembers `vector` under `reference`. Embeddings of
+    /// another dimension than `vector` are ignored.
+    pub fn check_and_insert(
+        &self,
+        caller: Option<&str>,
+        vector: &[f32],
+        reference: u64,
+    ) -> Option<Match> {
+        let vector = normalize(vector);
+        let now = Instant::now();
+        let window = self.window(caller, now);
+        let mut window = window.lock().unwrap();
+        window.evict_expired(now, self.ttl);
+        let closest = window
+            .entries
+            .iter()
+            .filter(|entry| entry.vector.len() == vector.len())
+            .map(|entry| Match {
+                similarity: dot(&entry.vector, &vector),
+                reference: entry.reference,
+            })
+            .max_by(|a, b| a.similarity.total_cmp(&b.similarity));
+
+        if self.capacity > 0 {
+            while window.entries.len() >= self.capacity {
+                window.evict_oldest();
+            }
+            window.push(Entry {
+                vector,
+                reference,
+                inserted: now,
+            });
+        }
+        drop(window);
+        if self.usage.entries.load(Ordering::Relaxed) > self.max_entries {
+            self.shed(caller);
+        }
+        closest
+    }
+}
+
+/// Pools the embeddings of a response into a single vector by averaging them, e.g. the
+/// embeddings of the chunks of a long text.
+pub fn mean_pool(response: &EmbeddingsResponse) -> Option<Vec<f32>> {
+    let first = response.embeddings.first()?;
+    let mut pooled = vec![0.0; first.values.len()];
+    for embedding in &response.embeddings {
+        for (sum, value) in pooled.iter_mut().zip(&embedding.values) {
+            *sum += value;
+        }
+    }
+    let count = response.embeddings.len() as f32;
+    pooled.iter_mut().for_each(|sum| *sum /= count);
+    Some(pooled)
+}
+
+fn normalize(vector: &[f32]) -> Vec<f32> {
+    let norm = dot(vector, vector).sqrt();
+    if norm == 0.0 {
+        return vector.to_vec();
+    }
+    vector.iter().map(|value| value / norm).collect()
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(window_size: usize, ttl: Duration) -> SimilarityWindow {
+        SimilarityWindow::new(&RecentlySimilarConfig {
+            window_size,
+            max_entries: 100,
+            ttl,
+            threshold: 0.95,
+        })
+    }
+
+    #[test]
+    fn test_check_and_insert() {
+        let window = window(2, Duration::from_secs(60));
+        assert_eq!(
+            window.check_and_insert(Some("search-indexer"), &[1.0, 0.0], 1),
+            None
+        );
+        assert_eq!(
+            window.check_and_insert(Some("search-indexer"), &[2.0, 0.0], 2),
+            Some(Match {
+                similarity: 1.0,
+                reference: 1
+            })
+        );
+        let closest = window
+            .check_and_insert(Some("search-indexer"), &[0.0, 1.0], 3)
+            .unwrap();
+        assert_eq!(closest.similarity, 0.0);
+
+        // The first embedding was evicted to make room for the third
+        let closest = window
+            .check_and_insert(Some("search-indexer"), &[1.0, 0.1], 4)
+            .unwrap();
+        assert_eq!(closest.reference, 2);
+        assert!(window
+            .check_and_insert(Some("search-indexer"), &[1.0, 0.0, 0.0], 5)
+            .is_none());
+    }
+
+    #[test]
+    fn test_callers_have_windows_of_their_own() {
+        let window = window(10, Duration::from_secs(60));
+        assert_eq!(
+            window.check_and_insert(Some("tenant-a"), &[1.0, 0.0], 1),
+            None
+        );
+        assert_eq!(
+            window.check_and_insert(Some("tenant-b"), &[1.0, 0.0], 2),
+            None
+        );
+        let closest = window
+            .check_and_insert(Some("tenant-a"), &[1.0, 0.0], 3)
+            .unwrap();
+        assert_eq!(closest.reference, 1);
+    }
+
+    #[test]
+    fn test_anonymous_callers_share_a_window() {
+        let window = window(10, Duration::from_secs(60));
+        assert_eq!(window.check_and_insert(None, &[1.0, 0.0], 1), None);
+        assert_eq!(
+            window.check_and_insert(Some("tenant-a"), &[1.0, 0.0], 2),
+            None
+        );
+        let closest = window.check_and_insert(None, &[1.0, 0.0], 3).unwrap();
+        assert_eq!(closest.reference, 1);
+    }
+
+    #[test]
+    fn test_expired_embeddings_are_evicted() {
+        let window = window(10, Duration::ZERO);
+        window.check_and_insert(Some("search-indexer"), &[1.0, 0.0], 1);
+        assert_eq!(
+            window.check_and_insert(Some("search-indexer"), &[1.0, 0.0], 2),
+            None
+        );
+    }
+
+    #[test]
+    fn test_expired_windows_are_dropped() {
+        let window = window(10, Duration::ZERO);
+        window.check_and_insert(Some("tenant-a"), &[1.0, 0.0], 1);
+        window.check_and_insert(Some("tenant-b"), &[1.0, 0.0], 2);
+        let callers = window.callers.lock().unwrap();
+        assert_eq!(callers.windows.len(), 1);
+        assert!(callers.windows.contains_key(&Some("tenant-b".to_string())));
+    }
+
+    #[test]
+    fn test_idle_callers_are_dropped_beyond_max_entries() {
+        let window = SimilarityWindow::new(&RecentlySimilarConfig {
+            window_size: 2,
+            max_entries: 3,
+            ttl: Duration::from_secs(60),
+            threshold: 0.95,
+        });
+        window.check_and_insert(Some("tenant-a"), &[1.0, 0.0], 1);
+        window.check_and_insert(Some("tenant-b"), &[1.0, 0.0], 2);
+        window.check_and_insert(Some("tenant-c"), &[1.0, 0.0], 3);
+        window.check_and_insert(Some("tenant-c"), &[1.0, 0.0], 4);
+        assert_eq!(window.usage.entries.load(Ordering::Relaxed), 3);
+        assert_eq!(
+            window.check_and_insert(Some("tenant-a"), &[1.0, 0.0], 5),
+            None
+        );
+        let closest = window
+            .check_and_insert(Some("tenant-c"), &[1.0, 0.0], 6)
+            .unwrap();
+        assert_eq!(closest.reference, 4);
+    }
+
+    #[test]
+    fn test_concurrent_identical_texts_match() {
+        let window = window(10, Duration::from_secs(60));
+        let matches = std::thread::scope(|scope| {
+            let checks: Vec<_> = (0..8)
+                .map(|reference| {
+                    let window = &window;
+                    scope.spawn(move || {
+                        window.check_and_insert(Some("tenant-a"), &[1.0, 0.0], reference)
+                    })
+                })
+                .collect();
+            checks
+                .into_iter()
+                .filter_map(|check| check.join().unwrap())
+                .count()
+        });
+        assert_eq!(matches, 7);
+    }
+}
@@ -0,0 +1,154 @@
+use tonic::Status;
+
+use crate::config::RequestValidationConfig;
+use crate::proto::mighty_proto::{
+    Empty, QuestionAnswerRequest, RecentlySimilarRequest, RerankRequest, StreamEmbeddingsRequest,
+    TextRequest,
+};
+
+/// A request message whose texts are checked before it is sent upstream.
+pub trait Validate {
+    /// Checks the texts of the request against `config`, failing with `INVALID_ARGUMENT` and a
+    /// message naming the offending field.
+    fn validate(&self, config: &RequestValidationConfig) -> Result<(), Status>;
+}
+
+/// Checks that the text of `field` is neither empty nor over the configured limits, and is free
+/// of control characters other than tabs and line breaks.
+pub fn check_text(config: &RequestValidationConfig, field: &str, text: &str) -> Result<(), Status> {
+    if text.trim().is_empty() {
+        return Err(Status::invalid_argument(format!("`{}` is empty", field)));
+    }
+    if let Some(max_bytes) = config.max_text_bytes {
+        if text.len() as u64 > max_bytes {
+            return Err(Status::invalid_argument(format!(
+                "`{}` is {} bytes long, over the limit of {}",
+                field,
+                text.len(),
+                max_bytes
+            )));
+        }
+    }
+    if let Some(max_chars) = config.max_text_chars {
+        let chars = text.chars().count();
+        if chars > max_chars {
+            return Err(Status::invalid_argument(format!(
+                "`{}` is {} characters long, over the limit of {}",
+                field, chars, max_chars
+            )));
+        }
+    }
+    let control = text
+        .chars()
+        .enumerate()
+        .find(|(_, c)| c.is_control() && !matches!(c, '\t' | '\n' | '\r'));
+    if let Some((index, c)) = control {
+        return Err(Status::invalid_argument(format!(
+            "`{}` contains the control character U+{:04X} at character {}",
+            field, c as u32, index
+        )));
+    }
+    Ok(())
+}
+
+impl Validate for Empty {
+    fn validate(&self, _config: &RequestValidationConfig) -> Result<(), Status> {
+        Ok(())
+    }
+}
+
+impl Validate for TextRequest {
+    fn validate(&self, config: &RequestValidationConfig) -> Result<(), Status> {
+        check_text(config, "text", &self.text)
+    }
+}
+
+impl Validate for QuestionAnswerRequest {
+    fn validate(&self, config: &RequestValidationConfig) -> Result<(), Status> {
+        check_text(config, "question", &self.question)?;
+        check_text(config, "context", &self.context)
+    }
+}
+
+impl Validate for RecentlySimilarRequest {
+    fn validate(&self, config: &RequestValidationConfig) -> Result<(), Status> {
+        check_text(config, "text", &self.text)
+    }
+}
+
+impl Validate for RerankRequest {
+    fn validate(&self, config: &RequestValidationConfig) -> Result<(), Status> {
+        check_text(config, "query", &self.query)?;
+        self.texts
+            .iter()
+            .enumerate()
+            .try_for_each(|(index, text)| check_text(config, &format!("texts[{}]", index), text))
+    }
+}
+
+impl Validate for StreamEmbeddingsRequest {
+    fn validate(&self, config: &RequestValidationConfig) -> Result<(), Status> {
+        self.texts
+            .iter()
+            .enumerate()
+            .try_for_each(|(index, text)| check_text(config, &format!("texts[{}]", index), text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use super::*;
+
+    fn message(result: Result<(), Status>) -> String {
+        let status = result.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        status.message().to_string()
+    }
+
+    #[test]
+    fn test_check_text() {
+        let config = RequestValidationConfig {
+            max_text_bytes: Some(8),
+            max_text_chars: Some(5),
+        };
+        assert!(check_text(&config, "text", "héllo").is_ok());
+        assert!(check_text(&config, "text", "a\tb\r\n").is_ok());
+        assert_eq!(
+            message(check_text(&config, "text", " \n")),
+            "`text` is empty"
+        );
+        assert_eq!(
+            message(check_text(&config, "text", "ééééé")),
+            "`text` is 10 bytes long, over the limit of 8"
+        );
+        assert_eq!(
+            message(check_text(&config, "text", "hello!")),
+            "`text` is 6 characters long, over the limit of 5"
+        );
+        assert_eq!(
+            message(check_text(&config, "text", "ab\0")),
+            "`text` contains the control character U+0000 at character 2"
+        );
+    }
+
+    #[test]
+    fn test_requests_name_the_offending_field() {
+        let config = RequestValidationConfig::default();
+        let question = QuestionAnswerRequest {
+            question: "Who?".to_string(),
+            context: String::new(),
+        };
+        assert_eq!(message(question.validate(&config)), "`context` is empty");
+        let rerank = RerankRequest {
+            query: "query".to_string(),
+            texts: vec!["first".to_string(), "\u{7}".to_string()],
+        };
+        assert_eq!(
+            message(rerank.validate(&config)),
+            "`texts[1]` contains the control character U+0007 at character 0"
+        );
+        assert!(StreamEmbeddingsRequest::default().validate(&config).is_ok());
+    }
+}
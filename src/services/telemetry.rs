@@ -0,0 +1,189 @@
+//! Spans of the calls made to the gRPC server, exported to an OpenTelemetry collector over OTLP
+//! with the `otel` feature. The W3C trace context (`traceparent` and `tracestate`) of incoming
+//! calls is continued by their spans, and propagated to the upstream HTTP requests they make.
+
+use reqwest::header::HeaderMap;
+use tonic::codegen::http;
+use tracing::Span;
+
+use crate::config::TracingConfig;
+use crate::startup::StartupError;
+
+/// Creates the span of an incoming gRPC call, the parent of the spans of its upstream calls,
+/// continuing the trace of the caller when spans are exported.
+pub fn grpc_request_span<B>(request: &http::Request<B>) -> Span {
+    let span = tracing::debug_span!("grpc_request", path = request.uri().path());
+    #[cfg(feature = "otel")]
+    {
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let parent = opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.extract(&otel::HeaderExtractor(request.headers()))
+        });
+        span.set_parent(parent);
+    }
+    span
+}
+
+/// Returns the headers propagating the trace context of the current span to an upstream HTTP
+/// request, empty when spans aren't exported.
+pub fn trace_context_headers() -> HeaderMap {
+    #[allow(unused_mut)]
+    let mut headers = HeaderMap::new();
+    #[cfg(feature = "otel")]
+    {
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let context = Span::current().context();
+        opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&context, &mut otel::HeaderInjector(&mut headers))
+        });
+    }
+    headers
+}
+
+/// Flushes the spans not exported yet when dropped, at shutdown.
+pub struct TracingGuard {
+    enabled: bool,
+}
+
+impl Drop for TracingGuard {
+    fn drop(&mut self) {
+        if self.enabled {
+            #[cfg(feature = "otel")]
+            opentelemetry::global::shutdown_tracer_provider();
+        }
+    }
+}
+
+/// Starts exporting spans as configured in `[tracing]`, until the returned guard is dropped.
+///
+/// # Errors
+///
+/// Returns a `StartupError` if tracing is enabled without the `otel` feature, or the exporter
+/// can't be set up.
+pub fn init_tracing(config: &TracingConfig) -> Result<TracingGuard, StartupError> {
+    if !config.enabled {
+        return Ok(TracingGuard { enabled: false });
+    }
+    #[cfg(not(feature = "otel"))]
+    return Err(StartupError::FeatureMismatch(
+        "tracing requires `--features otel`".to_string(),
+    ));
+    #[cfg(feature = "otel")]
+    {
+        otel::install(config)?;
+        log::info!("Exporting spans to {}", config.endpoint);
+        Ok(TracingGuard { enabled: true })
+    }
+}
+
+#[cfg(feature = "otel")]
+mod otel {
+    use opentelemetry::propagation::{Extractor, Injector};
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::trace::{self, Sampler};
+    use opentelemetry_sdk::Resource;
+    use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+    use tonic::codegen::http;
+    use tracing::Level;
+    use tracing_subscriber::filter::Targets;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Layer;
+
+    use crate::config::TracingConfig;
+    use crate::startup::StartupError;
+
+    /// Reads the trace context from the metadata of an incoming gRPC call.
+    pub(super) struct HeaderExtractor<'a>(pub(super) &'a http::HeaderMap);
+
+    impl Extractor for HeaderExtractor<'_> {
+        fn get(&self, key: &str) -> Option<&str> {
+            self.0.get(key).and_then(|value| value.to_str().ok())
+        }
+
+        fn keys(&self) -> Vec<&str> {
+            self.0.keys().map(|key| key.as_str()).collect()
+        }
+    }
+
+    /// Writes the trace context into the headers of an upstream HTTP request.
+    pub(super) struct HeaderInjector<'a>(pub(super) &'a mut HeaderMap);
+
+    impl Injector for HeaderInjector<'_> {
+        fn set(&mut self, key: &str, value: String) {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(key.as_bytes()),
+                HeaderValue::try_from(value),
+            ) {
+                self.0.insert(name, value);
+            }
+        }
+    }
+
+    /// Installs the OTLP exporter behind a `tracing` subscriber exporting the spans of the
+    /// gateway, and the W3C trace context propagator.
+    pub(super) fn install(config: &TracingConfig) -> Result<(), StartupError> {
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        let exporter = opentelemetry_otlp::new_exporter()
+            .tonic()
+            .with_endpoint(&config.endpoint)
+            .with_timeout(config.export_timeout);
+        let sampler = Sampler::TraceIdRatioBased(config.sample_ratio);
+        let resource = Resource::new([KeyValue::new("service.name", config.service_name.clone())]);
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(exporter)
+            .with_trace_config(
+                trace::config()
+                    .with_sampler(Sampler::ParentBased(Box::new(sampler)))
+                    .with_resource(resource),
+            )
+            .install_batch(opentelemetry_sdk::runtime::Tokio)
+            .map_err(|e| StartupError::Config(format!("Invalid tracing settings: {}", e)))?;
+        // The spans of the gateway only, not those of its HTTP and gRPC libraries
+        let layer = tracing_opentelemetry::layer()
+            .with_tracer(tracer)
+            .with_filter(Targets::new().with_target("mighty_grpc", Level::DEBUG));
+        tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))
+            .map_err(|e| StartupError::Server(e.to_string()))
+    }
+}
+
+#[cfg(all(test, feature = "otel"))]
+mod tests {
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::trace::TracerProvider;
+    use tracing::Instrument;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    #[tokio::test]
+    async fn test_trace_context_is_propagated_upstream() {
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        let provider = TracerProvider::builder().build();
+        let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("test"));
+        let subscriber = tracing_subscriber::registry().with(layer);
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let request = http::Request::builder()
+            .header(
+                "traceparent",
+                format!("00-{}-00f067aa0ba902b7-01", TRACE_ID),
+            )
+            .body(())
+            .unwrap();
+        let span = grpc_request_span(&request);
+        let headers = async { trace_context_headers() }.instrument(span).await;
+
+        let traceparent = headers["traceparent"].to_str().unwrap();
+        assert!(traceparent.starts_with(&format!("00-{}-", TRACE_ID)));
+        assert!(!traceparent.contains("00f067aa0ba902b7"));
+    }
+}
@@ -0,0 +1,372 @@
+//! TLS termination for the gRPC server, with hot reloading of rotated certificates and optional
+//! client certificate authentication (mutual TLS).
+
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use futures::channel::mpsc;
+use futures::{SinkExt, Stream, StreamExt};
+use log::{debug, info, warn};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::rustls::pki_types::CertificateDer;
+use tokio_rustls::rustls::server::danger::ClientCertVerifier;
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use x509_parser::extensions::GeneralName;
+
+use crate::config::TlsConfig;
+use crate::startup::StartupError;
+
+/// The maximum duration of a TLS handshake, after which the connection is dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The number of connections past their handshake waiting to be served.
+const ACCEPTED_BACKLOG: usize = 64;
+
+fn open(path: &Path) -> Result<BufReader<File>, String> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))
+}
+
+/// Loads the certificates of the PEM file at `path`.
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = rustls_pemfile::certs(&mut open(path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificate in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+/// Loads the certificate chain and private key configured in `config`.
+fn load_certified_key(config: &TlsConfig) -> Result<CertifiedKey, String> {
+    let certs = load_certs(&config.cert_path)?;
+    let key = rustls_pemfile::private_key(&mut open(&config.key_path)?)
+        .map_err(|e| format!("Failed to read {}: {}", config.key_path.display(), e))?
+        .ok_or_else(|| format!("No private key in {}", config.key_path.display()))?;
+    let key = tokio_rustls::rustls::crypto::ring::sign::any_supported_type(&key)
+        .map_err(|e| format!("Unsupported key in {}: {}", config.key_path.display(), e))?;
+    Ok(CertifiedKey::new(certs, key))
+}
+
+/// Returns the verifier of client certificates, issued by the CAs configured in `config`, or
+/// `None` when clients aren't authenticated.
+fn load_client_verifier(config: &TlsConfig) -> Result<Option<Arc<dyn ClientCertVerifier>>, String> {
+    let Some(path) = &config.client_ca_path else {
+        return Ok(None);
+    };
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots
+            .add(cert)
+            .map_err(|e| format!("Invalid CA certificate in {}: {}", path.display(), e))?;
+    }
+    let mut verifier = WebPkiClientVerifier::builder(Arc::new(roots));
+    if config.client_cert_optional {
+        verifier = verifier.allow_unauthenticated();
+    }
+    let verifier = verifier
+        .build()
+        .map_err(|e| format!("Invalid client CAs in {}: {}", path.display(), e))?;
+    Ok(Some(verifier))
+}
+
+/// Returns the identity of a client presenting the DER `certificate`: its first URI subject
+/// alternative name (e.g. a SPIFFE id), else its first DNS name, else its subject common name.
+pub fn client_identity(certificate: &[u8]) -> Option<String> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(certificate).ok()?;
+    if let Ok(Some(names)) = certificate.subject_alternative_name() {
+        let names = &names.value.general_names;
+        let uri = names.iter().find_map(|name| match name {
+            GeneralName::URI(uri) => Some(*uri),
+            _ => None,
+        });
+        let dns = names.iter().find_map(|name| match name {
+            GeneralName::DNSName(dns) => Some(*dns),
+            _ => None,
+        });
+        if let Some(name) = uri.or(dns) {
+            return Some(name.to_string());
+        }
+    }
+    let common_name = certificate.subject().iter_common_name().next()?;
+    common_name.as_str().ok().map(str::to_string)
+}
+
+/// Returns the identity of the client certificate `request` was made with, if any.
+pub fn peer_identity<T>(request: &tonic::Request<T>) -> Option<String> {
+    let certs = request.peer_certs()?;
+    client_identity(certs.first()?.get_ref())
+}
+
+/// Returns the modification times of the certificate and key files, to detect rotations.
+fn modified(config: &TlsConfig) -> Option<(SystemTime, SystemTime)> {
+    let modified = |path: &Path| {
+        path.metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+    Some((modified(&config.cert_path)?, modified(&config.key_path)?))
+}
+
+/// Resolves the server certificate to the one last loaded, whatever the client asked for.
+#[derive(Debug)]
+struct ReloadingResolver {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for ReloadingResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+/// Checks the certificate and key files every `interval`, reloading them once they changed.
+/// A certificate failing to load, e.g. while its files are being replaced, is retried at the
+/// next check, the previous one being served meanwhile.
+async fn watch(resolver: Arc<ReloadingResolver>, config: TlsConfig, interval: Duration) {
+    let mut loaded = modified(&config);
+    loop {
+        tokio::time::sleep(interval).await;
+        let current = modified(&config);
+        if current.is_none() || current == loaded {
+            continue;
+        }
+        match load_certified_key(&config) {
+            Ok(key) => {
+                *resolver.current.write().unwrap() = Arc::new(key);
+                loaded = current;
+                info!(
+                    "Reloaded the TLS certificate {}",
+                    config.cert_path.display()
+                );
+            }
+            Err(e) => warn!("Failed to reload the TLS certificate: {}", e),
+        }
+    }
+}
+
+/// The `TlsListener` struct terminates TLS on the connections accepted by the gRPC server,
+/// serving the certificate configured in `grpc_server.tls`. When a `reload_interval` is set,
+/// the certificate files are watched and a rotated certificate is served to new connections,
+/// existing ones keeping theirs.
+///
+/// With a `client_ca_path`, clients must present a certificate issued by one of its CAs
+/// (unless `client_cert_optional` is set). The identity of the certificate is then attached to
+/// the `RequestContext` of every call made over the connection, for authorization decisions.
+pub struct TlsListener {
+    acceptor: TlsAcceptor,
+}
+
+impl TlsListener {
+    /// Loads the configured certificate and client CAs, and starts watching the certificate
+    /// files for rotations.
+    pub fn new(config: &TlsConfig) -> Result<Self, StartupError> {
+        let key = load_certified_key(config).map_err(StartupError::Tls)?;
+        let client_verifier = load_client_verifier(config).map_err(StartupError::Tls)?;
+        let resolver = Arc::new(ReloadingResolver {
+            current: RwLock::new(Arc::new(key)),
+        });
+        if let Some(interval) = config.reload_interval {
+            tokio::spawn(watch(resolver.clone(), config.clone(), interval));
+        }
+        let builder = ServerConfig::builder();
+        let builder = match client_verifier {
+            Some(verifier) => builder.with_client_cert_verifier(verifier),
+            None => builder.with_no_client_auth(),
+        };
+        let mut server_config = builder.with_cert_resolver(resolver);
+        server_config.alpn_protocols = vec![b"h2".to_vec()];
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::new(server_config)),
+        })
+    }
+
+    /// Returns the TLS streams of the connections accepted by `incoming`. Handshakes run
+    /// concurrently, so a slow client doesn't hold back the others, and failed handshakes are
+    /// dropped without stopping the server.
+    pub fn accept<S, IO>(self, mut incoming: S) -> impl Stream<Item = io::Result<TlsStream<IO>>>
+    where
+        S: Stream<Item = io::Result<IO>> + Unpin + Send + 'static,
+        IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel(ACCEPTED_BACKLOG);
+        tokio::spawn(async move {
+            while let Some(connection) = incoming.next().await {
+                let connection = match connection {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        warn!("Failed to accept a connection: {}", e);
+                        continue;
+                    }
+                };
+                if sender.is_closed() {
+                    break;
+                }
+                let handshake = self.acceptor.accept(connection);
+                let mut sender = sender.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake).await {
+                        Ok(Ok(stream)) => {
+                            let _ = sender.send(Ok(stream)).await;
+                        }
+                        Ok(Err(e)) => debug!("TLS handshake failed: {}", e),
+                        Err(_) => debug!("TLS handshake timed out"),
+                    }
+                });
+            }
+        });
+        receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+    use std::pin::Pin;
+
+    use futures::stream;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::rustls::pki_types::ServerName;
+    use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+    use tokio_rustls::TlsConnector;
+
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/tls")
+            .join(name)
+    }
+
+    fn connector(client_cert: bool) -> TlsConnector {
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(&fixture("ca.pem")).unwrap() {
+            roots.add(cert).unwrap();
+        }
+        let config = ClientConfig::builder().with_root_certificates(roots);
+        let config = if client_cert {
+            let certs = load_certs(&fixture("client.pem")).unwrap();
+            let mut key = open(&fixture("client.key")).unwrap();
+            let key = rustls_pemfile::private_key(&mut key).unwrap().unwrap();
+            config.with_client_auth_cert(certs, key).unwrap()
+        } else {
+            config.with_no_client_auth()
+        };
+        TlsConnector::from(Arc::new(config))
+    }
+
+    /// Listens on a local port, returning its address and the TLS streams accepted.
+    async fn listen(
+        config: &TlsConfig,
+    ) -> (
+        SocketAddr,
+        Pin<Box<impl Stream<Item = io::Result<TlsStream<TcpStream>>>>>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let incoming = Box::pin(stream::unfold(listener, |listener| async {
+            let connection = listener.accept().await.map(|(tcp, _)| tcp);
+            Some((connection, listener))
+        }));
+        let accepted = TlsListener::new(config).unwrap().accept(incoming);
+        (addr, Box::pin(accepted))
+    }
+
+    async fn connect(
+        addr: SocketAddr,
+        client_cert: bool,
+    ) -> io::Result<tokio_rustls::client::TlsStream<TcpStream>> {
+        let tcp = TcpStream::connect(addr).await?;
+        let name = ServerName::try_from("localhost").unwrap();
+        connector(client_cert).connect(name, tcp).await
+    }
+
+    /// Connects to `addr` over TLS, returning the leaf certificate served.
+    async fn served_certificate(addr: SocketAddr) -> Vec<u8> {
+        let stream = connect(addr, false).await.unwrap();
+        let (_, session) = stream.get_ref();
+        session.peer_certificates().unwrap()[0].to_vec()
+    }
+
+    fn leaf(name: &str) -> Vec<u8> {
+        load_certs(&fixture(name)).unwrap()[0].to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_rotated_certificates_are_served_to_new_connections() {
+        let dir = std::env::temp_dir().join(format!("mighty-grpc-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let install = |name: &str| {
+            std::fs::copy(fixture(&format!("{}.pem", name)), dir.join("cert.pem")).unwrap();
+            std::fs::copy(fixture(&format!("{}.key", name)), dir.join("key.pem")).unwrap();
+        };
+        install("server");
+        let config = TlsConfig {
+            cert_path: dir.join("cert.pem"),
+            key_path: dir.join("key.pem"),
+            reload_interval: Some(Duration::from_millis(20)),
+            client_ca_path: None,
+            client_cert_optional: false,
+        };
+        let (addr, mut accepted) = listen(&config).await;
+
+        let (served, _) = tokio::join!(served_certificate(addr), accepted.next());
+        assert_eq!(served, leaf("server.pem"));
+
+        // Modification times may have a coarse resolution
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        install("rotated");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let (served, _) = tokio::join!(served_certificate(addr), accepted.next());
+        assert_eq!(served, leaf("rotated.pem"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_certificates_are_required_and_identified() {
+        let config = TlsConfig {
+            cert_path: fixture("server.pem"),
+            key_path: fixture("server.key"),
+            reload_interval: None,
+            client_ca_path: Some(fixture("ca.pem")),
+            client_cert_optional: false,
+        };
+        let (addr, mut accepted) = listen(&config).await;
+
+        let (_, stream) = tokio::join!(connect(addr, true), accepted.next());
+        let stream = stream.unwrap().unwrap();
+        let (_, session) = stream.get_ref();
+        let certificate = &session.peer_certificates().unwrap()[0];
+        assert_eq!(
+            client_identity(certificate).as_deref(),
+            Some("spiffe://mighty/search-indexer")
+        );
+
+        let _anonymous = connect(addr, false).await;
+        let next = tokio::time::timeout(Duration::from_millis(200), accepted.next()).await;
+        assert!(next.is_err(), "a client without certificate was accepted");
+    }
+
+    #[test]
+    fn test_missing_keys_fail_to_load() {
+        let config = TlsConfig {
+            cert_path: fixture("server.pem"),
+            key_path: fixture("server.pem"),
+            reload_interval: None,
+            client_ca_path: None,
+            client_cert_optional: false,
+        };
+        let error = load_certified_key(&config).err().unwrap();
+        assert!(error.starts_with("No private key"), "{}", error);
+    }
+}
@@ -0,0 +1,148 @@
+//! Unix domain socket listeners of the gRPC server, e.g. to serve sidecars without a TCP port.
+//!
+//! Their peers have no IP address: the network ACL leaves them to the permissions of the socket
+//! file, and client rate limiting tells them apart by the user ID of their process.
+
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+
+use futures::stream::{self, Stream};
+use tokio::net::{UnixListener, UnixStream};
+use tonic::transport::server::UdsConnectInfo;
+use tonic::Request;
+
+use crate::startup::StartupError;
+
+/// Whether `request` was made over a Unix domain socket.
+pub fn is_unix_peer<T>(request: &Request<T>) -> bool {
+    request.extensions().get::<UdsConnectInfo>().is_some()
+}
+
+/// The user ID of the process at the other end of the Unix domain socket of `request`, if known.
+pub fn peer_uid<T>(request: &Request<T>) -> Option<u32> {
+    let info = request.extensions().get::<UdsConnectInfo>()?;
+    info.peer_cred.map(|cred| cred.uid())
+}
+
+/// Listens on the Unix domain socket at `path`, replacing any stale socket file left by a
+/// previous run. Files at `path` that aren't sockets are left alone, failing the bind.
+pub fn bind_unix_socket(
+    path: &Path,
+) -> Result<impl Stream<Item = std::io::Result<UnixStream>> + Unpin + Send + 'static, StartupError>
+{
+    let bind_error = |reason: String| StartupError::Bind {
+        addr: format!("unix:{}", path.display()),
+        reason,
+    };
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            std::fs::remove_file(path).map_err(|e| bind_error(e.to_string()))?
+        }
+        Ok(_) => return Err(bind_error("the file exists and isn't a socket".to_string())),
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(bind_error(e.to_string()))
+        }
+        Err(_) => {}
+    }
+    let listener = UnixListener::bind(path).map_err(|e| bind_error(e.to_string()))?;
+    Ok(Box::pin(stream::unfold(listener, |listener| async {
+        let connection = listener.accept().await.map(|(stream, _)| stream);
+        Some((connection, listener))
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::task::{Context, Poll};
+
+    use futures::future::BoxFuture;
+    use tonic::codegen::http::Uri;
+    use tonic::transport::{Endpoint, Server};
+    use tonic::Code;
+    use tower_service::Service;
+
+    use crate::config::{ClientRateLimitConfig, IpRange, NetworkAclConfig};
+    use crate::proto::mighty_proto::mighty_inference_client::MightyInferenceClient;
+    use crate::proto::mighty_proto::mighty_inference_server::MightyInferenceServer;
+    use crate::proto::mighty_proto::Empty;
+    use crate::services::client_rate_limit::ClientRateLimitInterceptor;
+    use crate::services::clients::mock::MockMightyClient;
+    use crate::services::network_acl::NetworkAclInterceptor;
+    use crate::services::server_proxy::MightyInferenceServerProxy;
+
+    use super::*;
+
+    /// Connects channels to the Unix domain socket at its path, whatever their URI.
+    struct UnixConnector(PathBuf);
+
+    impl Service<Uri> for UnixConnector {
+        type Response = UnixStream;
+        type Error = std::io::Error;
+        type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _uri: Uri) -> Self::Future {
+            Box::pin(UnixStream::connect(self.0.clone()))
+        }
+    }
+
+    fn socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("mighty-grpc-{}-{}.sock", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_files_that_arent_sockets_are_not_replaced() {
+        let path = socket_path("regular");
+        std::fs::write(&path, "not a socket").unwrap();
+
+        assert!(matches!(
+            bind_unix_socket(&path),
+            Err(StartupError::Bind { .. })
+        ));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a socket");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_calls_are_served_over_unix_sockets() {
+        let path = socket_path("served");
+        // A stale socket left by a previous run is replaced
+        drop(UnixListener::bind(&path).unwrap());
+        let incoming = bind_unix_socket(&path).unwrap();
+
+        let acl = NetworkAclInterceptor::new(&NetworkAclConfig {
+            allow: vec![IpRange::try_from("10.0.0.0/8".to_string()).unwrap()],
+            deny: Vec::new(),
+        });
+        let client_rate_limit = ClientRateLimitInterceptor::new(&ClientRateLimitConfig {
+            enabled: true,
+            requests_per_second: 0.001,
+            burst: 2,
+            ..Default::default()
+        });
+        let proxy = MightyInferenceServerProxy::new(Box::new(MockMightyClient::new()));
+        let server = Server::builder()
+            .layer(tonic::service::interceptor(acl))
+            .layer(tonic::service::interceptor(client_rate_limit))
+            .add_service(MightyInferenceServer::new(proxy))
+            .serve_with_incoming(incoming);
+        tokio::spawn(server);
+
+        let channel = Endpoint::from_static("http://localhost")
+            .connect_with_connector(UnixConnector(path.clone()))
+            .await
+            .unwrap();
+        let mut client = MightyInferenceClient::new(channel);
+        // Allowed by the ACL despite having no IP address, then limited as a local user
+        for _ in 0..2 {
+            client.health_check(Empty {}).await.unwrap();
+        }
+        let status = client.health_check(Empty {}).await.unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
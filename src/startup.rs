@@ -0,0 +1,156 @@
+//! Startup failure classification for the binaries.
+//!
+//! Each class of failure exits with its own code, following the BSD `sysexits.h` conventions,
+//! and is reported as a single JSON line on stderr, so orchestration and humans can tell a bad
+//! configuration from an unreachable upstream without reading stack traces.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::process::ExitCode;
+
+use config::ConfigError;
+use serde_json::json;
+use tokio::runtime::{Builder, Runtime};
+
+use crate::config::{RuntimeConfig, RuntimeFlavor};
+
+/// A failure preventing the gateway from starting (or from continuing to serve).
+#[derive(Debug)]
+pub enum StartupError {
+    /// The command-line arguments could not be parsed.
+    Usage(String),
+    /// The configuration could not be read, parsed or is incomplete.
+    Config(String),
+    /// The server could not bind its listening address.
+    Bind { addr: String, reason: String },
+    /// The TLS certificates or keys could not be loaded.
+    Tls(String),
+    /// The upstream Mighty server could not be reached.
+    UpstreamUnreachable(String),
+    /// The binary was built without the features required by the requested mode.
+    FeatureMismatch(String),
+    /// The server failed while running.
+    Server(String),
+}
+
+impl StartupError {
+    /// Returns the failure class, as reported in the structured error report.
+    pub fn class(&self) -> &'static str {
+        match self {
+            StartupError::Usage(_) => "usage",
+            StartupError::Config(_) => "config",
+            StartupError::Bind { .. } => "bind",
+            StartupError::Tls(_) => "tls",
+            StartupError::UpstreamUnreachable(_) => "upstream_unreachable",
+            StartupError::FeatureMismatch(_) => "feature_mismatch",
+            StartupError::Server(_) => "server",
+        }
+    }
+
+    /// Returns the process exit code for the failure class.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            StartupError::Usage(_) => 64,               // EX_USAGE
+            StartupError::FeatureMismatch(_) => 64,     // EX_USAGE
+            StartupError::UpstreamUnreachable(_) => 69, // EX_UNAVAILABLE
+            StartupError::Server(_) => 70,              // EX_SOFTWARE
+            StartupError::Bind { .. } => 71,            // EX_OSERR
+            StartupError::Tls(_) => 74,                 // EX_IOERR
+            StartupError::Config(_) => 78,              // EX_CONFIG
+        }
+    }
+
+    /// Builds a bind failure for `addr`.
+    pub fn bind(addr: SocketAddr, reason: impl fmt::Display) -> Self {
+        StartupError::Bind {
+            addr: addr.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    /// Returns the structured, single-line JSON report of the failure.
+    pub fn report(&self) -> String {
+        let mut report = json!({
+            "event": "startup_failure",
+            "class": self.class(),
+            "exit_code": self.exit_code(),
+            "message": self.to_string(),
+        });
+        if let StartupError::Bind { addr, .. } = self {
+            report["addr"] = json!(addr);
+        }
+        report.to_string()
+    }
+
+    /// Prints the report on stderr and returns the matching exit code.
+    pub fn exit(&self) -> ExitCode {
+        eprintln!("{}", self.report());
+        ExitCode::from(self.exit_code())
+    }
+}
+
+impl fmt::Display for StartupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StartupError::Usage(reason) => write!(f, "Invalid arguments: {} (see --help)", reason),
+            StartupError::Config(reason) => write!(f, "Invalid configuration: {}", reason),
+            StartupError::Bind { addr, reason } => write!(f, "Unable to bind {}: {}", addr, reason),
+            StartupError::Tls(reason) => write!(f, "Unable to load TLS configuration: {}", reason),
+            StartupError::UpstreamUnreachable(reason) => {
+                write!(f, "Upstream Mighty server unreachable: {}", reason)
+            }
+            StartupError::FeatureMismatch(reason) => write!(f, "Feature mismatch: {}", reason),
+            StartupError::Server(reason) => write!(f, "Server error: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for StartupError {}
+
+/// Builds the Tokio runtime configured by `config`, which the binaries run on instead of that of
+/// `#[tokio::main]`.
+///
+/// # Errors
+///
+/// Returns a server failure if the threads of the runtime can't be spawned.
+pub fn build_runtime(config: &RuntimeConfig) -> Result<Runtime, StartupError> {
+    let mut builder = match config.flavor {
+        RuntimeFlavor::MultiThread => Builder::new_multi_thread(),
+        RuntimeFlavor::CurrentThread => Builder::new_current_thread(),
+    };
+    if let (RuntimeFlavor::MultiThread, Some(threads)) = (config.flavor, config.worker_threads) {
+        builder.worker_threads(threads);
+    }
+    if let Some(threads) = config.max_blocking_threads {
+        builder.max_blocking_threads(threads);
+    }
+    builder
+        .enable_all()
+        .build()
+        .map_err(|e| StartupError::Server(format!("Unable to start the Tokio runtime: {}", e)))
+}
+
+impl From<ConfigError> for StartupError {
+    fn from(error: ConfigError) -> Self {
+        StartupError::Config(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_is_structured() {
+        let error = StartupError::bind("127.0.0.1:50051".parse().unwrap(), "address in use");
+        let report: serde_json::Value = serde_json::from_str(&error.report()).unwrap();
+
+        assert_eq!(report["class"], "bind");
+        assert_eq!(report["exit_code"], 71);
+        assert_eq!(report["addr"], "127.0.0.1:50051");
+        assert_eq!(
+            report["message"],
+            "Unable to bind 127.0.0.1:50051: address in use"
+        );
+    }
+}
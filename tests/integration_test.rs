@@ -1,7 +1,9 @@
 use mighty_inference_server::mighty_inference_client::MightyInferenceClient;
 use mighty_inference_server::{Empty};
 use crate::mighty_inference_server::TextRequest;
+use tonic::codec::CompressionEncoding;
 
+#[allow(clippy::module_inception)]
 pub mod mighty_inference_server {
     tonic::include_proto!("mighty_inference_server");
 }
@@ -28,6 +30,27 @@ async fn test_embeddings() {
     }
 }
 
+#[tokio::test]
+async fn test_compressed_embeddings() {
+    let mut client = MightyInferenceClient::connect("http://127.0.0.1:50051")
+        .await
+        .expect("Failed to connect to gRPC server")
+        .send_compressed(CompressionEncoding::Gzip)
+        .accept_compressed(CompressionEncoding::Gzip);
+
+    let request = tonic::Request::new(TextRequest {
+        text: "test text".into(),
+    });
+
+    match client.embeddings(request).await {
+        Ok(response) => {
+            let response_inner = response.into_inner();
+            assert_eq!(response_inner.text, "test text");
+        },
+        Err(e) => panic!("Failed to get compressed embeddings: {:?}", e),
+    }
+}
+
 #[tokio::test]
 async fn test_healthcheck() {
     let mut client = MightyInferenceClient::connect("http://127.0.0.1:50051")